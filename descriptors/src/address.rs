@@ -0,0 +1,232 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Address classification and conversion between addresses and
+//! [`ScriptPubkeyDescr`]. Since [`AddressCompat`] is defined outside of this
+//! library, the functionality is provided via [`AddressClassify`] extension
+//! trait implemented both for it and for [`bitcoin::Address`].
+
+use bitcoin::util::address::{Payload, WitnessVersion};
+use bitcoin::{Address, Network};
+use bitcoin_scripts::address::AddressCompat;
+use bitcoin_scripts::PubkeyScript;
+
+use crate::{ScriptPubkeyDescr, UnsupportedScriptPubkey};
+
+/// Classes of bitcoin addresses
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+pub enum AddressClass {
+    /// Pay-to-public-key-hash address
+    #[display("p2pkh")]
+    P2pkh,
+
+    /// Pay-to-script-hash address
+    #[display("p2sh")]
+    P2sh,
+
+    /// Pay-to-witness-public-key-hash address
+    #[display("p2wpkh")]
+    P2wpkh,
+
+    /// Pay-to-witness-script-hash address
+    #[display("p2wsh")]
+    P2wsh,
+
+    /// Pay-to-taproot address
+    #[display("p2tr")]
+    P2tr,
+
+    /// Witness program of a version (or a length, for v1) which has no
+    /// consensus meaning yet
+    #[display("future({0})")]
+    Future(WitnessVersion),
+}
+
+impl AddressClass {
+    /// Returns witness version used by the address class, if any
+    pub fn witness_version(self) -> Option<WitnessVersion> {
+        match self {
+            AddressClass::P2pkh | AddressClass::P2sh => None,
+            AddressClass::P2wpkh | AddressClass::P2wsh => Some(WitnessVersion::V0),
+            AddressClass::P2tr => Some(WitnessVersion::V1),
+            AddressClass::Future(version) => Some(version),
+        }
+    }
+
+    /// Detects whether the address class is a witness program of a version
+    /// which has no consensus meaning yet
+    #[inline]
+    pub fn is_future(self) -> bool { matches!(self, AddressClass::Future(_)) }
+}
+
+impl From<&Payload> for AddressClass {
+    fn from(payload: &Payload) -> Self {
+        match payload {
+            Payload::PubkeyHash(_) => AddressClass::P2pkh,
+            Payload::ScriptHash(_) => AddressClass::P2sh,
+            Payload::WitnessProgram {
+                version: WitnessVersion::V0,
+                program,
+            } if program.len() == 20 => AddressClass::P2wpkh,
+            Payload::WitnessProgram {
+                version: WitnessVersion::V0,
+                program,
+            } if program.len() == 32 => AddressClass::P2wsh,
+            Payload::WitnessProgram {
+                version: WitnessVersion::V1,
+                program,
+            } if program.len() == 32 => AddressClass::P2tr,
+            Payload::WitnessProgram { version, .. } => AddressClass::Future(*version),
+        }
+    }
+}
+
+/// Classification API for address types
+pub trait AddressClassify {
+    /// Returns class of the address
+    fn address_class(&self) -> AddressClass;
+
+    /// Returns network the address is defined for. Signet addresses are
+    /// indistinguishable from testnet ones and are reported as
+    /// [`Network::Testnet`].
+    fn address_network(&self) -> Network;
+
+    /// Detects addresses which are known to be unspendable by convention:
+    /// those committing to all-zero hash or witness program (like
+    /// `1111111111111111111114oLvT2`). Funds sent to such addresses are
+    /// burned.
+    fn is_burn(&self) -> bool;
+
+    /// Converts the address into `scriptPubkey` descriptor. Errors for
+    /// taproot addresses with output key not belonging to Secp256k1 curve.
+    fn to_script_pubkey_descr(&self) -> Result<ScriptPubkeyDescr, UnsupportedScriptPubkey>;
+}
+
+impl AddressClassify for Address {
+    #[inline]
+    fn address_class(&self) -> AddressClass { AddressClass::from(&self.payload) }
+
+    #[inline]
+    fn address_network(&self) -> Network { self.network }
+
+    fn is_burn(&self) -> bool {
+        let data = match &self.payload {
+            Payload::PubkeyHash(hash) => &hash[..],
+            Payload::ScriptHash(hash) => &hash[..],
+            Payload::WitnessProgram { program, .. } => &program[..],
+        };
+        data.iter().all(|byte| *byte == 0)
+    }
+
+    fn to_script_pubkey_descr(&self) -> Result<ScriptPubkeyDescr, UnsupportedScriptPubkey> {
+        ScriptPubkeyDescr::try_from(PubkeyScript::from(self.script_pubkey()))
+    }
+}
+
+impl AddressClassify for AddressCompat {
+    #[inline]
+    fn address_class(&self) -> AddressClass { Address::from(*self).address_class() }
+
+    #[inline]
+    fn address_network(&self) -> Network { Address::from(*self).address_network() }
+
+    #[inline]
+    fn is_burn(&self) -> bool { Address::from(*self).is_burn() }
+
+    #[inline]
+    fn to_script_pubkey_descr(&self) -> Result<ScriptPubkeyDescr, UnsupportedScriptPubkey> {
+        Address::from(*self).to_script_pubkey_descr()
+    }
+}
+
+impl ScriptPubkeyDescr {
+    /// Detects `OP_RETURN` outputs, which are provably unspendable
+    #[inline]
+    pub fn is_op_return(&self) -> bool {
+        matches!(self, ScriptPubkeyDescr::Bare(spk) if spk.is_op_return())
+    }
+
+    /// Detects `scriptPubkey`s burning the funds: `OP_RETURN` outputs and
+    /// outputs with burn addresses (see [`AddressClassify::is_burn`]).
+    pub fn is_burn(&self) -> bool {
+        self.is_op_return()
+            || self
+                .to_address(Network::Bitcoin)
+                .is_some_and(|address| address.is_burn())
+    }
+
+    /// Constructs address for the `scriptPubkey`, if it has an address form.
+    /// Unlike [`ScriptPubkeyDescr::to_address_compat`], supports future
+    /// witness versions.
+    pub fn to_address(&self, network: Network) -> Option<Address> {
+        Address::from_script(&PubkeyScript::from(self.clone()), network)
+    }
+
+    /// Constructs [`AddressCompat`] for the `scriptPubkey`, if it has an
+    /// address form.
+    pub fn to_address_compat(&self, network: Network) -> Option<AddressCompat> {
+        AddressCompat::from_script(&PubkeyScript::from(self.clone()), network.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn classify() {
+        for (addr, class) in [
+            ("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", AddressClass::P2pkh),
+            ("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", AddressClass::P2sh),
+            (
+                "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+                AddressClass::P2wpkh,
+            ),
+            (
+                "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3",
+                AddressClass::P2wsh,
+            ),
+            (
+                "bc1p5d7rjq7g6rdk2yhzks9smlaqtedr4dekq08ge8ztwac72sfr9rusxg3297",
+                AddressClass::P2tr,
+            ),
+            (
+                "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7kt5nd6y",
+                AddressClass::Future(WitnessVersion::V1),
+            ),
+        ] {
+            let address = Address::from_str(addr).unwrap();
+            assert_eq!(address.address_class(), class);
+            assert_eq!(address.address_network(), Network::Bitcoin);
+            assert!(!address.is_burn());
+
+            let descr = address.to_script_pubkey_descr().unwrap();
+            assert_eq!(descr.is_future(), class.is_future());
+            assert_eq!(descr.witness_version(), class.witness_version());
+            assert_eq!(descr.to_address(Network::Bitcoin), Some(address.clone()));
+            assert_eq!(
+                descr.to_address_compat(Network::Bitcoin).is_some(),
+                !class.is_future()
+            );
+        }
+
+        let burn = Address::from_str("1111111111111111111114oLvT2").unwrap();
+        assert!(burn.is_burn());
+        assert!(burn.to_script_pubkey_descr().unwrap().is_burn());
+
+        let op_return = ScriptPubkeyDescr::from_str("bare(6a)").unwrap();
+        assert!(op_return.is_op_return());
+        assert!(op_return.is_burn());
+        assert_eq!(op_return.to_address(Network::Bitcoin), None);
+    }
+}
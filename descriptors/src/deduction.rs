@@ -9,10 +9,23 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/Apache-2.0>.
 
+//! Deduction of descriptor types from the spent `scriptPubkey`, and recovery
+//! of the descriptors and taproot internal keys from the data revealed by the
+//! spending inputs, used by wallet recovery and chain analysis tools.
+
+use amplify::Wrapper;
+use bitcoin::blockdata::opcodes::all as op;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::schnorr::TapTweak;
 use bitcoin::util::address::WitnessVersion;
-use bitcoin_scripts::{PubkeyScript, RedeemScript};
+use bitcoin::util::taproot::{
+    ControlBlock, LeafVersion, TapBranchHash, TapLeafHash, TaprootMerkleBranch,
+};
+use bitcoin::{secp256k1, Script, Witness, XOnlyPublicKey};
+use bitcoin_scripts::{LeafScript, PubkeyScript, RedeemScript, TaprootWitness, WitnessScript};
 
-use crate::CompositeDescrType;
+use crate::{BareDescriptor, CompositeDescrType};
 
 /// Errors that happens during deduction process
 #[derive(
@@ -20,12 +33,6 @@ use crate::CompositeDescrType;
 )]
 #[display(doc_comments)]
 pub enum DeductionError {
-    /// input spends non-taproot witness version 1
-    NonTaprootV1,
-
-    /// input spends future witness version {0}
-    UnsupportedWitnessVersion(WitnessVersion),
-
     /// input spends P2SH output, but no `redeedScript` is present in the PSBT
     /// input data
     P2shWithoutRedeemScript,
@@ -37,7 +44,9 @@ pub enum DeductionError {
 
 impl CompositeDescrType {
     /// Deduction of a descriptor type from a `scriptPubkey` data and data
-    /// inside redeem script and witness scripts.
+    /// inside redeem script and witness scripts. Witness programs of future
+    /// versions and non-taproot witness v1 programs are deduced as
+    /// [`CompositeDescrType::Future`].
     ///
     /// # Errors
     ///
@@ -77,9 +86,695 @@ impl CompositeDescrType {
                     Ok(CompositeDescrType::Sh)
                 }
             }
-            (_, Some(WitnessVersion::V1)) => Err(DeductionError::NonTaprootV1),
-            (_, Some(version)) => Err(DeductionError::UnsupportedWitnessVersion(version)),
-            (_, None) => Ok(CompositeDescrType::Bare),
+            (spk, Some(version)) if version != WitnessVersion::V0 && spk.is_witness_program() => {
+                Ok(CompositeDescrType::Future)
+            }
+            _ => Ok(CompositeDescrType::Bare),
+        }
+    }
+
+    /// Guesses descriptor type from the `scriptSig` and witness of the
+    /// spending input when the spent `scriptPubkey` is not known. Unlike
+    /// [`CompositeDescrType::deduce`], the result is heuristic: for instance,
+    /// a bare script may be spent with the same data as a P2SH one.
+    ///
+    /// # Returns
+    ///
+    /// `None` if the input does not provide any spending data, or if its
+    /// `scriptSig` is not push-only.
+    pub fn deduce_spending(script_sig: &Script, witness: &Witness) -> Option<Self> {
+        let pushes = script_sig
+            .instructions()
+            .map(|instruction| match instruction.ok()? {
+                Instruction::PushBytes(data) => Some(data),
+                Instruction::Op(_) => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let segwit = !witness.is_empty();
+        Some(match (pushes.as_slice(), segwit) {
+            ([], false) => return None,
+            ([], true) => deduce_witness(witness),
+            ([program], true) if program.len() == 22 && program[..2] == [0x00, 0x14] => {
+                CompositeDescrType::ShWpkh
+            }
+            ([program], true) if program.len() == 34 && program[..2] == [0x00, 0x20] => {
+                CompositeDescrType::ShWsh
+            }
+            (_, true) => return None,
+            ([sig], false) if is_signature(sig) => CompositeDescrType::Pk,
+            ([sig, key], false) if is_signature(sig) && is_public_key(key) => {
+                CompositeDescrType::Pkh
+            }
+            ([.., sig], false) if is_signature(sig) => CompositeDescrType::Bare,
+            (_, false) => CompositeDescrType::Sh,
+        })
+    }
+}
+
+/// Errors recovering descriptor from the input spending data
+#[derive(
+    Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Error, From
+)]
+#[display(doc_comments)]
+pub enum SpendDeductionError {
+    /// unable to deduce type of the spent descriptor: {0}
+    #[from]
+    DescrType(DeductionError),
+
+    /// input does not reveal public key or script committed to by the spent
+    /// `scriptPubkey`
+    NoSpendingData,
+
+    /// input reveals invalid public key
+    InvalidPublicKey,
+
+    /// public key or script revealed by the input does not match the spent
+    /// `scriptPubkey`
+    ScriptMismatch,
+
+    /// input spends taproot output with invalid witness
+    InvalidTaprootWitness,
+}
+
+/// Descriptor recovered from the data revealed by the input spending a
+/// transaction output
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SpendDeduction {
+    /// Type of the spent descriptor
+    pub descr_type: CompositeDescrType,
+
+    /// Spent descriptor with the reconstructed scripts. Taproot key path
+    /// spendings do not reveal internal key and future witness versions have
+    /// no descriptors, so the descriptor is unknown for them.
+    pub descriptor: Option<BareDescriptor>,
+
+    /// Threshold and number of keys, if the spent script (or taproot leaf
+    /// script) is a `multi` or `multi_a` multisig
+    pub multisig: Option<(usize, usize)>,
+
+    /// Taproot leaf script used in script path spending
+    pub tap_leaf: Option<LeafScript>,
+}
+
+impl SpendDeduction {
+    /// Recovers descriptor from the spent `scriptPubkey` and the `scriptSig`
+    /// and witness of the spending input. All public keys and scripts
+    /// revealed by the input are checked to match the spent `scriptPubkey`.
+    pub fn deduce(
+        spk: &PubkeyScript,
+        script_sig: &Script,
+        witness: &Witness,
+    ) -> Result<Self, SpendDeductionError> {
+        let redeem_script = last_push(script_sig).map(RedeemScript::from);
+        let descr_type =
+            CompositeDescrType::deduce(spk, redeem_script.as_ref(), !witness.is_empty())?;
+        let mut deduction = SpendDeduction {
+            descr_type,
+            descriptor: None,
+            multisig: None,
+            tap_leaf: None,
+        };
+
+        let witness_script = || {
+            witness
+                .last()
+                .map(|script| WitnessScript::from(Script::from(script.to_vec())))
+                .ok_or(SpendDeductionError::NoSpendingData)
+        };
+        let descriptor = match descr_type {
+            CompositeDescrType::Bare => BareDescriptor::Bare(spk.clone()),
+            CompositeDescrType::Pk => {
+                let key = &spk.as_bytes()[1..spk.len() - 1];
+                BareDescriptor::Pk(
+                    bitcoin::PublicKey::from_slice(key)
+                        .map_err(|_| SpendDeductionError::InvalidPublicKey)?,
+                )
+            }
+            CompositeDescrType::Pkh => {
+                let key = last_push(script_sig).ok_or(SpendDeductionError::NoSpendingData)?;
+                BareDescriptor::Pkh(
+                    bitcoin::PublicKey::from_slice(&key)
+                        .map_err(|_| SpendDeductionError::InvalidPublicKey)?,
+                )
+            }
+            CompositeDescrType::Sh => {
+                BareDescriptor::Sh(redeem_script.expect("checked by descriptor type deduction"))
+            }
+            CompositeDescrType::ShWpkh => BareDescriptor::ShWpkh(witness_key(witness)?),
+            CompositeDescrType::ShWsh => BareDescriptor::ShWsh(witness_script()?),
+            CompositeDescrType::Wpkh => BareDescriptor::Wpkh(witness_key(witness)?),
+            CompositeDescrType::Wsh => BareDescriptor::Wsh(witness_script()?),
+            CompositeDescrType::Tr => {
+                let tw = TaprootWitness::try_from(witness.clone())
+                    .map_err(|_| SpendDeductionError::InvalidTaprootWitness)?;
+                let (control_block, leaf) = match tw {
+                    TaprootWitness::PubkeySpending { .. } => return Ok(deduction),
+                    TaprootWitness::ScriptSpending {
+                        control_block,
+                        script,
+                        ..
+                    } => (control_block, script),
+                };
+                let leaf_hash = TapLeafHash::from_script(leaf.script.as_inner(), leaf.version);
+                let merkle_root = tap_merkle_root(leaf_hash, &control_block.merkle_branch);
+                if leaf.version == LeafVersion::TapScript {
+                    deduction.multisig = multi_a_composition(leaf.script.as_inner());
+                }
+                deduction.tap_leaf = Some(leaf);
+                BareDescriptor::Tr(control_block.internal_key, Some(merkle_root))
+            }
+            CompositeDescrType::Future => return Ok(deduction),
+        };
+
+        if descriptor.pubkey_script(secp256k1::SECP256K1) != *spk {
+            return Err(SpendDeductionError::ScriptMismatch);
         }
+        let script = match &descriptor {
+            BareDescriptor::Bare(script) => Some(script.as_inner()),
+            BareDescriptor::Sh(script) => Some(script.as_inner()),
+            BareDescriptor::ShWsh(script) | BareDescriptor::Wsh(script) => Some(script.as_inner()),
+            _ => None,
+        };
+        if let Some(script) = script {
+            deduction.multisig = multi_composition(script);
+        }
+        deduction.descriptor = Some(descriptor);
+        Ok(deduction)
+    }
+}
+
+/// Errors validating taproot script path spending
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TapLeafError {
+    /// witness is not a valid taproot witness
+    InvalidWitness,
+
+    /// witness spends taproot output with the key path, which does not reveal
+    /// internal key
+    KeySpending,
+
+    /// control block commits to leaf version {0}, while the leaf script has
+    /// version {1}
+    LeafVersionMismatch(LeafVersion, LeafVersion),
+
+    /// control block and leaf script do not commit to the output key {0}
+    OutputKeyMismatch(XOnlyPublicKey),
+
+    /// parity of the output key does not match the one committed to by the
+    /// control block
+    ParityMismatch,
+}
+
+/// Internal key and merkle path recovered from taproot script path spending
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TapLeafProof {
+    /// Internal key of the spent output
+    pub internal_key: XOnlyPublicKey,
+
+    /// Hash of the spent leaf script
+    pub leaf_hash: TapLeafHash,
+
+    /// Merkle path from the spent leaf to the root of the script tree
+    pub merkle_branch: TaprootMerkleBranch,
+
+    /// Merkle root of the script tree
+    pub merkle_root: TapBranchHash,
+}
+
+/// Computes merkle root of a taproot script tree from the leaf hash and the
+/// merkle path to the leaf.
+pub fn tap_merkle_root(
+    leaf_hash: TapLeafHash,
+    merkle_branch: &TaprootMerkleBranch,
+) -> TapBranchHash {
+    let root = merkle_branch.as_inner().iter().fold(
+        sha256::Hash::from_inner(leaf_hash.into_inner()),
+        |node, sibling| {
+            sha256::Hash::from_inner(TapBranchHash::from_node_hashes(node, *sibling).into_inner())
+        },
+    );
+    TapBranchHash::from_inner(root.into_inner())
+}
+
+/// Validates that the control block and the leaf script of taproot script
+/// path spending commit to the `output_key`, recovering internal key and
+/// merkle path of the spent output.
+pub fn recover_internal_key(
+    control_block: &ControlBlock,
+    leaf_script: &LeafScript,
+    output_key: XOnlyPublicKey,
+) -> Result<TapLeafProof, TapLeafError> {
+    if control_block.leaf_version != leaf_script.version {
+        return Err(TapLeafError::LeafVersionMismatch(
+            control_block.leaf_version,
+            leaf_script.version,
+        ));
+    }
+    let leaf_hash = TapLeafHash::from_script(leaf_script.script.as_inner(), leaf_script.version);
+    let merkle_root = tap_merkle_root(leaf_hash, &control_block.merkle_branch);
+    let (tweaked_key, parity) = control_block
+        .internal_key
+        .tap_tweak(secp256k1::SECP256K1, Some(merkle_root));
+    if tweaked_key.to_inner() != output_key {
+        return Err(TapLeafError::OutputKeyMismatch(output_key));
+    }
+    if parity != control_block.output_key_parity {
+        return Err(TapLeafError::ParityMismatch);
+    }
+    Ok(TapLeafProof {
+        internal_key: control_block.internal_key,
+        leaf_hash,
+        merkle_branch: control_block.merkle_branch.clone(),
+        merkle_root,
+    })
+}
+
+/// Parses raw witness of taproot script path spending and recovers internal
+/// key and merkle path of the spent output (see [`recover_internal_key`]).
+pub fn recover_witness_internal_key(
+    witness: &Witness,
+    output_key: XOnlyPublicKey,
+) -> Result<TapLeafProof, TapLeafError> {
+    match TaprootWitness::try_from(witness.clone()) {
+        Ok(TaprootWitness::ScriptSpending {
+            control_block,
+            script,
+            ..
+        }) => recover_internal_key(&control_block, &script, output_key),
+        Ok(TaprootWitness::PubkeySpending { .. }) => Err(TapLeafError::KeySpending),
+        Err(_) => Err(TapLeafError::InvalidWitness),
+    }
+}
+
+/// Checks whether the `scriptPubkey` is standard for relay: either one of the
+/// known output types (including future witness versions), bare multisig
+/// with up to three keys, or `OP_RETURN` output carrying up to 80 bytes of
+/// data.
+pub fn is_standard_script(spk: &Script) -> bool {
+    if spk.is_op_return() {
+        return spk.len() <= 83;
+    }
+    spk.is_p2pk()
+        || spk.is_p2pkh()
+        || spk.is_p2sh()
+        || spk.witness_version().is_some()
+        || matches!(multi_composition(spk), Some((_, keys)) if keys <= 3)
+}
+
+/// Detects type of the segwit spending with empty `scriptSig`.
+fn deduce_witness(witness: &Witness) -> CompositeDescrType {
+    if witness.len() == 2 {
+        let mut elements = witness.iter();
+        let sig = elements.next().expect("witness has two elements");
+        let key = elements.next().expect("witness has two elements");
+        if is_signature(sig) && key.len() == 33 && is_public_key(key) {
+            return CompositeDescrType::Wpkh;
+        }
+    }
+    match TaprootWitness::try_from(witness.clone()) {
+        Ok(TaprootWitness::PubkeySpending { .. }) => CompositeDescrType::Tr,
+        Ok(TaprootWitness::ScriptSpending { script, .. })
+            if script.version == LeafVersion::TapScript =>
+        {
+            CompositeDescrType::Tr
+        }
+        _ => CompositeDescrType::Wsh,
+    }
+}
+
+/// Checks whether the data look like a DER-encoded ECDSA signature followed
+/// by a sighash type.
+fn is_signature(data: &[u8]) -> bool { (9..=73).contains(&data.len()) && data[0] == 0x30 }
+
+/// Checks whether the data look like a compressed or uncompressed public key.
+fn is_public_key(data: &[u8]) -> bool {
+    matches!(
+        (data.len(), data.first()),
+        (33, Some(0x02 | 0x03)) | (65, Some(0x04))
+    )
+}
+
+/// Returns data of the last push operation in the script, if the script
+/// consists of push operations only.
+fn last_push(script: &Script) -> Option<Vec<u8>> {
+    let mut last = None;
+    for instruction in script.instructions() {
+        match instruction.ok()? {
+            Instruction::PushBytes(data) => last = Some(data.to_vec()),
+            Instruction::Op(_) => return None,
+        }
+    }
+    last
+}
+
+/// Extracts public key from the P2WPKH spending witness.
+fn witness_key(witness: &Witness) -> Result<secp256k1::PublicKey, SpendDeductionError> {
+    if witness.len() != 2 {
+        return Err(SpendDeductionError::NoSpendingData);
+    }
+    let key = witness.last().expect("witness has two elements");
+    secp256k1::PublicKey::from_slice(key).map_err(|_| SpendDeductionError::InvalidPublicKey)
+}
+
+/// Reads script number encoded with a small integer opcode or a short push.
+fn read_number(instruction: Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::Op(opcode)
+            if (op::OP_PUSHNUM_1.to_u8()..=op::OP_PUSHNUM_16.to_u8()).contains(&opcode.to_u8()) =>
+        {
+            Some((opcode.to_u8() - op::OP_PUSHNUM_1.to_u8() + 1) as usize)
+        }
+        Instruction::PushBytes(&[n]) if n < 0x80 => Some(n as usize),
+        Instruction::PushBytes(&[lo, hi]) if hi < 0x80 => Some(lo as usize | ((hi as usize) << 8)),
+        _ => None,
+    }
+}
+
+/// Detects `threshold`-of-`keys` composition of the `multi` script.
+fn multi_composition(script: &Script) -> Option<(usize, usize)> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let (first, rest) = instructions.split_first()?;
+    let (last, rest) = rest.split_last()?;
+    let (count, keys) = rest.split_last()?;
+    if *last != Instruction::Op(op::OP_CHECKMULTISIG) {
+        return None;
+    }
+    let threshold = read_number(*first)?;
+    let count = read_number(*count)?;
+    let all_keys = keys.iter().all(
+        |key| matches!(key, Instruction::PushBytes(key) if key.len() == 33 || key.len() == 65),
+    );
+    if !all_keys || keys.len() != count || threshold == 0 || threshold > count {
+        return None;
+    }
+    Some((threshold, count))
+}
+
+/// Detects `threshold`-of-`keys` composition of the `multi_a` tapscript.
+fn multi_a_composition(script: &Script) -> Option<(usize, usize)> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let (last, rest) = instructions.split_last()?;
+    let (threshold, keys) = rest.split_last()?;
+    if *last != Instruction::Op(op::OP_NUMEQUAL) || keys.len() % 2 != 0 {
+        return None;
+    }
+    for (index, pair) in keys.chunks(2).enumerate() {
+        let checksig = if index == 0 {
+            op::OP_CHECKSIG
+        } else {
+            op::OP_CHECKSIGADD
+        };
+        match pair {
+            [Instruction::PushBytes(key), Instruction::Op(opcode)]
+                if key.len() == 32 && *opcode == checksig => {}
+            _ => return None,
+        }
+    }
+    let threshold = read_number(*threshold)?;
+    let count = keys.len() / 2;
+    if threshold == 0 || threshold > count {
+        return None;
+    }
+    Some((threshold, count))
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::secp256k1::{SecretKey, SECP256K1};
+
+    use super::*;
+
+    fn pubkey(seed: u8) -> secp256k1::PublicKey {
+        SecretKey::from_slice(&[seed; 32])
+            .unwrap()
+            .public_key(SECP256K1)
+    }
+
+    #[test]
+    fn wpkh_spending() {
+        let pk = pubkey(1);
+        let wpkh = bitcoin::PublicKey::new(pk).wpubkey_hash().unwrap();
+        let spk = PubkeyScript::from(Script::new_v0_p2wpkh(&wpkh));
+        let witness = Witness::from_vec(vec![vec![0x30; 71], pk.serialize().to_vec()]);
+        let deduction = SpendDeduction::deduce(&spk, &Script::new(), &witness).unwrap();
+        assert_eq!(deduction.descr_type, CompositeDescrType::Wpkh);
+        assert_eq!(deduction.descriptor, Some(BareDescriptor::Wpkh(pk)));
+        assert_eq!(deduction.multisig, None);
+
+        let witness = Witness::from_vec(vec![vec![0x30; 71], pubkey(2).serialize().to_vec()]);
+        assert_eq!(
+            SpendDeduction::deduce(&spk, &Script::new(), &witness),
+            Err(SpendDeductionError::ScriptMismatch)
+        );
+    }
+
+    #[test]
+    fn multisig_spending() {
+        let script = Builder::new()
+            .push_int(2)
+            .push_slice(&pubkey(1).serialize())
+            .push_slice(&pubkey(2).serialize())
+            .push_slice(&pubkey(3).serialize())
+            .push_int(3)
+            .push_opcode(op::OP_CHECKMULTISIG)
+            .into_script();
+        let witness_script = WitnessScript::from(script.clone());
+        let witness = Witness::from_vec(vec![
+            vec![],
+            vec![0x30; 71],
+            vec![0x30; 71],
+            script.to_bytes(),
+        ]);
+
+        let spk = PubkeyScript::from(Script::new_v0_p2wsh(&script.wscript_hash()));
+        let deduction = SpendDeduction::deduce(&spk, &Script::new(), &witness).unwrap();
+        assert_eq!(deduction.descr_type, CompositeDescrType::Wsh);
+        assert_eq!(
+            deduction.descriptor,
+            Some(BareDescriptor::Wsh(witness_script.clone()))
+        );
+        assert_eq!(deduction.multisig, Some((2, 3)));
+
+        let redeem_script = Script::new_v0_p2wsh(&script.wscript_hash());
+        let script_sig = Builder::new()
+            .push_slice(redeem_script.as_bytes())
+            .into_script();
+        let spk = PubkeyScript::from(Script::new_p2sh(&redeem_script.script_hash()));
+        let deduction = SpendDeduction::deduce(&spk, &script_sig, &witness).unwrap();
+        assert_eq!(deduction.descr_type, CompositeDescrType::ShWsh);
+        assert_eq!(
+            deduction.descriptor,
+            Some(BareDescriptor::ShWsh(witness_script))
+        );
+        assert_eq!(deduction.multisig, Some((2, 3)));
+    }
+
+    #[test]
+    fn taproot_spending() {
+        let (internal_key, _) = pubkey(1).x_only_public_key();
+        let leaf = Builder::new()
+            .push_slice(&pubkey(2).x_only_public_key().0.serialize())
+            .push_opcode(op::OP_CHECKSIG)
+            .push_slice(&pubkey(3).x_only_public_key().0.serialize())
+            .push_opcode(op::OP_CHECKSIGADD)
+            .push_int(1)
+            .push_opcode(op::OP_NUMEQUAL)
+            .into_script();
+        let sibling = sha256::Hash::hash(b"sibling");
+        let leaf_hash = TapLeafHash::from_script(&leaf, LeafVersion::TapScript);
+        let merkle_root = TapBranchHash::from_node_hashes(
+            sha256::Hash::from_inner(leaf_hash.into_inner()),
+            sibling,
+        );
+        let (output_key, output_key_parity) = internal_key.tap_tweak(SECP256K1, Some(merkle_root));
+        let control_block = ControlBlock {
+            leaf_version: LeafVersion::TapScript,
+            output_key_parity,
+            internal_key,
+            merkle_branch: TaprootMerkleBranch::from_slice(&sibling[..]).unwrap(),
+        };
+        let spk = PubkeyScript::from(Script::new_v1_p2tr_tweaked(output_key));
+
+        let witness = Witness::from_vec(vec![
+            vec![0x01; 64],
+            leaf.to_bytes(),
+            control_block.serialize(),
+        ]);
+        let deduction = SpendDeduction::deduce(&spk, &Script::new(), &witness).unwrap();
+        assert_eq!(deduction.descr_type, CompositeDescrType::Tr);
+        assert_eq!(
+            deduction.descriptor,
+            Some(BareDescriptor::Tr(internal_key, Some(merkle_root)))
+        );
+        assert_eq!(deduction.multisig, Some((1, 2)));
+        assert_eq!(
+            deduction.tap_leaf.map(|leaf| leaf.script.into_inner()),
+            Some(leaf)
+        );
+
+        let witness = Witness::from_vec(vec![vec![0x01; 64]]);
+        let deduction = SpendDeduction::deduce(&spk, &Script::new(), &witness).unwrap();
+        assert_eq!(deduction.descr_type, CompositeDescrType::Tr);
+        assert_eq!(deduction.descriptor, None);
+    }
+
+    #[test]
+    fn internal_key_recovery() {
+        let (internal_key, _) = pubkey(1).x_only_public_key();
+        let leaf = Builder::new()
+            .push_slice(&pubkey(2).x_only_public_key().0.serialize())
+            .push_opcode(op::OP_CHECKSIG)
+            .into_script();
+        let sibling = sha256::Hash::hash(b"sibling");
+        let merkle_branch = TaprootMerkleBranch::from_slice(&sibling[..]).unwrap();
+        let leaf_hash = TapLeafHash::from_script(&leaf, LeafVersion::TapScript);
+        let merkle_root = tap_merkle_root(leaf_hash, &merkle_branch);
+        assert_eq!(
+            merkle_root,
+            TapBranchHash::from_node_hashes(
+                sha256::Hash::from_inner(leaf_hash.into_inner()),
+                sibling
+            )
+        );
+        let (output_key, output_key_parity) = internal_key.tap_tweak(SECP256K1, Some(merkle_root));
+        let output_key = output_key.to_inner();
+        let mut control_block = ControlBlock {
+            leaf_version: LeafVersion::TapScript,
+            output_key_parity,
+            internal_key,
+            merkle_branch: merkle_branch.clone(),
+        };
+
+        let witness = Witness::from_vec(vec![
+            vec![0x01; 64],
+            leaf.to_bytes(),
+            control_block.serialize(),
+        ]);
+        assert_eq!(
+            recover_witness_internal_key(&witness, output_key),
+            Ok(TapLeafProof {
+                internal_key,
+                leaf_hash,
+                merkle_branch,
+                merkle_root
+            })
+        );
+        let foreign_key = pubkey(3).x_only_public_key().0;
+        assert_eq!(
+            recover_witness_internal_key(&witness, foreign_key),
+            Err(TapLeafError::OutputKeyMismatch(foreign_key))
+        );
+
+        control_block.output_key_parity = match output_key_parity {
+            secp256k1::Parity::Even => secp256k1::Parity::Odd,
+            secp256k1::Parity::Odd => secp256k1::Parity::Even,
+        };
+        let witness = Witness::from_vec(vec![
+            vec![0x01; 64],
+            leaf.to_bytes(),
+            control_block.serialize(),
+        ]);
+        assert_eq!(
+            recover_witness_internal_key(&witness, output_key),
+            Err(TapLeafError::ParityMismatch)
+        );
+
+        let witness = Witness::from_vec(vec![vec![0x01; 64]]);
+        assert_eq!(
+            recover_witness_internal_key(&witness, output_key),
+            Err(TapLeafError::KeySpending)
+        );
+    }
+
+    #[test]
+    fn future_witness() {
+        let future =
+            PubkeyScript::from(Script::new_witness_program(WitnessVersion::V2, &[0xAB; 32]));
+        assert_eq!(
+            CompositeDescrType::deduce(&future, None, false),
+            Ok(CompositeDescrType::Future)
+        );
+        let non_taproot =
+            PubkeyScript::from(Script::new_witness_program(WitnessVersion::V1, &[0xAB; 20]));
+        assert_eq!(
+            CompositeDescrType::deduce(&non_taproot, None, false),
+            Ok(CompositeDescrType::Future)
+        );
+        let witness = Witness::from_vec(vec![vec![0x01; 64]]);
+        let deduction = SpendDeduction::deduce(&future, &Script::new(), &witness).unwrap();
+        assert_eq!(deduction.descr_type, CompositeDescrType::Future);
+        assert_eq!(deduction.descriptor, None);
+
+        // Bare script starting with `OP_1` is not a witness program
+        let bare = PubkeyScript::from(
+            Builder::new()
+                .push_int(1)
+                .push_slice(&pubkey(1).serialize())
+                .push_int(1)
+                .push_opcode(op::OP_CHECKMULTISIG)
+                .into_script(),
+        );
+        assert_eq!(
+            CompositeDescrType::deduce(&bare, None, false),
+            Ok(CompositeDescrType::Bare)
+        );
+    }
+
+    #[test]
+    fn spending_heuristics() {
+        let key = pubkey(1).serialize().to_vec();
+        let sig = vec![0x30; 71];
+        let empty = Script::new();
+        let no_witness = Witness::default();
+
+        let witness = Witness::from_vec(vec![sig.clone(), key.clone()]);
+        assert_eq!(
+            CompositeDescrType::deduce_spending(&empty, &witness),
+            Some(CompositeDescrType::Wpkh)
+        );
+        let wpkh = bitcoin::PublicKey::new(pubkey(1)).wpubkey_hash().unwrap();
+        let program = Script::new_v0_p2wpkh(&wpkh);
+        let script_sig = Builder::new().push_slice(program.as_bytes()).into_script();
+        assert_eq!(
+            CompositeDescrType::deduce_spending(&script_sig, &witness),
+            Some(CompositeDescrType::ShWpkh)
+        );
+        let script_sig = Builder::new()
+            .push_slice(&sig)
+            .push_slice(&key)
+            .into_script();
+        assert_eq!(
+            CompositeDescrType::deduce_spending(&script_sig, &no_witness),
+            Some(CompositeDescrType::Pkh)
+        );
+        let witness = Witness::from_vec(vec![vec![0x01; 64]]);
+        assert_eq!(
+            CompositeDescrType::deduce_spending(&empty, &witness),
+            Some(CompositeDescrType::Tr)
+        );
+        let witness = Witness::from_vec(vec![vec![0x51]]);
+        assert_eq!(
+            CompositeDescrType::deduce_spending(&empty, &witness),
+            Some(CompositeDescrType::Wsh)
+        );
+        let script_sig = Builder::new().push_opcode(op::OP_DUP).into_script();
+        assert_eq!(
+            CompositeDescrType::deduce_spending(&script_sig, &no_witness),
+            None
+        );
+        assert_eq!(
+            CompositeDescrType::deduce_spending(&empty, &no_witness),
+            None
+        );
+
+        assert!(is_standard_script(&program));
+        assert!(!is_standard_script(&script_sig));
+        let data = Builder::new()
+            .push_opcode(op::OP_RETURN)
+            .push_slice(&[0u8; 81])
+            .into_script();
+        assert!(!is_standard_script(&data));
     }
 }
@@ -0,0 +1,301 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Delegation (cold staking) contracts: descriptors controlled by an owner
+//! key at any time, which may also be spent by a delegate key once a timelock
+//! expires.
+//!
+//! Delegation descriptors are ordinary miniscript descriptors, so derivation,
+//! address generation, PSBT construction and signing work for them in the same
+//! way as for any other wallet descriptor. Spending with the delegate key
+//! additionally requires the input sequence number and transaction lock time
+//! returned by [`Delegation::seq_no`] and [`Delegation::lock_time`].
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use bitcoin::{PackedLockTime, Sequence};
+use bitcoin_blockchain::locks::SeqNo;
+use miniscript::policy::{Concrete, Liftable, Semantic};
+use miniscript::{Descriptor, MiniscriptKey};
+
+use crate::policy::{compile, CompiledPolicy, PolicyError};
+use crate::DescriptorClass;
+
+/// Timelock restricting spendings with the delegate key
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum DelegationLock {
+    /// Relative timelock (`older` miniscript fragment), in blocks or 512-second
+    /// intervals since the output was mined
+    Older(Sequence),
+
+    /// Absolute timelock (`after` miniscript fragment), as a block height or
+    /// UNIX timestamp
+    After(PackedLockTime),
+}
+
+impl Display for DelegationLock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DelegationLock::Older(seq) => write!(f, "older({})", seq.to_consensus_u32()),
+            DelegationLock::After(lock_time) => write!(f, "after({})", lock_time.0),
+        }
+    }
+}
+
+/// Error parsing delegation timelock
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DelegationLockParseError {
+    /// invalid delegation timelock `{0}`; timelock must have either
+    /// `older(<blocks>)` or `after(<height>)` form
+    InvalidFormat(String),
+
+    /// relative timelock value {0} is not a valid `older` argument
+    InvalidOlder(u32),
+
+    /// absolute timelock value {0} is not a valid `after` argument
+    InvalidAfter(u32),
+}
+
+impl FromStr for DelegationLock {
+    type Err = DelegationLockParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DelegationLockParseError::InvalidFormat(s.to_owned());
+        let s = s.trim();
+        let (name, value) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(invalid)?;
+        let value = u32::from_str(value.trim()).map_err(|_| invalid())?;
+        match name.trim() {
+            "older" if value > 0 && Sequence(value).is_relative_lock_time() => {
+                Ok(DelegationLock::Older(Sequence(value)))
+            }
+            "older" => Err(DelegationLockParseError::InvalidOlder(value)),
+            "after" if value > 0 && value < 0x8000_0000 => {
+                Ok(DelegationLock::After(PackedLockTime(value)))
+            }
+            "after" => Err(DelegationLockParseError::InvalidAfter(value)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl DelegationLock {
+    fn to_policy<Pk: MiniscriptKey>(self) -> Concrete<Pk> {
+        match self {
+            DelegationLock::Older(seq) => Concrete::Older(seq),
+            DelegationLock::After(lock_time) => Concrete::After(lock_time),
+        }
+    }
+
+    fn from_policy<Pk: MiniscriptKey>(policy: &Semantic<Pk>) -> Option<Self> {
+        match policy {
+            Semantic::Older(seq) => Some(DelegationLock::Older(*seq)),
+            Semantic::After(lock_time) => Some(DelegationLock::After(*lock_time)),
+            _ => None,
+        }
+    }
+}
+
+/// Role of a key in a delegation contract
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+#[display(lowercase)]
+pub enum DelegationRole {
+    /// Owner of the funds, which may spend them at any time
+    Owner,
+
+    /// Delegate, which may spend the funds only after the timelock expiry
+    Delegate,
+}
+
+/// Delegation contract between an owner and a delegate keys
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Delegation<Pk>
+where
+    Pk: MiniscriptKey,
+{
+    /// Owner key, which may spend funds at any time
+    pub owner: Pk,
+
+    /// Delegate key, which may spend funds after the timelock expiry
+    pub delegate: Pk,
+
+    /// Timelock restricting spendings with the delegate key
+    pub lock: DelegationLock,
+}
+
+impl<Pk> Delegation<Pk>
+where
+    Pk: MiniscriptKey,
+{
+    /// Constructs delegation contract from the owner and delegate keys and the
+    /// delegate timelock
+    pub fn with(owner: Pk, delegate: Pk, lock: DelegationLock) -> Self {
+        Delegation {
+            owner,
+            delegate,
+            lock,
+        }
+    }
+
+    /// Returns concrete spending policy of the contract. Owner spending path
+    /// is assumed to be more probable, so for taproot descriptors the owner
+    /// key becomes the internal key.
+    pub fn policy(&self) -> Concrete<Pk> {
+        Concrete::Or(vec![
+            (9, Concrete::Key(self.owner.clone())),
+            (
+                1,
+                Concrete::And(vec![
+                    Concrete::Key(self.delegate.clone()),
+                    self.lock.to_policy(),
+                ]),
+            ),
+        ])
+    }
+
+    /// Compiles the contract into a descriptor of a given class
+    pub fn compile(&self, class: DescriptorClass) -> Result<CompiledPolicy<Pk>, PolicyError> {
+        compile(&self.policy(), class)
+    }
+
+    /// Detects delegation contract from the spending policy of a descriptor.
+    /// Returns `None` if the descriptor is not a delegation contract.
+    pub fn from_descriptor(descriptor: &Descriptor<Pk>) -> Option<Self> {
+        let policy = descriptor.lift().ok()?.normalized();
+        let branches = match policy {
+            Semantic::Threshold(1, ref branches) if branches.len() == 2 => branches,
+            _ => return None,
+        };
+        let (owner, restricted) = match (&branches[0], &branches[1]) {
+            (Semantic::Key(owner), restricted) | (restricted, Semantic::Key(owner)) => {
+                (owner, restricted)
+            }
+            _ => return None,
+        };
+        let (delegate, lock) = match restricted {
+            Semantic::Threshold(2, conditions) if conditions.len() == 2 => {
+                match (&conditions[0], &conditions[1]) {
+                    (Semantic::Key(delegate), lock) | (lock, Semantic::Key(delegate)) => {
+                        (delegate, DelegationLock::from_policy(lock)?)
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some(Delegation::with(owner.clone(), delegate.clone(), lock))
+    }
+
+    /// Detects role of the key in the contract
+    pub fn role(&self, key: &Pk) -> Option<DelegationRole> {
+        if key == &self.owner {
+            Some(DelegationRole::Owner)
+        } else if key == &self.delegate {
+            Some(DelegationRole::Delegate)
+        } else {
+            None
+        }
+    }
+
+    /// Returns sequence number which must be used by transaction inputs
+    /// spending the contract with a given role, or `None` if the role does
+    /// not put restrictions on the sequence number.
+    pub fn seq_no(&self, role: DelegationRole) -> Option<SeqNo> {
+        match (role, self.lock) {
+            (DelegationRole::Owner, _) => None,
+            (DelegationRole::Delegate, DelegationLock::Older(seq)) => {
+                Some(SeqNo::from_consensus(seq.to_consensus_u32()))
+            }
+            // Absolute timelocks are enforced only for inputs with non-final
+            // sequence numbers
+            (DelegationRole::Delegate, DelegationLock::After(_)) => Some(SeqNo::from_consensus(
+                Sequence::ENABLE_LOCKTIME_NO_RBF.to_consensus_u32(),
+            )),
+        }
+    }
+
+    /// Returns minimal lock time of a transaction spending the contract with
+    /// a given role, or `None` if the role does not require lock time.
+    pub fn lock_time(&self, role: DelegationRole) -> Option<PackedLockTime> {
+        match (role, self.lock) {
+            (DelegationRole::Delegate, DelegationLock::After(lock_time)) => Some(lock_time),
+            _ => None,
+        }
+    }
+}
+
+impl<Pk> Display for Delegation<Pk>
+where
+    Pk: MiniscriptKey,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { Display::fmt(&self.policy(), f) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delegation_roundtrip() {
+        let lock = DelegationLock::from_str("older(144)").unwrap();
+        assert_eq!(lock, DelegationLock::Older(Sequence(144)));
+        assert_eq!(lock.to_string(), "older(144)");
+        assert!(DelegationLock::from_str("older(0)").is_err());
+        assert!(DelegationLock::from_str("before(10)").is_err());
+
+        let delegation = Delegation::with(s!("A"), s!("B"), lock);
+        assert_eq!(delegation.role(&s!("B")), Some(DelegationRole::Delegate));
+        assert_eq!(delegation.role(&s!("C")), None);
+        assert_eq!(delegation.seq_no(DelegationRole::Owner), None);
+        assert_eq!(
+            delegation.seq_no(DelegationRole::Delegate),
+            Some(SeqNo::from_consensus(144))
+        );
+        assert_eq!(delegation.lock_time(DelegationRole::Delegate), None);
+
+        for class in [
+            DescriptorClass::PreSegwit,
+            DescriptorClass::SegwitV0,
+            DescriptorClass::NestedV0,
+            DescriptorClass::TaprootC0,
+        ] {
+            let compiled = delegation.compile(class).unwrap();
+            assert_eq!(
+                Delegation::from_descriptor(&compiled.descriptor),
+                Some(delegation.clone())
+            );
+        }
+        let tr = delegation.compile(DescriptorClass::TaprootC0).unwrap();
+        assert!(tr.to_string().starts_with("tr(A,"));
+
+        let after = Delegation::with(
+            s!("A"),
+            s!("B"),
+            DelegationLock::After(PackedLockTime(800_000)),
+        );
+        assert_eq!(
+            after.lock_time(DelegationRole::Delegate),
+            Some(PackedLockTime(800_000))
+        );
+        let compiled = after.compile(DescriptorClass::SegwitV0).unwrap();
+        assert_eq!(
+            Delegation::from_descriptor(&compiled.descriptor),
+            Some(after)
+        );
+
+        let multi = Descriptor::<String>::from_str("wsh(multi(1,A,B))").unwrap();
+        assert_eq!(Delegation::from_descriptor(&multi), None);
+    }
+}
@@ -15,6 +15,7 @@ use bitcoin::{Network, Script};
 use bitcoin_hd::{DerivationAccount, DerivePatternError};
 use bitcoin_hd::{DeriveError, UnhardenedIndex};
 use bitcoin_scripts::address::AddressCompat;
+use slip132::NetworkParams;
 
 #[cfg(not(feature = "miniscript"))]
 pub mod miniscript {
@@ -50,18 +51,32 @@ pub trait Descriptor<Key> {
     /// participating descriptor
     fn derive_pattern_len(&self) -> Result<usize, DeriveError>;
 
-    /// Detects bitcoin network which should be used with the provided
-    /// descriptor
-    fn network(&self, regtest: bool) -> Result<Network, DeriveError>;
+    /// Detects bitcoin network of the keys used in the descriptor. Since
+    /// testnet, signet and regtest share the same extended key versions, this
+    /// is either [`Network::Bitcoin`] or [`Network::Testnet`].
+    fn network(&self) -> Result<Network, DeriveError>;
 
-    /// Generates address from the descriptor for specific derive pattern
+    /// Generates address from the descriptor for specific derive pattern for
+    /// a given network. Errors with [`DeriveError::InconsistentKeyNetwork`] if
+    /// the descriptor keys can't be used on the network.
     fn address<C: Verification>(
         &self,
         secp: &Secp256k1<C>,
         pat: impl AsRef<[UnhardenedIndex]>,
-        regtest: bool,
+        network: Network,
     ) -> Result<AddressCompat, DeriveError>;
 
+    /// Generates address string from the descriptor for specific derive
+    /// pattern using address prefixes of a custom network. Errors with
+    /// [`DeriveError::InconsistentKeyNetwork`] if the descriptor keys can't be
+    /// used with the base network of the provided parameters.
+    fn address_with_params<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        pat: impl AsRef<[UnhardenedIndex]>,
+        params: &NetworkParams,
+    ) -> Result<String, DeriveError>;
+
     /// Creates scriptPubkey for specific derive pattern in pre-taproot
     /// descriptors
     fn script_pubkey_pretr<C: Verification>(
@@ -86,6 +101,7 @@ mod ms {
     use bitcoin_hd::account::DerivePublicKey;
     use bitcoin_hd::SegmentIndexes;
     use bitcoin_scripts::address::AddressNetwork;
+    use miniscript::descriptor::{DescriptorPublicKey, DescriptorType, SinglePub, SinglePubKey};
     use miniscript::{translate_hash_fail, ForEachKey, TranslatePk, Translator};
 
     use super::*;
@@ -121,6 +137,72 @@ mod ms {
         translate_hash_fail!(DerivationAccount, XOnlyPublicKey, DerivePatternError);
     }
 
+    struct PinTranslator<'a, C: Verification> {
+        secp: &'a Secp256k1<C>,
+        pat: &'a [UnhardenedIndex],
+        xonly: bool,
+    }
+
+    impl<'a, C> Translator<DerivationAccount, DescriptorPublicKey, DerivePatternError>
+        for PinTranslator<'a, C>
+    where
+        C: Verification,
+    {
+        fn pk(
+            &mut self,
+            pk: &DerivationAccount,
+        ) -> Result<DescriptorPublicKey, DerivePatternError> {
+            let pubkey = pk.derive_public_key(self.secp, self.pat)?;
+            let origin = match pk.master_fingerprint() {
+                Some(fingerprint) => (fingerprint, pk.to_full_derivation_path(self.pat)?),
+                None => (
+                    pk.account_fingerprint(),
+                    pk.to_terminal_derivation_path(self.pat)?,
+                ),
+            };
+            let key = if self.xonly {
+                SinglePubKey::XOnly(XOnlyPublicKey::from(pubkey))
+            } else {
+                SinglePubKey::FullKey(bitcoin::PublicKey::new(pubkey))
+            };
+            Ok(DescriptorPublicKey::Single(SinglePub {
+                origin: Some(origin),
+                key,
+            }))
+        }
+
+        translate_hash_fail!(DerivationAccount, DescriptorPublicKey, DerivePatternError);
+    }
+
+    /// Pins descriptor wildcards to specific indexes, producing non-ranged
+    /// descriptor with single public keys. Key origins are extended with the
+    /// terminal derivation path, such that they point to the derived keys.
+    ///
+    /// Pinned descriptor is used to provide counterparties with a descriptor
+    /// for a single payment when they are unable to process ranged
+    /// descriptors.
+    impl DeriveDescriptor<DescriptorPublicKey> for miniscript::Descriptor<DerivationAccount>
+    where
+        Self: TranslatePk<DerivationAccount, DescriptorPublicKey>,
+    {
+        type Output = miniscript::Descriptor<DescriptorPublicKey>;
+
+        fn derive_descriptor<C: Verification>(
+            &self,
+            secp: &Secp256k1<C>,
+            pat: impl AsRef<[UnhardenedIndex]>,
+        ) -> Result<miniscript::Descriptor<DescriptorPublicKey>, DeriveError> {
+            let pat = pat.as_ref();
+            if pat.len() != self.derive_pattern_len()? {
+                return Err(DeriveError::DerivePatternMismatch);
+            }
+            let xonly = self.desc_type() == DescriptorType::Tr;
+            let mut translator = PinTranslator { secp, pat, xonly };
+            <miniscript::Descriptor<DerivationAccount> as TranslatePk<_, DescriptorPublicKey>>::translate_pk(self, &mut translator)
+                .map_err(DeriveError::from)
+        }
+    }
+
     impl DeriveDescriptor<bitcoin::PublicKey> for miniscript::Descriptor<DerivationAccount>
     where
         Self: TranslatePk<DerivationAccount, bitcoin::PublicKey>,
@@ -167,7 +249,7 @@ mod ms {
         #[inline]
         fn check_sanity(&self) -> Result<(), DeriveError> {
             self.derive_pattern_len()?;
-            self.network(false)?;
+            self.network()?;
             Ok(())
         }
 
@@ -191,23 +273,22 @@ mod ms {
             len.get().ok_or(DeriveError::NoKeys)
         }
 
-        fn network(&self, regtest: bool) -> Result<Network, DeriveError> {
+        fn network(&self) -> Result<Network, DeriveError> {
             let network = Cell::new(None);
-            self.for_each_key(|key| match (network.get(), key.account_xpub.network) {
-                (None, net) => {
-                    network.set(Some(net));
-                    true
-                }
-                (Some(net1), net2) if net1 != net2 => false,
-                _ => true,
-            });
-            let network = network.get().ok_or(DeriveError::NoKeys)?;
-            match (network, regtest) {
-                (network, false) => Ok(network),
-                (Network::Testnet | Network::Signet | Network::Regtest, true) if regtest => {
-                    Ok(Network::Regtest)
-                }
-                _ => Err(DeriveError::InconsistentKeyNetwork),
+            let consistent =
+                self.for_each_key(|key| match (network.get(), key.account_xpub.network) {
+                    (None, net) => {
+                        network.set(Some(net));
+                        true
+                    }
+                    (Some(net1), net2) => net1 == net2,
+                });
+            if !consistent {
+                return Err(DeriveError::InconsistentKeyNetwork);
+            }
+            match network.get().ok_or(DeriveError::NoKeys)? {
+                Network::Bitcoin => Ok(Network::Bitcoin),
+                _ => Ok(Network::Testnet),
             }
         }
 
@@ -216,14 +297,33 @@ mod ms {
             &self,
             secp: &Secp256k1<C>,
             pat: impl AsRef<[UnhardenedIndex]>,
-            regtest: bool,
+            network: Network,
         ) -> Result<AddressCompat, DeriveError> {
-            let network = AddressNetwork::from(self.network(regtest)?);
+            if (self.network()? == Network::Bitcoin) != (network == Network::Bitcoin) {
+                return Err(DeriveError::InconsistentKeyNetwork);
+            }
+            let network = AddressNetwork::from(network);
             let spk = Descriptor::script_pubkey_pretr(self, secp, pat)?;
             AddressCompat::from_script(&spk.into(), network)
                 .ok_or(DeriveError::NoAddressForDescriptor)
         }
 
+        #[inline]
+        fn address_with_params<C: Verification>(
+            &self,
+            secp: &Secp256k1<C>,
+            pat: impl AsRef<[UnhardenedIndex]>,
+            params: &NetworkParams,
+        ) -> Result<String, DeriveError> {
+            if (self.network()? == Network::Bitcoin) != (params.base == Network::Bitcoin) {
+                return Err(DeriveError::InconsistentKeyNetwork);
+            }
+            let spk = Descriptor::script_pubkey_pretr(self, secp, pat)?;
+            params
+                .encode_address(&spk)
+                .ok_or(DeriveError::NoAddressForDescriptor)
+        }
+
         #[inline]
         fn script_pubkey_pretr<C: Verification>(
             &self,
@@ -246,3 +346,71 @@ mod ms {
         }
     }
 }
+
+#[cfg(all(test, feature = "miniscript"))]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::secp256k1::SECP256K1;
+    use miniscript::descriptor::DescriptorPublicKey;
+
+    use super::*;
+
+    #[test]
+    fn pin_descriptor() {
+        let descriptor = miniscript::Descriptor::<DerivationAccount>::from_str(
+            "wpkh([d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqF\
+             JPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/0/*)",
+        )
+        .unwrap();
+        let pat = [UnhardenedIndex::from(5u8)];
+
+        let pinned =
+            DeriveDescriptor::<DescriptorPublicKey>::derive_descriptor(&descriptor, SECP256K1, pat)
+                .unwrap();
+        assert!(!pinned.has_wildcard());
+        assert!(pinned.to_string().contains("[d34db33f/84'/0'/0'/0/5]"));
+        assert_eq!(
+            pinned.at_derivation_index(0).script_pubkey(),
+            descriptor.script_pubkey_pretr(SECP256K1, pat).unwrap()
+        );
+
+        assert!(matches!(
+            DeriveDescriptor::<DescriptorPublicKey>::derive_descriptor(&descriptor, SECP256K1, []),
+            Err(DeriveError::DerivePatternMismatch)
+        ));
+    }
+
+    #[test]
+    fn address_with_params() {
+        let descriptor = miniscript::Descriptor::<DerivationAccount>::from_str(
+            "wpkh([d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqF\
+             JPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/0/*)",
+        )
+        .unwrap();
+        let pat = [UnhardenedIndex::from(5u8)];
+
+        let params = NetworkParams::from(Network::Bitcoin);
+        assert_eq!(
+            descriptor
+                .address_with_params(SECP256K1, pat, &params)
+                .unwrap(),
+            descriptor
+                .address(SECP256K1, pat, Network::Bitcoin)
+                .unwrap()
+                .to_string()
+        );
+
+        let mut params = NetworkParams::from(Network::Bitcoin);
+        params.bech32_hrp = s!("ex");
+        assert!(descriptor
+            .address_with_params(SECP256K1, pat, &params)
+            .unwrap()
+            .starts_with("ex1q"));
+
+        assert!(matches!(
+            descriptor.address_with_params(SECP256K1, pat, &NetworkParams::from(Network::Regtest)),
+            Err(DeriveError::InconsistentKeyNetwork)
+        ));
+    }
+}
@@ -29,22 +29,46 @@ use miniscript::descriptor::DescriptorType;
 #[cfg(feature = "miniscript")]
 use miniscript::policy::compiler::CompilerError;
 #[cfg(feature = "miniscript")]
+use miniscript::{BareCtx, Legacy, Miniscript, ScriptContext, Segwitv0};
+#[cfg(feature = "miniscript")]
 use miniscript::{Descriptor, MiniscriptKey, Terminal};
 
-#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
 #[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[cfg_attr(
     feature = "serde",
     derive(Serialize, Deserialize),
     serde(crate = "serde_crate")
 )]
 pub enum DescriptorClass {
+    #[display("sh")]
     PreSegwit,
+
+    #[display("wsh")]
     SegwitV0,
+
+    #[display("sh-wsh")]
     NestedV0,
+
+    #[display("tr")]
     TaprootC0,
 }
 
+impl FromStr for DescriptorClass {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().trim() {
+            "sh" | "legacy" | "presegwit" => DescriptorClass::PreSegwit,
+            "wsh" | "segwit" | "segwitv0" => DescriptorClass::SegwitV0,
+            "sh-wsh" | "shwsh" | "nested" => DescriptorClass::NestedV0,
+            "tr" | "taproot" => DescriptorClass::TaprootC0,
+            unknown => return Err(ParseError::UnrecognizedDescriptorName(unknown.to_owned())),
+        })
+    }
+}
+
 impl From<&DescriptorType> for DescriptorClass {
     fn from(ty: &DescriptorType) -> Self {
         match ty {
@@ -98,6 +122,7 @@ impl DescriptorClass {
     Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Default, Debug, Display
 )]
 #[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[repr(u8)]
 pub enum SpkClass {
     #[display("bare")]
@@ -112,6 +137,9 @@ pub enum SpkClass {
 
     #[display("taproot")]
     Taproot,
+
+    #[display("future")]
+    Future,
 }
 
 impl SpkClass {
@@ -126,6 +154,7 @@ impl SpkClass {
             (SpkClass::SegWit, true) => InnerDescrType::Wsh,
 
             (SpkClass::Taproot, _) => InnerDescrType::Tr,
+            (SpkClass::Future, _) => InnerDescrType::Future,
         }
     }
 
@@ -140,6 +169,7 @@ impl SpkClass {
             (SpkClass::SegWit, true) => OuterDescrType::Wsh,
 
             (SpkClass::Taproot, _) => OuterDescrType::Tr,
+            (SpkClass::Future, _) => OuterDescrType::Future,
         }
     }
 
@@ -154,6 +184,7 @@ impl SpkClass {
             (SpkClass::SegWit, true) => OuterDescrType::Sh,
 
             (SpkClass::Taproot, _) => OuterDescrType::Tr,
+            (SpkClass::Future, _) => OuterDescrType::Future,
         }
     }
 }
@@ -168,6 +199,7 @@ impl From<CompositeDescrType> for SpkClass {
             | CompositeDescrType::ShWpkh
             | CompositeDescrType::ShWsh => SpkClass::SegWit,
             CompositeDescrType::Tr => SpkClass::Taproot,
+            CompositeDescrType::Future => SpkClass::Future,
         }
     }
 }
@@ -192,6 +224,7 @@ impl FromStr for SpkClass {
             "hashed" | "pkh" | "sh" => SpkClass::Hashed,
             "segwit" | "wsh" | "shwsh" | "wpkh" | "shwpkh" => SpkClass::SegWit,
             "taproot" | "tr" => SpkClass::Taproot,
+            "future" => SpkClass::Future,
             unknown => return Err(ParseError::UnrecognizedDescriptorName(unknown.to_owned())),
         })
     }
@@ -204,6 +237,7 @@ impl FromStr for SpkClass {
 )]
 #[derive(Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
 #[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[repr(u8)]
 pub enum CompositeDescrType {
     #[display("bare")]
@@ -232,6 +266,11 @@ pub enum CompositeDescrType {
 
     #[display("tr")]
     Tr,
+
+    /// Witness program of a future witness version, or of witness version 1
+    /// which is not taproot
+    #[display("future")]
+    Future,
 }
 
 impl CompositeDescrType {
@@ -242,6 +281,7 @@ impl CompositeDescrType {
             CompositeDescrType::Wpkh | CompositeDescrType::Wsh => SpkClass::SegWit,
             CompositeDescrType::ShWpkh | CompositeDescrType::ShWsh => SpkClass::Hashed,
             CompositeDescrType::Tr => SpkClass::Taproot,
+            CompositeDescrType::Future => SpkClass::Future,
         }
     }
 
@@ -252,9 +292,25 @@ impl CompositeDescrType {
             CompositeDescrType::Wpkh | CompositeDescrType::Wsh => SpkClass::SegWit,
             CompositeDescrType::ShWpkh | CompositeDescrType::ShWsh => SpkClass::SegWit,
             CompositeDescrType::Tr => SpkClass::Taproot,
+            CompositeDescrType::Future => SpkClass::Future,
         }
     }
 
+    /// Returns descriptor class of the type, or `None` for future witness
+    /// versions, which have no descriptors defined.
+    pub fn descriptor_class(self) -> Option<DescriptorClass> {
+        Some(match self {
+            CompositeDescrType::Bare
+            | CompositeDescrType::Pk
+            | CompositeDescrType::Pkh
+            | CompositeDescrType::Sh => DescriptorClass::PreSegwit,
+            CompositeDescrType::Wpkh | CompositeDescrType::Wsh => DescriptorClass::SegwitV0,
+            CompositeDescrType::ShWpkh | CompositeDescrType::ShWsh => DescriptorClass::NestedV0,
+            CompositeDescrType::Tr => DescriptorClass::TaprootC0,
+            CompositeDescrType::Future => return None,
+        })
+    }
+
     #[inline]
     pub fn is_segwit(self) -> bool { self.inner_category() == SpkClass::SegWit }
 
@@ -317,6 +373,7 @@ impl FromStr for CompositeDescrType {
             "wpkh" => CompositeDescrType::Wpkh,
             "wsh" => CompositeDescrType::Wsh,
             "tr" => CompositeDescrType::Tr,
+            "future" => CompositeDescrType::Future,
             unknown => return Err(ParseError::UnrecognizedDescriptorName(unknown.to_owned())),
         })
     }
@@ -329,6 +386,7 @@ impl FromStr for CompositeDescrType {
 )]
 #[derive(Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
 #[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[repr(u8)]
 pub enum OuterDescrType {
     #[display("bare")]
@@ -351,6 +409,9 @@ pub enum OuterDescrType {
 
     #[display("tr")]
     Tr,
+
+    #[display("future")]
+    Future,
 }
 
 impl OuterDescrType {
@@ -360,6 +421,7 @@ impl OuterDescrType {
             OuterDescrType::Pkh | OuterDescrType::Sh => SpkClass::Hashed,
             OuterDescrType::Wpkh | OuterDescrType::Wsh => SpkClass::SegWit,
             OuterDescrType::Tr => SpkClass::Taproot,
+            OuterDescrType::Future => SpkClass::Future,
         }
     }
 }
@@ -376,6 +438,7 @@ impl From<CompositeDescrType> for OuterDescrType {
             CompositeDescrType::ShWpkh => OuterDescrType::Sh,
             CompositeDescrType::ShWsh => OuterDescrType::Sh,
             CompositeDescrType::Tr => OuterDescrType::Tr,
+            CompositeDescrType::Future => OuterDescrType::Future,
         }
     }
 }
@@ -400,6 +463,7 @@ impl FromStr for OuterDescrType {
             "wpkh" => OuterDescrType::Wpkh,
             "wsh" => OuterDescrType::Wsh,
             "tr" => OuterDescrType::Tr,
+            "future" => OuterDescrType::Future,
             unknown => return Err(ParseError::UnrecognizedDescriptorName(unknown.to_owned())),
         })
     }
@@ -412,6 +476,7 @@ impl FromStr for OuterDescrType {
 )]
 #[derive(Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
 #[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[repr(u8)]
 pub enum InnerDescrType {
     #[display("bare")]
@@ -434,6 +499,9 @@ pub enum InnerDescrType {
 
     #[display("tr")]
     Tr,
+
+    #[display("future")]
+    Future,
 }
 
 impl InnerDescrType {
@@ -443,6 +511,7 @@ impl InnerDescrType {
             InnerDescrType::Pkh | InnerDescrType::Sh => SpkClass::Hashed,
             InnerDescrType::Wpkh | InnerDescrType::Wsh => SpkClass::SegWit,
             InnerDescrType::Tr => SpkClass::Taproot,
+            InnerDescrType::Future => SpkClass::Future,
         }
     }
 }
@@ -459,6 +528,7 @@ impl From<CompositeDescrType> for InnerDescrType {
             CompositeDescrType::ShWpkh => InnerDescrType::Wpkh,
             CompositeDescrType::ShWsh => InnerDescrType::Wsh,
             CompositeDescrType::Tr => InnerDescrType::Tr,
+            CompositeDescrType::Future => InnerDescrType::Future,
         }
     }
 }
@@ -483,6 +553,7 @@ impl FromStr for InnerDescrType {
             "wpkh" | "shwpkh" => InnerDescrType::Wpkh,
             "wsh" | "shwsh" => InnerDescrType::Wsh,
             "tr" => InnerDescrType::Tr,
+            "future" => InnerDescrType::Future,
             unknown => return Err(ParseError::UnrecognizedDescriptorName(unknown.to_owned())),
         })
     }
@@ -495,6 +566,7 @@ impl FromStr for InnerDescrType {
 )]
 #[derive(Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default)]
 #[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 #[repr(C)]
 pub struct DescrVariants {
     pub bare: bool,
@@ -589,6 +661,32 @@ pub enum ScriptPubkeyDescr {
 
     #[display("tr({0})")]
     Tr(TweakedPublicKey),
+
+    /// Witness program of a version which has no consensus meaning yet: v1
+    /// program which is not a taproot output, or v2 and above program.
+    #[display("future({0})", alt = "future({0:#})")]
+    Future(PubkeyScript),
+}
+
+impl ScriptPubkeyDescr {
+    /// Returns witness version of the `scriptPubkey`, if it is a witness
+    /// program
+    pub fn witness_version(&self) -> Option<WitnessVersion> {
+        match self {
+            ScriptPubkeyDescr::Bare(_)
+            | ScriptPubkeyDescr::Pk(_)
+            | ScriptPubkeyDescr::Pkh(_)
+            | ScriptPubkeyDescr::Sh(_) => None,
+            ScriptPubkeyDescr::Wpkh(_) | ScriptPubkeyDescr::Wsh(_) => Some(WitnessVersion::V0),
+            ScriptPubkeyDescr::Tr(_) => Some(WitnessVersion::V1),
+            ScriptPubkeyDescr::Future(spk) => spk.witness_version(),
+        }
+    }
+
+    /// Detects whether the `scriptPubkey` is a witness program of a version
+    /// which has no consensus meaning yet
+    #[inline]
+    pub fn is_future(&self) -> bool { matches!(self, ScriptPubkeyDescr::Future(_)) }
 }
 
 impl FromStr for ScriptPubkeyDescr {
@@ -628,6 +726,15 @@ impl FromStr for ScriptPubkeyDescr {
             Ok(ScriptPubkeyDescr::Wsh(
                 inner.parse().map_err(|_| Error::CantParseDescriptor)?,
             ))
+        } else if s.starts_with("future(") {
+            let inner = s.trim_start_matches("future(");
+            let script: PubkeyScript = Script::from_str(inner)
+                .map_err(|_| Error::CantParseDescriptor)?
+                .into();
+            match ScriptPubkeyDescr::try_from(script) {
+                Ok(descr @ ScriptPubkeyDescr::Future(_)) => Ok(descr),
+                _ => Err(Error::CantParseDescriptor),
+            }
         } else if s.starts_with("tr(") {
             let inner = s.trim_start_matches("tr(");
             let pk = XOnlyPublicKey::from_str(inner).map_err(|_| Error::CantParseDescriptor)?;
@@ -651,12 +758,6 @@ pub enum UnsupportedScriptPubkey {
     #[from(bitcoin::util::key::Error)]
     #[from(secp256k1::Error)]
     WrongPubkeyValue,
-
-    /// input spends non-taproot witness version 1
-    NonTaprootV1,
-
-    /// input spends future witness version {0}
-    UnsupportedWitnessVersion(WitnessVersion),
 }
 
 impl TryFrom<PubkeyScript> for ScriptPubkeyDescr {
@@ -697,9 +798,24 @@ impl TryFrom<PubkeyScript> for ScriptPubkeyDescr {
                 hash_inner.copy_from_slice(&bytes[2..22]);
                 Ok(ScriptPubkeyDescr::Sh(ScriptHash::from_inner(hash_inner)))
             }
-            (_, Some(WitnessVersion::V1)) => Err(UnsupportedScriptPubkey::NonTaprootV1),
-            (_, Some(version)) => Err(UnsupportedScriptPubkey::UnsupportedWitnessVersion(version)),
-            (_, None) => Ok(ScriptPubkeyDescr::Bare(spk)),
+            (_, Some(version)) if version != WitnessVersion::V0 && script.is_witness_program() => {
+                Ok(ScriptPubkeyDescr::Future(spk))
+            }
+            _ => Ok(ScriptPubkeyDescr::Bare(spk)),
+        }
+    }
+}
+
+impl From<ScriptPubkeyDescr> for PubkeyScript {
+    fn from(descr: ScriptPubkeyDescr) -> Self {
+        match descr {
+            ScriptPubkeyDescr::Bare(spk) | ScriptPubkeyDescr::Future(spk) => spk,
+            ScriptPubkeyDescr::Pk(pk) => Script::new_p2pk(&pk).into(),
+            ScriptPubkeyDescr::Pkh(hash) => Script::new_p2pkh(&hash).into(),
+            ScriptPubkeyDescr::Sh(hash) => Script::new_p2sh(&hash).into(),
+            ScriptPubkeyDescr::Wpkh(hash) => Script::new_v0_p2wpkh(&hash).into(),
+            ScriptPubkeyDescr::Wsh(hash) => Script::new_v0_p2wsh(&hash).into(),
+            ScriptPubkeyDescr::Tr(output_key) => Script::new_v1_p2tr_tweaked(output_key).into(),
         }
     }
 }
@@ -867,6 +983,128 @@ impl BareDescriptor {
     }
 }
 
+#[cfg(feature = "miniscript")]
+impl ScriptPubkeyDescr {
+    /// Converts script pubkey descriptor into miniscript descriptor. Only
+    /// `pk` and `bare` script pubkeys, which bare script is a valid
+    /// miniscript, can be converted: other script pubkeys commit to a hash or
+    /// a tweak of the spending conditions.
+    pub fn into_descriptor(
+        self,
+    ) -> Result<Descriptor<bitcoin::PublicKey>, MiniscriptConversionError> {
+        match self {
+            ScriptPubkeyDescr::Bare(spk) => {
+                let ms = Miniscript::<_, BareCtx>::parse_insane(spk.as_inner())?;
+                Ok(Descriptor::new_bare(ms)?)
+            }
+            ScriptPubkeyDescr::Pk(pk) => Ok(Descriptor::new_pk(pk)),
+            ScriptPubkeyDescr::Tr(output_key) => {
+                Err(MiniscriptConversionError::TweakedKey(output_key))
+            }
+            ScriptPubkeyDescr::Future(spk) => Err(MiniscriptConversionError::FutureWitness(spk)),
+            descr => Err(MiniscriptConversionError::HashCommitment(descr)),
+        }
+    }
+}
+
+#[cfg(feature = "miniscript")]
+impl BareDescriptor {
+    /// Converts bare descriptor into miniscript descriptor. Multisig scripts
+    /// with lexicographically sorted keys are converted into `sortedmulti`
+    /// descriptors; taproot descriptors can be converted only if they do not
+    /// commit to a script tree, which is known only by its merkle root.
+    pub fn into_miniscript(
+        self,
+    ) -> Result<Descriptor<bitcoin::PublicKey>, MiniscriptConversionError> {
+        Ok(match self {
+            BareDescriptor::Bare(spk) => {
+                Descriptor::new_bare(Miniscript::<_, BareCtx>::parse_insane(spk.as_inner())?)?
+            }
+            BareDescriptor::Pk(pk) => Descriptor::new_pk(pk),
+            BareDescriptor::Pkh(pk) => Descriptor::new_pkh(pk),
+            BareDescriptor::Sh(script) => {
+                let ms = Miniscript::<_, Legacy>::parse_insane(script.as_inner())?;
+                match sorted_multi(&ms) {
+                    Some((threshold, keys)) => Descriptor::new_sh_sortedmulti(threshold, keys)?,
+                    None => Descriptor::new_sh(ms)?,
+                }
+            }
+            BareDescriptor::ShWpkh(pk) => Descriptor::new_sh_wpkh(bitcoin::PublicKey::new(pk))?,
+            BareDescriptor::ShWsh(script) => {
+                let ms = Miniscript::<_, Segwitv0>::parse_insane(script.as_inner())?;
+                match sorted_multi(&ms) {
+                    Some((threshold, keys)) => Descriptor::new_sh_wsh_sortedmulti(threshold, keys)?,
+                    None => Descriptor::new_sh_wsh(ms)?,
+                }
+            }
+            BareDescriptor::Wpkh(pk) => Descriptor::new_wpkh(bitcoin::PublicKey::new(pk))?,
+            BareDescriptor::Wsh(script) => {
+                let ms = Miniscript::<_, Segwitv0>::parse_insane(script.as_inner())?;
+                match sorted_multi(&ms) {
+                    Some((threshold, keys)) => Descriptor::new_wsh_sortedmulti(threshold, keys)?,
+                    None => Descriptor::new_wsh(ms)?,
+                }
+            }
+            BareDescriptor::Tr(internal_key, None) => {
+                let pk = internal_key.public_key(secp256k1::Parity::Even);
+                Descriptor::new_tr(bitcoin::PublicKey::new(pk), None)?
+            }
+            BareDescriptor::Tr(internal_key, Some(merkle_root)) => {
+                return Err(MiniscriptConversionError::TapTreeUnknown(
+                    internal_key,
+                    merkle_root,
+                ));
+            }
+        })
+    }
+}
+
+/// Detects multisig scripts which keys are sorted as required by
+/// `sortedmulti` descriptors.
+#[cfg(feature = "miniscript")]
+fn sorted_multi<Ctx: ScriptContext>(
+    ms: &Miniscript<bitcoin::PublicKey, Ctx>,
+) -> Option<(usize, Vec<bitcoin::PublicKey>)> {
+    match &ms.node {
+        Terminal::Multi(threshold, keys)
+            if keys
+                .windows(2)
+                .all(|pair| pair[0].to_bytes() <= pair[1].to_bytes()) =>
+        {
+            Some((*threshold, keys.clone()))
+        }
+        _ => None,
+    }
+}
+
+/// Errors converting script pubkeys and bare descriptors into miniscript
+/// descriptors
+#[cfg(feature = "miniscript")]
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum MiniscriptConversionError {
+    /// script pubkey `{0}` commits to a hash of the public key or script and
+    /// can't be represented by a miniscript descriptor
+    HashCommitment(ScriptPubkeyDescr),
+
+    /// taproot output key {0} can't be represented by a miniscript descriptor
+    /// since the internal key is unknown
+    TweakedKey(TweakedPublicKey),
+
+    /// taproot output with internal key {0} commits to a script tree known
+    /// only by its merkle root {1}, which can't be represented by a miniscript
+    /// descriptor
+    TapTreeUnknown(UntweakedPublicKey, TapBranchHash),
+
+    /// witness program `{0}` of a future witness version can't be represented
+    /// by a miniscript descriptor
+    FutureWitness(PubkeyScript),
+
+    /// script is not a valid miniscript. {0}
+    #[from]
+    Miniscript(miniscript::Error),
+}
+
 /// Descriptor parse error
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error)]
 #[display(doc_comments)]
@@ -968,4 +1206,103 @@ mod test {
             Err(ParseError::UnrecognizedDescriptorName("???".into()))
         );
     }
+
+    #[test]
+    fn future_witness_classification() {
+        let future = Script::new_witness_program(WitnessVersion::V2, &[0xAB; 32]);
+        let descr = ScriptPubkeyDescr::try_from(PubkeyScript::from(future.clone())).unwrap();
+        assert_eq!(descr, ScriptPubkeyDescr::Future(future.into()));
+        assert_eq!(descr.witness_version(), Some(WitnessVersion::V2));
+        assert!(descr.is_future());
+
+        let non_taproot = Script::new_witness_program(WitnessVersion::V1, &[0xAB; 20]);
+        let descr = ScriptPubkeyDescr::try_from(PubkeyScript::from(non_taproot)).unwrap();
+        assert!(descr.is_future());
+
+        // Scripts starting with a witness version opcode which are not witness
+        // programs are bare scripts
+        let bare = Script::from(vec![0x51, 0x51]);
+        let descr = ScriptPubkeyDescr::try_from(PubkeyScript::from(bare.clone())).unwrap();
+        assert_eq!(descr, ScriptPubkeyDescr::Bare(bare.into()));
+        assert_eq!(descr.witness_version(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "miniscript")]
+    fn miniscript_conversion() {
+        let secp = Secp256k1::verification_only();
+        let g = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let g2 = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let descriptor = |s: String| Descriptor::<bitcoin::PublicKey>::from_str(&s).unwrap();
+
+        let sorted = descriptor(format!("wsh(sortedmulti(1,{},{}))", g2, g));
+        let script = WitnessScript::from(sorted.explicit_script().unwrap());
+        let bare = BareDescriptor::Wsh(script.clone());
+        assert_eq!(bare.into_miniscript().unwrap(), sorted);
+        let bare = BareDescriptor::ShWsh(script);
+        let expected = descriptor(format!("sh(wsh(sortedmulti(1,{},{})))", g, g2));
+        assert_eq!(bare.into_miniscript().unwrap(), expected);
+
+        let unsorted = descriptor(format!("wsh(multi(1,{},{}))", g2, g));
+        let script = WitnessScript::from(unsorted.explicit_script().unwrap());
+        assert_eq!(
+            BareDescriptor::Wsh(script).into_miniscript().unwrap(),
+            unsorted
+        );
+
+        let internal_key = XOnlyPublicKey::from_str(&g[2..]).unwrap();
+        let bare = BareDescriptor::Tr(internal_key, None);
+        let spk = bare.pubkey_script(&secp);
+        let descr = bare.into_miniscript().unwrap();
+        assert_eq!(descr.script_pubkey(), spk.into_inner());
+        let bare = BareDescriptor::Tr(internal_key, Some(TapBranchHash::hash(b"tree")));
+        assert!(matches!(
+            bare.into_miniscript(),
+            Err(MiniscriptConversionError::TapTreeUnknown(key, _)) if key == internal_key
+        ));
+
+        let pk = bitcoin::PublicKey::from_str(g).unwrap();
+        let descr = ScriptPubkeyDescr::Pk(pk);
+        assert_eq!(descr.into_descriptor().unwrap(), Descriptor::new_pk(pk));
+        let descr = ScriptPubkeyDescr::Wpkh(pk.wpubkey_hash().unwrap());
+        assert!(matches!(
+            descr.into_descriptor(),
+            Err(MiniscriptConversionError::HashCommitment(
+                ScriptPubkeyDescr::Wpkh(_)
+            ))
+        ));
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let data = (0..32u8)
+            .flat_map(|i| bitcoin::hashes::sha256::Hash::hash(&[i]).into_inner())
+            .collect::<Vec<_>>();
+        let mut u = Unstructured::new(&data);
+        for _ in 0..64 {
+            let class = DescriptorClass::arbitrary(&mut u).unwrap();
+            assert_eq!(DescriptorClass::from_str(&class.to_string()), Ok(class));
+            let class = SpkClass::arbitrary(&mut u).unwrap();
+            assert_eq!(SpkClass::from_str(&class.to_string()), Ok(class));
+            let ty = CompositeDescrType::arbitrary(&mut u).unwrap();
+            assert_eq!(CompositeDescrType::from_str(&ty.to_string()), Ok(ty));
+            let ty = OuterDescrType::arbitrary(&mut u).unwrap();
+            assert_eq!(OuterDescrType::from_str(&ty.to_string()), Ok(ty));
+            let ty = InnerDescrType::arbitrary(&mut u).unwrap();
+            assert_eq!(InnerDescrType::from_str(&ty.to_string()), Ok(ty));
+            let variants = DescrVariants::arbitrary(&mut u).unwrap();
+            if variants.count() > 0 {
+                assert_eq!(DescrVariants::from_str(&variants.to_string()), Ok(variants));
+            }
+        }
+
+        let mut u = Unstructured::new(&[]);
+        assert_eq!(
+            DescrVariants::arbitrary(&mut u),
+            Ok(DescrVariants::default())
+        );
+    }
 }
@@ -0,0 +1,450 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Upper bounds on the size of input satisfactions (`scriptSig` and witness)
+//! for each of [`CompositeDescrType`]s, allowing fee estimation before the
+//! inputs are signed.
+//!
+//! All estimates assume compressed public keys, 73-byte ECDSA signatures
+//! (including sighash type byte) and 65-byte BIP-340 signatures (with
+//! non-default sighash type), so the actual satisfaction never exceeds the
+//! estimate.
+
+use bitcoin::VarInt;
+#[cfg(feature = "miniscript")]
+use miniscript::descriptor::{ShInner, WshInner};
+#[cfg(feature = "miniscript")]
+use miniscript::{Descriptor, Miniscript, MiniscriptKey, ScriptContext, Terminal};
+
+use crate::CompositeDescrType;
+
+/// Weight of the transaction input data not including `scriptSig` and witness:
+/// previous outpoint and `nSequence`.
+pub const TXIN_BASE_WEIGHT: usize = (32 + 4 + 4) * 4;
+
+/// Maximal size of ECDSA signature, including sighash type byte
+pub const ECDSA_SIG_MAX_LEN: usize = 73;
+
+/// Maximal size of BIP-340 signature, including non-default sighash type byte
+pub const SCHNORR_SIG_MAX_LEN: usize = 65;
+
+/// Size of compressed public key
+const PUBKEY_LEN: usize = 33;
+
+/// Size of x-only public key
+const XONLY_PUBKEY_LEN: usize = 32;
+
+/// Script spending path which has to be satisfied by the input
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum SpendingPath {
+    /// Signature with a single key: `pk`, `pkh`, `wpkh`, P2SH- and
+    /// P2WSH-wrapped `pk` or taproot key path spending
+    #[display("key")]
+    SingleKey,
+
+    /// `threshold`-of-`keys` `multi` or `sortedmulti` script
+    #[display("multi({threshold},{keys})")]
+    Multisig {
+        /// Number of required signatures
+        threshold: usize,
+        /// Total number of keys in the script
+        keys: usize,
+    },
+
+    /// Taproot script path spending `threshold`-of-`keys` `multi_a` script
+    /// leaf at a given `depth` of the script tree. Single-key `pk` leaves are
+    /// estimated as 1-of-1 `multi_a`.
+    #[display("multi_a({threshold},{keys})@{depth}")]
+    TapMultisig {
+        /// Number of required signatures
+        threshold: usize,
+        /// Total number of keys in the script
+        keys: usize,
+        /// Depth of the leaf in the script tree
+        depth: u8,
+    },
+}
+
+/// Upper bound on the size of the data required to spend a transaction input
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct SatisfactionEstimate {
+    /// Size of the `scriptSig` in bytes, not including its length prefix
+    pub script_sig: usize,
+
+    /// Size of the serialized witness in bytes, including the number of
+    /// witness elements. Zero for inputs not using witness.
+    pub witness: usize,
+}
+
+impl SatisfactionEstimate {
+    /// Weight of the satisfaction, including `scriptSig` length prefix, in
+    /// weight units. Matches miniscript `max_satisfaction_weight` definition.
+    pub fn weight(self) -> usize {
+        (VarInt(self.script_sig as u64).len() + self.script_sig) * 4 + self.witness
+    }
+
+    /// Weight of a transaction input spending the output, in weight units.
+    #[inline]
+    pub fn input_weight(self) -> usize { TXIN_BASE_WEIGHT + self.weight() }
+
+    /// Virtual size of a transaction input spending the output, in vbytes.
+    #[inline]
+    pub fn input_vsize(self) -> usize { (self.input_weight() + 3) / 4 }
+
+    /// Fee (in satoshis) required to spend the input at a given feerate (in
+    /// sat/vbyte).
+    #[inline]
+    pub fn input_fee(self, feerate: f32) -> u64 {
+        (self.input_vsize() as f32 * feerate).ceil() as u64
+    }
+}
+
+/// Size of a data push inside a script, including push opcode.
+fn push_len(len: usize) -> usize {
+    len + match len {
+        0..=75 => 1,
+        76..=0xFF => 2,
+        0x100..=0xFFFF => 3,
+        _ => 5,
+    }
+}
+
+/// Size of a witness element, including its length prefix.
+fn element_len(len: usize) -> usize { VarInt(len as u64).len() + len }
+
+/// Size of a script number push for a non-negative number.
+fn scriptnum_len(n: usize) -> usize {
+    match n {
+        0..=16 => 1,
+        17..=0x7F => 2,
+        0x80..=0x7FFF => 3,
+        _ => 4,
+    }
+}
+
+/// Legacy (`scriptSig`) or segwit v0 script with its maximal satisfaction
+struct Satisfied {
+    script_len: usize,
+    /// Sizes of the satisfaction stack elements
+    elements: Vec<usize>,
+}
+
+impl Satisfied {
+    fn with(path: SpendingPath) -> Option<Self> {
+        match path {
+            SpendingPath::SingleKey => Some(Satisfied {
+                script_len: push_len(PUBKEY_LEN) + 1,
+                elements: vec![ECDSA_SIG_MAX_LEN],
+            }),
+            SpendingPath::Multisig { threshold, keys }
+                if threshold > 0 && threshold <= keys && keys <= 20 =>
+            {
+                let mut elements = vec![0];
+                elements.extend(vec![ECDSA_SIG_MAX_LEN; threshold]);
+                Some(Satisfied {
+                    script_len: scriptnum_len(threshold)
+                        + keys * push_len(PUBKEY_LEN)
+                        + scriptnum_len(keys)
+                        + 1,
+                    elements,
+                })
+            }
+            SpendingPath::Multisig { .. } | SpendingPath::TapMultisig { .. } => None,
+        }
+    }
+
+    fn script_sig(&self) -> usize {
+        self.elements.iter().copied().map(push_len).sum::<usize>() + push_len(self.script_len)
+    }
+
+    fn witness(&self) -> usize {
+        VarInt(self.elements.len() as u64 + 1).len()
+            + self
+                .elements
+                .iter()
+                .copied()
+                .map(element_len)
+                .sum::<usize>()
+            + element_len(self.script_len)
+    }
+}
+
+/// Estimates maximal satisfaction size of an input spending output of a given
+/// descriptor type with a given spending path. Returns `None` if the
+/// spending path is not applicable to the descriptor type (like multisig for
+/// `wpkh`) or has invalid parameters.
+pub fn estimate(ty: CompositeDescrType, path: SpendingPath) -> Option<SatisfactionEstimate> {
+    let p2wpkh_witness = 1 + element_len(ECDSA_SIG_MAX_LEN) + element_len(PUBKEY_LEN);
+    let estimate = match (ty, path) {
+        (CompositeDescrType::Pk, SpendingPath::SingleKey)
+        | (CompositeDescrType::Bare, SpendingPath::SingleKey | SpendingPath::Multisig { .. }) => {
+            let satisfied = Satisfied::with(path)?;
+            SatisfactionEstimate {
+                script_sig: satisfied.script_sig() - push_len(satisfied.script_len),
+                witness: 0,
+            }
+        }
+        (CompositeDescrType::Pkh, SpendingPath::SingleKey) => SatisfactionEstimate {
+            script_sig: push_len(ECDSA_SIG_MAX_LEN) + push_len(PUBKEY_LEN),
+            witness: 0,
+        },
+        (CompositeDescrType::Sh, SpendingPath::SingleKey | SpendingPath::Multisig { .. }) => {
+            SatisfactionEstimate {
+                script_sig: Satisfied::with(path)?.script_sig(),
+                witness: 0,
+            }
+        }
+        (CompositeDescrType::Wpkh, SpendingPath::SingleKey) => SatisfactionEstimate {
+            script_sig: 0,
+            witness: p2wpkh_witness,
+        },
+        (CompositeDescrType::Wsh, SpendingPath::SingleKey | SpendingPath::Multisig { .. }) => {
+            SatisfactionEstimate {
+                script_sig: 0,
+                witness: Satisfied::with(path)?.witness(),
+            }
+        }
+        (CompositeDescrType::ShWpkh, SpendingPath::SingleKey) => SatisfactionEstimate {
+            script_sig: push_len(22),
+            witness: p2wpkh_witness,
+        },
+        (CompositeDescrType::ShWsh, SpendingPath::SingleKey | SpendingPath::Multisig { .. }) => {
+            SatisfactionEstimate {
+                script_sig: push_len(34),
+                witness: Satisfied::with(path)?.witness(),
+            }
+        }
+        (CompositeDescrType::Tr, SpendingPath::SingleKey) => SatisfactionEstimate {
+            script_sig: 0,
+            witness: 1 + element_len(SCHNORR_SIG_MAX_LEN),
+        },
+        (
+            CompositeDescrType::Tr,
+            SpendingPath::TapMultisig {
+                threshold,
+                keys,
+                depth,
+            },
+        ) if threshold > 0 && threshold <= keys && depth <= 128 => {
+            // <key> OP_CHECKSIG <key> OP_CHECKSIGADD ... <threshold> OP_NUMEQUAL
+            let script_len = keys * (push_len(XONLY_PUBKEY_LEN) + 1) + scriptnum_len(threshold) + 1;
+            let control_block_len = 33 + 32 * depth as usize;
+            SatisfactionEstimate {
+                script_sig: 0,
+                witness: VarInt(keys as u64 + 2).len()
+                    + threshold * element_len(SCHNORR_SIG_MAX_LEN)
+                    + (keys - threshold) * element_len(0)
+                    + element_len(script_len)
+                    + element_len(control_block_len),
+            }
+        }
+        _ => return None,
+    };
+    Some(estimate)
+}
+
+#[cfg(feature = "miniscript")]
+fn ms_spending_path<Pk, Ctx>(ms: &Miniscript<Pk, Ctx>) -> Option<SpendingPath>
+where
+    Pk: MiniscriptKey,
+    Ctx: ScriptContext,
+{
+    match &ms.node {
+        Terminal::PkK(_) => Some(SpendingPath::SingleKey),
+        Terminal::Check(inner) if matches!(inner.node, Terminal::PkK(_)) => {
+            Some(SpendingPath::SingleKey)
+        }
+        Terminal::Multi(threshold, keys) => Some(SpendingPath::Multisig {
+            threshold: *threshold,
+            keys: keys.len(),
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "miniscript")]
+impl SpendingPath {
+    /// Detects spending paths of the descriptor, if the descriptor uses one of
+    /// the standard single-key or multisig scripts. For taproot descriptors
+    /// returns key path and all script tree leaves.
+    pub fn from_descriptor<Pk>(descriptor: &Descriptor<Pk>) -> Option<Vec<SpendingPath>>
+    where
+        Pk: MiniscriptKey,
+    {
+        let path = match descriptor {
+            Descriptor::Bare(bare) => ms_spending_path(bare.as_inner())?,
+            Descriptor::Pkh(_) | Descriptor::Wpkh(_) => SpendingPath::SingleKey,
+            Descriptor::Sh(sh) => match sh.as_inner() {
+                ShInner::Wpkh(_) => SpendingPath::SingleKey,
+                ShInner::Wsh(wsh) => wsh_spending_path(wsh.as_inner())?,
+                ShInner::SortedMulti(smv) => SpendingPath::Multisig {
+                    threshold: smv.k,
+                    keys: smv.pks.len(),
+                },
+                ShInner::Ms(ms) => ms_spending_path(ms)?,
+            },
+            Descriptor::Wsh(wsh) => wsh_spending_path(wsh.as_inner())?,
+            Descriptor::Tr(tr) => {
+                let mut paths = vec![SpendingPath::SingleKey];
+                for (depth, ms) in tr.iter_scripts() {
+                    let (threshold, keys) = match (&ms.node, ms_spending_path(ms)) {
+                        (Terminal::MultiA(threshold, keys), _) => (*threshold, keys.len()),
+                        (_, Some(SpendingPath::SingleKey)) => (1, 1),
+                        _ => return None,
+                    };
+                    paths.push(SpendingPath::TapMultisig {
+                        threshold,
+                        keys,
+                        depth,
+                    });
+                }
+                return Some(paths);
+            }
+        };
+        Some(vec![path])
+    }
+}
+
+#[cfg(feature = "miniscript")]
+fn wsh_spending_path<Pk>(inner: &WshInner<Pk>) -> Option<SpendingPath>
+where
+    Pk: MiniscriptKey,
+{
+    match inner {
+        WshInner::SortedMulti(smv) => Some(SpendingPath::Multisig {
+            threshold: smv.k,
+            keys: smv.pks.len(),
+        }),
+        WshInner::Ms(ms) => ms_spending_path(ms),
+    }
+}
+
+/// Estimates maximal satisfaction size of an input spending the descriptor,
+/// taking the most expensive of its spending paths. Returns `None` for
+/// descriptors not using standard single-key or multisig scripts; for them
+/// miniscript `max_satisfaction_weight` may be used.
+#[cfg(feature = "miniscript")]
+pub fn estimate_descriptor<Pk>(descriptor: &Descriptor<Pk>) -> Option<SatisfactionEstimate>
+where
+    Pk: MiniscriptKey,
+{
+    let ty = CompositeDescrType::from(descriptor);
+    SpendingPath::from_descriptor(descriptor)?
+        .into_iter()
+        .map(|path| estimate(ty, path))
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .max_by_key(|estimate| estimate.weight())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn single_key() {
+        let pkh = estimate(CompositeDescrType::Pkh, SpendingPath::SingleKey).unwrap();
+        assert_eq!(pkh.script_sig, 1 + 73 + 1 + 33);
+        assert_eq!(pkh.witness, 0);
+        assert_eq!(pkh.weight(), (1 + 108) * 4);
+
+        let wpkh = estimate(CompositeDescrType::Wpkh, SpendingPath::SingleKey).unwrap();
+        assert_eq!(wpkh, SatisfactionEstimate {
+            script_sig: 0,
+            witness: 1 + 1 + 73 + 1 + 33,
+        });
+        assert_eq!(wpkh.input_vsize(), 69);
+
+        let sh_wpkh = estimate(CompositeDescrType::ShWpkh, SpendingPath::SingleKey).unwrap();
+        assert_eq!(sh_wpkh.script_sig, 23);
+        assert_eq!(sh_wpkh.witness, wpkh.witness);
+
+        let tr = estimate(CompositeDescrType::Tr, SpendingPath::SingleKey).unwrap();
+        assert_eq!(tr.witness, 1 + 1 + 65);
+        assert_eq!(tr.input_vsize(), 58);
+
+        assert_eq!(
+            estimate(CompositeDescrType::Wpkh, SpendingPath::Multisig {
+                threshold: 2,
+                keys: 3
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn multisig() {
+        let path = SpendingPath::Multisig {
+            threshold: 2,
+            keys: 3,
+        };
+        // OP_2 <key> <key> <key> OP_3 OP_CHECKMULTISIG
+        let script_len = 1 + 3 * 34 + 1 + 1;
+
+        let sh = estimate(CompositeDescrType::Sh, path).unwrap();
+        assert_eq!(sh.script_sig, 1 + 2 * 74 + 2 + script_len);
+
+        let wsh = estimate(CompositeDescrType::Wsh, path).unwrap();
+        assert_eq!(wsh.witness, 1 + 1 + 2 * 74 + 1 + script_len);
+
+        let sh_wsh = estimate(CompositeDescrType::ShWsh, path).unwrap();
+        assert_eq!(sh_wsh.script_sig, 35);
+        assert_eq!(sh_wsh.witness, wsh.witness);
+
+        assert_eq!(
+            estimate(CompositeDescrType::Wsh, SpendingPath::Multisig {
+                threshold: 3,
+                keys: 2
+            }),
+            None
+        );
+
+        let tap = estimate(CompositeDescrType::Tr, SpendingPath::TapMultisig {
+            threshold: 2,
+            keys: 3,
+            depth: 1,
+        })
+        .unwrap();
+        // 3 signature slots, leaf script and control block
+        let script_len = 3 * 34 + 1 + 1;
+        assert_eq!(tap.witness, 1 + 2 * 66 + 1 + 1 + script_len + 1 + 65);
+    }
+
+    #[cfg(feature = "miniscript")]
+    #[test]
+    fn descriptor_estimate() {
+        use std::str::FromStr;
+
+        use miniscript::Descriptor;
+
+        let descriptor = Descriptor::<bitcoin::PublicKey>::from_str(
+            "wsh(sortedmulti(2,0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798,\
+             02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5,\
+             02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9))",
+        )
+        .unwrap();
+        let estimated = estimate_descriptor(&descriptor).unwrap();
+        assert_eq!(
+            Some(estimated),
+            estimate(CompositeDescrType::Wsh, SpendingPath::Multisig {
+                threshold: 2,
+                keys: 3
+            })
+        );
+        // Our estimate must not be below miniscript one
+        assert!(estimated.weight() >= descriptor.max_satisfaction_weight().unwrap());
+    }
+}
@@ -0,0 +1,371 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Generic hash time-locked contract (HTLC) scripts, as used by atomic swaps
+//! and payment channels: the output is claimable by one party revealing a
+//! hash preimage, or refundable to the other party after a timeout.
+//!
+//! The module provides script construction, recognition of such scripts in
+//! the wild and assembly of witness stacks for the claim and refund spending
+//! paths. Hash locks and preimages are represented with
+//! [`bitcoin_scripts::hlc`] types.
+
+use amplify::{Slice32, Wrapper};
+use bitcoin::blockdata::opcodes::all::*;
+use bitcoin::blockdata::script::{self, Builder, Instruction};
+use bitcoin::hashes::{ripemd160, Hash};
+use bitcoin::{EcdsaSig, PackedLockTime, PublicKey, Script, Sequence};
+use bitcoin_scripts::hlc::{HashLock, HashPreimage};
+use bitcoin_scripts::LockScript;
+
+use crate::locks::{Lock, LOCKTIME_THRESHOLD};
+
+/// Size of the hash preimage required by the HTLC scripts
+pub const PREIMAGE_LEN: usize = 32;
+
+/// Errors constructing and spending hash time-locked contract scripts
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum HlcError {
+    /// timelock {0} can't be used in the contract script since its value
+    /// does not match the lock type
+    InvalidTimeout(Lock),
+
+    /// preimage does not match the contract hash lock
+    PreimageMismatch,
+}
+
+/// Commitment to the [`HashLock`] (i.e. SHA256 hash of the preimage) in the
+/// contract script
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum HashCommitment {
+    /// Script checks the preimage with `OP_SHA256` against the hash lock
+    #[display("sha256({0})")]
+    Sha256(HashLock),
+
+    /// Script checks the preimage with `OP_HASH160` against RIPEMD160 hash of
+    /// the hash lock, as BOLT-3 HTLC scripts do
+    #[display("hash160({0})")]
+    Hash160(ripemd160::Hash),
+}
+
+impl HashCommitment {
+    /// Constructs commitment checking the preimage with `OP_HASH160`.
+    pub fn hash160(hash_lock: HashLock) -> HashCommitment {
+        HashCommitment::Hash160(ripemd160::Hash::hash(hash_lock.as_inner().as_slice()))
+    }
+
+    /// Checks whether the commitment commits to the hash lock.
+    pub fn commits_to(&self, hash_lock: HashLock) -> bool {
+        match self {
+            HashCommitment::Sha256(lock) => *lock == hash_lock,
+            HashCommitment::Hash160(_) => *self == HashCommitment::hash160(hash_lock),
+        }
+    }
+
+    /// Checks whether the preimage unlocks the contract.
+    pub fn verify(&self, preimage: &HashPreimage) -> bool {
+        self.commits_to(HashLock::from(*preimage))
+    }
+}
+
+/// Hash time-locked contract script:
+///
+/// ```text
+/// OP_IF
+///     OP_SIZE 32 OP_EQUALVERIFY OP_SHA256|OP_HASH160 <commitment> OP_EQUALVERIFY
+///     <claim_pubkey>
+/// OP_ELSE
+///     <timeout> OP_CLTV|OP_CSV OP_DROP
+///     <refund_pubkey>
+/// OP_ENDIF
+/// OP_CHECKSIG
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct HlcScript {
+    /// Hash lock commitment of the claim path
+    pub hash_commitment: HashCommitment,
+
+    /// Key which can claim the funds by revealing the hash preimage
+    pub claim_pubkey: PublicKey,
+
+    /// Absolute or relative timelock of the refund path
+    pub timeout: Lock,
+
+    /// Key which can take the funds back after the timeout
+    pub refund_pubkey: PublicKey,
+}
+
+pub(crate) fn instructions(script: &Script) -> Option<Vec<Instruction>> {
+    script.instructions().collect::<Result<_, _>>().ok()
+}
+
+pub(crate) fn read_pubkey(instruction: &Instruction) -> Option<PublicKey> {
+    match instruction {
+        Instruction::PushBytes(data) => PublicKey::from_slice(data).ok(),
+        Instruction::Op(_) => None,
+    }
+}
+
+pub(crate) fn read_int(instruction: &Instruction) -> Option<i64> {
+    match instruction {
+        Instruction::PushBytes(data) => script::read_scriptint(data).ok(),
+        Instruction::Op(op) => {
+            let n = op.to_u8().wrapping_sub(OP_PUSHNUM_1.to_u8());
+            (n < 16).then_some(n as i64 + 1)
+        }
+    }
+}
+
+pub(crate) fn read_hash<H: Hash>(instruction: &Instruction) -> Option<H> {
+    match instruction {
+        Instruction::PushBytes(data) => H::from_slice(data).ok(),
+        Instruction::Op(_) => None,
+    }
+}
+
+/// Parses script fields with `extract` and checks that the script re-built
+/// from the fields is byte-identical to the original one, ensuring that all
+/// opcodes match the template and the values use minimal encoding.
+pub(crate) fn parse<T>(
+    script: &LockScript,
+    extract: impl FnOnce(&[Instruction]) -> Option<T>,
+    build: impl FnOnce(&T) -> LockScript,
+) -> Option<T> {
+    let instructions = instructions(script.as_inner())?;
+    let value = extract(&instructions)?;
+    (build(&value) == *script).then_some(value)
+}
+
+impl HlcScript {
+    /// Constructs script of the contract.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`HlcError::InvalidTimeout`] if the absolute timelock value
+    /// is zero or does not match the lock type (block height or timestamp).
+    pub fn to_lock_script(&self) -> Result<LockScript, HlcError> {
+        let builder = Builder::new()
+            .push_opcode(OP_IF)
+            .push_opcode(OP_SIZE)
+            .push_int(PREIMAGE_LEN as i64)
+            .push_opcode(OP_EQUALVERIFY);
+        let builder = match self.hash_commitment {
+            HashCommitment::Sha256(hash_lock) => builder
+                .push_opcode(OP_SHA256)
+                .push_slice(hash_lock.as_inner().as_slice()),
+            HashCommitment::Hash160(hash) => builder.push_opcode(OP_HASH160).push_slice(&hash[..]),
+        };
+        let builder = builder
+            .push_opcode(OP_EQUALVERIFY)
+            .push_key(&self.claim_pubkey)
+            .push_opcode(OP_ELSE);
+        let (value, opcode) = match self.timeout {
+            Lock::AfterHeight(height) if height > 0 && height < LOCKTIME_THRESHOLD => {
+                (height, OP_CLTV)
+            }
+            Lock::AfterTime(time) if time >= LOCKTIME_THRESHOLD => (time, OP_CLTV),
+            Lock::OlderBlocks(blocks) => (Sequence::from_height(blocks).0, OP_CSV),
+            Lock::OlderTime(intervals) => {
+                (Sequence::from_512_second_intervals(intervals).0, OP_CSV)
+            }
+            timeout => return Err(HlcError::InvalidTimeout(timeout)),
+        };
+        Ok(builder
+            .push_int(value as i64)
+            .push_opcode(opcode)
+            .push_opcode(OP_DROP)
+            .push_key(&self.refund_pubkey)
+            .push_opcode(OP_ENDIF)
+            .push_opcode(OP_CHECKSIG)
+            .into_script()
+            .into())
+    }
+
+    /// Recognizes hash time-locked contract script, returning `None` if the
+    /// script does not match the template.
+    pub fn from_lock_script(script: &LockScript) -> Option<Self> {
+        parse(
+            script,
+            |i| {
+                let hash_commitment = match (i.get(4)?, i.get(5)?) {
+                    (Instruction::Op(op), Instruction::PushBytes(data)) if *op == OP_SHA256 => {
+                        HashCommitment::Sha256(HashLock::from_inner(Slice32::from_slice(data)?))
+                    }
+                    (Instruction::Op(op), hash) if *op == OP_HASH160 => {
+                        HashCommitment::Hash160(read_hash(hash)?)
+                    }
+                    _ => return None,
+                };
+                let value = u32::try_from(read_int(i.get(9)?)?).ok()?;
+                let timeout = match i.get(10)? {
+                    Instruction::Op(op) if *op == OP_CLTV => {
+                        Lock::from_lock_time(PackedLockTime(value))?
+                    }
+                    Instruction::Op(op) if *op == OP_CSV => Lock::from_sequence(Sequence(value))?,
+                    _ => return None,
+                };
+                Some(HlcScript {
+                    hash_commitment,
+                    claim_pubkey: read_pubkey(i.get(7)?)?,
+                    timeout,
+                    refund_pubkey: read_pubkey(i.get(12)?)?,
+                })
+            },
+            // Parsed timeouts are always valid, so the empty script is never
+            // produced here
+            |hlc| {
+                hlc.to_lock_script()
+                    .unwrap_or_else(|_| LockScript::from(Script::new()))
+            },
+        )
+    }
+
+    /// Assembles P2WSH witness stack spending the contract via the claim
+    /// path.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`HlcError::PreimageMismatch`] if the preimage does not
+    /// unlock the contract hash lock, and with [`HlcError::InvalidTimeout`]
+    /// if the contract script can't be constructed.
+    pub fn claim_witness(
+        &self,
+        sig: &EcdsaSig,
+        preimage: &HashPreimage,
+    ) -> Result<Vec<Vec<u8>>, HlcError> {
+        if !self.hash_commitment.verify(preimage) {
+            return Err(HlcError::PreimageMismatch);
+        }
+        Ok(vec![
+            sig.to_vec(),
+            preimage.as_inner().to_vec(),
+            vec![0x01],
+            self.to_lock_script()?.into_inner().into_bytes(),
+        ])
+    }
+
+    /// Assembles P2WSH witness stack spending the contract via the refund
+    /// path. The spending transaction must satisfy the contract timeout, see
+    /// [`Lock::to_lock_time`] and [`Lock::to_seq_no`].
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`HlcError::InvalidTimeout`] if the contract script can't
+    /// be constructed.
+    pub fn refund_witness(&self, sig: &EcdsaSig) -> Result<Vec<Vec<u8>>, HlcError> {
+        Ok(vec![
+            sig.to_vec(),
+            vec![],
+            self.to_lock_script()?.into_inner().into_bytes(),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::EcdsaSighashType;
+
+    use super::*;
+
+    #[test]
+    fn hlc_scripts() {
+        let pubkey = |hex| PublicKey::from_str(hex).unwrap();
+        let claim_pubkey =
+            pubkey("0394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b");
+        let refund_pubkey =
+            pubkey("030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e7");
+        let preimage = HashPreimage::from_inner(Slice32::from_inner([0x42u8; PREIMAGE_LEN]));
+        let hash_lock = HashLock::from(preimage);
+
+        for (hash_commitment, timeout) in [
+            (
+                HashCommitment::Sha256(hash_lock),
+                Lock::AfterHeight(800_000),
+            ),
+            (
+                HashCommitment::hash160(hash_lock),
+                Lock::AfterTime(1_700_000_000),
+            ),
+            (HashCommitment::Sha256(hash_lock), Lock::OlderBlocks(144)),
+            (HashCommitment::hash160(hash_lock), Lock::OlderTime(10)),
+        ] {
+            assert!(hash_commitment.verify(&preimage));
+            let hlc = HlcScript {
+                hash_commitment,
+                claim_pubkey,
+                timeout,
+                refund_pubkey,
+            };
+            let script = hlc.to_lock_script().unwrap();
+            assert_eq!(HlcScript::from_lock_script(&script), Some(hlc));
+        }
+        assert_eq!(
+            HashCommitment::hash160(hash_lock),
+            HashCommitment::Hash160(ripemd160::Hash::hash(&bitcoin::hashes::sha256::Hash::hash(
+                &[0x42u8; PREIMAGE_LEN]
+            )))
+        );
+
+        let mut hlc = HlcScript {
+            hash_commitment: HashCommitment::Sha256(hash_lock),
+            claim_pubkey,
+            timeout: Lock::OlderBlocks(144),
+            refund_pubkey,
+        };
+        assert!(hlc.to_lock_script().unwrap().as_inner().asm().starts_with(
+            "OP_IF OP_SIZE OP_PUSHBYTES_1 20 OP_EQUALVERIFY OP_SHA256 OP_PUSHBYTES_32"
+        ));
+
+        let seckey = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let msg = Message::from_slice(&[0x33; 32]).unwrap();
+        let sig = EcdsaSig {
+            sig: Secp256k1::new().sign_ecdsa(&msg, &seckey),
+            hash_ty: EcdsaSighashType::All,
+        };
+        let witness = hlc.claim_witness(&sig, &preimage).unwrap();
+        assert_eq!(witness.len(), 4);
+        assert_eq!(witness[1], vec![0x42u8; PREIMAGE_LEN]);
+        let wrong = HashPreimage::from_inner(Slice32::from_inner([0x00; PREIMAGE_LEN]));
+        assert_eq!(
+            hlc.claim_witness(&sig, &wrong),
+            Err(HlcError::PreimageMismatch)
+        );
+        let witness = hlc.refund_witness(&sig).unwrap();
+        assert!(witness[1].is_empty());
+        assert_eq!(
+            witness[2],
+            hlc.to_lock_script().unwrap().into_inner().into_bytes()
+        );
+
+        let mut bytes = hlc.to_lock_script().unwrap().into_inner().into_bytes();
+        bytes.pop();
+        bytes.push(OP_CHECKSIGVERIFY.to_u8());
+        let script = LockScript::from(Script::from(bytes));
+        assert_eq!(HlcScript::from_lock_script(&script), None);
+
+        for timeout in [
+            Lock::AfterHeight(0),
+            Lock::AfterHeight(LOCKTIME_THRESHOLD),
+            Lock::AfterTime(1_000),
+        ] {
+            hlc.timeout = timeout;
+            assert_eq!(hlc.to_lock_script(), Err(HlcError::InvalidTimeout(timeout)));
+            assert_eq!(
+                hlc.refund_witness(&sig),
+                Err(HlcError::InvalidTimeout(timeout))
+            );
+        }
+    }
+}
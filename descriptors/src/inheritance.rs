@@ -0,0 +1,185 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Timelocked inheritance wallets: taproot descriptors of the
+//! `tr(owner, and_v(v:pk(heir), older(N)))` form, spendable by the owner key
+//! at any time and by the heir key once the funds remained unmoved for `N`
+//! blocks.
+//!
+//! Inheritance is a special case of a [`Delegation`] contract using taproot
+//! and a relative timelock, such that the owner key spendings are
+//! indistinguishable from ordinary single-key spendings. The owner has to
+//! periodically move the funds to restart the timelock.
+
+use bitcoin::Sequence;
+use miniscript::{Descriptor, MiniscriptKey};
+
+use crate::locks::{BlockInfo, Lock, Maturity, Satisfaction};
+use crate::policy::PolicyError;
+use crate::{Delegation, DelegationLock, DescriptorClass};
+
+/// Inheritance contract between an owner and an heir keys
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Inheritance<Pk>
+where
+    Pk: MiniscriptKey,
+{
+    /// Owner key, which may spend funds at any time
+    pub owner: Pk,
+
+    /// Heir key, which may spend funds after they remained unmoved for
+    /// [`Inheritance::delay`] blocks
+    pub heir: Pk,
+
+    /// Number of blocks since the output confirmation after which the heir
+    /// can spend it
+    pub delay: u16,
+}
+
+impl<Pk> Inheritance<Pk>
+where
+    Pk: MiniscriptKey,
+{
+    /// Constructs inheritance contract from the owner and heir keys and the
+    /// heir timelock in blocks
+    pub fn with(owner: Pk, heir: Pk, delay: u16) -> Self { Inheritance { owner, heir, delay } }
+
+    /// Returns delegation contract with the heir as a delegate
+    pub fn to_delegation(&self) -> Delegation<Pk> {
+        Delegation::with(
+            self.owner.clone(),
+            self.heir.clone(),
+            DelegationLock::Older(Sequence::from_height(self.delay)),
+        )
+    }
+
+    /// Produces taproot descriptor of the contract, which uses owner key as
+    /// the internal key and has a single script leaf for the heir.
+    pub fn descriptor(&self) -> Result<Descriptor<Pk>, PolicyError> {
+        Ok(self
+            .to_delegation()
+            .compile(DescriptorClass::TaprootC0)?
+            .descriptor)
+    }
+
+    /// Detects inheritance contract from a taproot descriptor. Returns `None`
+    /// if the descriptor is not a taproot descriptor with the owner internal
+    /// key and the heir script path restricted by a block-based relative
+    /// timelock.
+    pub fn from_descriptor(descriptor: &Descriptor<Pk>) -> Option<Self> {
+        let tr = match descriptor {
+            Descriptor::Tr(tr) => tr,
+            _ => return None,
+        };
+        let delegation = Delegation::from_descriptor(descriptor)?;
+        if tr.internal_key() != &delegation.owner {
+            return None;
+        }
+        match delegation.lock {
+            DelegationLock::Older(seq) => match Lock::from_sequence(seq)? {
+                Lock::OlderBlocks(delay) => Some(Inheritance::with(
+                    delegation.owner,
+                    delegation.delegate,
+                    delay,
+                )),
+                _ => None,
+            },
+            DelegationLock::After(_) => None,
+        }
+    }
+
+    /// Returns timelock restricting the heir spendings
+    pub fn heir_lock(&self) -> Lock { Lock::OlderBlocks(self.delay) }
+
+    /// Computes the minimal chain tip on top of which the heir may spend an
+    /// output, which relative timelock is measured from the `anchor` block
+    /// (see [`crate::locks`]).
+    pub fn heir_maturity(&self, anchor: BlockInfo) -> Maturity { self.heir_lock().maturity(anchor) }
+
+    /// Returns the way the heir satisfies the timelock when spending an
+    /// output, which relative timelock is measured from the `anchor` block.
+    /// The satisfaction is applied to the spending inputs with
+    /// [`crate::InputDescriptor::apply_satisfaction`].
+    pub fn heir_satisfaction(&self, anchor: BlockInfo) -> Satisfaction {
+        Satisfaction {
+            maturity: self.heir_maturity(anchor),
+            lock_time: None,
+            seq_no: self.heir_lock().to_seq_no(),
+        }
+    }
+
+    /// Returns number of blocks which have to be mined on top of the chain
+    /// `tip` before the heir can spend an output, which relative timelock is
+    /// measured from the `anchor` block. Zero means that the output can be
+    /// spent by the heir in the next block.
+    pub fn heir_blocks_left(&self, anchor: BlockInfo, tip: BlockInfo) -> u32 {
+        self.heir_maturity(anchor).height.saturating_sub(tip.height)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn inheritance_descriptor() {
+        let inheritance = Inheritance::with(s!("A"), s!("B"), 52_560);
+        let descriptor = inheritance.descriptor().unwrap();
+        assert!(descriptor.to_string().starts_with("tr(A,"));
+        assert!(descriptor.to_string().contains("older(52560)"));
+        assert_eq!(
+            Inheritance::from_descriptor(&descriptor),
+            Some(inheritance.clone())
+        );
+
+        let wsh = inheritance
+            .to_delegation()
+            .compile(DescriptorClass::SegwitV0)
+            .unwrap();
+        assert_eq!(Inheritance::from_descriptor(&wsh.descriptor), None);
+        let swapped = Descriptor::<String>::from_str("tr(B,and_v(v:pk(A),older(10)))").unwrap();
+        assert_eq!(
+            Inheritance::from_descriptor(&swapped),
+            Some(Inheritance::with(s!("B"), s!("A"), 10))
+        );
+        let after = Descriptor::<String>::from_str("tr(A,and_v(v:pk(B),after(10)))").unwrap();
+        assert_eq!(Inheritance::from_descriptor(&after), None);
+        let time = Descriptor::<String>::from_str("tr(A,and_v(v:pk(B),older(4194305)))").unwrap();
+        assert_eq!(Inheritance::from_descriptor(&time), None);
+    }
+
+    #[test]
+    fn heir_maturity() {
+        let inheritance = Inheritance::with(s!("A"), s!("B"), 144);
+        let anchor = BlockInfo {
+            height: 800_000,
+            median_time_past: 1_690_000_000,
+        };
+        let tip = BlockInfo {
+            height: 800_100,
+            median_time_past: 1_690_060_000,
+        };
+        assert_eq!(inheritance.heir_maturity(anchor).height, 800_144);
+        assert_eq!(inheritance.heir_blocks_left(anchor, tip), 44);
+        let tip = BlockInfo {
+            height: 800_144,
+            ..tip
+        };
+        assert_eq!(inheritance.heir_blocks_left(anchor, tip), 0);
+
+        let satisfaction = inheritance.heir_satisfaction(anchor);
+        assert!(satisfaction.maturity.is_reached(tip));
+        assert_eq!(satisfaction.lock_time, None);
+        assert_eq!(satisfaction.seq_no.unwrap().into_consensus(), 144);
+    }
+}
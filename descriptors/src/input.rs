@@ -12,14 +12,17 @@
 use core::fmt::{self, Display, Formatter};
 use core::str::FromStr;
 
+use amplify::hex::{FromHex, ToHex};
 use bitcoin::blockdata::transaction::ParseOutPointError;
 use bitcoin::hashes::sha256;
 use bitcoin::util::bip32;
 use bitcoin::util::bip32::Fingerprint;
-use bitcoin::{EcdsaSighashType as SighashType, OutPoint};
+use bitcoin::{EcdsaSighashType as SighashType, OutPoint, PackedLockTime, Script, Sequence, TxOut};
 use bitcoin_blockchain::locks::{self, SeqNo};
 use bitcoin_hd::{DerivationSubpath, UnhardenedIndex};
 
+use crate::locks::Satisfaction;
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 #[derive(StrictEncode, StrictDecode)]
 pub struct InputDescriptor {
@@ -28,6 +31,150 @@ pub struct InputDescriptor {
     pub seq_no: SeqNo,
     pub tweak: Option<(Fingerprint, sha256::Hash)>,
     pub sighash_type: SighashType,
+    /// Pre-built satisfaction for inputs which are not controlled by the
+    /// wallet and are satisfied by an external system (like an L2 contract).
+    /// Such inputs are not matched against the wallet descriptor and are not
+    /// signed or finalized by the wallet.
+    pub external: Option<ExternalSatisfaction>,
+    /// Explicit redeem script for spending P2SH outputs which are not covered
+    /// by the wallet descriptor (like legacy multisigs), but are signable by
+    /// its keys.
+    pub redeem_script: Option<Script>,
+    /// Explicit witness script for spending P2WSH outputs (native or nested
+    /// into P2SH) which are not covered by the wallet descriptor, but are
+    /// signable by its keys.
+    pub witness_script: Option<Script>,
+}
+
+/// Input satisfaction (`scriptSig` and witness) produced by a system external
+/// to the wallet.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct ExternalSatisfaction {
+    /// Finalized `scriptSig`; empty for segwit inputs.
+    pub script_sig: Script,
+    /// Finalized witness stack; empty for non-segwit inputs.
+    pub witness: Vec<Vec<u8>>,
+}
+
+/// Input spending an output which is not controlled by the wallet descriptor
+/// ("foreign" input), used to construct transactions with mixed ownership of
+/// inputs, like payjoins, coinjoins or multisig fundings.
+///
+/// Since the wallet can't derive data for such inputs, the spent output and
+/// its scripts must be provided explicitly. Foreign inputs are signed by their
+/// owners and are not touched by the wallet signer.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct ExternalInput {
+    /// Outpoint of the spent output.
+    pub outpoint: OutPoint,
+    /// Spent output, providing its amount and `scriptPubkey`.
+    pub utxo: TxOut,
+    /// Redeem script for P2SH outputs, including nested segwit.
+    pub redeem_script: Option<Script>,
+    /// Witness script for P2WSH outputs.
+    pub witness_script: Option<Script>,
+    /// Sequence number used by the input.
+    pub seq_no: SeqNo,
+    /// Signature hash type which should be used by the input owner.
+    pub sighash_type: SighashType,
+}
+
+impl ExternalInput {
+    /// Constructs foreign input spending `utxo` at `outpoint` without redeem
+    /// and witness scripts, with the default sequence number and
+    /// `SIGHASH_ALL` signature hash type.
+    pub fn with(outpoint: OutPoint, utxo: TxOut) -> ExternalInput {
+        ExternalInput {
+            outpoint,
+            utxo,
+            redeem_script: None,
+            witness_script: None,
+            seq_no: none!(),
+            sighash_type: SighashType::All,
+        }
+    }
+
+    /// Detects whether the input spends segwit output, either native or
+    /// nested into P2SH.
+    pub fn is_segwit(&self) -> bool {
+        self.utxo.script_pubkey.is_witness_program()
+            || self
+                .redeem_script
+                .as_ref()
+                .map(Script::is_witness_program)
+                .unwrap_or_default()
+    }
+}
+
+impl InputDescriptor {
+    /// Constructs input descriptor spending `outpoint` controlled by the
+    /// wallet descriptor at the `terminal` derivation, using default sequence
+    /// number and `SIGHASH_ALL` signature hash type.
+    pub fn with(outpoint: OutPoint, terminal: DerivationSubpath<UnhardenedIndex>) -> Self {
+        InputDescriptor {
+            outpoint,
+            terminal,
+            seq_no: none!(),
+            tweak: None,
+            sighash_type: SighashType::All,
+            external: None,
+            redeem_script: None,
+            witness_script: None,
+        }
+    }
+
+    /// Returns `scriptPubkey` of the spent output defined by the explicit
+    /// redeem and witness scripts, if any of them is present.
+    pub fn explicit_script_pubkey(&self) -> Option<Script> {
+        match (&self.redeem_script, &self.witness_script) {
+            (Some(redeem_script), _) => Some(Script::new_p2sh(&redeem_script.script_hash())),
+            (None, Some(witness_script)) => {
+                Some(Script::new_v0_p2wsh(&witness_script.wscript_hash()))
+            }
+            (None, None) => None,
+        }
+    }
+
+    /// Updates input sequence number to satisfy timelock policy of the spent
+    /// output with a given [`Satisfaction`]. Returns minimal lock time of the
+    /// spending transaction required by the satisfaction, if any.
+    pub fn apply_satisfaction(&mut self, satisfaction: &Satisfaction) -> Option<PackedLockTime> {
+        match satisfaction.seq_no {
+            Some(seq_no) => self.seq_no = seq_no,
+            // Absolute timelocks are enforced only for inputs with non-final
+            // sequence numbers
+            None if satisfaction.lock_time.is_some()
+                && self.seq_no.into_consensus() == Sequence::MAX.to_consensus_u32() =>
+            {
+                self.seq_no =
+                    SeqNo::from_consensus(Sequence::ENABLE_LOCKTIME_NO_RBF.to_consensus_u32())
+            }
+            None => {}
+        }
+        satisfaction.lock_time
+    }
+}
+
+impl Display for ExternalSatisfaction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if !self.script_sig.is_empty() {
+            write!(f, "scriptsig({})", self.script_sig.as_bytes().to_hex())?;
+        }
+        if !self.witness.is_empty() {
+            if !self.script_sig.is_empty() {
+                f.write_str(" ")?;
+            }
+            let elements = self
+                .witness
+                .iter()
+                .map(|element| element.to_hex())
+                .collect::<Vec<_>>();
+            write!(f, "witness({})", elements.join(","))?;
+        }
+        Ok(())
+    }
 }
 
 impl Display for InputDescriptor {
@@ -48,6 +195,16 @@ impl Display for InputDescriptor {
             f.write_str(" ")?;
             Display::fmt(&self.sighash_type, f)?;
         }
+        if let Some(external) = &self.external {
+            f.write_str(" ")?;
+            Display::fmt(external, f)?;
+        }
+        if let Some(redeem_script) = &self.redeem_script {
+            write!(f, " redeem_script={}", redeem_script.as_bytes().to_hex())?;
+        }
+        if let Some(witness_script) = &self.witness_script {
+            write!(f, " witness_script={}", witness_script.as_bytes().to_hex())?;
+        }
         Ok(())
     }
 }
@@ -84,6 +241,14 @@ pub enum ParseError {
     /// invalid input descriptor: terminal derivation information is required
     NoDerivation,
 
+    /// invalid hexadecimal representation of external input satisfaction
+    /// in `{0}`
+    InvalidExternal(String),
+
+    /// invalid hexadecimal representation of explicit redeem or witness
+    /// script in `{0}`
+    InvalidScript(String),
+
     /// unrecognized input descriptor fragment `{0}`
     UnrecognizedFragment(String),
 }
@@ -99,6 +264,8 @@ impl std::error::Error for ParseError {
             ParseError::InvalidTweakFormat(_) => None,
             ParseError::NoOutpoint => None,
             ParseError::NoDerivation => None,
+            ParseError::InvalidExternal(_) => None,
+            ParseError::InvalidScript(_) => None,
             ParseError::UnrecognizedFragment(_) => None,
         }
     }
@@ -112,16 +279,38 @@ impl FromStr for InputDescriptor {
         let outpoint = split.next().ok_or(ParseError::NoOutpoint)?;
         let derivation = split.next().ok_or(ParseError::NoDerivation)?;
 
-        let mut d = InputDescriptor {
-            outpoint: outpoint.parse()?,
-            terminal: derivation.parse()?,
-            seq_no: none!(),
-            tweak: None,
-            sighash_type: SighashType::All,
-        };
+        let mut d = InputDescriptor::with(outpoint.parse()?, derivation.parse()?);
 
         for fragment in split {
-            if let Ok(seq_no) = SeqNo::from_str(fragment) {
+            let invalid_external = || ParseError::InvalidExternal(fragment.to_owned());
+            let parse_script = |hex| {
+                Vec::<u8>::from_hex(hex)
+                    .map(Script::from)
+                    .map_err(|_| ParseError::InvalidScript(fragment.to_owned()))
+            };
+            if let Some(hex) = fragment.strip_prefix("redeem_script=") {
+                d.redeem_script = Some(parse_script(hex)?);
+            } else if let Some(hex) = fragment.strip_prefix("witness_script=") {
+                d.witness_script = Some(parse_script(hex)?);
+            } else if let Some(hex) = fragment
+                .strip_prefix("scriptsig(")
+                .and_then(|s| s.strip_suffix(')'))
+            {
+                let script = Vec::<u8>::from_hex(hex).map_err(|_| invalid_external())?;
+                let external = d.external.get_or_insert_with(ExternalSatisfaction::default);
+                external.script_sig = Script::from(script);
+            } else if let Some(elements) = fragment
+                .strip_prefix("witness(")
+                .and_then(|s| s.strip_suffix(')'))
+            {
+                let witness = elements
+                    .split(',')
+                    .map(Vec::<u8>::from_hex)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid_external())?;
+                let external = d.external.get_or_insert_with(ExternalSatisfaction::default);
+                external.witness = witness;
+            } else if let Ok(seq_no) = SeqNo::from_str(fragment) {
                 d.seq_no = seq_no;
             } else if let Ok(sighash_type) = SighashType::from_str(fragment) {
                 d.sighash_type = sighash_type;
@@ -143,6 +332,104 @@ impl FromStr for InputDescriptor {
     }
 }
 
+/// Errors discovering input descriptor for a transaction output
+#[cfg(feature = "miniscript")]
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum InputResolveError {
+    /// unable to resolve transaction output {0}
+    UnknownOutpoint(OutPoint),
+
+    /// output {0} does not belong to the wallet descriptor within the first
+    /// {1} addresses of each keychain
+    NotFound(OutPoint, u32),
+
+    /// wallet descriptor uses derivation pattern of {0} segments, while
+    /// input discovery supports only `/*` and `/<keychain>/*` patterns
+    UnsupportedPattern(usize),
+
+    /// descriptor does not contain any keys
+    NoKeys,
+
+    /// unable to derive descriptor scripts. Details: {0}
+    #[from]
+    Derive(bitcoin_hd::DeriveError),
+}
+
+#[cfg(feature = "miniscript")]
+mod _resolve {
+    use std::cell::Cell;
+
+    use bitcoin::secp256k1::{Secp256k1, Verification};
+    use bitcoin_hd::{DerivationAccount, DerivationIndexMap, SegmentIndexes};
+    use miniscript::{Descriptor, ForEachKey};
+
+    use super::*;
+    use crate::derive::Descriptor as _;
+
+    impl InputDescriptor {
+        /// Discovers input descriptor for a transaction output belonging to
+        /// the wallet `descriptor`.
+        ///
+        /// The `scriptPubkey` of the spent output is provided by the
+        /// `resolver` and looked up in the `index` map. If it is not found,
+        /// the map is extended to cover the first `lookahead` addresses of
+        /// the descriptor keychains (`/0/*` and `/1/*` for two-segment
+        /// derivation patterns, or the `/*` for single-segment patterns), so
+        /// the same map may be reused to resolve multiple inputs without
+        /// re-deriving the addresses.
+        ///
+        /// Resolved input descriptor uses default sequence number and
+        /// `SIGHASH_ALL` signature hash type.
+        pub fn resolve_from_outpoint<C: Verification>(
+            secp: &Secp256k1<C>,
+            descriptor: &Descriptor<DerivationAccount>,
+            outpoint: OutPoint,
+            resolver: impl FnOnce(OutPoint) -> Option<Script>,
+            index: &mut DerivationIndexMap,
+            lookahead: u32,
+        ) -> Result<InputDescriptor, InputResolveError> {
+            let script = resolver(outpoint).ok_or(InputResolveError::UnknownOutpoint(outpoint))?;
+
+            let account = Cell::new(None);
+            descriptor.for_each_key(|key| {
+                account.set(account.get().or(Some(key.account_fingerprint())));
+                true
+            });
+            let account = account.get().ok_or(InputResolveError::NoKeys)?;
+
+            if index.lookup(&script).is_none() {
+                let keychains = match descriptor.derive_pattern_len()? {
+                    1 => vec![DerivationSubpath::new()],
+                    2 => vec![
+                        DerivationSubpath::from(vec![UnhardenedIndex::zero()]),
+                        DerivationSubpath::from(vec![UnhardenedIndex::one()]),
+                    ],
+                    len => return Err(InputResolveError::UnsupportedPattern(len)),
+                };
+                for keychain in keychains {
+                    let derived = index.next_index(account, &keychain).first_index();
+                    index.extend(
+                        account,
+                        &keychain,
+                        lookahead.saturating_sub(derived),
+                        |terminal| match descriptor {
+                            Descriptor::Tr(_) => descriptor.script_pubkey_tr(secp, terminal),
+                            _ => descriptor.script_pubkey_pretr(secp, terminal),
+                        },
+                    )?;
+                }
+            }
+
+            let (_, terminal) = index
+                .lookup(&script)
+                .ok_or(InputResolveError::NotFound(outpoint, lookahead))?;
+
+            Ok(InputDescriptor::with(outpoint, terminal.clone()))
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -157,6 +444,9 @@ mod test {
             seq_no: "rbf(1)".parse().unwrap(),
             tweak: None,
             sighash_type: SighashType::AllPlusAnyoneCanPay,
+            external: None,
+            redeem_script: None,
+            witness_script: None,
         };
 
         assert_eq!(
@@ -172,4 +462,112 @@ mod test {
                 .unwrap()
         );
     }
+
+    #[test]
+    fn external_satisfaction() {
+        let s = "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8 /0/0 \
+                 witness(,51,0102)";
+        let input = InputDescriptor::from_str(s).unwrap();
+        assert_eq!(
+            input.external,
+            Some(ExternalSatisfaction {
+                script_sig: Script::new(),
+                witness: vec![vec![], vec![0x51], vec![0x01, 0x02]],
+            })
+        );
+        assert_eq!(input.to_string(), s);
+
+        let s = "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8 /0/0 \
+                 scriptsig(0051) witness()";
+        let input = InputDescriptor::from_str(s).unwrap();
+        assert_eq!(input.external.as_ref().unwrap().witness, vec![
+            Vec::<u8>::new()
+        ]);
+        assert_eq!(input.to_string(), s);
+
+        assert_eq!(
+            InputDescriptor::from_str(
+                "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8 /0/0 \
+                 witness(zz)"
+            ),
+            Err(ParseError::InvalidExternal(s!("witness(zz)")))
+        );
+    }
+
+    #[test]
+    fn explicit_scripts() {
+        let s =
+            "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8 /0/0 \
+             redeem_script=0020ff9ac1d7f02b9f2e3d2d0e5c98c9d0e8c1a0b4a3e1d2c9f8e7d6c5b4a39281a1";
+        let input = InputDescriptor::from_str(s).unwrap();
+        let redeem_script = input.redeem_script.clone().unwrap();
+        assert!(redeem_script.is_v0_p2wsh());
+        assert_eq!(input.witness_script, None);
+        assert_eq!(
+            input.explicit_script_pubkey(),
+            Some(Script::new_p2sh(&redeem_script.script_hash()))
+        );
+        assert_eq!(input.to_string(), s);
+
+        let witness_script = Script::from(vec![0x51]);
+        let s = "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8 /0/0 \
+                 witness_script=51";
+        let input = InputDescriptor::from_str(s).unwrap();
+        assert_eq!(input.witness_script, Some(witness_script.clone()));
+        assert_eq!(
+            input.explicit_script_pubkey(),
+            Some(Script::new_v0_p2wsh(&witness_script.wscript_hash()))
+        );
+        assert_eq!(input.to_string(), s);
+
+        assert_eq!(
+            InputDescriptor::from_str(
+                "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8 /0/0 \
+                 redeem_script=5"
+            ),
+            Err(ParseError::InvalidScript(s!("redeem_script=5")))
+        );
+    }
+
+    #[cfg(feature = "miniscript")]
+    #[test]
+    fn resolve_from_outpoint() {
+        use bitcoin::secp256k1::SECP256K1;
+        use bitcoin_hd::{DerivationAccount, DerivationIndexMap};
+
+        use crate::derive::Descriptor as _;
+
+        let descriptor = miniscript::Descriptor::<DerivationAccount>::from_str(
+            "wpkh([d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqF\
+             JPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/0/*)",
+        )
+        .unwrap();
+        let outpoint = OutPoint::from_str(
+            "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8",
+        )
+        .unwrap();
+        let terminal = [UnhardenedIndex::from(15u8)];
+        let script = descriptor.script_pubkey_pretr(SECP256K1, terminal).unwrap();
+
+        let mut index = DerivationIndexMap::new();
+        let resolve = |index: &mut DerivationIndexMap, lookahead| {
+            InputDescriptor::resolve_from_outpoint(
+                SECP256K1,
+                &descriptor,
+                outpoint,
+                |_| Some(script.clone()),
+                index,
+                lookahead,
+            )
+        };
+        assert!(matches!(
+            resolve(&mut index, 10),
+            Err(InputResolveError::NotFound(_, 10))
+        ));
+        assert_eq!(index.len(), 10);
+        let input = resolve(&mut index, 20).unwrap();
+        assert_eq!(index.len(), 20);
+        assert_eq!(input.terminal, DerivationSubpath::from(terminal.to_vec()));
+        assert_eq!(input.to_string(), format!("{} /15", outpoint));
+    }
 }
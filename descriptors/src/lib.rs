@@ -30,18 +30,52 @@ extern crate miniscript_crate as miniscript;
 #[macro_use]
 extern crate serde_crate as serde;
 
+pub mod address;
 mod deduction;
+#[cfg(feature = "miniscript")]
+mod delegation;
 pub mod derive;
 mod descriptor;
+pub mod estimate;
+pub mod hlc;
+#[cfg(feature = "miniscript")]
+mod inheritance;
 mod input;
+pub mod lightning;
+pub mod locks;
+#[cfg(feature = "miniscript")]
+pub mod policy;
+pub mod taptree;
 #[cfg(feature = "miniscript")]
 mod templates;
+pub mod trkey;
 
-pub use deduction::DeductionError;
+pub use deduction::{
+    is_standard_script, recover_internal_key, recover_witness_internal_key, tap_merkle_root,
+    DeductionError, SpendDeduction, SpendDeductionError, TapLeafError, TapLeafProof,
+};
+#[cfg(feature = "miniscript")]
+pub use delegation::{Delegation, DelegationLock, DelegationLockParseError, DelegationRole};
+#[cfg(feature = "miniscript")]
+pub use descriptor::MiniscriptConversionError;
 pub use descriptor::{
     BareDescriptor, CompositeDescrType, DescrVariants, DescriptorClass, Error, InnerDescrType,
     OuterDescrType, ParseError, ScriptPubkeyDescr, SpkClass, UnsupportedScriptPubkey,
 };
-pub use input::InputDescriptor;
+#[cfg(feature = "miniscript")]
+pub use inheritance::Inheritance;
+#[cfg(feature = "miniscript")]
+pub use input::InputResolveError;
+pub use input::{ExternalInput, ExternalSatisfaction, InputDescriptor};
+#[cfg(feature = "miniscript")]
+pub use policy::{CompiledPolicy, PolicyError};
+pub use taptree::{
+    to_tap_tree, verify_taproot_commitment, DfsPath, DisclosureError, LeafScriptText, MerkleProof,
+    MerkleProofError, ScriptTreeText, TreeDisclosure, TreeNodeInfo, TreeNodeText, TreeParseError,
+    WeightedTree, WeightedTreeError,
+};
+#[cfg(feature = "miniscript")]
+pub use taptree::{DescriptorTree, DescriptorTreeError};
 #[cfg(feature = "miniscript")]
 pub use templates::ScriptTemplate;
+pub use trkey::{is_key_only_output, TrKeyDescriptor, TrKeyError};
@@ -0,0 +1,494 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Lightning network scripts defined by BOLT-3, which can't be represented
+//! with miniscript: commitment transaction `to_local`, `to_remote` and anchor
+//! outputs, and offered and received HTLC outputs, including their
+//! `option_anchors` variants.
+//!
+//! Each script type can be constructed into [`LockScript`] and parsed back
+//! from it, providing access to the CSV and CLTV parameters of the script.
+
+use amplify::Wrapper;
+use bitcoin::blockdata::opcodes::all::*;
+use bitcoin::blockdata::script::{Builder, Instruction};
+use bitcoin::hashes::{ripemd160, sha256, Hash};
+use bitcoin::{PubkeyHash, PublicKey, Script};
+use bitcoin_scripts::{LockScript, PubkeyScript};
+
+use crate::hlc::{parse, read_hash, read_int, read_pubkey};
+
+/// Relative timelock used by `option_anchors` outputs to prevent CPFP carve-out
+/// abuse.
+pub const ANCHORS_CSV: u16 = 1;
+
+/// Relative timelock after which anyone may sweep an anchor output.
+pub const ANCHOR_SWEEP_CSV: u16 = 16;
+
+/// BOLT-3 `to_local` output script: spendable by the revocation key or, after
+/// `to_self_delay` blocks, by the local delayed key.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ToLocal {
+    /// Revocation public key
+    pub revocation_pubkey: PublicKey,
+    /// Relative timelock (CSV) of the local delayed key path
+    pub to_self_delay: u16,
+    /// Local delayed payment public key
+    pub local_delayed_pubkey: PublicKey,
+}
+
+/// BOLT-3 `to_remote` output script used by `option_anchors` channels. Without
+/// `option_anchors` `to_remote` output is a plain P2WPKH and has no script.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ToRemote {
+    /// Remote payment public key
+    pub remote_pubkey: PublicKey,
+}
+
+/// BOLT-3 anchor output script: spendable by the funding key or, after
+/// [`ANCHOR_SWEEP_CSV`] blocks, by anyone.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Anchor {
+    /// Funding public key of the anchor owner
+    pub funding_pubkey: PublicKey,
+}
+
+/// Part of HTLC output scripts common for offered and received HTLCs.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct HtlcKeys {
+    /// `RIPEMD160(SHA256(revocationpubkey))`
+    pub revocation_key_hash: PubkeyHash,
+    /// Remote HTLC public key
+    pub remote_htlc_pubkey: PublicKey,
+    /// Local HTLC public key
+    pub local_htlc_pubkey: PublicKey,
+    /// `RIPEMD160(payment_hash)`
+    pub payment_hash160: ripemd160::Hash,
+    /// Whether the script uses `option_anchors` variant, requiring
+    /// [`ANCHORS_CSV`] relative timelock for the remote party paths
+    pub anchors: bool,
+}
+
+impl HtlcKeys {
+    /// Constructs HTLC script keys from the revocation public key and payment
+    /// hash, which are represented in the script by their hashes.
+    pub fn with(
+        revocation_pubkey: &PublicKey,
+        remote_htlc_pubkey: PublicKey,
+        local_htlc_pubkey: PublicKey,
+        payment_hash: sha256::Hash,
+        anchors: bool,
+    ) -> HtlcKeys {
+        HtlcKeys {
+            revocation_key_hash: revocation_pubkey.pubkey_hash(),
+            remote_htlc_pubkey,
+            local_htlc_pubkey,
+            payment_hash160: ripemd160::Hash::hash(&payment_hash[..]),
+            anchors,
+        }
+    }
+}
+
+/// BOLT-3 offered HTLC output script.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct OfferedHtlc(pub HtlcKeys);
+
+/// BOLT-3 received HTLC output script.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ReceivedHtlc {
+    /// Keys and hashes used by the script
+    pub keys: HtlcKeys,
+    /// Absolute timelock (CLTV) of the HTLC timeout path
+    pub cltv_expiry: u32,
+}
+
+/// Any of the BOLT-3 output scripts
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, From)]
+pub enum LnScript {
+    /// `to_local` output script
+    #[from]
+    ToLocal(ToLocal),
+
+    /// `to_remote` output script of `option_anchors` channels
+    #[from]
+    ToRemote(ToRemote),
+
+    /// Anchor output script
+    #[from]
+    Anchor(Anchor),
+
+    /// Offered HTLC output script
+    #[from]
+    OfferedHtlc(OfferedHtlc),
+
+    /// Received HTLC output script
+    #[from]
+    ReceivedHtlc(ReceivedHtlc),
+}
+
+impl ToLocal {
+    /// Constructs script of the output.
+    pub fn to_lock_script(&self) -> LockScript {
+        Builder::new()
+            .push_opcode(OP_IF)
+            .push_key(&self.revocation_pubkey)
+            .push_opcode(OP_ELSE)
+            .push_int(self.to_self_delay as i64)
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_key(&self.local_delayed_pubkey)
+            .push_opcode(OP_ENDIF)
+            .push_opcode(OP_CHECKSIG)
+            .into_script()
+            .into()
+    }
+
+    /// Parses script of the output, returning `None` if the script does not
+    /// match the template.
+    pub fn from_lock_script(script: &LockScript) -> Option<Self> {
+        parse(
+            script,
+            |i| {
+                Some(ToLocal {
+                    revocation_pubkey: read_pubkey(i.get(1)?)?,
+                    to_self_delay: u16::try_from(read_int(i.get(3)?)?).ok()?,
+                    local_delayed_pubkey: read_pubkey(i.get(6)?)?,
+                })
+            },
+            Self::to_lock_script,
+        )
+    }
+}
+
+impl ToRemote {
+    /// Constructs script of the output.
+    pub fn to_lock_script(&self) -> LockScript {
+        Builder::new()
+            .push_key(&self.remote_pubkey)
+            .push_opcode(OP_CHECKSIGVERIFY)
+            .push_int(ANCHORS_CSV as i64)
+            .push_opcode(OP_CSV)
+            .into_script()
+            .into()
+    }
+
+    /// Parses script of the output, returning `None` if the script does not
+    /// match the template.
+    pub fn from_lock_script(script: &LockScript) -> Option<Self> {
+        parse(
+            script,
+            |i| {
+                Some(ToRemote {
+                    remote_pubkey: read_pubkey(i.first()?)?,
+                })
+            },
+            Self::to_lock_script,
+        )
+    }
+}
+
+impl Anchor {
+    /// Constructs script of the output.
+    pub fn to_lock_script(&self) -> LockScript {
+        Builder::new()
+            .push_key(&self.funding_pubkey)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_IFDUP)
+            .push_opcode(OP_NOTIF)
+            .push_int(ANCHOR_SWEEP_CSV as i64)
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_ENDIF)
+            .into_script()
+            .into()
+    }
+
+    /// Parses script of the output, returning `None` if the script does not
+    /// match the template.
+    pub fn from_lock_script(script: &LockScript) -> Option<Self> {
+        parse(
+            script,
+            |i| {
+                Some(Anchor {
+                    funding_pubkey: read_pubkey(i.first()?)?,
+                })
+            },
+            Self::to_lock_script,
+        )
+    }
+}
+
+impl HtlcKeys {
+    /// Script prefix common for both offered and received HTLCs, up to the
+    /// check of the witness element size.
+    fn script_prefix(&self) -> Builder {
+        Builder::new()
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_HASH160)
+            .push_slice(&self.revocation_key_hash[..])
+            .push_opcode(OP_EQUAL)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ELSE)
+            .push_key(&self.remote_htlc_pubkey)
+            .push_opcode(OP_SWAP)
+            .push_opcode(OP_SIZE)
+            .push_int(32)
+            .push_opcode(OP_EQUAL)
+    }
+
+    fn script_suffix(&self, builder: Builder) -> LockScript {
+        let builder = if self.anchors {
+            builder
+                .push_int(ANCHORS_CSV as i64)
+                .push_opcode(OP_CSV)
+                .push_opcode(OP_DROP)
+        } else {
+            builder
+        };
+        builder.push_opcode(OP_ENDIF).into_script().into()
+    }
+
+    /// Extracts keys from HTLC script instructions, given the positions of
+    /// the local HTLC key and payment hash, which differ between offered and
+    /// received HTLCs.
+    fn extract(
+        i: &[Instruction],
+        len: usize,
+        local_key_pos: usize,
+        payment_hash_pos: usize,
+    ) -> Option<HtlcKeys> {
+        let anchors = match i.len() {
+            l if l == len => false,
+            l if l == len + 3 => true,
+            _ => return None,
+        };
+        Some(HtlcKeys {
+            revocation_key_hash: read_hash(i.get(2)?)?,
+            remote_htlc_pubkey: read_pubkey(i.get(7)?)?,
+            local_htlc_pubkey: read_pubkey(i.get(local_key_pos)?)?,
+            payment_hash160: read_hash(i.get(payment_hash_pos)?)?,
+            anchors,
+        })
+    }
+}
+
+impl OfferedHtlc {
+    /// Constructs script of the output.
+    pub fn to_lock_script(&self) -> LockScript {
+        let keys = &self.0;
+        let builder = keys
+            .script_prefix()
+            .push_opcode(OP_NOTIF)
+            .push_opcode(OP_DROP)
+            .push_int(2)
+            .push_opcode(OP_SWAP)
+            .push_key(&keys.local_htlc_pubkey)
+            .push_int(2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .push_opcode(OP_ELSE)
+            .push_opcode(OP_HASH160)
+            .push_slice(&keys.payment_hash160[..])
+            .push_opcode(OP_EQUALVERIFY)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ENDIF);
+        keys.script_suffix(builder)
+    }
+
+    /// Parses script of the output, returning `None` if the script does not
+    /// match the template.
+    pub fn from_lock_script(script: &LockScript) -> Option<Self> {
+        parse(
+            script,
+            |i| HtlcKeys::extract(i, 26, 16, 21).map(OfferedHtlc),
+            Self::to_lock_script,
+        )
+    }
+}
+
+impl ReceivedHtlc {
+    /// Constructs script of the output.
+    pub fn to_lock_script(&self) -> LockScript {
+        let keys = &self.keys;
+        let builder = keys
+            .script_prefix()
+            .push_opcode(OP_IF)
+            .push_opcode(OP_HASH160)
+            .push_slice(&keys.payment_hash160[..])
+            .push_opcode(OP_EQUALVERIFY)
+            .push_int(2)
+            .push_opcode(OP_SWAP)
+            .push_key(&keys.local_htlc_pubkey)
+            .push_int(2)
+            .push_opcode(OP_CHECKMULTISIG)
+            .push_opcode(OP_ELSE)
+            .push_opcode(OP_DROP)
+            .push_int(self.cltv_expiry as i64)
+            .push_opcode(OP_CLTV)
+            .push_opcode(OP_DROP)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ENDIF);
+        keys.script_suffix(builder)
+    }
+
+    /// Parses script of the output, returning `None` if the script does not
+    /// match the template.
+    pub fn from_lock_script(script: &LockScript) -> Option<Self> {
+        parse(
+            script,
+            |i| {
+                Some(ReceivedHtlc {
+                    keys: HtlcKeys::extract(i, 29, 18, 14)?,
+                    cltv_expiry: u32::try_from(read_int(i.get(23)?)?).ok()?,
+                })
+            },
+            Self::to_lock_script,
+        )
+    }
+}
+
+impl LnScript {
+    /// Detects which of BOLT-3 templates the script matches, returning `None`
+    /// for scripts not matching any of them.
+    pub fn from_lock_script(script: &LockScript) -> Option<Self> {
+        ToLocal::from_lock_script(script)
+            .map(LnScript::from)
+            .or_else(|| ToRemote::from_lock_script(script).map(LnScript::from))
+            .or_else(|| Anchor::from_lock_script(script).map(LnScript::from))
+            .or_else(|| OfferedHtlc::from_lock_script(script).map(LnScript::from))
+            .or_else(|| ReceivedHtlc::from_lock_script(script).map(LnScript::from))
+    }
+
+    /// Constructs script of the output.
+    pub fn to_lock_script(&self) -> LockScript {
+        match self {
+            LnScript::ToLocal(script) => script.to_lock_script(),
+            LnScript::ToRemote(script) => script.to_lock_script(),
+            LnScript::Anchor(script) => script.to_lock_script(),
+            LnScript::OfferedHtlc(script) => script.to_lock_script(),
+            LnScript::ReceivedHtlc(script) => script.to_lock_script(),
+        }
+    }
+
+    /// Returns P2WSH `scriptPubkey` of the output.
+    pub fn to_script_pubkey(&self) -> PubkeyScript {
+        Script::new_v0_p2wsh(&self.to_lock_script().as_inner().wscript_hash()).into()
+    }
+
+    /// Returns relative timelock (CSV) required by the script, if any. For
+    /// HTLCs with `option_anchors` and `to_remote` output this is the timelock
+    /// of the remote party spending path.
+    pub fn csv_delay(&self) -> Option<u16> {
+        match self {
+            LnScript::ToLocal(script) => Some(script.to_self_delay),
+            LnScript::ToRemote(_) => Some(ANCHORS_CSV),
+            LnScript::Anchor(_) => Some(ANCHOR_SWEEP_CSV),
+            LnScript::OfferedHtlc(OfferedHtlc(keys))
+            | LnScript::ReceivedHtlc(ReceivedHtlc { keys, .. }) => {
+                keys.anchors.then_some(ANCHORS_CSV)
+            }
+        }
+    }
+
+    /// Returns absolute timelock (CLTV) required by the script, if any.
+    pub fn cltv_expiry(&self) -> Option<u32> {
+        match self {
+            LnScript::ReceivedHtlc(script) => Some(script.cltv_expiry),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn pubkey(hex: &str) -> PublicKey { PublicKey::from_str(hex).unwrap() }
+
+    #[test]
+    fn bolt3_scripts() {
+        // Keys and `to_local` script are taken from BOLT-3 test vectors
+        let revocation_pubkey =
+            pubkey("0212a140cd0c6539d07cd08dfe09984dec3251ea808b892efeac3ede9402bf2b19");
+        let local_delayed_pubkey =
+            pubkey("03fd5960528dc152014952efdb702a88f71e3c1653b2314431701ec77e57fde83c");
+        let to_local = ToLocal {
+            revocation_pubkey,
+            to_self_delay: 144,
+            local_delayed_pubkey,
+        };
+        let script = to_local.to_lock_script();
+        assert_eq!(
+            script.as_inner().asm(),
+            "OP_IF OP_PUSHBYTES_33 \
+             0212a140cd0c6539d07cd08dfe09984dec3251ea808b892efeac3ede9402bf2b19 OP_ELSE \
+             OP_PUSHBYTES_2 9000 OP_CSV OP_DROP OP_PUSHBYTES_33 \
+             03fd5960528dc152014952efdb702a88f71e3c1653b2314431701ec77e57fde83c OP_ENDIF \
+             OP_CHECKSIG"
+        );
+        let parsed = LnScript::from_lock_script(&script).unwrap();
+        assert_eq!(parsed, LnScript::ToLocal(to_local));
+        assert_eq!(parsed.csv_delay(), Some(144));
+        assert_eq!(parsed.cltv_expiry(), None);
+
+        let remote_htlc_pubkey =
+            pubkey("0394854aa6eab5b2a8122cc726e9dded053a2184d88256816826d6231c068d4a5b");
+        let local_htlc_pubkey =
+            pubkey("030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e7");
+        let payment_hash = sha256::Hash::hash(&[0x02; 32]);
+        for anchors in [false, true] {
+            let keys = HtlcKeys::with(
+                &revocation_pubkey,
+                remote_htlc_pubkey,
+                local_htlc_pubkey,
+                payment_hash,
+                anchors,
+            );
+            let offered = OfferedHtlc(keys);
+            let received = ReceivedHtlc {
+                keys,
+                cltv_expiry: 501,
+            };
+            let csv = anchors.then_some(ANCHORS_CSV);
+
+            let parsed = LnScript::from_lock_script(&offered.to_lock_script()).unwrap();
+            assert_eq!(parsed, LnScript::OfferedHtlc(offered));
+            assert_eq!(parsed.csv_delay(), csv);
+
+            let parsed = LnScript::from_lock_script(&received.to_lock_script()).unwrap();
+            assert_eq!(parsed, LnScript::ReceivedHtlc(received));
+            assert_eq!(parsed.csv_delay(), csv);
+            assert_eq!(parsed.cltv_expiry(), Some(501));
+        }
+
+        let to_remote = ToRemote {
+            remote_pubkey: remote_htlc_pubkey,
+        };
+        assert_eq!(
+            LnScript::from_lock_script(&to_remote.to_lock_script()),
+            Some(LnScript::ToRemote(to_remote))
+        );
+        let anchor = Anchor {
+            funding_pubkey: local_htlc_pubkey,
+        };
+        assert_eq!(
+            LnScript::from_lock_script(&anchor.to_lock_script()),
+            Some(LnScript::Anchor(anchor))
+        );
+
+        // Non-minimal encoding of the delay must not match the template
+        let mut bytes = to_local.to_lock_script().into_inner().into_bytes();
+        bytes.splice(36..39, [0x03, 0x90, 0x00, 0x00]);
+        let script = LockScript::from(Script::from(bytes));
+        assert_eq!(LnScript::from_lock_script(&script), None);
+    }
+}
@@ -0,0 +1,515 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Timelock policies combining absolute (`OP_CHECKLOCKTIMEVERIFY`, miniscript
+//! `after`) and relative (`OP_CHECKSEQUENCEVERIFY`, miniscript `older`)
+//! constraints, and their evaluation against the chain state.
+//!
+//! Relative timelocks are measured from the block preceding the one which
+//! has confirmed the spent output (the *anchor* block), as defined by BIP-68.
+
+use std::fmt::{self, Display, Formatter};
+
+use bitcoin::{PackedLockTime, Sequence};
+use bitcoin_blockchain::locks::SeqNo;
+#[cfg(feature = "miniscript")]
+use miniscript::policy::{Liftable, Semantic};
+#[cfg(feature = "miniscript")]
+use miniscript::{Descriptor, MiniscriptKey};
+
+/// Number of blocks used to compute median time past.
+pub const MEDIAN_TIME_SPAN: u32 = 11;
+
+/// Average number of seconds between blocks, used to estimate the height at
+/// which time-based locks expire.
+pub const SECONDS_PER_BLOCK: u32 = 600;
+
+/// Lock time values below this threshold are block heights; other values are
+/// UNIX timestamps.
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Height and median time past (MTP) of a block
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct BlockInfo {
+    /// Block height
+    pub height: u32,
+
+    /// Median timestamp of the block and its ten predecessors
+    pub median_time_past: u32,
+}
+
+/// Single timelock constraint
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum Lock {
+    /// Absolute timelock expiring once the chain tip reaches the height
+    #[display("after_height({0})")]
+    AfterHeight(u32),
+
+    /// Absolute timelock expiring once the chain tip median time past exceeds
+    /// the UNIX timestamp
+    #[display("after_time({0})")]
+    AfterTime(u32),
+
+    /// Relative timelock in blocks since the anchor block
+    #[display("older_blocks({0})")]
+    OlderBlocks(u16),
+
+    /// Relative timelock in 512-second intervals since the anchor block
+    #[display("older_time({0})")]
+    OlderTime(u16),
+}
+
+impl Lock {
+    /// Constructs absolute timelock from the consensus lock time value.
+    /// Returns `None` for zero lock time, which puts no restrictions.
+    pub fn from_lock_time(lock_time: PackedLockTime) -> Option<Lock> {
+        match lock_time.0 {
+            0 => None,
+            height if height < LOCKTIME_THRESHOLD => Some(Lock::AfterHeight(height)),
+            time => Some(Lock::AfterTime(time)),
+        }
+    }
+
+    /// Constructs relative timelock from the consensus sequence number.
+    /// Returns `None` if the sequence number does not encode relative
+    /// timelock.
+    pub fn from_sequence(seq: Sequence) -> Option<Lock> {
+        if !seq.is_relative_lock_time() {
+            return None;
+        }
+        let value = (seq.to_consensus_u32() & 0xFFFF) as u16;
+        Some(if seq.is_time_locked() {
+            Lock::OlderTime(value)
+        } else {
+            Lock::OlderBlocks(value)
+        })
+    }
+
+    /// Detects whether the lock is an absolute timelock
+    pub fn is_absolute(self) -> bool { matches!(self, Lock::AfterHeight(_) | Lock::AfterTime(_)) }
+
+    /// Returns minimal transaction lock time satisfying absolute timelock, or
+    /// `None` for relative timelocks.
+    pub fn to_lock_time(self) -> Option<PackedLockTime> {
+        match self {
+            Lock::AfterHeight(value) | Lock::AfterTime(value) => Some(PackedLockTime(value)),
+            Lock::OlderBlocks(_) | Lock::OlderTime(_) => None,
+        }
+    }
+
+    /// Returns input sequence number satisfying relative timelock, or `None`
+    /// for absolute timelocks.
+    pub fn to_seq_no(self) -> Option<SeqNo> {
+        let seq = match self {
+            Lock::OlderBlocks(blocks) => Sequence::from_height(blocks),
+            Lock::OlderTime(intervals) => Sequence::from_512_second_intervals(intervals),
+            Lock::AfterHeight(_) | Lock::AfterTime(_) => return None,
+        };
+        Some(SeqNo::from_consensus(seq.to_consensus_u32()))
+    }
+
+    /// Computes chain state at which the lock expires, i.e. the minimal chain
+    /// tip on top of which a transaction satisfying the lock may be mined.
+    pub fn maturity(self, anchor: BlockInfo) -> Maturity {
+        match self {
+            Lock::AfterHeight(height) => Maturity {
+                height,
+                median_time_past: 0,
+            },
+            Lock::AfterTime(time) => Maturity {
+                height: 0,
+                median_time_past: time.saturating_add(1),
+            },
+            Lock::OlderBlocks(blocks) => Maturity {
+                height: anchor.height.saturating_add(blocks as u32),
+                median_time_past: 0,
+            },
+            Lock::OlderTime(intervals) => Maturity {
+                height: 0,
+                median_time_past: anchor
+                    .median_time_past
+                    .saturating_add(intervals as u32 * 512),
+            },
+        }
+    }
+}
+
+/// Minimal chain tip height and median time past on top of which a
+/// transaction may be mined
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Maturity {
+    /// Minimal chain tip height
+    pub height: u32,
+
+    /// Minimal chain tip median time past
+    pub median_time_past: u32,
+}
+
+impl Maturity {
+    /// Detects whether the chain tip has reached the maturity
+    pub fn is_reached(self, tip: BlockInfo) -> bool {
+        tip.height >= self.height && tip.median_time_past >= self.median_time_past
+    }
+
+    /// Estimates chain tip height at which the maturity will be reached,
+    /// assuming [`SECONDS_PER_BLOCK`] interval between blocks.
+    pub fn estimated_height(self, tip: BlockInfo) -> u32 {
+        let time_left = self.median_time_past.saturating_sub(tip.median_time_past);
+        let time_blocks =
+            time_left / SECONDS_PER_BLOCK + (time_left % SECONDS_PER_BLOCK != 0) as u32;
+        self.height.max(tip.height.saturating_add(time_blocks))
+    }
+
+    fn join(self, other: Maturity) -> Maturity {
+        Maturity {
+            height: self.height.max(other.height),
+            median_time_past: self.median_time_past.max(other.median_time_past),
+        }
+    }
+}
+
+/// Locks which have to be satisfied simultaneously by a spending transaction.
+/// Transaction has a single lock time and input a single sequence number, so
+/// there may be at most one absolute and one relative lock of the same kind.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+struct Conjunction {
+    after: Option<Lock>,
+    older: Option<Lock>,
+}
+
+impl Conjunction {
+    fn with(lock: Lock) -> Conjunction {
+        if lock.is_absolute() {
+            Conjunction {
+                after: Some(lock),
+                older: None,
+            }
+        } else {
+            Conjunction {
+                after: None,
+                older: Some(lock),
+            }
+        }
+    }
+
+    fn merge(a: Option<Lock>, b: Option<Lock>) -> Result<Option<Lock>, ()> {
+        Ok(match (a, b) {
+            (None, lock) | (lock, None) => lock,
+            (Some(Lock::AfterHeight(a)), Some(Lock::AfterHeight(b))) => {
+                Some(Lock::AfterHeight(a.max(b)))
+            }
+            (Some(Lock::AfterTime(a)), Some(Lock::AfterTime(b))) => Some(Lock::AfterTime(a.max(b))),
+            (Some(Lock::OlderBlocks(a)), Some(Lock::OlderBlocks(b))) => {
+                Some(Lock::OlderBlocks(a.max(b)))
+            }
+            (Some(Lock::OlderTime(a)), Some(Lock::OlderTime(b))) => Some(Lock::OlderTime(a.max(b))),
+            _ => return Err(()),
+        })
+    }
+
+    fn join(self, other: Conjunction) -> Option<Conjunction> {
+        Some(Conjunction {
+            after: Conjunction::merge(self.after, other.after).ok()?,
+            older: Conjunction::merge(self.older, other.older).ok()?,
+        })
+    }
+
+    fn maturity(self, anchor: BlockInfo) -> Maturity {
+        self.after
+            .into_iter()
+            .chain(self.older)
+            .map(|lock| lock.maturity(anchor))
+            .fold(Maturity::default(), Maturity::join)
+    }
+}
+
+/// Way of satisfying timelock policy by a spending transaction
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Satisfaction {
+    /// Chain state at which the transaction may be mined
+    pub maturity: Maturity,
+
+    /// Minimal lock time of the transaction, if required
+    pub lock_time: Option<PackedLockTime>,
+
+    /// Sequence number which must be used by the input, if required
+    pub seq_no: Option<SeqNo>,
+}
+
+/// Timelock policy combining multiple absolute and relative timelocks
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Policy {
+    /// Policy without timelocks
+    Unlocked,
+
+    /// Policy which can't be satisfied
+    Unsatisfiable,
+
+    /// Single timelock
+    Lock(Lock),
+
+    /// Policy requiring all of the nested policies to be satisfied
+    All(Vec<Policy>),
+
+    /// Policy requiring any of the nested policies to be satisfied
+    Any(Vec<Policy>),
+}
+
+impl Display for Policy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (name, policies) = match self {
+            Policy::Unlocked => return f.write_str("unlocked"),
+            Policy::Unsatisfiable => return f.write_str("unsatisfiable"),
+            Policy::Lock(lock) => return Display::fmt(lock, f),
+            Policy::All(policies) => ("all", policies),
+            Policy::Any(policies) => ("any", policies),
+        };
+        write!(f, "{}(", name)?;
+        for (index, policy) in policies.iter().enumerate() {
+            if index > 0 {
+                f.write_str(",")?;
+            }
+            Display::fmt(policy, f)?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl From<Lock> for Policy {
+    fn from(lock: Lock) -> Self { Policy::Lock(lock) }
+}
+
+impl Policy {
+    /// Lists all lock combinations satisfying the policy, skipping
+    /// combinations which can't be satisfied by a single transaction input
+    fn conjunctions(&self) -> Vec<Conjunction> {
+        match self {
+            Policy::Unlocked => vec![Conjunction::default()],
+            Policy::Unsatisfiable => vec![],
+            Policy::Lock(lock) => vec![Conjunction::with(*lock)],
+            Policy::Any(policies) => policies.iter().flat_map(Policy::conjunctions).collect(),
+            Policy::All(policies) => {
+                policies
+                    .iter()
+                    .fold(vec![Conjunction::default()], |acc, policy| {
+                        let branches = policy.conjunctions();
+                        acc.iter()
+                            .flat_map(|a| branches.iter().filter_map(|b| a.join(*b)))
+                            .collect()
+                    })
+            }
+        }
+    }
+
+    /// Finds the earliest way of satisfying the policy for an output whose
+    /// relative timelocks are measured from the `anchor` block, given the
+    /// current chain `tip`. Returns `None` if the policy is unsatisfiable.
+    ///
+    /// If there are multiple ways to satisfy the policy, the one which is
+    /// expected to mature first is returned.
+    pub fn satisfaction(&self, anchor: BlockInfo, tip: BlockInfo) -> Option<Satisfaction> {
+        let conjunction = self.conjunctions().into_iter().min_by_key(|conjunction| {
+            let maturity = conjunction.maturity(anchor);
+            (
+                !maturity.is_reached(tip),
+                maturity.estimated_height(tip),
+                maturity.median_time_past,
+            )
+        })?;
+        Some(Satisfaction {
+            maturity: conjunction.maturity(anchor),
+            lock_time: conjunction.after.and_then(Lock::to_lock_time),
+            seq_no: conjunction.older.and_then(Lock::to_seq_no),
+        })
+    }
+
+    /// Computes earliest chain state at which the policy may be satisfied.
+    /// Returns `None` if the policy is unsatisfiable.
+    pub fn maturity(&self, anchor: BlockInfo, tip: BlockInfo) -> Option<Maturity> {
+        self.satisfaction(anchor, tip)
+            .map(|satisfaction| satisfaction.maturity)
+    }
+
+    /// Detects whether the policy may be satisfied by a transaction mined on
+    /// top of the chain `tip`.
+    pub fn is_satisfied(&self, anchor: BlockInfo, tip: BlockInfo) -> bool {
+        self.maturity(anchor, tip)
+            .map(|maturity| maturity.is_reached(tip))
+            .unwrap_or_default()
+    }
+
+    /// Extracts timelock policy from the semantic miniscript policy. Keys for
+    /// which `can_sign` returns `false` make spending paths requiring them
+    /// unsatisfiable; hash preimages are assumed to be known.
+    #[cfg(feature = "miniscript")]
+    pub fn from_semantic<Pk: MiniscriptKey>(
+        policy: &Semantic<Pk>,
+        can_sign: &impl Fn(&Pk) -> bool,
+    ) -> Policy {
+        match policy {
+            Semantic::Unsatisfiable => Policy::Unsatisfiable,
+            Semantic::Trivial => Policy::Unlocked,
+            Semantic::Key(pk) if can_sign(pk) => Policy::Unlocked,
+            Semantic::Key(_) => Policy::Unsatisfiable,
+            Semantic::After(lock_time) => Lock::from_lock_time(*lock_time)
+                .map(Policy::Lock)
+                .unwrap_or(Policy::Unlocked),
+            Semantic::Older(seq) => Lock::from_sequence(*seq)
+                .map(Policy::Lock)
+                .unwrap_or(Policy::Unlocked),
+            Semantic::Sha256(_)
+            | Semantic::Hash256(_)
+            | Semantic::Ripemd160(_)
+            | Semantic::Hash160(_) => Policy::Unlocked,
+            Semantic::Threshold(k, subs) => {
+                let subs = subs
+                    .iter()
+                    .map(|sub| Policy::from_semantic(sub, can_sign))
+                    .collect::<Vec<_>>();
+                Policy::threshold(*k, &subs)
+            }
+        }
+    }
+
+    /// Extracts timelock policy from the descriptor spending conditions; see
+    /// [`Policy::from_semantic`] for details.
+    #[cfg(feature = "miniscript")]
+    pub fn from_descriptor<Pk: MiniscriptKey>(
+        descriptor: &Descriptor<Pk>,
+        can_sign: &impl Fn(&Pk) -> bool,
+    ) -> Result<Policy, miniscript::Error> {
+        Ok(Policy::from_semantic(&descriptor.lift()?, can_sign))
+    }
+
+    /// Constructs policy requiring `k` of the `subs` policies to be satisfied
+    pub fn threshold(k: usize, subs: &[Policy]) -> Policy {
+        match (k, subs) {
+            (0, _) => Policy::Unlocked,
+            (k, subs) if k > subs.len() => Policy::Unsatisfiable,
+            (1, subs) => Policy::Any(subs.to_vec()),
+            (k, subs) if k == subs.len() => Policy::All(subs.to_vec()),
+            (k, [first, rest @ ..]) => Policy::Any(vec![
+                Policy::All(vec![first.clone(), Policy::threshold(k - 1, rest)]),
+                Policy::threshold(k, rest),
+            ]),
+            (_, []) => Policy::Unsatisfiable,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ANCHOR: BlockInfo = BlockInfo {
+        height: 800_000,
+        median_time_past: 1_690_000_000,
+    };
+
+    #[test]
+    fn lock_maturity() {
+        let tip = BlockInfo {
+            height: 800_100,
+            median_time_past: 1_690_060_000,
+        };
+
+        let older = Policy::Lock(Lock::OlderBlocks(144));
+        assert!(!older.is_satisfied(ANCHOR, tip));
+        let satisfaction = older.satisfaction(ANCHOR, tip).unwrap();
+        assert_eq!(satisfaction.maturity.height, 800_144);
+        assert_eq!(
+            satisfaction.seq_no,
+            Some(SeqNo::from_consensus(
+                Sequence::from_height(144).to_consensus_u32()
+            ))
+        );
+        assert_eq!(satisfaction.lock_time, None);
+
+        // Time-based relative lock of ~100 blocks is already expired
+        let older_time = Policy::Lock(Lock::OlderTime(100));
+        assert!(older_time.is_satisfied(ANCHOR, tip));
+
+        // Height-based and time-based relative locks can't be combined
+        let mixed = Policy::All(vec![older.clone(), older_time.clone()]);
+        assert_eq!(mixed.satisfaction(ANCHOR, tip), None);
+
+        // The earliest branch is chosen
+        let any = Policy::Any(vec![older.clone(), older_time]);
+        assert!(any.is_satisfied(ANCHOR, tip));
+
+        let after = Policy::Lock(Lock::AfterHeight(800_120));
+        let all = Policy::All(vec![older, after]);
+        let satisfaction = all.satisfaction(ANCHOR, tip).unwrap();
+        assert_eq!(satisfaction.maturity.height, 800_144);
+        assert_eq!(satisfaction.lock_time, Some(PackedLockTime(800_120)));
+        assert!(satisfaction.seq_no.is_some());
+
+        assert_eq!(
+            Lock::from_lock_time(PackedLockTime(1_700_000_000)),
+            Some(Lock::AfterTime(1_700_000_000))
+        );
+        assert_eq!(
+            all.to_string(),
+            "all(older_blocks(144),after_height(800120))"
+        );
+        assert_eq!(Lock::from_sequence(Sequence::MAX), None);
+        assert_eq!(
+            Lock::from_sequence(Sequence::from_512_second_intervals(10)),
+            Some(Lock::OlderTime(10))
+        );
+    }
+
+    #[test]
+    fn threshold() {
+        let locks = [
+            Policy::Lock(Lock::OlderBlocks(10)),
+            Policy::Lock(Lock::OlderBlocks(20)),
+            Policy::Lock(Lock::OlderBlocks(30)),
+        ];
+        let tip = BlockInfo {
+            height: 800_000,
+            median_time_past: ANCHOR.median_time_past,
+        };
+        let maturity = Policy::threshold(2, &locks).maturity(ANCHOR, tip).unwrap();
+        assert_eq!(maturity.height, 800_020);
+        assert_eq!(Policy::threshold(4, &locks).maturity(ANCHOR, tip), None);
+    }
+
+    #[cfg(feature = "miniscript")]
+    #[test]
+    fn semantic() {
+        use std::str::FromStr;
+
+        let policy = Semantic::<String>::from_str("or(pk(A),and(pk(B),older(144)))").unwrap();
+        let tip = ANCHOR;
+        let owner = Policy::from_semantic(&policy, &|pk: &String| pk == "A");
+        assert!(owner.is_satisfied(ANCHOR, tip));
+        let delegate = Policy::from_semantic(&policy, &|pk: &String| pk == "B");
+        assert_eq!(delegate.maturity(ANCHOR, tip).unwrap().height, 800_144);
+        let stranger = Policy::from_semantic(&policy, &|pk: &String| pk == "C");
+        assert_eq!(stranger.satisfaction(ANCHOR, tip), None);
+
+        let descriptor =
+            Descriptor::<String>::from_str("wsh(and_v(v:pk(A),after(800010)))").unwrap();
+        let policy = Policy::from_descriptor(&descriptor, &|_: &String| true).unwrap();
+        let satisfaction = policy.satisfaction(ANCHOR, tip).unwrap();
+        let mut input = crate::InputDescriptor::from_str(
+            "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8 /1/167",
+        )
+        .unwrap();
+        input.seq_no = SeqNo::from_consensus(u32::MAX);
+        assert_eq!(
+            input.apply_satisfaction(&satisfaction),
+            Some(PackedLockTime(800_010))
+        );
+        assert_eq!(input.seq_no.into_consensus(), 0xFFFF_FFFE);
+    }
+}
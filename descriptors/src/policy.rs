@@ -0,0 +1,222 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Front-end to the miniscript policy compiler producing wallet descriptors of
+//! a given [`DescriptorClass`] from concrete spending policies.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use miniscript::policy::compiler::CompilerError;
+use miniscript::policy::Concrete;
+use miniscript::{Descriptor, MiniscriptKey};
+
+use crate::estimate::TXIN_BASE_WEIGHT;
+use crate::DescriptorClass;
+
+/// Errors parsing and compiling miniscript concrete policies
+#[derive(Debug, Display)]
+#[display(doc_comments)]
+pub enum PolicyError {
+    /// invalid miniscript policy: {0}
+    Parse(miniscript::Error),
+
+    /// unable to compile policy into miniscript: {0}
+    Compiler(CompilerError),
+
+    /// compiled miniscript can't be used in a `{0}` descriptor: {1}
+    Descriptor(DescriptorClass, miniscript::Error),
+
+    /// unable to compute satisfaction weight for the compiled descriptor: {0}
+    Unsatisfiable(miniscript::Error),
+}
+
+impl std::error::Error for PolicyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PolicyError::Parse(err) => Some(err),
+            PolicyError::Compiler(err) => Some(err),
+            PolicyError::Descriptor(_, err) => Some(err),
+            PolicyError::Unsatisfiable(err) => Some(err),
+        }
+    }
+}
+
+/// Descriptor compiled from a concrete policy together with its spending
+/// cost parameters.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CompiledPolicy<Pk>
+where
+    Pk: MiniscriptKey,
+{
+    /// Descriptor class the policy was compiled for
+    pub class: DescriptorClass,
+
+    /// Compiled output descriptor
+    pub descriptor: Descriptor<Pk>,
+
+    /// Size of the compiled script in bytes. For taproot descriptors this is
+    /// the size of the largest script leaf (zero for key-only descriptors).
+    pub script_size: usize,
+
+    /// Upper bound on the weight of the `scriptSig` and witness data required
+    /// to satisfy the descriptor, in weight units.
+    pub max_satisfaction_weight: usize,
+}
+
+impl<Pk> CompiledPolicy<Pk>
+where
+    Pk: MiniscriptKey,
+{
+    /// Maximal weight of a transaction input spending the descriptor, in
+    /// weight units.
+    #[inline]
+    pub fn max_input_weight(&self) -> usize { TXIN_BASE_WEIGHT + self.max_satisfaction_weight }
+
+    /// Maximal virtual size of a transaction input spending the descriptor, in
+    /// vbytes.
+    #[inline]
+    pub fn max_input_vsize(&self) -> usize { (self.max_input_weight() + 3) / 4 }
+
+    /// Estimates fee (in satoshis) required to spend a single input of the
+    /// descriptor at a given feerate (in sat/vbyte).
+    #[inline]
+    pub fn input_fee(&self, feerate: f32) -> u64 {
+        (self.max_input_vsize() as f32 * feerate).ceil() as u64
+    }
+}
+
+impl<Pk> Display for CompiledPolicy<Pk>
+where
+    Pk: MiniscriptKey,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { Display::fmt(&self.descriptor, f) }
+}
+
+/// Parses miniscript concrete policy from a string and compiles it into a
+/// descriptor of a given class.
+pub fn compile_str<Pk>(
+    policy: &str,
+    class: DescriptorClass,
+) -> Result<CompiledPolicy<Pk>, PolicyError>
+where
+    Pk: MiniscriptKey + FromStr,
+    Pk::Sha256: FromStr,
+    Pk::Hash256: FromStr,
+    Pk::Ripemd160: FromStr,
+    Pk::Hash160: FromStr,
+    <Pk as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Sha256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash256 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Ripemd160 as FromStr>::Err: ToString,
+    <<Pk as MiniscriptKey>::Hash160 as FromStr>::Err: ToString,
+{
+    let policy = Concrete::<Pk>::from_str(policy).map_err(PolicyError::Parse)?;
+    compile(&policy, class)
+}
+
+/// Compiles miniscript concrete policy into a descriptor of a given class.
+///
+/// For taproot descriptors the internal key is selected by the compiler from
+/// the keys of the policy; if there is no key which can be used as an internal
+/// key the compilation fails.
+pub fn compile<Pk>(
+    policy: &Concrete<Pk>,
+    class: DescriptorClass,
+) -> Result<CompiledPolicy<Pk>, PolicyError>
+where
+    Pk: MiniscriptKey,
+{
+    let (descriptor, script_size) = match class {
+        DescriptorClass::PreSegwit => {
+            let ms = policy.compile().map_err(PolicyError::Compiler)?;
+            let script_size = ms.script_size();
+            let descriptor =
+                Descriptor::new_sh(ms).map_err(|err| PolicyError::Descriptor(class, err))?;
+            (descriptor, script_size)
+        }
+        DescriptorClass::SegwitV0 => {
+            let ms = policy.compile().map_err(PolicyError::Compiler)?;
+            let script_size = ms.script_size();
+            let descriptor =
+                Descriptor::new_wsh(ms).map_err(|err| PolicyError::Descriptor(class, err))?;
+            (descriptor, script_size)
+        }
+        DescriptorClass::NestedV0 => {
+            let ms = policy.compile().map_err(PolicyError::Compiler)?;
+            let script_size = ms.script_size();
+            let descriptor =
+                Descriptor::new_sh_wsh(ms).map_err(|err| PolicyError::Descriptor(class, err))?;
+            (descriptor, script_size)
+        }
+        DescriptorClass::TaprootC0 => {
+            let descriptor = policy
+                .compile_tr(None)
+                .map_err(|err| PolicyError::Descriptor(class, err))?;
+            let script_size = match descriptor {
+                Descriptor::Tr(ref tr) => tr
+                    .iter_scripts()
+                    .map(|(_, ms)| ms.script_size())
+                    .max()
+                    .unwrap_or_default(),
+                _ => unreachable!("taproot compiler produced non-taproot descriptor"),
+            };
+            (descriptor, script_size)
+        }
+    };
+
+    let max_satisfaction_weight = descriptor
+        .max_satisfaction_weight()
+        .map_err(PolicyError::Unsatisfiable)?;
+
+    Ok(CompiledPolicy {
+        class,
+        descriptor,
+        script_size,
+        max_satisfaction_weight,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compile_classes() {
+        let policy = "or(pk(A),and(pk(B),older(144)))";
+        for class in [
+            DescriptorClass::PreSegwit,
+            DescriptorClass::SegwitV0,
+            DescriptorClass::NestedV0,
+            DescriptorClass::TaprootC0,
+        ] {
+            let compiled = compile_str::<String>(policy, class).unwrap();
+            assert_eq!(compiled.class, class);
+            assert_eq!(
+                DescriptorClass::from(compiled.descriptor.desc_type()),
+                class
+            );
+            assert!(compiled.max_input_vsize() * 4 >= compiled.max_input_weight());
+            assert_eq!(compiled.input_fee(1.0), compiled.max_input_vsize() as u64);
+        }
+
+        let tr = compile_str::<String>(policy, DescriptorClass::TaprootC0).unwrap();
+        assert!(tr.to_string().starts_with("tr(A,"));
+    }
+
+    #[test]
+    fn invalid_policy() {
+        assert!(matches!(
+            compile_str::<String>("or(pk(A)", DescriptorClass::SegwitV0),
+            Err(PolicyError::Parse(_))
+        ));
+    }
+}
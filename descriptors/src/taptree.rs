@@ -0,0 +1,1504 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Stable text serialization and node listing for taproot script trees.
+//!
+//! The text form of a tree lists its script leaves and hidden nodes in DFS
+//! order, one per line, as `<depth> <leaf version> <script>` or
+//! `<depth> hidden <node hash>`, where leaf version is a two-digit
+//! hex-encoded consensus value and script is hex-encoded. Empty lines and
+//! lines starting with `#` are ignored. Since the node depths in DFS order
+//! unambiguously define the tree shape, the serialization is lossless.
+//!
+//! Trees, tree nodes, leaf scripts and DFS paths are serialized with serde (if
+//! `serde` feature is enabled) as text strings for human-readable formats and
+//! as strict-encoded bytes for binary formats. Trees and tree nodes use the
+//! descriptor script tree syntax (see [`TreeNodeText`]) as their text strings.
+//! Types defined outside of this crate are supported via [`serde_with`]
+//! adaptors: [`ScriptTreeText`] for [`TaprootScriptTree`], [`TreeNodeText`]
+//! for [`TreeNode`], [`LeafScriptText`] for [`LeafScript`] and [`DfsPath`] for
+//! `Vec<DfsOrder>`.
+//!
+//! The module also provides generation of merkle proofs for script leaves and
+//! their verification against taproot output keys, allowing external protocols
+//! to check script inclusion without constructing [`TaprootSpendInfo`].
+//!
+//! [`TaprootSpendInfo`]: bitcoin::util::taproot::TaprootSpendInfo
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::str::FromStr;
+
+use amplify::hex::{FromHex, ToHex};
+use amplify::Wrapper;
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::psbt::TapTree;
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::util::taproot::{
+    LeafVersion, TapBranchHash, TapLeafHash, TapTweakHash, TaprootBuilder, TaprootMerkleBranch,
+};
+use bitcoin::{Script, XOnlyPublicKey};
+use bitcoin_scripts::taproot::{BranchNode, DfsOrder, TaprootScriptTree, TreeNode};
+use bitcoin_scripts::{LeafScript, LockScript};
+#[cfg(feature = "miniscript")]
+use miniscript::{Miniscript, Tap};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+/// Errors parsing text representation of a taproot script tree
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TreeParseError {
+    /// line {0} of the script tree has invalid format; expected
+    /// `<depth> <leaf version> <script>` or `<depth> hidden <node hash>`
+    InvalidFormat(usize),
+
+    /// line {0} of the script tree has invalid leaf depth
+    InvalidDepth(usize),
+
+    /// line {0} of the script tree has invalid leaf version
+    InvalidLeafVersion(usize),
+
+    /// line {0} of the script tree has invalid hex encoding of the leaf script
+    InvalidScript(usize),
+
+    /// line {0} of the script tree has invalid hidden node hash
+    InvalidNodeHash(usize),
+
+    /// script tree is empty or has unfinished branches
+    Incomplete,
+
+    /// script tree exceeds maximal depth of taproot script trees
+    MaxDepthExceeded,
+
+    /// unexpected character at position {0} of the script tree
+    InvalidSyntax(usize),
+
+    /// invalid script tree node `{0}`
+    InvalidNode(String),
+
+    /// invalid DFS path `{0}`; path must consist of `0` (DFS first) and `1`
+    /// (DFS last) characters
+    InvalidPath(String),
+}
+
+/// Wrapper around [`TaprootScriptTree`] providing its stable text
+/// serialization via [`Display`] and [`FromStr`] implementations.
+#[derive(Wrapper, Clone, PartialEq, Eq, Debug, From)]
+pub struct ScriptTreeText(TaprootScriptTree);
+
+impl From<TapTree> for ScriptTreeText {
+    #[inline]
+    fn from(tree: TapTree) -> Self { ScriptTreeText(TaprootScriptTree::from(tree)) }
+}
+
+impl TryFrom<ScriptTreeText> for TapTree {
+    type Error = DisclosureError;
+
+    #[inline]
+    fn try_from(tree: ScriptTreeText) -> Result<Self, Self::Error> { to_tap_tree(&tree.0) }
+}
+
+impl Display for ScriptTreeText {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for entry in tree_entries(self.0.as_root_node()) {
+            match entry {
+                TreeEntry::Leaf(depth, leaf) => writeln!(f, "{} {}", depth, LeafScriptText(leaf))?,
+                TreeEntry::Hidden(depth, hash) => writeln!(f, "{} hidden {}", depth, hash)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ScriptTreeText {
+    type Err = TreeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = vec![];
+        for (no, line) in s.lines().enumerate() {
+            let no = no + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut split = line.split_whitespace();
+            let (depth, version, script) = match (split.next(), split.next(), split.next()) {
+                (Some(depth), Some(version), Some(script)) if split.next().is_none() => {
+                    (depth, version, script)
+                }
+                _ => return Err(TreeParseError::InvalidFormat(no)),
+            };
+            let depth = u8::from_str(depth).map_err(|_| TreeParseError::InvalidDepth(no))?;
+            if version == "hidden" {
+                let hash = sha256::Hash::from_str(script)
+                    .map_err(|_| TreeParseError::InvalidNodeHash(no))?;
+                entries.push(TreeEntry::Hidden(depth, hash));
+                continue;
+            }
+            let version = u8::from_str_radix(version, 16)
+                .ok()
+                .and_then(|ver| LeafVersion::from_consensus(ver).ok())
+                .ok_or(TreeParseError::InvalidLeafVersion(no))?;
+            let script = Script::from(
+                Vec::<u8>::from_hex(script).map_err(|_| TreeParseError::InvalidScript(no))?,
+            );
+            entries.push(TreeEntry::Leaf(depth, LeafScript {
+                version,
+                script: LockScript::from(script),
+            }));
+        }
+        let root = build_tree(&entries).ok_or(TreeParseError::Incomplete)?;
+        TaprootScriptTree::with(root)
+            .map(ScriptTreeText)
+            .map_err(|_| TreeParseError::MaxDepthExceeded)
+    }
+}
+
+impl StrictEncode for ScriptTreeText {
+    fn strict_encode<E: io::Write>(&self, e: E) -> Result<usize, strict_encoding::Error> {
+        tree_entries(self.0.as_root_node()).strict_encode(e)
+    }
+}
+
+impl StrictDecode for ScriptTreeText {
+    fn strict_decode<D: io::Read>(d: D) -> Result<Self, strict_encoding::Error> {
+        let entries = Vec::<TreeEntry>::strict_decode(d)?;
+        let root = build_tree(&entries).ok_or_else(|| {
+            strict_encoding::Error::DataIntegrityError(TreeParseError::Incomplete.to_string())
+        })?;
+        TaprootScriptTree::with(root)
+            .map(ScriptTreeText)
+            .map_err(|_| {
+                strict_encoding::Error::DataIntegrityError(
+                    TreeParseError::MaxDepthExceeded.to_string(),
+                )
+            })
+    }
+}
+
+/// Leaf or hidden node of a taproot script tree together with its depth.
+/// Since the depths of such nodes listed in DFS order unambiguously define
+/// the tree shape, the list of entries is used for the tree encodings.
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum TreeEntry {
+    Leaf(u8, LeafScript),
+    Hidden(u8, sha256::Hash),
+}
+
+impl StrictEncode for TreeEntry {
+    fn strict_encode<E: io::Write>(&self, mut e: E) -> Result<usize, strict_encoding::Error> {
+        Ok(match self {
+            TreeEntry::Leaf(depth, leaf) => {
+                depth.strict_encode(&mut e)?
+                    + 0u8.strict_encode(&mut e)?
+                    + LeafScriptText(leaf.clone()).strict_encode(&mut e)?
+            }
+            TreeEntry::Hidden(depth, hash) => {
+                let len = depth.strict_encode(&mut e)? + 1u8.strict_encode(&mut e)?;
+                e.write_all(&hash[..])?;
+                len + sha256::Hash::LEN
+            }
+        })
+    }
+}
+
+impl StrictDecode for TreeEntry {
+    fn strict_decode<D: io::Read>(mut d: D) -> Result<Self, strict_encoding::Error> {
+        let depth = u8::strict_decode(&mut d)?;
+        match u8::strict_decode(&mut d)? {
+            0 => Ok(TreeEntry::Leaf(
+                depth,
+                LeafScriptText::strict_decode(&mut d)?.into_inner(),
+            )),
+            1 => {
+                let mut hash = [0u8; 32];
+                d.read_exact(&mut hash)?;
+                Ok(TreeEntry::Hidden(depth, sha256::Hash::from_inner(hash)))
+            }
+            wrong => Err(strict_encoding::Error::DataIntegrityError(format!(
+                "invalid script tree node type {}",
+                wrong
+            ))),
+        }
+    }
+}
+
+/// Lists leaves and hidden nodes of the subtree under `node` in DFS order,
+/// with their depths counted from the subtree root.
+fn tree_entries(node: &TreeNode) -> Vec<TreeEntry> {
+    fn collect(node: &TreeNode, depth: u8, entries: &mut Vec<TreeEntry>) {
+        match node {
+            TreeNode::Leaf(leaf, _) => entries.push(TreeEntry::Leaf(depth, leaf.clone())),
+            TreeNode::Hidden(hash, _) => entries.push(TreeEntry::Hidden(depth, *hash)),
+            TreeNode::Branch(branch, _) => {
+                collect(
+                    branch.as_dfs_child_node(DfsOrder::First),
+                    depth + 1,
+                    entries,
+                );
+                collect(branch.as_dfs_child_node(DfsOrder::Last), depth + 1, entries);
+            }
+        }
+    }
+    let mut entries = vec![];
+    collect(node, 0, &mut entries);
+    entries
+}
+
+/// Reconstructs subtree rooted at depth 0 from the DFS-ordered list of its
+/// leaves and hidden nodes. Returns `None` if the depths of the entries do
+/// not form a complete tree.
+fn build_tree(entries: &[TreeEntry]) -> Option<TreeNode> {
+    fn build(entries: &[TreeEntry], pos: &mut usize, depth: u8) -> Option<TreeNode> {
+        let entry = entries.get(*pos)?;
+        let entry_depth = match entry {
+            TreeEntry::Leaf(depth, _) | TreeEntry::Hidden(depth, _) => *depth,
+        };
+        if entry_depth == depth {
+            *pos += 1;
+            return Some(match entry {
+                TreeEntry::Leaf(_, leaf) => TreeNode::Leaf(leaf.clone(), depth),
+                TreeEntry::Hidden(_, hash) => TreeNode::Hidden(*hash, depth),
+            });
+        }
+        if entry_depth < depth {
+            return None;
+        }
+        let first = build(entries, pos, depth + 1)?;
+        let last = build(entries, pos, depth + 1)?;
+        Some(TreeNode::Branch(BranchNode::with(first, last), depth))
+    }
+    let mut pos = 0usize;
+    let root = build(entries, &mut pos, 0)?;
+    if pos != entries.len() {
+        return None;
+    }
+    Some(root)
+}
+
+/// Converts script tree into the [`TapTree`] used by PSBTs, which can't
+/// contain hidden nodes.
+pub fn to_tap_tree(tree: &TaprootScriptTree) -> Result<TapTree, DisclosureError> {
+    match tree_nodes(tree).into_iter().find(|node| node.hidden) {
+        Some(node) => Err(DisclosureError::HiddenNode(node.path_string())),
+        None => Ok(TapTree::from(tree.clone())),
+    }
+}
+
+fn leaf_version(version: u8) -> Result<LeafVersion, strict_encoding::Error> {
+    LeafVersion::from_consensus(version).map_err(|_| {
+        strict_encoding::Error::DataIntegrityError(format!("invalid leaf version {:#04x}", version))
+    })
+}
+
+/// Wrapper around [`TreeNode`] providing representation of the subtree under
+/// the node in the descriptor script tree syntax (like `{A,{B,C}}`).
+///
+/// Unlike [`DescriptorTree`], the representation covers all possible trees:
+/// leaves which are not valid miniscript are written as `raw(<script hex>)`,
+/// leaves with non-tapscript versions as `leaf(<version hex>,<script hex>)`
+/// and hidden nodes as `hidden(<node hash>)`. Since the syntax does not
+/// specify depth of the subtree root, parsed subtrees are always rooted at
+/// depth 0.
+#[derive(Wrapper, Clone, PartialEq, Eq, Debug, From)]
+pub struct TreeNodeText(TreeNode);
+
+impl Display for TreeNodeText {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { write_tree_node(&self.0, f) }
+}
+
+impl FromStr for TreeNodeText {
+    type Err = TreeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (node, end) = parse_tree_node(s, 0, 0)?;
+        if end != s.len() {
+            return Err(TreeParseError::InvalidSyntax(end));
+        }
+        Ok(TreeNodeText(node))
+    }
+}
+
+impl StrictEncode for TreeNodeText {
+    fn strict_encode<E: io::Write>(&self, e: E) -> Result<usize, strict_encoding::Error> {
+        tree_entries(&self.0).strict_encode(e)
+    }
+}
+
+impl StrictDecode for TreeNodeText {
+    fn strict_decode<D: io::Read>(d: D) -> Result<Self, strict_encoding::Error> {
+        let entries = Vec::<TreeEntry>::strict_decode(d)?;
+        build_tree(&entries).map(TreeNodeText).ok_or_else(|| {
+            strict_encoding::Error::DataIntegrityError(TreeParseError::Incomplete.to_string())
+        })
+    }
+}
+
+/// Writes the subtree in the descriptor script tree syntax extended with
+/// `raw()`, `leaf()` and `hidden()` nodes (see [`TreeNodeText`]).
+fn write_tree_node(node: &TreeNode, f: &mut Formatter<'_>) -> fmt::Result {
+    match node {
+        TreeNode::Leaf(leaf, _) if leaf.version != LeafVersion::TapScript => write!(
+            f,
+            "leaf({:02x},{})",
+            leaf.version.to_consensus(),
+            leaf.script.as_inner().as_bytes().to_hex()
+        ),
+        TreeNode::Leaf(leaf, _) => match miniscript_string(leaf.script.as_inner()) {
+            Some(ms) => f.write_str(&ms),
+            None => write!(f, "raw({})", leaf.script.as_inner().as_bytes().to_hex()),
+        },
+        TreeNode::Hidden(hash, _) => write!(f, "hidden({})", hash),
+        TreeNode::Branch(branch, _) => {
+            f.write_str("{")?;
+            write_tree_node(branch.as_dfs_child_node(DfsOrder::First), f)?;
+            f.write_str(",")?;
+            write_tree_node(branch.as_dfs_child_node(DfsOrder::Last), f)?;
+            f.write_str("}")
+        }
+    }
+}
+
+/// Parses tree node at `depth` starting at byte position `start` of the string
+/// `s`, returning the node and position right after it.
+fn parse_tree_node(s: &str, start: usize, depth: u8) -> Result<(TreeNode, usize), TreeParseError> {
+    let bytes = s.as_bytes();
+    if bytes.get(start) == Some(&b'{') {
+        let child_depth = depth
+            .checked_add(1)
+            .ok_or(TreeParseError::MaxDepthExceeded)?;
+        let (first, pos) = parse_tree_node(s, start + 1, child_depth)?;
+        if bytes.get(pos) != Some(&b',') {
+            return Err(TreeParseError::InvalidSyntax(pos));
+        }
+        let (last, pos) = parse_tree_node(s, pos + 1, child_depth)?;
+        if bytes.get(pos) != Some(&b'}') {
+            return Err(TreeParseError::InvalidSyntax(pos));
+        }
+        return Ok((
+            TreeNode::Branch(BranchNode::with(first, last), depth),
+            pos + 1,
+        ));
+    }
+
+    let mut parens = 0usize;
+    let mut end = start;
+    while let Some(c) = bytes.get(end) {
+        match c {
+            b'(' => parens += 1,
+            b')' if parens == 0 => return Err(TreeParseError::InvalidSyntax(end)),
+            b')' => parens -= 1,
+            b',' | b'}' if parens == 0 => break,
+            b'{' => return Err(TreeParseError::InvalidSyntax(end)),
+            _ => {}
+        }
+        end += 1;
+    }
+    let node = s[start..end].trim();
+    if node.is_empty() || parens > 0 {
+        return Err(TreeParseError::InvalidSyntax(end));
+    }
+    let invalid = || TreeParseError::InvalidNode(node.to_owned());
+    let args = |prefix: &str| node.strip_prefix(prefix).and_then(|s| s.strip_suffix(')'));
+    if let Some(hash) = args("hidden(") {
+        let hash = sha256::Hash::from_str(hash).map_err(|_| invalid())?;
+        return Ok((TreeNode::Hidden(hash, depth), end));
+    }
+    let (version, script) = if let Some(script) = args("raw(") {
+        (
+            LeafVersion::TapScript,
+            Vec::<u8>::from_hex(script).map_err(|_| invalid())?,
+        )
+    } else if let Some(leaf) = args("leaf(") {
+        let (version, script) = leaf.split_once(',').ok_or_else(invalid)?;
+        let version = u8::from_str_radix(version, 16)
+            .ok()
+            .and_then(|ver| LeafVersion::from_consensus(ver).ok())
+            .ok_or_else(invalid)?;
+        (version, Vec::<u8>::from_hex(script).map_err(|_| invalid())?)
+    } else {
+        let script = parse_miniscript(node).ok_or_else(invalid)?;
+        (LeafVersion::TapScript, script.to_bytes())
+    };
+    let leaf = LeafScript {
+        version,
+        script: LockScript::from(Script::from(script)),
+    };
+    Ok((TreeNode::Leaf(leaf, depth), end))
+}
+
+/// Represents tapscript as a miniscript expression, if the script is a valid
+/// miniscript which encodes back into the same script.
+#[cfg(feature = "miniscript")]
+fn miniscript_string(script: &Script) -> Option<String> {
+    let ms = Miniscript::<XOnlyPublicKey, Tap>::parse_insane(script).ok()?;
+    if ms.encode() != *script {
+        return None;
+    }
+    Some(ms.to_string())
+}
+
+#[cfg(not(feature = "miniscript"))]
+fn miniscript_string(_: &Script) -> Option<String> { None }
+
+/// Parses miniscript expression into tapscript.
+#[cfg(feature = "miniscript")]
+fn parse_miniscript(s: &str) -> Option<Script> {
+    Miniscript::<XOnlyPublicKey, Tap>::from_str_insane(s)
+        .ok()
+        .map(|ms| ms.encode())
+}
+
+#[cfg(not(feature = "miniscript"))]
+fn parse_miniscript(_: &str) -> Option<Script> { None }
+
+/// Wrapper around [`LeafScript`] providing its text representation as
+/// `<leaf version> <script>`, in the same form as leaves of
+/// [`ScriptTreeText`] but without the leaf depth.
+#[derive(Wrapper, Clone, PartialEq, Eq, Debug, From)]
+pub struct LeafScriptText(LeafScript);
+
+impl Display for LeafScriptText {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02x} {}",
+            self.0.version.to_consensus(),
+            self.0.script.as_inner().as_bytes().to_hex()
+        )
+    }
+}
+
+impl FromStr for LeafScriptText {
+    type Err = TreeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.split_whitespace();
+        let (version, script) = match (split.next(), split.next(), split.next()) {
+            (Some(version), Some(script), None) => (version, script),
+            _ => return Err(TreeParseError::InvalidFormat(1)),
+        };
+        let version = u8::from_str_radix(version, 16)
+            .ok()
+            .and_then(|ver| LeafVersion::from_consensus(ver).ok())
+            .ok_or(TreeParseError::InvalidLeafVersion(1))?;
+        let script = Vec::<u8>::from_hex(script).map_err(|_| TreeParseError::InvalidScript(1))?;
+        let script = Script::from(script);
+        Ok(LeafScriptText(LeafScript {
+            version,
+            script: LockScript::from(script),
+        }))
+    }
+}
+
+impl StrictEncode for LeafScriptText {
+    fn strict_encode<E: io::Write>(&self, mut e: E) -> Result<usize, strict_encoding::Error> {
+        Ok(self.0.version.to_consensus().strict_encode(&mut e)?
+            + self.0.script.as_inner().to_bytes().strict_encode(&mut e)?)
+    }
+}
+
+impl StrictDecode for LeafScriptText {
+    fn strict_decode<D: io::Read>(mut d: D) -> Result<Self, strict_encoding::Error> {
+        let version = leaf_version(u8::strict_decode(&mut d)?)?;
+        let script = Script::from(Vec::<u8>::strict_decode(&mut d)?);
+        Ok(LeafScriptText(LeafScript {
+            version,
+            script: LockScript::from(script),
+        }))
+    }
+}
+
+/// Information about a node of the taproot script tree
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TreeNodeInfo {
+    /// DFS path from the tree root to the node
+    pub path: Vec<DfsOrder>,
+
+    /// Hash of the node (leaf hash for script leaves, branch hash otherwise)
+    pub node_hash: sha256::Hash,
+
+    /// Leaf version and script, if the node is a script leaf
+    pub leaf: Option<(LeafVersion, Script)>,
+
+    /// Whether the node is hidden, i.e. only its hash is known
+    pub hidden: bool,
+}
+
+impl TreeNodeInfo {
+    /// Returns depth of the node within the tree
+    #[inline]
+    pub fn depth(&self) -> usize { self.path.len() }
+
+    /// Returns DFS path to the node as a string of `0` (DFS first) and `1`
+    /// (DFS last) characters, in the form accepted by [`parse_dfs_path`].
+    #[inline]
+    pub fn path_string(&self) -> String { dfs_path_string(&self.path) }
+}
+
+/// Formats DFS path as a string of `0` (DFS first) and `1` (DFS last)
+/// characters, in the form accepted by [`parse_dfs_path`].
+pub fn dfs_path_string(path: &[DfsOrder]) -> String {
+    path.iter()
+        .map(|step| match step {
+            DfsOrder::First => '0',
+            DfsOrder::Last => '1',
+        })
+        .collect()
+}
+
+/// Parses DFS path from a string of `0` (DFS first) and `1` (DFS last)
+/// characters. Empty string corresponds to the tree root.
+pub fn parse_dfs_path(s: &str) -> Result<Vec<DfsOrder>, TreeParseError> {
+    s.chars()
+        .map(|c| match c {
+            '0' => Ok(DfsOrder::First),
+            '1' => Ok(DfsOrder::Last),
+            _ => Err(TreeParseError::InvalidPath(s.to_owned())),
+        })
+        .collect()
+}
+
+/// DFS path from the root of a taproot script tree to one of its nodes,
+/// represented as a string of `0` (DFS first) and `1` (DFS last) characters.
+#[derive(Wrapper, Clone, PartialEq, Eq, Debug, Default, From)]
+pub struct DfsPath(Vec<DfsOrder>);
+
+impl Display for DfsPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str(&dfs_path_string(&self.0)) }
+}
+
+impl FromStr for DfsPath {
+    type Err = TreeParseError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> { parse_dfs_path(s).map(DfsPath) }
+}
+
+impl StrictEncode for DfsPath {
+    fn strict_encode<E: io::Write>(&self, e: E) -> Result<usize, strict_encoding::Error> {
+        self.0
+            .iter()
+            .map(|step| match step {
+                DfsOrder::First => 0u8,
+                DfsOrder::Last => 1u8,
+            })
+            .collect::<Vec<_>>()
+            .strict_encode(e)
+    }
+}
+
+impl StrictDecode for DfsPath {
+    fn strict_decode<D: io::Read>(d: D) -> Result<Self, strict_encoding::Error> {
+        Vec::<u8>::strict_decode(d)?
+            .into_iter()
+            .map(|step| match step {
+                0 => Ok(DfsOrder::First),
+                1 => Ok(DfsOrder::Last),
+                wrong => Err(strict_encoding::Error::DataIntegrityError(format!(
+                    "invalid DFS path step {}",
+                    wrong
+                ))),
+            })
+            .collect::<Result<_, _>>()
+            .map(DfsPath)
+    }
+}
+
+#[cfg(feature = "serde")]
+mod _serde {
+    use serde::de::Error as _;
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_with::{Bytes, DeserializeAs, SerializeAs};
+
+    use super::*;
+
+    fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Display + StrictEncode,
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&value.to_string())
+        } else {
+            let data = value.strict_serialize().map_err(S::Error::custom)?;
+            serializer.serialize_bytes(&data)
+        }
+    }
+
+    fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr + StrictDecode,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            T::from_str(&s).map_err(D::Error::custom)
+        } else {
+            let data: Vec<u8> = Bytes::deserialize_as(deserializer)?;
+            T::strict_deserialize(data).map_err(D::Error::custom)
+        }
+    }
+
+    macro_rules! impl_serde {
+        ($wrapper:ty, $inner:ty) => {
+            impl Serialize for $wrapper {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serialize(self, serializer)
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $wrapper {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    deserialize(deserializer)
+                }
+            }
+
+            impl SerializeAs<$inner> for $wrapper {
+                fn serialize_as<S: Serializer>(
+                    source: &$inner,
+                    serializer: S,
+                ) -> Result<S::Ok, S::Error> {
+                    serialize(&<$wrapper>::from(source.clone()), serializer)
+                }
+            }
+
+            impl<'de> DeserializeAs<'de, $inner> for $wrapper {
+                fn deserialize_as<D: Deserializer<'de>>(
+                    deserializer: D,
+                ) -> Result<$inner, D::Error> {
+                    deserialize::<$wrapper, D>(deserializer).map(<$wrapper>::into_inner)
+                }
+            }
+        };
+    }
+
+    impl_serde!(TreeNodeText, TreeNode);
+    impl_serde!(LeafScriptText, LeafScript);
+    impl_serde!(DfsPath, Vec<DfsOrder>);
+
+    // Script trees are serialized as their root nodes, i.e. in the descriptor
+    // script tree syntax for human-readable formats
+    impl Serialize for ScriptTreeText {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            ScriptTreeText::serialize_as(&self.0, serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for ScriptTreeText {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            ScriptTreeText::deserialize_as(deserializer).map(ScriptTreeText)
+        }
+    }
+
+    impl SerializeAs<TaprootScriptTree> for ScriptTreeText {
+        fn serialize_as<S: Serializer>(
+            source: &TaprootScriptTree,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serialize(&TreeNodeText(source.as_root_node().clone()), serializer)
+        }
+    }
+
+    impl<'de> DeserializeAs<'de, TaprootScriptTree> for ScriptTreeText {
+        fn deserialize_as<D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<TaprootScriptTree, D::Error> {
+            let root = deserialize::<TreeNodeText, D>(deserializer)?.into_inner();
+            TaprootScriptTree::with(root)
+                .map_err(|_| D::Error::custom(TreeParseError::MaxDepthExceeded))
+        }
+    }
+}
+
+/// Lists all nodes of the script tree in DFS order (each branch precedes its
+/// child nodes), together with their DFS paths and node hashes.
+pub fn tree_nodes(tree: &TaprootScriptTree) -> Vec<TreeNodeInfo> {
+    let mut nodes = vec![];
+    collect_nodes(tree.as_root_node(), vec![], &mut nodes);
+    nodes
+}
+
+fn collect_nodes(node: &TreeNode, path: Vec<DfsOrder>, nodes: &mut Vec<TreeNodeInfo>) {
+    nodes.push(TreeNodeInfo {
+        path: path.clone(),
+        node_hash: node.node_hash(),
+        leaf: match node {
+            TreeNode::Leaf(leaf, _) => Some((leaf.version, leaf.script.as_inner().clone())),
+            _ => None,
+        },
+        hidden: matches!(node, TreeNode::Hidden(..)),
+    });
+    if let TreeNode::Branch(branch, _) = node {
+        for step in [DfsOrder::First, DfsOrder::Last] {
+            let mut child_path = path.clone();
+            child_path.push(step);
+            collect_nodes(branch.as_dfs_child_node(step), child_path, nodes);
+        }
+    }
+}
+
+/// Computes hash of the tree branch from the hashes of its child nodes, which
+/// are committed in lexicographic order.
+fn branch_hash(a: sha256::Hash, b: sha256::Hash) -> sha256::Hash {
+    let mut engine = TapBranchHash::engine();
+    if a < b {
+        engine.input(&a[..]);
+        engine.input(&b[..]);
+    } else {
+        engine.input(&b[..]);
+        engine.input(&a[..]);
+    }
+    sha256::Hash::from_inner(TapBranchHash::from_engine(engine).into_inner())
+}
+
+/// Errors generating merkle proofs for taproot script tree leaves
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum MerkleProofError {
+    /// DFS path `{0}` does not point to a script leaf of the tree
+    NoLeaf(String),
+}
+
+/// Generation of merkle proofs for the leaves of taproot script trees.
+pub trait MerkleProof {
+    /// Returns merkle branch for the script leaf located at the given DFS
+    /// path, i.e. hashes of the sibling nodes ordered from the leaf towards
+    /// the tree root, in the form used by taproot control blocks.
+    fn merkle_proof(&self, path: &[DfsOrder]) -> Result<TaprootMerkleBranch, MerkleProofError>;
+}
+
+impl MerkleProof for TaprootScriptTree {
+    fn merkle_proof(&self, path: &[DfsOrder]) -> Result<TaprootMerkleBranch, MerkleProofError> {
+        let nodes = tree_nodes(self);
+        if !nodes
+            .iter()
+            .any(|node| node.leaf.is_some() && node.path == path)
+        {
+            return Err(MerkleProofError::NoLeaf(dfs_path_string(path)));
+        }
+        let branch = (0..path.len())
+            .rev()
+            .map(|depth| {
+                let mut sibling = path[..depth].to_vec();
+                sibling.push(match path[depth] {
+                    DfsOrder::First => DfsOrder::Last,
+                    DfsOrder::Last => DfsOrder::First,
+                });
+                nodes
+                    .iter()
+                    .find(|node| node.path == sibling)
+                    .expect("complete script tree always has both children for each branch")
+                    .node_hash
+            })
+            .collect();
+        Ok(TaprootMerkleBranch::from_inner(branch)
+            .expect("script tree depth never exceeds taproot consensus limit"))
+    }
+}
+
+/// Verifies that the taproot output key commits to the leaf script with the
+/// given merkle branch and internal key, as it is done by the consensus rules
+/// for script path spending (BIP-341).
+pub fn verify_taproot_commitment(
+    output_key: XOnlyPublicKey,
+    internal_key: XOnlyPublicKey,
+    merkle_branch: &TaprootMerkleBranch,
+    leaf_script: &LeafScript,
+) -> bool {
+    let leaf_hash = TapLeafHash::from_script(leaf_script.script.as_inner(), leaf_script.version);
+    let mut node_hash = sha256::Hash::from_inner(leaf_hash.into_inner());
+    for sibling in merkle_branch.as_inner() {
+        node_hash = branch_hash(node_hash, *sibling);
+    }
+    let merkle_root = TapBranchHash::from_inner(node_hash.into_inner());
+    let tweak = TapTweakHash::from_key_and_tweak(internal_key, Some(merkle_root));
+    match internal_key.add_tweak(SECP256K1, &tweak.to_scalar()) {
+        Ok((tweaked_key, _)) => tweaked_key == output_key,
+        Err(_) => false,
+    }
+}
+
+/// Errors building taproot script trees from weighted leaves
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum WeightedTreeError {
+    /// no leaves were provided for the script tree
+    NoLeaves,
+
+    /// script tree can't be constructed: {0}
+    Builder(String),
+}
+
+/// Construction of taproot script trees with depths of the leaves optimized
+/// for their spending probability.
+pub trait WeightedTree: Sized {
+    /// Builds script tree from the leaves weighted by their relative spending
+    /// probabilities, such that the leaves which are more probable to be spent
+    /// are placed closer to the tree root, minimizing the expected size of the
+    /// control block in witness. The tree is constructed with Huffman coding;
+    /// leaves with equal weights are placed in the order they are provided.
+    fn with_weighted_leaves(
+        leaves: impl IntoIterator<Item = (u32, LeafScript)>,
+    ) -> Result<Self, WeightedTreeError>;
+}
+
+enum HuffmanNode {
+    Leaf(LeafScript),
+    Branch(Box<HuffmanNode>, Box<HuffmanNode>),
+}
+
+impl HuffmanNode {
+    fn collect_leaves(self, depth: u8, leaves: &mut Vec<(u8, LeafScript)>) {
+        match self {
+            HuffmanNode::Leaf(leaf) => leaves.push((depth, leaf)),
+            HuffmanNode::Branch(first, last) => {
+                let depth = depth.saturating_add(1);
+                first.collect_leaves(depth, leaves);
+                last.collect_leaves(depth, leaves);
+            }
+        }
+    }
+}
+
+impl WeightedTree for TaprootScriptTree {
+    fn with_weighted_leaves(
+        leaves: impl IntoIterator<Item = (u32, LeafScript)>,
+    ) -> Result<Self, WeightedTreeError> {
+        let mut seq = 0usize;
+        let mut nodes = BTreeMap::new();
+        for (weight, leaf) in leaves {
+            nodes.insert((weight as u64, seq), HuffmanNode::Leaf(leaf));
+            seq += 1;
+        }
+        loop {
+            let (first_key, first) = nodes.pop_first().ok_or(WeightedTreeError::NoLeaves)?;
+            let (last_key, last) = match nodes.pop_first() {
+                Some(entry) => entry,
+                None => {
+                    let mut leaves = vec![];
+                    first.collect_leaves(0, &mut leaves);
+                    let mut builder = TaprootBuilder::new();
+                    for (depth, leaf) in leaves {
+                        builder = builder
+                            .add_leaf_with_ver(depth, leaf.script.into_inner(), leaf.version)
+                            .map_err(|err| WeightedTreeError::Builder(err.to_string()))?;
+                    }
+                    let tree = TapTree::try_from(builder)
+                        .map_err(|_| WeightedTreeError::Builder(s!("incomplete tree")))?;
+                    return Ok(TaprootScriptTree::from(tree));
+                }
+            };
+            nodes.insert(
+                (first_key.0 + last_key.0, seq),
+                HuffmanNode::Branch(Box::new(first), Box::new(last)),
+            );
+            seq += 1;
+        }
+    }
+}
+
+/// Errors revealing and pruning hidden nodes of taproot script trees
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DisclosureError {
+    /// DFS path `{0}` does not point to a node of the script tree
+    PathNotFound(String),
+
+    /// node at DFS path `{0}` is not hidden and can't be revealed
+    NotHidden(String),
+
+    /// revealed subtree hash {actual} does not match hidden node hash
+    /// {expected}
+    HashMismatch {
+        expected: sha256::Hash,
+        actual: sha256::Hash,
+    },
+
+    /// revealed subtree exceeds maximal depth of the taproot script tree
+    MaxDepthExceeded,
+
+    /// script tree has hidden node at DFS path `{0}` and can't be used in
+    /// PSBT
+    HiddenNode(String),
+}
+
+/// Selective disclosure of taproot script tree parts, replacing hidden nodes
+/// with their subtrees and vice versa.
+pub trait TreeDisclosure: Sized {
+    /// Replaces hidden node at the given DFS path with the provided subtree,
+    /// checking that the subtree root hash matches the hash of the hidden
+    /// node.
+    fn reveal(&mut self, path: &[DfsOrder], subtree: Self) -> Result<(), DisclosureError>;
+
+    /// Replaces node at the given DFS path with a hidden node having the same
+    /// node hash, returning the removed subtree. Pruning is an inverse of
+    /// [`TreeDisclosure::reveal`].
+    fn prune(&mut self, path: &[DfsOrder]) -> Result<Self, DisclosureError>;
+}
+
+impl TreeDisclosure for TaprootScriptTree {
+    fn reveal(&mut self, path: &[DfsOrder], subtree: Self) -> Result<(), DisclosureError> {
+        let subtree = subtree.as_root_node().clone();
+        let root = replace_node(self.as_root_node(), path, 0, &mut |node, depth| {
+            let expected = match node {
+                TreeNode::Hidden(hash, _) => *hash,
+                _ => return Err(DisclosureError::NotHidden(dfs_path_string(path))),
+            };
+            let actual = subtree.node_hash();
+            if actual != expected {
+                return Err(DisclosureError::HashMismatch { expected, actual });
+            }
+            with_depth(&subtree, depth)
+        })?;
+        *self = TaprootScriptTree::with(root).map_err(|_| DisclosureError::MaxDepthExceeded)?;
+        Ok(())
+    }
+
+    fn prune(&mut self, path: &[DfsOrder]) -> Result<Self, DisclosureError> {
+        let mut pruned = None;
+        let root = replace_node(self.as_root_node(), path, 0, &mut |node, depth| {
+            pruned = Some(with_depth(node, 0)?);
+            Ok(TreeNode::Hidden(node.node_hash(), depth))
+        })?;
+        *self = TaprootScriptTree::with(root).map_err(|_| DisclosureError::MaxDepthExceeded)?;
+        let pruned = pruned.expect("replacement function is always called on success");
+        TaprootScriptTree::with(pruned).map_err(|_| DisclosureError::MaxDepthExceeded)
+    }
+}
+
+/// Reconstructs the tree under `node` located at `depth`, replacing its
+/// descendant at the DFS `path` (counted from the tree root) with the node
+/// returned by `f`.
+fn replace_node(
+    node: &TreeNode,
+    path: &[DfsOrder],
+    depth: u8,
+    f: &mut impl FnMut(&TreeNode, u8) -> Result<TreeNode, DisclosureError>,
+) -> Result<TreeNode, DisclosureError> {
+    let step = match path.get(depth as usize) {
+        None => return f(node, depth),
+        Some(step) => step,
+    };
+    let branch = match node {
+        TreeNode::Branch(branch, _) => branch,
+        _ => return Err(DisclosureError::PathNotFound(dfs_path_string(path))),
+    };
+    let mut first = branch.as_dfs_child_node(DfsOrder::First).clone();
+    let mut last = branch.as_dfs_child_node(DfsOrder::Last).clone();
+    match step {
+        DfsOrder::First => first = replace_node(&first, path, depth + 1, f)?,
+        DfsOrder::Last => last = replace_node(&last, path, depth + 1, f)?,
+    }
+    Ok(TreeNode::Branch(BranchNode::with(first, last), depth))
+}
+
+/// Copies the subtree under `node` placing it at the given depth.
+fn with_depth(node: &TreeNode, depth: u8) -> Result<TreeNode, DisclosureError> {
+    Ok(match node {
+        TreeNode::Leaf(leaf, _) => TreeNode::Leaf(leaf.clone(), depth),
+        TreeNode::Hidden(hash, _) => TreeNode::Hidden(*hash, depth),
+        TreeNode::Branch(branch, _) => {
+            let child_depth = depth
+                .checked_add(1)
+                .ok_or(DisclosureError::MaxDepthExceeded)?;
+            TreeNode::Branch(
+                BranchNode::with(
+                    with_depth(branch.as_dfs_child_node(DfsOrder::First), child_depth)?,
+                    with_depth(branch.as_dfs_child_node(DfsOrder::Last), child_depth)?,
+                ),
+                depth,
+            )
+        }
+    })
+}
+
+/// Errors converting taproot script trees from and to the descriptor tree
+/// syntax
+#[cfg(feature = "miniscript")]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DescriptorTreeError {
+    /// unexpected character at position {0} of the descriptor script tree
+    InvalidSyntax(usize),
+
+    /// invalid miniscript `{0}` in the descriptor script tree leaf: {1}
+    Miniscript(String, String),
+
+    /// script tree leaf can't be represented in descriptor since it uses
+    /// non-tapscript leaf version {0:#04x}
+    UnsupportedLeafVersion(u8),
+
+    /// script tree leaf `{0}` is not a valid miniscript and can't be
+    /// represented in descriptor
+    NonMiniscriptLeaf(Script),
+
+    /// script tree has hidden node {0}, which can't be represented in
+    /// descriptor
+    HiddenNode(sha256::Hash),
+
+    /// descriptor script tree can't be constructed: {0}
+    Builder(String),
+
+    /// descriptor script tree is empty
+    Empty,
+}
+
+/// Conversion of taproot script trees from and to the nested-brace syntax used
+/// by the second argument of `tr()` output descriptors (like `{A,{B,C}}`),
+/// preserving DFS ordering of the tree leaves. Tree leaves are represented by
+/// tapscript miniscript expressions.
+#[cfg(feature = "miniscript")]
+pub trait DescriptorTree: Sized {
+    /// Parses script tree from the descriptor tree syntax.
+    fn from_descriptor_tree(s: &str) -> Result<Self, DescriptorTreeError>;
+
+    /// Represents script tree in the descriptor tree syntax.
+    fn to_descriptor_tree(&self) -> Result<String, DescriptorTreeError>;
+}
+
+#[cfg(feature = "miniscript")]
+impl DescriptorTree for TaprootScriptTree {
+    fn from_descriptor_tree(s: &str) -> Result<Self, DescriptorTreeError> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(DescriptorTreeError::Empty);
+        }
+        let mut leaves = vec![];
+        let end = parse_descriptor_node(s, 0, 0, &mut leaves)?;
+        if end != s.len() {
+            return Err(DescriptorTreeError::InvalidSyntax(end));
+        }
+        let mut builder = TaprootBuilder::new();
+        for (depth, script) in leaves {
+            builder = builder
+                .add_leaf(depth, script)
+                .map_err(|err| DescriptorTreeError::Builder(err.to_string()))?;
+        }
+        let tree = TapTree::try_from(builder)
+            .map_err(|_| DescriptorTreeError::Builder(s!("incomplete tree")))?;
+        Ok(TaprootScriptTree::from(tree))
+    }
+
+    fn to_descriptor_tree(&self) -> Result<String, DescriptorTreeError> {
+        let mut s = String::new();
+        write_descriptor_node(self.as_root_node(), &mut s)?;
+        Ok(s)
+    }
+}
+
+/// Parses tree node starting at byte position `start` of the string `s`,
+/// returning position right after the node.
+#[cfg(feature = "miniscript")]
+fn parse_descriptor_node(
+    s: &str,
+    start: usize,
+    depth: u8,
+    leaves: &mut Vec<(u8, Script)>,
+) -> Result<usize, DescriptorTreeError> {
+    let bytes = s.as_bytes();
+    if bytes.get(start) == Some(&b'{') {
+        let depth = depth
+            .checked_add(1)
+            .ok_or(DescriptorTreeError::InvalidSyntax(start))?;
+        let pos = parse_descriptor_node(s, start + 1, depth, leaves)?;
+        if bytes.get(pos) != Some(&b',') {
+            return Err(DescriptorTreeError::InvalidSyntax(pos));
+        }
+        let pos = parse_descriptor_node(s, pos + 1, depth, leaves)?;
+        if bytes.get(pos) != Some(&b'}') {
+            return Err(DescriptorTreeError::InvalidSyntax(pos));
+        }
+        return Ok(pos + 1);
+    }
+
+    let mut parens = 0usize;
+    let mut end = start;
+    while let Some(c) = bytes.get(end) {
+        match c {
+            b'(' => parens += 1,
+            b')' if parens == 0 => return Err(DescriptorTreeError::InvalidSyntax(end)),
+            b')' => parens -= 1,
+            b',' | b'}' if parens == 0 => break,
+            b'{' => return Err(DescriptorTreeError::InvalidSyntax(end)),
+            _ => {}
+        }
+        end += 1;
+    }
+    let leaf = s[start..end].trim();
+    if leaf.is_empty() || parens > 0 {
+        return Err(DescriptorTreeError::InvalidSyntax(end));
+    }
+    let ms = Miniscript::<XOnlyPublicKey, Tap>::from_str_insane(leaf)
+        .map_err(|err| DescriptorTreeError::Miniscript(leaf.to_owned(), err.to_string()))?;
+    leaves.push((depth, ms.encode()));
+    Ok(end)
+}
+
+#[cfg(feature = "miniscript")]
+fn write_descriptor_node(node: &TreeNode, s: &mut String) -> Result<(), DescriptorTreeError> {
+    match node {
+        TreeNode::Leaf(leaf, _) => {
+            if leaf.version != LeafVersion::TapScript {
+                return Err(DescriptorTreeError::UnsupportedLeafVersion(
+                    leaf.version.to_consensus(),
+                ));
+            }
+            let script = leaf.script.as_inner();
+            let ms = Miniscript::<XOnlyPublicKey, Tap>::parse_insane(script)
+                .map_err(|_| DescriptorTreeError::NonMiniscriptLeaf(script.clone()))?;
+            s.push_str(&ms.to_string());
+        }
+        TreeNode::Hidden(hash, _) => return Err(DescriptorTreeError::HiddenNode(*hash)),
+        TreeNode::Branch(branch, _) => {
+            s.push('{');
+            write_descriptor_node(branch.as_dfs_child_node(DfsOrder::First), s)?;
+            s.push(',');
+            write_descriptor_node(branch.as_dfs_child_node(DfsOrder::Last), s)?;
+            s.push('}');
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn text_roundtrip() {
+        let text = "# sample tree\n1 c0 51\n2 c0 52\n2 c0 53\n";
+        let tree = ScriptTreeText::from_str(text).unwrap();
+        assert_eq!(tree.to_string(), "1 c0 51\n2 c0 52\n2 c0 53\n");
+        assert_eq!(ScriptTreeText::from_str(&tree.to_string()).unwrap(), tree);
+
+        let nodes = tree_nodes(tree.as_inner());
+        let paths = nodes
+            .iter()
+            .map(TreeNodeInfo::path_string)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["", "0", "1", "10", "11"]);
+        assert_eq!(nodes.iter().filter(|node| node.leaf.is_some()).count(), 3);
+
+        let root = TapTree::try_from(tree).unwrap().into_builder();
+        let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+        let internal_key = bitcoin::XOnlyPublicKey::from_str(
+            "93c7378d96518a75448821c4f7c8f4bae7ce60f804d03d1f0628dd5dd0f5de51",
+        )
+        .unwrap();
+        let info = root.finalize(&secp, internal_key).unwrap();
+        assert_eq!(
+            info.merkle_root().map(TapBranchHash::into_inner),
+            Some(nodes[0].node_hash.into_inner())
+        );
+    }
+
+    #[test]
+    fn strict_roundtrip() {
+        let tree = ScriptTreeText::from_str("1 c0 51\n2 c0 52\n2 c0 53\n").unwrap();
+        let data = tree.strict_serialize().unwrap();
+        assert_eq!(ScriptTreeText::strict_deserialize(data).unwrap(), tree);
+
+        let leaf = LeafScriptText::from_str("c0 5152").unwrap();
+        assert_eq!(leaf.to_string(), "c0 5152");
+        let data = leaf.strict_serialize().unwrap();
+        assert_eq!(LeafScriptText::strict_deserialize(data).unwrap(), leaf);
+        assert_eq!(
+            LeafScriptText::from_str("c1 51"),
+            Err(TreeParseError::InvalidLeafVersion(1))
+        );
+
+        let path = DfsPath::from_str("0110").unwrap();
+        assert_eq!(path.to_string(), "0110");
+        let data = path.strict_serialize().unwrap();
+        assert_eq!(data, vec![4, 0, 0, 1, 1, 0]);
+        assert_eq!(DfsPath::strict_deserialize(data).unwrap(), path);
+        assert!(DfsPath::strict_deserialize([1u8, 0, 2]).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        use serde_with::serde_as;
+
+        #[serde_as]
+        #[derive(PartialEq, Debug, Serialize, Deserialize)]
+        #[serde(crate = "serde_crate")]
+        struct Config {
+            #[serde_as(as = "ScriptTreeText")]
+            tree: TaprootScriptTree,
+            #[serde_as(as = "TreeNodeText")]
+            node: TreeNode,
+            #[serde_as(as = "LeafScriptText")]
+            leaf: LeafScript,
+            #[serde_as(as = "DfsPath")]
+            path: Vec<DfsOrder>,
+        }
+
+        let mut tree = ScriptTreeText::from_str("1 c0 52\n2 c0 53\n2 c2 54\n")
+            .unwrap()
+            .into_inner();
+        tree.prune(&parse_dfs_path("0").unwrap()).unwrap();
+        let hidden = tree_nodes(&tree)[1].node_hash;
+        let config = Config {
+            tree: tree.clone(),
+            node: tree.as_root_node().clone(),
+            leaf: LeafScript {
+                version: LeafVersion::TapScript,
+                script: LockScript::from(Script::from(vec![0x52])),
+            },
+            path: vec![DfsOrder::Last],
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        let tree_str = format!("{{hidden({}),{{raw(53),leaf(c2,54)}}}}", hidden);
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"tree":"{}","node":"{}","leaf":"c0 52","path":"1"}}"#,
+                tree_str, tree_str
+            )
+        );
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+    }
+
+    #[test]
+    fn merkle_proof() {
+        let tree = ScriptTreeText::from_str("1 c0 51\n2 c0 52\n2 c0 53\n").unwrap();
+        let internal_key = XOnlyPublicKey::from_str(
+            "93c7378d96518a75448821c4f7c8f4bae7ce60f804d03d1f0628dd5dd0f5de51",
+        )
+        .unwrap();
+        let info = TapTree::try_from(tree.clone())
+            .unwrap()
+            .into_builder()
+            .finalize(SECP256K1, internal_key)
+            .unwrap();
+        let output_key = info.output_key().to_inner();
+
+        for (path, code) in [("0", 0x51u8), ("10", 0x52), ("11", 0x53)] {
+            let path = parse_dfs_path(path).unwrap();
+            let script = Script::from(vec![code]);
+            let branch = tree.as_inner().merkle_proof(&path).unwrap();
+            let control_block = info
+                .control_block(&(script.clone(), LeafVersion::TapScript))
+                .unwrap();
+            assert_eq!(branch, control_block.merkle_branch);
+
+            let leaf = LeafScript::tapscript(script.into());
+            assert!(verify_taproot_commitment(
+                output_key,
+                internal_key,
+                &branch,
+                &leaf
+            ));
+            let other = LeafScript::tapscript(Script::from(vec![0x54]).into());
+            assert!(!verify_taproot_commitment(
+                output_key,
+                internal_key,
+                &branch,
+                &other
+            ));
+        }
+
+        assert_eq!(
+            tree.as_inner().merkle_proof(&parse_dfs_path("1").unwrap()),
+            Err(MerkleProofError::NoLeaf(s!("1")))
+        );
+        assert_eq!(
+            tree.as_inner().merkle_proof(&parse_dfs_path("00").unwrap()),
+            Err(MerkleProofError::NoLeaf(s!("00")))
+        );
+    }
+
+    #[test]
+    fn reveal_prune() {
+        let full = ScriptTreeText::from_str("1 c0 51\n2 c0 52\n2 c0 53\n")
+            .unwrap()
+            .into_inner();
+        let root_hash = full.as_root_node().node_hash();
+
+        let mut tree = full.clone();
+        let path = parse_dfs_path("1").unwrap();
+        let subtree = tree.prune(&path).unwrap();
+        assert_eq!(tree.as_root_node().node_hash(), root_hash);
+        assert_eq!(
+            ScriptTreeText::from(subtree.clone()).to_string(),
+            "1 c0 52\n1 c0 53\n"
+        );
+
+        let mut other = full.clone();
+        let wrong = other.prune(&parse_dfs_path("0").unwrap()).unwrap();
+        assert_eq!(
+            tree.reveal(&path, wrong.clone()),
+            Err(DisclosureError::HashMismatch {
+                expected: subtree.as_root_node().node_hash(),
+                actual: wrong.as_root_node().node_hash(),
+            })
+        );
+        assert_eq!(
+            tree.reveal(&parse_dfs_path("0").unwrap(), subtree.clone()),
+            Err(DisclosureError::NotHidden(s!("0")))
+        );
+        assert_eq!(
+            tree.reveal(&parse_dfs_path("10").unwrap(), subtree.clone()),
+            Err(DisclosureError::PathNotFound(s!("10")))
+        );
+
+        tree.reveal(&path, subtree).unwrap();
+        assert_eq!(tree, full);
+    }
+
+    #[test]
+    fn hidden_nodes() {
+        let mut tree = ScriptTreeText::from_str("1 c0 52\n2 c0 53\n2 c0 54\n")
+            .unwrap()
+            .into_inner();
+        tree.prune(&parse_dfs_path("1").unwrap()).unwrap();
+        let hidden = tree_nodes(&tree)[2].node_hash;
+
+        let text = ScriptTreeText::from(tree.clone()).to_string();
+        assert_eq!(text, format!("1 c0 52\n1 hidden {}\n", hidden));
+        assert_eq!(ScriptTreeText::from_str(&text).unwrap().into_inner(), tree);
+
+        let data = ScriptTreeText::from(tree.clone())
+            .strict_serialize()
+            .unwrap();
+        assert_eq!(
+            ScriptTreeText::strict_deserialize(data)
+                .unwrap()
+                .into_inner(),
+            tree
+        );
+
+        let node = TreeNodeText::from(tree.as_root_node().clone());
+        assert_eq!(node.to_string(), format!("{{raw(52),hidden({})}}", hidden));
+        assert_eq!(TreeNodeText::from_str(&node.to_string()).unwrap(), node);
+
+        let nodes = tree_nodes(&tree);
+        assert_eq!(nodes.len(), 3);
+        assert!(nodes[2].hidden);
+        assert_eq!(nodes[2].node_hash, hidden);
+        assert_eq!(nodes[0].node_hash, tree.as_root_node().node_hash());
+        assert_eq!(
+            to_tap_tree(&tree),
+            Err(DisclosureError::HiddenNode(s!("1")))
+        );
+        #[cfg(feature = "miniscript")]
+        assert_eq!(
+            tree.to_descriptor_tree(),
+            Err(DescriptorTreeError::HiddenNode(hidden))
+        );
+
+        assert_eq!(
+            TreeNodeText::from_str("{raw(52),hidden(00)}"),
+            Err(TreeParseError::InvalidNode(s!("hidden(00)")))
+        );
+        assert_eq!(
+            TreeNodeText::from_str("{raw(52)}"),
+            Err(TreeParseError::InvalidSyntax(8))
+        );
+        assert_eq!(
+            ScriptTreeText::from_str("1 hidden zz\n"),
+            Err(TreeParseError::InvalidNodeHash(1))
+        );
+    }
+
+    #[test]
+    fn invalid_text() {
+        assert_eq!(
+            ScriptTreeText::from_str(""),
+            Err(TreeParseError::Incomplete)
+        );
+        assert_eq!(
+            ScriptTreeText::from_str("1 c0 51\n"),
+            Err(TreeParseError::Incomplete)
+        );
+        assert_eq!(
+            ScriptTreeText::from_str("0 c0 zz"),
+            Err(TreeParseError::InvalidScript(1))
+        );
+        assert_eq!(
+            parse_dfs_path("012"),
+            Err(TreeParseError::InvalidPath(s!("012")))
+        );
+    }
+
+    #[test]
+    fn huffman_tree() {
+        let leaf = |code: u8| LeafScript::tapscript(Script::from(vec![code]).into());
+        let tree = TaprootScriptTree::with_weighted_leaves([
+            (1, leaf(0x52)),
+            (10, leaf(0x51)),
+            (1, leaf(0x53)),
+            (3, leaf(0x54)),
+        ])
+        .unwrap();
+        let depths = tree_nodes(&tree)
+            .into_iter()
+            .filter_map(|node| Some((node.leaf?.1.as_bytes()[0], node.path.len())))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(depths[&0x51], 1);
+        assert_eq!(depths[&0x54], 2);
+        assert_eq!(depths[&0x52], 3);
+        assert_eq!(depths[&0x53], 3);
+
+        let single = TaprootScriptTree::with_weighted_leaves([(5, leaf(0x51))]).unwrap();
+        assert_eq!(ScriptTreeText::from(single).to_string(), "0 c0 51\n");
+
+        assert_eq!(
+            TaprootScriptTree::with_weighted_leaves(vec![]),
+            Err(WeightedTreeError::NoLeaves)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "miniscript")]
+    fn descriptor_tree_roundtrip() {
+        let a = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let b = "c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+        let c = "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9";
+
+        let descr = format!("{{pk({}),{{and_v(v:pk({}),older(144)),pk({})}}}}", a, b, c);
+        let tree = TaprootScriptTree::from_descriptor_tree(&descr).unwrap();
+        assert_eq!(tree.to_descriptor_tree().unwrap(), descr);
+
+        let nodes = tree_nodes(&tree);
+        let paths = nodes
+            .iter()
+            .filter(|node| node.leaf.is_some())
+            .map(TreeNodeInfo::path_string)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["0", "10", "11"]);
+
+        let single = format!("pk({})", a);
+        let tree = TaprootScriptTree::from_descriptor_tree(&single).unwrap();
+        assert_eq!(tree.to_descriptor_tree().unwrap(), single);
+
+        assert_eq!(
+            TaprootScriptTree::from_descriptor_tree(&format!("{{pk({})}}", a)),
+            Err(DescriptorTreeError::InvalidSyntax(69))
+        );
+        assert_eq!(
+            TaprootScriptTree::from_descriptor_tree(""),
+            Err(DescriptorTreeError::Empty)
+        );
+
+        let raw = ScriptTreeText::from_str("0 c0 6a").unwrap();
+        assert!(matches!(
+            raw.as_inner().to_descriptor_tree(),
+            Err(DescriptorTreeError::NonMiniscriptLeaf(_))
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Taproot descriptors with key-spend path only (`tr(KEY)`).
+//!
+//! Output key of such descriptors is the internal key tweaked with
+//! `H_TapTweak(P)`, i.e. without any merkle root. Disclosing the internal key
+//! and the tweak allows auditors to verify that the output has no hidden
+//! script spending path.
+
+use std::fmt::{self, Display, Formatter};
+
+use bitcoin::schnorr::{TapTweak, TweakedPublicKey};
+use bitcoin::secp256k1::{Secp256k1, Verification};
+use bitcoin::util::taproot::TapTweakHash;
+use bitcoin::{Script, XOnlyPublicKey};
+use bitcoin_hd::account::DerivePublicKey;
+use bitcoin_hd::{DerivationAccount, DerivePatternError, UnhardenedIndex};
+#[cfg(feature = "miniscript")]
+use miniscript::Descriptor;
+
+/// Errors converting output descriptors into [`TrKeyDescriptor`]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TrKeyError {
+    /// descriptor is not a taproot descriptor
+    NotTaproot,
+
+    /// taproot descriptor has script tree and is not key-spend only
+    ScriptTree,
+}
+
+/// Checks that taproot `script_pubkey` commits to the `internal_key` without
+/// any script tree, i.e. that the output merkle root is nil.
+pub fn is_key_only_output<C: Verification>(
+    secp: &Secp256k1<C>,
+    internal_key: XOnlyPublicKey,
+    script_pubkey: &Script,
+) -> bool {
+    *script_pubkey == Script::new_v1_p2tr(secp, internal_key, None)
+}
+
+/// Taproot output descriptor with key-spend path only (`tr(KEY)`).
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, From)]
+pub struct TrKeyDescriptor(DerivationAccount);
+
+impl Display for TrKeyDescriptor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { write!(f, "tr({})", self.0) }
+}
+
+impl TrKeyDescriptor {
+    /// Constructs key-only descriptor with a given internal key account
+    #[inline]
+    pub fn new(internal_key: DerivationAccount) -> TrKeyDescriptor { TrKeyDescriptor(internal_key) }
+
+    /// Returns account used to derive the internal key
+    #[inline]
+    pub fn internal_account(&self) -> &DerivationAccount { &self.0 }
+
+    /// Derives internal key at a given terminal derivation pattern
+    pub fn internal_key<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        pat: impl IntoIterator<Item = impl Into<UnhardenedIndex>>,
+    ) -> Result<XOnlyPublicKey, DerivePatternError> {
+        self.0
+            .derive_public_key(secp, pat)
+            .map(XOnlyPublicKey::from)
+    }
+
+    /// Returns tweak applied to the internal key to produce output key,
+    /// which is `H_TapTweak(P)` since the descriptor has no script tree.
+    /// Together with the internal key it proves the absence of script
+    /// spending paths.
+    pub fn output_key_tweak<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        pat: impl IntoIterator<Item = impl Into<UnhardenedIndex>>,
+    ) -> Result<TapTweakHash, DerivePatternError> {
+        let internal_key = self.internal_key(secp, pat)?;
+        Ok(TapTweakHash::from_key_and_tweak(internal_key, None))
+    }
+
+    /// Derives output key at a given terminal derivation pattern
+    pub fn output_key<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        pat: impl IntoIterator<Item = impl Into<UnhardenedIndex>>,
+    ) -> Result<TweakedPublicKey, DerivePatternError> {
+        let (output_key, _) = self.internal_key(secp, pat)?.tap_tweak(secp, None);
+        Ok(output_key)
+    }
+
+    /// Derives `scriptPubkey` at a given terminal derivation pattern
+    pub fn script_pubkey<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        pat: impl IntoIterator<Item = impl Into<UnhardenedIndex>>,
+    ) -> Result<Script, DerivePatternError> {
+        Ok(Script::new_v1_p2tr_tweaked(self.output_key(secp, pat)?))
+    }
+}
+
+#[cfg(feature = "miniscript")]
+impl TryFrom<Descriptor<DerivationAccount>> for TrKeyDescriptor {
+    type Error = TrKeyError;
+
+    fn try_from(descriptor: Descriptor<DerivationAccount>) -> Result<Self, Self::Error> {
+        match descriptor {
+            Descriptor::Tr(tr) if tr.taptree().is_none() => {
+                Ok(TrKeyDescriptor(tr.internal_key().clone()))
+            }
+            Descriptor::Tr(_) => Err(TrKeyError::ScriptTree),
+            _ => Err(TrKeyError::NotTaproot),
+        }
+    }
+}
+
+#[cfg(feature = "miniscript")]
+impl From<TrKeyDescriptor> for Descriptor<DerivationAccount> {
+    fn from(descriptor: TrKeyDescriptor) -> Self {
+        Descriptor::new_tr(descriptor.0, None).expect("key-only taproot descriptor is always valid")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{Scalar, SECP256K1};
+    use bitcoin::util::taproot::TapBranchHash;
+
+    use super::*;
+
+    fn descriptor() -> TrKeyDescriptor {
+        TrKeyDescriptor::new(
+            DerivationAccount::from_str(
+                "[d34db33f/86h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJP\
+                 MM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/0/*",
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn output_key_tweak() {
+        let descriptor = descriptor();
+        let pat = [UnhardenedIndex::from(7u8)];
+        let internal_key = descriptor.internal_key(SECP256K1, pat).unwrap();
+        let tweak = descriptor.output_key_tweak(SECP256K1, pat).unwrap();
+        let output_key = descriptor.output_key(SECP256K1, pat).unwrap();
+
+        let scalar = Scalar::from_be_bytes(tweak.into_inner()).unwrap();
+        let (tweaked, _) = internal_key.add_tweak(SECP256K1, &scalar).unwrap();
+        assert_eq!(output_key.to_inner(), tweaked);
+
+        let script_pubkey = descriptor.script_pubkey(SECP256K1, pat).unwrap();
+        assert!(is_key_only_output(SECP256K1, internal_key, &script_pubkey));
+        let hidden = Script::new_v1_p2tr(SECP256K1, internal_key, Some(TapBranchHash::all_zeros()));
+        assert!(!is_key_only_output(SECP256K1, internal_key, &hidden));
+    }
+
+    #[cfg(feature = "miniscript")]
+    #[test]
+    fn miniscript_conversion() {
+        use crate::derive::Descriptor as _;
+
+        let descriptor = descriptor();
+        let pat = [UnhardenedIndex::from(7u8)];
+        let full = Descriptor::from(descriptor.clone());
+        assert_eq!(
+            full.to_string().split('#').next(),
+            Some(descriptor.to_string().as_str())
+        );
+        assert_eq!(
+            full.script_pubkey_tr(SECP256K1, pat).unwrap(),
+            descriptor.script_pubkey(SECP256K1, pat).unwrap()
+        );
+        assert_eq!(TrKeyDescriptor::try_from(full), Ok(descriptor.clone()));
+
+        let wpkh = Descriptor::new_wpkh(descriptor.internal_account().clone()).unwrap();
+        assert_eq!(TrKeyDescriptor::try_from(wpkh), Err(TrKeyError::NotTaproot));
+    }
+}
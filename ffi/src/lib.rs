@@ -0,0 +1,28 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! UniFFI bindings for mobile (Kotlin and Swift) wallets, providing a curated
+//! API for wallet creation, address derivation, UTXO scanning with an esplora
+//! server and PSBT construction and signing.
+
+// Coding conventions
+#![recursion_limit = "256"]
+#![deny(dead_code, missing_docs)]
+
+#[macro_use]
+extern crate amplify;
+extern crate miniscript_crate as miniscript;
+
+mod wallet;
+
+pub use wallet::{Network, Recipient, ScriptType, Wallet, WalletError, WalletUtxo};
+
+uniffi::setup_scaffolding!();
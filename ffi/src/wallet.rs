@@ -0,0 +1,593 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::{Address, OutPoint};
+use bitcoin_hd::standards::DerivationBlockchain;
+use bitcoin_hd::{Bip43, DerivationAccount, DerivationStandard, SegmentIndexes, UnhardenedIndex};
+use bitcoin_onchain::blockchain::MiningStatus;
+use bitcoin_onchain::{AsyncResolveDescriptor, AsyncResolveTx};
+use bitcoin_scripts::PubkeyScript;
+use descriptors::derive::Descriptor as _;
+use descriptors::InputDescriptor;
+use esplora_client::AsyncClient;
+use miniscript::psbt::PsbtExt;
+use miniscript::Descriptor;
+use psbt::sign::{MemoryKeyProvider, MemorySigningAccount, SignAll, SigningPolicy};
+use psbt::Psbt;
+
+/// Errors returned by the wallet API
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, uniffi::Error)]
+#[display(doc_comments)]
+#[uniffi(flat_error)]
+pub enum WalletError {
+    /// invalid mnemonic phrase: {0}
+    Mnemonic(String),
+
+    /// invalid wallet descriptor: {0}
+    Descriptor(String),
+
+    /// wallet keys can't be derived: {0}
+    Derivation(String),
+
+    /// invalid address: {0}
+    Address(String),
+
+    /// wallet has no private keys and can't sign
+    WatchOnly,
+
+    /// esplora server error: {0}
+    Esplora(String),
+
+    /// unable to construct PSBT: {0}
+    Construct(String),
+
+    /// invalid PSBT: {0}
+    Psbt(String),
+
+    /// unable to sign PSBT: {0}
+    Sign(String),
+
+    /// unable to finalize PSBT: {0}
+    Finalize(String),
+}
+
+/// Bitcoin network used by the wallet
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, uniffi::Enum)]
+pub enum Network {
+    /// Bitcoin mainnet
+    Bitcoin,
+    /// Bitcoin testnet
+    Testnet,
+    /// Bitcoin signet
+    Signet,
+    /// Bitcoin regtest
+    Regtest,
+}
+
+impl From<Network> for bitcoin::Network {
+    fn from(network: Network) -> Self {
+        match network {
+            Network::Bitcoin => bitcoin::Network::Bitcoin,
+            Network::Testnet => bitcoin::Network::Testnet,
+            Network::Signet => bitcoin::Network::Signet,
+            Network::Regtest => bitcoin::Network::Regtest,
+        }
+    }
+}
+
+/// Type of scripts used by wallets created from a mnemonic
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, uniffi::Enum)]
+pub enum ScriptType {
+    /// BIP-84 native segwit (P2WPKH) wallet
+    SegWit,
+    /// BIP-86 taproot key-spend (P2TR) wallet
+    Taproot,
+}
+
+/// Unspent output controlled by the wallet
+#[derive(Clone, PartialEq, Eq, Hash, Debug, uniffi::Record)]
+pub struct WalletUtxo {
+    /// Outpoint in `txid:vout` format
+    pub outpoint: String,
+    /// Output value, in satoshis
+    pub amount: u64,
+    /// Whether the output belongs to the change keychain
+    pub change: bool,
+    /// Derivation index of the output address
+    pub index: u32,
+    /// Height of the block mining the output, if it is mined
+    pub height: Option<u64>,
+}
+
+/// Payment recipient
+#[derive(Clone, PartialEq, Eq, Hash, Debug, uniffi::Record)]
+pub struct Recipient {
+    /// Recipient address
+    pub address: String,
+    /// Amount to pay, in satoshis
+    pub amount: u64,
+}
+
+/// Descriptor wallet with optional in-memory signing keys
+#[derive(Debug, uniffi::Object)]
+pub struct Wallet {
+    descriptor: Descriptor<DerivationAccount>,
+    network: bitcoin::Network,
+    master: Option<ExtendedPrivKey>,
+}
+
+impl Wallet {
+    fn terminal(change: bool, index: u32) -> Result<[UnhardenedIndex; 2], WalletError> {
+        let index = UnhardenedIndex::from_index(index)
+            .map_err(|err| WalletError::Derivation(err.to_string()))?;
+        Ok([UnhardenedIndex::from(u8::from(change)), index])
+    }
+
+    fn esplora(esplora_url: &str) -> Result<AsyncClient, WalletError> {
+        esplora_client::Builder::new(esplora_url)
+            .build_async()
+            .map_err(|err| WalletError::Esplora(err.to_string()))
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> Result<F::Output, WalletError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| WalletError::Esplora(err.to_string()))?;
+        Ok(runtime.block_on(future))
+    }
+}
+
+#[uniffi::export]
+impl Wallet {
+    /// Creates a signing wallet from a BIP-39 mnemonic, using the first
+    /// account of the BIP-84 or BIP-86 derivation scheme.
+    #[uniffi::constructor]
+    pub fn from_mnemonic(
+        mnemonic: String,
+        passphrase: String,
+        network: Network,
+        script_type: ScriptType,
+    ) -> Result<Arc<Self>, WalletError> {
+        let network = bitcoin::Network::from(network);
+        let mnemonic = bip39::Mnemonic::from_str(&mnemonic)
+            .map_err(|err| WalletError::Mnemonic(err.to_string()))?;
+        let seed = mnemonic.to_seed(&passphrase);
+        let master = ExtendedPrivKey::new_master(network, &seed)
+            .map_err(|err| WalletError::Derivation(err.to_string()))?;
+
+        let scheme = match script_type {
+            ScriptType::SegWit => Bip43::Bip84,
+            ScriptType::Taproot => Bip43::Bip86,
+        };
+        let blockchain = match network {
+            bitcoin::Network::Bitcoin => DerivationBlockchain::Bitcoin,
+            _ => DerivationBlockchain::Testnet,
+        };
+        let derivation =
+            scheme.to_account_derivation(ChildNumber::Hardened { index: 0 }, blockchain);
+        let account_xpriv = master
+            .derive_priv(SECP256K1, &derivation)
+            .map_err(|err| WalletError::Derivation(err.to_string()))?;
+        let master_xpub = ExtendedPubKey::from_priv(SECP256K1, &master);
+        let account = MemorySigningAccount::with(
+            SECP256K1,
+            master_xpub.identifier(),
+            derivation,
+            account_xpriv,
+        );
+        let descriptor = account
+            .recommended_descriptor()
+            .expect("BIP-84 and BIP-86 have recommended descriptors");
+
+        Ok(Arc::new(Wallet {
+            descriptor,
+            network,
+            master: Some(master),
+        }))
+    }
+
+    /// Creates a watch-only wallet from an output descriptor
+    #[uniffi::constructor]
+    pub fn from_descriptor(descriptor: String, network: Network) -> Result<Arc<Self>, WalletError> {
+        let descriptor = Descriptor::from_str(&descriptor)
+            .map_err(|err| WalletError::Descriptor(err.to_string()))?;
+        match descriptor.derive_pattern_len() {
+            Ok(2) => {}
+            Ok(len) => {
+                return Err(WalletError::Descriptor(format!(
+                    "descriptor must use two-step derivation pattern instead of {len} steps"
+                )))
+            }
+            Err(err) => return Err(WalletError::Descriptor(err.to_string())),
+        }
+        Ok(Arc::new(Wallet {
+            descriptor,
+            network: network.into(),
+            master: None,
+        }))
+    }
+
+    /// Returns wallet output descriptor
+    pub fn descriptor(&self) -> String { self.descriptor.to_string() }
+
+    /// Derives receiving or change address at a given index
+    pub fn address(&self, change: bool, index: u32) -> Result<String, WalletError> {
+        self.descriptor
+            .address(SECP256K1, Self::terminal(change, index)?, self.network)
+            .map(|address| address.to_string())
+            .map_err(|err| WalletError::Derivation(err.to_string()))
+    }
+
+    /// Scans both keychains for unspent outputs using the esplora server.
+    /// Scanning of a keychain stops after `gap_limit` consecutive addresses
+    /// which were never used, i.e. have no transaction history.
+    pub fn scan(
+        &self,
+        esplora_url: String,
+        gap_limit: u32,
+    ) -> Result<Vec<WalletUtxo>, WalletError> {
+        let client = Self::esplora(&esplora_url)?;
+        let gap_limit = gap_limit.max(1);
+        Self::block_on(async {
+            let mut utxos = vec![];
+            for change in [false, true] {
+                let keychain = UnhardenedIndex::from(u8::from(change));
+                let mut from = UnhardenedIndex::zero();
+                let mut unused = 0u32;
+                loop {
+                    let batch = client
+                        .resolve_descriptor_utxo(
+                            SECP256K1,
+                            &self.descriptor,
+                            [keychain],
+                            from,
+                            gap_limit,
+                        )
+                        .await
+                        .map_err(|err| WalletError::Esplora(err.to_string()))?;
+                    for (index, (script, set)) in batch {
+                        // Addresses with spent outputs have no UTXOs, but are
+                        // still used
+                        let used = !set.is_empty()
+                            || !client
+                                .scripthash_txs(&script, None)
+                                .await
+                                .map_err(|err| WalletError::Esplora(err.to_string()))?
+                                .is_empty();
+                        unused = if used { 0 } else { unused + 1 };
+                        utxos.extend(set.into_iter().map(|utxo| WalletUtxo {
+                            outpoint: utxo.outpoint().to_string(),
+                            amount: utxo.amount().to_sat(),
+                            change,
+                            index: index.first_index(),
+                            height: match utxo.mined() {
+                                MiningStatus::Blockchain(height) => Some(*height),
+                                _ => None,
+                            },
+                        }));
+                    }
+                    if unused >= gap_limit {
+                        break;
+                    }
+                    from = match from.checked_add(gap_limit) {
+                        Some(index) => index,
+                        None => break,
+                    };
+                }
+            }
+            Ok::<_, WalletError>(utxos)
+        })?
+    }
+
+    /// Constructs PSBT spending given wallet outputs to the recipients and
+    /// sending change to the change address at `change_index`. Returns
+    /// base64-encoded PSBT.
+    pub fn construct_psbt(
+        &self,
+        esplora_url: String,
+        utxos: Vec<WalletUtxo>,
+        recipients: Vec<Recipient>,
+        change_index: u32,
+        fee: u64,
+    ) -> Result<String, WalletError> {
+        let client = Self::esplora(&esplora_url)?;
+
+        let mut inputs = Vec::with_capacity(utxos.len());
+        for utxo in &utxos {
+            let outpoint = OutPoint::from_str(&utxo.outpoint)
+                .map_err(|err| WalletError::Construct(err.to_string()))?;
+            let terminal = Self::terminal(utxo.change, utxo.index)?;
+            inputs.push(InputDescriptor::with(outpoint, terminal[..].into()));
+        }
+
+        let tx_resolver = Self::block_on(async {
+            let mut txs = BTreeMap::new();
+            for input in &inputs {
+                let txid = input.outpoint.txid;
+                let tx = client
+                    .resolve_tx(txid)
+                    .await
+                    .map_err(|err| WalletError::Esplora(err.to_string()))?;
+                txs.insert(txid, tx);
+            }
+            Ok::<_, WalletError>(txs)
+        })??;
+
+        let outputs = recipients
+            .into_iter()
+            .map(|recipient| {
+                let address = Address::from_str(&recipient.address)
+                    .map_err(|err| WalletError::Address(err.to_string()))?;
+                if !address.is_valid_for_network(self.network) {
+                    return Err(WalletError::Address(format!(
+                        "{address} can't be used on {}",
+                        self.network
+                    )));
+                }
+                Ok((
+                    PubkeyScript::from(address.script_pubkey()),
+                    recipient.amount,
+                ))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let change_index = UnhardenedIndex::from_index(change_index)
+            .map_err(|err| WalletError::Derivation(err.to_string()))?;
+        let psbt = Psbt::construct(
+            &self.descriptor,
+            &inputs,
+            &outputs,
+            change_index,
+            fee,
+            &tx_resolver,
+        )
+        .map_err(|err| WalletError::Construct(err.to_string()))?;
+        Ok(psbt.to_string())
+    }
+
+    /// Signs all PSBT inputs spending wallet outputs and returns the signed
+    /// base64-encoded PSBT. Follows strict signing policy: signs only with
+    /// `SIGHASH_ALL` and only inputs providing `witness_utxo`.
+    pub fn sign_psbt(&self, psbt: String) -> Result<String, WalletError> {
+        let master = self.master.ok_or(WalletError::WatchOnly)?;
+        let mut psbt = Psbt::from_str(&psbt).map_err(|err| WalletError::Psbt(err.to_string()))?;
+
+        let master_xpub = ExtendedPubKey::from_priv(SECP256K1, &master);
+        let account = MemorySigningAccount::with(
+            SECP256K1,
+            master_xpub.identifier(),
+            DerivationPath::master(),
+            master,
+        );
+        let mut key_provider = MemoryKeyProvider::with(SECP256K1, false);
+        key_provider.add_account(account);
+        psbt.sign_all(&key_provider, &SigningPolicy::strict())
+            .map_err(|err| WalletError::Sign(err.to_string()))?;
+        Ok(psbt.to_string())
+    }
+
+    /// Finalizes fully signed PSBT and returns hex-encoded transaction ready
+    /// for publication
+    pub fn finalize_psbt(&self, psbt: String) -> Result<String, WalletError> {
+        let psbt = Psbt::from_str(&psbt).map_err(|err| WalletError::Psbt(err.to_string()))?;
+        let mut psbt = PartiallySignedTransaction::from(psbt);
+        for index in 0..psbt.inputs.len() {
+            psbt.finalize_inp_mut(SECP256K1, index)
+                .map_err(|err| WalletError::Finalize(err.to_string()))?;
+        }
+        Ok(serialize(&psbt.extract_tx()).to_hex())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::consensus::deserialize;
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{EcdsaSighashType, PackedLockTime, Transaction, TxIn, TxOut, Txid};
+
+    use super::*;
+
+    // BIP-84 and BIP-86 test vector mnemonic
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon \
+                            abandon abandon abandon about";
+    const ESPLORA: &str = "http://127.0.0.1:1";
+
+    fn wallet(script_type: ScriptType) -> Arc<Wallet> {
+        Wallet::from_mnemonic(s!(MNEMONIC), s!(""), Network::Bitcoin, script_type).unwrap()
+    }
+
+    fn psbt(wallet: &Wallet) -> (String, Address) {
+        let address = Address::from_str(&wallet.address(false, 0).unwrap()).unwrap();
+        let prev_tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), 0),
+                ..TxIn::default()
+            }],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        let destination = Address::from_str(&wallet.address(false, 5).unwrap()).unwrap();
+        let inputs = [InputDescriptor::with(
+            OutPoint::new(prev_tx.txid(), 0),
+            Wallet::terminal(false, 0).unwrap()[..].into(),
+        )];
+        let outputs = [(PubkeyScript::from(destination.script_pubkey()), 50_000)];
+        let tx_resolver = bmap! { prev_tx.txid() => prev_tx };
+        let psbt = Psbt::construct(
+            &wallet.descriptor,
+            &inputs,
+            &outputs,
+            UnhardenedIndex::one(),
+            1_000,
+            &tx_resolver,
+        )
+        .unwrap();
+        (psbt.to_string(), destination)
+    }
+
+    #[test]
+    fn mnemonic() {
+        let segwit = wallet(ScriptType::SegWit);
+        assert_eq!(
+            segwit.address(false, 0).unwrap(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+        assert_eq!(
+            segwit.address(true, 0).unwrap(),
+            "bc1q8c6fshw2dlwun7ekn9qwf37cu2rn755upcp6el"
+        );
+        assert_eq!(
+            wallet(ScriptType::Taproot).address(false, 0).unwrap(),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+
+        let testnet =
+            Wallet::from_mnemonic(s!(MNEMONIC), s!(""), Network::Testnet, ScriptType::SegWit)
+                .unwrap();
+        assert!(testnet.address(false, 0).unwrap().starts_with("tb1q"));
+        assert!(testnet.descriptor().contains("/84h/1h/0h]"));
+
+        assert!(matches!(
+            Wallet::from_mnemonic(s!("abandon"), s!(""), Network::Bitcoin, ScriptType::SegWit),
+            Err(WalletError::Mnemonic(_))
+        ));
+        assert!(matches!(
+            segwit.address(false, 0x8000_0000),
+            Err(WalletError::Derivation(_))
+        ));
+    }
+
+    #[test]
+    fn descriptor() {
+        let signing = wallet(ScriptType::SegWit);
+        let watch_only = Wallet::from_descriptor(signing.descriptor(), Network::Bitcoin).unwrap();
+        assert_eq!(watch_only.descriptor(), signing.descriptor());
+        assert_eq!(watch_only.address(true, 3), signing.address(true, 3));
+
+        let single = signing.descriptor().replace("/*/*", "/*");
+        assert!(matches!(
+            Wallet::from_descriptor(single, Network::Bitcoin),
+            Err(WalletError::Descriptor(_))
+        ));
+        assert!(matches!(
+            Wallet::from_descriptor(s!("wpkh(invalid)"), Network::Bitcoin),
+            Err(WalletError::Descriptor(_))
+        ));
+    }
+
+    #[test]
+    fn sign_finalize() {
+        let wallet = wallet(ScriptType::SegWit);
+        let (psbt, destination) = psbt(&wallet);
+        assert!(matches!(
+            wallet.finalize_psbt(psbt.clone()),
+            Err(WalletError::Finalize(_))
+        ));
+
+        let signed = wallet.sign_psbt(psbt).unwrap();
+        let tx: Transaction =
+            deserialize(&Vec::from_hex(&wallet.finalize_psbt(signed).unwrap()).unwrap()).unwrap();
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].witness.len(), 2);
+        assert!(tx
+            .output
+            .iter()
+            .any(|out| out.value == 50_000 && out.script_pubkey == destination.script_pubkey()));
+    }
+
+    #[test]
+    fn sign_errors() {
+        let signing = wallet(ScriptType::SegWit);
+        let (unsigned, _) = psbt(&signing);
+        let watch_only = Wallet::from_descriptor(signing.descriptor(), Network::Bitcoin).unwrap();
+        assert_eq!(
+            watch_only.sign_psbt(unsigned.clone()),
+            Err(WalletError::WatchOnly)
+        );
+        assert!(matches!(
+            signing.sign_psbt(s!("cHNidP8=")),
+            Err(WalletError::Psbt(_))
+        ));
+
+        let mut unsafe_psbt = Psbt::from_str(&unsigned).unwrap();
+        unsafe_psbt.inputs[0].sighash_type = Some(EcdsaSighashType::None.into());
+        assert!(matches!(
+            signing.sign_psbt(unsafe_psbt.to_string()),
+            Err(WalletError::Sign(_))
+        ));
+        let mut unsafe_psbt = Psbt::from_str(&unsigned).unwrap();
+        unsafe_psbt.inputs[0].witness_utxo = None;
+        assert!(matches!(
+            signing.sign_psbt(unsafe_psbt.to_string()),
+            Err(WalletError::Sign(_))
+        ));
+        assert!(matches!(
+            signing.finalize_psbt(s!("psbt")),
+            Err(WalletError::Psbt(_))
+        ));
+    }
+
+    #[test]
+    fn construct_errors() {
+        // None of the cases reach the esplora server: wallet has no inputs to
+        // resolve
+        let wallet = wallet(ScriptType::SegWit);
+        let recipient = |address: &str| Recipient {
+            address: address.to_owned(),
+            amount: 50_000,
+        };
+        let utxo = WalletUtxo {
+            outpoint: s!("txid:0"),
+            amount: 100_000,
+            change: false,
+            index: 0,
+            height: None,
+        };
+        let construct =
+            |utxos, recipients| wallet.construct_psbt(s!(ESPLORA), utxos, recipients, 1, 1_000);
+
+        assert!(matches!(
+            construct(vec![utxo], vec![]),
+            Err(WalletError::Construct(_))
+        ));
+        assert!(matches!(
+            construct(vec![], vec![recipient("bc1")]),
+            Err(WalletError::Address(_))
+        ));
+        let testnet =
+            Wallet::from_mnemonic(s!(MNEMONIC), s!(""), Network::Testnet, ScriptType::SegWit)
+                .unwrap();
+        assert!(matches!(
+            construct(vec![], vec![recipient(&testnet.address(false, 0).unwrap())]),
+            Err(WalletError::Address(_))
+        ));
+        // No funds to pay the recipient
+        assert!(matches!(
+            construct(vec![], vec![recipient(&wallet.address(false, 1).unwrap())]),
+            Err(WalletError::Construct(_))
+        ));
+    }
+}
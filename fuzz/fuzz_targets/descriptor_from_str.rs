@@ -0,0 +1,20 @@
+#![no_main]
+
+use std::str::FromStr;
+
+use bitcoin_hd::DerivationAccount;
+use libfuzzer_sys::fuzz_target;
+use miniscript_crate::Descriptor;
+
+fuzz_target!(|data: &[u8]| {
+    let s = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    if let Ok(descriptor) = Descriptor::<DerivationAccount>::from_str(s) {
+        let reparsed = Descriptor::<DerivationAccount>::from_str(&descriptor.to_string())
+            .expect("descriptor display output must be parseable");
+        assert_eq!(reparsed, descriptor);
+    }
+    let _ = DerivationAccount::from_str(s);
+});
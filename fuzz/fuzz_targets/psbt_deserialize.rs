@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use psbt::serialize::{Deserialize, Serialize};
+use psbt::Psbt;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(psbt) = Psbt::deserialize(data) {
+        let reserialized = psbt.serialize();
+        let decoded = Psbt::deserialize(&reserialized).expect("serialized PSBT must be valid");
+        assert_eq!(decoded, psbt);
+    }
+    let _ = Psbt::decode_any(data);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use psbt::serialize::{Deserialize, Serialize};
+use psbt::Psbt;
+
+fuzz_target!(|psbt: Psbt| {
+    let data = psbt.serialize();
+    let _ = Psbt::deserialize(&data);
+    let _ = psbt.to_string();
+});
@@ -14,6 +14,8 @@
 use std::fmt::{self, Display, Formatter};
 use std::str::FromStr;
 
+#[cfg(feature = "arbitrary")]
+use arbitrary::{Arbitrary, Unstructured};
 use bitcoin::util::bip32::{
     self, ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint, KeySource,
 };
@@ -463,6 +465,41 @@ impl miniscript::MiniscriptKey for DerivationAccount {
     type Hash160 = Self;
 }
 
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for DerivationAccount {
+    /// Generates account with an extended public key derived from an arbitrary
+    /// seed, up to four hardened account path steps and up to three terminal
+    /// steps, each of which is either a wildcard or a specific index.
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let seed = <[u8; 32]>::arbitrary(u)?;
+        let account_xpriv = ExtendedPrivKey::new_master(bitcoin::Network::Bitcoin, &seed)
+            .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+        let account_xpub = ExtendedPubKey::from_priv(secp256k1::SECP256K1, &account_xpriv);
+        let master = match Option::<[u8; 4]>::arbitrary(u)? {
+            Some(fingerprint) => XpubRef::Fingerprint(Fingerprint::from(&fingerprint[..])),
+            None => XpubRef::Unknown,
+        };
+        let mut account_path = vec![];
+        for _ in 0..u.int_in_range(0..=4)? {
+            account_path.push(AccountStep::hardened_index(u16::arbitrary(u)?));
+        }
+        let mut terminal_path = vec![];
+        for _ in 0..u.int_in_range(0..=3)? {
+            terminal_path.push(match Option::<u16>::arbitrary(u)? {
+                Some(index) => TerminalStep::from(index),
+                None => TerminalStep::Wildcard,
+            });
+        }
+        Ok(DerivationAccount {
+            master,
+            account_path: account_path.into(),
+            account_xpub,
+            revocation_seal: None,
+            terminal_path: terminal_path.into(),
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -552,4 +589,27 @@ mod test {
             assert_eq!(format!("{}", account), path);
         }
     }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary() {
+        use bitcoin::hashes::{sha256, Hash};
+
+        for seed in 0..16u8 {
+            let data = (0..4u8)
+                .flat_map(|i| sha256::Hash::hash(&[seed, i]).into_inner())
+                .collect::<Vec<_>>();
+            let account = DerivationAccount::arbitrary(&mut Unstructured::new(&data)).unwrap();
+            assert!(account.account_path.len() <= 4);
+            assert!(account.account_path.iter().all(AccountStep::is_hardened));
+            assert!(account.terminal_path.len() <= 3);
+            assert_eq!(account.revocation_seal, None);
+            assert_eq!(account.account_xpub.network, bitcoin::Network::Bitcoin);
+        }
+
+        let account = DerivationAccount::arbitrary(&mut Unstructured::new(&[])).unwrap();
+        assert_eq!(account.master, XpubRef::Unknown);
+        assert!(account.account_path.is_empty());
+        assert!(account.terminal_path.is_empty());
+    }
 }
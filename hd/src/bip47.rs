@@ -0,0 +1,489 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! BIP-47 reusable payment codes.
+//!
+//! Payment code is an extended public key of `m/47'/0'/n'` account, published
+//! by a receiver. The sender notifies the receiver about its own payment code
+//! with a notification transaction, after which both parties are able to
+//! derive a chain of deposit keys unique for the sender-receiver pair: the
+//! sender derives deposit public keys, and the receiver derives matching
+//! secret keys.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::{sha256, sha512, Hash, HashEngine, Hmac, HmacEngine};
+use bitcoin::util::base58;
+use bitcoin::util::bip32::{ChainCode, ExtendedPrivKey, ExtendedPubKey, Fingerprint};
+use bitcoin::{Address, Network, OutPoint, Script};
+use secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey, Signing, Verification};
+
+use crate::{
+    AccountStep, DerivationAccount, HardenedIndex, SegmentIndexes, TerminalStep, UnhardenedIndex,
+    XpubRef,
+};
+
+/// Version byte prefixing base58 encoding of payment codes
+pub const PAYMENT_CODE_PREFIX: u8 = 0x47;
+/// Length of the binary payment code serialization
+pub const PAYMENT_CODE_LEN: usize = 80;
+/// BIP-43 purpose of the payment code derivation path
+pub const BIP47_PURPOSE: u16 = 47;
+
+/// Errors parsing BIP-47 payment codes and deriving deposit keys
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum Bip47Error {
+    /// invalid base58 encoding of payment code: {0}
+    #[from]
+    Base58(base58::Error),
+
+    /// payment code starts with {0:#04x} byte instead of 0x47 prefix
+    WrongPrefix(u8),
+
+    /// payment code must be 80 bytes long, while {0} bytes were given
+    WrongLength(usize),
+
+    /// payment code version {0} is not supported
+    UnsupportedVersion(u8),
+
+    /// payment code contains invalid public key
+    InvalidPublicKey,
+
+    /// shared secret for deposit key {0} is not a valid scalar; the next index
+    /// must be used instead
+    InvalidSharedSecret(UnhardenedIndex),
+}
+
+/// BIP-47 version 1 payment code
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct PaymentCode {
+    /// Features bitfield; no features are defined for version 1 codes
+    pub features: u8,
+
+    /// Public key of the payment code account
+    pub public_key: PublicKey,
+
+    /// Chain code of the payment code account
+    pub chain_code: ChainCode,
+}
+
+impl Display for PaymentCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut data = vec![PAYMENT_CODE_PREFIX];
+        data.extend(self.to_bytes());
+        f.write_str(&base58::check_encode_slice(&data))
+    }
+}
+
+impl FromStr for PaymentCode {
+    type Err = Bip47Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = base58::from_check(s)?;
+        match data.split_first() {
+            Some((&PAYMENT_CODE_PREFIX, code)) => PaymentCode::from_bytes(code),
+            Some((prefix, _)) => Err(Bip47Error::WrongPrefix(*prefix)),
+            None => Err(Bip47Error::WrongLength(0)),
+        }
+    }
+}
+
+impl PaymentCode {
+    /// Constructs payment code from the extended public key of `m/47'/0'/n'`
+    /// account
+    pub fn from_account_xpub(xpub: &ExtendedPubKey) -> PaymentCode {
+        PaymentCode {
+            features: 0,
+            public_key: xpub.public_key,
+            chain_code: xpub.chain_code,
+        }
+    }
+
+    /// Constructs payment code from the extended private key of `m/47'/0'/n'`
+    /// account
+    pub fn from_account_xpriv<C: Signing>(
+        secp: &Secp256k1<C>,
+        xpriv: &ExtendedPrivKey,
+    ) -> PaymentCode {
+        PaymentCode::from_account_xpub(&ExtendedPubKey::from_priv(secp, xpriv))
+    }
+
+    /// Parses binary payment code serialization
+    pub fn from_bytes(data: &[u8]) -> Result<PaymentCode, Bip47Error> {
+        if data.len() != PAYMENT_CODE_LEN {
+            return Err(Bip47Error::WrongLength(data.len()));
+        }
+        if data[0] != 1 {
+            return Err(Bip47Error::UnsupportedVersion(data[0]));
+        }
+        Ok(PaymentCode {
+            features: data[1],
+            public_key: PublicKey::from_slice(&data[2..35])
+                .map_err(|_| Bip47Error::InvalidPublicKey)?,
+            chain_code: ChainCode::from(&data[35..67]),
+        })
+    }
+
+    /// Returns binary payment code serialization
+    pub fn to_bytes(&self) -> [u8; PAYMENT_CODE_LEN] {
+        let mut data = [0u8; PAYMENT_CODE_LEN];
+        data[0] = 1;
+        data[1] = self.features;
+        data[2..35].copy_from_slice(&self.public_key.serialize());
+        data[35..67].copy_from_slice(&self.chain_code[..]);
+        data
+    }
+
+    /// Returns extended public key of the payment code account with a given
+    /// account number
+    pub fn to_account_xpub(&self, network: Network, account: HardenedIndex) -> ExtendedPubKey {
+        ExtendedPubKey {
+            network,
+            depth: 3,
+            parent_fingerprint: Fingerprint::default(),
+            child_number: account.into(),
+            public_key: self.public_key,
+            chain_code: self.chain_code,
+        }
+    }
+
+    /// Converts payment code into a tracking account with `m/47'/coin'/n'`
+    /// account derivation path and a single wildcard terminal step, deriving
+    /// the payment code keys which are tweaked into the deposit keys.
+    pub fn to_derivation_account(
+        &self,
+        network: Network,
+        account: HardenedIndex,
+    ) -> DerivationAccount {
+        let coin_type = if network == Network::Bitcoin { 0 } else { 1 };
+        DerivationAccount {
+            master: XpubRef::Unknown,
+            account_path: [
+                AccountStep::hardened_index(BIP47_PURPOSE),
+                AccountStep::hardened_index(coin_type),
+                AccountStep::hardened(account),
+            ]
+            .into_iter()
+            .collect(),
+            account_xpub: self.to_account_xpub(network, account),
+            revocation_seal: None,
+            terminal_path: [TerminalStep::Wildcard].into_iter().collect(),
+        }
+    }
+
+    fn derive_key<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: UnhardenedIndex,
+    ) -> PublicKey {
+        self.to_account_xpub(Network::Bitcoin, HardenedIndex::zero())
+            .ckd_pub(secp, index.into())
+            .expect("negligible probability")
+            .public_key
+    }
+
+    /// Returns notification public key of the payment code
+    pub fn notification_key<C: Verification>(&self, secp: &Secp256k1<C>) -> PublicKey {
+        self.derive_key(secp, UnhardenedIndex::zero())
+    }
+
+    /// Returns P2PKH notification address of the payment code, which receives
+    /// notification transactions
+    pub fn notification_address<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        network: Network,
+    ) -> Address {
+        Address::p2pkh(
+            &bitcoin::PublicKey::new(self.notification_key(secp)),
+            network,
+        )
+    }
+
+    /// Derives deposit public key with a given index for payments to this
+    /// payment code from the owner of the `sender` payment code account
+    /// extended private key.
+    pub fn deposit_public_key<C: Signing + Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        sender: &ExtendedPrivKey,
+        index: UnhardenedIndex,
+    ) -> Result<PublicKey, Bip47Error> {
+        let notification_secret = sender
+            .ckd_priv(secp, UnhardenedIndex::zero().into())
+            .expect("negligible probability")
+            .private_key;
+        let key = self.derive_key(secp, index);
+        let tweak = shared_secret(secp, &key, &notification_secret)
+            .ok_or(Bip47Error::InvalidSharedSecret(index))?;
+        Ok(key
+            .add_exp_tweak(secp, &tweak)
+            .expect("negligible probability"))
+    }
+
+    /// Derives deposit secret key with a given index for payments from this
+    /// payment code to the owner of the `receiver` payment code account
+    /// extended private key.
+    pub fn deposit_secret_key<C: Signing + Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        receiver: &ExtendedPrivKey,
+        index: UnhardenedIndex,
+    ) -> Result<SecretKey, Bip47Error> {
+        let secret = receiver
+            .ckd_priv(secp, index.into())
+            .expect("negligible probability")
+            .private_key;
+        let tweak = shared_secret(secp, &self.notification_key(secp), &secret)
+            .ok_or(Bip47Error::InvalidSharedSecret(index))?;
+        Ok(secret.add_tweak(&tweak).expect("negligible probability"))
+    }
+
+    /// Produces payload of the notification transaction `OP_RETURN` output,
+    /// containing this payment code blinded for the `receiver`.
+    ///
+    /// Designated key is the secret key for the public key exposed by the
+    /// first notification transaction input spending `outpoint`.
+    pub fn to_notification_payload<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        receiver: &PaymentCode,
+        designated_key: &SecretKey,
+        outpoint: OutPoint,
+    ) -> [u8; PAYMENT_CODE_LEN] {
+        let mut payload = self.to_bytes();
+        let mask = blinding_mask(
+            secp,
+            &receiver.notification_key(secp),
+            designated_key,
+            outpoint,
+        );
+        payload[3..67]
+            .iter_mut()
+            .zip(mask)
+            .for_each(|(byte, mask)| *byte ^= mask);
+        payload
+    }
+
+    /// Recovers sender payment code from the notification transaction
+    /// `OP_RETURN` payload by the owner of the `receiver` payment code account
+    /// extended private key.
+    ///
+    /// Designated public key is the public key exposed by the first
+    /// notification transaction input, which spends `outpoint`.
+    pub fn from_notification_payload<C: Signing + Verification>(
+        secp: &Secp256k1<C>,
+        payload: &[u8],
+        receiver: &ExtendedPrivKey,
+        designated_pubkey: &PublicKey,
+        outpoint: OutPoint,
+    ) -> Result<PaymentCode, Bip47Error> {
+        if payload.len() != PAYMENT_CODE_LEN {
+            return Err(Bip47Error::WrongLength(payload.len()));
+        }
+        let notification_secret = receiver
+            .ckd_priv(secp, UnhardenedIndex::zero().into())
+            .expect("negligible probability")
+            .private_key;
+        let mut data = payload.to_vec();
+        let mask = blinding_mask(secp, designated_pubkey, &notification_secret, outpoint);
+        data[3..67]
+            .iter_mut()
+            .zip(mask)
+            .for_each(|(byte, mask)| *byte ^= mask);
+        PaymentCode::from_bytes(&data)
+    }
+
+    /// Constructs notification transaction `OP_RETURN` output script for a
+    /// given payload
+    #[inline]
+    pub fn notification_script(payload: &[u8; PAYMENT_CODE_LEN]) -> Script {
+        Script::new_op_return(payload)
+    }
+}
+
+/// Computes x coordinate of the ECDH point
+fn ecdh_x<C: Verification>(secp: &Secp256k1<C>, key: &PublicKey, secret: &SecretKey) -> [u8; 32] {
+    let point = key
+        .mul_tweak(secp, &Scalar::from(*secret))
+        .expect("negligible probability");
+    let mut x = [0u8; 32];
+    x.copy_from_slice(&point.serialize()[1..]);
+    x
+}
+
+/// Computes scalar tweaking payment code key into the deposit key
+fn shared_secret<C: Verification>(
+    secp: &Secp256k1<C>,
+    key: &PublicKey,
+    secret: &SecretKey,
+) -> Option<Scalar> {
+    let hash = sha256::Hash::hash(&ecdh_x(secp, key, secret));
+    Scalar::from_be_bytes(hash.into_inner()).ok()
+}
+
+/// Computes mask blinding payment code public key x coordinate and chain code
+/// in notification transactions
+fn blinding_mask<C: Verification>(
+    secp: &Secp256k1<C>,
+    key: &PublicKey,
+    secret: &SecretKey,
+    outpoint: OutPoint,
+) -> [u8; 64] {
+    let mut engine = HmacEngine::<sha512::Hash>::new(&serialize(&outpoint));
+    engine.input(&ecdh_x(secp, key, secret));
+    Hmac::<sha512::Hash>::from_engine(engine).into_inner()
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::util::bip32::DerivationPath;
+
+    use super::*;
+    use crate::account::DerivePublicKey;
+
+    // Test vectors from BIP-47
+    const ALICE_SEED: &str = "64dca76abc9c6f0cf3d212d248c380c4622c8f93b2c425ec6a5567fd5db57e10\
+                              d3e6f94a2f6af4ac2edb8998072aad92098db73558c323777abf5bd1082d970a";
+    const BOB_SEED: &str = "87eaaac5a539ab028df44d9110defbef3797ddb805ca309f61a69ff96dbaa7ab\
+                            5b24038cf029edec5235d933110f0aea8aeecf939ed14fc20730bba71e4b1110";
+    const ALICE_CODE: &str = "PM8TJTLJbPRGxSbc8EJi42Wrr6QbNSaSSVJ5Y3E4pbCYiTHUskHg13935Ubb7q8tx9\
+                              GVbh2UuRnBc3WSyJHhUrw8KhprKnn9eDznYGieTzFcwQRya4GA";
+    const BOB_CODE: &str = "PM8TJS2JxQ5ztXUpBBRnpTbcUXbUHy2T1abfrb3KkAAtMEGNbey4oumH7Hc578WgQJ\
+                            hPjBxteQ5GHHToTYHE3A1w6p7tU6KSoFmWBVbFGjKPisZDbP97";
+
+    fn account_xpriv(seed: &str) -> ExtendedPrivKey {
+        let secp = Secp256k1::new();
+        let seed = Vec::<u8>::from_hex(seed).unwrap();
+        let path = DerivationPath::from_str("m/47'/0'/0'").unwrap();
+        ExtendedPrivKey::new_master(Network::Bitcoin, &seed)
+            .unwrap()
+            .derive_priv(&secp, &path)
+            .unwrap()
+    }
+
+    #[test]
+    fn bip47_payment_codes() {
+        let secp = Secp256k1::new();
+        let alice = PaymentCode::from_account_xpriv(&secp, &account_xpriv(ALICE_SEED));
+        let bob = PaymentCode::from_account_xpriv(&secp, &account_xpriv(BOB_SEED));
+        assert_eq!(alice.to_string(), ALICE_CODE);
+        assert_eq!(bob.to_string(), BOB_CODE);
+        assert_eq!(PaymentCode::from_str(ALICE_CODE), Ok(alice));
+        assert_eq!(PaymentCode::from_str(BOB_CODE), Ok(bob));
+        let xpub = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8\
+                    YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+        assert_eq!(
+            PaymentCode::from_str(xpub),
+            Err(Bip47Error::WrongPrefix(0x04))
+        );
+
+        assert_eq!(
+            alice
+                .notification_address(&secp, Network::Bitcoin)
+                .to_string(),
+            "1JDdmqFLhpzcUwPeinhJbUPw4Co3aWLyzW"
+        );
+        assert_eq!(
+            bob.notification_address(&secp, Network::Bitcoin)
+                .to_string(),
+            "1ChvUUvht2hUQufHBXF8NgLhW8SwE2ecGV"
+        );
+
+        let account = bob.to_derivation_account(Network::Bitcoin, HardenedIndex::zero());
+        assert_eq!(
+            account.to_account_derivation_path(),
+            DerivationPath::from_str("m/47'/0'/0'").unwrap()
+        );
+        assert_eq!(
+            account.derive_public_key(&secp, [UnhardenedIndex::zero()]),
+            Ok(bob.notification_key(&secp))
+        );
+    }
+
+    #[test]
+    fn bip47_deposit_keys() {
+        let secp = Secp256k1::new();
+        let alice_xpriv = account_xpriv(ALICE_SEED);
+        let bob_xpriv = account_xpriv(BOB_SEED);
+        let alice = PaymentCode::from_account_xpriv(&secp, &alice_xpriv);
+        let bob = PaymentCode::from_account_xpriv(&secp, &bob_xpriv);
+
+        for (index, address, secret) in [
+            (
+                0u8,
+                "141fi7TY3h936vRUKh1qfUZr8rSBuYbVBK",
+                "d687f6b820e6e3d47296b01f3b73ccdc930eded39d559921a7dd8ed81b2c8f82",
+            ),
+            (
+                1u8,
+                "12u3Uued2fuko2nY4SoSFGCoGLCBUGPkk6",
+                "c7a376a4ddc5ca6ecc3822fd06f6c5009911e71ce38e9b1e52bd2aaf735fd505",
+            ),
+            (
+                2u8,
+                "1FsBVhT5dQutGwaPePTYMe5qvYqqjxyftc",
+                "72ab5f58870e5b24e13c1bedf674e2419bdba319bedeccf5eb4851050fc6feed",
+            ),
+        ] {
+            let index = UnhardenedIndex::from(index);
+            let key = bob.deposit_public_key(&secp, &alice_xpriv, index).unwrap();
+            let secret_key = alice.deposit_secret_key(&secp, &bob_xpriv, index).unwrap();
+            assert_eq!(
+                Address::p2pkh(&bitcoin::PublicKey::new(key), Network::Bitcoin).to_string(),
+                address
+            );
+            assert_eq!(secret_key, SecretKey::from_str(secret).unwrap());
+            assert_eq!(PublicKey::from_secret_key(&secp, &secret_key), key);
+        }
+    }
+
+    #[test]
+    fn bip47_notification_payload() {
+        let secp = Secp256k1::new();
+        let bob_xpriv = account_xpriv(BOB_SEED);
+        let alice = PaymentCode::from_str(ALICE_CODE).unwrap();
+        let bob = PaymentCode::from_account_xpriv(&secp, &bob_xpriv);
+
+        let designated_key =
+            SecretKey::from_str("1b7a10f45118e2519a8dd46ef81591c1ae501d082b6610fdda3de7a3c932880d")
+                .unwrap();
+        let outpoint = OutPoint::from_str(
+            "9c6000d597c5008f7bfc2618aed5e4a6ae57677aab95078aae708e1cab11f486:1",
+        )
+        .unwrap();
+        let payload = alice.to_notification_payload(&secp, &bob, &designated_key, outpoint);
+        assert_eq!(
+            payload.to_vec(),
+            Vec::<u8>::from_hex(
+                "010002063e4eb95e62791b06c50e1a3a942e1ecaaa9afbbeb324d16ae6821e091611fa96c0cf048f\
+                 607fe51a0327f5e2528979311c78cb2de0d682c61e1180fc3d543b00000000000000000000000000"
+            )
+            .unwrap()
+        );
+
+        let designated_pubkey = PublicKey::from_secret_key(&secp, &designated_key);
+        let recovered = PaymentCode::from_notification_payload(
+            &secp,
+            &payload,
+            &bob_xpriv,
+            &designated_pubkey,
+            outpoint,
+        )
+        .unwrap();
+        assert_eq!(recovered, alice);
+        assert!(PaymentCode::notification_script(&payload).is_op_return());
+    }
+}
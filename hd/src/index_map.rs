@@ -0,0 +1,182 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Persistent reverse map from derived `scriptPubkey`s to the account and
+//! terminal derivation path they were derived with.
+
+use std::collections::BTreeMap;
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::util::bip32::Fingerprint;
+use bitcoin::Script;
+
+use crate::{DerivationSubpath, SegmentIndexes, UnhardenedIndex};
+
+/// Cache of scripts derived by a wallet, allowing fast lookup of the account
+/// and terminal derivation path for a given `scriptPubkey` without re-deriving
+/// the whole range of wallet addresses.
+///
+/// Scripts are indexed by their SHA256 hash (the same value electrum servers
+/// use as a script hash). Accounts are identified by the fingerprint of the
+/// account extended public key; the map tracks how many scripts were derived
+/// for each account keychain, so it may be extended incrementally as the
+/// wallet issues new addresses.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct DerivationIndexMap {
+    scripts: BTreeMap<sha256::Hash, (Fingerprint, DerivationSubpath<UnhardenedIndex>)>,
+    extents: BTreeMap<(Fingerprint, DerivationSubpath<UnhardenedIndex>), UnhardenedIndex>,
+}
+
+impl DerivationIndexMap {
+    /// Constructs empty index map
+    #[inline]
+    pub fn new() -> DerivationIndexMap { DerivationIndexMap::default() }
+
+    /// Returns number of scripts in the map
+    #[inline]
+    pub fn len(&self) -> usize { self.scripts.len() }
+
+    /// Detects whether the map contains no scripts
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.scripts.is_empty() }
+
+    /// Returns the first index in the account keychain which was not derived
+    /// yet, i.e. the index from which [`DerivationIndexMap::extend`] will
+    /// continue derivation.
+    pub fn next_index(
+        &self,
+        account: Fingerprint,
+        keychain: &DerivationSubpath<UnhardenedIndex>,
+    ) -> UnhardenedIndex {
+        self.extents
+            .get(&(account, keychain.clone()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Adds script derived with a given account and terminal derivation path
+    /// to the map, returning the previous derivation info for the script, if
+    /// any.
+    ///
+    /// Terminal path must be non-empty; the last segment of the path is
+    /// treated as the address index and the preceding segments as the
+    /// keychain.
+    pub fn insert(
+        &mut self,
+        script: &Script,
+        account: Fingerprint,
+        terminal: DerivationSubpath<UnhardenedIndex>,
+    ) -> Option<(Fingerprint, DerivationSubpath<UnhardenedIndex>)> {
+        if let Some((index, keychain)) = terminal.split_last() {
+            let next = index.checked_inc().unwrap_or(UnhardenedIndex::largest());
+            let extent = self
+                .extents
+                .entry((account, keychain.to_vec().into()))
+                .or_default();
+            if *extent < next {
+                *extent = next;
+            }
+        }
+        self.scripts
+            .insert(sha256::Hash::hash(script.as_bytes()), (account, terminal))
+    }
+
+    /// Derives `count` more scripts for an account keychain, starting from
+    /// [`DerivationIndexMap::next_index`], and adds them to the map. The
+    /// `derive` callback receives full terminal derivation path (keychain
+    /// followed by the address index) and must return the derived script.
+    ///
+    /// Returns number of added scripts, which may be less than `count` if the
+    /// keychain index space is exhausted.
+    pub fn extend<E>(
+        &mut self,
+        account: Fingerprint,
+        keychain: &DerivationSubpath<UnhardenedIndex>,
+        count: u32,
+        mut derive: impl FnMut(&[UnhardenedIndex]) -> Result<Script, E>,
+    ) -> Result<usize, E> {
+        let mut index = Some(self.next_index(account, keychain));
+        let mut added = 0usize;
+        while let Some(current) = index {
+            if added >= count as usize {
+                break;
+            }
+            let mut terminal = keychain.clone();
+            terminal.push(current);
+            let script = derive(&terminal)?;
+            self.insert(&script, account, terminal);
+            added += 1;
+            index = current.checked_inc();
+        }
+        Ok(added)
+    }
+
+    /// Looks up account and terminal derivation path used to derive a given
+    /// script.
+    pub fn lookup(
+        &self,
+        script: &Script,
+    ) -> Option<(Fingerprint, &DerivationSubpath<UnhardenedIndex>)> {
+        self.scripts
+            .get(&sha256::Hash::hash(script.as_bytes()))
+            .map(|(account, terminal)| (*account, terminal))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::blockdata::script::Builder;
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::*;
+
+    fn script(terminal: &[UnhardenedIndex]) -> Result<Script, ()> {
+        let data = terminal
+            .iter()
+            .flat_map(|index| index.first_index().to_le_bytes())
+            .collect::<Vec<_>>();
+        Ok(Builder::new().push_slice(&data).into_script())
+    }
+
+    #[test]
+    fn index_map() {
+        let account = Fingerprint::from(&[0xde, 0xad, 0xbe, 0xef][..]);
+        let external = DerivationSubpath::from(vec![UnhardenedIndex::zero()]);
+        let change = DerivationSubpath::from(vec![UnhardenedIndex::one()]);
+
+        let mut map = DerivationIndexMap::new();
+        assert!(map.is_empty());
+        assert_eq!(map.extend(account, &external, 10, script), Ok(10));
+        assert_eq!(map.extend(account, &change, 5, script), Ok(5));
+        assert_eq!(
+            map.next_index(account, &external),
+            UnhardenedIndex::from(10u8)
+        );
+        assert_eq!(map.extend(account, &external, 10, script), Ok(10));
+        assert_eq!(map.len(), 25);
+        assert_eq!(
+            map.next_index(account, &external),
+            UnhardenedIndex::from(20u8)
+        );
+
+        let terminal = vec![UnhardenedIndex::one(), UnhardenedIndex::from(3u8)];
+        let target = script(&terminal).unwrap();
+        assert_eq!(
+            map.lookup(&target),
+            Some((account, &DerivationSubpath::from(terminal)))
+        );
+        assert_eq!(map.lookup(&Script::new()), None);
+
+        let data = map.strict_serialize().unwrap();
+        assert_eq!(DerivationIndexMap::strict_deserialize(data).unwrap(), map);
+    }
+}
@@ -30,10 +30,13 @@ extern crate serde_crate as serde;
 extern crate miniscript_crate as miniscript;
 
 pub mod account;
+pub mod bip47;
 mod derive;
+mod index_map;
 mod indexes;
 mod path;
 mod ranges;
+pub mod rotation;
 pub mod standards;
 mod traits;
 mod unsatisfiable;
@@ -41,16 +44,26 @@ mod xkey;
 mod xpubref;
 
 pub use account::DerivationAccount;
+pub use bip47::{Bip47Error, PaymentCode};
 pub use derive::{DeriveError, DerivePatternError};
+pub use index_map::DerivationIndexMap;
 pub use indexes::{
     AccountStep, HardenedIndex, HardenedIndexExpected, SegmentIndexes, TerminalStep,
     UnhardenedIndex, UnhardenedIndexExpected,
 };
-pub use path::DerivationSubpath;
+pub use path::{
+    DerivationPathAlgebra, DerivationSubpath, HardenedNotation, PathError, MAX_DERIVATION_DEPTH,
+};
 pub use ranges::{IndexRange, IndexRangeList};
-pub use standards::{Bip43, DerivationStandard, DescriptorType};
+pub use rotation::{KeyRotation, KeyRotations, RotationError};
+pub use standards::{
+    discover_accounts, Bip43, DerivationStandard, DescriptorType, DiscoveryError,
+    ResolveAccountUsage,
+};
 pub use traits::{DerivationPathMaster, HardenedNormalSplit};
-pub use unsatisfiable::UnsatisfiableKey;
+#[cfg(feature = "miniscript")]
+pub use unsatisfiable::tr_script_only;
+pub use unsatisfiable::{FromUnspendableKey, UnsatisfiableKey, UnspendableKey, BIP341_NUMS_POINT};
 pub use xkey::{
     NonStandardDerivation, XpubDescriptor, XpubOrigin, XpubParseError, XpubRequirementError,
     XpubkeyCore,
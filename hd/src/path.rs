@@ -15,7 +15,7 @@ use std::borrow::{Borrow, BorrowMut};
 use std::io;
 use std::ops::{Deref, DerefMut};
 
-use bitcoin::util::bip32;
+use bitcoin::util::bip32::{self, DerivationPath};
 use strict_encoding::{StrictDecode, StrictEncode};
 
 use crate::SegmentIndexes;
@@ -190,3 +190,187 @@ where
     /// Constructs empty derivation path.
     pub fn new() -> Self { Self::default() }
 }
+
+/// Maximal depth of the BIP-32 derivation path
+pub const MAX_DERIVATION_DEPTH: usize = u8::MAX as usize;
+
+/// Errors in derivation path algebra operations
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PathError {
+    /// derivation path does not start with the provided prefix
+    PrefixMismatch,
+
+    /// derivation path depth {0} exceeds maximal depth of 255 allowed by BIP-32
+    DepthOverflow(usize),
+}
+
+/// Notation used for the hardened derivation path segments
+#[derive(
+    Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default, Display
+)]
+pub enum HardenedNotation {
+    /// Apostrophe notation (`0'`), used by BIP-32 and the [`bitcoin`] library
+    #[display("'")]
+    Apostrophe,
+
+    /// Letter notation (`0h`), which does not require shell escaping and is
+    /// used by output descriptors by default
+    #[display("h")]
+    #[default]
+    Letter,
+}
+
+impl HardenedNotation {
+    /// Re-writes string representation of a derivation path using this
+    /// notation for all hardened segments. Key references following `=` sign
+    /// inside path segments are kept intact.
+    pub fn apply(self, path: &str) -> String {
+        path.split('/')
+            .map(|segment| {
+                let (index, xpub_ref) = match segment.find('=') {
+                    Some(pos) => segment.split_at(pos),
+                    None => (segment, ""),
+                };
+                match index.strip_suffix(&['\'', 'h', 'H'][..]) {
+                    Some(index) if !index.is_empty() => format!("{}{}{}", index, self, xpub_ref),
+                    _ => segment.to_owned(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+/// Algebraic operations on derivation paths, used for re-mapping key origin
+/// information between different accounts
+pub trait DerivationPathAlgebra: Sized {
+    /// Returns the longest common prefix of two derivation paths
+    fn common_prefix(&self, other: &Self) -> Self;
+
+    /// Returns remainder of the derivation path after a given `prefix`, or
+    /// errors if the path does not start with the `prefix`.
+    fn strip_prefix(&self, prefix: &Self) -> Result<Self, PathError>;
+
+    /// Concatenates two derivation paths, checking that the resulting path
+    /// does not exceed [`MAX_DERIVATION_DEPTH`].
+    fn checked_concat(&self, other: &Self) -> Result<Self, PathError>;
+
+    /// Replaces `from` account prefix of the derivation path with `onto`
+    /// prefix, keeping the rest (terminal part) of the path unchanged.
+    fn rebase(&self, from: &Self, onto: &Self) -> Result<Self, PathError> {
+        onto.checked_concat(&self.strip_prefix(from)?)
+    }
+
+    /// Formats derivation path using a given notation for the hardened
+    /// segments
+    fn to_string_with(&self, notation: HardenedNotation) -> String;
+}
+
+fn common_prefix_len<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn checked_concat<T: Clone>(a: &[T], b: &[T]) -> Result<Vec<T>, PathError> {
+    let depth = a.len() + b.len();
+    if depth > MAX_DERIVATION_DEPTH {
+        return Err(PathError::DepthOverflow(depth));
+    }
+    Ok(a.iter().chain(b).cloned().collect())
+}
+
+impl<Segment> DerivationPathAlgebra for DerivationSubpath<Segment>
+where
+    Segment: SegmentIndexes + Display,
+{
+    fn common_prefix(&self, other: &Self) -> Self {
+        self[..common_prefix_len(self.as_slice(), other.as_slice())].into()
+    }
+
+    fn strip_prefix(&self, prefix: &Self) -> Result<Self, PathError> {
+        self.0
+            .strip_prefix(prefix.as_slice())
+            .map(Self::from)
+            .ok_or(PathError::PrefixMismatch)
+    }
+
+    fn checked_concat(&self, other: &Self) -> Result<Self, PathError> {
+        checked_concat(self.as_slice(), other.as_slice()).map(Self)
+    }
+
+    fn to_string_with(&self, notation: HardenedNotation) -> String {
+        notation.apply(&self.to_string())
+    }
+}
+
+impl DerivationPathAlgebra for DerivationPath {
+    fn common_prefix(&self, other: &Self) -> Self {
+        let len = common_prefix_len(self.as_ref(), other.as_ref());
+        self.as_ref()[..len].to_vec().into()
+    }
+
+    fn strip_prefix(&self, prefix: &Self) -> Result<Self, PathError> {
+        self.as_ref()
+            .strip_prefix(prefix.as_ref())
+            .map(|path| path.to_vec().into())
+            .ok_or(PathError::PrefixMismatch)
+    }
+
+    fn checked_concat(&self, other: &Self) -> Result<Self, PathError> {
+        checked_concat(self.as_ref(), other.as_ref()).map(DerivationPath::from)
+    }
+
+    fn to_string_with(&self, notation: HardenedNotation) -> String {
+        notation.apply(&self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{AccountStep, HardenedIndex};
+
+    #[test]
+    fn path_algebra() {
+        let origin = DerivationPath::from_str("m/48'/0'/0'/2'/0/5").unwrap();
+        let account = DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
+        let cosigner = DerivationPath::from_str("m/48'/0'/1'/2'").unwrap();
+
+        assert_eq!(
+            origin.common_prefix(&cosigner),
+            DerivationPath::from_str("m/48'/0'").unwrap()
+        );
+        assert_eq!(
+            origin.strip_prefix(&account),
+            Ok(DerivationPath::from_str("m/0/5").unwrap())
+        );
+        assert_eq!(
+            origin.rebase(&account, &cosigner),
+            Ok(DerivationPath::from_str("m/48'/0'/1'/2'/0/5").unwrap())
+        );
+        assert_eq!(
+            origin.rebase(&cosigner, &account),
+            Err(PathError::PrefixMismatch)
+        );
+
+        let deep = DerivationPath::from(vec![bip32::ChildNumber::Normal { index: 0 }; 200]);
+        assert_eq!(
+            deep.checked_concat(&deep),
+            Err(PathError::DepthOverflow(400))
+        );
+
+        assert_eq!(
+            origin.to_string_with(HardenedNotation::Letter),
+            "m/48h/0h/0h/2h/0/5"
+        );
+        let subpath = DerivationSubpath::<AccountStep>::from_str("/84h/0h/1h=[d34db33f]").unwrap();
+        assert_eq!(
+            subpath.to_string_with(HardenedNotation::Apostrophe),
+            "/84'/0'/1'=[d34db33f]"
+        );
+
+        let hardened = DerivationSubpath::<HardenedIndex>::from_str("/84h/0h/0h").unwrap();
+        let other = DerivationSubpath::<HardenedIndex>::from_str("/84h/1h").unwrap();
+        assert_eq!(hardened.common_prefix(&other).len(), 1);
+    }
+}
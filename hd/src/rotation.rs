@@ -0,0 +1,286 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Account key rotation: supersession of an account extended public key (for
+//! instance, after its compromise) by a new one, starting from a given index
+//! of the last terminal derivation step.
+//!
+//! Keys with indexes below the rotation boundary are still derived from the
+//! superseded account, while keys at and above the boundary are derived from
+//! the new account. The new account may be superseded itself, forming a chain
+//! of rotations with increasing boundaries.
+
+use std::collections::BTreeMap;
+
+use bitcoin::util::bip32::Fingerprint;
+#[cfg(feature = "miniscript")]
+use miniscript::{translate_hash_clone, Descriptor, ForEachKey, TranslatePk, Translator};
+
+use crate::{DerivationAccount, SegmentIndexes, UnhardenedIndex};
+
+/// Errors registering account key rotations
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum RotationError {
+    /// account key {0} is already superseded by another key
+    AlreadySuperseded(Fingerprint),
+
+    /// account key {0} was already used by the rotated account and can't
+    /// supersede it
+    KeyReuse(Fingerprint),
+
+    /// rotation boundary {boundary} must exceed boundary {previous} at which
+    /// the rotated account key came into use
+    BoundaryOrder {
+        /// Boundary of the new rotation
+        boundary: UnhardenedIndex,
+        /// Boundary of the rotation introducing the superseded key
+        previous: UnhardenedIndex,
+    },
+}
+
+/// Supersession of an account extended public key by a new account
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct KeyRotation {
+    /// First index of the last terminal derivation step which is derived
+    /// using the new account
+    pub boundary: UnhardenedIndex,
+
+    /// Account superseding the old one
+    pub account: DerivationAccount,
+}
+
+/// Set of account key rotations, indexed by the fingerprint of the
+/// superseded account extended public key.
+#[derive(Wrapper, Clone, PartialEq, Eq, Hash, Debug, Default, From)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct KeyRotations(BTreeMap<Fingerprint, KeyRotation>);
+
+impl KeyRotations {
+    /// Detects whether there are no key rotations
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Returns rotation superseding account key with a given fingerprint, if
+    /// any
+    #[inline]
+    pub fn rotation(&self, superseded: Fingerprint) -> Option<&KeyRotation> {
+        self.0.get(&superseded)
+    }
+
+    /// Supersedes the `account` key with the key of the `new` account for all
+    /// derivation indexes starting from `boundary`.
+    ///
+    /// Errors if the account key is already superseded, if the new account
+    /// key was already used in the rotation chain of the account, or if the
+    /// boundary does not exceed the boundary at which the account key came
+    /// into use.
+    pub fn supersede(
+        &mut self,
+        account: &DerivationAccount,
+        boundary: impl Into<UnhardenedIndex>,
+        new: DerivationAccount,
+    ) -> Result<(), RotationError> {
+        let boundary = boundary.into();
+        let superseded = account.account_fingerprint();
+        if self.0.contains_key(&superseded) {
+            return Err(RotationError::AlreadySuperseded(superseded));
+        }
+        let fingerprint = new.account_fingerprint();
+        if fingerprint == superseded || self.0.contains_key(&fingerprint) {
+            return Err(RotationError::KeyReuse(fingerprint));
+        }
+        if let Some(previous) = self
+            .0
+            .values()
+            .find(|rotation| rotation.account.account_fingerprint() == superseded)
+            .map(|rotation| rotation.boundary)
+        {
+            if boundary <= previous {
+                return Err(RotationError::BoundaryOrder { boundary, previous });
+            }
+        }
+        self.0.insert(superseded, KeyRotation {
+            boundary,
+            account: new,
+        });
+        Ok(())
+    }
+
+    /// Returns all versions of the `account`, starting with the account itself
+    /// and followed by the accounts superseding it, together with the
+    /// boundary starting from which each version is used.
+    pub fn account_versions<'account>(
+        &'account self,
+        account: &'account DerivationAccount,
+    ) -> Vec<(UnhardenedIndex, &'account DerivationAccount)> {
+        let mut versions = vec![(UnhardenedIndex::zero(), account)];
+        let mut current = account;
+        // Length limit protects from cycles in the decoded data
+        while versions.len() <= self.0.len() {
+            match self.0.get(&current.account_fingerprint()) {
+                Some(rotation) => {
+                    current = &rotation.account;
+                    versions.push((rotation.boundary, current));
+                }
+                None => break,
+            }
+        }
+        versions
+    }
+
+    /// Returns version of the `account` used for key derivation at a given
+    /// `index` of the last terminal derivation step.
+    pub fn effective_account<'account>(
+        &'account self,
+        account: &'account DerivationAccount,
+        index: impl Into<UnhardenedIndex>,
+    ) -> &'account DerivationAccount {
+        let index = index.into();
+        self.account_versions(account)
+            .into_iter()
+            .take_while(|(boundary, _)| *boundary <= index)
+            .last()
+            .map(|(_, account)| account)
+            .unwrap_or(account)
+    }
+}
+
+#[cfg(feature = "miniscript")]
+struct EffectiveKeys<'a>(&'a KeyRotations, UnhardenedIndex);
+
+#[cfg(feature = "miniscript")]
+impl<'a> Translator<DerivationAccount, DerivationAccount, ()> for EffectiveKeys<'a> {
+    fn pk(&mut self, pk: &DerivationAccount) -> Result<DerivationAccount, ()> {
+        Ok(self.0.effective_account(pk, self.1).clone())
+    }
+
+    translate_hash_clone!(DerivationAccount, DerivationAccount, ());
+}
+
+#[cfg(feature = "miniscript")]
+impl KeyRotations {
+    /// Returns version of the `descriptor` with all keys replaced by the
+    /// account versions used at a given `index` of the last terminal
+    /// derivation step.
+    pub fn effective_descriptor(
+        &self,
+        descriptor: &Descriptor<DerivationAccount>,
+        index: impl Into<UnhardenedIndex>,
+    ) -> Descriptor<DerivationAccount> {
+        descriptor
+            .translate_pk(&mut EffectiveKeys(self, index.into()))
+            .expect("infallible key translation")
+    }
+
+    /// Returns all versions of the `descriptor`, produced by key rotations of
+    /// its accounts, together with the boundary starting from which each
+    /// version is used. The first version always starts from index zero and
+    /// matches the original descriptor.
+    pub fn descriptor_versions(
+        &self,
+        descriptor: &Descriptor<DerivationAccount>,
+    ) -> Vec<(UnhardenedIndex, Descriptor<DerivationAccount>)> {
+        let mut boundaries = bset![UnhardenedIndex::zero()];
+        descriptor.for_each_key(|account| {
+            boundaries.extend(
+                self.account_versions(account)
+                    .into_iter()
+                    .map(|(boundary, _)| boundary),
+            );
+            true
+        });
+        boundaries
+            .into_iter()
+            .map(|boundary| (boundary, self.effective_descriptor(descriptor, boundary)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::*;
+
+    fn accounts() -> [DerivationAccount; 3] {
+        [
+            "[d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3\
+             No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/0/*",
+            "[d34db33f/84h/0h/1h]xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWb\
+             WMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ/0/*",
+            "[d34db33f/84h/0h/2h]xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjT\
+             Awm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV/0/*",
+        ]
+        .map(|s| DerivationAccount::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn rotation_chain() {
+        let [old, new, newest] = accounts();
+        let mut rotations = KeyRotations::default();
+        assert_eq!(rotations.effective_account(&old, 1000u16), &old);
+
+        rotations.supersede(&old, 100u16, new.clone()).unwrap();
+        assert_eq!(
+            rotations.supersede(&old, 200u16, newest.clone()),
+            Err(RotationError::AlreadySuperseded(old.account_fingerprint()))
+        );
+        assert_eq!(
+            rotations.supersede(&new, 200u16, old.clone()),
+            Err(RotationError::KeyReuse(old.account_fingerprint()))
+        );
+        assert_eq!(
+            rotations.supersede(&new, 50u16, newest.clone()),
+            Err(RotationError::BoundaryOrder {
+                boundary: UnhardenedIndex::from(50u16),
+                previous: UnhardenedIndex::from(100u16)
+            })
+        );
+        rotations.supersede(&new, 200u16, newest.clone()).unwrap();
+
+        assert_eq!(rotations.effective_account(&old, 99u16), &old);
+        assert_eq!(rotations.effective_account(&old, 100u16), &new);
+        assert_eq!(rotations.effective_account(&old, 199u16), &new);
+        assert_eq!(rotations.effective_account(&old, 200u16), &newest);
+        assert_eq!(rotations.account_versions(&old).len(), 3);
+
+        let data = rotations.strict_serialize().unwrap();
+        assert_eq!(KeyRotations::strict_deserialize(data).unwrap(), rotations);
+    }
+
+    #[cfg(feature = "miniscript")]
+    #[test]
+    fn descriptor_versions() {
+        let [old, new, _] = accounts();
+        let mut rotations = KeyRotations::default();
+        let descriptor = Descriptor::new_wpkh(old.clone()).unwrap();
+        assert_eq!(rotations.descriptor_versions(&descriptor), vec![(
+            UnhardenedIndex::zero(),
+            descriptor.clone()
+        )]);
+
+        rotations.supersede(&old, 100u16, new.clone()).unwrap();
+        let rotated = Descriptor::new_wpkh(new).unwrap();
+        assert_eq!(
+            rotations.effective_descriptor(&descriptor, 99u16),
+            descriptor
+        );
+        assert_eq!(rotations.effective_descriptor(&descriptor, 100u16), rotated);
+        assert_eq!(rotations.descriptor_versions(&descriptor), vec![
+            (UnhardenedIndex::zero(), descriptor),
+            (UnhardenedIndex::from(100u16), rotated)
+        ]);
+    }
+}
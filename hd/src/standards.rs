@@ -13,7 +13,8 @@
 
 use core::str::FromStr;
 
-use bitcoin::util::bip32::{ChildNumber, DerivationPath};
+use bitcoin::secp256k1::{Secp256k1, Signing};
+use bitcoin::util::bip32::{self, ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey};
 use bitcoin::Network;
 #[cfg(feature = "miniscript")]
 pub use miniscript::descriptor::DescriptorType;
@@ -620,3 +621,138 @@ pub enum DescriptorType {
     /// Tr Descriptor
     Tr,
 }
+
+/// Errors discovering wallet accounts with [`discover_accounts`]
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DiscoveryError<E: std::error::Error> {
+    /// derivation standard {0} does not define account-level keys
+    NoAccounts(Bip43),
+
+    /// unable to derive account key: {0}
+    Derivation(bip32::Error),
+
+    /// unable to check account usage: {0}
+    Resolver(E),
+}
+
+/// Resolver checking whether wallet accounts were used on-chain, required for
+/// account discovery with [`discover_accounts`].
+pub trait ResolveAccountUsage {
+    /// Error returned by the resolver
+    type Error: std::error::Error;
+
+    /// Checks whether an account with a given derivation path and extended
+    /// public key was used, i.e. whether any of the account addresses within
+    /// the address gap limit has transaction history.
+    fn is_account_used(
+        &mut self,
+        derivation: &DerivationPath,
+        account_xpub: &ExtendedPubKey,
+    ) -> Result<bool, Self::Error>;
+}
+
+impl<F, E> ResolveAccountUsage for F
+where
+    F: FnMut(&DerivationPath, &ExtendedPubKey) -> Result<bool, E>,
+    E: std::error::Error,
+{
+    type Error = E;
+
+    fn is_account_used(
+        &mut self,
+        derivation: &DerivationPath,
+        account_xpub: &ExtendedPubKey,
+    ) -> Result<bool, Self::Error> {
+        self(derivation, account_xpub)
+    }
+}
+
+/// Discovers used wallet accounts following BIP-44 account discovery
+/// procedure, generalized to all BIP-43-based standards with account-level
+/// keys.
+///
+/// Account indexes are checked with `resolver` sequentially starting from
+/// zero; the discovery stops after `gap_limit` consecutive unused accounts
+/// (BIP-44 uses gap limit of one, i.e. stops at the first unused account).
+/// Since account keys are hardened, discovery requires master extended
+/// private key.
+///
+/// # Returns
+///
+/// Derivation paths of all used accounts, ordered by the account index.
+pub fn discover_accounts<C: Signing, R: ResolveAccountUsage>(
+    secp: &Secp256k1<C>,
+    master: &ExtendedPrivKey,
+    scheme: &Bip43,
+    blockchain: DerivationBlockchain,
+    gap_limit: u32,
+    resolver: &mut R,
+) -> Result<Vec<DerivationPath>, DiscoveryError<R::Error>> {
+    if scheme.account_depth().is_none() {
+        return Err(DiscoveryError::NoAccounts(scheme.clone()));
+    }
+
+    let mut used = vec![];
+    let mut gap = 0u32;
+    let mut account = HardenedIndex::zero();
+    while gap < gap_limit {
+        let derivation = scheme.to_account_derivation(account.into(), blockchain);
+        let account_xpriv = master
+            .derive_priv(secp, &derivation)
+            .map_err(DiscoveryError::Derivation)?;
+        let account_xpub = ExtendedPubKey::from_priv(secp, &account_xpriv);
+        if resolver
+            .is_account_used(&derivation, &account_xpub)
+            .map_err(DiscoveryError::Resolver)?
+        {
+            used.push(derivation);
+            gap = 0;
+        } else {
+            gap += 1;
+        }
+        if account.checked_inc_assign().is_none() {
+            break;
+        }
+    }
+    Ok(used)
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    #[test]
+    fn account_discovery() {
+        let secp = Secp256k1::new();
+        let master = ExtendedPrivKey::new_master(Network::Testnet, &[0x42; 32]).unwrap();
+        let used_accounts = [0u32, 1, 3, 6];
+
+        let mut checked = vec![];
+        let mut resolver = |derivation: &DerivationPath, _: &ExtendedPubKey| {
+            let account = HardenedIndex::try_from(derivation[3])
+                .unwrap()
+                .first_index();
+            checked.push(account);
+            Ok::<_, Infallible>(used_accounts.contains(&account))
+        };
+
+        let scheme = Bip43::singlesig_segwit0();
+        let blockchain = DerivationBlockchain::Testnet;
+        let used =
+            discover_accounts(&secp, &master, &scheme, blockchain, 2, &mut resolver).unwrap();
+        // Account #6 is behind the gap of two unused accounts
+        assert_eq!(used, vec![
+            scheme.to_account_derivation(HardenedIndex::from(0u8).into(), blockchain),
+            scheme.to_account_derivation(HardenedIndex::from(1u8).into(), blockchain),
+            scheme.to_account_derivation(HardenedIndex::from(3u8).into(), blockchain),
+        ]);
+        assert_eq!(
+            discover_accounts(&secp, &master, &Bip43::Bip45, blockchain, 1, &mut resolver),
+            Err(DiscoveryError::NoAccounts(Bip43::Bip45))
+        );
+        assert_eq!(checked, vec![0, 1, 2, 3, 4, 5]);
+    }
+}
@@ -9,14 +9,101 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/Apache-2.0>.
 
+//! Provably unspendable keys, which can be used as taproot internal keys for
+//! script-only spending conditions.
+//!
+//! Following BIP-341 recommendation, unspendable keys are constructed as
+//! `H + r·G`, where `H` is a "nothing up my sleeve" (NUMS) point with unknown
+//! discrete logarithm and `r` is a tweak. Revealing `r` allows third parties
+//! to verify that the key is unspendable, while different `r` values prevent
+//! linking different script-only outputs by their internal key.
+
 use bitcoin::hashes::{sha256, Hash};
 use bitcoin::util::bip32::ExtendedPubKey;
-use secp256k1::{PublicKey, SECP256K1};
+use bitcoin::XOnlyPublicKey;
+#[cfg(feature = "miniscript")]
+use miniscript::descriptor::TapTree;
+#[cfg(feature = "miniscript")]
+use miniscript::{Descriptor, MiniscriptKey};
+use secp256k1::{PublicKey, Scalar, SECP256K1};
 
 use crate::{DerivationAccount, DerivationSubpath, TerminalStep, XpubRef};
 
+/// X coordinate of the NUMS point `H` defined in BIP-341, which is the SHA256
+/// hash of the uncompressed secp256k1 generator point `G` encoding
+pub const BIP341_NUMS_POINT: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// Provably unspendable public key `H + r·G`, recording tweak `r`, which allows
+/// third parties to verify its unspendability.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct UnspendableKey {
+    tweak: [u8; 32],
+    key: PublicKey,
+}
+
+impl Default for UnspendableKey {
+    /// Constructs unspendable key with the tweak equal to the SHA256 hash of
+    /// the NUMS point `H` x coordinate.
+    fn default() -> Self { UnspendableKey::with_hash_tweak(BIP341_NUMS_POINT) }
+}
+
+impl UnspendableKey {
+    /// Returns NUMS point `H` with even Y coordinate
+    pub fn nums_point() -> PublicKey {
+        let mut data = [0x02u8; 33];
+        data[1..].copy_from_slice(&BIP341_NUMS_POINT);
+        PublicKey::from_slice(&data).expect("BIP-341 NUMS point is a valid curve point")
+    }
+
+    /// Constructs unspendable key using a given tweak value. Errors if the
+    /// tweak exceeds curve order.
+    pub fn with_tweak(tweak: [u8; 32]) -> Result<UnspendableKey, secp256k1::Error> {
+        let scalar = Scalar::from_be_bytes(tweak).map_err(|_| secp256k1::Error::InvalidTweak)?;
+        let key = UnspendableKey::nums_point().add_exp_tweak(SECP256K1, &scalar)?;
+        Ok(UnspendableKey { tweak, key })
+    }
+
+    /// Constructs unspendable key using SHA256 hash of the provided `data` as
+    /// a tweak. Using different data (like the script tree merkle root) for
+    /// different outputs makes their internal keys unlinkable.
+    pub fn with_hash_tweak(data: impl AsRef<[u8]>) -> UnspendableKey {
+        let tweak = sha256::Hash::hash(data.as_ref()).into_inner();
+        UnspendableKey::with_tweak(tweak).expect("negligible probability")
+    }
+
+    /// Returns tweak `r` used to construct the key
+    #[inline]
+    pub fn tweak(&self) -> [u8; 32] { self.tweak }
+
+    /// Returns unspendable public key
+    #[inline]
+    pub fn public_key(&self) -> PublicKey { self.key }
+
+    /// Returns unspendable x-only public key for use as taproot internal key
+    #[inline]
+    pub fn x_only_public_key(&self) -> XOnlyPublicKey { self.key.x_only_public_key().0 }
+
+    /// Verifies that a given x-only public key is the unspendable key
+    /// constructed with a given `tweak`.
+    pub fn verify(key: XOnlyPublicKey, tweak: [u8; 32]) -> bool {
+        UnspendableKey::with_tweak(tweak)
+            .map(|unspendable| unspendable.x_only_public_key() == key)
+            .unwrap_or_default()
+    }
+}
+
 /// Extension trait for types containing EC keys, which can be made provably
 /// unspendable
+///
+/// The keys produced by this trait are kept unchanged since the first
+/// versions of the library, such that descriptors created with them continue
+/// to derive the same addresses. They are computed as `G + sha256(G)·G`, i.e.
+/// are not based on a NUMS point and have a publicly known discrete
+/// logarithm; new script-only descriptors should use [`UnspendableKey`] via
+/// [`FromUnspendableKey`] or [`tr_script_only`] instead.
 pub trait UnsatisfiableKey {
     /// A parameter supplied to [`UnsatisfiableKey::unsatisfiable_key`], like an
     /// information on the use of testnet for extended keys, or derivation path
@@ -27,6 +114,14 @@ pub trait UnsatisfiableKey {
     fn unsatisfiable_key(_: Self::Param) -> Self;
 }
 
+/// Extension trait for types containing EC keys, which can be constructed
+/// from a BIP-341 NUMS-based [`UnspendableKey`]
+pub trait FromUnspendableKey: UnsatisfiableKey {
+    /// Constructs key from the provided unspendable key using parameter of
+    /// the same meaning as in [`UnsatisfiableKey::unsatisfiable_key`]
+    fn from_unspendable_key(key: UnspendableKey, param: Self::Param) -> Self;
+}
+
 impl UnsatisfiableKey for PublicKey {
     type Param = ();
 
@@ -41,22 +136,63 @@ impl UnsatisfiableKey for PublicKey {
     }
 }
 
+impl FromUnspendableKey for PublicKey {
+    fn from_unspendable_key(key: UnspendableKey, _: Self::Param) -> Self { key.public_key() }
+}
+
+impl UnsatisfiableKey for XOnlyPublicKey {
+    type Param = ();
+
+    /// Returns x-only version of [`PublicKey::unsatisfiable_key`]
+    fn unsatisfiable_key(_: Self::Param) -> Self {
+        PublicKey::unsatisfiable_key(()).x_only_public_key().0
+    }
+}
+
+impl FromUnspendableKey for XOnlyPublicKey {
+    fn from_unspendable_key(key: UnspendableKey, _: Self::Param) -> Self { key.x_only_public_key() }
+}
+
+/// Constructs extended public key with zero depth, fingerprint, child number
+/// and chain code, using a given public key
+fn xpub_with_key(key: PublicKey, testnet: bool) -> ExtendedPubKey {
+    let mut buf = Vec::with_capacity(78);
+    buf.extend(if testnet {
+        [0x04u8, 0x35, 0x87, 0xCF]
+    } else {
+        [0x04u8, 0x88, 0xB2, 0x1E]
+    });
+    buf.extend([0u8; 5]); // depth + fingerprint
+    buf.extend([0u8; 4]); // child no
+    buf.extend(&key.serialize()[1..]);
+    buf.extend(key.serialize());
+    ExtendedPubKey::decode(&buf).expect("broken unspendable key construction")
+}
+
 impl UnsatisfiableKey for ExtendedPubKey {
     type Param = bool;
 
     fn unsatisfiable_key(testnet: Self::Param) -> Self {
-        let unspendable_key = PublicKey::unsatisfiable_key(());
-        let mut buf = Vec::with_capacity(78);
-        buf.extend(if testnet {
-            [0x04u8, 0x35, 0x87, 0xCF]
-        } else {
-            [0x04u8, 0x88, 0xB2, 0x1E]
-        });
-        buf.extend([0u8; 5]); // depth + fingerprint
-        buf.extend([0u8; 4]); // child no
-        buf.extend(&unspendable_key.serialize()[1..]);
-        buf.extend(unspendable_key.serialize());
-        ExtendedPubKey::decode(&buf).expect("broken unspendable key construction")
+        xpub_with_key(PublicKey::unsatisfiable_key(()), testnet)
+    }
+}
+
+impl FromUnspendableKey for ExtendedPubKey {
+    fn from_unspendable_key(key: UnspendableKey, testnet: Self::Param) -> Self {
+        xpub_with_key(key.public_key(), testnet)
+    }
+}
+
+fn unspendable_account(
+    account_xpub: ExtendedPubKey,
+    terminal_path: DerivationSubpath<TerminalStep>,
+) -> DerivationAccount {
+    DerivationAccount {
+        master: XpubRef::Unknown,
+        account_path: empty!(),
+        account_xpub,
+        revocation_seal: None,
+        terminal_path,
     }
 }
 
@@ -65,12 +201,120 @@ impl UnsatisfiableKey for DerivationAccount {
 
     fn unsatisfiable_key(param: Self::Param) -> Self {
         let (testnet, terminal_path) = param;
-        DerivationAccount {
-            master: XpubRef::Unknown,
-            account_path: empty!(),
-            account_xpub: ExtendedPubKey::unsatisfiable_key(testnet),
-            revocation_seal: None,
+        unspendable_account(ExtendedPubKey::unsatisfiable_key(testnet), terminal_path)
+    }
+}
+
+impl FromUnspendableKey for DerivationAccount {
+    fn from_unspendable_key(key: UnspendableKey, param: Self::Param) -> Self {
+        let (testnet, terminal_path) = param;
+        unspendable_account(
+            ExtendedPubKey::from_unspendable_key(key, testnet),
             terminal_path,
+        )
+    }
+}
+
+/// Constructs taproot descriptor which can be spent only by the script path,
+/// using a given BIP-341 NUMS-based unspendable `key` as the internal key,
+/// converted with [`FromUnspendableKey::from_unspendable_key`] and a given
+/// `param`.
+#[cfg(feature = "miniscript")]
+pub fn tr_script_only<Pk>(
+    tree: TapTree<Pk>,
+    key: UnspendableKey,
+    param: Pk::Param,
+) -> Result<Descriptor<Pk>, miniscript::Error>
+where
+    Pk: MiniscriptKey + FromUnspendableKey,
+{
+    Descriptor::new_tr(Pk::from_unspendable_key(key, param), Some(tree))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unspendable_key() {
+        let nums = UnspendableKey::nums_point();
+        let generator = PublicKey::from_secret_key(SECP256K1, &secp256k1::ONE_KEY);
+        assert_eq!(
+            sha256::Hash::hash(&generator.serialize_uncompressed()).into_inner(),
+            BIP341_NUMS_POINT
+        );
+        assert_eq!(nums.x_only_public_key().0.serialize(), BIP341_NUMS_POINT);
+
+        let key = UnspendableKey::default();
+        assert_eq!(
+            key.tweak(),
+            sha256::Hash::hash(&BIP341_NUMS_POINT).into_inner()
+        );
+        assert!(UnspendableKey::verify(key.x_only_public_key(), key.tweak()));
+        assert!(!UnspendableKey::verify(key.x_only_public_key(), [1u8; 32]));
+        assert_eq!(
+            XOnlyPublicKey::from_unspendable_key(key, ()),
+            key.x_only_public_key()
+        );
+        assert_eq!(
+            ExtendedPubKey::from_unspendable_key(key, false).public_key,
+            key.public_key()
+        );
+
+        let other = UnspendableKey::with_hash_tweak(b"merkle root");
+        assert_ne!(other.x_only_public_key(), key.x_only_public_key());
+        assert!(UnspendableKey::verify(
+            other.x_only_public_key(),
+            other.tweak()
+        ));
+    }
+
+    #[test]
+    fn legacy_unsatisfiable_key() {
+        // Keys used by existing descriptors must not change
+        let key = PublicKey::unsatisfiable_key(());
+        assert_eq!(
+            key.to_string(),
+            "02283577966d15ac2ff90be1cf0a3364fe1228de54d7dd2e3496a457c5a9f9c3af"
+        );
+        assert_ne!(key, UnspendableKey::default().public_key());
+        assert_eq!(
+            XOnlyPublicKey::unsatisfiable_key(()),
+            key.x_only_public_key().0
+        );
+        assert_eq!(ExtendedPubKey::unsatisfiable_key(true).public_key, key);
+        let account = DerivationAccount::unsatisfiable_key((false, empty!()));
+        assert_eq!(
+            account.account_xpub,
+            ExtendedPubKey::unsatisfiable_key(false)
+        );
+        let account =
+            DerivationAccount::from_unspendable_key(UnspendableKey::default(), (false, empty!()));
+        assert_eq!(
+            account.account_xpub.public_key,
+            UnspendableKey::default().public_key()
+        );
+    }
+
+    #[cfg(feature = "miniscript")]
+    #[test]
+    fn script_only_descriptor() {
+        use std::str::FromStr;
+        use std::sync::Arc;
+
+        use miniscript::{Miniscript, Tap};
+
+        let leaf = Miniscript::<XOnlyPublicKey, Tap>::from_str(
+            "pk(79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798)",
+        )
+        .unwrap();
+        let key = UnspendableKey::with_hash_tweak(b"merkle root");
+        let descriptor = tr_script_only(TapTree::Leaf(Arc::new(leaf)), key, ()).unwrap();
+        match descriptor {
+            Descriptor::Tr(tr) => {
+                assert_eq!(*tr.internal_key(), key.x_only_public_key())
+            }
+            _ => panic!("taproot descriptor expected"),
         }
     }
 }
@@ -15,12 +15,15 @@ use std::str::FromStr;
 
 use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::{PublicKey, Secp256k1, VerifyOnly};
-use bitcoin::util::bip32;
 use bitcoin::util::bip32::{ChainCode, ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint};
+use bitcoin::util::{base58, bip32};
 use bitcoin::XpubIdentifier;
 use slip132::{DefaultResolver, FromSlip132, KeyVersion};
 
-use crate::{DerivationStandard, HardenedIndex, SegmentIndexes, UnhardenedIndex};
+use crate::{
+    DerivationStandard, DerivationSubpath, HardenedIndex, HardenedNotation, SegmentIndexes,
+    TerminalStep, UnhardenedIndex,
+};
 
 /// Errors constructing [`XpubOrigin`].
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error)]
@@ -363,6 +366,15 @@ where
     standard: Option<Standard>,
     #[getter(as_copy, as_mut)]
     account: Option<HardenedIndex>,
+
+    #[getter(as_ref)]
+    origin_path: Option<DerivationPath>,
+    #[getter(as_ref)]
+    terminal_path: DerivationSubpath<TerminalStep>,
+    #[getter(as_copy)]
+    slip: Option<KeyVersion>,
+    #[getter(as_copy, as_mut)]
+    notation: HardenedNotation,
 }
 
 /// Error parsing [`XpubDescriptor`] string representation
@@ -382,6 +394,32 @@ pub enum XpubParseError {
     /// Inconsistency error
     #[from]
     Inconsistency(XpubRequirementError),
+
+    /// key origin information is not terminated with `]`
+    #[display(doc_comments)]
+    UnterminatedOrigin,
+
+    /// invalid master key fingerprint `{0}` in key origin information
+    #[display(doc_comments)]
+    InvalidFingerprint(String),
+
+    /// invalid derivation path in key origin information: {0}
+    #[display(doc_comments)]
+    InvalidOriginPath(bip32::Error),
+
+    /// invalid terminal derivation path following the extended key: {0}
+    #[display(doc_comments)]
+    InvalidTerminalPath(bip32::Error),
+
+    /// key origin derivation path has {origin_depth} segments, while the
+    /// extended key has depth {key_depth}
+    #[display(doc_comments)]
+    DepthMismatch {
+        /// Number of segments in the origin derivation path
+        origin_depth: usize,
+        /// Depth of the extended public key
+        key_depth: u8,
+    },
 }
 
 impl<Standard> FromStr for XpubDescriptor<Standard>
@@ -390,18 +428,108 @@ where
 {
     type Err = XpubParseError;
 
+    /// Parses extended public key which may be prefixed with the key origin
+    /// information in `[fingerprint/derivation/path]` form and followed by a
+    /// terminal derivation path, like in `[d34db33f/84h/0h/0h]xpub.../0/*`.
+    /// Hardened segments of the origin path may use both `h` and `'`
+    /// notations; the used notation is preserved by the [`Display`]
+    /// implementation.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // The string here could be just a xpub, slip132 xpub or xpub prefixed
-        // with origin information in a different formats.
-
-        // TODO: Implement `[fp/derivation/path]xpub` processing
         // TODO: Implement `m=[fp]/derivation/path/account=[xpub]` processing
 
-        let xpub = ExtendedPubKey::from_str(s).or_else(|_| ExtendedPubKey::from_slip132_str(s))?;
+        let (origin, rest) = match s.strip_prefix('[') {
+            Some(rest) => {
+                let end = rest.find(']').ok_or(XpubParseError::UnterminatedOrigin)?;
+                (Some(&rest[..end]), &rest[end + 1..])
+            }
+            None => (None, s),
+        };
+        let (key, terminal) = match rest.find('/') {
+            Some(pos) => rest.split_at(pos),
+            None => (rest, ""),
+        };
+
+        let xpub =
+            ExtendedPubKey::from_str(key).or_else(|_| ExtendedPubKey::from_slip132_str(key))?;
+        let slip = KeyVersion::from_xkey_str(key).ok();
 
-        let slip = KeyVersion::from_xkey_str(s).ok();
+        let terminal_path = if terminal.is_empty() {
+            empty!()
+        } else {
+            DerivationSubpath::from_str(terminal).map_err(XpubParseError::InvalidTerminalPath)?
+        };
+
+        let mut xd = match origin {
+            None => XpubDescriptor::with_unchecked(None, xpub, None, slip),
+            Some(origin) => {
+                let (fingerprint, path) = match origin.find('/') {
+                    Some(pos) => (&origin[..pos], &origin[pos..]),
+                    None => (origin, ""),
+                };
+                if fingerprint.len() != 8 {
+                    return Err(XpubParseError::InvalidFingerprint(fingerprint.to_owned()));
+                }
+                let fingerprint = Fingerprint::from_str(fingerprint)
+                    .map_err(|_| XpubParseError::InvalidFingerprint(fingerprint.to_owned()))?;
+                let origin_path = DerivationPath::from_str(&format!("m{}", path))
+                    .map_err(XpubParseError::InvalidOriginPath)?;
+                let origin_depth = origin_path.as_ref().len();
+                if origin_depth > 0 && origin_depth != xpub.depth as usize {
+                    return Err(XpubParseError::DepthMismatch {
+                        origin_depth,
+                        key_depth: xpub.depth,
+                    });
+                }
+
+                // Origin information not matching SLIP-132 key version is
+                // common (for instance, Bitcoin Core always uses `xpub`), so
+                // we do not fail but just leave the standard undetected
+                let deduced = XpubDescriptor::deduce(Some(fingerprint), &origin_path, xpub, slip);
+                let mut xd = match deduced {
+                    Ok(Ok(xd)) => xd,
+                    _ => XpubDescriptor::with_unchecked(Some(fingerprint), xpub, None, slip),
+                };
+                if path.contains(&['h', 'H'][..]) {
+                    xd.notation = HardenedNotation::Letter;
+                } else if path.contains('\'') {
+                    xd.notation = HardenedNotation::Apostrophe;
+                }
+                xd.origin_path = Some(origin_path);
+                xd
+            }
+        };
+        xd.terminal_path = terminal_path;
+        xd.slip = slip;
+
+        Ok(xd)
+    }
+}
 
-        Ok(XpubDescriptor::with_unchecked(None, xpub, None, slip))
+impl<Standard> Display for XpubDescriptor<Standard>
+where
+    Standard: DerivationStandard,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(fingerprint) = self.master_fingerprint {
+            write!(f, "[{}", fingerprint)?;
+            for child in self.origin_path.iter().flat_map(|path| path.as_ref()) {
+                match child {
+                    ChildNumber::Normal { index } => write!(f, "/{}", index)?,
+                    ChildNumber::Hardened { index } => write!(f, "/{}{}", index, self.notation)?,
+                }
+            }
+            f.write_str("]")?;
+        }
+        let xpub = ExtendedPubKey::from(self);
+        match self.slip {
+            Some(slip) => {
+                let mut data = xpub.encode();
+                data[0..4].copy_from_slice(slip.as_slice());
+                f.write_str(&base58::check_encode_slice(&data))?;
+            }
+            None => Display::fmt(&xpub, f)?,
+        }
+        Display::fmt(&self.terminal_path, f)
     }
 }
 
@@ -420,6 +548,10 @@ where
             master_fingerprint: None,
             standard: None,
             account: None,
+            origin_path: None,
+            terminal_path: empty!(),
+            slip: None,
+            notation: HardenedNotation::default(),
         }
     }
 }
@@ -529,6 +661,7 @@ where
         let mut xd = XpubDescriptor::from(xpub);
         xd.standard = standard;
         xd.master_fingerprint = master_fingerprint;
+        xd.slip = slip;
         xd.checked(testnet, slip)?;
         Ok(xd)
     }
@@ -543,6 +676,7 @@ where
         let mut xd = XpubDescriptor::from(xpub);
         xd.standard = standard.clone();
         xd.master_fingerprint = master_fingerprint;
+        xd.slip = slip;
         let origin = XpubOrigin::with_unchecked(master_fingerprint, xpub, standard, slip);
         xd.account = origin.account;
         xd
@@ -620,6 +754,8 @@ where
         xd.standard = origin.standard;
         xd.master_fingerprint = master_fingerprint;
         xd.account = origin.account;
+        xd.origin_path = Some(source.clone());
+        xd.slip = slip;
         Ok(Ok(xd))
     }
 }
@@ -660,3 +796,68 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Bip43;
+
+    const XPUB: &str = "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDF\
+                        GTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5";
+
+    fn parse(s: &str) -> Result<XpubDescriptor<Bip43>, XpubParseError> {
+        XpubDescriptor::from_str(s)
+    }
+
+    #[test]
+    fn origin_string_roundtrip() {
+        for s in [
+            format!("[d34db33f/84h/0h/0h]{}/0/*", XPUB),
+            format!("[d34db33f/84'/0'/0']{}/1/*", XPUB),
+            format!("[d34db33f]{}", XPUB),
+            format!("{}/0/5", XPUB),
+            XPUB.to_owned(),
+        ] {
+            assert_eq!(parse(&s).unwrap().to_string(), s);
+        }
+
+        let xd = parse(&format!("[d34db33f/84h/0h/0h]{}/0/*", XPUB)).unwrap();
+        assert_eq!(
+            xd.master_fingerprint(),
+            Some(Fingerprint::from_str("d34db33f").unwrap())
+        );
+        assert_eq!(
+            xd.origin_path(),
+            Some(&DerivationPath::from_str("m/84'/0'/0'").unwrap())
+        );
+        assert_eq!(xd.terminal_path().len(), 2);
+        assert_eq!(xd.notation(), HardenedNotation::Letter);
+    }
+
+    #[test]
+    fn origin_string_errors() {
+        assert_eq!(
+            parse(&format!("[d34db33f/84h/0h/0h{}", XPUB)),
+            Err(XpubParseError::UnterminatedOrigin)
+        );
+        assert_eq!(
+            parse(&format!("[d34db3/84h/0h/0h]{}", XPUB)),
+            Err(XpubParseError::InvalidFingerprint(s!("d34db3")))
+        );
+        assert!(matches!(
+            parse(&format!("[d34db33f/84x/0h/0h]{}", XPUB)),
+            Err(XpubParseError::InvalidOriginPath(_))
+        ));
+        assert!(matches!(
+            parse(&format!("{}/0/x", XPUB)),
+            Err(XpubParseError::InvalidTerminalPath(_))
+        ));
+        assert_eq!(
+            parse(&format!("[d34db33f/84h/0h]{}", XPUB)),
+            Err(XpubParseError::DepthMismatch {
+                origin_depth: 2,
+                key_depth: 3
+            })
+        );
+    }
+}
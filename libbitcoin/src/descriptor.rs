@@ -0,0 +1,135 @@
+// C library for building descriptor-based bitcoin wallets
+//
+// Written in 2021 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache 2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+use std::ffi::CStr;
+use std::str::FromStr;
+
+use bitcoin::Network;
+use bitcoin_hd::{DerivationAccount, SegmentIndexes, UnhardenedIndex};
+use descriptors::derive::Descriptor as _;
+use libc::c_char;
+use miniscript::Descriptor;
+
+use crate::{error_t, string_result_t, SECP256K1};
+
+pub(crate) unsafe fn parse_descriptor(
+    descriptor: *const c_char,
+) -> Result<Descriptor<DerivationAccount>, error_t> {
+    if descriptor.is_null() {
+        return Err(error_t::null_pointer);
+    }
+    let descriptor = unsafe { CStr::from_ptr(descriptor).to_str()? };
+    let descriptor = Descriptor::<DerivationAccount>::from_str(descriptor)?;
+    descriptor.check_sanity()?;
+    Ok(descriptor)
+}
+
+/// Parses and validates wallet output descriptor, returning its normalized
+/// representation with the checksum.
+#[no_mangle]
+pub unsafe extern "C" fn descriptor_parse(descriptor: *const c_char) -> string_result_t {
+    let descriptor = parse_descriptor(descriptor)?;
+    string_result_t::success(descriptor)
+}
+
+/// Derives address from the wallet descriptor at a given `index`. For
+/// descriptors with two-step derivation pattern `change` flag selects change
+/// (internal) addresses.
+#[no_mangle]
+pub unsafe extern "C" fn descriptor_address(
+    descriptor: *const c_char,
+    change: bool,
+    index: u32,
+    testnet: bool,
+) -> string_result_t {
+    let descriptor = parse_descriptor(descriptor)?;
+    let index = UnhardenedIndex::from_index(index)?;
+    let pat = match descriptor.derive_pattern_len()? {
+        1 if !change => vec![index],
+        2 => vec![UnhardenedIndex::from(u8::from(change)), index],
+        _ => Err(error_t::invalid_derivation_path)?,
+    };
+    let network = if testnet {
+        Network::Testnet
+    } else {
+        Network::Bitcoin
+    };
+    let address = descriptor.address(&SECP256K1, pat, network)?;
+    string_result_t::success(address)
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+    use std::ptr;
+
+    use super::*;
+
+    const XPUB: &str = "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqF\
+                        JPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5";
+
+    fn descriptor(terminal: &str) -> CString {
+        CString::new(format!("wpkh([d34db33f/84h/0h/0h]{}{})", XPUB, terminal)).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let normalized = unsafe { descriptor_parse(descriptor("/*/*").as_ptr()) }
+            .into_result()
+            .unwrap();
+        assert!(normalized.starts_with("wpkh("));
+        assert!(normalized.contains('#'));
+        let normalized_c = CString::new(normalized.clone()).unwrap();
+        assert_eq!(
+            unsafe { descriptor_parse(normalized_c.as_ptr()) }.into_result(),
+            Ok(normalized)
+        );
+
+        let invalid = CString::new("wpkh(invalid)").unwrap();
+        assert_eq!(
+            unsafe { descriptor_parse(invalid.as_ptr()) }.into_result(),
+            Err(error_t::invalid_descriptor)
+        );
+        assert_eq!(
+            unsafe { descriptor_parse(ptr::null()) }.into_result(),
+            Err(error_t::null_pointer)
+        );
+    }
+
+    #[test]
+    fn address() {
+        let descriptor = descriptor("/*/*");
+        let address = |change, index, testnet| {
+            unsafe { descriptor_address(descriptor.as_ptr(), change, index, testnet) }.into_result()
+        };
+
+        let receive = address(false, 0, false).unwrap();
+        let change = address(true, 0, false).unwrap();
+        assert!(receive.starts_with("bc1q"));
+        assert!(change.starts_with("bc1q"));
+        assert_ne!(receive, change);
+        assert_ne!(receive, address(false, 1, false).unwrap());
+        assert!(address(false, 0, true).unwrap().starts_with("tb1q"));
+        assert_eq!(
+            address(false, 0x8000_0000, false),
+            Err(error_t::invalid_derivation_path)
+        );
+
+        let single = descriptor("/0/*");
+        let address =
+            |change| unsafe { descriptor_address(single.as_ptr(), change, 0, false) }.into_result();
+        assert_eq!(address(false), Ok(receive));
+        assert_eq!(address(true), Err(error_t::invalid_derivation_path));
+    }
+}
@@ -22,7 +22,11 @@ extern crate amplify_derive;
 #[macro_use]
 extern crate lazy_static;
 
+mod descriptor;
 pub mod helpers;
 mod signer;
+mod transaction;
 
+pub use descriptor::*;
 pub use signer::*;
+pub use transaction::*;
@@ -21,7 +21,10 @@ use std::str::{FromStr, Utf8Error};
 use bip39::Mnemonic;
 use bitcoin::util::bip32::{self, DerivationPath, Error, ExtendedPrivKey, ExtendedPubKey};
 use bitcoin::Network;
+use bitcoin_hd::DeriveError;
 use libc::c_char;
+use psbt::sign::{MemoryKeyProvider, MemorySigningAccount, SignAll, SignError, SigningPolicy};
+use psbt::{construct, Psbt, PsbtParseError};
 use rand::RngCore;
 
 use crate::helpers::Wipe;
@@ -70,6 +73,34 @@ pub enum error_t {
 
     /// invalid hexadecimal value
     hex,
+
+    /// invalid output descriptor
+    #[from(miniscript::Error)]
+    invalid_descriptor,
+
+    /// unable to derive descriptor scripts
+    #[from(DeriveError)]
+    descriptor_derivation,
+
+    /// invalid PSBT data
+    #[from(PsbtParseError)]
+    invalid_psbt,
+
+    /// invalid JSON transaction description
+    #[from(serde_json::Error)]
+    invalid_json,
+
+    /// unable to construct PSBT
+    #[from(construct::Error)]
+    psbt_construction,
+
+    /// unable to sign PSBT
+    #[from(SignError)]
+    psbt_signing,
+
+    /// unable to finalize PSBT
+    #[from(miniscript::psbt::Error)]
+    psbt_finalization,
 }
 
 impl Default for error_t {
@@ -127,6 +158,17 @@ impl string_result_t {
     }
 
     pub fn is_success(&self) -> bool { self.code == error_t::success }
+
+    /// Takes ownership of the result string, returning it for successful
+    /// results or the error code otherwise
+    #[cfg(test)]
+    pub(crate) fn into_result(self) -> Result<String, error_t> {
+        let data = unsafe { CString::from_raw(self.details.data as *mut c_char) };
+        match self.code {
+            error_t::success => Ok(data.into_string().expect("non-UTF8 result data")),
+            code => Err(code),
+        }
+    }
 }
 
 impl<E> FromResidual<Result<Infallible, E>> for string_result_t
@@ -374,11 +416,45 @@ pub unsafe extern "C" fn bip32_derive_xpub(
     }
 }
 
+/// Signs all PSBT inputs which can be signed with the keys derived from the
+/// master extended private key. Takes base64-encoded PSBT and returns signed
+/// PSBT in the same encoding.
 #[no_mangle]
-pub extern "C" fn psbt_sign(
-    _psbt: *const c_char,
-    _xpriv: *const c_char,
-    _wipe: bool,
+pub unsafe extern "C" fn psbt_sign(
+    psbt: *const c_char,
+    master: *mut c_char,
+    wipe: bool,
 ) -> string_result_t {
-    todo!("#12 psbt_sign")
+    if psbt.is_null() || master.is_null() {
+        Err(error_t::null_pointer)?
+    }
+
+    let mut psbt = Psbt::from_str(unsafe { CStr::from_ptr(psbt).to_str()? })?;
+
+    let master_cstring = unsafe { CString::from_raw(master) };
+    let mut master = ExtendedPrivKey::from_str(master_cstring.to_str()?)?;
+    if wipe {
+        unsafe { master_cstring.wipe() };
+    }
+
+    let master_xpub = ExtendedPubKey::from_priv(&SECP256K1, &master);
+    let account = MemorySigningAccount::with(
+        &SECP256K1,
+        master_xpub.identifier(),
+        DerivationPath::master(),
+        master,
+    );
+    let mut key_provider = MemoryKeyProvider::with(&SECP256K1, false);
+    key_provider.add_account(account);
+    let result = psbt.sign_all(&key_provider, &SigningPolicy::default());
+
+    let ptr = master.private_key.as_mut_ptr();
+    for i in 0..32 {
+        unsafe {
+            *ptr.offset(i) = 0;
+        }
+    }
+
+    result?;
+    string_result_t::success(psbt)
 }
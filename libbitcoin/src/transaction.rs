@@ -0,0 +1,272 @@
+// C library for building descriptor-based bitcoin wallets
+//
+// Written in 2021 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache 2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::str::FromStr;
+
+use bitcoin::consensus::encode::{self, deserialize, serialize};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::{Address, Transaction};
+use bitcoin_hd::{SegmentIndexes, UnhardenedIndex};
+use bitcoin_scripts::PubkeyScript;
+use descriptors::InputDescriptor;
+use libc::c_char;
+use miniscript::psbt::PsbtExt;
+use psbt::Psbt;
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
+
+use crate::descriptor::parse_descriptor;
+use crate::{error_t, string_result_t, SECP256K1};
+
+/// JSON description of the transaction to construct
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct TxDescription {
+    /// Wallet inputs in the input descriptor format (outpoint followed by the
+    /// terminal derivation path and optional sequence number and sighash
+    /// type)
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    inputs: Vec<InputDescriptor>,
+
+    /// Hex-encoded transactions spent by the inputs
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    spent_txs: Vec<HexTx>,
+
+    /// Transaction outputs
+    outputs: Vec<TxOutput>,
+
+    /// Index of the change address
+    change_index: u32,
+
+    /// Transaction fee in satoshis
+    fee: u64,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TxOutput {
+    #[serde_as(as = "DisplayFromStr")]
+    address: Address,
+    amount: u64,
+}
+
+#[derive(Clone, Debug)]
+struct HexTx(Transaction);
+
+impl FromStr for HexTx {
+    type Err = encode::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = Vec::<u8>::from_hex(s)
+            .map_err(|_| encode::Error::ParseFailed("invalid hexadecimal transaction"))?;
+        deserialize(&data).map(HexTx)
+    }
+}
+
+/// Constructs PSBT spending wallet outputs described by the descriptor. The
+/// transaction is given as a JSON object with the following fields:
+/// - `inputs`: array of input descriptor strings (`<txid>:<vout> <terminal>`);
+/// - `spentTxs`: array of hex-encoded transactions spent by the inputs;
+/// - `outputs`: array of objects with `address` and `amount` (in sats);
+/// - `changeIndex`: index of the change address;
+/// - `fee`: transaction fee in sats.
+///
+/// Returns base64-encoded PSBT.
+#[no_mangle]
+pub unsafe extern "C" fn psbt_construct(
+    descriptor: *const c_char,
+    tx_json: *const c_char,
+) -> string_result_t {
+    let descriptor = parse_descriptor(descriptor)?;
+    if tx_json.is_null() {
+        Err(error_t::null_pointer)?
+    }
+    let tx_json = unsafe { CStr::from_ptr(tx_json).to_str()? };
+    let tx: TxDescription = serde_json::from_str(tx_json)?;
+
+    let tx_resolver = tx
+        .spent_txs
+        .into_iter()
+        .map(|HexTx(tx)| (tx.txid(), tx))
+        .collect::<BTreeMap<_, _>>();
+    let outputs = tx
+        .outputs
+        .into_iter()
+        .map(|output| {
+            (
+                PubkeyScript::from(output.address.script_pubkey()),
+                output.amount,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let psbt = Psbt::construct(
+        &descriptor,
+        &tx.inputs,
+        &outputs,
+        UnhardenedIndex::from_index(tx.change_index)?,
+        tx.fee,
+        &tx_resolver,
+    )?;
+    string_result_t::success(psbt)
+}
+
+/// Finalizes all inputs of a fully signed base64-encoded PSBT and returns
+/// hex-encoded signed transaction ready for publication.
+#[no_mangle]
+pub unsafe extern "C" fn psbt_finalize(psbt: *const c_char) -> string_result_t {
+    if psbt.is_null() {
+        Err(error_t::null_pointer)?
+    }
+    let psbt = Psbt::from_str(unsafe { CStr::from_ptr(psbt).to_str()? })?;
+    let mut psbt = PartiallySignedTransaction::from(psbt);
+    for index in 0..psbt.inputs.len() {
+        let input = &psbt.inputs[index];
+        // Inputs satisfied by external systems are already final
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+        psbt.finalize_inp_mut(&SECP256K1, index)?;
+    }
+    let tx = psbt.extract_tx();
+    string_result_t::success(serialize(&tx).to_hex())
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CString;
+    use std::ptr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+    use bitcoin::{Network, OutPoint, PackedLockTime, TxIn, TxOut, Txid};
+    use serde_json::json;
+
+    use super::*;
+    use crate::{descriptor_address, psbt_sign};
+
+    fn wallet() -> (ExtendedPrivKey, CString) {
+        let master = ExtendedPrivKey::new_master(Network::Testnet, &[0x07; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let xpriv = master.derive_priv(&SECP256K1, &path).unwrap();
+        let xpub = ExtendedPubKey::from_priv(&SECP256K1, &xpriv);
+        let fingerprint = master.fingerprint(&SECP256K1);
+        let descriptor = format!("wpkh([{}/84h/1h/0h]{}/*/*)", fingerprint, xpub);
+        (master, CString::new(descriptor).unwrap())
+    }
+
+    fn address(descriptor: &CString, index: u32) -> Address {
+        let address = unsafe { descriptor_address(descriptor.as_ptr(), false, index, true) }
+            .into_result()
+            .unwrap();
+        Address::from_str(&address).unwrap()
+    }
+
+    fn tx_json(descriptor: &CString) -> (CString, Address) {
+        let prev_tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), 0),
+                ..TxIn::default()
+            }],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: address(descriptor, 0).script_pubkey(),
+            }],
+        };
+        let destination = address(descriptor, 5);
+        let tx_json = json!({
+            "inputs": [format!("{}:0 /0/0", prev_tx.txid())],
+            "spentTxs": [serialize(&prev_tx).to_hex()],
+            "outputs": [{ "address": destination.to_string(), "amount": 50_000 }],
+            "changeIndex": 1,
+            "fee": 1_000,
+        });
+        (CString::new(tx_json.to_string()).unwrap(), destination)
+    }
+
+    #[test]
+    fn construct_sign_finalize() {
+        let (master, descriptor) = wallet();
+        let (tx_json, destination) = tx_json(&descriptor);
+
+        let psbt = unsafe { psbt_construct(descriptor.as_ptr(), tx_json.as_ptr()) }
+            .into_result()
+            .unwrap();
+        let psbt = CString::new(psbt).unwrap();
+        assert_eq!(
+            unsafe { psbt_finalize(psbt.as_ptr()) }.into_result(),
+            Err(error_t::psbt_finalization)
+        );
+
+        let master = CString::new(master.to_string()).unwrap().into_raw();
+        let signed = unsafe { psbt_sign(psbt.as_ptr(), master, false) }
+            .into_result()
+            .unwrap();
+        let signed = CString::new(signed).unwrap();
+
+        let tx_hex = unsafe { psbt_finalize(signed.as_ptr()) }
+            .into_result()
+            .unwrap();
+        let tx: Transaction = deserialize(&Vec::<u8>::from_hex(&tx_hex).unwrap()).unwrap();
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].witness.len(), 2);
+        assert_eq!(tx.output.len(), 2);
+        assert!(tx
+            .output
+            .iter()
+            .any(|out| out.value == 50_000 && out.script_pubkey == destination.script_pubkey()));
+        assert_eq!(tx.output.iter().map(|out| out.value).sum::<u64>(), 99_000);
+    }
+
+    #[test]
+    fn construct_errors() {
+        let (_, descriptor) = wallet();
+        let (tx_json, _) = tx_json(&descriptor);
+        let construct = |descriptor: *const c_char, tx_json: *const c_char| {
+            unsafe { psbt_construct(descriptor, tx_json) }.into_result()
+        };
+
+        assert_eq!(
+            construct(ptr::null(), tx_json.as_ptr()),
+            Err(error_t::null_pointer)
+        );
+        assert_eq!(
+            construct(descriptor.as_ptr(), ptr::null()),
+            Err(error_t::null_pointer)
+        );
+
+        let unknown_field = CString::new(r#"{"inputs": [], "unknown": 0}"#).unwrap();
+        assert_eq!(
+            construct(descriptor.as_ptr(), unknown_field.as_ptr()),
+            Err(error_t::invalid_json)
+        );
+
+        let invalid_psbt = CString::new("cHNidP8=").unwrap();
+        assert_eq!(
+            unsafe { psbt_finalize(invalid_psbt.as_ptr()) }.into_result(),
+            Err(error_t::invalid_psbt)
+        );
+        assert_eq!(
+            unsafe { psbt_finalize(ptr::null()) }.into_result(),
+            Err(error_t::null_pointer)
+        );
+    }
+}
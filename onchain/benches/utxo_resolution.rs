@@ -0,0 +1,78 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Compares per-script and batched UTXO resolution against electrum server
+//! provided in `ELECTRUM_SERVER` environment variable (for instance,
+//! `ELECTRUM_SERVER=tcp://localhost:50001 cargo bench --features electrum`).
+
+use bitcoin::hashes::Hash;
+use bitcoin::{Script, WPubkeyHash};
+use bitcoin_onchain::{BatchConfig, ElectrumResolver, ResolveUtxo};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use electrum_client::{Client, ElectrumApi};
+
+const SCRIPT_COUNT: u32 = 1000;
+
+fn scripts() -> Vec<Script> {
+    (0..SCRIPT_COUNT)
+        .map(|index| Script::new_v0_p2wpkh(&WPubkeyHash::hash(&index.to_be_bytes())))
+        .collect()
+}
+
+fn utxo_resolution(c: &mut Criterion) {
+    let server = match std::env::var("ELECTRUM_SERVER") {
+        Ok(server) => server,
+        Err(_) => {
+            eprintln!("ELECTRUM_SERVER environment variable is not set, skipping benchmarks");
+            return;
+        }
+    };
+    let scripts = scripts();
+
+    let mut group = c.benchmark_group("utxo_resolution");
+    group.sample_size(10);
+
+    let client = Client::new(&server).expect("unable to connect to electrum server");
+    group.bench_function("per_script", |b| {
+        b.iter(|| {
+            for script in &scripts {
+                client
+                    .script_list_unspent(script)
+                    .expect("electrum server error");
+            }
+        })
+    });
+
+    for batch_size in [10usize, 100, 500] {
+        let config = BatchConfig {
+            batch_size,
+            ..BatchConfig::default()
+        };
+        let client = Client::new(&server).expect("unable to connect to electrum server");
+        let resolver = ElectrumResolver::with(client, config);
+        group.bench_with_input(
+            BenchmarkId::new("batched", batch_size),
+            &scripts,
+            |b, scripts| {
+                b.iter(|| {
+                    resolver
+                        .resolve_utxo(scripts)
+                        .expect("electrum server error")
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, utxo_resolution);
+criterion_main!(benches);
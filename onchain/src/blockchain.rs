@@ -16,6 +16,7 @@ use std::hash::Hash;
 use std::str::FromStr;
 
 use bitcoin::blockdata::constants;
+use bitcoin::blockdata::locktime::LOCK_TIME_THRESHOLD;
 use bitcoin::{BlockHash, Network, OutPoint};
 use chrono::{DateTime, NaiveDateTime};
 #[cfg(feature = "electrum")]
@@ -75,6 +76,46 @@ impl FromStr for TimeHeight {
     }
 }
 
+/// Wallet birthday: the earliest point in the blockchain at which outputs of
+/// the wallet may appear. Blockchain history before the birthday is skipped
+/// during wallet scans.
+///
+/// Like `nLockTime`, values below 500000000 are block heights and the rest are
+/// UNIX timestamps.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[display(inner)]
+pub enum Birthday {
+    /// Height of the first block which may contain wallet outputs
+    Height(u32),
+
+    /// UNIX timestamp preceding creation of the first wallet output
+    Time(u32),
+}
+
+impl From<u32> for Birthday {
+    fn from(value: u32) -> Self {
+        if value < LOCK_TIME_THRESHOLD {
+            Birthday::Height(value)
+        } else {
+            Birthday::Time(value)
+        }
+    }
+}
+
+impl From<Birthday> for u32 {
+    fn from(birthday: Birthday) -> Self {
+        match birthday {
+            Birthday::Height(value) | Birthday::Time(value) => value,
+        }
+    }
+}
+
+impl FromStr for Birthday {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Ok(Birthday::from(u32::from_str(s)?)) }
+}
+
 /// Information about transaction mining status
 #[cfg_attr(
     feature = "serde",
@@ -126,6 +167,24 @@ pub struct Utxo {
     amount: bitcoin::Amount,
 }
 
+impl Utxo {
+    /// Constructs UTXO information from its mining status, outpoint and value
+    pub fn with(mined: MiningStatus, outpoint: OutPoint, amount: bitcoin::Amount) -> Utxo {
+        Utxo {
+            mined,
+            outpoint,
+            amount,
+        }
+    }
+
+    /// Detects whether the UTXO was mined in a block preceding a given height.
+    /// Unconfirmed UTXOs and UTXOs with unknown mining status are never
+    /// considered mined before any height.
+    pub fn is_mined_before(&self, height: u32) -> bool {
+        matches!(self.mined, MiningStatus::Blockchain(mined) if mined < height as u64)
+    }
+}
+
 impl FromStr for Utxo {
     type Err = ParseError;
 
@@ -156,3 +215,31 @@ impl From<ListUnspentRes> for Utxo {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bitcoin::Amount;
+
+    use super::*;
+
+    #[test]
+    fn birthday_utxo_filter() {
+        assert_eq!(Birthday::from_str("770000"), Ok(Birthday::Height(770000)));
+        assert_eq!(
+            Birthday::from_str("1672531200"),
+            Ok(Birthday::Time(1672531200))
+        );
+        assert_eq!(Birthday::Time(1672531200).to_string(), "1672531200");
+        assert!(Birthday::from_str("yesterday").is_err());
+
+        let mined = Utxo::with(
+            MiningStatus::Blockchain(100),
+            OutPoint::default(),
+            Amount::ZERO,
+        );
+        let mempool = Utxo::with(MiningStatus::Mempool, OutPoint::default(), Amount::ZERO);
+        assert!(mined.is_mined_before(101));
+        assert!(!mined.is_mined_before(100));
+        assert!(!mempool.is_mined_before(u32::MAX));
+    }
+}
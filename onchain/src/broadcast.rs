@@ -0,0 +1,558 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Transaction broadcasting and typed representation of the transaction
+//! rejection reasons reported by bitcoin nodes.
+
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use bitcoin::{Transaction, Txid};
+
+/// Bitcoin Core RPC error code for the transaction deserialization failures.
+pub const RPC_DESERIALIZATION_ERROR: i64 = -22;
+/// Bitcoin Core RPC error code for the general transaction verification
+/// failures (including missing inputs).
+pub const RPC_VERIFY_ERROR: i64 = -25;
+/// Bitcoin Core RPC error code for transactions rejected by network rules.
+pub const RPC_VERIFY_REJECTED: i64 = -26;
+/// Bitcoin Core RPC error code for transactions already included into the
+/// blockchain.
+pub const RPC_VERIFY_ALREADY_IN_CHAIN: i64 = -27;
+
+/// Script verification errors reported by bitcoin nodes in
+/// `mandatory-script-verify-flag-failed` and
+/// `non-mandatory-script-verify-flag` rejection messages.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(doc_comments)]
+#[non_exhaustive]
+pub enum ScriptVerifyFlag {
+    /// Script evaluated without error but finished with a false/empty top stack
+    /// element
+    EvalFalse,
+
+    /// Script failed an OP_VERIFY operation
+    Verify,
+
+    /// Script failed an OP_EQUALVERIFY operation
+    EqualVerify,
+
+    /// Script failed an OP_CHECKSIGVERIFY operation
+    CheckSigVerify,
+
+    /// Script failed an OP_CHECKMULTISIGVERIFY operation
+    CheckMultiSigVerify,
+
+    /// Operation not valid with the current stack size
+    InvalidStackOperation,
+
+    /// Locktime requirement not satisfied
+    UnsatisfiedLocktime,
+
+    /// Signature hash type missing or not understood
+    SigHashType,
+
+    /// Non-canonical DER signature
+    SigDer,
+
+    /// Non-canonical signature: S value is unnecessarily high
+    SigHighS,
+
+    /// Signature must be zero for failed CHECK(MULTI)SIG operation
+    NullFail,
+
+    /// Public key is neither compressed or uncompressed
+    PubkeyType,
+
+    /// Extra items left on stack after execution
+    CleanStack,
+
+    /// Witness program has incorrect length
+    WitnessProgramWrongLength,
+
+    /// Witness program was passed an empty witness
+    WitnessProgramWitnessEmpty,
+
+    /// Witness program hash mismatch
+    WitnessProgramMismatch,
+
+    /// Witness requires empty scriptSig
+    WitnessMalleated,
+
+    /// Witness requires only-redeemscript scriptSig
+    WitnessMalleatedP2sh,
+
+    /// Witness provided for non-witness script
+    WitnessUnexpected,
+
+    /// Using non-compressed keys in segwit
+    WitnessPubkeyType,
+
+    /// Invalid Schnorr signature size
+    SchnorrSigSize,
+
+    /// Invalid Schnorr signature hash type
+    SchnorrSigHashType,
+
+    /// Invalid Schnorr signature
+    SchnorrSig,
+
+    /// Invalid Taproot control block size
+    TaprootWrongControlSize,
+
+    /// {0}
+    Other(String),
+}
+
+impl ScriptVerifyFlag {
+    const KNOWN: [ScriptVerifyFlag; 24] = [
+        ScriptVerifyFlag::EvalFalse,
+        ScriptVerifyFlag::Verify,
+        ScriptVerifyFlag::EqualVerify,
+        ScriptVerifyFlag::CheckSigVerify,
+        ScriptVerifyFlag::CheckMultiSigVerify,
+        ScriptVerifyFlag::InvalidStackOperation,
+        ScriptVerifyFlag::UnsatisfiedLocktime,
+        ScriptVerifyFlag::SigHashType,
+        ScriptVerifyFlag::SigDer,
+        ScriptVerifyFlag::SigHighS,
+        ScriptVerifyFlag::NullFail,
+        ScriptVerifyFlag::PubkeyType,
+        ScriptVerifyFlag::CleanStack,
+        ScriptVerifyFlag::WitnessProgramWrongLength,
+        ScriptVerifyFlag::WitnessProgramWitnessEmpty,
+        ScriptVerifyFlag::WitnessProgramMismatch,
+        ScriptVerifyFlag::WitnessMalleated,
+        ScriptVerifyFlag::WitnessMalleatedP2sh,
+        ScriptVerifyFlag::WitnessUnexpected,
+        ScriptVerifyFlag::WitnessPubkeyType,
+        ScriptVerifyFlag::SchnorrSigSize,
+        ScriptVerifyFlag::SchnorrSigHashType,
+        ScriptVerifyFlag::SchnorrSig,
+        ScriptVerifyFlag::TaprootWrongControlSize,
+    ];
+}
+
+impl FromStr for ScriptVerifyFlag {
+    type Err = std::convert::Infallible;
+
+    /// Parses script error message as produced by Bitcoin Core. Unknown
+    /// messages are parsed into [`ScriptVerifyFlag::Other`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Ok(ScriptVerifyFlag::KNOWN
+            .iter()
+            .find(|flag| flag.to_string().eq_ignore_ascii_case(s))
+            .cloned()
+            .unwrap_or_else(|| ScriptVerifyFlag::Other(s.to_owned())))
+    }
+}
+
+/// Reasons for the transaction broadcast failure
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+#[non_exhaustive]
+pub enum BroadcastError {
+    /// transaction spends inputs which are either unknown or already spent
+    MissingInputs,
+
+    /// transaction conflicts with other transaction already present in the
+    /// mempool
+    MempoolConflict,
+
+    /// transaction is already present in the mempool
+    AlreadyInMempool,
+
+    /// transaction is already included into the blockchain
+    AlreadyInChain,
+
+    /// transaction fee is below minimum relay fee
+    MinRelayFeeNotMet,
+
+    /// transaction fee is below current minimum mempool fee
+    MempoolMinFeeNotMet,
+
+    /// transaction fee is insufficient to replace conflicting mempool
+    /// transaction(s)
+    InsufficientReplacementFee,
+
+    /// transaction is not final: its lock time is not yet satisfied
+    NonFinal,
+
+    /// transaction is not final: relative lock time (BIP-68) of some of its
+    /// inputs is not yet satisfied
+    NonBip68Final,
+
+    /// transaction exceeds mempool limit for the chain of unconfirmed
+    /// transactions
+    TooLongMempoolChain,
+
+    /// transaction contains dust outputs
+    Dust,
+
+    /// transaction can't be deserialized
+    TxDecodeFailed,
+
+    /// transaction script verification failed: {flag} (consensus rule:
+    /// {mandatory})
+    ScriptVerify {
+        /// Whether the failed rule is a consensus rule (`true`) or just a
+        /// node relay policy
+        mandatory: bool,
+
+        /// Failed script verification rule
+        flag: ScriptVerifyFlag,
+    },
+
+    /// transaction is rejected by node policy as non-standard: {0}
+    NonStandard(String),
+
+    /// transaction is rejected with unrecognized error (code {code:?}):
+    /// {message}
+    Other {
+        /// Error code returned by the node, if any
+        code: Option<i64>,
+
+        /// Error message returned by the node
+        message: String,
+    },
+
+    /// unable to communicate with the node: {0}
+    Transport(String),
+}
+
+impl BroadcastError {
+    /// Detects whether the error is caused by the failure to communicate with
+    /// the node (and not by transaction rejection), so the broadcast may be
+    /// retried with the same or some other node.
+    pub fn is_transport(&self) -> bool { matches!(self, BroadcastError::Transport(_)) }
+
+    /// Recognizes transaction rejection reason from the error code and message
+    /// returned by Bitcoin Core RPC `sendrawtransaction` call, or by Electrum
+    /// and Esplora servers relaying Bitcoin Core responses.
+    pub fn with_rpc_error(code: Option<i64>, message: impl AsRef<str>) -> BroadcastError {
+        let message = message.as_ref();
+        let lowercase = message.to_ascii_lowercase();
+        let has = |pat: &str| lowercase.contains(pat);
+
+        let script_verify = |prefix: &str| {
+            let pos = lowercase.find(prefix)? + prefix.len();
+            let details = &message[pos..];
+            let details = details
+                .find('(')
+                .and_then(|start| {
+                    details[start + 1..]
+                        .find(')')
+                        .map(|end| &details[start + 1..start + 1 + end])
+                })
+                .unwrap_or(details);
+            Some(ScriptVerifyFlag::from_str(details).expect("infallible"))
+        };
+
+        if let Some(flag) = script_verify("non-mandatory-script-verify-flag") {
+            BroadcastError::ScriptVerify {
+                mandatory: false,
+                flag,
+            }
+        } else if let Some(flag) = script_verify("mandatory-script-verify-flag-failed") {
+            BroadcastError::ScriptVerify {
+                mandatory: true,
+                flag,
+            }
+        } else if has("missing-inputs") || has("missingorspent") || has("missing inputs") {
+            BroadcastError::MissingInputs
+        } else if has("txn-mempool-conflict") {
+            BroadcastError::MempoolConflict
+        } else if has("txn-already-in-mempool") || has("txn-already-known") {
+            BroadcastError::AlreadyInMempool
+        } else if has("already in block chain")
+            || has("txn-already-in-chain")
+            || code == Some(RPC_VERIFY_ALREADY_IN_CHAIN)
+        {
+            BroadcastError::AlreadyInChain
+        } else if has("min relay fee not met") || has("min-relay-fee-not-met") {
+            BroadcastError::MinRelayFeeNotMet
+        } else if has("mempool min fee not met") {
+            BroadcastError::MempoolMinFeeNotMet
+        } else if has("insufficient fee") {
+            BroadcastError::InsufficientReplacementFee
+        } else if has("non-bip68-final") {
+            BroadcastError::NonBip68Final
+        } else if has("non-final") {
+            BroadcastError::NonFinal
+        } else if has("too-long-mempool-chain") {
+            BroadcastError::TooLongMempoolChain
+        } else if has("dust") {
+            BroadcastError::Dust
+        } else if has("tx decode failed") || code == Some(RPC_DESERIALIZATION_ERROR) {
+            BroadcastError::TxDecodeFailed
+        } else if code == Some(RPC_VERIFY_REJECTED) {
+            BroadcastError::NonStandard(message.trim().to_owned())
+        } else {
+            BroadcastError::Other {
+                code,
+                message: message.trim().to_owned(),
+            }
+        }
+    }
+}
+
+/// Transaction broadcasting API
+pub trait BroadcastTx {
+    /// Broadcasts transaction to the bitcoin network, returning its id.
+    fn broadcast_tx(&self, tx: &Transaction) -> Result<Txid, BroadcastError>;
+
+    /// Checks whether the transaction would be accepted into the mempool of
+    /// the backend without broadcasting it (like Bitcoin Core
+    /// `testmempoolaccept` RPC call does).
+    ///
+    /// Returns `Ok(false)` if the backend does not support the check, which is
+    /// the default implementation.
+    fn test_mempool_accept(&self, _tx: &Transaction) -> Result<bool, BroadcastError> { Ok(false) }
+}
+
+impl<T> BroadcastTx for Box<T>
+where
+    T: BroadcastTx + ?Sized,
+{
+    fn broadcast_tx(&self, tx: &Transaction) -> Result<Txid, BroadcastError> {
+        T::broadcast_tx(self, tx)
+    }
+
+    fn test_mempool_accept(&self, tx: &Transaction) -> Result<bool, BroadcastError> {
+        T::test_mempool_accept(self, tx)
+    }
+}
+
+/// Broadcaster trying a list of backends in order, retrying each of them on
+/// transport failures before falling back to the next one.
+///
+/// Before broadcasting, transaction is checked for the mempool acceptance by
+/// the first backend supporting such check. Transaction rejection by a
+/// backend is not retried, since other nodes will apply the same rules.
+pub struct FallbackBroadcaster {
+    backends: Vec<Box<dyn BroadcastTx>>,
+
+    /// Number of times a broadcast is retried with each of the backends after
+    /// a transport error
+    pub retries: u8,
+
+    /// Delay before retrying failed broadcast
+    pub retry_delay: Duration,
+}
+
+impl Default for FallbackBroadcaster {
+    fn default() -> Self { FallbackBroadcaster::with(vec![]) }
+}
+
+impl FallbackBroadcaster {
+    /// Constructs broadcaster over a list of backends using default retry
+    /// configuration
+    pub fn with(backends: Vec<Box<dyn BroadcastTx>>) -> Self {
+        FallbackBroadcaster {
+            backends,
+            retries: 1,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+
+    /// Adds backend to the end of the fallback list
+    pub fn push(&mut self, backend: impl BroadcastTx + 'static) {
+        self.backends.push(Box::new(backend))
+    }
+
+    /// Returns number of the backends used by the broadcaster
+    pub fn len(&self) -> usize { self.backends.len() }
+
+    /// Detects whether the broadcaster has no backends
+    pub fn is_empty(&self) -> bool { self.backends.is_empty() }
+}
+
+impl BroadcastTx for FallbackBroadcaster {
+    fn broadcast_tx(&self, tx: &Transaction) -> Result<Txid, BroadcastError> {
+        self.test_mempool_accept(tx)?;
+
+        let mut last_err = BroadcastError::Transport(s!("no broadcasting backends provided"));
+        let mut attempted = false;
+        for backend in &self.backends {
+            for attempt in 0..=self.retries {
+                if attempt > 0 {
+                    thread::sleep(self.retry_delay);
+                }
+                match backend.broadcast_tx(tx) {
+                    Ok(txid) => return Ok(txid),
+                    // Previous attempt may have reached the network before
+                    // the transport failure
+                    Err(BroadcastError::AlreadyInMempool) if attempted => return Ok(tx.txid()),
+                    Err(err) if err.is_transport() => last_err = err,
+                    Err(err) => return Err(err),
+                }
+                attempted = true;
+            }
+        }
+        Err(last_err)
+    }
+
+    fn test_mempool_accept(&self, tx: &Transaction) -> Result<bool, BroadcastError> {
+        for backend in &self.backends {
+            match backend.test_mempool_accept(tx) {
+                Ok(false) => continue,
+                Err(err) if err.is_transport() => continue,
+                res => return res,
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use bitcoin::PackedLockTime;
+
+    use super::*;
+
+    struct MockBackend {
+        responses: Vec<Result<(), BroadcastError>>,
+        calls: Cell<usize>,
+        mempool_check: Option<BroadcastError>,
+    }
+
+    impl MockBackend {
+        fn with(responses: Vec<Result<(), BroadcastError>>) -> Self {
+            MockBackend {
+                responses,
+                calls: Cell::new(0),
+                mempool_check: None,
+            }
+        }
+    }
+
+    impl BroadcastTx for MockBackend {
+        fn broadcast_tx(&self, tx: &Transaction) -> Result<Txid, BroadcastError> {
+            let call = self.calls.get();
+            self.calls.set(call + 1);
+            self.responses[call.min(self.responses.len() - 1)]
+                .clone()
+                .map(|_| tx.txid())
+        }
+
+        fn test_mempool_accept(&self, _tx: &Transaction) -> Result<bool, BroadcastError> {
+            match &self.mempool_check {
+                Some(err) => Err(err.clone()),
+                None => Ok(false),
+            }
+        }
+    }
+
+    fn transport() -> BroadcastError { BroadcastError::Transport(s!("connection refused")) }
+
+    #[test]
+    fn fallback_broadcast() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        };
+
+        let mut broadcaster = FallbackBroadcaster::default();
+        broadcaster.retry_delay = Duration::from_millis(0);
+        assert!(broadcaster.broadcast_tx(&tx).unwrap_err().is_transport());
+
+        broadcaster.push(MockBackend::with(vec![Err(transport())]));
+        broadcaster.push(MockBackend::with(vec![Err(transport()), Ok(())]));
+        assert_eq!(broadcaster.broadcast_tx(&tx), Ok(tx.txid()));
+
+        let broadcaster = FallbackBroadcaster::with(vec![
+            Box::new(MockBackend::with(vec![Err(BroadcastError::Dust)])),
+            Box::new(MockBackend::with(vec![Ok(())])),
+        ]);
+        assert_eq!(broadcaster.broadcast_tx(&tx), Err(BroadcastError::Dust));
+
+        let mut broadcaster = FallbackBroadcaster::with(vec![Box::new(MockBackend::with(vec![
+            Err(transport()),
+            Err(BroadcastError::AlreadyInMempool),
+        ]))]);
+        broadcaster.retry_delay = Duration::from_millis(0);
+        assert_eq!(broadcaster.broadcast_tx(&tx), Ok(tx.txid()));
+
+        let mut backend = MockBackend::with(vec![Ok(())]);
+        backend.mempool_check = Some(BroadcastError::MinRelayFeeNotMet);
+        let broadcaster = FallbackBroadcaster::with(vec![Box::new(backend)]);
+        assert_eq!(
+            broadcaster.broadcast_tx(&tx),
+            Err(BroadcastError::MinRelayFeeNotMet)
+        );
+    }
+
+    #[test]
+    fn rpc_error_taxonomy() {
+        assert_eq!(
+            BroadcastError::with_rpc_error(
+                Some(RPC_VERIFY_REJECTED),
+                "non-mandatory-script-verify-flag (Witness program hash mismatch)"
+            ),
+            BroadcastError::ScriptVerify {
+                mandatory: false,
+                flag: ScriptVerifyFlag::WitnessProgramMismatch
+            }
+        );
+        assert_eq!(
+            BroadcastError::with_rpc_error(
+                Some(RPC_VERIFY_REJECTED),
+                "mandatory-script-verify-flag-failed (Signature must be zero for failed \
+                 CHECK(MULTI)SIG operation)"
+            ),
+            BroadcastError::ScriptVerify {
+                mandatory: true,
+                flag: ScriptVerifyFlag::NullFail
+            }
+        );
+        assert_eq!(
+            BroadcastError::with_rpc_error(
+                Some(RPC_VERIFY_REJECTED),
+                "non-mandatory-script-verify-flag (Some future error)"
+            ),
+            BroadcastError::ScriptVerify {
+                mandatory: false,
+                flag: ScriptVerifyFlag::Other(s!("Some future error"))
+            }
+        );
+        assert_eq!(
+            BroadcastError::with_rpc_error(
+                Some(RPC_VERIFY_ERROR),
+                "bad-txns-inputs-missingorspent"
+            ),
+            BroadcastError::MissingInputs
+        );
+        assert_eq!(
+            BroadcastError::with_rpc_error(
+                Some(RPC_VERIFY_REJECTED),
+                "min relay fee not met, 100 < 141"
+            ),
+            BroadcastError::MinRelayFeeNotMet
+        );
+        assert_eq!(
+            BroadcastError::with_rpc_error(Some(RPC_VERIFY_REJECTED), "non-BIP68-final"),
+            BroadcastError::NonBip68Final
+        );
+        assert_eq!(
+            BroadcastError::with_rpc_error(
+                Some(RPC_VERIFY_ALREADY_IN_CHAIN),
+                "Transaction already in block chain"
+            ),
+            BroadcastError::AlreadyInChain
+        );
+    }
+}
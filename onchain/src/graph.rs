@@ -0,0 +1,414 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Transaction graph around wallet-owned coins, constructed by walking
+//! ancestors and descendants of the transactions containing wallet UTXOs.
+//! The graph is used for coin age computation, discovery of CPFP candidates
+//! and reconstruction of the wallet history.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use bitcoin::{OutPoint, Transaction, Txid};
+
+use crate::blockchain::MiningStatus;
+use crate::{ResolveTxGraph, TxResolverError};
+
+/// Transaction in the [`TxGraph`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TxNode {
+    /// The transaction
+    pub tx: Transaction,
+
+    /// Mining status of the transaction
+    pub status: MiningStatus,
+
+    /// Transaction fee, if all spent transactions are known to the graph
+    pub fee: Option<u64>,
+
+    /// Distance from the nearest transaction containing wallet UTXO, which
+    /// have zero depth
+    pub depth: u32,
+}
+
+impl TxNode {
+    /// Detects whether the transaction is not mined yet
+    #[inline]
+    pub fn is_unconfirmed(&self) -> bool { !matches!(self.status, MiningStatus::Blockchain(_)) }
+
+    /// Virtual size of the transaction in vbytes
+    #[inline]
+    pub fn vsize(&self) -> usize { (self.tx.weight() + 3) / 4 }
+}
+
+/// Unconfirmed wallet UTXO which can be spent by a child transaction paying
+/// for its unconfirmed ancestors (CPFP)
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct CpfpCandidate {
+    /// Wallet UTXO which may be spent by a child transaction
+    pub outpoint: OutPoint,
+
+    /// Total fee paid by the transaction containing the UTXO and all its
+    /// unconfirmed ancestors, if known
+    pub package_fee: Option<u64>,
+
+    /// Total virtual size of the transaction containing the UTXO and all
+    /// its unconfirmed ancestors
+    pub package_vsize: usize,
+}
+
+impl CpfpCandidate {
+    /// Fee rate of the unconfirmed package in sat/vbyte, if the fee is known
+    pub fn package_fee_rate(&self) -> Option<f32> {
+        self.package_fee
+            .map(|fee| fee as f32 / self.package_vsize as f32)
+    }
+}
+
+/// Graph of transactions related to the wallet coins
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct TxGraph {
+    utxos: BTreeSet<OutPoint>,
+    nodes: BTreeMap<Txid, TxNode>,
+}
+
+impl TxGraph {
+    /// Constructs transaction graph by walking ancestors and descendants of
+    /// the transactions containing given wallet UTXOs, up to `max_depth`
+    /// transactions away from them.
+    pub fn walk<R>(
+        resolver: &R,
+        utxos: impl IntoIterator<Item = OutPoint>,
+        max_depth: u32,
+    ) -> Result<TxGraph, TxResolverError>
+    where
+        R: ResolveTxGraph,
+    {
+        let utxos = utxos.into_iter().collect::<BTreeSet<_>>();
+        let mut nodes = BTreeMap::<Txid, TxNode>::new();
+        let mut queue = utxos
+            .iter()
+            .map(|outpoint| (outpoint.txid, None, 0u32))
+            .collect::<VecDeque<(Txid, Option<Transaction>, u32)>>();
+
+        while let Some((txid, tx, depth)) = queue.pop_front() {
+            if nodes.contains_key(&txid) {
+                continue;
+            }
+            let tx = match tx {
+                Some(tx) => tx,
+                None => resolver.resolve_tx(txid)?,
+            };
+            let status = resolver.resolve_tx_status(&tx)?;
+
+            if depth < max_depth {
+                for txin in &tx.input {
+                    let prev_txid = txin.previous_output.txid;
+                    if !txin.previous_output.is_null() && !nodes.contains_key(&prev_txid) {
+                        queue.push_back((prev_txid, None, depth + 1));
+                    }
+                }
+                for child in resolver.resolve_spending_txs(&tx)? {
+                    let child_txid = child.txid();
+                    if !nodes.contains_key(&child_txid) {
+                        queue.push_back((child_txid, Some(child), depth + 1));
+                    }
+                }
+            }
+
+            nodes.insert(txid, TxNode {
+                tx,
+                status,
+                fee: None,
+                depth,
+            });
+        }
+
+        let fees = nodes
+            .iter()
+            .map(|(txid, node)| (*txid, Self::compute_fee(&nodes, &node.tx)))
+            .collect::<Vec<_>>();
+        for (txid, fee) in fees {
+            if let Some(node) = nodes.get_mut(&txid) {
+                node.fee = fee;
+            }
+        }
+
+        Ok(TxGraph { utxos, nodes })
+    }
+
+    fn compute_fee(nodes: &BTreeMap<Txid, TxNode>, tx: &Transaction) -> Option<u64> {
+        let mut input_sum = 0u64;
+        for txin in &tx.input {
+            let prevout = txin.previous_output;
+            let prev_tx = &nodes.get(&prevout.txid)?.tx;
+            input_sum += prev_tx.output.get(prevout.vout as usize)?.value;
+        }
+        let output_sum = tx.output.iter().map(|txout| txout.value).sum::<u64>();
+        input_sum.checked_sub(output_sum)
+    }
+
+    /// Wallet UTXOs the graph was constructed from
+    #[inline]
+    pub fn utxos(&self) -> &BTreeSet<OutPoint> { &self.utxos }
+
+    /// Number of transactions in the graph
+    #[inline]
+    pub fn len(&self) -> usize { self.nodes.len() }
+
+    /// Detects whether the graph has no transactions
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.nodes.is_empty() }
+
+    /// Returns graph node for a given transaction, if it is present
+    #[inline]
+    pub fn get(&self, txid: Txid) -> Option<&TxNode> { self.nodes.get(&txid) }
+
+    /// Iterates over all graph transactions in the order of their ids
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (Txid, &TxNode)> {
+        self.nodes.iter().map(|(txid, node)| (*txid, node))
+    }
+
+    /// Returns ids of the graph transactions spent by a given transaction
+    pub fn parents(&self, txid: Txid) -> BTreeSet<Txid> {
+        self.nodes
+            .get(&txid)
+            .map(|node| {
+                node.tx
+                    .input
+                    .iter()
+                    .map(|txin| txin.previous_output.txid)
+                    .filter(|prev_txid| self.nodes.contains_key(prev_txid))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns ids of the graph transactions spending outputs of a given
+    /// transaction
+    pub fn children(&self, txid: Txid) -> BTreeSet<Txid> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| {
+                node.tx
+                    .input
+                    .iter()
+                    .any(|txin| txin.previous_output.txid == txid)
+            })
+            .map(|(child_txid, _)| *child_txid)
+            .collect()
+    }
+
+    /// Returns ids of all unconfirmed graph ancestors of a given transaction,
+    /// not including the transaction itself
+    pub fn unconfirmed_ancestors(&self, txid: Txid) -> BTreeSet<Txid> {
+        let mut ancestors = BTreeSet::new();
+        let mut queue = self.parents(txid).into_iter().collect::<Vec<_>>();
+        while let Some(parent) = queue.pop() {
+            match self.nodes.get(&parent) {
+                Some(node) if node.is_unconfirmed() && ancestors.insert(parent) => {
+                    queue.extend(self.parents(parent));
+                }
+                _ => {}
+            }
+        }
+        ancestors
+    }
+
+    /// Removes all transactions located more than `max_depth` transactions
+    /// away from wallet UTXOs. Fees of the remaining transactions are kept.
+    pub fn prune(&mut self, max_depth: u32) {
+        self.nodes.retain(|_, node| node.depth <= max_depth);
+    }
+
+    /// Returns number of confirmations of a given wallet coin for the chain
+    /// tip at `tip_height`: zero for unconfirmed coins and `None` if the
+    /// coin is not known to the graph or has unknown mining status.
+    pub fn coin_age(&self, outpoint: OutPoint, tip_height: u32) -> Option<u32> {
+        match self.nodes.get(&outpoint.txid)?.status {
+            MiningStatus::Blockchain(height) => {
+                Some((tip_height as u64 + 1).saturating_sub(height) as u32)
+            }
+            MiningStatus::Mempool => Some(0),
+            MiningStatus::Undefined | MiningStatus::UnknownTx => None,
+        }
+    }
+
+    /// Lists unconfirmed wallet UTXOs together with the fee and size of
+    /// their unconfirmed ancestor packages
+    pub fn cpfp_candidates(&self) -> Vec<CpfpCandidate> {
+        self.utxos
+            .iter()
+            .filter_map(|outpoint| {
+                let node = self.nodes.get(&outpoint.txid)?;
+                if !node.is_unconfirmed() {
+                    return None;
+                }
+                let package = self
+                    .unconfirmed_ancestors(outpoint.txid)
+                    .into_iter()
+                    .filter_map(|txid| self.nodes.get(&txid))
+                    .chain(Some(node))
+                    .collect::<Vec<_>>();
+                Some(CpfpCandidate {
+                    outpoint: *outpoint,
+                    package_fee: package.iter().map(|node| node.fee).sum(),
+                    package_vsize: package.iter().map(|node| node.vsize()).sum(),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns graph transactions in the order they were mined, followed by
+    /// unconfirmed transactions
+    pub fn history(&self) -> Vec<&TxNode> {
+        let mut history = self.nodes.values().collect::<Vec<_>>();
+        history.sort_by_key(|node| match node.status {
+            MiningStatus::Blockchain(height) => (false, height),
+            _ => (true, 0),
+        });
+        history
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use bitcoin::{PackedLockTime, Script, Sequence, TxIn, TxOut};
+
+    use super::*;
+    use crate::ResolveTx;
+
+    #[derive(Default)]
+    struct MockChain {
+        txs: HashMap<Txid, (Transaction, MiningStatus)>,
+    }
+
+    impl MockChain {
+        fn add(&mut self, inputs: &[OutPoint], outputs: &[u64], status: MiningStatus) -> Txid {
+            let tx = Transaction {
+                version: 2,
+                lock_time: PackedLockTime::ZERO,
+                input: inputs
+                    .iter()
+                    .map(|outpoint| TxIn {
+                        previous_output: *outpoint,
+                        script_sig: none!(),
+                        sequence: Sequence::MAX,
+                        witness: empty!(),
+                    })
+                    .collect(),
+                output: outputs
+                    .iter()
+                    .map(|value| TxOut {
+                        value: *value,
+                        script_pubkey: Script::new(),
+                    })
+                    .collect(),
+            };
+            let txid = tx.txid();
+            self.txs.insert(txid, (tx, status));
+            txid
+        }
+    }
+
+    impl ResolveTx for MockChain {
+        fn resolve_tx(&self, txid: Txid) -> Result<Transaction, TxResolverError> {
+            self.txs
+                .get(&txid)
+                .map(|(tx, _)| tx.clone())
+                .ok_or_else(|| TxResolverError::with(txid))
+        }
+    }
+
+    impl ResolveTxGraph for MockChain {
+        fn resolve_tx_status(&self, tx: &Transaction) -> Result<MiningStatus, TxResolverError> {
+            Ok(self
+                .txs
+                .get(&tx.txid())
+                .map(|(_, status)| *status)
+                .unwrap_or(MiningStatus::UnknownTx))
+        }
+
+        fn resolve_spending_txs(
+            &self,
+            tx: &Transaction,
+        ) -> Result<Vec<Transaction>, TxResolverError> {
+            let txid = tx.txid();
+            Ok(self
+                .txs
+                .values()
+                .map(|(tx, _)| tx)
+                .filter(|child| {
+                    child
+                        .input
+                        .iter()
+                        .any(|txin| txin.previous_output.txid == txid)
+                })
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[test]
+    fn graph_walk() {
+        let mut chain = MockChain::default();
+        let coinbase = chain.add(
+            &[OutPoint::null()],
+            &[100_000],
+            MiningStatus::Blockchain(100),
+        );
+        let parent = chain.add(
+            &[OutPoint::new(coinbase, 0)],
+            &[60_000, 39_000],
+            MiningStatus::Mempool,
+        );
+        let wallet_tx = chain.add(
+            &[OutPoint::new(parent, 0)],
+            &[59_500],
+            MiningStatus::Mempool,
+        );
+        let sibling = chain.add(
+            &[OutPoint::new(parent, 1)],
+            &[38_000],
+            MiningStatus::Mempool,
+        );
+
+        let utxo = OutPoint::new(wallet_tx, 0);
+        let graph = TxGraph::walk(&chain, [utxo], 3).unwrap();
+        assert_eq!(graph.len(), 4);
+        assert_eq!(graph.get(coinbase).unwrap().depth, 2);
+        assert_eq!(graph.get(sibling).unwrap().depth, 2);
+        assert_eq!(graph.get(parent).unwrap().fee, Some(1_000));
+        assert_eq!(graph.get(wallet_tx).unwrap().fee, Some(500));
+        assert_eq!(graph.get(coinbase).unwrap().fee, None);
+        assert_eq!(graph.children(parent), bset![wallet_tx, sibling]);
+        assert_eq!(graph.unconfirmed_ancestors(wallet_tx), bset![parent]);
+
+        let candidates = graph.cpfp_candidates();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].outpoint, utxo);
+        assert_eq!(candidates[0].package_fee, Some(1_500));
+        assert_eq!(graph.coin_age(utxo, 110), Some(0));
+        assert_eq!(graph.coin_age(OutPoint::new(coinbase, 0), 110), Some(11));
+        assert_eq!(graph.history()[0].tx.txid(), coinbase);
+
+        let mut pruned = graph.clone();
+        pruned.prune(1);
+        assert_eq!(pruned.len(), 2);
+        assert_eq!(pruned.get(parent).unwrap().fee, Some(1_000));
+
+        let shallow = TxGraph::walk(&chain, [utxo], 0).unwrap();
+        assert_eq!(shallow.len(), 1);
+        assert_eq!(shallow.get(wallet_tx).unwrap().fee, None);
+    }
+}
@@ -27,10 +27,28 @@ extern crate serde_crate as serde;
 extern crate miniscript_crate as miniscript;
 
 pub mod blockchain;
+mod broadcast;
+pub mod graph;
 mod network;
 mod resolvers;
+pub mod subscribe;
 
-pub use network::PublicNetwork;
+pub use broadcast::{
+    BroadcastError, BroadcastTx, FallbackBroadcaster, ScriptVerifyFlag, RPC_DESERIALIZATION_ERROR,
+    RPC_VERIFY_ALREADY_IN_CHAIN, RPC_VERIFY_ERROR, RPC_VERIFY_REJECTED,
+};
+pub use network::{PublicNetwork, UnknownNetwork};
+#[cfg(all(feature = "async", feature = "miniscript_descriptors"))]
+pub use resolvers::AsyncResolveDescriptor;
+#[cfg(feature = "async")]
+pub use resolvers::{AsyncResolveTx, AsyncResolveTxFee, AsyncResolveUtxo};
+#[cfg(feature = "electrum")]
+pub use resolvers::{
+    BatchConfig, ElectrumConfig, ElectrumProtocol, ElectrumResolver, ElectrumUrlError,
+};
+pub use resolvers::{
+    BlockResolverError, ResolveTx, ResolveTxFee, ResolveTxGraph, ResolveUtxo, TxResolverError,
+    UtxoResolverError,
+};
 #[cfg(feature = "miniscript_descriptors")]
-pub use resolvers::ResolveDescriptor;
-pub use resolvers::{ResolveTx, ResolveTxFee, ResolveUtxo, TxResolverError, UtxoResolverError};
+pub use resolvers::{ResolveBlockInfo, ResolveDescriptor};
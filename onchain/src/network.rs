@@ -9,10 +9,15 @@
 // along with this software.
 // If not, see <https://opensource.org/licenses/Apache-2.0>.
 
+use std::str::FromStr;
+
 use bitcoin::Network;
 use bitcoin_hd::standards::DerivationBlockchain;
 
-/// Public variants of bitcoin networks
+/// Bitcoin networks supported by the wallet. Testnet-like networks (testnet3,
+/// signet and regtest) share the same key versions and BIP-44 coin type, but
+/// are distinguished for the purposes of address generation, electrum server
+/// selection and transaction publishing.
 #[derive(
     Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Debug, Display
 )]
@@ -35,6 +40,29 @@ pub enum PublicNetwork {
     /// Bitcoin signet
     #[display("signet")]
     Signet,
+
+    /// Local bitcoin regtest network
+    #[display("regtest")]
+    Regtest,
+}
+
+/// Error parsing [`PublicNetwork`] name
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display("unknown bitcoin network `{0}`")]
+pub struct UnknownNetwork(pub String);
+
+impl FromStr for PublicNetwork {
+    type Err = UnknownNetwork;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "mainnet" | "bitcoin" => PublicNetwork::Mainnet,
+            "testnet" | "testnet3" => PublicNetwork::Testnet,
+            "signet" => PublicNetwork::Signet,
+            "regtest" => PublicNetwork::Regtest,
+            _ => return Err(UnknownNetwork(s.to_owned())),
+        })
+    }
 }
 
 impl From<PublicNetwork> for Network {
@@ -47,19 +75,19 @@ impl From<&PublicNetwork> for Network {
             PublicNetwork::Mainnet => Network::Bitcoin,
             PublicNetwork::Testnet => Network::Testnet,
             PublicNetwork::Signet => Network::Signet,
+            PublicNetwork::Regtest => Network::Regtest,
         }
     }
 }
 
-impl TryFrom<Network> for PublicNetwork {
-    type Error = ();
-    fn try_from(network: Network) -> Result<Self, Self::Error> {
-        Ok(match network {
+impl From<Network> for PublicNetwork {
+    fn from(network: Network) -> Self {
+        match network {
             Network::Bitcoin => PublicNetwork::Mainnet,
             Network::Testnet => PublicNetwork::Testnet,
             Network::Signet => PublicNetwork::Signet,
-            Network::Regtest => return Err(()),
-        })
+            Network::Regtest => PublicNetwork::Regtest,
+        }
     }
 }
 
@@ -73,14 +101,20 @@ impl From<&PublicNetwork> for DerivationBlockchain {
             PublicNetwork::Mainnet => DerivationBlockchain::Bitcoin,
             PublicNetwork::Testnet => DerivationBlockchain::Testnet,
             PublicNetwork::Signet => DerivationBlockchain::Testnet,
+            PublicNetwork::Regtest => DerivationBlockchain::Testnet,
         }
     }
 }
 
 impl PublicNetwork {
-    /// Detects if the public network is belongs to a testnet
-    pub fn is_testnet(self) -> bool {
-        matches!(self, PublicNetwork::Testnet | PublicNetwork::Signet)
+    /// Detects if the network is a testnet-like network, using testnet key
+    /// versions and BIP-44 coin type
+    pub fn is_testnet(self) -> bool { self != PublicNetwork::Mainnet }
+
+    /// Detects whether extended keys and descriptors created for `key_network`
+    /// (as reported by the key version) can be used on this network
+    pub fn is_compatible_with(self, key_network: Network) -> bool {
+        self.is_testnet() == (key_network != Network::Bitcoin)
     }
 
     /// Returns default electrum server port for the network
@@ -89,6 +123,49 @@ impl PublicNetwork {
             PublicNetwork::Mainnet => 50001,
             PublicNetwork::Testnet => 60001,
             PublicNetwork::Signet => 60601,
+            PublicNetwork::Regtest => 60401,
+        }
+    }
+
+    /// Returns default SSL electrum server port for the network
+    pub fn electrum_ssl_port(self) -> u16 {
+        match self {
+            PublicNetwork::Mainnet => 50002,
+            PublicNetwork::Testnet => 60002,
+            PublicNetwork::Signet => 60602,
+            PublicNetwork::Regtest => 60402,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn network_compatibility() {
+        for network in [
+            PublicNetwork::Mainnet,
+            PublicNetwork::Testnet,
+            PublicNetwork::Signet,
+            PublicNetwork::Regtest,
+        ] {
+            assert_eq!(PublicNetwork::from_str(&network.to_string()), Ok(network));
+            assert_eq!(PublicNetwork::from(Network::from(network)), network);
+        }
+        assert_eq!(
+            PublicNetwork::from_str("bitcoin"),
+            Ok(PublicNetwork::Mainnet)
+        );
+        assert!(PublicNetwork::from_str("liquid").is_err());
+
+        assert!(PublicNetwork::Regtest.is_compatible_with(Network::Testnet));
+        assert!(PublicNetwork::Signet.is_compatible_with(Network::Testnet));
+        assert!(!PublicNetwork::Regtest.is_compatible_with(Network::Bitcoin));
+        assert!(!PublicNetwork::Mainnet.is_compatible_with(Network::Testnet));
+        assert_eq!(
+            DerivationBlockchain::from(PublicNetwork::Regtest),
+            DerivationBlockchain::Testnet
+        );
+    }
+}
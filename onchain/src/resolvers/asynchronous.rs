@@ -0,0 +1,309 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Asynchronous versions of the resolver traits, which may be used from async
+//! runtimes (like `tokio`) without blocking executor threads.
+
+use std::collections::{BTreeMap, HashSet};
+
+use async_trait::async_trait;
+use bitcoin::{Script, Transaction, Txid};
+
+use super::{TxResolverError, UtxoResolverError};
+use crate::blockchain::Utxo;
+
+/// Asynchronous transaction resolver
+#[async_trait]
+pub trait AsyncResolveTx {
+    /// Tries to find a transaction by transaction id ([`Txid`])
+    async fn resolve_tx(&self, txid: Txid) -> Result<Transaction, TxResolverError>;
+}
+
+/// Asynchronous transaction fee resolver
+#[async_trait]
+pub trait AsyncResolveTxFee {
+    /// Tries to find a transaction and compute its fee by transaction id
+    /// ([`Txid`])
+    async fn resolve_tx_fee(
+        &self,
+        txid: Txid,
+    ) -> Result<Option<(Transaction, u64)>, TxResolverError>;
+}
+
+/// Asynchronous UTXO resolver
+#[async_trait]
+pub trait AsyncResolveUtxo {
+    /// Finds UTXO set for the provided address lists
+    async fn resolve_utxo<'script>(
+        &self,
+        scripts: impl IntoIterator<Item = &'script Script> + Send + 'script,
+    ) -> Result<Vec<HashSet<Utxo>>, UtxoResolverError>;
+
+    /// Finds UTXO set for the provided address lists, skipping outputs mined
+    /// before the wallet birthday block `height`.
+    ///
+    /// Resolvers which are able to skip blockchain history before a given
+    /// height should override the default implementation, which filters
+    /// results of [`AsyncResolveUtxo::resolve_utxo`].
+    async fn resolve_utxo_since<'script>(
+        &self,
+        scripts: impl IntoIterator<Item = &'script Script> + Send + 'script,
+        height: u32,
+    ) -> Result<Vec<HashSet<Utxo>>, UtxoResolverError> {
+        Ok(self
+            .resolve_utxo(scripts)
+            .await?
+            .into_iter()
+            .map(|utxo_set| {
+                utxo_set
+                    .into_iter()
+                    .filter(|utxo| !utxo.is_mined_before(height))
+                    .collect()
+            })
+            .collect())
+    }
+}
+
+#[cfg(feature = "miniscript_descriptors")]
+mod _miniscript_descriptors {
+    use std::collections::{BTreeMap, HashSet};
+
+    use async_trait::async_trait;
+    use bitcoin::secp256k1::{Secp256k1, Verification};
+    use bitcoin::Script;
+    use bitcoin_hd::{DerivationAccount, UnhardenedIndex};
+
+    use super::AsyncResolveUtxo;
+    use crate::blockchain::Utxo;
+    use crate::resolvers::_miniscript_descriptors::descriptor_scripts;
+    use crate::UtxoResolverError;
+
+    /// Does complex asynchronous resolution for miniscript descriptors
+    #[async_trait]
+    pub trait AsyncResolveDescriptor: AsyncResolveUtxo + Sync {
+        /// Finds UTXO set for the addresses derivable from the given descriptor
+        async fn resolve_descriptor_utxo<C: Verification + Sync>(
+            &self,
+            secp: &Secp256k1<C>,
+            descriptor: &miniscript::Descriptor<DerivationAccount>,
+            terminal_derivation: impl AsRef<[UnhardenedIndex]> + Send,
+            from_index: UnhardenedIndex,
+            count: u32,
+        ) -> Result<BTreeMap<UnhardenedIndex, (Script, HashSet<Utxo>)>, UtxoResolverError> {
+            self.resolve_descriptor_utxo_since(
+                secp,
+                descriptor,
+                terminal_derivation,
+                from_index,
+                count,
+                0,
+            )
+            .await
+        }
+
+        /// Finds UTXO set for the addresses derivable from the given
+        /// descriptor, skipping outputs mined before the wallet birthday block
+        /// `height`
+        async fn resolve_descriptor_utxo_since<C: Verification + Sync>(
+            &self,
+            secp: &Secp256k1<C>,
+            descriptor: &miniscript::Descriptor<DerivationAccount>,
+            terminal_derivation: impl AsRef<[UnhardenedIndex]> + Send,
+            from_index: UnhardenedIndex,
+            count: u32,
+            height: u32,
+        ) -> Result<BTreeMap<UnhardenedIndex, (Script, HashSet<Utxo>)>, UtxoResolverError> {
+            let scripts = descriptor_scripts(
+                secp,
+                descriptor,
+                terminal_derivation.as_ref(),
+                from_index,
+                count,
+            )?;
+
+            Ok(self
+                .resolve_utxo_since(scripts.values(), height)
+                .await?
+                .into_iter()
+                .zip(scripts.keys())
+                .zip(scripts.values())
+                .map(|((utxo_set, index), script)| (*index, (script.clone(), utxo_set)))
+                .collect())
+        }
+    }
+
+    impl<T> AsyncResolveDescriptor for T where T: AsyncResolveUtxo + Sync {}
+}
+#[cfg(feature = "miniscript_descriptors")]
+pub use _miniscript_descriptors::AsyncResolveDescriptor;
+
+#[async_trait]
+impl AsyncResolveTx for BTreeMap<Txid, Transaction> {
+    async fn resolve_tx(&self, txid: Txid) -> Result<Transaction, TxResolverError> {
+        self.get(&txid)
+            .cloned()
+            .ok_or_else(|| TxResolverError::with(txid))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, OutPoint, PackedLockTime};
+
+    use super::*;
+    use crate::blockchain::MiningStatus;
+
+    /// Resolver returning the same UTXO set for each of the scripts
+    struct MockResolver(HashSet<Utxo>);
+
+    #[async_trait]
+    impl AsyncResolveUtxo for MockResolver {
+        async fn resolve_utxo<'script>(
+            &self,
+            scripts: impl IntoIterator<Item = &'script Script> + Send + 'script,
+        ) -> Result<Vec<HashSet<Utxo>>, UtxoResolverError> {
+            Ok(scripts.into_iter().map(|_| self.0.clone()).collect())
+        }
+    }
+
+    fn utxo(mined: MiningStatus, vout: u32) -> Utxo {
+        Utxo::with(
+            mined,
+            OutPoint::new(Txid::from_inner([0x01; 32]), vout),
+            Amount::from_sat(1000),
+        )
+    }
+
+    fn resolver() -> MockResolver {
+        MockResolver(
+            [
+                utxo(MiningStatus::Blockchain(99), 0),
+                utxo(MiningStatus::Blockchain(100), 1),
+                utxo(MiningStatus::Mempool, 2),
+                utxo(MiningStatus::Undefined, 3),
+            ]
+            .into_iter()
+            .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn btreemap_resolver() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![],
+        };
+        let txid = tx.txid();
+        let resolver = bmap! { txid => tx.clone() };
+
+        assert_eq!(
+            AsyncResolveTx::resolve_tx(&resolver, txid).await.unwrap(),
+            tx
+        );
+        let err = AsyncResolveTx::resolve_tx(&resolver, Txid::all_zeros())
+            .await
+            .unwrap_err();
+        assert_eq!(err.txid, Txid::all_zeros());
+    }
+
+    #[tokio::test]
+    async fn utxo_since() {
+        let resolver = resolver();
+        let scripts = [Script::new(), Script::new_op_return(&[])];
+
+        let all = resolver.resolve_utxo(&scripts).await.unwrap();
+        assert_eq!(all, vec![resolver.0.clone(); 2]);
+
+        let since = resolver.resolve_utxo_since(&scripts, 100).await.unwrap();
+        assert_eq!(since.len(), 2);
+        let vouts = since[0]
+            .iter()
+            .map(|utxo| utxo.outpoint().vout)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(vouts, bset! {1, 2, 3});
+
+        assert_eq!(resolver.resolve_utxo_since(&scripts, 0).await.unwrap(), all);
+    }
+
+    #[cfg(feature = "miniscript_descriptors")]
+    #[tokio::test]
+    async fn descriptor_utxo() {
+        use std::str::FromStr;
+
+        use bitcoin::secp256k1::Secp256k1;
+        use bitcoin_hd::{DerivationAccount, SegmentIndexes, UnhardenedIndex};
+
+        let secp = Secp256k1::verification_only();
+        let descriptor = miniscript::Descriptor::<DerivationAccount>::from_str(
+            "wpkh([d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VU\
+             NgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/*/*)",
+        )
+        .unwrap();
+        let resolver = resolver();
+
+        let utxo = resolver
+            .resolve_descriptor_utxo_since(
+                &secp,
+                &descriptor,
+                [UnhardenedIndex::zero()],
+                UnhardenedIndex::from(5u8),
+                3,
+                100,
+            )
+            .await
+            .unwrap();
+        assert_eq!(utxo.keys().copied().collect::<Vec<_>>(), vec![
+            UnhardenedIndex::from(5u8),
+            UnhardenedIndex::from(6u8),
+            UnhardenedIndex::from(7u8)
+        ]);
+        let scripts = utxo
+            .values()
+            .map(|(script, _)| script.clone())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(scripts.len(), 3);
+        assert!(scripts.iter().all(Script::is_v0_p2wpkh));
+        assert!(utxo.values().all(|(_, set)| set.len() == 3));
+
+        let all = resolver
+            .resolve_descriptor_utxo(
+                &secp,
+                &descriptor,
+                [UnhardenedIndex::zero()],
+                UnhardenedIndex::from(5u8),
+                3,
+            )
+            .await
+            .unwrap();
+        assert!(all.values().all(|(_, set)| set.len() == 4));
+
+        let err = resolver
+            .resolve_descriptor_utxo(
+                &secp,
+                &descriptor,
+                [UnhardenedIndex::zero()],
+                UnhardenedIndex::largest(),
+                2,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            UtxoResolverError::IndexOutOfRange(index)
+                if index == UnhardenedIndex::largest().first_index() as usize + 1
+        ));
+    }
+}
@@ -10,12 +10,23 @@
 // If not, see <https://opensource.org/licenses/Apache-2.0>.
 
 use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+use std::ops::Deref;
+use std::thread;
+use std::time::Duration;
 
 use bitcoin::{Script, Transaction, Txid};
-use electrum_client::{Client, ElectrumApi};
+#[cfg(feature = "miniscript_descriptors")]
+use descriptors::locks::{BlockInfo, MEDIAN_TIME_SPAN};
+use electrum_client::{Client, ConfigBuilder, ElectrumApi, Error, ListUnspentRes, Socks5Config};
 
-use super::{ResolveTx, ResolveTxFee, ResolveUtxo, TxResolverError, UtxoResolverError};
-use crate::blockchain::Utxo;
+#[cfg(feature = "miniscript_descriptors")]
+use super::{BlockResolverError, ResolveBlockInfo};
+use super::{
+    ResolveTx, ResolveTxFee, ResolveTxGraph, ResolveUtxo, TxResolverError, UtxoResolverError,
+};
+use crate::blockchain::{MiningStatus, Utxo};
+use crate::{BroadcastError, BroadcastTx, PublicNetwork};
 
 impl ResolveTx for Client {
     fn resolve_tx(&self, txid: Txid) -> Result<Transaction, TxResolverError> {
@@ -52,15 +63,524 @@ impl ResolveTxFee for Client {
     }
 }
 
+// Electrum servers index transactions by scripts only, so we use history of
+// the transaction output scripts to detect mining status and spendings
+impl ResolveTxGraph for Client {
+    fn resolve_tx_status(&self, tx: &Transaction) -> Result<MiningStatus, TxResolverError> {
+        let txid = tx.txid();
+        let script = match tx.output.first() {
+            Some(txout) => &txout.script_pubkey,
+            None => return Ok(MiningStatus::UnknownTx),
+        };
+        let history = self
+            .script_get_history(script)
+            .map_err(|err| TxResolverError {
+                txid,
+                err: Some(Box::new(err)),
+            })?;
+        Ok(history
+            .into_iter()
+            .find(|entry| entry.tx_hash == txid)
+            .map(|entry| match entry.height {
+                height if height > 0 => MiningStatus::Blockchain(height as u64),
+                _ => MiningStatus::Mempool,
+            })
+            .unwrap_or(MiningStatus::UnknownTx))
+    }
+
+    fn resolve_spending_txs(&self, tx: &Transaction) -> Result<Vec<Transaction>, TxResolverError> {
+        let txid = tx.txid();
+        let map_err = |err: Error| TxResolverError {
+            txid,
+            err: Some(Box::new(err)),
+        };
+        let scripts = tx
+            .output
+            .iter()
+            .map(|txout| &txout.script_pubkey)
+            .filter(|script| !script.is_op_return())
+            .collect::<Vec<_>>();
+        let txids = self
+            .batch_script_get_history(scripts)
+            .map_err(map_err)?
+            .into_iter()
+            .flatten()
+            .map(|entry| entry.tx_hash)
+            .filter(|tx_hash| *tx_hash != txid)
+            .collect::<HashSet<_>>();
+        Ok(self
+            .batch_transaction_get(&txids)
+            .map_err(map_err)?
+            .into_iter()
+            .filter(|child| {
+                child
+                    .input
+                    .iter()
+                    .any(|txin| txin.previous_output.txid == txid)
+            })
+            .collect())
+    }
+}
+
+/// Transport protocol used for connecting electrum server
+#[derive(
+    Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Default
+)]
+pub enum ElectrumProtocol {
+    /// Plain TCP connection
+    #[default]
+    #[display("tcp")]
+    Tcp,
+
+    /// TLS-encrypted connection
+    #[display("ssl")]
+    Ssl,
+}
+
+/// Errors parsing electrum server URL
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ElectrumUrlError {
+    /// unsupported electrum server protocol `{0}`; only `tcp` and `ssl` are
+    /// supported
+    UnsupportedProtocol(String),
+
+    /// invalid electrum server port `{0}`
+    InvalidPort(String),
+
+    /// electrum server URL does not contain host name
+    NoHost,
+}
+
+/// Electrum server connection configuration, used for constructing electrum
+/// [`Client`] and [`ElectrumResolver`].
+///
+/// Displays as an URL in `protocol://host:port` format, which is accepted by
+/// [`Client::from_config`].
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ElectrumConfig {
+    /// Transport protocol
+    pub protocol: ElectrumProtocol,
+    /// Server host name or IP address (including Tor onion addresses)
+    pub host: String,
+    /// Server port
+    pub port: u16,
+    /// SOCKS5 proxy (`host:port`) to connect through, like a Tor daemon
+    pub proxy: Option<String>,
+    /// Connection timeout in seconds; can't be used together with a proxy
+    pub timeout: Option<u8>,
+    /// Number of times a failed request is retried
+    pub retries: u8,
+    /// Whether server TLS certificate must be validated against its domain;
+    /// used only with [`ElectrumProtocol::Ssl`]
+    pub validate_domain: bool,
+}
+
+impl Display for ElectrumConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}:{}", self.protocol, self.host, self.port)
+    }
+}
+
+impl ElectrumConfig {
+    /// Constructs configuration for a direct connection to the server
+    pub fn new(protocol: ElectrumProtocol, host: impl ToString, port: u16) -> ElectrumConfig {
+        ElectrumConfig {
+            protocol,
+            host: host.to_string(),
+            port,
+            proxy: None,
+            timeout: None,
+            retries: 1,
+            validate_domain: true,
+        }
+    }
+
+    /// Parses server URL in `[protocol://]host[:port]` format. If the
+    /// protocol is not specified, `default_protocol` is used; if the port is
+    /// not specified, the default port for the protocol and network is used.
+    pub fn parse(
+        url: &str,
+        default_protocol: ElectrumProtocol,
+        network: PublicNetwork,
+    ) -> Result<ElectrumConfig, ElectrumUrlError> {
+        let (protocol, addr) = match url.split_once("://") {
+            Some(("tcp", addr)) => (ElectrumProtocol::Tcp, addr),
+            Some(("ssl", addr)) => (ElectrumProtocol::Ssl, addr),
+            Some((protocol, _)) => {
+                return Err(ElectrumUrlError::UnsupportedProtocol(protocol.to_owned()))
+            }
+            None => (default_protocol, url),
+        };
+        let (host, port) = match addr.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| ElectrumUrlError::InvalidPort(port.to_owned()))?;
+                (host, port)
+            }
+            None => (addr, match protocol {
+                ElectrumProtocol::Tcp => network.electrum_port(),
+                ElectrumProtocol::Ssl => network.electrum_ssl_port(),
+            }),
+        };
+        if host.is_empty() {
+            return Err(ElectrumUrlError::NoHost);
+        }
+        Ok(ElectrumConfig::new(protocol, host, port))
+    }
+
+    /// Constructs electrum client configuration
+    pub fn client_config(&self) -> Result<electrum_client::Config, Error> {
+        let mut builder = ConfigBuilder::new()
+            .retry(self.retries)
+            .validate_domain(self.validate_domain)
+            .timeout(self.timeout)?;
+        if let Some(proxy) = &self.proxy {
+            builder = builder.socks5(Some(Socks5Config::new(proxy)))?;
+        }
+        Ok(builder.build())
+    }
+
+    /// Connects electrum server
+    pub fn connect(&self) -> Result<Client, Error> {
+        Client::from_config(&self.to_string(), self.client_config()?)
+    }
+}
+
+/// Configuration of batched electrum server requests used for UTXO resolution
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BatchConfig {
+    /// Maximal number of scripts queried in a single batch request
+    pub batch_size: usize,
+    /// Number of times a batch request is retried after a transport error
+    pub retries: u8,
+    /// Delay before retrying failed batch request
+    pub retry_delay: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            batch_size: 100,
+            retries: 3,
+            retry_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+impl BatchConfig {
+    fn list_unspent<'script>(
+        &self,
+        client: &Client,
+        scripts: impl IntoIterator<Item = &'script Script>,
+    ) -> Result<Vec<HashSet<Utxo>>, UtxoResolverError> {
+        self.batched(scripts, |batch| {
+            client.batch_script_list_unspent(batch.iter().copied())
+        })
+    }
+
+    fn batched<'script>(
+        &self,
+        scripts: impl IntoIterator<Item = &'script Script>,
+        request: impl Fn(&[&'script Script]) -> Result<Vec<Vec<ListUnspentRes>>, Error>,
+    ) -> Result<Vec<HashSet<Utxo>>, UtxoResolverError> {
+        let scripts = scripts.into_iter().collect::<Vec<_>>();
+        let mut utxo_sets = Vec::with_capacity(scripts.len());
+        for batch in scripts.chunks(self.batch_size.max(1)) {
+            utxo_sets.extend(
+                self.with_retries(|| request(batch))?
+                    .into_iter()
+                    .map(|res| res.into_iter().map(Utxo::from).collect()),
+            );
+        }
+        Ok(utxo_sets)
+    }
+
+    fn with_retries(
+        &self,
+        request: impl Fn() -> Result<Vec<Vec<ListUnspentRes>>, Error>,
+    ) -> Result<Vec<Vec<ListUnspentRes>>, Error> {
+        let mut attempt = 0u8;
+        loop {
+            match request() {
+                Err(Error::IOError(_) | Error::SharedIOError(_) | Error::AllAttemptsErrored(_))
+                    if attempt < self.retries =>
+                {
+                    attempt += 1;
+                    thread::sleep(self.retry_delay);
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+/// Electrum client performing UTXO resolution with batched requests according
+/// to a [`BatchConfig`].
+///
+/// Dereferences to the wrapped [`Client`], so it can be used for any other
+/// electrum server requests.
+pub struct ElectrumResolver {
+    client: Client,
+    config: BatchConfig,
+}
+
+impl ElectrumResolver {
+    /// Constructs resolver from electrum client using default batch
+    /// configuration
+    pub fn new(client: Client) -> Self { ElectrumResolver::with(client, BatchConfig::default()) }
+
+    /// Constructs resolver from electrum client using provided batch
+    /// configuration
+    pub fn with(client: Client, config: BatchConfig) -> Self { ElectrumResolver { client, config } }
+
+    /// Connects electrum server and constructs resolver using provided batch
+    /// configuration
+    pub fn connect(electrum: &ElectrumConfig, config: BatchConfig) -> Result<Self, Error> {
+        Ok(ElectrumResolver::with(electrum.connect()?, config))
+    }
+
+    /// Returns batch configuration used by the resolver
+    pub fn config(&self) -> BatchConfig { self.config }
+
+    /// Returns wrapped electrum client
+    pub fn into_client(self) -> Client { self.client }
+}
+
+impl Deref for ElectrumResolver {
+    type Target = Client;
+
+    fn deref(&self) -> &Self::Target { &self.client }
+}
+
 impl ResolveUtxo for Client {
     fn resolve_utxo<'script>(
         &self,
         scripts: impl IntoIterator<Item = &'script Script> + Clone,
     ) -> Result<Vec<HashSet<Utxo>>, UtxoResolverError> {
-        Ok(self
-            .batch_script_list_unspent(scripts)?
-            .into_iter()
-            .map(|res| res.into_iter().map(Utxo::from).collect())
-            .collect())
+        BatchConfig::default().list_unspent(self, scripts)
+    }
+}
+
+impl ResolveUtxo for ElectrumResolver {
+    fn resolve_utxo<'script>(
+        &self,
+        scripts: impl IntoIterator<Item = &'script Script> + Clone,
+    ) -> Result<Vec<HashSet<Utxo>>, UtxoResolverError> {
+        self.config.list_unspent(&self.client, scripts)
+    }
+}
+
+impl ResolveTx for ElectrumResolver {
+    fn resolve_tx(&self, txid: Txid) -> Result<Transaction, TxResolverError> {
+        self.client.resolve_tx(txid)
+    }
+}
+
+impl ResolveTxGraph for ElectrumResolver {
+    fn resolve_tx_status(&self, tx: &Transaction) -> Result<MiningStatus, TxResolverError> {
+        self.client.resolve_tx_status(tx)
+    }
+
+    fn resolve_spending_txs(&self, tx: &Transaction) -> Result<Vec<Transaction>, TxResolverError> {
+        self.client.resolve_spending_txs(tx)
+    }
+}
+
+impl ResolveTxFee for ElectrumResolver {
+    fn resolve_tx_fee(&self, txid: Txid) -> Result<Option<(Transaction, u64)>, TxResolverError> {
+        self.client.resolve_tx_fee(txid)
+    }
+}
+
+#[cfg(feature = "miniscript_descriptors")]
+impl ResolveBlockInfo for Client {
+    fn resolve_chain_tip(&self) -> Result<BlockInfo, BlockResolverError> {
+        let tip = self.block_headers_subscribe()?;
+        self.resolve_block_info(tip.height as u32)
+    }
+
+    fn resolve_block_info(&self, height: u32) -> Result<BlockInfo, BlockResolverError> {
+        let start = height.saturating_sub(MEDIAN_TIME_SPAN - 1);
+        let count = (height - start + 1) as usize;
+        let res = self.block_headers(start as usize, count)?;
+        if res.headers.len() != count {
+            return Err(BlockResolverError::UnknownHeight(height));
+        }
+        let mut timestamps = res
+            .headers
+            .iter()
+            .map(|header| header.time)
+            .collect::<Vec<_>>();
+        timestamps.sort_unstable();
+        Ok(BlockInfo {
+            height,
+            median_time_past: timestamps[timestamps.len() / 2],
+        })
+    }
+}
+
+#[cfg(feature = "miniscript_descriptors")]
+impl ResolveBlockInfo for ElectrumResolver {
+    fn resolve_chain_tip(&self) -> Result<BlockInfo, BlockResolverError> {
+        self.client.resolve_chain_tip()
+    }
+
+    fn resolve_block_info(&self, height: u32) -> Result<BlockInfo, BlockResolverError> {
+        self.client.resolve_block_info(height)
+    }
+}
+
+impl From<Error> for BroadcastError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Protocol(value) => {
+                let code = value.get("code").and_then(|code| code.as_i64());
+                match value.get("message").and_then(|msg| msg.as_str()) {
+                    Some(message) => BroadcastError::with_rpc_error(code, message),
+                    None => BroadcastError::with_rpc_error(code, value.to_string()),
+                }
+            }
+            err => BroadcastError::Transport(err.to_string()),
+        }
+    }
+}
+
+impl BroadcastTx for Client {
+    fn broadcast_tx(&self, tx: &Transaction) -> Result<Txid, BroadcastError> {
+        self.transaction_broadcast(tx).map_err(BroadcastError::from)
+    }
+}
+
+impl BroadcastTx for ElectrumResolver {
+    fn broadcast_tx(&self, tx: &Transaction) -> Result<Txid, BroadcastError> {
+        self.client.broadcast_tx(tx)
+    }
+}
+
+// Connects the server only at the moment of broadcasting, such that connection
+// failures can be handled by `FallbackBroadcaster` as transport errors
+impl BroadcastTx for ElectrumConfig {
+    fn broadcast_tx(&self, tx: &Transaction) -> Result<Txid, BroadcastError> {
+        self.connect()?.broadcast_tx(tx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::io;
+
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    type Response = Result<Vec<Vec<ListUnspentRes>>, Error>;
+
+    fn config(batch_size: usize, retries: u8) -> BatchConfig {
+        BatchConfig {
+            batch_size,
+            retries,
+            retry_delay: Duration::from_millis(1),
+        }
+    }
+
+    fn unspent(script: &Script) -> Vec<ListUnspentRes> {
+        vec![ListUnspentRes {
+            height: 100,
+            tx_hash: Txid::hash(script.as_bytes()),
+            tx_pos: 0,
+            value: 1000,
+        }]
+    }
+
+    #[test]
+    fn batches() {
+        let scripts = (0u8..5)
+            .map(|no| Script::new_op_return(&[no]))
+            .collect::<Vec<_>>();
+        let sizes = RefCell::new(vec![]);
+        let request = |batch: &[&Script]| -> Response {
+            sizes.borrow_mut().push(batch.len());
+            Ok(batch.iter().map(|script| unspent(script)).collect())
+        };
+
+        let utxo_sets = config(2, 0).batched(&scripts, request).unwrap();
+        assert_eq!(sizes.take(), vec![2, 2, 1]);
+        assert_eq!(utxo_sets.len(), 5);
+        for (utxo_set, script) in utxo_sets.iter().zip(&scripts) {
+            let utxo = utxo_set.iter().next().unwrap();
+            assert_eq!(utxo_set.len(), 1);
+            assert_eq!(utxo.outpoint().txid, Txid::hash(script.as_bytes()));
+        }
+
+        assert_eq!(config(0, 0).batched(&scripts, request).unwrap(), utxo_sets);
+        assert_eq!(sizes.take(), vec![1; 5]);
+        assert_eq!(config(10, 0).batched(&scripts, request).unwrap(), utxo_sets);
+        assert_eq!(sizes.take(), vec![5]);
+    }
+
+    #[test]
+    fn retries() {
+        let attempts = &RefCell::new(0u8);
+        let failing = |failures: u8| {
+            move || -> Response {
+                *attempts.borrow_mut() += 1;
+                if *attempts.borrow() <= failures {
+                    Err(Error::IOError(io::Error::from(
+                        io::ErrorKind::ConnectionReset,
+                    )))
+                } else {
+                    Ok(vec![])
+                }
+            }
+        };
+
+        assert!(config(1, 2).with_retries(failing(2)).is_ok());
+        assert_eq!(attempts.take(), 3);
+
+        assert!(matches!(
+            config(1, 2).with_retries(failing(3)),
+            Err(Error::IOError(_))
+        ));
+        assert_eq!(attempts.take(), 3);
+
+        let protocol_error = || -> Response {
+            *attempts.borrow_mut() += 1;
+            Err(Error::Message(s!("unknown method")))
+        };
+        assert!(matches!(
+            config(1, 2).with_retries(protocol_error),
+            Err(Error::Message(_))
+        ));
+        assert_eq!(attempts.take(), 1);
+    }
+
+    #[test]
+    fn electrum_url() {
+        let tcp = ElectrumProtocol::Tcp;
+        let ssl = ElectrumProtocol::Ssl;
+        let mainnet = PublicNetwork::Mainnet;
+
+        let config = ElectrumConfig::parse("ssl://electrum.example.com", tcp, mainnet).unwrap();
+        assert_eq!(config.to_string(), "ssl://electrum.example.com:50002");
+        let config = ElectrumConfig::parse("localhost", tcp, PublicNetwork::Testnet).unwrap();
+        assert_eq!(config.to_string(), "tcp://localhost:60001");
+        let config = ElectrumConfig::parse("localhost:50005", ssl, PublicNetwork::Regtest).unwrap();
+        assert_eq!(config.to_string(), "ssl://localhost:50005");
+
+        assert_eq!(
+            ElectrumConfig::parse("http://localhost", tcp, mainnet),
+            Err(ElectrumUrlError::UnsupportedProtocol(s!("http")))
+        );
+        assert_eq!(
+            ElectrumConfig::parse("localhost:port", tcp, mainnet),
+            Err(ElectrumUrlError::InvalidPort(s!("port")))
+        );
+        assert_eq!(
+            ElectrumConfig::parse("tcp://:50001", tcp, mainnet),
+            Err(ElectrumUrlError::NoHost)
+        );
     }
 }
@@ -0,0 +1,135 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use bitcoin::{Amount, OutPoint, Script, Transaction, Txid};
+use esplora_client::AsyncClient;
+
+use super::{
+    AsyncResolveTx, AsyncResolveTxFee, AsyncResolveUtxo, TxResolverError, UtxoResolverError,
+};
+use crate::blockchain::{MiningStatus, Utxo};
+
+/// Number of confirmed transactions returned by esplora server per page of
+/// script history.
+const ESPLORA_PAGE_SIZE: usize = 25;
+
+#[async_trait]
+impl AsyncResolveTx for AsyncClient {
+    async fn resolve_tx(&self, txid: Txid) -> Result<Transaction, TxResolverError> {
+        match self.get_tx(&txid).await {
+            Ok(Some(tx)) => Ok(tx),
+            Ok(None) => Err(TxResolverError::with(txid)),
+            Err(err) => Err(TxResolverError {
+                txid,
+                err: Some(Box::new(err)),
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncResolveTxFee for AsyncClient {
+    async fn resolve_tx_fee(
+        &self,
+        txid: Txid,
+    ) -> Result<Option<(Transaction, u64)>, TxResolverError> {
+        let tx = self.resolve_tx(txid).await?;
+
+        let mut input_amount = 0u64;
+        for input in &tx.input {
+            let prev_tx = self.resolve_tx(input.previous_output.txid).await?;
+            let vout = input.previous_output.vout as usize;
+            input_amount += prev_tx
+                .output
+                .get(vout)
+                .ok_or_else(|| TxResolverError::with(txid))?
+                .value;
+        }
+        let output_amount = tx.output.iter().fold(0, |sum, o| sum + o.value);
+        let fee = input_amount
+            .checked_sub(output_amount)
+            .ok_or_else(|| TxResolverError::with(txid))?;
+
+        Ok(Some((tx, fee)))
+    }
+}
+
+#[async_trait]
+impl AsyncResolveUtxo for AsyncClient {
+    async fn resolve_utxo<'script>(
+        &self,
+        scripts: impl IntoIterator<Item = &'script Script> + Send + 'script,
+    ) -> Result<Vec<HashSet<Utxo>>, UtxoResolverError> {
+        self.resolve_utxo_since(scripts, 0).await
+    }
+
+    /// Esplora returns script history newest-first, so paging stops as soon as
+    /// a page reaches transactions mined before the birthday `height`.
+    async fn resolve_utxo_since<'script>(
+        &self,
+        scripts: impl IntoIterator<Item = &'script Script> + Send + 'script,
+        height: u32,
+    ) -> Result<Vec<HashSet<Utxo>>, UtxoResolverError> {
+        let scripts = scripts.into_iter().collect::<Vec<_>>();
+        let mut utxo_sets = Vec::with_capacity(scripts.len());
+        for script in scripts {
+            let mut utxo_set = HashSet::new();
+            let mut last_seen = None;
+            loop {
+                let txs = self.scripthash_txs(script, last_seen).await?;
+                let mut reached_birthday = false;
+                for tx in &txs {
+                    let mined = match tx.status.block_height {
+                        Some(mined) if tx.status.confirmed && mined < height => {
+                            reached_birthday = true;
+                            continue;
+                        }
+                        Some(mined) if tx.status.confirmed => {
+                            MiningStatus::Blockchain(mined as u64)
+                        }
+                        _ => MiningStatus::Mempool,
+                    };
+                    for (vout, output) in tx.vout.iter().enumerate() {
+                        if &output.scriptpubkey != script {
+                            continue;
+                        }
+                        let spent = self
+                            .get_output_status(&tx.txid, vout as u64)
+                            .await?
+                            .map(|status| status.spent)
+                            .unwrap_or_default();
+                        if !spent {
+                            utxo_set.insert(Utxo::with(
+                                mined,
+                                OutPoint::new(tx.txid, vout as u32),
+                                Amount::from_sat(output.value),
+                            ));
+                        }
+                    }
+                }
+                let confirmed = txs.iter().filter(|tx| tx.status.confirmed).count();
+                last_seen = txs
+                    .iter()
+                    .rev()
+                    .find(|tx| tx.status.confirmed)
+                    .map(|tx| tx.txid);
+                if reached_birthday || confirmed < ESPLORA_PAGE_SIZE {
+                    break;
+                }
+            }
+            utxo_sets.push(utxo_set);
+        }
+        Ok(utxo_sets)
+    }
+}
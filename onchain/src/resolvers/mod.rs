@@ -12,15 +12,25 @@
 //! Resolvers are traits allow accessing or computing information from a
 //! bitcoin transaction graph (from blockchain, state channel, index, PSBT etc).
 
+#[cfg(feature = "async")]
+mod asynchronous;
 #[cfg(feature = "electrum")]
 mod electrum;
+#[cfg(feature = "esplora")]
+mod esplora;
 
 use std::collections::{BTreeMap, HashSet};
 
 use bitcoin::{Script, Transaction, Txid};
 use bitcoin_hd::DeriveError;
+#[cfg(feature = "miniscript_descriptors")]
+use descriptors::locks::BlockInfo;
+#[cfg(feature = "electrum")]
+pub use electrum::{
+    BatchConfig, ElectrumConfig, ElectrumProtocol, ElectrumResolver, ElectrumUrlError,
+};
 
-use crate::blockchain::Utxo;
+use crate::blockchain::{MiningStatus, Utxo};
 
 #[derive(Debug, Display, Error)]
 #[display(doc_comments)]
@@ -54,6 +64,11 @@ pub enum UtxoResolverError {
     #[from]
     Electrum(electrum_client::Error),
 
+    /// esplora server error {0}
+    #[cfg(feature = "esplora")]
+    #[from]
+    Esplora(esplora_client::Error),
+
     /// Derivation error
     #[from]
     #[display(inner)]
@@ -71,6 +86,29 @@ pub trait ResolveUtxo {
         &self,
         scripts: impl IntoIterator<Item = &'script Script> + Clone,
     ) -> Result<Vec<HashSet<Utxo>>, UtxoResolverError>;
+
+    /// Finds UTXO set for the provided address lists, skipping outputs mined
+    /// before the wallet birthday block `height`.
+    ///
+    /// Resolvers which are able to skip blockchain history before a given
+    /// height should override the default implementation, which filters
+    /// results of [`ResolveUtxo::resolve_utxo`].
+    fn resolve_utxo_since<'script>(
+        &self,
+        scripts: impl IntoIterator<Item = &'script Script> + Clone,
+        height: u32,
+    ) -> Result<Vec<HashSet<Utxo>>, UtxoResolverError> {
+        Ok(self
+            .resolve_utxo(scripts)?
+            .into_iter()
+            .map(|utxo_set| {
+                utxo_set
+                    .into_iter()
+                    .filter(|utxo| !utxo.is_mined_before(height))
+                    .collect()
+            })
+            .collect())
+    }
 }
 
 #[cfg(feature = "miniscript_descriptors")]
@@ -87,6 +125,45 @@ mod _miniscript_descriptors {
     use crate::blockchain::Utxo;
     use crate::{ResolveUtxo, UtxoResolverError};
 
+    /// Derives scripts for `count` addresses of the descriptor starting with
+    /// `from_index`
+    pub(crate) fn descriptor_scripts<C: Verification>(
+        secp: &Secp256k1<C>,
+        descriptor: &miniscript::Descriptor<DerivationAccount>,
+        terminal_derivation: &[UnhardenedIndex],
+        from_index: UnhardenedIndex,
+        count: u32,
+    ) -> Result<BTreeMap<UnhardenedIndex, Script>, UtxoResolverError> {
+        let mut derivation = Vec::<UnhardenedIndex>::with_capacity(terminal_derivation.len() + 1);
+        derivation.extend(terminal_derivation);
+        derivation.push(UnhardenedIndex::zero());
+        let derivation = Rc::new(RefCell::new(derivation));
+
+        let indexes = (0..count)
+            .map(|offset| {
+                from_index.checked_add(offset).ok_or_else(|| {
+                    UtxoResolverError::IndexOutOfRange(
+                        from_index.first_index() as usize + offset as usize,
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, UtxoResolverError>>()?;
+
+        let scripts = indexes
+            .into_iter()
+            .map(|index| {
+                if let Some(i) = derivation.borrow_mut().last_mut() {
+                    *i = index
+                }
+                Ok((
+                    index,
+                    descriptor.script_pubkey_pretr(secp, &*derivation.borrow())?,
+                ))
+            })
+            .collect::<Result<BTreeMap<_, _>, DeriveError>>()?;
+        Ok(scripts)
+    }
+
     /// Does complex resolution for miniscript descriptors
     pub trait ResolveDescriptor: ResolveUtxo {
         /// Finds UTXO set for the addresses derivable from the given descriptor
@@ -98,38 +175,38 @@ mod _miniscript_descriptors {
             from_index: UnhardenedIndex,
             count: u32,
         ) -> Result<BTreeMap<UnhardenedIndex, (Script, HashSet<Utxo>)>, UtxoResolverError> {
-            let terminal_derivation = terminal_derivation.as_ref();
-            let mut derivation =
-                Vec::<UnhardenedIndex>::with_capacity(terminal_derivation.len() + 1);
-            derivation.extend(terminal_derivation);
-            derivation.push(UnhardenedIndex::zero());
-            let derivation = Rc::new(RefCell::new(derivation));
-
-            let indexes = (0..count)
-                .map(|offset| {
-                    from_index.checked_add(offset).ok_or_else(|| {
-                        UtxoResolverError::IndexOutOfRange(
-                            from_index.first_index() as usize + offset as usize,
-                        )
-                    })
-                })
-                .collect::<Result<Vec<_>, UtxoResolverError>>()?;
+            self.resolve_descriptor_utxo_since(
+                secp,
+                descriptor,
+                terminal_derivation,
+                from_index,
+                count,
+                0,
+            )
+        }
 
-            let scripts = indexes
-                .into_iter()
-                .map(|index| {
-                    if let Some(i) = derivation.borrow_mut().last_mut() {
-                        *i = index
-                    }
-                    Ok((
-                        index,
-                        descriptor.script_pubkey_pretr(secp, &*derivation.borrow())?,
-                    ))
-                })
-                .collect::<Result<BTreeMap<_, _>, DeriveError>>()?;
+        /// Finds UTXO set for the addresses derivable from the given
+        /// descriptor, skipping outputs mined before the wallet birthday block
+        /// `height`
+        fn resolve_descriptor_utxo_since<C: Verification>(
+            &self,
+            secp: &Secp256k1<C>,
+            descriptor: &miniscript::Descriptor<DerivationAccount>,
+            terminal_derivation: impl AsRef<[UnhardenedIndex]>,
+            from_index: UnhardenedIndex,
+            count: u32,
+            height: u32,
+        ) -> Result<BTreeMap<UnhardenedIndex, (Script, HashSet<Utxo>)>, UtxoResolverError> {
+            let scripts = descriptor_scripts(
+                secp,
+                descriptor,
+                terminal_derivation.as_ref(),
+                from_index,
+                count,
+            )?;
 
             Ok(self
-                .resolve_utxo(scripts.values())?
+                .resolve_utxo_since(scripts.values(), height)?
                 .into_iter()
                 .zip(scripts.keys())
                 .zip(scripts.values())
@@ -143,6 +220,62 @@ mod _miniscript_descriptors {
 #[cfg(feature = "miniscript_descriptors")]
 pub use _miniscript_descriptors::ResolveDescriptor;
 
+/// Errors during block information resolution
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum BlockResolverError {
+    /// electrum server error {0}
+    #[cfg(feature = "electrum")]
+    #[from]
+    Electrum(electrum_client::Error),
+
+    /// block at height {0} is unknown
+    UnknownHeight(u32),
+}
+
+/// Resolver of block heights and median time past, required to evaluate
+/// timelock policies (see [`descriptors::locks::Policy`])
+#[cfg(feature = "miniscript_descriptors")]
+pub trait ResolveBlockInfo {
+    /// Returns height and median time past of the current chain tip
+    fn resolve_chain_tip(&self) -> Result<BlockInfo, BlockResolverError>;
+
+    /// Returns height and median time past of the block at a given height
+    fn resolve_block_info(&self, height: u32) -> Result<BlockInfo, BlockResolverError>;
+
+    /// Returns height of the last block which median time past precedes a
+    /// given UNIX timestamp, or zero if there is no such block. Used to
+    /// convert time-based wallet birthday into a block height.
+    fn resolve_height_before(&self, time: u32) -> Result<u32, BlockResolverError> {
+        let tip = self.resolve_chain_tip()?;
+        if tip.median_time_past < time {
+            return Ok(tip.height);
+        }
+        // Median time past is monotonic, so binary search keeps the block at
+        // `low` preceding the timestamp and the block at `high` following it
+        let (mut low, mut high) = (0u32, tip.height);
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            if self.resolve_block_info(mid)?.median_time_past < time {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(low)
+    }
+
+    /// Returns anchor block for the relative timelocks of an output confirmed
+    /// in the block at `confirmation_height`, which is the preceding block
+    fn resolve_anchor(&self, confirmation_height: u32) -> Result<BlockInfo, BlockResolverError> {
+        self.resolve_block_info(confirmation_height.saturating_sub(1))
+    }
+}
+#[cfg(all(feature = "async", feature = "miniscript_descriptors"))]
+pub use asynchronous::AsyncResolveDescriptor;
+#[cfg(feature = "async")]
+pub use asynchronous::{AsyncResolveTx, AsyncResolveTxFee, AsyncResolveUtxo};
+
 impl ResolveTx for BTreeMap<Txid, Transaction> {
     fn resolve_tx(&self, txid: Txid) -> Result<Transaction, TxResolverError> {
         self.get(&txid)
@@ -151,6 +284,17 @@ impl ResolveTx for BTreeMap<Txid, Transaction> {
     }
 }
 
+/// Resolver of the transaction graph information: mining status of
+/// transactions and transactions spending their outputs
+pub trait ResolveTxGraph: ResolveTx {
+    /// Returns mining status of a given transaction
+    fn resolve_tx_status(&self, tx: &Transaction) -> Result<MiningStatus, TxResolverError>;
+
+    /// Returns all known (mined or mempool) transactions spending outputs of a
+    /// given transaction
+    fn resolve_spending_txs(&self, tx: &Transaction) -> Result<Vec<Transaction>, TxResolverError>;
+}
+
 /// Transaction resolver
 pub trait ResolveTxFee {
     /// Tries to find a transaction and comput its fee by transaction id
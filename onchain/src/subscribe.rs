@@ -0,0 +1,549 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Subscriptions to script (address) status notifications, allowing to watch
+//! wallet for new incoming transactions and changes in their mining status.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use bitcoin::{Script, Txid};
+use bitcoin_hd::{DeriveError, SegmentIndexes, UnhardenedIndex};
+
+use crate::blockchain::MiningStatus;
+
+/// Errors watching wallet scripts
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum SubscribeError {
+    /// electrum server error {0}
+    #[cfg(feature = "electrum")]
+    #[from]
+    Electrum(electrum_client::Error),
+
+    /// unable to derive watched script: {0}
+    #[from]
+    Derive(DeriveError),
+
+    /// keychain {0} has no more derivable scripts
+    IndexOverflow(usize),
+}
+
+/// Backend providing notifications about script status changes
+pub trait SubscribeScript {
+    /// Opaque status of the script, which changes each time a transaction
+    /// affecting the script appears or changes its mining status
+    type Status: Clone + Eq + Debug;
+
+    /// Subscribes to script status changes, returning current script status,
+    /// which is `None` for scripts without transaction history.
+    fn subscribe_script(&self, script: &Script) -> Result<Option<Self::Status>, SubscribeError>;
+
+    /// Returns the latest script status received since the last call, if
+    /// any.
+    fn poll_script(&self, script: &Script) -> Result<Option<Self::Status>, SubscribeError>;
+
+    /// Returns transactions affecting the script together with their mining
+    /// status
+    fn script_history(&self, script: &Script) -> Result<Vec<(Txid, MiningStatus)>, SubscribeError>;
+
+    /// Processes notifications received by the backend since the last call.
+    /// Must be called before polling scripts; default implementation does
+    /// nothing.
+    fn refresh(&self) -> Result<(), SubscribeError> { Ok(()) }
+}
+
+/// Events detected by [`ScriptWatcher`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(doc_comments)]
+pub enum WatchEvent {
+    /// new transaction {txid} affecting script {keychain}/{index} ({status})
+    NewTx {
+        /// Keychain of the script
+        keychain: usize,
+        /// Index of the script inside the keychain
+        index: UnhardenedIndex,
+        /// Transaction id
+        txid: Txid,
+        /// Mining status of the transaction
+        status: MiningStatus,
+    },
+
+    /// transaction {txid} mining status changed from {from} to {to}
+    StatusChange {
+        /// Transaction id
+        txid: Txid,
+        /// Previous mining status
+        from: MiningStatus,
+        /// New mining status
+        to: MiningStatus,
+    },
+
+    /// watching {count} scripts of keychain {keychain}
+    Extended {
+        /// Keychain which got new scripts subscribed
+        keychain: usize,
+        /// Total number of subscribed keychain scripts
+        count: u32,
+    },
+}
+
+impl WatchEvent {
+    /// Detects whether the event signals a chain reorganization, i.e. a
+    /// transaction mined in some block returned to mempool or got mined in a
+    /// block at a different height
+    pub fn is_reorg(&self) -> bool {
+        matches!(
+            self,
+            WatchEvent::StatusChange {
+                from: MiningStatus::Blockchain(from),
+                to,
+                ..
+            } if *to != MiningStatus::Blockchain(*from)
+        )
+    }
+}
+
+/// Tracker of the transaction confirmation depths, detecting transactions
+/// which reached one of the configured numbers of confirmations
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ConfirmationTracker {
+    thresholds: Vec<u32>,
+    reached: BTreeMap<Txid, u32>,
+}
+
+impl ConfirmationTracker {
+    /// Constructs tracker for the given confirmation thresholds
+    pub fn with(thresholds: impl IntoIterator<Item = u32>) -> Self {
+        let mut thresholds = thresholds
+            .into_iter()
+            .filter(|threshold| *threshold > 0)
+            .collect::<Vec<_>>();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        ConfirmationTracker {
+            thresholds,
+            reached: empty!(),
+        }
+    }
+
+    /// Updates confirmation depths of the transactions for the given height
+    /// of the blockchain tip, returning transactions which reached a new
+    /// threshold since the previous call together with the highest threshold
+    /// they reached.
+    ///
+    /// The first call reports all already confirmed transactions.
+    /// Transactions which are no longer mined (for instance due to chain
+    /// reorganization) are reported again once they reach thresholds anew.
+    pub fn update(
+        &mut self,
+        txs: &BTreeMap<Txid, MiningStatus>,
+        tip_height: u64,
+    ) -> Vec<(Txid, u32)> {
+        let mut reached = vec![];
+        for (txid, status) in txs {
+            let confirmations = match status {
+                MiningStatus::Blockchain(height) if *height <= tip_height => {
+                    (tip_height - height + 1).min(u32::MAX as u64) as u32
+                }
+                _ => {
+                    self.reached.remove(txid);
+                    continue;
+                }
+            };
+            let threshold = match self
+                .thresholds
+                .iter()
+                .rev()
+                .find(|threshold| **threshold <= confirmations)
+            {
+                Some(threshold) => *threshold,
+                None => continue,
+            };
+            if self.reached.insert(*txid, threshold) != Some(threshold) {
+                reached.push((*txid, threshold));
+            }
+        }
+        reached
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+struct KeychainState {
+    subscribed: u32,
+    used: Option<u32>,
+}
+
+/// Watcher of a set of keychains, which are sequences of scripts derived by
+/// index (like receive and change addresses of a wallet descriptor).
+///
+/// For each keychain the watcher keeps `gap_limit` scripts following the last
+/// used one subscribed, extending subscriptions once new scripts get used.
+pub struct ScriptWatcher<'backend, B, F>
+where
+    B: SubscribeScript,
+    F: FnMut(usize, UnhardenedIndex) -> Result<Script, DeriveError>,
+{
+    backend: &'backend B,
+    derive: F,
+    gap_limit: u32,
+    keychains: Vec<KeychainState>,
+    scripts: BTreeMap<Script, (usize, UnhardenedIndex, Option<B::Status>)>,
+    txs: BTreeMap<Txid, MiningStatus>,
+}
+
+impl<'backend, B, F> ScriptWatcher<'backend, B, F>
+where
+    B: SubscribeScript,
+    F: FnMut(usize, UnhardenedIndex) -> Result<Script, DeriveError>,
+{
+    /// Subscribes to the scripts of `keychains` number of keychains, produced
+    /// by `derive` function from the keychain number and script index.
+    ///
+    /// Transactions known at the moment of subscription are not reported as
+    /// events and are accessible via [`ScriptWatcher::transactions`].
+    pub fn with(
+        backend: &'backend B,
+        keychains: usize,
+        gap_limit: u32,
+        derive: F,
+    ) -> Result<Self, SubscribeError> {
+        let mut watcher = ScriptWatcher {
+            backend,
+            derive,
+            gap_limit: gap_limit.max(1),
+            keychains: vec![KeychainState::default(); keychains],
+            scripts: empty!(),
+            txs: empty!(),
+        };
+        for keychain in 0..keychains {
+            watcher.subscribe(keychain, None)?;
+        }
+        Ok(watcher)
+    }
+
+    /// Returns all transactions affecting watched scripts with their mining
+    /// status
+    pub fn transactions(&self) -> &BTreeMap<Txid, MiningStatus> { &self.txs }
+
+    /// Returns number of subscribed scripts
+    pub fn script_count(&self) -> usize { self.scripts.len() }
+
+    /// Returns keychain and index of a subscribed script, if the script is
+    /// watched
+    pub fn script_index(&self, script: &Script) -> Option<(usize, UnhardenedIndex)> {
+        self.scripts
+            .get(script)
+            .map(|(keychain, index, _)| (*keychain, *index))
+    }
+
+    /// Returns index of the last used script in a keychain, if any
+    pub fn last_used(&self, keychain: usize) -> Option<UnhardenedIndex> {
+        self.keychains
+            .get(keychain)?
+            .used
+            .and_then(|index| UnhardenedIndex::from_index(index).ok())
+    }
+
+    /// Checks notifications received from the backend, returning events for
+    /// the new transactions and mining status changes, and extends
+    /// subscriptions if new scripts were used.
+    pub fn poll(&mut self) -> Result<Vec<WatchEvent>, SubscribeError> {
+        self.backend.refresh()?;
+
+        let mut changed = vec![];
+        for (script, (_, _, status)) in &mut self.scripts {
+            match self.backend.poll_script(script)? {
+                Some(new) if status.as_ref() != Some(&new) => {
+                    *status = Some(new);
+                    changed.push(script.clone());
+                }
+                _ => {}
+            }
+        }
+
+        let mut events = vec![];
+        for script in changed {
+            self.update_history(&script, Some(&mut events))?;
+        }
+        for keychain in 0..self.keychains.len() {
+            self.subscribe(keychain, Some(&mut events))?;
+        }
+        Ok(events)
+    }
+
+    fn subscribe(
+        &mut self,
+        keychain: usize,
+        mut events: Option<&mut Vec<WatchEvent>>,
+    ) -> Result<(), SubscribeError> {
+        loop {
+            let state = self.keychains[keychain];
+            let target = state
+                .used
+                .map(|index| index.saturating_add(1))
+                .unwrap_or_default()
+                .saturating_add(self.gap_limit);
+            if state.subscribed >= target {
+                return Ok(());
+            }
+            for no in state.subscribed..target {
+                let index = UnhardenedIndex::from_index(no)
+                    .map_err(|_| SubscribeError::IndexOverflow(keychain))?;
+                let script = (self.derive)(keychain, index)?;
+                let status = self.backend.subscribe_script(&script)?;
+                let used = status.is_some();
+                self.scripts
+                    .insert(script.clone(), (keychain, index, status));
+                if used {
+                    self.update_history(&script, events.as_deref_mut())?;
+                }
+            }
+            self.keychains[keychain].subscribed = target;
+            if let Some(events) = events.as_deref_mut() {
+                events.push(WatchEvent::Extended {
+                    keychain,
+                    count: target,
+                });
+            }
+        }
+    }
+
+    fn update_history(
+        &mut self,
+        script: &Script,
+        mut events: Option<&mut Vec<WatchEvent>>,
+    ) -> Result<(), SubscribeError> {
+        let (keychain, index) = match self.scripts.get(script) {
+            Some((keychain, index, _)) => (*keychain, *index),
+            None => return Ok(()),
+        };
+        let state = &mut self.keychains[keychain];
+        state.used = state.used.max(Some(index.first_index()));
+
+        for (txid, status) in self.backend.script_history(script)? {
+            let event = match self.txs.insert(txid, status) {
+                None => WatchEvent::NewTx {
+                    keychain,
+                    index,
+                    txid,
+                    status,
+                },
+                Some(from) if from != status => WatchEvent::StatusChange {
+                    txid,
+                    from,
+                    to: status,
+                },
+                Some(_) => continue,
+            };
+            if let Some(events) = events.as_mut() {
+                events.push(event);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "electrum")]
+mod electrum {
+    use electrum_client::{Client, ElectrumApi, ScriptStatus};
+
+    use super::*;
+
+    impl SubscribeScript for Client {
+        type Status = ScriptStatus;
+
+        fn subscribe_script(
+            &self,
+            script: &Script,
+        ) -> Result<Option<Self::Status>, SubscribeError> {
+            Ok(self.script_subscribe(script)?)
+        }
+
+        fn poll_script(&self, script: &Script) -> Result<Option<Self::Status>, SubscribeError> {
+            let mut last = None;
+            while let Some(status) = self.script_pop(script)? {
+                last = Some(status);
+            }
+            Ok(last)
+        }
+
+        fn script_history(
+            &self,
+            script: &Script,
+        ) -> Result<Vec<(Txid, MiningStatus)>, SubscribeError> {
+            Ok(self
+                .script_get_history(script)?
+                .into_iter()
+                .map(|entry| {
+                    let status = match entry.height {
+                        height if height > 0 => MiningStatus::Blockchain(height as u64),
+                        _ => MiningStatus::Mempool,
+                    };
+                    (entry.tx_hash, status)
+                })
+                .collect())
+        }
+
+        // Electrum client reads notifications only when processing responses
+        // to other requests
+        fn refresh(&self) -> Result<(), SubscribeError> { Ok(self.ping()?) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use bitcoin::hashes::Hash;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MockBackend {
+        history: RefCell<BTreeMap<Script, Vec<(Txid, MiningStatus)>>>,
+        notifications: RefCell<BTreeMap<Script, usize>>,
+    }
+
+    impl MockBackend {
+        fn add_tx(&self, script: &Script, txid: Txid, status: MiningStatus) {
+            let mut history = self.history.borrow_mut();
+            let entries = history.entry(script.clone()).or_default();
+            entries.retain(|(id, _)| *id != txid);
+            entries.push((txid, status));
+            self.notifications
+                .borrow_mut()
+                .insert(script.clone(), entries.len() * 10 + status_code(status));
+        }
+    }
+
+    fn status_code(status: MiningStatus) -> usize {
+        match status {
+            MiningStatus::Blockchain(height) => height as usize,
+            _ => 1,
+        }
+    }
+
+    impl SubscribeScript for MockBackend {
+        type Status = usize;
+
+        fn subscribe_script(&self, script: &Script) -> Result<Option<usize>, SubscribeError> {
+            Ok(self.history.borrow().get(script).map(Vec::len))
+        }
+
+        fn poll_script(&self, script: &Script) -> Result<Option<usize>, SubscribeError> {
+            Ok(self.notifications.borrow_mut().remove(script))
+        }
+
+        fn script_history(
+            &self,
+            script: &Script,
+        ) -> Result<Vec<(Txid, MiningStatus)>, SubscribeError> {
+            Ok(self
+                .history
+                .borrow()
+                .get(script)
+                .cloned()
+                .unwrap_or_default())
+        }
+    }
+
+    fn script(keychain: usize, index: UnhardenedIndex) -> Script {
+        Script::from(vec![keychain as u8, index.first_index() as u8])
+    }
+
+    #[test]
+    fn gap_limit_watch() {
+        let backend = MockBackend::default();
+        let tx1 = Txid::hash(b"tx1");
+        let tx2 = Txid::hash(b"tx2");
+        let index = |no: u8| UnhardenedIndex::from(no);
+
+        backend.add_tx(&script(0, index(0)), tx1, MiningStatus::Mempool);
+        backend.notifications.borrow_mut().clear();
+
+        let mut watcher = ScriptWatcher::with(&backend, 2, 2, |keychain, index| {
+            Ok(script(keychain, index))
+        })
+        .unwrap();
+        assert_eq!(watcher.script_count(), 5);
+        assert_eq!(watcher.last_used(0), Some(index(0)));
+        assert_eq!(watcher.last_used(1), None);
+        assert_eq!(
+            watcher.transactions().get(&tx1),
+            Some(&MiningStatus::Mempool)
+        );
+        assert_eq!(watcher.poll().unwrap(), vec![]);
+
+        backend.add_tx(&script(0, index(0)), tx1, MiningStatus::Blockchain(100));
+        backend.add_tx(&script(0, index(2)), tx2, MiningStatus::Mempool);
+        assert_eq!(watcher.poll().unwrap(), vec![
+            WatchEvent::StatusChange {
+                txid: tx1,
+                from: MiningStatus::Mempool,
+                to: MiningStatus::Blockchain(100),
+            },
+            WatchEvent::NewTx {
+                keychain: 0,
+                index: index(2),
+                txid: tx2,
+                status: MiningStatus::Mempool,
+            },
+            WatchEvent::Extended {
+                keychain: 0,
+                count: 5,
+            },
+        ]);
+        assert_eq!(watcher.script_count(), 7);
+        assert_eq!(watcher.last_used(0), Some(index(2)));
+    }
+
+    #[test]
+    fn reorg_events() {
+        let txid = Txid::hash(b"tx");
+        let event = |from, to| WatchEvent::StatusChange { txid, from, to };
+        assert!(event(MiningStatus::Blockchain(100), MiningStatus::Mempool).is_reorg());
+        assert!(event(MiningStatus::Blockchain(100), MiningStatus::Blockchain(101)).is_reorg());
+        assert!(!event(MiningStatus::Mempool, MiningStatus::Blockchain(100)).is_reorg());
+        assert!(!event(MiningStatus::Blockchain(100), MiningStatus::Blockchain(100)).is_reorg());
+        assert!(!WatchEvent::Extended {
+            keychain: 0,
+            count: 20
+        }
+        .is_reorg());
+    }
+
+    #[test]
+    fn confirmation_thresholds() {
+        let tx1 = Txid::hash(b"tx1");
+        let tx2 = Txid::hash(b"tx2");
+        let mut tracker = ConfirmationTracker::with([6, 1, 0, 6]);
+        let mut txs = BTreeMap::new();
+        txs.insert(tx1, MiningStatus::Mempool);
+        assert_eq!(tracker.update(&txs, 100), vec![]);
+
+        txs.insert(tx1, MiningStatus::Blockchain(101));
+        txs.insert(tx2, MiningStatus::Blockchain(90));
+        assert_eq!(tracker.update(&txs, 101), vec![(tx1, 1), (tx2, 6)]);
+        assert_eq!(tracker.update(&txs, 102), vec![]);
+        assert_eq!(tracker.update(&txs, 106), vec![(tx1, 6)]);
+
+        // Reorged transaction is reported again once re-mined
+        txs.insert(tx1, MiningStatus::Mempool);
+        assert_eq!(tracker.update(&txs, 106), vec![]);
+        txs.insert(tx1, MiningStatus::Blockchain(107));
+        assert_eq!(tracker.update(&txs, 107), vec![(tx1, 1)]);
+
+        // Transactions mined above the known tip are not confirmed yet
+        txs.insert(tx2, MiningStatus::Blockchain(108));
+        assert_eq!(tracker.update(&txs, 107), vec![]);
+    }
+}
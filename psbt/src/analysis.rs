@@ -0,0 +1,398 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Fee and weight analysis of PSBTs: absolute fee, transaction size before
+//! and after signing, effective fee rate, per-input satisfaction weight and
+//! detection of change outputs.
+
+use bitcoin::util::bip32::{ChildNumber, KeySource};
+use bitcoin::{SchnorrSighashType, VarInt};
+
+use crate::{Input, Output, Psbt};
+
+/// Size of the `scriptSig` spending P2PKH output with a compressed public key,
+/// assuming 72-byte DER signature
+const P2PKH_SCRIPT_SIG_SIZE: usize = 1 + 72 + 1 + 33;
+
+/// Size of the serialized witness spending P2WPKH output, assuming 72-byte DER
+/// signature
+const P2WPKH_WITNESS_SIZE: usize = 1 + 1 + 72 + 1 + 33;
+
+/// Size of the `scriptSig` spending P2SH-wrapped segwit v0 key hash output
+const P2SH_P2WPKH_SCRIPT_SIG_SIZE: usize = 1 + 22;
+
+/// Size of the `scriptSig` spending P2SH-wrapped segwit v0 script hash output
+const P2SH_P2WSH_SCRIPT_SIG_SIZE: usize = 1 + 34;
+
+/// Size of `scriptSig` and witness data required to spend a transaction input
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct SatisfactionSize {
+    /// Size of the `scriptSig` in bytes, not including its length prefix
+    pub script_sig: usize,
+
+    /// Size of the serialized witness in bytes, including the number of
+    /// witness elements. Zero for inputs not using witness.
+    pub witness: usize,
+}
+
+impl SatisfactionSize {
+    /// Weight added to the unsigned transaction input by its satisfaction,
+    /// not counting segwit marker and flag.
+    pub fn weight(self) -> usize {
+        let script_sig_len = self.script_sig + VarInt(self.script_sig as u64).len() - 1;
+        script_sig_len * 4 + self.witness
+    }
+}
+
+/// Weight and amount information about a single PSBT input
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct InputAnalysis {
+    /// Index of the input in the transaction
+    pub index: usize,
+
+    /// Amount of the spent output, if known
+    pub amount: Option<u64>,
+
+    /// Whether the input is already finalized
+    pub finalized: bool,
+
+    /// Actual (for finalized inputs) or estimated size of the input
+    /// satisfaction. `None` if the type of the spent output is unknown or
+    /// not supported by the estimator.
+    pub satisfaction: Option<SatisfactionSize>,
+}
+
+/// Fee and weight report for a PSBT
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct FeeAnalysis {
+    /// Absolute fee paid by the transaction, if all spent outputs are known
+    pub fee: Option<u64>,
+
+    /// Weight of the transaction with empty `scriptSig`s and witnesses
+    pub unsigned_weight: usize,
+
+    /// Estimated weight of the fully signed transaction, if satisfaction
+    /// sizes for all inputs are known
+    pub signed_weight: Option<usize>,
+
+    /// Per-input weight breakdown
+    pub inputs: Vec<InputAnalysis>,
+
+    /// Indexes of the outputs detected as change outputs
+    pub change_outputs: Vec<usize>,
+}
+
+impl FeeAnalysis {
+    /// Virtual size of the unsigned transaction in vbytes
+    #[inline]
+    pub fn unsigned_vsize(&self) -> usize { (self.unsigned_weight + 3) / 4 }
+
+    /// Estimated virtual size of the signed transaction in vbytes
+    #[inline]
+    pub fn signed_vsize(&self) -> Option<usize> { self.signed_weight.map(|w| (w + 3) / 4) }
+
+    /// Effective fee rate of the signed transaction in sat/vbyte
+    pub fn fee_rate(&self) -> Option<f32> {
+        let fee = self.fee?;
+        let vsize = self.signed_vsize()?;
+        Some(fee as f32 / vsize as f32)
+    }
+
+    /// Total amount of the change outputs
+    pub fn change_amount(&self, psbt: &Psbt) -> u64 {
+        self.change_outputs
+            .iter()
+            .filter_map(|index| psbt.outputs.get(*index))
+            .map(|output| output.amount)
+            .sum()
+    }
+}
+
+impl Psbt {
+    /// Analyzes PSBT fee and transaction weight, estimating the size of the
+    /// signed transaction for all non-finalized inputs.
+    pub fn analyze(&self) -> FeeAnalysis {
+        let inputs = self
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(index, input)| InputAnalysis {
+                index,
+                amount: input.input_prevout().ok().map(|prevout| prevout.value),
+                finalized: input.final_script_sig.is_some() || input.final_script_witness.is_some(),
+                satisfaction: input.satisfaction_size(),
+            })
+            .collect::<Vec<_>>();
+
+        let unsigned_weight = self.to_unsigned_tx().weight();
+        let signed_weight = inputs
+            .iter()
+            .map(|input| input.satisfaction)
+            .collect::<Option<Vec<_>>>()
+            .map(|sizes| {
+                let mut weight = unsigned_weight;
+                weight += sizes.iter().map(|size| size.weight()).sum::<usize>();
+                if sizes.iter().any(|size| size.witness > 0) {
+                    // Segwit marker & flag plus empty witnesses of the legacy
+                    // inputs
+                    weight += 2;
+                    weight += sizes.iter().filter(|size| size.witness == 0).count();
+                }
+                weight
+            });
+
+        let change_outputs = self
+            .outputs
+            .iter()
+            .enumerate()
+            .filter(|(_, output)| output.is_change())
+            .map(|(index, _)| index)
+            .collect();
+
+        FeeAnalysis {
+            fee: self.fee().ok(),
+            unsigned_weight,
+            signed_weight,
+            inputs,
+            change_outputs,
+        }
+    }
+}
+
+impl Input {
+    /// Returns actual size of the satisfaction for finalized inputs, or
+    /// estimates it for non-finalized ones basing on the type of the spent
+    /// output and provided scripts.
+    pub fn satisfaction_size(&self) -> Option<SatisfactionSize> {
+        if self.final_script_sig.is_some() || self.final_script_witness.is_some() {
+            return Some(SatisfactionSize {
+                script_sig: self
+                    .final_script_sig
+                    .as_ref()
+                    .map(|script| script.len())
+                    .unwrap_or_default(),
+                witness: self
+                    .final_script_witness
+                    .as_ref()
+                    .filter(|witness| !witness.is_empty())
+                    .map(|witness| witness.serialized_len())
+                    .unwrap_or_default(),
+            });
+        }
+
+        let script_pubkey = &self.input_prevout().ok()?.script_pubkey;
+        if script_pubkey.is_p2pkh() {
+            Some(SatisfactionSize {
+                script_sig: P2PKH_SCRIPT_SIG_SIZE,
+                witness: 0,
+            })
+        } else if script_pubkey.is_v0_p2wpkh() {
+            Some(SatisfactionSize {
+                script_sig: 0,
+                witness: P2WPKH_WITNESS_SIZE,
+            })
+        } else if script_pubkey.is_v0_p2wsh() {
+            Some(SatisfactionSize {
+                script_sig: 0,
+                witness: self.estimate_wsh_witness()?,
+            })
+        } else if script_pubkey.is_p2sh() {
+            let redeem_script = self.redeem_script.as_ref()?;
+            if redeem_script.is_v0_p2wpkh() {
+                Some(SatisfactionSize {
+                    script_sig: P2SH_P2WPKH_SCRIPT_SIG_SIZE,
+                    witness: P2WPKH_WITNESS_SIZE,
+                })
+            } else if redeem_script.is_v0_p2wsh() {
+                Some(SatisfactionSize {
+                    script_sig: P2SH_P2WSH_SCRIPT_SIG_SIZE,
+                    witness: self.estimate_wsh_witness()?,
+                })
+            } else {
+                None
+            }
+        } else if script_pubkey.is_v1_p2tr() {
+            let key_spend = self.tap_scripts.is_empty()
+                || self
+                    .tap_internal_key
+                    .map(|key| self.tap_key_origins.contains_key(&key))
+                    .unwrap_or_default();
+            let witness = if key_spend {
+                let sighash_len = match self.sighash_type.map(|ty| ty.schnorr_hash_ty()) {
+                    None | Some(Ok(SchnorrSighashType::Default)) => 0,
+                    _ => 1,
+                };
+                1 + 1 + 64 + sighash_len
+            } else {
+                self.estimate_tap_script_witness()?
+            };
+            Some(SatisfactionSize {
+                script_sig: 0,
+                witness,
+            })
+        } else {
+            None
+        }
+    }
+
+    #[cfg(feature = "miniscript")]
+    fn estimate_wsh_witness(&self) -> Option<usize> {
+        use miniscript::{Miniscript, Segwitv0};
+
+        let script = self.witness_script.as_ref()?;
+        let ms = Miniscript::<bitcoin::PublicKey, Segwitv0>::parse_insane(script).ok()?;
+        let elements = ms.max_satisfaction_witness_elements().ok()?;
+        let size = ms.max_satisfaction_size().ok()?;
+        Some(
+            VarInt(elements as u64 + 1).len()
+                + size
+                + VarInt(script.len() as u64).len()
+                + script.len(),
+        )
+    }
+
+    #[cfg(not(feature = "miniscript"))]
+    fn estimate_wsh_witness(&self) -> Option<usize> { None }
+
+    #[cfg(feature = "miniscript")]
+    fn estimate_tap_script_witness(&self) -> Option<usize> {
+        use bitcoin::XOnlyPublicKey;
+        use miniscript::{Miniscript, Tap};
+
+        // Without knowing which leaf is going to be used we take the most
+        // expensive one
+        self.tap_scripts
+            .iter()
+            .map(|(control_block, (script, _))| {
+                let ms = Miniscript::<XOnlyPublicKey, Tap>::parse_insane(script).ok()?;
+                let elements = ms.max_satisfaction_witness_elements().ok()?;
+                let size = ms.max_satisfaction_size().ok()?;
+                Some(
+                    VarInt(elements as u64 + 2).len()
+                        + size
+                        + VarInt(script.len() as u64).len()
+                        + script.len()
+                        + VarInt(control_block.size() as u64).len()
+                        + control_block.size(),
+                )
+            })
+            .collect::<Option<Vec<_>>>()?
+            .into_iter()
+            .max()
+    }
+
+    #[cfg(not(feature = "miniscript"))]
+    fn estimate_tap_script_witness(&self) -> Option<usize> { None }
+}
+
+impl Output {
+    /// Detects whether the output is a change output, i.e. whether any of its
+    /// keys is derived using the change branch (`1`) of a BIP-44-like
+    /// derivation scheme.
+    pub fn is_change(&self) -> bool {
+        fn is_change_origin((_, path): &KeySource) -> bool {
+            let path = path.as_ref();
+            path.len() >= 2 && path[path.len() - 2] == ChildNumber::Normal { index: 1 }
+        }
+
+        self.bip32_derivation.values().any(is_change_origin)
+            || self
+                .tap_key_origins
+                .values()
+                .any(|(_, origin)| is_change_origin(origin))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+    use bitcoin::{
+        OutPoint, PackedLockTime, PubkeyHash, Script, Sequence, Transaction, TxIn, TxOut,
+        WPubkeyHash,
+    };
+
+    use super::*;
+    use crate::PsbtVersion;
+
+    #[test]
+    fn p2wpkh_fee_analysis() {
+        let change_key = bitcoin::secp256k1::PublicKey::from_str(
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: none!(),
+                sequence: Sequence::MAX,
+                witness: empty!(),
+            }],
+            output: vec![
+                TxOut {
+                    value: 50_000,
+                    script_pubkey: Script::new_p2pkh(&PubkeyHash::all_zeros()),
+                },
+                TxOut {
+                    value: 49_000,
+                    script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()),
+                },
+            ],
+        };
+        let mut psbt = Psbt::with(tx, PsbtVersion::V0).unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::all_zeros()),
+        });
+        psbt.outputs[1].bip32_derivation.insert(
+            change_key,
+            (
+                Fingerprint::default(),
+                DerivationPath::from_str("m/84'/0'/0'/1/0").unwrap(),
+            ),
+        );
+
+        let analysis = psbt.analyze();
+        assert_eq!(analysis.fee, Some(1000));
+        assert_eq!(analysis.unsigned_weight, 464);
+        assert_eq!(analysis.signed_weight, Some(574));
+        assert_eq!(analysis.signed_vsize(), Some(144));
+        assert_eq!(analysis.change_outputs, vec![1]);
+        assert_eq!(analysis.change_amount(&psbt), 49_000);
+        assert_eq!(
+            analysis.inputs[0].satisfaction,
+            Some(SatisfactionSize {
+                script_sig: 0,
+                witness: P2WPKH_WITNESS_SIZE
+            })
+        );
+        assert!(!analysis.inputs[0].finalized);
+    }
+}
@@ -0,0 +1,113 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Taproot witness annex (BIP-341), reserved for the future soft forks.
+//!
+//! The annex is the last element of a taproot witness stack containing at
+//! least two elements, which starts with [`TAPROOT_ANNEX_PREFIX`] byte. Its
+//! content has no consensus meaning yet, but it is committed to by the
+//! signature hash, so it must be detected and removed before interpreting the
+//! rest of the witness stack.
+
+use std::fmt::{self, Display, Formatter};
+
+use amplify::hex::ToHex;
+use bitcoin::util::sighash;
+use bitcoin::util::taproot::TAPROOT_ANNEX_PREFIX;
+use bitcoin::Witness;
+
+/// Errors constructing taproot witness annex
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AnnexError {
+    /// taproot annex must not be empty
+    Empty,
+
+    /// taproot annex must start with 0x50 byte, while it starts with {0:#04x}
+    WrongPrefix(u8),
+}
+
+/// Validated taproot witness annex
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Annex(Vec<u8>);
+
+impl Display for Annex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str(&self.0.to_hex()) }
+}
+
+impl Annex {
+    /// Constructs annex from its data, checking that the data start with the
+    /// annex prefix byte
+    pub fn new(data: impl Into<Vec<u8>>) -> Result<Annex, AnnexError> {
+        let data = data.into();
+        match data.first() {
+            None => Err(AnnexError::Empty),
+            Some(&TAPROOT_ANNEX_PREFIX) => Ok(Annex(data)),
+            Some(prefix) => Err(AnnexError::WrongPrefix(*prefix)),
+        }
+    }
+
+    /// Splits taproot witness into the witness stack elements and the annex,
+    /// if the witness has one.
+    pub fn split_witness(witness: &Witness) -> (Vec<Vec<u8>>, Option<Annex>) {
+        let mut elements = witness.to_vec();
+        let annex = match elements.last() {
+            Some(last) if elements.len() >= 2 && last.first() == Some(&TAPROOT_ANNEX_PREFIX) => {
+                elements.pop().map(Annex)
+            }
+            _ => None,
+        };
+        (elements, annex)
+    }
+
+    /// Extracts annex from a taproot witness, if present
+    pub fn from_witness(witness: &Witness) -> Option<Annex> { Annex::split_witness(witness).1 }
+
+    /// Returns annex data, including the prefix byte
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] { &self.0 }
+
+    /// Returns annex data, including the prefix byte
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> { self.0 }
+
+    /// Returns annex in the form used by signature hash computation
+    pub fn to_sighash_annex(&self) -> sighash::Annex<'_> {
+        sighash::Annex::new(&self.0).expect("annex prefix is checked on construction")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn annex_parsing() {
+        assert_eq!(Annex::new(vec![]), Err(AnnexError::Empty));
+        assert_eq!(Annex::new(vec![0x51]), Err(AnnexError::WrongPrefix(0x51)));
+        let annex = Annex::new(vec![0x50, 0x01]).unwrap();
+        assert_eq!(annex.to_string(), "5001");
+
+        let key_spend = Witness::from_vec(vec![vec![0xAA; 64], vec![0x50, 0x01]]);
+        let (elements, found) = Annex::split_witness(&key_spend);
+        assert_eq!(elements, vec![vec![0xAA; 64]]);
+        assert_eq!(found, Some(annex.clone()));
+        assert_eq!(
+            found.unwrap().to_sighash_annex().as_bytes(),
+            annex.as_bytes()
+        );
+
+        // A single element is never an annex
+        let single = Witness::from_vec(vec![vec![0x50, 0x01]]);
+        assert_eq!(Annex::from_witness(&single), None);
+        assert_eq!(Annex::from_witness(&Witness::new()), None);
+    }
+}
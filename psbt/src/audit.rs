@@ -0,0 +1,460 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Verification of the signatures present in PSBT inputs, allowing multisig
+//! coordinators to validate contributions of co-signers before finalizing the
+//! transaction.
+
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::psbt::PsbtSighashType;
+use bitcoin::secp256k1::{Message, Secp256k1, Verification, XOnlyPublicKey};
+use bitcoin::util::sighash::{self, Prevouts, SighashCache};
+use bitcoin::util::taproot::TapLeafHash;
+use bitcoin::{
+    EcdsaSig, PubkeyHash, PublicKey, SchnorrSig, SchnorrSighashType, Script, Transaction, TxOut,
+};
+
+use crate::{Input, InputMatchError, Psbt};
+
+/// Errors verifying a signature from a PSBT input
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum AuditError {
+    /// signature does not match the signed key and transaction data
+    InvalidSignature,
+
+    /// signature uses sighash type {used} while the input requires {required}
+    SighashTypeMismatch {
+        /// Sighash type used by the signature
+        used: PsbtSighashType,
+        /// Sighash type required by the PSBT input
+        required: PsbtSighashType,
+    },
+
+    /// signing key is not used by the spent output
+    UnknownKey,
+
+    /// ECDSA signature can't be used for spending taproot output
+    EcdsaInTaproot,
+
+    /// input spending P2SH output does not contain redeem script
+    NoRedeemScript,
+
+    /// input spending P2WSH output does not contain witness script
+    NoWitnessScript,
+
+    /// redeem or witness script does not match spent `scriptPubkey`
+    ScriptPubkeyMismatch,
+
+    /// taproot signature can't be verified since information on the outputs
+    /// spent by other transaction inputs is absent
+    TaprootPrevoutsMissed,
+
+    /// information about spent output is absent or invalid: {0}
+    #[from]
+    Prevout(InputMatchError),
+
+    /// unable to compute signature hash: {0}
+    #[from]
+    Sighash(sighash::Error),
+}
+
+/// Signature found in a PSBT input
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub enum SigSource {
+    /// ECDSA signature from `partial_sigs` made with a given key
+    #[display("ecdsa({0})")]
+    Ecdsa(PublicKey),
+
+    /// Taproot key path spending signature (`tap_key_sig`) made with the
+    /// output key
+    #[display("tr-key")]
+    TapKey,
+
+    /// Taproot script path spending signature (`tap_script_sigs`) made with a
+    /// given key for a given script leaf
+    #[display("tr-script({0}, {1})")]
+    TapScript(XOnlyPublicKey, TapLeafHash),
+}
+
+/// Result of verification of a single signature
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SigAudit {
+    /// Signature location within the input
+    pub source: SigSource,
+
+    /// Sighash type used by the signature
+    pub sighash_type: PsbtSighashType,
+
+    /// Verification status: the signature is valid if there is no error
+    pub status: Result<(), AuditError>,
+}
+
+/// Result of verification of all signatures from a PSBT input
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct InputAudit {
+    /// Index of the input
+    pub index: usize,
+
+    /// Verification results for each of the input signatures
+    pub signatures: Vec<SigAudit>,
+}
+
+impl InputAudit {
+    /// Detects whether all signatures of the input are valid. Inputs without
+    /// signatures are always valid.
+    #[inline]
+    pub fn is_valid(&self) -> bool { self.signatures.iter().all(|sig| sig.status.is_ok()) }
+}
+
+impl Psbt {
+    /// Verifies all signatures present in `partial_sigs`, `tap_key_sig` and
+    /// `tap_script_sigs` fields of each PSBT input against the signature hash
+    /// computed for the input according to the type of the spent output.
+    ///
+    /// Signatures which can't be verified because of the missed or
+    /// inconsistent input data are reported with the corresponding error.
+    pub fn verify_signatures<C: Verification>(&self, secp: &Secp256k1<C>) -> Vec<InputAudit> {
+        let tx = self.to_unsigned_tx();
+        let mut sig_hasher = SighashCache::new(&tx);
+        let spent = self
+            .inputs
+            .iter()
+            .map(|input| input.input_prevout().ok().cloned())
+            .collect::<Option<Vec<_>>>();
+
+        self.inputs
+            .iter()
+            .map(|input| InputAudit {
+                index: input.index(),
+                signatures: input.verify_signatures(secp, &mut sig_hasher, spent.as_deref()),
+            })
+            .collect()
+    }
+}
+
+impl Input {
+    fn verify_signatures<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        sig_hasher: &mut SighashCache<&Transaction>,
+        spent: Option<&[TxOut]>,
+    ) -> Vec<SigAudit> {
+        let mut audits = vec![];
+        for (pubkey, sig) in &self.partial_sigs {
+            audits.push(SigAudit {
+                source: SigSource::Ecdsa(*pubkey),
+                sighash_type: sig.hash_ty.into(),
+                status: self.verify_ecdsa(secp, sig_hasher, *pubkey, sig),
+            });
+        }
+
+        let prevout =
+            match self.input_prevout() {
+                Ok(prevout) => prevout,
+                Err(err) => {
+                    let err = AuditError::from(err);
+                    let mut tap_sigs = self
+                        .tap_key_sig
+                        .iter()
+                        .map(|sig| (SigSource::TapKey, sig))
+                        .collect::<Vec<_>>();
+                    tap_sigs.extend(self.tap_script_sigs.iter().map(|((pk, leaf_hash), sig)| {
+                        (SigSource::TapScript(*pk, *leaf_hash), sig)
+                    }));
+                    audits.extend(tap_sigs.into_iter().map(|(source, sig)| SigAudit {
+                        source,
+                        sighash_type: sig.hash_ty.into(),
+                        status: Err(err.clone()),
+                    }));
+                    return audits;
+                }
+            };
+        let output_key = if prevout.script_pubkey.is_v1_p2tr() {
+            XOnlyPublicKey::from_slice(&prevout.script_pubkey[2..34]).ok()
+        } else {
+            None
+        };
+
+        if let Some(sig) = self.tap_key_sig {
+            let status = match output_key {
+                Some(output_key) => {
+                    self.verify_schnorr(secp, sig_hasher, prevout, spent, output_key, None, sig)
+                }
+                None => Err(AuditError::UnknownKey),
+            };
+            audits.push(SigAudit {
+                source: SigSource::TapKey,
+                sighash_type: sig.hash_ty.into(),
+                status,
+            });
+        }
+
+        for ((pubkey, leaf_hash), sig) in &self.tap_script_sigs {
+            let status = match output_key {
+                Some(_) => self.verify_schnorr(
+                    secp,
+                    sig_hasher,
+                    prevout,
+                    spent,
+                    *pubkey,
+                    Some(*leaf_hash),
+                    *sig,
+                ),
+                None => Err(AuditError::UnknownKey),
+            };
+            audits.push(SigAudit {
+                source: SigSource::TapScript(*pubkey, *leaf_hash),
+                sighash_type: sig.hash_ty.into(),
+                status,
+            });
+        }
+
+        audits
+    }
+
+    fn check_sighash_type(&self, used: PsbtSighashType) -> Result<(), AuditError> {
+        match self.sighash_type {
+            Some(required) if required != used => {
+                Err(AuditError::SighashTypeMismatch { used, required })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn verify_ecdsa<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        sig_hasher: &mut SighashCache<&Transaction>,
+        pubkey: PublicKey,
+        sig: &EcdsaSig,
+    ) -> Result<(), AuditError> {
+        self.check_sighash_type(sig.hash_ty.into())?;
+
+        let index = self.index();
+        let prevout = self.input_prevout()?;
+        let script_pubkey = &prevout.script_pubkey;
+        let redeem_script = self.redeem_script.as_deref();
+        let witness_script = self.witness_script.as_deref();
+
+        let pubkey_hash_code = |program: &[u8]| -> Result<Script, AuditError> {
+            if program != &pubkey.wpubkey_hash().ok_or(AuditError::UnknownKey)?[..] {
+                return Err(AuditError::UnknownKey);
+            }
+            Ok(Script::new_p2pkh(
+                &PubkeyHash::from_slice(program).expect("wpkh program length is checked"),
+            ))
+        };
+        let witness_script_code = || -> Result<&Script, AuditError> {
+            let witness_script = witness_script.ok_or(AuditError::NoWitnessScript)?;
+            check_key_in_script(witness_script, pubkey)?;
+            Ok(witness_script)
+        };
+
+        let sighash = if script_pubkey.is_v1_p2tr() {
+            return Err(AuditError::EcdsaInTaproot);
+        } else if script_pubkey.is_v0_p2wpkh() {
+            let script_code = pubkey_hash_code(&script_pubkey[2..22])?;
+            sig_hasher.segwit_signature_hash(index, &script_code, prevout.value, sig.hash_ty)?
+        } else if script_pubkey.is_v0_p2wsh() {
+            let script_code = witness_script_code()?;
+            if script_pubkey != &script_code.to_v0_p2wsh() {
+                return Err(AuditError::ScriptPubkeyMismatch);
+            }
+            sig_hasher.segwit_signature_hash(index, script_code, prevout.value, sig.hash_ty)?
+        } else if script_pubkey.is_p2sh() {
+            let redeem_script = redeem_script.ok_or(AuditError::NoRedeemScript)?;
+            if script_pubkey != &redeem_script.to_p2sh() {
+                return Err(AuditError::ScriptPubkeyMismatch);
+            }
+            if redeem_script.is_v0_p2wpkh() {
+                let script_code = pubkey_hash_code(&redeem_script[2..22])?;
+                sig_hasher.segwit_signature_hash(index, &script_code, prevout.value, sig.hash_ty)?
+            } else if redeem_script.is_v0_p2wsh() {
+                let script_code = witness_script_code()?;
+                if redeem_script != &script_code.to_v0_p2wsh() {
+                    return Err(AuditError::ScriptPubkeyMismatch);
+                }
+                sig_hasher.segwit_signature_hash(index, script_code, prevout.value, sig.hash_ty)?
+            } else {
+                check_key_in_script(redeem_script, pubkey)?;
+                sig_hasher.legacy_signature_hash(index, redeem_script, sig.hash_ty.to_u32())?
+            }
+        } else {
+            if script_pubkey.is_p2pkh() {
+                if script_pubkey[3..23] != pubkey.pubkey_hash()[..] {
+                    return Err(AuditError::UnknownKey);
+                }
+            } else {
+                check_key_in_script(script_pubkey, pubkey)?;
+            }
+            sig_hasher.legacy_signature_hash(index, script_pubkey, sig.hash_ty.to_u32())?
+        };
+
+        let msg = Message::from_slice(&sighash[..]).expect("sighash has 32 bytes");
+        secp.verify_ecdsa(&msg, &sig.sig, &pubkey.inner)
+            .map_err(|_| AuditError::InvalidSignature)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn verify_schnorr<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        sig_hasher: &mut SighashCache<&Transaction>,
+        prevout: &TxOut,
+        spent: Option<&[TxOut]>,
+        pubkey: XOnlyPublicKey,
+        leaf_hash: Option<TapLeafHash>,
+        sig: SchnorrSig,
+    ) -> Result<(), AuditError> {
+        self.check_sighash_type(sig.hash_ty.into())?;
+
+        let index = self.index();
+        let prevouts = match spent {
+            Some(spent) => Prevouts::All(spent),
+            None if matches!(
+                sig.hash_ty,
+                SchnorrSighashType::AllPlusAnyoneCanPay
+                    | SchnorrSighashType::NonePlusAnyoneCanPay
+                    | SchnorrSighashType::SinglePlusAnyoneCanPay
+            ) =>
+            {
+                Prevouts::One(index, prevout.clone())
+            }
+            None => return Err(AuditError::TaprootPrevoutsMissed),
+        };
+        let sighash = match leaf_hash {
+            None => sig_hasher.taproot_key_spend_signature_hash(index, &prevouts, sig.hash_ty)?,
+            Some(leaf_hash) => sig_hasher.taproot_script_spend_signature_hash(
+                index,
+                &prevouts,
+                leaf_hash,
+                sig.hash_ty,
+            )?,
+        };
+
+        let msg = Message::from_slice(&sighash[..]).expect("sighash has 32 bytes");
+        secp.verify_schnorr(&sig.sig, &msg, &pubkey)
+            .map_err(|_| AuditError::InvalidSignature)
+    }
+}
+
+fn check_key_in_script(script: &Script, pubkey: PublicKey) -> Result<(), AuditError> {
+    let key = pubkey.to_bytes();
+    if script
+        .instructions()
+        .any(|instruction| matches!(instruction, Ok(Instruction::PushBytes(data)) if data == key))
+    {
+        Ok(())
+    } else {
+        Err(AuditError::UnknownKey)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+    use bitcoin::schnorr::TapTweak;
+    use bitcoin::secp256k1::{KeyPair, SecretKey};
+    use bitcoin::{EcdsaSighashType, OutPoint, PackedLockTime, TxIn, Txid};
+
+    use super::*;
+    use crate::PsbtVersion;
+
+    #[test]
+    fn wpkh_and_taproot_signatures() {
+        let secp = Secp256k1::new();
+        let seckey = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let pubkey = PublicKey::new(seckey.public_key(&secp));
+        let keypair = KeyPair::from_seckey_slice(&secp, &[0x22; 32]).unwrap();
+        let (internal_key, _) = keypair.x_only_public_key();
+
+        let txin = |vout| TxIn {
+            previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), vout),
+            ..TxIn::default()
+        };
+        let spent = vec![
+            TxOut {
+                value: 10_000,
+                script_pubkey: Script::new_v0_p2wpkh(&pubkey.wpubkey_hash().unwrap()),
+            },
+            TxOut {
+                value: 20_000,
+                script_pubkey: Script::new_v1_p2tr(&secp, internal_key, None),
+            },
+        ];
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![txin(0), txin(1)],
+            output: vec![TxOut {
+                value: 29_000,
+                script_pubkey: Script::new_op_return(&[]),
+            }],
+        };
+        let mut psbt = Psbt::with(tx.clone(), PsbtVersion::V0).unwrap();
+        psbt.inputs[0].witness_utxo = Some(spent[0].clone());
+        psbt.inputs[1].witness_utxo = Some(spent[1].clone());
+
+        let mut sig_hasher = SighashCache::new(&tx);
+        let script_code = Script::new_p2pkh(&pubkey.pubkey_hash());
+        let sighash = sig_hasher
+            .segwit_signature_hash(0, &script_code, 10_000, EcdsaSighashType::All)
+            .unwrap();
+        let msg = Message::from_slice(&sighash[..]).unwrap();
+        let ecdsa_sig = EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, &seckey));
+        psbt.inputs[0].partial_sigs.insert(pubkey, ecdsa_sig);
+
+        let sighash = sig_hasher
+            .taproot_key_spend_signature_hash(
+                1,
+                &Prevouts::All(&spent),
+                SchnorrSighashType::Default,
+            )
+            .unwrap();
+        let msg = Message::from_slice(&sighash[..]).unwrap();
+        let tweaked = keypair.tap_tweak(&secp, None).to_inner();
+        psbt.inputs[1].tap_key_sig = Some(SchnorrSig {
+            sig: secp.sign_schnorr(&msg, &tweaked),
+            hash_ty: SchnorrSighashType::Default,
+        });
+
+        let audit = psbt.verify_signatures(&secp);
+        assert!(audit.iter().all(InputAudit::is_valid));
+        assert_eq!(audit[0].signatures[0].source, SigSource::Ecdsa(pubkey));
+        assert_eq!(audit[1].signatures[0].source, SigSource::TapKey);
+
+        // Signature by a different key under the same public key
+        let forged = SecretKey::from_slice(&[0x33; 32]).unwrap();
+        psbt.inputs[0].partial_sigs.insert(
+            pubkey,
+            EcdsaSig::sighash_all(secp.sign_ecdsa(&msg, &forged)),
+        );
+        psbt.inputs[1].sighash_type = Some(SchnorrSighashType::All.into());
+        let audit = psbt.verify_signatures(&secp);
+        assert_eq!(
+            audit[0].signatures[0].status,
+            Err(AuditError::InvalidSignature)
+        );
+        assert_eq!(
+            audit[1].signatures[0].status,
+            Err(AuditError::SighashTypeMismatch {
+                used: SchnorrSighashType::Default.into(),
+                required: SchnorrSighashType::All.into(),
+            })
+        );
+    }
+}
@@ -0,0 +1,120 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Templates for BIP-47 notification transactions, which send sender payment
+//! code to the receiver before the first payment.
+
+use bitcoin::secp256k1::{Secp256k1, SecretKey, Verification};
+use bitcoin::{Network, OutPoint, PackedLockTime, Sequence, Transaction, TxIn, TxOut};
+use bitcoin_hd::PaymentCode;
+
+use crate::{Psbt, PsbtVersion};
+
+/// Amount sent to the receiver notification address by notification
+/// transactions
+pub const BIP47_NOTIFICATION_AMOUNT: u64 = 546;
+
+impl Psbt {
+    /// Constructs template of BIP-47 notification transaction, sending
+    /// `sender` payment code to the `receiver`.
+    ///
+    /// The template spends `prevout` with its first (designated) input, which
+    /// must expose the public key for the `designated_key` in its `scriptSig`
+    /// or witness. The template has two outputs: the first one pays to the
+    /// receiver notification address, and the second is an `OP_RETURN`
+    /// output with the blinded sender payment code.
+    ///
+    /// The caller must provide the information about the spent output for the
+    /// designated input, add change output and other inputs paying the fee
+    /// after the designated one. Notification transactions must not be sorted
+    /// lexicographically, since this may move the designated input.
+    pub fn bip47_notification<C: Verification>(
+        secp: &Secp256k1<C>,
+        sender: &PaymentCode,
+        receiver: &PaymentCode,
+        designated_key: &SecretKey,
+        prevout: OutPoint,
+        network: Network,
+    ) -> Psbt {
+        let payload = sender.to_notification_payload(secp, receiver, designated_key, prevout);
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: prevout,
+                script_sig: none!(),
+                sequence: Sequence::MAX,
+                witness: empty!(),
+            }],
+            output: vec![
+                TxOut {
+                    value: BIP47_NOTIFICATION_AMOUNT,
+                    script_pubkey: receiver.notification_address(secp, network).script_pubkey(),
+                },
+                TxOut {
+                    value: 0,
+                    script_pubkey: PaymentCode::notification_script(&payload),
+                },
+            ],
+        };
+        Psbt::with(tx, PsbtVersion::V0).expect("unsigned transaction")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::{Address, Script};
+
+    use super::*;
+
+    #[test]
+    fn bip47_notification_template() {
+        let secp = Secp256k1::new();
+        let alice = PaymentCode::from_str(
+            "PM8TJTLJbPRGxSbc8EJi42Wrr6QbNSaSSVJ5Y3E4pbCYiTHUskHg13935Ubb7q8tx9GVbh2UuRnBc3WSyJHh\
+             Urw8KhprKnn9eDznYGieTzFcwQRya4GA",
+        )
+        .unwrap();
+        let bob = PaymentCode::from_str(
+            "PM8TJS2JxQ5ztXUpBBRnpTbcUXbUHy2T1abfrb3KkAAtMEGNbey4oumH7Hc578WgQJhPjBxteQ5GHHToTYH\
+             E3A1w6p7tU6KSoFmWBVbFGjKPisZDbP97",
+        )
+        .unwrap();
+        let designated_key =
+            SecretKey::from_str("1b7a10f45118e2519a8dd46ef81591c1ae501d082b6610fdda3de7a3c932880d")
+                .unwrap();
+        let prevout = OutPoint::from_str(
+            "9c6000d597c5008f7bfc2618aed5e4a6ae57677aab95078aae708e1cab11f486:1",
+        )
+        .unwrap();
+
+        let network = Network::Bitcoin;
+        let psbt = Psbt::bip47_notification(&secp, &alice, &bob, &designated_key, prevout, network);
+        let tx = psbt.to_unsigned_tx();
+        assert_eq!(tx.input[0].previous_output, prevout);
+        assert_eq!(
+            Address::from_script(&tx.output[0].script_pubkey, network)
+                .unwrap()
+                .to_string(),
+            "1ChvUUvht2hUQufHBXF8NgLhW8SwE2ecGV"
+        );
+        assert_eq!(tx.output[0].value, BIP47_NOTIFICATION_AMOUNT);
+        let payload = Vec::<u8>::from_hex(
+            "010002063e4eb95e62791b06c50e1a3a942e1ecaaa9afbbeb324d16ae6821e091611fa96c0cf048f607f\
+             e51a0327f5e2528979311c78cb2de0d682c61e1180fc3d543b00000000000000000000000000",
+        )
+        .unwrap();
+        assert_eq!(tx.output[1].script_pubkey, Script::new_op_return(&payload));
+    }
+}
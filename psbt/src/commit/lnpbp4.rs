@@ -0,0 +1,338 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+// TODO: Relocate to BP DBC library
+
+//! Processing proprietary PSBT keys related to LNPBP-4 multi-protocol
+//! commitments and building the commitments for PSBT outputs.
+//!
+//! The multi-protocol commitment tree is constructed with [`commit_verify`]
+//! LNPBP-4 implementation; the merkle root of the tree is then embedded into
+//! the output as a tapret or opret commitment, depending on the host type of
+//! the output.
+
+use amplify::{Slice32, Wrapper};
+use bitcoin::hashes::Hash;
+use commit_verify::lnpbp4::{self, MerkleTree, MessageMap, MultiSource};
+use commit_verify::{ConsensusCommit, TryCommitVerify};
+
+use super::{opret, tapret};
+use crate::raw::ProprietaryKey;
+use crate::{Output, Psbt};
+
+/// Proprietary key prefix used for LNPBP-4-related PSBT keys
+pub const PSBT_LNPBP4_PREFIX: &[u8] = b"LNPBP4";
+/// Message committed to the output under a specific protocol. The key data
+/// is the 32-byte protocol id and the value is the 32-byte message.
+pub const PSBT_OUT_LNPBP4_MESSAGE: u8 = 0x00;
+/// Entropy used to fill multi-commitment tree slots not taken by protocols,
+/// as 8-byte little-endian integer.
+pub const PSBT_OUT_LNPBP4_ENTROPY: u8 = 0x01;
+/// Minimal depth of the multi-commitment tree as a single byte.
+pub const PSBT_OUT_LNPBP4_MIN_TREE_DEPTH: u8 = 0x02;
+
+/// Errors building LNPBP-4 multi-protocol commitments
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum Lnpbp4Error {
+    /// output #{0} has LNPBP-4 message key with invalid protocol id or
+    /// message value
+    InvalidMessage(usize),
+
+    /// output #{0} has LNPBP-4 messages, but is not marked as a host for
+    /// either tapret or opret commitment
+    NoHost(usize),
+
+    /// output #{0} is marked as a host for both tapret and opret commitments
+    AmbiguousHost(usize),
+
+    /// output #{0} already has commitment which was not produced by LNPBP-4
+    /// multi-protocol commitment
+    CommitmentExists(usize),
+
+    #[from]
+    #[display(inner)]
+    Tree(lnpbp4::Error),
+
+    #[from]
+    #[display(inner)]
+    Tapret(tapret::TapretError),
+
+    #[from]
+    #[display(inner)]
+    Opret(opret::OpretError),
+}
+
+impl Output {
+    fn lnpbp4_key(subtype: u8, key: Vec<u8>) -> ProprietaryKey {
+        ProprietaryKey {
+            prefix: PSBT_LNPBP4_PREFIX.to_vec(),
+            subtype,
+            key,
+        }
+    }
+
+    /// Adds message which has to be committed to the output under the given
+    /// protocol id, returning the message previously added for the same
+    /// protocol, if any.
+    pub fn set_lnpbp4_message(
+        &mut self,
+        protocol_id: Slice32,
+        message: Slice32,
+    ) -> Option<Slice32> {
+        self.proprietary
+            .insert(
+                Self::lnpbp4_key(PSBT_OUT_LNPBP4_MESSAGE, protocol_id.to_vec()),
+                message.to_vec(),
+            )
+            .and_then(|value| Slice32::from_slice(value))
+    }
+
+    /// Returns all messages committed to the output by their protocol ids.
+    ///
+    /// # Errors
+    ///
+    /// If any of the message keys has protocol id or message value which is
+    /// not 32 bytes long.
+    pub fn lnpbp4_message_map(&self) -> Result<MessageMap, Lnpbp4Error> {
+        self.proprietary
+            .iter()
+            .filter(|(key, _)| {
+                key.prefix == PSBT_LNPBP4_PREFIX && key.subtype == PSBT_OUT_LNPBP4_MESSAGE
+            })
+            .map(
+                |(key, value)| match (Slice32::from_slice(&key.key), Slice32::from_slice(value)) {
+                    (Some(protocol_id), Some(message)) => Ok((protocol_id.into(), message.into())),
+                    _ => Err(Lnpbp4Error::InvalidMessage(self.index)),
+                },
+            )
+            .collect()
+    }
+
+    /// Detects whether the output has any LNPBP-4 messages.
+    pub fn has_lnpbp4_messages(&self) -> bool {
+        self.proprietary
+            .keys()
+            .any(|key| key.prefix == PSBT_LNPBP4_PREFIX && key.subtype == PSBT_OUT_LNPBP4_MESSAGE)
+    }
+
+    /// Returns entropy of the multi-commitment tree, which is present only if
+    /// the commitment was already constructed with [`Output::lnpbp4_commit`].
+    pub fn lnpbp4_entropy(&self) -> Option<u64> {
+        let value = self
+            .proprietary
+            .get(&Self::lnpbp4_key(PSBT_OUT_LNPBP4_ENTROPY, vec![]))?;
+        Some(u64::from_le_bytes(value.as_slice().try_into().ok()?))
+    }
+
+    /// Sets minimal depth of the multi-commitment tree.
+    pub fn set_lnpbp4_min_tree_depth(&mut self, depth: u8) {
+        self.proprietary.insert(
+            Self::lnpbp4_key(PSBT_OUT_LNPBP4_MIN_TREE_DEPTH, vec![]),
+            vec![depth],
+        );
+    }
+
+    /// Returns minimal depth of the multi-commitment tree, defaulting to 0
+    /// (i.e. to the smallest tree fitting all the protocols).
+    pub fn lnpbp4_min_tree_depth(&self) -> u8 {
+        self.proprietary
+            .get(&Self::lnpbp4_key(PSBT_OUT_LNPBP4_MIN_TREE_DEPTH, vec![]))
+            .and_then(|value| value.first().copied())
+            .unwrap_or_default()
+    }
+
+    /// Constructs LNPBP-4 multi-commitment tree of the messages added to the
+    /// output, honoring minimal tree depth requirement. Stores entropy used
+    /// by the tree in [`PSBT_OUT_LNPBP4_ENTROPY`] key and returns merkle root
+    /// of the tree, which should be embedded into the output.
+    pub fn lnpbp4_commit(&mut self) -> Result<Slice32, Lnpbp4Error> {
+        let source = MultiSource {
+            min_depth: self.lnpbp4_min_tree_depth(),
+            messages: self.lnpbp4_message_map()?,
+        };
+        let tree = MerkleTree::try_commit(&source)?;
+        self.proprietary.insert(
+            Self::lnpbp4_key(PSBT_OUT_LNPBP4_ENTROPY, vec![]),
+            tree.entropy().to_le_bytes().to_vec(),
+        );
+        Ok(Slice32::from_inner(tree.consensus_commit().into_inner()))
+    }
+}
+
+/// Builds LNPBP-4 multi-commitments for all outputs having LNPBP-4 messages
+/// and embeds them into the outputs via tapret or opret commitment, depending
+/// on whether the output is marked as [`tapret::PSBT_OUT_TAPRET_HOST`] or
+/// [`opret::PSBT_OUT_OPRET_HOST`].
+///
+/// Outputs having both LNPBP-4 entropy and host commitment were committed by
+/// a previous call and are skipped, so the function may be called multiple
+/// times. Returns number of outputs which received LNPBP-4 commitment.
+pub fn finalize(psbt: &mut Psbt) -> Result<usize, Lnpbp4Error> {
+    let mut count = 0usize;
+    for output in &mut psbt.outputs {
+        if !output.has_lnpbp4_messages() {
+            continue;
+        }
+        let existing = match (output.is_tapret_host(), output.is_opret_host()) {
+            (true, true) => return Err(Lnpbp4Error::AmbiguousHost(output.index)),
+            (true, false) => output.tapret_commitment(),
+            (false, true) => output.opret_commitment(),
+            (false, false) => return Err(Lnpbp4Error::NoHost(output.index)),
+        };
+        match (output.lnpbp4_entropy(), existing) {
+            (Some(_), Some(_)) => continue,
+            (None, Some(_)) => return Err(Lnpbp4Error::CommitmentExists(output.index)),
+            (_, None) => {}
+        }
+        let commitment = output.lnpbp4_commit()?;
+        if output.is_tapret_host() {
+            output.set_tapret_commitment(commitment);
+        } else {
+            output.set_opret_commitment(commitment);
+        }
+        count += 1;
+    }
+    tapret::finalize(psbt)?;
+    opret::finalize(psbt)?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::blockdata::opcodes::all::OP_RETURN;
+    use bitcoin::secp256k1::{KeyPair, SecretKey, SECP256K1};
+    use commit_verify::lnpbp4::{Message, ProtocolId};
+
+    use super::*;
+
+    fn protocol(first: u8) -> Slice32 {
+        let mut id = [0x77u8; 32];
+        id[0] = first;
+        Slice32::from_inner(id)
+    }
+
+    #[test]
+    fn lnpbp4_keys() {
+        let mut output = Output::default();
+        assert!(!output.has_lnpbp4_messages());
+        assert_eq!(output.lnpbp4_message_map(), Ok(MessageMap::new()));
+        assert_eq!(output.lnpbp4_min_tree_depth(), 0);
+        assert_eq!(output.lnpbp4_entropy(), None);
+
+        let message = Slice32::from_inner([1u8; 32]);
+        assert_eq!(output.set_lnpbp4_message(protocol(0), message), None);
+        assert_eq!(
+            output.set_lnpbp4_message(protocol(0), message),
+            Some(message)
+        );
+        output.set_lnpbp4_message(protocol(2), Slice32::from_inner([2u8; 32]));
+        assert!(output.has_lnpbp4_messages());
+        let map = output.lnpbp4_message_map().unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(
+            map.get(&ProtocolId::from(protocol(0))),
+            Some(&Message::from(message))
+        );
+
+        output.set_lnpbp4_min_tree_depth(4);
+        assert_eq!(output.lnpbp4_min_tree_depth(), 4);
+
+        output.proprietary.insert(
+            Output::lnpbp4_key(PSBT_OUT_LNPBP4_MESSAGE, vec![0u8; 31]),
+            vec![0u8; 32],
+        );
+        assert_eq!(
+            output.lnpbp4_message_map(),
+            Err(Lnpbp4Error::InvalidMessage(0))
+        );
+    }
+
+    #[test]
+    fn lnpbp4_commit() {
+        let mut output = Output::default();
+        output.set_lnpbp4_message(protocol(0), Slice32::from_inner([1u8; 32]));
+        output.set_lnpbp4_message(protocol(2), Slice32::from_inner([2u8; 32]));
+        output.set_lnpbp4_min_tree_depth(3);
+
+        let commitment = output.lnpbp4_commit().unwrap();
+        assert!(output.lnpbp4_entropy().is_some());
+        // Each commitment uses fresh entropy, hiding the committed protocols
+        let other = output.lnpbp4_commit().unwrap();
+        assert_ne!(commitment, other);
+    }
+
+    #[test]
+    fn lnpbp4_finalize() {
+        let sk = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let (internal_key, _) = KeyPair::from_secret_key(SECP256K1, &sk).x_only_public_key();
+        let message = Slice32::from_inner([0xAB; 32]);
+
+        let mut tapret = Output {
+            index: 0,
+            tap_internal_key: Some(internal_key),
+            ..Output::default()
+        };
+        tapret.set_tapret_host();
+        tapret.set_lnpbp4_message(protocol(1), message);
+
+        let mut opret = Output {
+            index: 1,
+            ..Output::default()
+        };
+        opret.set_opret_host();
+        opret.set_lnpbp4_message(protocol(1), message);
+
+        let plain = Output {
+            index: 2,
+            ..Output::default()
+        };
+        let mut psbt = Psbt {
+            outputs: vec![tapret, opret, plain],
+            ..Psbt::default()
+        };
+
+        assert_eq!(finalize(&mut psbt), Ok(2));
+        assert!(psbt.outputs[0].lnpbp4_entropy().is_some());
+        assert!(psbt.outputs[0].tapret_commitment().is_some());
+        assert!(psbt.outputs[0].tapret_proof().is_some());
+
+        assert!(psbt.outputs[1].lnpbp4_entropy().is_some());
+        let commitment = psbt.outputs[1].opret_commitment().unwrap();
+        let script = psbt.outputs[1].script.as_inner();
+        assert_eq!(script.as_bytes()[0], OP_RETURN.to_u8());
+        assert_eq!(&script.as_bytes()[2..], &commitment[..]);
+        assert_eq!(psbt.outputs[2], Output {
+            index: 2,
+            ..Output::default()
+        });
+
+        // Repeated finalization keeps the commitments
+        assert_eq!(finalize(&mut psbt), Ok(0));
+        assert_eq!(psbt.outputs[1].opret_commitment(), Some(commitment));
+
+        let mut output = Output::default();
+        output.set_lnpbp4_message(protocol(1), message);
+        let mut psbt = Psbt {
+            outputs: vec![output],
+            ..Psbt::default()
+        };
+        assert_eq!(finalize(&mut psbt), Err(Lnpbp4Error::NoHost(0)));
+        psbt.outputs[0].set_tapret_host();
+        psbt.outputs[0].set_opret_host();
+        assert_eq!(finalize(&mut psbt), Err(Lnpbp4Error::AmbiguousHost(0)));
+        psbt.outputs[0] = Output::default();
+        psbt.outputs[0].set_lnpbp4_message(protocol(1), message);
+        psbt.outputs[0].set_opret_host();
+        psbt.outputs[0].set_opret_commitment(message);
+        assert_eq!(finalize(&mut psbt), Err(Lnpbp4Error::CommitmentExists(0)));
+    }
+}
@@ -0,0 +1,16 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Embedding deterministic bitcoin commitments (DBC) into PSBT outputs.
+
+pub mod lnpbp4;
+pub mod opret;
+pub mod tapret;
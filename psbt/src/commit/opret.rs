@@ -0,0 +1,167 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+// TODO: Relocate to BP DBC library
+
+//! Processing proprietary PSBT keys related to opret commitments and
+//! embedding the commitments into `OP_RETURN` outputs.
+//!
+//! Opret commitment is an `OP_RETURN` output script containing a single push
+//! of the 32-byte commitment.
+
+use amplify::{Slice32, Wrapper};
+use bitcoin::Script;
+
+use crate::raw::ProprietaryKey;
+use crate::{Output, Psbt};
+
+/// Proprietary key prefix used for opret-related PSBT keys
+pub const PSBT_OPRET_PREFIX: &[u8] = b"OPRET";
+/// Output which may host opret commitment; the value must be empty.
+pub const PSBT_OUT_OPRET_HOST: u8 = 0x00;
+/// 32-byte commitment to be embedded into the output script.
+pub const PSBT_OUT_OPRET_COMMITMENT: u8 = 0x01;
+
+/// Errors finalizing opret commitments
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum OpretError {
+    /// output #{0} is marked as opret host, but has no commitment value
+    NoCommitment(usize),
+
+    /// output #{0} is marked as opret host, but already has a script which is
+    /// not an empty `OP_RETURN`
+    NonEmptyScript(usize),
+}
+
+/// Constructs opret commitment output script for the given commitment.
+pub fn opret_script(commitment: Slice32) -> Script { Script::new_op_return(&commitment[..]) }
+
+impl Output {
+    fn opret_key(subtype: u8) -> ProprietaryKey {
+        ProprietaryKey {
+            prefix: PSBT_OPRET_PREFIX.to_vec(),
+            subtype,
+            key: vec![],
+        }
+    }
+
+    /// Marks output as a host for opret commitment.
+    pub fn set_opret_host(&mut self) {
+        self.proprietary
+            .insert(Self::opret_key(PSBT_OUT_OPRET_HOST), vec![]);
+    }
+
+    /// Detects whether the output is marked as a host for opret commitment.
+    pub fn is_opret_host(&self) -> bool {
+        self.proprietary
+            .contains_key(&Self::opret_key(PSBT_OUT_OPRET_HOST))
+    }
+
+    /// Sets commitment which has to be embedded into the output by
+    /// [`finalize`].
+    pub fn set_opret_commitment(&mut self, commitment: Slice32) {
+        self.proprietary.insert(
+            Self::opret_key(PSBT_OUT_OPRET_COMMITMENT),
+            commitment.to_vec(),
+        );
+    }
+
+    /// Returns commitment which has to be embedded into the output, if any.
+    pub fn opret_commitment(&self) -> Option<Slice32> {
+        self.proprietary
+            .get(&Self::opret_key(PSBT_OUT_OPRET_COMMITMENT))
+            .and_then(|value| Slice32::from_slice(value))
+    }
+}
+
+/// Embeds opret commitments into all outputs marked with
+/// [`PSBT_OUT_OPRET_HOST`] key, taking the commitment from the
+/// [`PSBT_OUT_OPRET_COMMITMENT`] key. The output `scriptPubkey` must be
+/// either empty or a bare `OP_RETURN` and is replaced with the commitment
+/// script.
+///
+/// Outputs already containing the commitment are skipped, so the function may
+/// be called multiple times. Returns number of outputs which received the
+/// commitment.
+pub fn finalize(psbt: &mut Psbt) -> Result<usize, OpretError> {
+    let mut count = 0usize;
+    for output in &mut psbt.outputs {
+        if !output.is_opret_host() {
+            continue;
+        }
+        let commitment = output
+            .opret_commitment()
+            .ok_or(OpretError::NoCommitment(output.index))?;
+        let script = opret_script(commitment);
+        if output.script.as_inner() == &script {
+            continue;
+        }
+        if !output.script.as_inner().is_empty()
+            && output.script.as_inner() != &Script::new_op_return(&[])
+        {
+            return Err(OpretError::NonEmptyScript(output.index));
+        }
+        output.script = script.into();
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn opret_finalize() {
+        let commitment = Slice32::from_inner([0xAB; 32]);
+
+        let mut empty = Output {
+            index: 0,
+            ..Output::default()
+        };
+        empty.set_opret_host();
+        empty.set_opret_commitment(commitment);
+
+        let mut op_return = Output {
+            index: 1,
+            script: Script::new_op_return(&[]).into(),
+            ..Output::default()
+        };
+        op_return.set_opret_host();
+        op_return.set_opret_commitment(commitment);
+
+        let plain = Output {
+            index: 2,
+            script: Script::from(vec![0x51]).into(),
+            ..Output::default()
+        };
+        let mut psbt = Psbt {
+            outputs: vec![empty, op_return, plain],
+            ..Psbt::default()
+        };
+
+        assert_eq!(finalize(&mut psbt), Ok(2));
+        assert_eq!(finalize(&mut psbt), Ok(0));
+
+        let script = opret_script(commitment);
+        assert_eq!(script.len(), 34);
+        assert!(script.is_op_return());
+        assert_eq!(psbt.outputs[0].script.as_inner(), &script);
+        assert_eq!(psbt.outputs[1].script.as_inner(), &script);
+        assert_eq!(psbt.outputs[2].script.as_inner(), &Script::from(vec![0x51]));
+
+        psbt.outputs[2].set_opret_host();
+        assert_eq!(finalize(&mut psbt), Err(OpretError::NoCommitment(2)));
+        psbt.outputs[2].set_opret_commitment(commitment);
+        assert_eq!(finalize(&mut psbt), Err(OpretError::NonEmptyScript(2)));
+    }
+}
@@ -0,0 +1,465 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+// TODO: Relocate to BP DBC library
+
+//! Processing proprietary PSBT keys related to tapret commitments and
+//! embedding the commitments into taproot script trees of PSBT outputs.
+//!
+//! Tapret commitment is a script leaf consisting of 29 `OP_RESERVED` opcodes,
+//! `OP_RETURN` and a push of the 32-byte commitment followed by a one-byte
+//! nonce. The leaf can't be satisfied and is added as a sibling of the root
+//! of the output script tree. The nonce is selected such that the commitment
+//! leaf is the right-most leaf of the tree in the consensus (lexicographic)
+//! ordering of the tree nodes, as required by LNPBP-12.
+
+use std::io;
+
+use amplify::Slice32;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::psbt::TapTree;
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::util::taproot::{LeafVersion, TapLeafHash, TaprootBuilder};
+use bitcoin::{Script, XOnlyPublicKey};
+use bitcoin_scripts::taproot::{DfsOrder, TaprootScriptTree};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::raw::ProprietaryKey;
+use crate::{Output, Psbt};
+
+/// Proprietary key prefix used for tapret-related PSBT keys
+pub const PSBT_TAPRET_PREFIX: &[u8] = b"TAPRET";
+/// Output which may host tapret commitment; the value must be empty.
+pub const PSBT_OUT_TAPRET_HOST: u8 = 0x00;
+/// 32-byte commitment to be embedded into the output script tree.
+pub const PSBT_OUT_TAPRET_COMMITMENT: u8 = 0x01;
+/// Strict-encoded tapret proof ([`TapretProof`]) of the commitment embedded
+/// into the output.
+pub const PSBT_OUT_TAPRET_PROOF: u8 = 0x02;
+
+/// Number of `OP_RESERVED` opcodes prefixing tapret commitment leaf script.
+pub const TAPRET_SCRIPT_RESERVED_OPS: usize = 29;
+
+/// Errors finalizing tapret commitments
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TapretError {
+    /// output #{0} is marked as tapret host, but has no commitment value
+    NoCommitment(usize),
+
+    /// output #{0} hosting tapret commitment has no taproot internal key
+    NoInternalKey(usize),
+
+    /// tapret commitment can't be added to the script tree of output #{0}:
+    /// {1}
+    TreeDepth(usize, String),
+
+    /// no nonce value places tapret commitment of output #{0} right-most in
+    /// its script tree
+    NonceExhausted(usize),
+}
+
+/// Proof of tapret commitment, allowing to verify that the commitment leaf is
+/// the right-most leaf of the output script tree.
+///
+/// The proof is serialized in the LNPBP-12 form as an optional partner node
+/// followed by the nonce. Since the commitment leaf is always added as a
+/// sibling of the original script tree root and the nonce is selected to put
+/// the leaf right-most, the partner node is always the left node represented
+/// by its hash. Proofs with other partner node types are not supported.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct TapretProof {
+    /// Hash of the original script tree root, which is the left sibling of the
+    /// commitment leaf, or `None` if the output had no script tree.
+    pub partner_node: Option<sha256::Hash>,
+
+    /// Nonce used in the commitment leaf script.
+    pub nonce: u8,
+}
+
+impl StrictEncode for TapretProof {
+    fn strict_encode<E: io::Write>(&self, mut e: E) -> Result<usize, strict_encoding::Error> {
+        Ok(match self.partner_node {
+            None => 0u8.strict_encode(&mut e)? + self.nonce.strict_encode(&mut e)?,
+            Some(hash) => {
+                // `Some` followed by the left node partner type
+                e.write_all(&[1u8, 0u8])?;
+                e.write_all(&hash[..])?;
+                2 + sha256::Hash::LEN + self.nonce.strict_encode(&mut e)?
+            }
+        })
+    }
+}
+
+impl StrictDecode for TapretProof {
+    fn strict_decode<D: io::Read>(mut d: D) -> Result<Self, strict_encoding::Error> {
+        let partner_node = match u8::strict_decode(&mut d)? {
+            0 => None,
+            1 => match u8::strict_decode(&mut d)? {
+                0 => {
+                    let mut hash = [0u8; 32];
+                    d.read_exact(&mut hash)?;
+                    Some(sha256::Hash::from_inner(hash))
+                }
+                wrong => {
+                    return Err(strict_encoding::Error::DataIntegrityError(format!(
+                        "unsupported tapret partner node type {}",
+                        wrong
+                    )))
+                }
+            },
+            wrong => {
+                return Err(strict_encoding::Error::DataIntegrityError(format!(
+                    "invalid optional tapret partner node tag {}",
+                    wrong
+                )))
+            }
+        };
+        let nonce = u8::strict_decode(&mut d)?;
+        Ok(TapretProof {
+            partner_node,
+            nonce,
+        })
+    }
+}
+
+/// Constructs tapret commitment leaf script for the given commitment and
+/// nonce.
+pub fn tapret_script(commitment: Slice32, nonce: u8) -> Script {
+    let mut data = vec![0x50u8; TAPRET_SCRIPT_RESERVED_OPS];
+    // OP_RETURN OP_PUSHBYTES_33
+    data.extend([0x6a, 0x21]);
+    data.extend(&commitment[..]);
+    data.push(nonce);
+    Script::from(data)
+}
+
+/// Finds the first nonce for which the tapret commitment leaf sorts after its
+/// `partner` node in the consensus ordering of taproot tree nodes, i.e. is the
+/// right-most child of their common branch.
+pub fn tapret_nonce(commitment: Slice32, partner: sha256::Hash) -> Option<u8> {
+    (0..=u8::MAX).find(|nonce| {
+        let leaf_hash =
+            TapLeafHash::from_script(&tapret_script(commitment, *nonce), LeafVersion::TapScript);
+        leaf_hash.into_inner() > partner.into_inner()
+    })
+}
+
+impl Output {
+    fn tapret_key(subtype: u8) -> ProprietaryKey {
+        ProprietaryKey {
+            prefix: PSBT_TAPRET_PREFIX.to_vec(),
+            subtype,
+            key: vec![],
+        }
+    }
+
+    /// Marks output as a host for tapret commitment.
+    pub fn set_tapret_host(&mut self) {
+        self.proprietary
+            .insert(Self::tapret_key(PSBT_OUT_TAPRET_HOST), vec![]);
+    }
+
+    /// Detects whether the output is marked as a host for tapret commitment.
+    pub fn is_tapret_host(&self) -> bool {
+        self.proprietary
+            .contains_key(&Self::tapret_key(PSBT_OUT_TAPRET_HOST))
+    }
+
+    /// Sets commitment which has to be embedded into the output by
+    /// [`finalize`].
+    pub fn set_tapret_commitment(&mut self, commitment: Slice32) {
+        self.proprietary.insert(
+            Self::tapret_key(PSBT_OUT_TAPRET_COMMITMENT),
+            commitment.to_vec(),
+        );
+    }
+
+    /// Returns commitment which has to be embedded into the output, if any.
+    pub fn tapret_commitment(&self) -> Option<Slice32> {
+        self.proprietary
+            .get(&Self::tapret_key(PSBT_OUT_TAPRET_COMMITMENT))
+            .and_then(|value| Slice32::from_slice(value))
+    }
+
+    /// Returns proof of the tapret commitment, if the commitment was already
+    /// embedded into the output.
+    pub fn tapret_proof(&self) -> Option<TapretProof> {
+        let value = self
+            .proprietary
+            .get(&Self::tapret_key(PSBT_OUT_TAPRET_PROOF))?;
+        TapretProof::strict_deserialize(value).ok()
+    }
+
+    fn set_tapret_proof(&mut self, proof: TapretProof) {
+        let value = proof.strict_serialize().expect("in-memory strict encoding");
+        self.proprietary
+            .insert(Self::tapret_key(PSBT_OUT_TAPRET_PROOF), value);
+    }
+
+    fn embed_tapret(
+        &mut self,
+        internal_key: XOnlyPublicKey,
+        commitment: Slice32,
+    ) -> Result<(), TapretError> {
+        let leaf = |nonce: u8| {
+            let leaf = TaprootBuilder::new()
+                .add_leaf(0, tapret_script(commitment, nonce))
+                .expect("single leaf at zero depth");
+            TaprootScriptTree::from(
+                TapTree::try_from(leaf).expect("single-leaf tree is always complete"),
+            )
+        };
+
+        let (tap_tree, proof) = match self.tap_tree.clone() {
+            None => (TapTree::from(leaf(0)), TapretProof {
+                partner_node: None,
+                nonce: 0,
+            }),
+            Some(tap_tree) => {
+                let mut tree = TaprootScriptTree::from(tap_tree);
+                let partner = tree.as_root_node().node_hash();
+                let nonce = tapret_nonce(commitment, partner)
+                    .ok_or(TapretError::NonceExhausted(self.index))?;
+                let root: &[DfsOrder] = &[];
+                tree.instill(leaf(nonce), root, DfsOrder::Last)
+                    .map_err(|err| TapretError::TreeDepth(self.index, err.to_string()))?;
+                (TapTree::from(tree), TapretProof {
+                    partner_node: Some(partner),
+                    nonce,
+                })
+            }
+        };
+
+        let spend_info = tap_tree
+            .clone()
+            .into_builder()
+            .finalize(SECP256K1, internal_key)
+            .expect("TapTree is always finalizable");
+        self.script = Script::new_v1_p2tr_tweaked(spend_info.output_key()).into();
+        self.tap_tree = Some(tap_tree);
+        self.set_tapret_proof(proof);
+        Ok(())
+    }
+}
+
+/// Embeds tapret commitments into all outputs marked with
+/// [`PSBT_OUT_TAPRET_HOST`] key, taking the commitment from the
+/// [`PSBT_OUT_TAPRET_COMMITMENT`] key. Updates output script tree and
+/// `scriptPubkey` and stores the [`TapretProof`] in [`PSBT_OUT_TAPRET_PROOF`]
+/// key.
+///
+/// Outputs already having tapret proof are skipped, so the function may be
+/// called multiple times. Returns number of outputs which received the
+/// commitment.
+pub fn finalize(psbt: &mut Psbt) -> Result<usize, TapretError> {
+    let mut count = 0usize;
+    for output in &mut psbt.outputs {
+        if !output.is_tapret_host() || output.tapret_proof().is_some() {
+            continue;
+        }
+        let commitment = output
+            .tapret_commitment()
+            .ok_or(TapretError::NoCommitment(output.index))?;
+        let internal_key = output
+            .tap_internal_key
+            .ok_or(TapretError::NoInternalKey(output.index))?;
+        output.embed_tapret(internal_key, commitment)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use amplify::Wrapper;
+    use bitcoin::secp256k1::{KeyPair, SecretKey};
+    use bitcoin::util::taproot::{LeafVersion, TapLeafHash};
+
+    use super::*;
+
+    #[test]
+    fn tapret_finalize() {
+        let sk = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let (internal_key, _) = KeyPair::from_secret_key(SECP256K1, &sk).x_only_public_key();
+        let commitment = Slice32::from_inner([0xAB; 32]);
+
+        let mut keyonly = Output {
+            index: 0,
+            tap_internal_key: Some(internal_key),
+            ..Output::default()
+        };
+        keyonly.set_tapret_host();
+        keyonly.set_tapret_commitment(commitment);
+
+        let script = Script::from(vec![0x51]);
+        let tree = TaprootBuilder::new().add_leaf(0, script.clone()).unwrap();
+        let mut scripted = Output {
+            index: 1,
+            tap_internal_key: Some(internal_key),
+            tap_tree: Some(TapTree::try_from(tree).unwrap()),
+            ..Output::default()
+        };
+        scripted.set_tapret_host();
+        scripted.set_tapret_commitment(commitment);
+
+        let plain = Output {
+            index: 2,
+            ..Output::default()
+        };
+        let mut psbt = Psbt {
+            outputs: vec![keyonly, scripted, plain],
+            ..Psbt::default()
+        };
+
+        assert_eq!(finalize(&mut psbt), Ok(2));
+        assert_eq!(finalize(&mut psbt), Ok(0));
+
+        let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+        let partner = sha256::Hash::from_inner(leaf_hash.into_inner());
+        assert_eq!(
+            psbt.outputs[0].tapret_proof(),
+            Some(TapretProof {
+                partner_node: None,
+                nonce: 0
+            })
+        );
+        // Nonce 0 puts the commitment leaf before the original leaf
+        assert_eq!(
+            psbt.outputs[1].tapret_proof(),
+            Some(TapretProof {
+                partner_node: Some(partner),
+                nonce: 1
+            })
+        );
+        assert!(psbt.outputs[2].tapret_proof().is_none());
+
+        for (output, nonce) in psbt.outputs[..2].iter().zip([0u8, 1]) {
+            let tapret = tapret_script(commitment, nonce);
+            assert_eq!(tapret.len(), 64);
+            let tap_tree = output.tap_tree.clone().unwrap();
+            assert!(tap_tree
+                .script_leaves()
+                .any(|leaf| leaf.script() == &tapret));
+            let spend_info = tap_tree
+                .into_builder()
+                .finalize(SECP256K1, internal_key)
+                .unwrap();
+            assert_eq!(
+                output.script.as_inner(),
+                &Script::new_v1_p2tr_tweaked(spend_info.output_key())
+            );
+        }
+        let tap_tree = psbt.outputs[1].tap_tree.clone().unwrap();
+        assert!(tap_tree.script_leaves().any(|leaf| {
+            TapLeafHash::from_script(leaf.script(), leaf.leaf_version()) == leaf_hash
+        }));
+
+        psbt.outputs[2].set_tapret_host();
+        assert_eq!(finalize(&mut psbt), Err(TapretError::NoCommitment(2)));
+    }
+
+    #[test]
+    fn tapret_nonce_right_most() {
+        let sk = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let (internal_key, _) = KeyPair::from_secret_key(SECP256K1, &sk).x_only_public_key();
+        let commitment = Slice32::from_inner([0xAB; 32]);
+
+        // Asymmetric tree which root hash sorts after the commitment leaf
+        // with nonce 0
+        let tree = TaprootBuilder::new()
+            .add_leaf(1, Script::from(vec![0x51]))
+            .unwrap()
+            .add_leaf(2, Script::from(vec![0x52]))
+            .unwrap()
+            .add_leaf(2, Script::from(vec![0x5c]))
+            .unwrap();
+        let merkle_root = tree
+            .clone()
+            .finalize(SECP256K1, internal_key)
+            .unwrap()
+            .merkle_root()
+            .unwrap();
+        let partner = sha256::Hash::from_inner(merkle_root.into_inner());
+        let leaf_hash = |nonce: u8| {
+            TapLeafHash::from_script(&tapret_script(commitment, nonce), LeafVersion::TapScript)
+                .into_inner()
+        };
+        assert!(leaf_hash(0) < partner.into_inner());
+        assert_eq!(tapret_nonce(commitment, partner), Some(14));
+
+        let mut output = Output {
+            index: 0,
+            tap_internal_key: Some(internal_key),
+            tap_tree: Some(TapTree::try_from(tree).unwrap()),
+            ..Output::default()
+        };
+        output.set_tapret_host();
+        output.set_tapret_commitment(commitment);
+        let mut psbt = Psbt {
+            outputs: vec![output],
+            ..Psbt::default()
+        };
+        assert_eq!(finalize(&mut psbt), Ok(1));
+
+        let proof = psbt.outputs[0].tapret_proof().unwrap();
+        assert_eq!(proof, TapretProof {
+            partner_node: Some(partner),
+            nonce: 14
+        });
+        assert!(leaf_hash(proof.nonce) > partner.into_inner());
+
+        // The commitment leaf and the original tree root are the children of
+        // the new root, with the commitment leaf being the right one
+        let tap_tree = psbt.outputs[0].tap_tree.clone().unwrap();
+        let spend_info = tap_tree
+            .into_builder()
+            .finalize(SECP256K1, internal_key)
+            .unwrap();
+        let tapret = tapret_script(commitment, 14);
+        let control_block = spend_info
+            .control_block(&(tapret.clone(), LeafVersion::TapScript))
+            .unwrap();
+        assert_eq!(control_block.merkle_branch.as_inner().len(), 1);
+        assert_eq!(control_block.merkle_branch.as_inner(), &[partner]);
+        assert_eq!(
+            psbt.outputs[0].script.as_inner(),
+            &Script::new_v1_p2tr_tweaked(spend_info.output_key())
+        );
+    }
+
+    #[test]
+    fn tapret_proof_encoding() {
+        let proof = TapretProof {
+            partner_node: None,
+            nonce: 3,
+        };
+        assert_eq!(proof.strict_serialize().unwrap(), vec![0u8, 3]);
+        assert_eq!(TapretProof::strict_deserialize(&[0u8, 3]).unwrap(), proof);
+
+        let hash = sha256::Hash::hash(b"partner");
+        let proof = TapretProof {
+            partner_node: Some(hash),
+            nonce: 14,
+        };
+        let data = proof.strict_serialize().unwrap();
+        assert_eq!(data.len(), 35);
+        assert_eq!(&data[..2], &[1u8, 0]);
+        assert_eq!(&data[2..34], &hash[..]);
+        assert_eq!(data[34], 14);
+        assert_eq!(TapretProof::strict_deserialize(&data).unwrap(), proof);
+
+        let mut data = data;
+        data[1] = 1;
+        assert!(TapretProof::strict_deserialize(&data).is_err());
+        assert!(TapretProof::strict_deserialize(&[2u8, 0]).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Interoperability layer between the rust-bitcoin (v0.29) and miniscript (v9)
+//! versions used by this library and the latest rust-bitcoin (v0.32) and
+//! miniscript (v12) releases, allowing downstream crates depending on either
+//! of the versions to exchange transactions, PSBTs, scripts and descriptors.
+//!
+//! Transactions and PSBTs are converted using their consensus and BIP-174
+//! serialization, which is identical in both versions; scripts, outpoints and
+//! transaction outputs are converted field by field; descriptors are converted
+//! through their string representation.
+//!
+//! The library itself stays on rust-bitcoin v0.29 and miniscript v9: moving
+//! to the latest versions changes public APIs of all the workspace crates and
+//! is not a part of this module. Until then, the conversions provided here are
+//! the supported way of bridging the two versions.
+
+use amplify::Wrapper;
+use bitcoin::consensus;
+use bitcoin::hashes::Hash;
+use bitcoin_latest as latest;
+use bitcoin_latest::hashes::Hash as _;
+use bitcoin_scripts::PubkeyScript;
+
+use crate::serialize::{Deserialize, Serialize};
+use crate::Psbt;
+
+/// Errors converting data between rust-bitcoin and miniscript versions
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum CompatError {
+    /// data produced by rust-bitcoin v0.32 can't be decoded: {0}
+    #[from]
+    Legacy(consensus::encode::Error),
+
+    /// data can't be decoded by rust-bitcoin v0.32: {0}
+    #[from]
+    Latest(latest::consensus::encode::Error),
+
+    /// PSBT can't be decoded by rust-bitcoin v0.32: {0}
+    #[from]
+    LatestPsbt(latest::psbt::Error),
+
+    /// descriptor produced by miniscript v12 can't be parsed: {0}
+    #[cfg(feature = "miniscript")]
+    #[from]
+    LegacyDescriptor(miniscript::Error),
+
+    /// descriptor can't be parsed by miniscript v12: {0}
+    #[cfg(feature = "miniscript")]
+    #[from]
+    LatestDescriptor(miniscript_latest::Error),
+}
+
+/// Conversion of the data types into their counterparts from the latest
+/// rust-bitcoin and miniscript versions
+pub trait ToLatest {
+    /// Data type from the latest rust-bitcoin or miniscript version
+    type Latest;
+
+    /// Converts the data into the latest rust-bitcoin or miniscript type
+    fn to_latest(&self) -> Result<Self::Latest, CompatError>;
+}
+
+/// Conversion of the data types from their counterparts from the latest
+/// rust-bitcoin and miniscript versions
+pub trait FromLatest: Sized {
+    /// Data type from the latest rust-bitcoin or miniscript version
+    type Latest;
+
+    /// Constructs the data from the latest rust-bitcoin or miniscript type
+    fn from_latest(latest: &Self::Latest) -> Result<Self, CompatError>;
+}
+
+impl ToLatest for Psbt {
+    type Latest = latest::Psbt;
+
+    fn to_latest(&self) -> Result<Self::Latest, CompatError> {
+        Ok(latest::Psbt::deserialize(&self.serialize())?)
+    }
+}
+
+impl FromLatest for Psbt {
+    type Latest = latest::Psbt;
+
+    fn from_latest(latest: &Self::Latest) -> Result<Self, CompatError> {
+        Ok(Psbt::deserialize(&latest.serialize())?)
+    }
+}
+
+impl TryFrom<Psbt> for latest::Psbt {
+    type Error = CompatError;
+
+    fn try_from(psbt: Psbt) -> Result<Self, Self::Error> { psbt.to_latest() }
+}
+
+impl TryFrom<latest::Psbt> for Psbt {
+    type Error = CompatError;
+
+    fn try_from(psbt: latest::Psbt) -> Result<Self, Self::Error> { Psbt::from_latest(&psbt) }
+}
+
+impl ToLatest for bitcoin::Transaction {
+    type Latest = latest::Transaction;
+
+    fn to_latest(&self) -> Result<Self::Latest, CompatError> {
+        Ok(latest::consensus::deserialize(&consensus::serialize(self))?)
+    }
+}
+
+impl FromLatest for bitcoin::Transaction {
+    type Latest = latest::Transaction;
+
+    fn from_latest(latest: &Self::Latest) -> Result<Self, CompatError> {
+        Ok(consensus::deserialize(&latest::consensus::serialize(
+            latest,
+        ))?)
+    }
+}
+
+impl ToLatest for bitcoin::Script {
+    type Latest = latest::ScriptBuf;
+
+    fn to_latest(&self) -> Result<Self::Latest, CompatError> {
+        Ok(latest::ScriptBuf::from_bytes(self.to_bytes()))
+    }
+}
+
+impl FromLatest for bitcoin::Script {
+    type Latest = latest::ScriptBuf;
+
+    fn from_latest(latest: &Self::Latest) -> Result<Self, CompatError> {
+        Ok(bitcoin::Script::from(latest.to_bytes()))
+    }
+}
+
+impl ToLatest for PubkeyScript {
+    type Latest = latest::ScriptBuf;
+
+    fn to_latest(&self) -> Result<Self::Latest, CompatError> { self.as_inner().to_latest() }
+}
+
+impl FromLatest for PubkeyScript {
+    type Latest = latest::ScriptBuf;
+
+    fn from_latest(latest: &Self::Latest) -> Result<Self, CompatError> {
+        bitcoin::Script::from_latest(latest).map(PubkeyScript::from)
+    }
+}
+
+impl ToLatest for bitcoin::OutPoint {
+    type Latest = latest::OutPoint;
+
+    fn to_latest(&self) -> Result<Self::Latest, CompatError> {
+        let txid = latest::Txid::from_byte_array(self.txid.into_inner());
+        Ok(latest::OutPoint::new(txid, self.vout))
+    }
+}
+
+impl FromLatest for bitcoin::OutPoint {
+    type Latest = latest::OutPoint;
+
+    fn from_latest(latest: &Self::Latest) -> Result<Self, CompatError> {
+        let txid = bitcoin::Txid::from_inner(latest.txid.to_byte_array());
+        Ok(bitcoin::OutPoint::new(txid, latest.vout))
+    }
+}
+
+impl ToLatest for bitcoin::TxOut {
+    type Latest = latest::TxOut;
+
+    fn to_latest(&self) -> Result<Self::Latest, CompatError> {
+        Ok(latest::TxOut {
+            value: latest::Amount::from_sat(self.value),
+            script_pubkey: self.script_pubkey.to_latest()?,
+        })
+    }
+}
+
+impl FromLatest for bitcoin::TxOut {
+    type Latest = latest::TxOut;
+
+    fn from_latest(latest: &Self::Latest) -> Result<Self, CompatError> {
+        Ok(bitcoin::TxOut {
+            value: latest.value.to_sat(),
+            script_pubkey: bitcoin::Script::from_latest(&latest.script_pubkey)?,
+        })
+    }
+}
+
+#[cfg(feature = "miniscript")]
+mod _miniscript {
+    use std::str::FromStr;
+
+    use miniscript::{Descriptor, DescriptorPublicKey};
+
+    use super::*;
+
+    impl ToLatest for Descriptor<bitcoin::PublicKey> {
+        type Latest = miniscript_latest::Descriptor<latest::PublicKey>;
+
+        fn to_latest(&self) -> Result<Self::Latest, CompatError> {
+            Ok(miniscript_latest::Descriptor::from_str(&self.to_string())?)
+        }
+    }
+
+    impl FromLatest for Descriptor<bitcoin::PublicKey> {
+        type Latest = miniscript_latest::Descriptor<latest::PublicKey>;
+
+        fn from_latest(latest: &Self::Latest) -> Result<Self, CompatError> {
+            Ok(Descriptor::from_str(&latest.to_string())?)
+        }
+    }
+
+    impl ToLatest for Descriptor<DescriptorPublicKey> {
+        type Latest = miniscript_latest::Descriptor<miniscript_latest::DescriptorPublicKey>;
+
+        fn to_latest(&self) -> Result<Self::Latest, CompatError> {
+            Ok(miniscript_latest::Descriptor::from_str(&self.to_string())?)
+        }
+    }
+
+    impl FromLatest for Descriptor<DescriptorPublicKey> {
+        type Latest = miniscript_latest::Descriptor<miniscript_latest::DescriptorPublicKey>;
+
+        fn from_latest(latest: &Self::Latest) -> Result<Self, CompatError> {
+            Ok(Descriptor::from_str(&latest.to_string())?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::{PackedLockTime, Transaction, TxIn, TxOut, Txid, WPubkeyHash};
+
+    use super::*;
+    use crate::PsbtVersion;
+
+    #[test]
+    fn psbt_roundtrip() {
+        let script_pubkey = bitcoin::Script::new_v0_p2wpkh(&WPubkeyHash::hash(b"payee"));
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(800_000),
+            input: vec![TxIn {
+                previous_output: bitcoin::OutPoint::new(Txid::hash(b"prev"), 1),
+                ..TxIn::default()
+            }],
+            output: vec![TxOut {
+                value: 50_000,
+                script_pubkey: script_pubkey.clone(),
+            }],
+        };
+
+        let latest_tx = tx.to_latest().unwrap();
+        assert_eq!(
+            latest_tx.compute_txid().to_byte_array(),
+            tx.txid().into_inner()
+        );
+        assert_eq!(bitcoin::Transaction::from_latest(&latest_tx).unwrap(), tx);
+
+        let txout = tx.output[0].to_latest().unwrap();
+        assert_eq!(txout.value.to_sat(), 50_000);
+        assert_eq!(txout.script_pubkey.as_bytes(), script_pubkey.as_bytes());
+        let outpoint = tx.input[0].previous_output;
+        let latest_outpoint = outpoint.to_latest().unwrap();
+        assert_eq!(
+            bitcoin::OutPoint::from_latest(&latest_outpoint).unwrap(),
+            outpoint
+        );
+
+        let psbt = Psbt::with(tx, PsbtVersion::V0).unwrap();
+        let latest_psbt = latest::Psbt::try_from(psbt.clone()).unwrap();
+        assert_eq!(latest_psbt.unsigned_tx, latest_tx);
+        assert_eq!(Psbt::try_from(latest_psbt).unwrap(), psbt);
+    }
+}
@@ -0,0 +1,399 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Fee bumping of transactions with anchor outputs.
+//!
+//! Anchor outputs can be spent by anyone without a signature, allowing any
+//! party to bump the fee of the parent transaction with a child paying for the
+//! whole package (CPFP). The sponsor constructed here spends the first anchor
+//! output of the parent together with the wallet inputs, and sends all the
+//! funds left after paying the fee to the wallet change address.
+
+use std::iter;
+
+use bitcoin::blockdata::opcodes::all::OP_PUSHNUM_1;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::{OutPoint, Script, Transaction, TxOut, Txid, Witness};
+use bitcoin_hd::{DerivationAccount, SegmentIndexes, UnhardenedIndex};
+use bitcoin_onchain::{ResolveTx, TxResolverError};
+use descriptors::derive::Descriptor as _;
+use descriptors::estimate::SatisfactionEstimate;
+use descriptors::{ExternalInput, InputDescriptor};
+use miniscript::Descriptor;
+
+use super::batch::{max_input_weight, output_weight, tx_weight};
+use super::{BatchParams, Error};
+use crate::Psbt;
+
+/// Witness program of pay-to-anchor (P2A) outputs
+pub const P2A_PROGRAM: [u8; 2] = [0x4e, 0x73];
+
+/// Maximal virtual size of a child of a version 3 (TRUC) transaction, in
+/// vbytes
+pub const TRUC_CHILD_MAX_VSIZE: usize = 1000;
+
+/// Types of the anchor outputs which can be spent by anyone
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum AnchorType {
+    /// Pay-to-anchor output: witness v1 program `4e73`, spent with an empty
+    /// witness
+    #[display("p2a")]
+    P2a,
+
+    /// P2WSH output committing to `OP_TRUE` witness script
+    #[display("wsh(true)")]
+    WshTrue,
+}
+
+impl AnchorType {
+    /// Detects type of the anchor output from its `scriptPubkey`
+    pub fn from_script(script: &Script) -> Option<Self> {
+        if *script == AnchorType::P2a.script_pubkey() {
+            Some(AnchorType::P2a)
+        } else if *script == AnchorType::WshTrue.script_pubkey() {
+            Some(AnchorType::WshTrue)
+        } else {
+            None
+        }
+    }
+
+    /// Returns `scriptPubkey` of the anchor output
+    pub fn script_pubkey(self) -> Script {
+        match self {
+            AnchorType::P2a => Builder::new()
+                .push_opcode(OP_PUSHNUM_1)
+                .push_slice(&P2A_PROGRAM)
+                .into_script(),
+            AnchorType::WshTrue => Script::new_v0_p2wsh(&true_script().wscript_hash()),
+        }
+    }
+
+    /// Returns witness script spent by the anchor, if any
+    pub fn witness_script(self) -> Option<Script> {
+        match self {
+            AnchorType::P2a => None,
+            AnchorType::WshTrue => Some(true_script()),
+        }
+    }
+
+    /// Returns final witness spending the anchor output
+    pub fn witness(self) -> Witness {
+        match self {
+            AnchorType::P2a => Witness::new(),
+            AnchorType::WshTrue => Witness::from_vec(vec![true_script().to_bytes()]),
+        }
+    }
+
+    /// Size of the data spending the anchor output
+    pub fn satisfaction(self) -> SatisfactionEstimate {
+        SatisfactionEstimate {
+            script_sig: 0,
+            witness: self.witness().serialized_len(),
+        }
+    }
+}
+
+fn true_script() -> Script { Builder::new().push_opcode(OP_PUSHNUM_1).into_script() }
+
+/// Anchor output of a transaction
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display("{anchor_type}:{vout}")]
+pub struct AnchorOutput {
+    /// Number of the output in the transaction
+    pub vout: u32,
+
+    /// Amount of the output, in satoshis
+    pub value: u64,
+
+    /// Type of the anchor output
+    pub anchor_type: AnchorType,
+}
+
+/// Returns all anchor outputs of the transaction, in the order of their
+/// appearance.
+pub fn anchor_outputs(tx: &Transaction) -> Vec<AnchorOutput> {
+    tx.output
+        .iter()
+        .enumerate()
+        .filter_map(|(vout, txout)| {
+            AnchorType::from_script(&txout.script_pubkey).map(|anchor_type| AnchorOutput {
+                vout: vout as u32,
+                value: txout.value,
+                anchor_type,
+            })
+        })
+        .collect()
+}
+
+/// Errors constructing anchor fee bump transaction
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum AnchorError {
+    /// {0}
+    #[from]
+    #[from(TxResolverError)]
+    Construct(Error),
+
+    /// transaction {0} has no anchor outputs
+    NoAnchor(Txid),
+
+    /// parent transaction {0} spends more than the sum of its input amounts
+    ParentInflation(Txid),
+
+    /// wallet inputs are insufficient to pay the package fee
+    InsufficientFunds,
+
+    /// fee bump transaction size {0} vbytes exceeds limit of 1000 vbytes for
+    /// a child of version 3 transaction
+    ChildTooLarge(usize),
+}
+
+impl Psbt {
+    /// Constructs fee bump (CPFP) transaction spending the first anchor output
+    /// of the `parent` transaction, such that the package of both
+    /// transactions pays [`BatchParams::feerate`].
+    ///
+    /// Wallet inputs are taken from the `inputs` in the order they are
+    /// provided until they cover the package fee and a change above the
+    /// [`BatchParams::dust_limit`]; the change is sent to the change
+    /// descriptor at `change_index`. Parent fee is computed from its spent
+    /// outputs, which must be known to the `tx_resolver`. If the parent is a
+    /// version 3 (TRUC) transaction, the fee bump uses version 3 as well.
+    ///
+    /// The anchor input is finalized in the returned PSBT; wallet inputs are
+    /// left for signing.
+    pub fn construct_anchor_bump<'inputs>(
+        descriptor: &Descriptor<DerivationAccount>,
+        change_descriptor: &Descriptor<DerivationAccount>,
+        parent: &Transaction,
+        inputs: impl IntoIterator<Item = &'inputs InputDescriptor>,
+        change_index: impl Into<UnhardenedIndex>,
+        params: &BatchParams,
+        tx_resolver: &impl ResolveTx,
+    ) -> Result<Psbt, AnchorError> {
+        let txid = parent.txid();
+        let anchor = *anchor_outputs(parent)
+            .first()
+            .ok_or(AnchorError::NoAnchor(txid))?;
+
+        let mut parent_spent = 0u64;
+        for txin in &parent.input {
+            let prevout = txin.previous_output;
+            let tx = tx_resolver.resolve_tx(prevout.txid)?;
+            let txout = tx
+                .output
+                .get(prevout.vout as usize)
+                .ok_or(Error::OutputUnknown(prevout.txid, prevout.vout))?;
+            parent_spent += txout.value;
+        }
+        let parent_sent = parent.output.iter().map(|txout| txout.value).sum::<u64>();
+        let parent_fee = parent_spent
+            .checked_sub(parent_sent)
+            .ok_or(AnchorError::ParentInflation(txid))?;
+
+        let change_index = change_index.into();
+        let change_script = match change_descriptor {
+            Descriptor::Tr(_) => change_descriptor
+                .script_pubkey_tr(SECP256K1, [UnhardenedIndex::one(), change_index]),
+            _ => change_descriptor
+                .script_pubkey_pretr(SECP256K1, [UnhardenedIndex::one(), change_index]),
+        }
+        .map_err(Error::from)?;
+        let input_weight = max_input_weight(descriptor)?.max(max_input_weight(change_descriptor)?);
+        let anchor_weight = anchor.anchor_type.satisfaction().input_weight();
+        let change_weight = output_weight(&change_script);
+
+        let mut wallet_inputs = vec![];
+        let mut spent = anchor.value;
+        let mut inputs = inputs.into_iter();
+        let (weight, fee) = loop {
+            // Anchor input is accounted as a wallet input and then replaced
+            let weight = tx_weight(wallet_inputs.len() + 1, input_weight, 1, change_weight);
+            let weight = weight - input_weight + anchor_weight;
+            let package_fee = params.fee(parent.weight() + weight);
+            let fee = package_fee
+                .saturating_sub(parent_fee)
+                .max(params.fee(weight));
+            if spent >= fee + params.dust_limit {
+                break (weight, fee);
+            }
+            let input = inputs.next().ok_or(AnchorError::InsufficientFunds)?;
+            let tx = tx_resolver.resolve_tx(input.outpoint.txid)?;
+            let prev_output =
+                tx.output
+                    .get(input.outpoint.vout as usize)
+                    .ok_or(Error::OutputUnknown(
+                        input.outpoint.txid,
+                        input.outpoint.vout,
+                    ))?;
+            spent += prev_output.value;
+            wallet_inputs.push(input);
+        };
+
+        let vsize = (weight + 3) / 4;
+        if parent.version == 3 && vsize > TRUC_CHILD_MAX_VSIZE {
+            return Err(AnchorError::ChildTooLarge(vsize));
+        }
+
+        let anchor_input = ExternalInput {
+            witness_script: anchor.anchor_type.witness_script(),
+            ..ExternalInput::with(OutPoint::new(txid, anchor.vout), TxOut {
+                value: anchor.value,
+                script_pubkey: anchor.anchor_type.script_pubkey(),
+            })
+        };
+        let mut psbt = Psbt::construct_with_foreign(
+            descriptor,
+            change_descriptor,
+            wallet_inputs,
+            iter::once(&anchor_input),
+            iter::empty(),
+            change_index,
+            fee,
+            tx_resolver,
+        )?;
+        if parent.version == 3 {
+            psbt.tx_version = 3;
+        }
+        let anchor_index = psbt.inputs.len() - 1;
+        psbt.inputs[anchor_index].final_script_witness = Some(anchor.anchor_type.witness());
+        Ok(psbt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::{PackedLockTime, TxIn, WPubkeyHash};
+    use bitcoin_hd::DerivationSubpath;
+
+    use super::*;
+
+    #[test]
+    fn p2a_fee_bump() {
+        let descriptor = Descriptor::<DerivationAccount>::from_str(
+            "wpkh([d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqF\
+             JPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/*/*)",
+        )
+        .unwrap();
+        let terminal = DerivationSubpath::from(&[UnhardenedIndex::zero(); 2][..]);
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: descriptor
+                    .script_pubkey_pretr(SECP256K1, &terminal)
+                    .unwrap(),
+            }],
+        };
+        let grandparent = Transaction {
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::hash(b"payer")),
+            }],
+            ..funding.clone()
+        };
+        let parent = Transaction {
+            version: 3,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(grandparent.txid(), 0),
+                ..TxIn::default()
+            }],
+            output: vec![
+                TxOut {
+                    value: 9_900,
+                    script_pubkey: Script::new_v0_p2wpkh(&WPubkeyHash::hash(b"payee")),
+                },
+                TxOut {
+                    value: 0,
+                    script_pubkey: AnchorType::P2a.script_pubkey(),
+                },
+            ],
+        };
+        assert_eq!(anchor_outputs(&parent), vec![AnchorOutput {
+            vout: 1,
+            value: 0,
+            anchor_type: AnchorType::P2a,
+        }]);
+        assert_eq!(
+            AnchorType::from_script(&AnchorType::WshTrue.script_pubkey()),
+            Some(AnchorType::WshTrue)
+        );
+
+        let resolver = bmap! {
+            funding.txid() => funding.clone(),
+            grandparent.txid() => grandparent,
+        };
+        let inputs = [InputDescriptor::with(
+            OutPoint::new(funding.txid(), 0),
+            terminal,
+        )];
+        let params = BatchParams {
+            feerate: 10.0,
+            ..default!()
+        };
+        let psbt = Psbt::construct_anchor_bump(
+            &descriptor,
+            &descriptor,
+            &parent,
+            &inputs,
+            0u8,
+            &params,
+            &resolver,
+        )
+        .unwrap();
+
+        assert_eq!(psbt.tx_version, 3);
+        assert_eq!(psbt.inputs.len(), 2);
+        assert_eq!(
+            psbt.inputs[1].previous_outpoint,
+            OutPoint::new(parent.txid(), 1)
+        );
+        assert_eq!(psbt.inputs[1].final_script_witness, Some(Witness::new()));
+        assert_eq!(psbt.outputs.len(), 1);
+        assert!(psbt.outputs[0].has_change_flag());
+
+        // Signed weight: wallet input witness, segwit marker and flag and
+        // empty anchor witness
+        let witness_weight = max_input_weight(&descriptor).unwrap() - 41 * 4;
+        let weight = psbt.to_unsigned_tx().weight() + witness_weight + 2 + 1;
+        assert_eq!(
+            psbt.fee().unwrap() + 100,
+            params.fee(parent.weight() + weight)
+        );
+
+        let no_anchor = Transaction {
+            output: vec![parent.output[0].clone()],
+            ..parent
+        };
+        assert!(matches!(
+            Psbt::construct_anchor_bump(
+                &descriptor,
+                &descriptor,
+                &no_anchor,
+                &inputs,
+                0u8,
+                &params,
+                &resolver
+            ),
+            Err(AnchorError::NoAnchor(_))
+        ));
+    }
+}
@@ -0,0 +1,417 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Batch construction of payout transactions.
+//!
+//! Payout engines need to pay hundreds of outputs at once, which may not fit
+//! into a single standard transaction. Batch constructor splits the payouts
+//! into a chain of transactions, each of which stays within the standardness
+//! limits. Wallet inputs are taken from a shared pool in the order they are
+//! provided, and the change of each transaction is spent by the next one, so
+//! the whole chain can be broadcast at once.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use bitcoin::blockdata::opcodes::all::*;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::{OutPoint, Script, Transaction, Txid, VarInt};
+use bitcoin_hd::{DerivationAccount, DerivationSubpath, SegmentIndexes, UnhardenedIndex};
+use bitcoin_onchain::{ResolveTx, TxResolverError};
+use bitcoin_scripts::PubkeyScript;
+use descriptors::derive::Descriptor as _;
+use descriptors::estimate::{estimate_descriptor, TXIN_BASE_WEIGHT};
+use descriptors::{CompositeDescrType, InputDescriptor};
+use miniscript::{Descriptor, ForEachKey};
+
+use super::Error;
+use crate::Psbt;
+
+/// Maximal weight of a transaction relayed by nodes with the default policy.
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+
+/// Maximal signature operations cost of a transaction relayed by nodes with
+/// the default policy.
+pub const MAX_STANDARD_TX_SIGOPS_COST: usize = 80_000;
+
+/// Default amount below which change is not created and is added to the fee.
+pub const DEFAULT_DUST_LIMIT: u64 = 546;
+
+/// Weight of transaction version, lock time and segwit marker and flag.
+const TX_OVERHEAD_WEIGHT: usize = (4 + 4) * 4 + 2;
+
+/// Errors constructing batch of payout transactions
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum BatchError {
+    /// {0}
+    #[from]
+    #[from(TxResolverError)]
+    Construct(Error),
+
+    /// wallet inputs are insufficient to pay output #{0} and transaction fees
+    InsufficientFunds(usize),
+
+    /// output #{0} can't be paid by a transaction within the standardness
+    /// limits
+    OutputTooLarge(usize),
+
+    /// batch transaction #{0} spends non-segwit inputs; its id is malleable,
+    /// so its change can't be spent by the next transaction of the batch
+    MalleableChain(usize),
+
+    /// change derivation index overflow
+    ChangeIndexOverflow,
+}
+
+/// Parameters of batch construction
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BatchParams {
+    /// Feerate, in satoshis per vbyte
+    pub feerate: f32,
+
+    /// Maximal weight of each of the transactions
+    pub max_weight: usize,
+
+    /// Maximal signature operations cost of each of the transactions
+    pub max_sigops_cost: usize,
+
+    /// Change below this amount is not created and is added to the fee
+    pub dust_limit: u64,
+}
+
+impl Default for BatchParams {
+    fn default() -> Self {
+        BatchParams {
+            feerate: 1.0,
+            max_weight: MAX_STANDARD_TX_WEIGHT,
+            max_sigops_cost: MAX_STANDARD_TX_SIGOPS_COST,
+            dust_limit: DEFAULT_DUST_LIMIT,
+        }
+    }
+}
+
+impl BatchParams {
+    /// Fee for a transaction of a given weight.
+    pub fn fee(&self, weight: usize) -> u64 {
+        (((weight + 3) / 4) as f32 * self.feerate).ceil() as u64
+    }
+}
+
+/// Resolver which knows transactions of the batch constructed so far, in
+/// addition to the transactions known to the wallet resolver.
+struct ChainResolver<'resolver, R: ResolveTx> {
+    resolver: &'resolver R,
+    chain: BTreeMap<Txid, Transaction>,
+}
+
+impl<'resolver, R: ResolveTx> ResolveTx for ChainResolver<'resolver, R> {
+    fn resolve_tx(&self, txid: Txid) -> Result<Transaction, TxResolverError> {
+        match self.chain.get(&txid) {
+            Some(tx) => Ok(tx.clone()),
+            None => self.resolver.resolve_tx(txid),
+        }
+    }
+}
+
+pub(super) fn output_weight(script: &Script) -> usize {
+    (8 + VarInt(script.len() as u64).len() + script.len()) * 4
+}
+
+pub(super) fn tx_weight(
+    inputs: usize,
+    input_weight: usize,
+    outputs: usize,
+    outputs_weight: usize,
+) -> usize {
+    TX_OVERHEAD_WEIGHT
+        + (VarInt(inputs as u64).len() + VarInt(outputs as u64).len()) * 4
+        + inputs * input_weight
+        + outputs_weight
+}
+
+/// Signature operations cost of an output script.
+fn output_sigops_cost(script: &Script) -> usize {
+    let sigops = script
+        .instructions()
+        .filter_map(Result::ok)
+        .map(|instruction| match instruction {
+            Instruction::Op(op) if op == OP_CHECKSIG || op == OP_CHECKSIGVERIFY => 1,
+            Instruction::Op(op) if op == OP_CHECKMULTISIG || op == OP_CHECKMULTISIGVERIFY => 20,
+            _ => 0,
+        })
+        .sum::<usize>();
+    sigops * 4
+}
+
+/// Upper bound on the signature operations cost of an input spending the
+/// descriptor, assuming that each of the descriptor keys is checked once.
+fn input_sigops_cost(descriptor: &Descriptor<DerivationAccount>) -> usize {
+    let mut keys = 0usize;
+    descriptor.for_each_key(|_| {
+        keys += 1;
+        true
+    });
+    match CompositeDescrType::from(descriptor) {
+        CompositeDescrType::Tr => 0,
+        ty if ty.is_segwit() => keys,
+        _ => keys * 4,
+    }
+}
+
+/// Maximal weight of an input spending the descriptor, including the outpoint
+/// and sequence number.
+pub(crate) fn max_input_weight(descriptor: &Descriptor<DerivationAccount>) -> Result<usize, Error> {
+    Ok(match estimate_descriptor(descriptor) {
+        Some(estimate) => estimate.input_weight(),
+        None => TXIN_BASE_WEIGHT + descriptor.max_satisfaction_weight()?,
+    })
+}
+
+impl Psbt {
+    /// Constructs a chain of transactions paying all of the `outputs`,
+    /// splitting them into multiple transactions such that each of them stays
+    /// within [`BatchParams`] weight and signature operations limits. Outputs
+    /// are paid in the order they are provided.
+    ///
+    /// Each transaction spends inputs from the shared `inputs` pool (taken in
+    /// the provided order) and sends change to the change descriptor, using
+    /// sequential change indexes starting from `change_index`. All but the
+    /// first transactions spend the change of the previous transaction, which
+    /// requires the previous transaction to spend only segwit inputs.
+    ///
+    /// Transaction fees are computed from the feerate and the maximal
+    /// satisfaction size of the wallet descriptors (see
+    /// [`descriptors::estimate`]); inputs with explicit or external scripts
+    /// are assumed to have the same size.
+    pub fn construct_batch<'inputs, 'outputs>(
+        descriptor: &Descriptor<DerivationAccount>,
+        change_descriptor: &Descriptor<DerivationAccount>,
+        inputs: impl IntoIterator<Item = &'inputs InputDescriptor>,
+        outputs: impl IntoIterator<Item = &'outputs (PubkeyScript, u64)>,
+        change_index: impl Into<UnhardenedIndex>,
+        params: &BatchParams,
+        tx_resolver: &impl ResolveTx,
+    ) -> Result<Vec<Psbt>, BatchError> {
+        let mut pool = VecDeque::new();
+        for input in inputs {
+            let tx = tx_resolver.resolve_tx(input.outpoint.txid)?;
+            let prev_output =
+                tx.output
+                    .get(input.outpoint.vout as usize)
+                    .ok_or(Error::OutputUnknown(
+                        input.outpoint.txid,
+                        input.outpoint.vout,
+                    ))?;
+            pool.push_back((input.clone(), prev_output.value));
+        }
+        let outputs = outputs.into_iter().collect::<Vec<_>>();
+
+        let mut change_index = change_index.into();
+        let change_script = match change_descriptor {
+            Descriptor::Tr(_) => change_descriptor
+                .script_pubkey_tr(SECP256K1, [UnhardenedIndex::one(), change_index]),
+            _ => change_descriptor
+                .script_pubkey_pretr(SECP256K1, [UnhardenedIndex::one(), change_index]),
+        }
+        .map_err(Error::from)?;
+        let input_weight = max_input_weight(descriptor)?.max(max_input_weight(change_descriptor)?);
+        let input_sigops = input_sigops_cost(descriptor).max(input_sigops_cost(change_descriptor));
+
+        let mut resolver = ChainResolver {
+            resolver: tx_resolver,
+            chain: bmap! {},
+        };
+        let mut psbts = vec![];
+        let mut carry = None;
+        let mut next = 0usize;
+        while next < outputs.len() {
+            let mut batch = carry
+                .take()
+                .into_iter()
+                .collect::<Vec<(InputDescriptor, u64)>>();
+            let mut spent = batch.iter().map(|(_, value)| value).sum::<u64>();
+            let mut sent = 0u64;
+            // Change output is always accounted for
+            let mut outputs_weight = output_weight(&change_script);
+            let mut outputs_sigops = output_sigops_cost(&change_script);
+
+            let mut end = next;
+            while let Some((script, amount)) = outputs.get(end) {
+                let weight_with = outputs_weight + output_weight(script);
+                let sigops_with = outputs_sigops + output_sigops_cost(script);
+                let sent_with = sent + amount;
+                let output_count = end - next + 2;
+
+                let mut taken = 0usize;
+                let mut spent_with = spent;
+                let weight = loop {
+                    let weight =
+                        tx_weight(batch.len() + taken, input_weight, output_count, weight_with);
+                    if spent_with >= sent_with + params.fee(weight) {
+                        break weight;
+                    }
+                    let (_, value) = pool.get(taken).ok_or(BatchError::InsufficientFunds(end))?;
+                    spent_with += value;
+                    taken += 1;
+                };
+
+                let sigops = sigops_with + (batch.len() + taken) * input_sigops;
+                if weight > params.max_weight || sigops > params.max_sigops_cost {
+                    if end == next {
+                        return Err(BatchError::OutputTooLarge(end));
+                    }
+                    break;
+                }
+
+                batch.extend(pool.drain(..taken));
+                spent = spent_with;
+                sent = sent_with;
+                outputs_weight = weight_with;
+                outputs_sigops = sigops_with;
+                end += 1;
+            }
+
+            let weight = tx_weight(batch.len(), input_weight, end - next + 1, outputs_weight);
+            let mut fee = params.fee(weight);
+            let mut change = spent - sent - fee;
+            if change < params.dust_limit {
+                fee += change;
+                change = 0;
+            }
+            let psbt = Psbt::construct_with_change(
+                descriptor,
+                change_descriptor,
+                batch.iter().map(|(input, _)| input),
+                outputs[next..end].iter().copied(),
+                change_index,
+                fee,
+                &resolver,
+            )?;
+            next = end;
+
+            if next < outputs.len() {
+                if change > 0 {
+                    if psbt.inputs.iter().any(|input| input.witness_utxo.is_none()) {
+                        return Err(BatchError::MalleableChain(psbts.len()));
+                    }
+                    let tx = psbt.to_unsigned_tx();
+                    let txid = tx.txid();
+                    let outpoint = OutPoint::new(txid, psbt.outputs.len() as u32 - 1);
+                    let terminal =
+                        DerivationSubpath::from(&[UnhardenedIndex::one(), change_index][..]);
+                    let (first, _) = &batch[0];
+                    let input = InputDescriptor {
+                        seq_no: first.seq_no,
+                        sighash_type: first.sighash_type,
+                        ..InputDescriptor::with(outpoint, terminal)
+                    };
+                    carry = Some((input, change));
+                    resolver.chain.insert(txid, tx);
+                }
+                change_index = change_index
+                    .checked_inc()
+                    .ok_or(BatchError::ChangeIndexOverflow)?;
+            }
+            psbts.push(psbt);
+        }
+
+        Ok(psbts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::{PackedLockTime, TxOut, WPubkeyHash};
+
+    use super::*;
+
+    #[test]
+    fn chained_batch() {
+        let descriptor = Descriptor::<DerivationAccount>::from_str(
+            "wpkh([d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqF\
+             JPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/*/*)",
+        )
+        .unwrap();
+        let terminal = |index: u8| {
+            DerivationSubpath::from(&[UnhardenedIndex::zero(), UnhardenedIndex::from(index)][..])
+        };
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: (0..10u8)
+                .map(|index| TxOut {
+                    value: 100_000,
+                    script_pubkey: descriptor
+                        .script_pubkey_pretr(SECP256K1, terminal(index))
+                        .unwrap(),
+                })
+                .collect(),
+        };
+        let txid = funding.txid();
+        let resolver = bmap! { txid => funding };
+        let inputs = (0..10u8)
+            .map(|index| InputDescriptor::with(OutPoint::new(txid, index as u32), terminal(index)))
+            .collect::<Vec<_>>();
+        let payouts = (0..100u8)
+            .map(|tag| {
+                let script = Script::new_v0_p2wpkh(&WPubkeyHash::hash(&[tag]));
+                (PubkeyScript::from(script), 5_000)
+            })
+            .collect::<Vec<_>>();
+        let params = BatchParams {
+            feerate: 2.0,
+            max_weight: 4_000,
+            ..default!()
+        };
+
+        let construct = |inputs: &[InputDescriptor], params: &BatchParams| {
+            let (descr, change) = (&descriptor, &descriptor);
+            Psbt::construct_batch(descr, change, inputs, &payouts, 0u8, params, &resolver)
+        };
+
+        let psbts = construct(&inputs, &params).unwrap();
+        assert!(psbts.len() > 1);
+        let paid = psbts
+            .iter()
+            .flat_map(|psbt| &psbt.outputs)
+            .filter(|output| output.bip32_derivation.is_empty())
+            .map(|output| (output.script.clone(), output.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(paid, payouts);
+        for pair in psbts.windows(2) {
+            let prev = pair[0].to_unsigned_tx();
+            let change = OutPoint::new(prev.txid(), prev.output.len() as u32 - 1);
+            assert_eq!(pair[1].inputs[0].previous_outpoint, change);
+            assert!(!pair[0].outputs.last().unwrap().bip32_derivation.is_empty());
+        }
+
+        let params = BatchParams {
+            max_weight: 400,
+            ..default!()
+        };
+        assert!(matches!(
+            construct(&inputs, &params),
+            Err(BatchError::OutputTooLarge(0))
+        ));
+        assert!(matches!(
+            construct(&inputs[..1], &BatchParams::default()),
+            Err(BatchError::InsufficientFunds(19))
+        ));
+    }
+}
@@ -14,19 +14,44 @@
 
 //! Functions, errors and traits specific for PSBT constructor role.
 
-use std::collections::BTreeSet;
+mod anchor;
+mod batch;
+mod policy;
+mod replay;
 
-use bitcoin::secp256k1::SECP256K1;
+use std::collections::{BTreeMap, BTreeSet};
+use std::iter;
+
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::secp256k1::{self, SECP256K1};
+use bitcoin::util::bip32::KeySource;
 use bitcoin::util::psbt::TapTree;
 use bitcoin::util::taproot::{LeafVersion, TapLeafHash, TaprootBuilder, TaprootBuilderError};
-use bitcoin::{Script, Txid, XOnlyPublicKey};
-use bitcoin_hd::{DerivationAccount, DeriveError, SegmentIndexes, UnhardenedIndex};
+use bitcoin::{OutPoint, Script, Txid, Witness, XOnlyPublicKey};
+use bitcoin_hd::{
+    DerivationAccount, DerivationSubpath, DeriveError, KeyRotations, SegmentIndexes,
+    UnhardenedIndex,
+};
 use bitcoin_onchain::{ResolveTx, TxResolverError};
 use bitcoin_scripts::PubkeyScript;
-use descriptors::derive::DeriveDescriptor;
-use descriptors::InputDescriptor;
+use descriptors::derive::{DeriveDescriptor, Descriptor as _};
+use descriptors::{ExternalInput, InputDescriptor};
 use miniscript::{Descriptor, ForEachKey, ToPublicKey};
 
+pub use self::anchor::{
+    anchor_outputs, AnchorError, AnchorOutput, AnchorType, P2A_PROGRAM, TRUC_CHILD_MAX_VSIZE,
+};
+pub(crate) use self::batch::max_input_weight;
+pub use self::batch::{
+    BatchError, BatchParams, DEFAULT_DUST_LIMIT, MAX_STANDARD_TX_SIGOPS_COST,
+    MAX_STANDARD_TX_WEIGHT,
+};
+pub use self::policy::{
+    OutputScriptType, OutputScriptTypeParseError, SpendPolicy, SpendPolicyError,
+};
+pub use self::replay::{
+    ReplayGuard, ReplayPolicy, ReplayWarning, PSBT_GLOBAL_REPLAY_GUARD, PSBT_REPLAY_PREFIX,
+};
 use crate::{self as psbt, Psbt, PsbtVersion};
 
 #[derive(Debug, Display, From)]
@@ -48,6 +73,17 @@ pub enum Error {
     /// `{2}` for {0}:{1}
     ScriptPubkeyMismatch(Txid, u32, Script, Script),
 
+    /// spent output provided for foreign input {0} does not match output of
+    /// the spent transaction
+    ForeignUtxoMismatch(OutPoint),
+
+    /// explicit redeem or witness script of input {0} does not match
+    /// scriptPubkey of the spent output
+    ExplicitScriptMismatch(OutPoint),
+
+    /// script spent by input {0} does not contain any of the wallet keys
+    NoScriptKeys(OutPoint),
+
     /// one of PSBT outputs has invalid script data. {0}
     #[from]
     Miniscript(miniscript::Error),
@@ -66,6 +102,10 @@ pub enum Error {
         /// Amount sent: sum of output value + transaction fee
         output: u64,
     },
+
+    /// constructed PSBT violates the spend policy: {0}
+    #[from]
+    SpendPolicy(SpendPolicyError),
 }
 
 impl std::error::Error for Error {
@@ -75,13 +115,81 @@ impl std::error::Error for Error {
             Error::Derive(err) => Some(err),
             Error::OutputUnknown(_, _) => None,
             Error::ScriptPubkeyMismatch(_, _, _, _) => None,
+            Error::ForeignUtxoMismatch(_) => None,
+            Error::ExplicitScriptMismatch(_) => None,
+            Error::NoScriptKeys(_) => None,
             Error::Miniscript(err) => Some(err),
             Error::Inflation { .. } => None,
             Error::TaprootBuilderError(err) => Some(err),
+            Error::SpendPolicy(err) => Some(err),
         }
     }
 }
 
+/// Selects wallet descriptor controlling the spent output out of the
+/// `candidates`: the first candidate is used unless some other candidate
+/// derives the spent `scriptPubkey` at the input terminal.
+fn spending_descriptor<'descr>(
+    candidates: &'descr [Descriptor<DerivationAccount>],
+    terminal: &[UnhardenedIndex],
+    script_pubkey: &Script,
+) -> &'descr Descriptor<DerivationAccount> {
+    candidates
+        .iter()
+        .skip(1)
+        .filter(|candidate| *candidate != &candidates[0])
+        .find(|candidate| {
+            let derived = match candidate {
+                Descriptor::Tr(_) => candidate.script_pubkey_tr(SECP256K1, terminal),
+                _ => candidate.script_pubkey_pretr(SECP256K1, terminal),
+            };
+            matches!(derived, Ok(derived) if &derived == script_pubkey)
+        })
+        .unwrap_or(&candidates[0])
+}
+
+/// Detects bare scripts, which can't be produced by non-bare wallet
+/// descriptors but may still be signed with the wallet keys.
+fn is_bare(script_pubkey: &Script) -> bool {
+    !(script_pubkey.is_p2sh()
+        || script_pubkey.is_p2pkh()
+        || script_pubkey.is_witness_program()
+        || script_pubkey.is_op_return())
+}
+
+/// Collects derivation information for the descriptor keys which are used by
+/// an arbitrary script.
+fn script_bip32_derivation(
+    descriptors: [&Descriptor<DerivationAccount>; 2],
+    terminal: &DerivationSubpath<UnhardenedIndex>,
+    script: &Script,
+) -> BTreeMap<secp256k1::PublicKey, KeySource> {
+    let pushes = script
+        .instructions()
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(data)) => Some(data),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut bip32_derivation = bmap! {};
+    for descriptor in descriptors {
+        descriptor.for_each_key(|account| {
+            if let Ok((pubkey, key_source)) = account.bip32_derivation(SECP256K1, terminal) {
+                let compressed = pubkey.serialize();
+                let uncompressed = pubkey.serialize_uncompressed();
+                if pushes
+                    .iter()
+                    .any(|data| *data == &compressed[..] || *data == &uncompressed[..])
+                {
+                    bip32_derivation.insert(pubkey, key_source);
+                }
+            }
+            true
+        });
+    }
+    bip32_derivation
+}
+
 impl Psbt {
     pub fn construct<'inputs, 'outputs>(
         descriptor: &Descriptor<DerivationAccount>,
@@ -91,18 +199,158 @@ impl Psbt {
         fee: u64,
         tx_resolver: &impl ResolveTx,
     ) -> Result<Psbt, Error> {
+        Psbt::construct_with_change(
+            descriptor,
+            descriptor,
+            inputs,
+            outputs,
+            change_index,
+            fee,
+            tx_resolver,
+        )
+    }
+
+    /// Constructs PSBT sending change to a separate change descriptor, which
+    /// may use a script class different from the main wallet descriptor (for
+    /// instance, taproot change for a segwit wallet). Inputs may spend
+    /// outputs controlled by either of the descriptors.
+    pub fn construct_with_change<'inputs, 'outputs>(
+        descriptor: &Descriptor<DerivationAccount>,
+        change_descriptor: &Descriptor<DerivationAccount>,
+        inputs: impl IntoIterator<Item = &'inputs InputDescriptor>,
+        outputs: impl IntoIterator<Item = &'outputs (PubkeyScript, u64)>,
+        change_index: impl Into<UnhardenedIndex>,
+        fee: u64,
+        tx_resolver: &impl ResolveTx,
+    ) -> Result<Psbt, Error> {
+        Psbt::construct_with_foreign(
+            descriptor,
+            change_descriptor,
+            inputs,
+            iter::empty(),
+            outputs,
+            change_index,
+            fee,
+            tx_resolver,
+        )
+    }
+
+    /// Constructs PSBT with a separate change descriptor (see
+    /// [`Psbt::construct_with_change`]) and checks it against the spend
+    /// `policy`, failing on the first policy violation.
+    #[allow(clippy::too_many_arguments)]
+    pub fn construct_with_policy<'inputs, 'outputs>(
+        descriptor: &Descriptor<DerivationAccount>,
+        change_descriptor: &Descriptor<DerivationAccount>,
+        inputs: impl IntoIterator<Item = &'inputs InputDescriptor>,
+        outputs: impl IntoIterator<Item = &'outputs (PubkeyScript, u64)>,
+        change_index: impl Into<UnhardenedIndex>,
+        fee: u64,
+        policy: &SpendPolicy,
+        tx_resolver: &impl ResolveTx,
+    ) -> Result<Psbt, Error> {
+        let psbt = Psbt::construct_with_change(
+            descriptor,
+            change_descriptor,
+            inputs,
+            outputs,
+            change_index,
+            fee,
+            tx_resolver,
+        )?;
+        psbt.check_spend_policy(policy, &[descriptor.clone(), change_descriptor.clone()])?;
+        Ok(psbt)
+    }
+
+    /// Constructs PSBT which, in addition to the wallet inputs, spends
+    /// foreign outputs not controlled by the wallet descriptors, allowing
+    /// construction of transactions with mixed input ownership (payjoins,
+    /// coinjoins, multisig fundings etc). Foreign inputs follow the wallet
+    /// inputs and are left for signing by their owners.
+    ///
+    /// Spent transactions for the foreign inputs are resolved on the best
+    /// effort basis: if a transaction is known to the resolver, it is added
+    /// to the PSBT input and checked against the provided spent output.
+    #[allow(clippy::too_many_arguments)]
+    pub fn construct_with_foreign<'inputs, 'foreign, 'outputs>(
+        descriptor: &Descriptor<DerivationAccount>,
+        change_descriptor: &Descriptor<DerivationAccount>,
+        inputs: impl IntoIterator<Item = &'inputs InputDescriptor>,
+        foreign_inputs: impl IntoIterator<Item = &'foreign ExternalInput>,
+        outputs: impl IntoIterator<Item = &'outputs (PubkeyScript, u64)>,
+        change_index: impl Into<UnhardenedIndex>,
+        fee: u64,
+        tx_resolver: &impl ResolveTx,
+    ) -> Result<Psbt, Error> {
+        Psbt::construct_with_rotations(
+            descriptor,
+            change_descriptor,
+            &KeyRotations::default(),
+            inputs,
+            foreign_inputs,
+            outputs,
+            change_index,
+            fee,
+            tx_resolver,
+        )
+    }
+
+    /// Constructs PSBT for wallet descriptors which account keys were
+    /// superseded by new keys (see [`KeyRotations`]). Each wallet input is
+    /// spent with the descriptor version effective at the last index of the
+    /// input terminal, falling back to other descriptor versions deriving the
+    /// spent `scriptPubkey`; change output uses the change descriptor version
+    /// effective at the `change_index`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn construct_with_rotations<'inputs, 'foreign, 'outputs>(
+        descriptor: &Descriptor<DerivationAccount>,
+        change_descriptor: &Descriptor<DerivationAccount>,
+        rotations: &KeyRotations,
+        inputs: impl IntoIterator<Item = &'inputs InputDescriptor>,
+        foreign_inputs: impl IntoIterator<Item = &'foreign ExternalInput>,
+        outputs: impl IntoIterator<Item = &'outputs (PubkeyScript, u64)>,
+        change_index: impl Into<UnhardenedIndex>,
+        fee: u64,
+        tx_resolver: &impl ResolveTx,
+    ) -> Result<Psbt, Error> {
+        let versions = rotations
+            .descriptor_versions(descriptor)
+            .into_iter()
+            .chain(rotations.descriptor_versions(change_descriptor))
+            .map(|(_, version)| version)
+            .collect::<Vec<_>>();
+
         let mut xpub = bmap! {};
-        descriptor.for_each_key(|account| {
-            if let Some(key_source) = account.account_key_source() {
-                xpub.insert(account.account_xpub, key_source);
-            }
-            true
-        });
+        for descr in &versions {
+            descr.for_each_key(|account| {
+                if let Some(key_source) = account.account_key_source() {
+                    xpub.insert(account.account_xpub, key_source);
+                }
+                true
+            });
+        }
 
         let mut total_spent = 0u64;
         let mut psbt_inputs: Vec<psbt::Input> = vec![];
 
         for (index, input) in inputs.into_iter().enumerate() {
+            let last_index = input
+                .terminal
+                .as_ref()
+                .last()
+                .copied()
+                .unwrap_or_else(UnhardenedIndex::zero);
+            let mut candidates = vec![
+                rotations.effective_descriptor(descriptor, last_index),
+                rotations.effective_descriptor(change_descriptor, last_index),
+            ];
+            for version in &versions {
+                if !candidates.contains(version) {
+                    candidates.push(version.clone());
+                }
+            }
+            let (descriptor, change_descriptor) = (&candidates[0], &candidates[1]);
+
             let txid = input.outpoint.txid;
             let mut tx = tx_resolver.resolve_tx(txid)?;
 
@@ -115,6 +363,92 @@ impl Psbt {
                 .output
                 .get(input.outpoint.vout as usize)
                 .ok_or(Error::OutputUnknown(txid, input.outpoint.vout))?;
+
+            // Inputs satisfied by external systems are not controlled by the wallet
+            // descriptor: we just embed their satisfaction, which will be left intact
+            // by signer and finalizer
+            if let Some(external) = &input.external {
+                total_spent += prev_output.value;
+                let mut psbt_input = psbt::Input {
+                    index,
+                    previous_outpoint: input.outpoint,
+                    sequence_number: Some(input.seq_no),
+                    sighash_type: Some(input.sighash_type.into()),
+                    non_witness_utxo: Some(tx.clone()),
+                    ..default!()
+                };
+                if !external.script_sig.is_empty() {
+                    psbt_input.final_script_sig = Some(external.script_sig.clone().into());
+                }
+                if !external.witness.is_empty() {
+                    psbt_input.witness_utxo = Some(prev_output.clone());
+                    psbt_input.final_script_witness =
+                        Some(Witness::from_vec(external.witness.clone()));
+                }
+                psbt_inputs.push(psbt_input);
+                continue;
+            }
+
+            // Inputs with explicit redeem or witness scripts, as well as inputs spending
+            // bare scripts, are not covered by the wallet descriptor (like legacy P2SH
+            // multisigs). We add derivation information only for the wallet keys used by
+            // the script, leaving the rest of the signatures to the co-signers
+            let explicit_script = match (&input.witness_script, &input.redeem_script) {
+                (Some(script), _) | (None, Some(script)) => Some(script),
+                (None, None)
+                    if is_bare(&prev_output.script_pubkey)
+                        && !matches!(descriptor, Descriptor::Bare(_)) =>
+                {
+                    Some(&prev_output.script_pubkey)
+                }
+                (None, None) => None,
+            };
+            if let Some(script) = explicit_script {
+                let script_pubkey = input
+                    .explicit_script_pubkey()
+                    .unwrap_or_else(|| prev_output.script_pubkey.clone());
+                let nested_mismatch = match (&input.redeem_script, &input.witness_script) {
+                    (Some(redeem_script), Some(witness_script)) => {
+                        *redeem_script != Script::new_v0_p2wsh(&witness_script.wscript_hash())
+                    }
+                    _ => false,
+                };
+                if prev_output.script_pubkey != script_pubkey || nested_mismatch {
+                    return Err(Error::ExplicitScriptMismatch(input.outpoint));
+                }
+                let bip32_derivation = script_bip32_derivation(
+                    [descriptor, change_descriptor],
+                    &input.terminal,
+                    script,
+                );
+                if bip32_derivation.is_empty() {
+                    return Err(Error::NoScriptKeys(input.outpoint));
+                }
+
+                total_spent += prev_output.value;
+                let mut psbt_input = psbt::Input {
+                    index,
+                    previous_outpoint: input.outpoint,
+                    sequence_number: Some(input.seq_no),
+                    bip32_derivation,
+                    sighash_type: Some(input.sighash_type.into()),
+                    non_witness_utxo: Some(tx.clone()),
+                    redeem_script: input.redeem_script.clone().map(Into::into),
+                    witness_script: input.witness_script.clone().map(Into::into),
+                    ..default!()
+                };
+                if input.witness_script.is_some() {
+                    psbt_input.witness_utxo = Some(prev_output.clone());
+                }
+                psbt_inputs.push(psbt_input);
+                continue;
+            }
+
+            let descriptor = spending_descriptor(
+                &candidates,
+                input.terminal.as_ref(),
+                &prev_output.script_pubkey,
+            );
             let (script_pubkey, dtype, tr_descriptor, pretr_descriptor) = match descriptor {
                 Descriptor::Tr(_) => {
                     let output_descriptor = DeriveDescriptor::<XOnlyPublicKey>::derive_descriptor(
@@ -260,6 +594,38 @@ impl Psbt {
             psbt_inputs.push(psbt_input);
         }
 
+        for input in foreign_inputs {
+            let non_witness_utxo = match tx_resolver.resolve_tx(input.outpoint.txid) {
+                Ok(mut tx) => {
+                    if tx.output.get(input.outpoint.vout as usize) != Some(&input.utxo) {
+                        return Err(Error::ForeignUtxoMismatch(input.outpoint));
+                    }
+                    for inp in &mut tx.input {
+                        inp.witness = zero!();
+                    }
+                    Some(tx)
+                }
+                Err(_) => None,
+            };
+
+            total_spent += input.utxo.value;
+
+            let mut psbt_input = psbt::Input {
+                index: psbt_inputs.len(),
+                previous_outpoint: input.outpoint,
+                sequence_number: Some(input.seq_no),
+                sighash_type: Some(input.sighash_type.into()),
+                non_witness_utxo,
+                redeem_script: input.redeem_script.clone().map(Into::into),
+                witness_script: input.witness_script.clone().map(Into::into),
+                ..default!()
+            };
+            if input.is_segwit() {
+                psbt_input.witness_utxo = Some(input.utxo.clone());
+            }
+            psbt_inputs.push(psbt_input);
+        }
+
         let mut total_sent = 0u64;
         let mut psbt_outputs: Vec<_> = outputs
             .into_iter()
@@ -286,7 +652,10 @@ impl Psbt {
         };
 
         if change > 0 {
-            let change_derivation = [UnhardenedIndex::one(), change_index.into()];
+            let change_index = change_index.into();
+            let change_descriptor =
+                &rotations.effective_descriptor(change_descriptor, change_index);
+            let change_derivation = [UnhardenedIndex::one(), change_index];
             let mut bip32_derivation = bmap! {};
             let bip32_derivation_fn = |account: &DerivationAccount| {
                 let (pubkey, key_source) = account
@@ -301,24 +670,23 @@ impl Psbt {
                 amount: change,
                 ..default!()
             };
-            if let Descriptor::Tr(_) = descriptor {
-                let change_descriptor = DeriveDescriptor::<XOnlyPublicKey>::derive_descriptor(
-                    descriptor,
+            if let Descriptor::Tr(_) = change_descriptor {
+                let derived = DeriveDescriptor::<XOnlyPublicKey>::derive_descriptor(
+                    change_descriptor,
                     SECP256K1,
                     change_derivation,
                 )?;
-                let change_descriptor = match change_descriptor {
+                let derived = match derived {
                     Descriptor::Tr(tr) => tr,
                     _ => unreachable!(),
                 };
 
-                psbt_change_output.script = change_descriptor.script_pubkey().into();
-                descriptor.for_each_key(bip32_derivation_fn);
+                psbt_change_output.script = derived.script_pubkey().into();
+                change_descriptor.for_each_key(bip32_derivation_fn);
 
-                let internal_key: XOnlyPublicKey =
-                    change_descriptor.internal_key().to_x_only_pubkey();
+                let internal_key: XOnlyPublicKey = derived.internal_key().to_x_only_pubkey();
                 psbt_change_output.tap_internal_key = Some(internal_key);
-                if let Some(tree) = change_descriptor.taptree() {
+                if let Some(tree) = derived.taptree() {
                     let mut builder = TaprootBuilder::new();
                     for (depth, ms) in tree.iter() {
                         builder = builder
@@ -329,17 +697,17 @@ impl Psbt {
                         Some(TapTree::try_from(builder).expect("non-finalized TaprootBuilder"));
                 }
             } else {
-                let change_descriptor = DeriveDescriptor::<bitcoin::PublicKey>::derive_descriptor(
-                    descriptor,
+                let derived = DeriveDescriptor::<bitcoin::PublicKey>::derive_descriptor(
+                    change_descriptor,
                     SECP256K1,
                     change_derivation,
                 )?;
-                psbt_change_output.script = change_descriptor.script_pubkey().into();
+                psbt_change_output.script = derived.script_pubkey().into();
 
-                let dtype = descriptors::CompositeDescrType::from(&change_descriptor);
-                descriptor.for_each_key(bip32_derivation_fn);
+                let dtype = descriptors::CompositeDescrType::from(&derived);
+                change_descriptor.for_each_key(bip32_derivation_fn);
 
-                let lock_script = change_descriptor.explicit_script()?;
+                let lock_script = derived.explicit_script()?;
                 if dtype.has_redeem_script() {
                     psbt_change_output.redeem_script = Some(lock_script.clone().into());
                 }
@@ -349,6 +717,7 @@ impl Psbt {
             }
 
             psbt_change_output.bip32_derivation = bip32_derivation;
+            psbt_change_output.set_change_flag();
             psbt_outputs.push(psbt_change_output);
         }
 
@@ -0,0 +1,313 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Organizational spend policies restricting destinations and amounts of the
+//! constructed transactions.
+//!
+//! Outputs flagged as change (see [`crate::ownership`]) which derive from the
+//! wallet descriptors are not subject to the destination and amount limits;
+//! all other outputs, including outputs falsely claimed to be change, are
+//! treated as payments.
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use amplify::Wrapper;
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::Script;
+use bitcoin_hd::DerivationAccount;
+use miniscript::Descriptor;
+
+use crate::Psbt;
+
+/// Types of the output scripts which may be allowed by [`SpendPolicy`]
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+pub enum OutputScriptType {
+    /// Pay-to-public-key-hash
+    #[display("pkh")]
+    Pkh,
+
+    /// Pay-to-script-hash, including nested segwit
+    #[display("sh")]
+    Sh,
+
+    /// Pay-to-witness-public-key-hash
+    #[display("wpkh")]
+    Wpkh,
+
+    /// Pay-to-witness-script-hash
+    #[display("wsh")]
+    Wsh,
+
+    /// Pay-to-taproot
+    #[display("tr")]
+    Tr,
+
+    /// Provably unspendable `OP_RETURN` output
+    #[display("op_return")]
+    OpReturn,
+
+    /// Any other script, including future witness versions
+    #[display("bare")]
+    Bare,
+}
+
+impl OutputScriptType {
+    /// Detects type of the output script
+    pub fn from_script(script: &Script) -> Self {
+        if script.is_p2pkh() {
+            OutputScriptType::Pkh
+        } else if script.is_p2sh() {
+            OutputScriptType::Sh
+        } else if script.is_v0_p2wpkh() {
+            OutputScriptType::Wpkh
+        } else if script.is_v0_p2wsh() {
+            OutputScriptType::Wsh
+        } else if script.is_v1_p2tr() {
+            OutputScriptType::Tr
+        } else if script.is_op_return() {
+            OutputScriptType::OpReturn
+        } else {
+            OutputScriptType::Bare
+        }
+    }
+}
+
+/// Error parsing [`OutputScriptType`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(
+    "unknown output script type `{0}`; allowed types are pkh, sh, wpkh, wsh, tr, op_return and \
+     bare"
+)]
+pub struct OutputScriptTypeParseError(String);
+
+impl FromStr for OutputScriptType {
+    type Err = OutputScriptTypeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().trim() {
+            "pkh" | "p2pkh" => OutputScriptType::Pkh,
+            "sh" | "p2sh" => OutputScriptType::Sh,
+            "wpkh" | "p2wpkh" => OutputScriptType::Wpkh,
+            "wsh" | "p2wsh" => OutputScriptType::Wsh,
+            "tr" | "p2tr" => OutputScriptType::Tr,
+            "op_return" | "opreturn" => OutputScriptType::OpReturn,
+            "bare" => OutputScriptType::Bare,
+            _ => return Err(OutputScriptTypeParseError(s.to_owned())),
+        })
+    }
+}
+
+/// Violations of the [`SpendPolicy`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SpendPolicyError {
+    /// output #{0} pays to {1} script, which is not allowed by the spend
+    /// policy
+    ScriptTypeNotAllowed(usize, OutputScriptType),
+
+    /// output #{0} pays to `{1}`, which is not in the list of allowed
+    /// destinations
+    DestinationNotAllowed(usize, Script),
+
+    /// output #{0} sends {1} sats, exceeding per-output limit of {2} sats
+    OutputAmountExceeded(usize, u64, u64),
+
+    /// transaction sends {0} sats to non-change outputs, exceeding
+    /// per-transaction limit of {1} sats
+    TxAmountExceeded(u64, u64),
+
+    /// output #{0} is claimed to be a change, but it does not derive from any
+    /// of the wallet descriptors
+    ForeignChange(usize),
+}
+
+/// Organizational policy restricting outputs of the constructed transactions.
+/// Default policy allows everything.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct SpendPolicy {
+    /// Script types allowed for the payment outputs. If empty, all script
+    /// types are allowed.
+    pub allowed_script_types: BTreeSet<OutputScriptType>,
+
+    /// Destination scripts (addresses) allowed for the payment outputs. If
+    /// empty, all destinations are allowed.
+    pub allowed_destinations: BTreeSet<Script>,
+
+    /// Maximal amount of a single payment output, in satoshis
+    pub max_output_amount: Option<u64>,
+
+    /// Maximal sum of all payment outputs of the transaction, in satoshis
+    pub max_tx_amount: Option<u64>,
+
+    /// Requires all outputs flagged as change to derive from the wallet
+    /// descriptors. If not set, such outputs are treated as payments.
+    pub require_wallet_change: bool,
+}
+
+impl Psbt {
+    /// Checks PSBT outputs against the spend policy. Change outputs are
+    /// verified to derive from one of the wallet `descriptors`.
+    ///
+    /// # Returns
+    ///
+    /// List of all policy violations; empty list means that the PSBT
+    /// complies with the policy.
+    pub fn spend_policy_violations(
+        &self,
+        policy: &SpendPolicy,
+        descriptors: &[Descriptor<DerivationAccount>],
+    ) -> Vec<SpendPolicyError> {
+        let mut violations = vec![];
+        let mut total = 0u64;
+        for (index, output) in self.outputs.iter().enumerate() {
+            if output.has_change_flag() {
+                if descriptors
+                    .iter()
+                    .any(|descriptor| output.derives_from(SECP256K1, descriptor))
+                {
+                    continue;
+                }
+                if policy.require_wallet_change {
+                    violations.push(SpendPolicyError::ForeignChange(index));
+                }
+            }
+
+            let script = output.script.as_inner();
+            let script_type = OutputScriptType::from_script(script);
+            if !policy.allowed_script_types.is_empty()
+                && !policy.allowed_script_types.contains(&script_type)
+            {
+                violations.push(SpendPolicyError::ScriptTypeNotAllowed(index, script_type));
+            }
+            if !policy.allowed_destinations.is_empty()
+                && !policy.allowed_destinations.contains(script)
+            {
+                violations.push(SpendPolicyError::DestinationNotAllowed(
+                    index,
+                    script.clone(),
+                ));
+            }
+            match policy.max_output_amount {
+                Some(max) if output.amount > max => violations.push(
+                    SpendPolicyError::OutputAmountExceeded(index, output.amount, max),
+                ),
+                _ => {}
+            }
+            total = total.saturating_add(output.amount);
+        }
+        match policy.max_tx_amount {
+            Some(max) if total > max => {
+                violations.push(SpendPolicyError::TxAmountExceeded(total, max))
+            }
+            _ => {}
+        }
+        violations
+    }
+
+    /// Checks PSBT outputs against the spend policy, returning the first of
+    /// the policy violations as an error (see
+    /// [`Psbt::spend_policy_violations`]).
+    pub fn check_spend_policy(
+        &self,
+        policy: &SpendPolicy,
+        descriptors: &[Descriptor<DerivationAccount>],
+    ) -> Result<(), SpendPolicyError> {
+        match self
+            .spend_policy_violations(policy, descriptors)
+            .into_iter()
+            .next()
+        {
+            Some(violation) => Err(violation),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, PackedLockTime, Transaction, TxOut, WPubkeyHash};
+    use bitcoin_hd::{DerivationSubpath, SegmentIndexes, UnhardenedIndex};
+    use bitcoin_scripts::PubkeyScript;
+    use descriptors::derive::Descriptor as _;
+    use descriptors::InputDescriptor;
+
+    use super::*;
+
+    #[test]
+    fn spend_policy() {
+        let descriptor = Descriptor::<DerivationAccount>::from_str(
+            "wpkh([d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqF\
+             JPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/*/*)",
+        )
+        .unwrap();
+        let terminal = DerivationSubpath::from(&[UnhardenedIndex::zero(); 2][..]);
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: descriptor
+                    .script_pubkey_pretr(SECP256K1, &terminal)
+                    .unwrap(),
+            }],
+        };
+        let txid = funding.txid();
+        let resolver = bmap! { txid => funding };
+        let inputs = [InputDescriptor::with(OutPoint::new(txid, 0), terminal)];
+        let payee = Script::new_v0_p2wpkh(&WPubkeyHash::hash(b"payee"));
+        let outputs = [(PubkeyScript::from(payee.clone()), 50_000)];
+        let mut psbt =
+            Psbt::construct(&descriptor, &inputs, &outputs, 5u8, 1_000, &resolver).unwrap();
+        let descriptors = [descriptor];
+
+        let mut policy = SpendPolicy {
+            allowed_script_types: bset! { OutputScriptType::Wpkh },
+            allowed_destinations: bset! { payee.clone() },
+            max_output_amount: Some(50_000),
+            max_tx_amount: Some(50_000),
+            require_wallet_change: true,
+        };
+        assert_eq!(psbt.check_spend_policy(&policy, &descriptors), Ok(()));
+
+        policy.allowed_script_types = bset! { OutputScriptType::Tr };
+        policy.max_output_amount = Some(10_000);
+        policy.max_tx_amount = Some(40_000);
+        assert_eq!(psbt.spend_policy_violations(&policy, &descriptors), vec![
+            SpendPolicyError::ScriptTypeNotAllowed(0, OutputScriptType::Wpkh),
+            SpendPolicyError::OutputAmountExceeded(0, 50_000, 10_000),
+            SpendPolicyError::TxAmountExceeded(50_000, 40_000),
+        ]);
+
+        // Change output redirected to a foreign script
+        let thief = Script::new_v0_p2wpkh(&WPubkeyHash::hash(b"thief"));
+        psbt.outputs[1].script = thief.clone().into();
+        assert_eq!(
+            psbt.check_spend_policy(&SpendPolicy::default(), &descriptors),
+            Ok(())
+        );
+        policy = SpendPolicy {
+            allowed_destinations: bset! { payee },
+            require_wallet_change: true,
+            ..default!()
+        };
+        assert_eq!(psbt.spend_policy_violations(&policy, &descriptors), vec![
+            SpendPolicyError::ForeignChange(1),
+            SpendPolicyError::DestinationNotAllowed(1, thief),
+        ]);
+        assert_eq!(OutputScriptType::from_str("P2TR"), Ok(OutputScriptType::Tr));
+    }
+}
@@ -0,0 +1,221 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Chain-split awareness for constructed transactions.
+//!
+//! Transactions which do not spend outputs existing on a single side of a
+//! chain split can be replayed on all forks of the chain. Without spending
+//! such outputs consensus rules provide no way to bind a transaction to a
+//! specific fork, so the replay guard defined here is a policy-level one: the
+//! transaction `nLockTime` is set to the height of a recent block from the
+//! chain of interest, and the hash of this block is stored in the PSBT, such
+//! that the broadcasting party can verify that it broadcasts to the chain
+//! containing the guard block.
+
+use bitcoin::hashes::Hash;
+use bitcoin::BlockHash;
+use bitcoin_blockchain::locks::{LockTime, SeqNo};
+
+use crate::raw::ProprietaryKey;
+use crate::Psbt;
+
+/// Proprietary key prefix for replay guard data in PSBT.
+pub const PSBT_REPLAY_PREFIX: &[u8] = b"REPLAY";
+/// Proprietary global key type for the replay guard block: the value is the
+/// block height encoded as little-endian `u32` followed by the block hash.
+pub const PSBT_GLOBAL_REPLAY_GUARD: u8 = 0;
+
+/// Block from the chain of interest used to guard transaction against replay
+/// on the other forks of the chain.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[display("{block_hash}@{height}")]
+pub struct ReplayGuard {
+    /// Height of the guard block.
+    pub height: u32,
+
+    /// Hash of the guard block.
+    pub block_hash: BlockHash,
+}
+
+/// Replay protection policy used during PSBT construction.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct ReplayPolicy {
+    /// Recent block from the chain of interest to guard transaction with. If
+    /// absent, no guard is applied.
+    pub guard: Option<ReplayGuard>,
+
+    /// Heights of the first blocks of the known chain splits. The transaction
+    /// is considered to be valid on both sides of the split unless it is
+    /// guarded with a block mined after the split.
+    pub fork_heights: Vec<u32>,
+}
+
+/// Warnings about transaction replay possibility on multiple chain forks.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[display(doc_comments)]
+pub enum ReplayWarning {
+    /// transaction has no replay guard and may be valid on all forks of the
+    /// chain.
+    Unguarded,
+
+    /// transaction guard block at height {guard_height} precedes the chain
+    /// split at height {fork_height} and exists on both sides of it.
+    GuardBeforeFork { guard_height: u32, fork_height: u32 },
+
+    /// transaction lock time does not match the guard block height
+    /// {guard_height}, thus the transaction may be mined before the guard
+    /// block.
+    LockTimeMismatch { guard_height: u32 },
+}
+
+impl Psbt {
+    /// Applies replay protection policy to the PSBT, returning warnings about
+    /// the remaining possibility of transaction replay on other forks.
+    ///
+    /// If the policy defines guard block, the transaction fallback lock time
+    /// is set to the guard block height (unless it is already height-based
+    /// and larger), inputs with final sequence numbers are changed to enable
+    /// the lock time, and the guard block is stored in the PSBT global
+    /// proprietary key.
+    pub fn apply_replay_policy(&mut self, policy: &ReplayPolicy) -> Vec<ReplayWarning> {
+        if let Some(guard) = policy.guard {
+            let guard_lock = LockTime::from(guard.height);
+            match self.fallback_locktime {
+                Some(lock)
+                    if lock.is_height_based()
+                        && lock.into_consensus() >= guard_lock.into_consensus() => {}
+                _ => self.fallback_locktime = Some(guard_lock),
+            }
+            for input in &mut self.inputs {
+                if input.sequence_number.unwrap_or_default().into_consensus() == u32::MAX {
+                    input.sequence_number = Some(SeqNo::from_consensus(u32::MAX - 1));
+                }
+            }
+            self.set_replay_guard(guard);
+        }
+        self.replay_warnings(&policy.fork_heights)
+    }
+
+    /// Checks the PSBT for the possibility of transaction replay on the forks
+    /// created by chain splits at the given heights.
+    pub fn replay_warnings(&self, fork_heights: &[u32]) -> Vec<ReplayWarning> {
+        let guard = match self.replay_guard() {
+            Some(guard) => guard,
+            None => return vec![ReplayWarning::Unguarded],
+        };
+        let mut warnings = vec![];
+        let lock_time = self.lock_time();
+        if !lock_time.is_height_based() || lock_time.into_consensus() < guard.height {
+            warnings.push(ReplayWarning::LockTimeMismatch {
+                guard_height: guard.height,
+            });
+        }
+        warnings.extend(
+            fork_heights
+                .iter()
+                .filter(|fork_height| guard.height < **fork_height)
+                .map(|fork_height| ReplayWarning::GuardBeforeFork {
+                    guard_height: guard.height,
+                    fork_height: *fork_height,
+                }),
+        );
+        warnings
+    }
+
+    /// Stores replay guard block in the PSBT global proprietary key.
+    pub fn set_replay_guard(&mut self, guard: ReplayGuard) {
+        let mut value = guard.height.to_le_bytes().to_vec();
+        value.extend(&guard.block_hash[..]);
+        self.proprietary.insert(
+            ProprietaryKey {
+                prefix: PSBT_REPLAY_PREFIX.to_vec(),
+                subtype: PSBT_GLOBAL_REPLAY_GUARD,
+                key: vec![],
+            },
+            value,
+        );
+    }
+
+    /// Returns replay guard block stored in the PSBT, if any.
+    pub fn replay_guard(&self) -> Option<ReplayGuard> {
+        let value = self.proprietary.get(&ProprietaryKey {
+            prefix: PSBT_REPLAY_PREFIX.to_vec(),
+            subtype: PSBT_GLOBAL_REPLAY_GUARD,
+            key: vec![],
+        })?;
+        if value.len() != 4 + 32 {
+            return None;
+        }
+        let mut height = [0u8; 4];
+        height.copy_from_slice(&value[..4]);
+        Some(ReplayGuard {
+            height: u32::from_le_bytes(height),
+            block_hash: BlockHash::from_slice(&value[4..]).ok()?,
+        })
+    }
+
+    /// Verifies that the replay guard block belongs to the chain to which the
+    /// transaction is going to be broadcasted. The chain is represented by a
+    /// function returning hash of its block at a given height.
+    ///
+    /// Returns `None` if the PSBT has no replay guard.
+    pub fn verify_replay_guard(
+        &self,
+        block_hash_at: impl FnOnce(u32) -> Option<BlockHash>,
+    ) -> Option<bool> {
+        let guard = self.replay_guard()?;
+        Some(block_hash_at(guard.height) == Some(guard.block_hash))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replay_guard() {
+        let guard = ReplayGuard {
+            height: 800_000,
+            block_hash: BlockHash::from_inner([0x11; 32]),
+        };
+        let mut psbt = Psbt::default();
+        assert_eq!(psbt.replay_warnings(&[]), vec![ReplayWarning::Unguarded]);
+        assert_eq!(psbt.verify_replay_guard(|_| None), None);
+
+        let policy = ReplayPolicy {
+            guard: Some(guard),
+            fork_heights: vec![700_000, 900_000],
+        };
+        assert_eq!(psbt.apply_replay_policy(&policy), vec![
+            ReplayWarning::GuardBeforeFork {
+                guard_height: 800_000,
+                fork_height: 900_000
+            }
+        ]);
+        assert_eq!(psbt.replay_guard(), Some(guard));
+        assert_eq!(psbt.lock_time().into_consensus(), 800_000);
+        assert_eq!(
+            psbt.verify_replay_guard(|height| (height == 800_000).then_some(guard.block_hash)),
+            Some(true)
+        );
+        assert_eq!(psbt.verify_replay_guard(|_| None), Some(false));
+
+        psbt.fallback_locktime = None;
+        assert_eq!(psbt.replay_warnings(&[]), vec![
+            ReplayWarning::LockTimeMismatch {
+                guard_height: 800_000
+            }
+        ]);
+    }
+}
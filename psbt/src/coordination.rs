@@ -0,0 +1,406 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Coordination of m-of-n multisig signing sessions.
+//!
+//! A coordinator distributes PSBT to the co-signers, collects partially
+//! signed PSBTs from them and merges the signatures with [`Psbt::combine`].
+//! [`SigningSession`] tracks which co-signers (identified by the master key
+//! fingerprints from the PSBT key origins) have already signed, which are
+//! still missing, and refuses to merge signatures using inconsistent sighash
+//! types.
+
+use std::collections::BTreeSet;
+
+use bitcoin::util::bip32::Fingerprint;
+use bitcoin::Txid;
+
+use crate::{Error, Input, Psbt, PsbtSighashType};
+
+/// Errors of multisig signing session coordination
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CoordinationError {
+    /// signing threshold {threshold} is invalid for {cosigners} co-signers
+    InvalidThreshold {
+        /// Required number of signatures
+        threshold: usize,
+        /// Number of co-signers found in the PSBT key origins
+        cosigners: usize,
+    },
+
+    /// the signed PSBT contains transaction {actual}, while the session
+    /// signs transaction {expected}
+    TxidMismatch {
+        /// Transaction id of the session PSBT
+        expected: Txid,
+        /// Transaction id of the signed PSBT
+        actual: Txid,
+    },
+
+    /// input #{input} is signed with sighash type {used}, which conflicts with
+    /// sighash type {expected} used by the other signatures or required by the
+    /// input
+    SighashConflict {
+        /// Index of the input
+        input: usize,
+        /// Sighash type expected for the input signatures
+        expected: PsbtSighashType,
+        /// Conflicting sighash type
+        used: PsbtSighashType,
+    },
+
+    /// unable to combine PSBTs: {0}
+    #[from]
+    Combine(Error),
+}
+
+/// Signing status of a single PSBT input
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct InputStatus {
+    /// Index of the input
+    pub index: usize,
+
+    /// Co-signers which have signed the input
+    pub signed: BTreeSet<Fingerprint>,
+
+    /// Co-signers having keys in the input which have not signed it yet
+    pub missing: BTreeSet<Fingerprint>,
+
+    /// Whether the input is already finalized
+    pub finalized: bool,
+
+    /// Whether the input has enough signatures to be finalized
+    pub complete: bool,
+}
+
+/// Machine-readable status of a signing session
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct SessionStatus {
+    /// Transaction being signed
+    pub txid: Txid,
+
+    /// Number of required signatures
+    pub threshold: usize,
+
+    /// All co-signers of the session
+    pub cosigners: BTreeSet<Fingerprint>,
+
+    /// Co-signers which have signed all inputs they have keys for
+    pub signed: BTreeSet<Fingerprint>,
+
+    /// Co-signers which have not signed some of the inputs they have keys for
+    pub missing: BTreeSet<Fingerprint>,
+
+    /// Per-input signing status
+    pub inputs: Vec<InputStatus>,
+
+    /// Whether all inputs have enough signatures to be finalized
+    pub complete: bool,
+}
+
+/// Signing session of a PSBT spending m-of-n multisig outputs
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SigningSession {
+    psbt: Psbt,
+    threshold: usize,
+    cosigners: BTreeSet<Fingerprint>,
+}
+
+/// Returns master key fingerprints of all keys having origin information in
+/// the input.
+fn input_cosigners(input: &Input) -> BTreeSet<Fingerprint> {
+    input
+        .bip32_derivation
+        .values()
+        .map(|(fingerprint, _)| *fingerprint)
+        .chain(
+            input
+                .tap_key_origins
+                .values()
+                .map(|(_, (fingerprint, _))| *fingerprint),
+        )
+        .collect()
+}
+
+/// Returns master key fingerprints of the keys which have signed the input.
+/// Signatures made with keys without origin information are ignored.
+fn input_signers(input: &Input) -> BTreeSet<Fingerprint> {
+    let ecdsa = input
+        .partial_sigs
+        .keys()
+        .filter_map(|pubkey| input.bip32_derivation.get(&pubkey.inner));
+    let tap_key = input
+        .tap_key_sig
+        .and(input.tap_internal_key)
+        .and_then(|internal_key| input.tap_key_origins.get(&internal_key))
+        .map(|(_, key_source)| key_source);
+    let tap_script = input
+        .tap_script_sigs
+        .keys()
+        .filter_map(|(pubkey, _)| input.tap_key_origins.get(pubkey))
+        .map(|(_, key_source)| key_source);
+    ecdsa
+        .chain(tap_key)
+        .chain(tap_script)
+        .map(|(fingerprint, _)| *fingerprint)
+        .collect()
+}
+
+/// Returns sighash types of all signatures present in the input.
+fn input_sighash_types(input: &Input) -> impl Iterator<Item = PsbtSighashType> + '_ {
+    input
+        .partial_sigs
+        .values()
+        .map(|sig| PsbtSighashType::from(sig.hash_ty))
+        .chain(
+            input
+                .tap_key_sig
+                .map(|sig| PsbtSighashType::from(sig.hash_ty)),
+        )
+        .chain(
+            input
+                .tap_script_sigs
+                .values()
+                .map(|sig| PsbtSighashType::from(sig.hash_ty)),
+        )
+}
+
+impl SigningSession {
+    /// Starts signing session for a PSBT requiring `threshold` signatures per
+    /// input. Co-signers are detected from the key origin information of the
+    /// PSBT inputs.
+    pub fn new(psbt: Psbt, threshold: usize) -> Result<Self, CoordinationError> {
+        let cosigners = psbt
+            .inputs
+            .iter()
+            .flat_map(input_cosigners)
+            .collect::<BTreeSet<_>>();
+        if threshold == 0 || threshold > cosigners.len() {
+            return Err(CoordinationError::InvalidThreshold {
+                threshold,
+                cosigners: cosigners.len(),
+            });
+        }
+        let session = SigningSession {
+            psbt,
+            threshold,
+            cosigners,
+        };
+        session.check_sighashes(&session.psbt)?;
+        Ok(session)
+    }
+
+    /// Returns PSBT with all signatures collected so far.
+    #[inline]
+    pub fn psbt(&self) -> &Psbt { &self.psbt }
+
+    /// Returns PSBT with all signatures collected so far, ending the session.
+    #[inline]
+    pub fn into_psbt(self) -> Psbt { self.psbt }
+
+    /// Returns number of signatures required for each of the inputs.
+    #[inline]
+    pub fn threshold(&self) -> usize { self.threshold }
+
+    /// Returns master key fingerprints of all session co-signers.
+    #[inline]
+    pub fn cosigners(&self) -> &BTreeSet<Fingerprint> { &self.cosigners }
+
+    /// Checks that signatures of each input in `psbt`, together with the
+    /// signatures already collected by the session, use the same sighash
+    /// type, which must match the sighash type required by the input (if
+    /// any).
+    fn check_sighashes(&self, psbt: &Psbt) -> Result<(), CoordinationError> {
+        for (index, (own, other)) in self.psbt.inputs.iter().zip(&psbt.inputs).enumerate() {
+            let mut expected = own.sighash_type.or(other.sighash_type);
+            for used in input_sighash_types(own).chain(input_sighash_types(other)) {
+                match expected {
+                    None => expected = Some(used),
+                    Some(expected) if expected == used => {}
+                    Some(expected) => {
+                        return Err(CoordinationError::SighashConflict {
+                            input: index,
+                            expected,
+                            used,
+                        })
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges partially signed PSBT received from a co-signer into the
+    /// session. Returns co-signers whose signatures were added by the merge.
+    ///
+    /// If the PSBT spends a different transaction or contains signatures
+    /// with conflicting sighash types, the session is left unchanged.
+    pub fn merge(&mut self, signed: Psbt) -> Result<BTreeSet<Fingerprint>, CoordinationError> {
+        let expected = self.psbt.to_txid();
+        let actual = signed.to_txid();
+        if expected != actual {
+            return Err(CoordinationError::TxidMismatch { expected, actual });
+        }
+        self.check_sighashes(&signed)?;
+
+        let before = self.status();
+        self.psbt = self.psbt.clone().combine(signed)?;
+        let after = self.status();
+
+        Ok(after
+            .inputs
+            .iter()
+            .zip(&before.inputs)
+            .flat_map(|(after, before)| after.signed.difference(&before.signed).copied())
+            .collect())
+    }
+
+    /// Reports signing status of the session.
+    pub fn status(&self) -> SessionStatus {
+        let inputs = self
+            .psbt
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(index, input)| {
+                let signed = input_signers(input);
+                let missing = input_cosigners(input)
+                    .difference(&signed)
+                    .copied()
+                    .collect::<BTreeSet<_>>();
+                let finalized =
+                    input.final_script_sig.is_some() || input.final_script_witness.is_some();
+                InputStatus {
+                    index,
+                    complete: finalized || signed.len() >= self.threshold,
+                    signed,
+                    missing,
+                    finalized,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let missing = inputs
+            .iter()
+            .filter(|input| !input.finalized)
+            .flat_map(|input| input.missing.iter().copied())
+            .collect::<BTreeSet<_>>();
+        let signed = inputs
+            .iter()
+            .flat_map(|input| input.signed.iter().copied())
+            .filter(|fingerprint| !missing.contains(fingerprint))
+            .collect();
+
+        SessionStatus {
+            txid: self.psbt.to_txid(),
+            threshold: self.threshold,
+            cosigners: self.cosigners.clone(),
+            signed,
+            missing,
+            complete: inputs.iter().all(|input| input.complete),
+            inputs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::util::bip32::DerivationPath;
+    use bitcoin::{EcdsaSig, EcdsaSighashType, OutPoint, PublicKey};
+
+    use super::*;
+
+    #[test]
+    fn session() {
+        let secp = Secp256k1::new();
+        let keys = (1..=3u8)
+            .map(|tag| {
+                let seckey = SecretKey::from_slice(&[tag; 32]).unwrap();
+                let fingerprint = Fingerprint::from(&[tag; 4][..]);
+                (
+                    seckey,
+                    PublicKey::new(seckey.public_key(&secp)),
+                    fingerprint,
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut input = Input {
+            previous_outpoint: OutPoint::from_str(
+                "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8",
+            )
+            .unwrap(),
+            ..Input::default()
+        };
+        for (_, pubkey, fingerprint) in &keys {
+            input.bip32_derivation.insert(
+                pubkey.inner,
+                (*fingerprint, DerivationPath::from_str("m/0").unwrap()),
+            );
+        }
+        let psbt = Psbt {
+            inputs: vec![input],
+            ..Psbt::default()
+        };
+        let sign = |index: usize, hash_ty| {
+            let (seckey, pubkey, _) = keys[index];
+            let sig = secp.sign_ecdsa(&Message::from_slice(&[0x33; 32]).unwrap(), &seckey);
+            let mut signed = psbt.clone();
+            signed.inputs[0]
+                .partial_sigs
+                .insert(pubkey, EcdsaSig { sig, hash_ty });
+            signed
+        };
+
+        assert_eq!(
+            SigningSession::new(psbt.clone(), 4),
+            Err(CoordinationError::InvalidThreshold {
+                threshold: 4,
+                cosigners: 3
+            })
+        );
+        let mut session = SigningSession::new(psbt.clone(), 2).unwrap();
+        let status = session.status();
+        assert!(!status.complete);
+        assert_eq!(status.missing.len(), 3);
+
+        let added = session.merge(sign(0, EcdsaSighashType::All)).unwrap();
+        assert_eq!(added, bset! { keys[0].2 });
+        assert!(matches!(
+            session.merge(sign(1, EcdsaSighashType::Single)),
+            Err(CoordinationError::SighashConflict { input: 0, .. })
+        ));
+        assert!(!session.status().complete);
+
+        session.merge(sign(2, EcdsaSighashType::All)).unwrap();
+        let status = session.status();
+        assert!(status.complete);
+        assert_eq!(status.signed, bset! { keys[0].2, keys[2].2 });
+        assert_eq!(status.missing, bset! { keys[1].2 });
+        assert_eq!(session.psbt().inputs[0].partial_sigs.len(), 2);
+    }
+}
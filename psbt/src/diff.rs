@@ -0,0 +1,563 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Field-level differences between two versions of the same PSBT, allowing
+//! multisig coordinators to keep an audit trail of the changes made by each
+//! of the participants and to replay these changes on other PSBT copies.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin::{EcdsaSig, SchnorrSig, TxOut, Txid};
+#[cfg(feature = "serde")]
+use serde_with::{hex::Hex, As};
+
+use crate::{Input, Output, ProprietaryKey, ProprietaryKeyLocation, Psbt, SigSource};
+
+/// Errors applying [`PsbtDiff`] to a PSBT
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DiffError {
+    /// the diff was made against transaction {expected}, while the PSBT
+    /// contains transaction {actual}
+    TxidMismatch {
+        /// Transaction id of the PSBT the diff was made against
+        expected: Txid,
+        /// Transaction id of the PSBT the diff is applied to
+        actual: Txid,
+    },
+
+    /// PSBT has no input {0}
+    NoInput(usize),
+
+    /// PSBT has no output {0}
+    NoOutput(usize),
+
+    /// output {0} can't be added or removed since outputs may be added or
+    /// removed only at the end of the output list
+    OutputOrder(usize),
+
+    /// {source} signature of input {input} differs from the one the diff was
+    /// made against
+    SigConflict {
+        /// Index of the input
+        input: usize,
+        /// Signature location within the input
+        source: SigSource,
+    },
+
+    /// proprietary key {key:?} in {location} differs from the one the diff was
+    /// made against
+    ProprietaryConflict {
+        /// Location of the proprietary key
+        location: ProprietaryKeyLocation,
+        /// The proprietary key
+        key: ProprietaryKey,
+    },
+
+    /// output {0} differs from the one the diff was made against
+    OutputConflict(usize),
+
+    /// after applying the diff the PSBT contains transaction {actual} instead
+    /// of {expected}; the PSBTs differ in fields not covered by the diff
+    /// (transaction inputs, version or lock time)
+    Incomplete {
+        /// Transaction id the diff should have produced
+        expected: Txid,
+        /// Transaction id produced by applying the diff
+        actual: Txid,
+    },
+}
+
+/// Signature stored in a PSBT input
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum Signature {
+    /// ECDSA signature from `partial_sigs`
+    Ecdsa(EcdsaSig),
+
+    /// BIP-340 signature from `tap_key_sig` or `tap_script_sigs`
+    Schnorr(SchnorrSig),
+}
+
+/// Change in a signature of a PSBT input.
+///
+/// Absent `from` value means that the signature was added; absent `to` value
+/// means that the signature was removed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct SigChange {
+    /// Index of the input
+    pub input: usize,
+
+    /// Signature location within the input
+    pub source: SigSource,
+
+    /// Signature in the original PSBT
+    pub from: Option<Signature>,
+
+    /// Signature in the modified PSBT
+    pub to: Option<Signature>,
+}
+
+/// Change in a proprietary key value.
+///
+/// Absent `from` value means that the key was added; absent `to` value means
+/// that the key was removed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct ProprietaryChange {
+    /// Location of the proprietary key
+    pub location: ProprietaryKeyLocation,
+
+    /// The proprietary key
+    pub key: ProprietaryKey,
+
+    /// Key value in the original PSBT
+    #[cfg_attr(feature = "serde", serde(with = "As::<Option<Hex>>"))]
+    pub from: Option<Vec<u8>>,
+
+    /// Key value in the modified PSBT
+    #[cfg_attr(feature = "serde", serde(with = "As::<Option<Hex>>"))]
+    pub to: Option<Vec<u8>>,
+}
+
+/// Change in a transaction output.
+///
+/// Absent `from` value means that the output was added; absent `to` value
+/// means that the output was removed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct OutputChange {
+    /// Index of the output
+    pub index: usize,
+
+    /// Output in the original PSBT
+    pub from: Option<TxOut>,
+
+    /// Output in the modified PSBT
+    pub to: Option<TxOut>,
+}
+
+/// Structured description of differences between two versions of a PSBT.
+///
+/// The diff covers input signatures, proprietary keys and transaction
+/// outputs. Other PSBT fields are not tracked.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct PsbtDiff {
+    /// Id of the unsigned transaction from the original PSBT
+    pub from_txid: Txid,
+
+    /// Id of the unsigned transaction from the modified PSBT
+    pub to_txid: Txid,
+
+    /// Added, removed and replaced input signatures
+    pub signatures: Vec<SigChange>,
+
+    /// Added, removed and modified proprietary keys
+    pub proprietary: Vec<ProprietaryChange>,
+
+    /// Added, removed and modified transaction outputs
+    pub outputs: Vec<OutputChange>,
+}
+
+impl PsbtDiff {
+    /// Detects whether both PSBTs are the same with respect to the fields
+    /// covered by the diff.
+    pub fn is_empty(&self) -> bool {
+        self.from_txid == self.to_txid
+            && self.signatures.is_empty()
+            && self.proprietary.is_empty()
+            && self.outputs.is_empty()
+    }
+}
+
+fn map_changes<K, V>(from: &BTreeMap<K, V>, to: &BTreeMap<K, V>) -> Vec<(K, Option<V>, Option<V>)>
+where
+    K: Ord + Clone,
+    V: Clone + PartialEq,
+{
+    from.keys()
+        .chain(to.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|key| {
+            let (a, b) = (from.get(key), to.get(key));
+            (a != b).then(|| (key.clone(), a.cloned(), b.cloned()))
+        })
+        .collect()
+}
+
+fn input_signatures(input: &Input) -> BTreeMap<SigSource, Signature> {
+    let ecdsa = input
+        .partial_sigs
+        .iter()
+        .map(|(pk, sig)| (SigSource::Ecdsa(*pk), Signature::Ecdsa(*sig)));
+    let tap_key = input
+        .tap_key_sig
+        .map(|sig| (SigSource::TapKey, Signature::Schnorr(sig)));
+    let tap_script = input
+        .tap_script_sigs
+        .iter()
+        .map(|((pk, leaf), sig)| (SigSource::TapScript(*pk, *leaf), Signature::Schnorr(*sig)));
+    ecdsa.chain(tap_key).chain(tap_script).collect()
+}
+
+fn proprietary_at(
+    psbt: &mut Psbt,
+    location: ProprietaryKeyLocation,
+) -> Result<&mut BTreeMap<ProprietaryKey, Vec<u8>>, DiffError> {
+    Ok(match location {
+        ProprietaryKeyLocation::Global => &mut psbt.proprietary,
+        ProprietaryKeyLocation::Input(no) => {
+            &mut psbt
+                .inputs
+                .get_mut(no as usize)
+                .ok_or(DiffError::NoInput(no as usize))?
+                .proprietary
+        }
+        ProprietaryKeyLocation::Output(no) => {
+            &mut psbt
+                .outputs
+                .get_mut(no as usize)
+                .ok_or(DiffError::NoOutput(no as usize))?
+                .proprietary
+        }
+    })
+}
+
+impl Psbt {
+    /// Computes field-level differences between this PSBT and `other`, which
+    /// is treated as a modified version of this PSBT.
+    pub fn diff(&self, other: &Psbt) -> PsbtDiff {
+        let mut signatures = vec![];
+        for (input, (a, b)) in self.inputs.iter().zip(&other.inputs).enumerate() {
+            signatures.extend(
+                map_changes(&input_signatures(a), &input_signatures(b))
+                    .into_iter()
+                    .map(|(source, from, to)| SigChange {
+                        input,
+                        source,
+                        from,
+                        to,
+                    }),
+            );
+        }
+
+        let empty = BTreeMap::new();
+        let mut locations = vec![(
+            ProprietaryKeyLocation::Global,
+            &self.proprietary,
+            &other.proprietary,
+        )];
+        let inputs = self.inputs.len().max(other.inputs.len());
+        let outputs = self.outputs.len().max(other.outputs.len());
+        for no in 0..inputs {
+            locations.push((
+                ProprietaryKeyLocation::Input(no as u16),
+                self.inputs
+                    .get(no)
+                    .map(|i| &i.proprietary)
+                    .unwrap_or(&empty),
+                other
+                    .inputs
+                    .get(no)
+                    .map(|i| &i.proprietary)
+                    .unwrap_or(&empty),
+            ));
+        }
+        for no in 0..outputs {
+            locations.push((
+                ProprietaryKeyLocation::Output(no as u16),
+                self.outputs
+                    .get(no)
+                    .map(|o| &o.proprietary)
+                    .unwrap_or(&empty),
+                other
+                    .outputs
+                    .get(no)
+                    .map(|o| &o.proprietary)
+                    .unwrap_or(&empty),
+            ));
+        }
+        let proprietary = locations
+            .into_iter()
+            .flat_map(|(location, a, b)| {
+                map_changes(a, b)
+                    .into_iter()
+                    .map(move |(key, from, to)| ProprietaryChange {
+                        location,
+                        key,
+                        from,
+                        to,
+                    })
+            })
+            .collect();
+
+        let outputs = (0..outputs)
+            .filter_map(|index| {
+                let from = self.outputs.get(index).map(Output::to_txout);
+                let to = other.outputs.get(index).map(Output::to_txout);
+                (from != to).then_some(OutputChange { index, from, to })
+            })
+            .collect();
+
+        PsbtDiff {
+            from_txid: self.to_txid(),
+            to_txid: other.to_txid(),
+            signatures,
+            proprietary,
+            outputs,
+        }
+    }
+
+    /// Applies changes from the diff to the PSBT.
+    ///
+    /// The PSBT must contain the same field values as the original PSBT used
+    /// to produce the diff, otherwise a conflict error is returned. In case of
+    /// an error the PSBT is left unmodified.
+    pub fn apply(&mut self, diff: &PsbtDiff) -> Result<(), DiffError> {
+        let actual = self.to_txid();
+        if actual != diff.from_txid {
+            return Err(DiffError::TxidMismatch {
+                expected: diff.from_txid,
+                actual,
+            });
+        }
+
+        let mut psbt = self.clone();
+
+        for change in diff.outputs.iter().filter(|change| change.to.is_some()) {
+            let txout = change.to.clone().expect("filtered");
+            match (&change.from, psbt.outputs.get_mut(change.index)) {
+                (None, None) if change.index == psbt.outputs.len() => {
+                    psbt.outputs.push(Output::new(change.index, txout))
+                }
+                (None, _) => return Err(DiffError::OutputOrder(change.index)),
+                (Some(_), None) => return Err(DiffError::NoOutput(change.index)),
+                (Some(from), Some(output)) if output.to_txout() == *from => {
+                    output.amount = txout.value;
+                    output.script = txout.script_pubkey.into();
+                }
+                (Some(_), Some(_)) => return Err(DiffError::OutputConflict(change.index)),
+            }
+        }
+
+        for change in &diff.signatures {
+            let input = psbt
+                .inputs
+                .get_mut(change.input)
+                .ok_or(DiffError::NoInput(change.input))?;
+            if input_signatures(input).get(&change.source) != change.from.as_ref() {
+                return Err(DiffError::SigConflict {
+                    input: change.input,
+                    source: change.source,
+                });
+            }
+            match (change.source, change.to) {
+                (SigSource::Ecdsa(pk), Some(Signature::Ecdsa(sig))) => {
+                    input.partial_sigs.insert(pk, sig);
+                }
+                (SigSource::Ecdsa(pk), None) => {
+                    input.partial_sigs.remove(&pk);
+                }
+                (SigSource::TapKey, Some(Signature::Schnorr(sig))) => {
+                    input.tap_key_sig = Some(sig);
+                }
+                (SigSource::TapKey, None) => input.tap_key_sig = None,
+                (SigSource::TapScript(pk, leaf), Some(Signature::Schnorr(sig))) => {
+                    input.tap_script_sigs.insert((pk, leaf), sig);
+                }
+                (SigSource::TapScript(pk, leaf), None) => {
+                    input.tap_script_sigs.remove(&(pk, leaf));
+                }
+                (source, Some(_)) => {
+                    return Err(DiffError::SigConflict {
+                        input: change.input,
+                        source,
+                    })
+                }
+            }
+        }
+
+        for change in &diff.proprietary {
+            let map = proprietary_at(&mut psbt, change.location)?;
+            if map.get(&change.key) != change.from.as_ref() {
+                return Err(DiffError::ProprietaryConflict {
+                    location: change.location,
+                    key: change.key.clone(),
+                });
+            }
+            match &change.to {
+                Some(value) => map.insert(change.key.clone(), value.clone()),
+                None => map.remove(&change.key),
+            };
+        }
+
+        for change in diff
+            .outputs
+            .iter()
+            .rev()
+            .filter(|change| change.to.is_none())
+        {
+            match (&change.from, psbt.outputs.last()) {
+                (Some(from), Some(output))
+                    if output.index() == change.index && output.to_txout() == *from =>
+                {
+                    psbt.outputs.pop();
+                }
+                (_, Some(output)) if output.index() == change.index => {
+                    return Err(DiffError::OutputConflict(change.index))
+                }
+                _ => return Err(DiffError::OutputOrder(change.index)),
+            }
+        }
+
+        let actual = psbt.to_txid();
+        if actual != diff.to_txid {
+            return Err(DiffError::Incomplete {
+                expected: diff.to_txid,
+                actual,
+            });
+        }
+
+        *self = psbt;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{self, Secp256k1, SecretKey};
+    use bitcoin::{
+        EcdsaSighashType, OutPoint, PackedLockTime, PublicKey, Script, Transaction, TxIn,
+    };
+
+    use super::*;
+    use crate::PsbtVersion;
+
+    #[test]
+    fn diff_and_apply() {
+        let secp = Secp256k1::new();
+        let seckey = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let pubkey = PublicKey::new(seckey.public_key(&secp));
+        let msg = secp256k1::Message::from_slice(&[0x33; 32]).unwrap();
+        let sig = EcdsaSig {
+            sig: secp.sign_ecdsa(&msg, &seckey),
+            hash_ty: EcdsaSighashType::All,
+        };
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), 0),
+                ..TxIn::default()
+            }],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: Script::new_op_return(b"first"),
+            }],
+        };
+        let base = Psbt::with(tx, PsbtVersion::V0).unwrap();
+        assert!(base.diff(&base).is_empty());
+
+        let key = ProprietaryKey {
+            prefix: b"test".to_vec(),
+            subtype: 0,
+            key: vec![],
+        };
+        let mut signed = base.clone();
+        signed.inputs[0].partial_sigs.insert(pubkey, sig);
+        signed.outputs[0]
+            .proprietary
+            .insert(key.clone(), vec![0xab]);
+        signed.outputs[0].amount = 9_000;
+        signed.outputs.push(Output::new(1, TxOut {
+            value: 500,
+            script_pubkey: Script::new_op_return(b"second"),
+        }));
+
+        let diff = base.diff(&signed);
+        assert_eq!(diff.signatures, vec![SigChange {
+            input: 0,
+            source: SigSource::Ecdsa(pubkey),
+            from: None,
+            to: Some(Signature::Ecdsa(sig)),
+        }]);
+        assert_eq!(diff.proprietary, vec![ProprietaryChange {
+            location: ProprietaryKeyLocation::Output(0),
+            key: key.clone(),
+            from: None,
+            to: Some(vec![0xab]),
+        }]);
+        assert_eq!(diff.outputs.len(), 2);
+
+        let mut patched = base.clone();
+        patched.apply(&diff).unwrap();
+        assert_eq!(patched, signed);
+        assert_eq!(
+            patched.apply(&diff),
+            Err(DiffError::TxidMismatch {
+                expected: base.to_txid(),
+                actual: signed.to_txid()
+            })
+        );
+
+        let revert = signed.diff(&base);
+        patched.apply(&revert).unwrap();
+        assert_eq!(patched, base);
+
+        let mut conflicting = base.clone();
+        conflicting.inputs[0]
+            .proprietary
+            .insert(key.clone(), vec![0x01]);
+        let mut changed = conflicting.clone();
+        changed.inputs[0]
+            .proprietary
+            .insert(key.clone(), vec![0x02]);
+        let diff = conflicting.diff(&changed);
+        let mut target = base.clone();
+        target.inputs[0].proprietary.insert(key.clone(), vec![0x03]);
+        assert_eq!(
+            target.apply(&diff),
+            Err(DiffError::ProprietaryConflict {
+                location: ProprietaryKeyLocation::Input(0),
+                key
+            })
+        );
+        assert_eq!(
+            target.inputs[0].proprietary.values().next(),
+            Some(&vec![0x03])
+        );
+    }
+}
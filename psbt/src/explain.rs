@@ -0,0 +1,360 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Human-readable explanation of the finalized input satisfactions, decoding
+//! input witness and `scriptSig` into the trace of the satisfied script
+//! conditions.
+
+use std::fmt::{self, Display, Formatter};
+
+use amplify::hex::ToHex;
+use amplify::Wrapper;
+use bitcoin::util::address::WitnessVersion;
+use bitcoin::util::taproot::{ControlBlock, TapLeafHash};
+use bitcoin::{LockTime, Script, Sequence, Witness};
+use miniscript::interpreter::{HashLockType, KeySigPair, SatisfiedConstraint};
+use miniscript::Interpreter;
+
+use crate::{Annex, InputMatchError, Psbt};
+
+/// Errors explaining input satisfaction
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ExplainError {
+    /// PSBT does not contain input #{0}
+    UnknownInput(usize),
+
+    /// input #{0} is not finalized
+    NotFinalized(usize),
+
+    /// unable to detect spent output: {0}
+    #[from]
+    Prevout(InputMatchError),
+
+    /// input witness or `scriptSig` does not satisfy the spent output: {0}
+    Interpreter(String),
+}
+
+/// Spending path used by the input satisfaction.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum SpendingPath {
+    /// Spending of non-taproot output.
+    Script,
+
+    /// Taproot key path spending.
+    KeyPath,
+
+    /// Taproot script path spending using a specific script leaf.
+    ScriptPath {
+        /// Hash of the spent leaf.
+        leaf_hash: TapLeafHash,
+
+        /// Spent leaf script.
+        script: Script,
+    },
+
+    /// Spending of witness program with a version not having consensus
+    /// meaning yet (v1 programs other than taproot and v2 and above), which
+    /// does not require any satisfaction.
+    FutureWitness(WitnessVersion),
+}
+
+impl Display for SpendingPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SpendingPath::Script => f.write_str("script"),
+            SpendingPath::KeyPath => f.write_str("taproot key path"),
+            SpendingPath::ScriptPath { leaf_hash, script } => {
+                write!(
+                    f,
+                    "taproot script path, leaf {} `{}`",
+                    leaf_hash,
+                    script.asm()
+                )
+            }
+            SpendingPath::FutureWitness(version) => {
+                write!(f, "future witness version {}", version.to_num())
+            }
+        }
+    }
+}
+
+/// Single script condition satisfied by an input witness or `scriptSig`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(doc_comments)]
+pub enum SatisfactionStep {
+    /// signature by key {key} with {sighash} sighash type
+    Signature { key: String, sighash: String },
+
+    /// signature by key {key} having hash {key_hash} with {sighash} sighash
+    /// type
+    KeyHashSignature {
+        key_hash: String,
+        key: String,
+        sighash: String,
+    },
+
+    /// {hash_type} hash lock {hash} unlocked with preimage {preimage}
+    HashLock {
+        hash_type: &'static str,
+        hash: String,
+        preimage: String,
+    },
+
+    /// relative time lock {0}
+    RelativeTimelock(Sequence),
+
+    /// absolute time lock {0}
+    AbsoluteTimelock(LockTime),
+}
+
+impl From<SatisfiedConstraint> for SatisfactionStep {
+    fn from(constraint: SatisfiedConstraint) -> Self {
+        fn key_sig(key_sig: KeySigPair) -> (String, String) {
+            match key_sig {
+                KeySigPair::Ecdsa(key, sig) => (key.to_string(), sig.hash_ty.to_string()),
+                KeySigPair::Schnorr(key, sig) => (key.to_string(), sig.hash_ty.to_string()),
+            }
+        }
+
+        match constraint {
+            SatisfiedConstraint::PublicKey { key_sig: pair } => {
+                let (key, sighash) = key_sig(pair);
+                SatisfactionStep::Signature { key, sighash }
+            }
+            SatisfiedConstraint::PublicKeyHash {
+                keyhash,
+                key_sig: pair,
+            } => {
+                let (key, sighash) = key_sig(pair);
+                SatisfactionStep::KeyHashSignature {
+                    key_hash: keyhash.to_string(),
+                    key,
+                    sighash,
+                }
+            }
+            SatisfiedConstraint::HashLock { hash, preimage } => {
+                let (hash_type, hash) = match hash {
+                    HashLockType::Sha256(hash) => ("SHA256", hash.to_string()),
+                    HashLockType::Hash256(hash) => ("HASH256", hash.to_string()),
+                    HashLockType::Hash160(hash) => ("HASH160", hash.to_string()),
+                    HashLockType::Ripemd160(hash) => ("RIPEMD160", hash.to_string()),
+                };
+                SatisfactionStep::HashLock {
+                    hash_type,
+                    hash,
+                    preimage: preimage[..].to_hex(),
+                }
+            }
+            SatisfiedConstraint::RelativeTimelock { n } => SatisfactionStep::RelativeTimelock(n),
+            SatisfiedConstraint::AbsoluteTimelock { n } => SatisfactionStep::AbsoluteTimelock(n),
+        }
+    }
+}
+
+/// Explanation of a finalized input satisfaction.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SatisfactionTrace {
+    /// Descriptor of the spent output inferred from the satisfaction data.
+    pub descriptor: String,
+
+    /// Spending path taken by the satisfaction.
+    pub path: SpendingPath,
+
+    /// Satisfied script conditions, in order of their execution.
+    pub steps: Vec<SatisfactionStep>,
+
+    /// Taproot witness annex, if present.
+    pub annex: Option<Annex>,
+}
+
+impl Display for SatisfactionTrace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "descriptor: {}", self.descriptor)?;
+        write!(f, "path: {}", self.path)?;
+        if let Some(annex) = &self.annex {
+            write!(f, "\nannex: {}", annex)?;
+        }
+        for step in &self.steps {
+            write!(f, "\n- {}", step)?;
+        }
+        Ok(())
+    }
+}
+
+impl Psbt {
+    /// Explains how the finalized input satisfies the spent output script:
+    /// which spending path was taken, which keys have signed and which hash-
+    /// and time-locks were used.
+    ///
+    /// Signatures are not verified; the method is intended for debugging
+    /// script verification failures.
+    pub fn explain_input(&self, index: usize) -> Result<SatisfactionTrace, ExplainError> {
+        let input = self
+            .inputs
+            .get(index)
+            .ok_or(ExplainError::UnknownInput(index))?;
+        if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+            return Err(ExplainError::NotFinalized(index));
+        }
+        let script_pubkey = &input.input_prevout()?.script_pubkey;
+        let empty_script = Script::new();
+        let script_sig = input
+            .final_script_sig
+            .as_ref()
+            .map(Wrapper::as_inner)
+            .unwrap_or(&empty_script);
+        let empty_witness = Witness::new();
+        let witness = input
+            .final_script_witness
+            .as_ref()
+            .unwrap_or(&empty_witness);
+
+        match script_pubkey.witness_version() {
+            Some(WitnessVersion::V0) | None => {}
+            Some(WitnessVersion::V1) if script_pubkey.is_v1_p2tr() => {}
+            Some(version) => {
+                return Ok(SatisfactionTrace {
+                    descriptor: format!("raw({})", script_pubkey.as_bytes().to_hex()),
+                    path: SpendingPath::FutureWitness(version),
+                    steps: vec![],
+                    annex: Annex::from_witness(witness),
+                });
+            }
+        }
+
+        let tx = self.to_unsigned_tx();
+        let interpreter = Interpreter::from_txdata(
+            script_pubkey,
+            script_sig,
+            witness,
+            tx.input[index].sequence,
+            LockTime::from(tx.lock_time),
+        )
+        .map_err(|err| ExplainError::Interpreter(err.to_string()))?;
+
+        let mut annex = None;
+        let path = if interpreter.is_taproot_v1_key_spend() {
+            annex = Annex::from_witness(witness);
+            SpendingPath::KeyPath
+        } else if interpreter.is_taproot_v1_script_spend() {
+            annex = Annex::from_witness(witness);
+            script_path(witness).ok_or_else(|| {
+                ExplainError::Interpreter(s!("invalid taproot script path witness"))
+            })?
+        } else {
+            SpendingPath::Script
+        };
+
+        let steps = interpreter
+            .iter_assume_sigs()
+            .map(|res| {
+                res.map(SatisfactionStep::from)
+                    .map_err(|err| ExplainError::Interpreter(err.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(SatisfactionTrace {
+            descriptor: interpreter.inferred_descriptor_string(),
+            path,
+            steps,
+            annex,
+        })
+    }
+}
+
+/// Extracts spent leaf from the taproot script path spending witness.
+fn script_path(witness: &Witness) -> Option<SpendingPath> {
+    let (mut elements, _) = Annex::split_witness(witness);
+    let control_block = ControlBlock::from_slice(&elements.pop()?).ok()?;
+    let script = Script::from(elements.pop()?);
+    let leaf_hash = TapLeafHash::from_script(&script, control_block.leaf_version);
+    Some(SpendingPath::ScriptPath { leaf_hash, script })
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{Message, SecretKey, SECP256K1};
+    use bitcoin::{
+        EcdsaSig, EcdsaSighashType, OutPoint, PackedLockTime, PublicKey, Transaction, TxIn, TxOut,
+        Txid,
+    };
+
+    use super::*;
+    use crate::PsbtVersion;
+
+    #[test]
+    fn explain_wpkh() {
+        let sk = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let pubkey = PublicKey::new(sk.public_key(SECP256K1));
+        let sig = EcdsaSig {
+            sig: SECP256K1.sign_ecdsa(&Message::from_slice(&[0x01; 32]).unwrap(), &sk),
+            hash_ty: EcdsaSighashType::All,
+        };
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_inner([0x02; 32]), 0),
+                ..TxIn::default()
+            }],
+            output: vec![],
+        };
+        let mut psbt = Psbt::with(tx, PsbtVersion::V0).unwrap();
+        assert_eq!(psbt.explain_input(0), Err(ExplainError::NotFinalized(0)));
+        assert_eq!(psbt.explain_input(1), Err(ExplainError::UnknownInput(1)));
+
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: Script::new_v0_p2wpkh(&pubkey.wpubkey_hash().unwrap()),
+        });
+        psbt.inputs[0].final_script_witness =
+            Some(Witness::from_vec(vec![sig.to_vec(), pubkey.to_bytes()]));
+
+        let trace = psbt.explain_input(0).unwrap();
+        assert!(trace.descriptor.starts_with("wpkh("));
+        assert_eq!(trace.path, SpendingPath::Script);
+        assert_eq!(trace.steps.len(), 1);
+        assert!(trace.steps[0].to_string().contains(&pubkey.to_string()));
+    }
+
+    #[test]
+    fn explain_future_witness() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_inner([0x02; 32]), 0),
+                ..TxIn::default()
+            }],
+            output: vec![],
+        };
+        let mut psbt = Psbt::with(tx, PsbtVersion::V0).unwrap();
+        let script_pubkey = Script::new_witness_program(WitnessVersion::V2, &[0xAB; 32]);
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: script_pubkey.clone(),
+        });
+        psbt.inputs[0].final_script_witness =
+            Some(Witness::from_vec(vec![vec![0x01], vec![0x50, 0x01]]));
+
+        let trace = psbt.explain_input(0).unwrap();
+        assert_eq!(
+            trace.descriptor,
+            format!("raw({})", script_pubkey.as_bytes().to_hex())
+        );
+        assert_eq!(trace.path, SpendingPath::FutureWitness(WitnessVersion::V2));
+        assert!(trace.steps.is_empty());
+        assert_eq!(trace.annex, Some(Annex::new(vec![0x50, 0x01]).unwrap()));
+    }
+}
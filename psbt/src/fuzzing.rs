@@ -0,0 +1,239 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Implementations of [`Arbitrary`] for PSBT data structures, used by fuzz
+//! targets and property tests.
+//!
+//! Generated PSBTs are structurally valid (i.e. they have consistent input and
+//! output indexes and can be serialized), but their signatures, key origins
+//! and spent outputs are not related to each other.
+
+use std::collections::BTreeMap;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use bitcoin::hashes::Hash;
+use bitcoin::psbt::PsbtSighashType;
+use bitcoin::secp256k1::{self, SecretKey, SECP256K1};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, Fingerprint, KeySource};
+use bitcoin::{OutPoint, Script, TxOut, Txid, Witness, XOnlyPublicKey};
+use bitcoin_blockchain::locks::{LockTime, SeqNo};
+
+use crate::{raw, Input, Output, Psbt, PsbtVersion};
+
+fn script(u: &mut Unstructured) -> Result<Script> { Ok(Script::from(Vec::<u8>::arbitrary(u)?)) }
+
+fn pubkey(u: &mut Unstructured) -> Result<Option<secp256k1::PublicKey>> {
+    let secret = <[u8; 32]>::arbitrary(u)?;
+    Ok(SecretKey::from_slice(&secret)
+        .ok()
+        .map(|sk| sk.public_key(SECP256K1)))
+}
+
+fn key_source(u: &mut Unstructured) -> Result<KeySource> {
+    let fingerprint = Fingerprint::from(&<[u8; 4]>::arbitrary(u)?[..]);
+    let path = Vec::<u32>::arbitrary(u)?
+        .into_iter()
+        .map(ChildNumber::from)
+        .collect::<DerivationPath>();
+    Ok((fingerprint, path))
+}
+
+fn bip32_derivation(u: &mut Unstructured) -> Result<BTreeMap<secp256k1::PublicKey, KeySource>> {
+    let mut map = bmap! {};
+    for _ in 0..u.int_in_range(0..=3)? {
+        if let Some(pubkey) = pubkey(u)? {
+            map.insert(pubkey, key_source(u)?);
+        }
+    }
+    Ok(map)
+}
+
+fn tap_internal_key(u: &mut Unstructured) -> Result<Option<XOnlyPublicKey>> {
+    Ok(pubkey(u)?.map(XOnlyPublicKey::from))
+}
+
+fn proprietary(u: &mut Unstructured) -> Result<BTreeMap<raw::ProprietaryKey, Vec<u8>>> {
+    let mut map = bmap! {};
+    for _ in 0..u.int_in_range(0..=3)? {
+        let key = raw::ProprietaryKey {
+            prefix: Vec::arbitrary(u)?,
+            subtype: u8::arbitrary(u)?,
+            key: Vec::arbitrary(u)?,
+        };
+        map.insert(key, Vec::arbitrary(u)?);
+    }
+    Ok(map)
+}
+
+fn unknown(u: &mut Unstructured) -> Result<BTreeMap<raw::Key, Vec<u8>>> {
+    let mut map = bmap! {};
+    for _ in 0..u.int_in_range(0..=3)? {
+        let key = raw::Key {
+            // Avoid clashes with the keys defined by BIP-174, BIP-370 and BIP-371
+            type_value: u.int_in_range(0x20..=0xFB)?,
+            key: Vec::arbitrary(u)?,
+        };
+        map.insert(key, Vec::arbitrary(u)?);
+    }
+    Ok(map)
+}
+
+impl<'a> Arbitrary<'a> for PsbtVersion {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(if bool::arbitrary(u)? {
+            PsbtVersion::V2
+        } else {
+            PsbtVersion::V0
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Input {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let txid = Txid::from_inner(<[u8; 32]>::arbitrary(u)?);
+        let witness_utxo = match bool::arbitrary(u)? {
+            true => Some(TxOut {
+                value: u64::arbitrary(u)?,
+                script_pubkey: script(u)?,
+            }),
+            false => None,
+        };
+        let redeem_script = match bool::arbitrary(u)? {
+            true => Some(script(u)?.into()),
+            false => None,
+        };
+        let witness_script = match bool::arbitrary(u)? {
+            true => Some(script(u)?.into()),
+            false => None,
+        };
+        let final_script_witness = match bool::arbitrary(u)? {
+            true => Some(Witness::from_vec(Vec::arbitrary(u)?)),
+            false => None,
+        };
+        Ok(Input {
+            index: 0,
+            previous_outpoint: OutPoint::new(txid, u32::arbitrary(u)?),
+            sequence_number: Option::<u32>::arbitrary(u)?.map(SeqNo::from_consensus),
+            witness_utxo,
+            sighash_type: Option::<u32>::arbitrary(u)?.map(PsbtSighashType::from_u32),
+            redeem_script,
+            witness_script,
+            bip32_derivation: bip32_derivation(u)?,
+            final_script_witness,
+            tap_internal_key: tap_internal_key(u)?,
+            proprietary: proprietary(u)?,
+            unknown: unknown(u)?,
+            ..default!()
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Output {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let redeem_script = match bool::arbitrary(u)? {
+            true => Some(script(u)?.into()),
+            false => None,
+        };
+        let witness_script = match bool::arbitrary(u)? {
+            true => Some(script(u)?.into()),
+            false => None,
+        };
+        Ok(Output {
+            index: 0,
+            amount: u64::arbitrary(u)?,
+            script: script(u)?.into(),
+            redeem_script,
+            witness_script,
+            bip32_derivation: bip32_derivation(u)?,
+            tap_internal_key: tap_internal_key(u)?,
+            proprietary: proprietary(u)?,
+            unknown: unknown(u)?,
+            ..default!()
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Psbt {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut inputs = Vec::<Input>::arbitrary(u)?;
+        for (index, input) in inputs.iter_mut().enumerate() {
+            input.index = index;
+        }
+        let mut outputs = Vec::<Output>::arbitrary(u)?;
+        for (index, output) in outputs.iter_mut().enumerate() {
+            output.index = index;
+        }
+        Ok(Psbt {
+            psbt_version: PsbtVersion::arbitrary(u)?,
+            tx_version: u.int_in_range(1..=2)?,
+            fallback_locktime: Option::<u32>::arbitrary(u)?.map(LockTime::from),
+            inputs,
+            outputs,
+            xpub: none!(),
+            proprietary: proprietary(u)?,
+            unknown: unknown(u)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::sha256;
+
+    use super::*;
+    use crate::serialize::Serialize;
+
+    fn data(seed: u8) -> Vec<u8> {
+        (0..32u8)
+            .flat_map(|i| sha256::Hash::hash(&[seed, i]).into_inner())
+            .collect()
+    }
+
+    #[test]
+    fn arbitrary_psbt() {
+        for seed in 0..16 {
+            let data = data(seed);
+            let psbt = Psbt::arbitrary(&mut Unstructured::new(&data)).unwrap();
+            assert!((1..=2).contains(&psbt.tx_version));
+            assert!(psbt.xpub.is_empty());
+            for (index, input) in psbt.inputs.iter().enumerate() {
+                assert_eq!(input.index, index);
+                assert!(input.unknown.keys().all(|key| key.type_value >= 0x20));
+            }
+            for (index, output) in psbt.outputs.iter().enumerate() {
+                assert_eq!(output.index, index);
+                assert!(output.unknown.keys().all(|key| key.type_value <= 0xFB));
+            }
+            assert!(!psbt.serialize().is_empty());
+            assert!(psbt.to_string().starts_with("cHNidP8"));
+        }
+    }
+
+    #[test]
+    fn arbitrary_exhausted() {
+        let psbt = Psbt::arbitrary(&mut Unstructured::new(&[])).unwrap();
+        assert_eq!(psbt.psbt_version, PsbtVersion::V0);
+        assert_eq!(psbt.tx_version, 1);
+        assert!(psbt.inputs.is_empty());
+        assert!(psbt.outputs.is_empty());
+        assert!(psbt.proprietary.is_empty());
+        assert!(psbt.unknown.is_empty());
+
+        let input = Input::arbitrary(&mut Unstructured::new(&[])).unwrap();
+        assert_eq!(input.witness_utxo, None);
+        assert_eq!(input.tap_internal_key, None);
+        assert!(input.bip32_derivation.is_empty());
+
+        let output = Output::arbitrary(&mut Unstructured::new(&[])).unwrap();
+        assert_eq!(output.amount, 0);
+        assert!(output.script.as_inner().is_empty());
+    }
+}
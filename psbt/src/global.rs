@@ -13,6 +13,7 @@ use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
+use amplify::hex::{self, FromHex, ToHex};
 use base64::Engine;
 use bitcoin::util::bip32::{ExtendedPubKey, KeySource};
 use bitcoin::{consensus, Transaction, Txid};
@@ -293,16 +294,102 @@ impl Deserialize for Psbt {
     }
 }
 
-impl Display for Psbt {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+/// Magic bytes starting binary PSBT serialization (BIP-174).
+pub const PSBT_MAGIC: [u8; 5] = *b"psbt\xff";
+
+impl Psbt {
+    /// Encodes PSBT into a Base64 string, as used by Bitcoin Core and other
+    /// wallets.
+    pub fn to_base64(&self) -> String {
+        let engine = base64::engine::GeneralPurpose::new(
+            &base64::alphabet::STANDARD,
+            base64::engine::GeneralPurposeConfig::new(),
+        );
+        engine.encode(self.serialize())
+    }
+
+    /// Decodes PSBT from a Base64 string, as produced by Bitcoin Core and
+    /// other wallets.
+    pub fn from_base64(s: &str) -> Result<Psbt, PsbtParseError> {
         let engine = base64::engine::GeneralPurpose::new(
             &base64::alphabet::STANDARD,
             base64::engine::GeneralPurposeConfig::new(),
         );
-        f.write_str(&engine.encode(self.serialize()))
+        let bytes = engine.decode(s.trim())?;
+        Psbt::deserialize(&bytes).map_err(PsbtParseError::from)
+    }
+
+    /// Encodes PSBT into a hexadecimal string.
+    pub fn to_hex(&self) -> String { self.serialize().to_hex() }
+
+    /// Decodes PSBT from a hexadecimal string.
+    pub fn from_hex(s: &str) -> Result<Psbt, PsbtParseError> {
+        let bytes = Vec::<u8>::from_hex(s.trim())?;
+        Psbt::deserialize(&bytes).map_err(PsbtParseError::from)
+    }
+
+    /// Decodes PSBT from the data in binary, hexadecimal or Base64 encoding,
+    /// detecting the encoding automatically from the PSBT magic bytes.
+    pub fn decode_any(data: &[u8]) -> Result<Psbt, PsbtParseError> {
+        Psbt::decode(data, PsbtEncoding::detect(data))
+    }
+
+    /// Decodes PSBT from the data in a given encoding.
+    pub fn decode(data: &[u8], encoding: PsbtEncoding) -> Result<Psbt, PsbtParseError> {
+        match encoding {
+            PsbtEncoding::Binary => Psbt::deserialize(data).map_err(PsbtParseError::from),
+            PsbtEncoding::Hex => Psbt::from_hex(&String::from_utf8_lossy(data)),
+            PsbtEncoding::Base64 => Psbt::from_base64(&String::from_utf8_lossy(data)),
+        }
+    }
+
+    /// Encodes PSBT into data using a given encoding.
+    pub fn encode(&self, encoding: PsbtEncoding) -> Vec<u8> {
+        match encoding {
+            PsbtEncoding::Binary => self.serialize(),
+            PsbtEncoding::Hex => self.to_hex().into_bytes(),
+            PsbtEncoding::Base64 => self.to_base64().into_bytes(),
+        }
     }
 }
 
+/// Encodings used for storing and transferring PSBT data.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum PsbtEncoding {
+    /// Binary BIP-174 serialization.
+    #[display("binary")]
+    Binary,
+
+    /// Hexadecimal string with binary BIP-174 serialization.
+    #[display("hex")]
+    Hex,
+
+    /// Base64 string with binary BIP-174 serialization.
+    #[display("base64")]
+    Base64,
+}
+
+impl PsbtEncoding {
+    /// Detects encoding of the PSBT data from the PSBT magic bytes. Data
+    /// which are neither binary nor hexadecimal PSBT are considered to be
+    /// Base64-encoded.
+    pub fn detect(data: &[u8]) -> PsbtEncoding {
+        if data.starts_with(&PSBT_MAGIC) {
+            return PsbtEncoding::Binary;
+        }
+        let s = String::from_utf8_lossy(data);
+        if s.trim_start().starts_with(&PSBT_MAGIC.to_hex()) {
+            PsbtEncoding::Hex
+        } else {
+            PsbtEncoding::Base64
+        }
+    }
+}
+
+impl Display for Psbt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result { f.write_str(&self.to_base64()) }
+}
+
 #[derive(Debug, Display, Error, From)]
 #[display(inner)]
 pub enum PsbtParseError {
@@ -311,29 +398,23 @@ pub enum PsbtParseError {
 
     #[from]
     Base64(base64::DecodeError),
+
+    #[from]
+    Hex(hex::Error),
 }
 
 impl FromStr for Psbt {
     type Err = PsbtParseError;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let engine = base64::engine::GeneralPurpose::new(
-            &base64::alphabet::STANDARD,
-            base64::engine::GeneralPurposeConfig::new(),
-        );
-        let bytes = engine.decode(s)?;
-        Psbt::deserialize(&bytes).map_err(PsbtParseError::from)
-    }
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Psbt::from_base64(s) }
 }
 
 #[cfg(test)]
 mod test {
-    use amplify::hex::FromHex;
-
     use super::*;
 
     #[test]
-    #[ignore]
     fn psbt_bip174_serialization() {
         let hex = "\
             70736274ff0100750200000001268171371edff285e937adeea4b37b78000c0566\
@@ -354,10 +435,33 @@ mod test {
             167c8684031c05d1f2592a01210223b72beef0965d10be0778efecd61fcac6f79a4\
             ea169393380734464f84f2ab300000000000000";
 
-        let psbt = Psbt::from_str(hex).unwrap();
-        let hex_prime = psbt.to_string();
+        let psbt = Psbt::from_hex(hex).unwrap();
+        let hex_prime = psbt.to_hex();
         let psbt_prime = Psbt::deserialize(&Vec::from_hex(&hex_prime).unwrap()).unwrap();
         assert_eq!(psbt, psbt_prime);
         assert_eq!(hex, hex_prime);
+
+        let base64 = psbt.to_base64();
+        assert!(base64.starts_with("cHNidP"));
+        assert_eq!(base64, psbt.to_string());
+        assert_eq!(Psbt::from_base64(&base64).unwrap(), psbt);
+        assert_eq!(Psbt::from_str(&base64).unwrap(), psbt);
+
+        assert_eq!(Psbt::decode_any(hex.as_bytes()).unwrap(), psbt);
+        assert_eq!(Psbt::decode_any(base64.as_bytes()).unwrap(), psbt);
+        assert_eq!(Psbt::decode_any(&psbt.serialize()).unwrap(), psbt);
+
+        for encoding in [
+            PsbtEncoding::Binary,
+            PsbtEncoding::Hex,
+            PsbtEncoding::Base64,
+        ] {
+            let data = psbt.encode(encoding);
+            assert_eq!(PsbtEncoding::detect(&data), encoding);
+            assert_eq!(Psbt::decode(&data, encoding).unwrap(), psbt);
+        }
+        assert_eq!(PsbtEncoding::detect(b"\n70736274ff"), PsbtEncoding::Hex);
+        assert_eq!(psbt.encode(PsbtEncoding::Hex), hex.as_bytes());
+        assert_eq!(psbt.encode(PsbtEncoding::Base64), base64.as_bytes());
     }
 }
@@ -0,0 +1,147 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Finalization of PSBT inputs spending hash time-locked contracts (see
+//! [`descriptors::hlc`]), which are not supported by the miniscript-based
+//! finalizer.
+
+use amplify::Wrapper;
+use bitcoin::{PublicKey, Witness};
+use bitcoin_scripts::hlc::HashPreimage;
+use bitcoin_scripts::LockScript;
+use descriptors::hlc::{HlcError, HlcScript};
+
+use crate::Input;
+
+/// Errors finalizing PSBT input spending hash time-locked contract
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum HlcFinalizeError {
+    /// input {0} does not provide witness script
+    NoWitnessScript(usize),
+
+    /// witness script of input {0} is not a hash time-locked contract
+    NotHlc(usize),
+
+    /// preimage provided for input {0} does not match the contract hash lock
+    PreimageMismatch(usize),
+
+    /// input {0} has no signature made with the contract key {1}
+    NoSignature(usize, PublicKey),
+}
+
+impl Input {
+    /// Finalizes input spending P2WSH hash time-locked contract, taking the
+    /// contract from the input witness script and the signature from the
+    /// partial signatures. If `preimage` is given, the claim path is used;
+    /// otherwise the input spends the refund path, and the transaction must
+    /// satisfy the contract timeout.
+    ///
+    /// On success, clears the fields which are not required anymore (as per
+    /// BIP-174 finalizer role) and returns the contract script.
+    pub fn finalize_hlc(
+        &mut self,
+        preimage: Option<&HashPreimage>,
+    ) -> Result<HlcScript, HlcFinalizeError> {
+        let script = self
+            .witness_script
+            .as_ref()
+            .ok_or(HlcFinalizeError::NoWitnessScript(self.index))?;
+        let hlc = HlcScript::from_lock_script(&LockScript::from(script.as_inner().clone()))
+            .ok_or(HlcFinalizeError::NotHlc(self.index))?;
+
+        let pubkey = match preimage {
+            Some(_) => hlc.claim_pubkey,
+            None => hlc.refund_pubkey,
+        };
+        let sig = self
+            .partial_sigs
+            .get(&pubkey)
+            .ok_or(HlcFinalizeError::NoSignature(self.index, pubkey))?;
+        let witness = match preimage {
+            Some(preimage) => hlc.claim_witness(sig, preimage),
+            None => hlc.refund_witness(sig),
+        }
+        .map_err(|err| match err {
+            HlcError::PreimageMismatch => HlcFinalizeError::PreimageMismatch(self.index),
+            HlcError::InvalidTimeout(_) => HlcFinalizeError::NotHlc(self.index),
+        })?;
+
+        self.final_script_witness = Some(Witness::from_vec(witness));
+        self.partial_sigs.clear();
+        self.sighash_type = None;
+        self.redeem_script = None;
+        self.witness_script = None;
+        self.bip32_derivation.clear();
+        Ok(hlc)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use amplify::Slice32;
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::{EcdsaSig, EcdsaSighashType};
+    use bitcoin_scripts::hlc::HashLock;
+    use descriptors::hlc::{HashCommitment, PREIMAGE_LEN};
+    use descriptors::locks::Lock;
+
+    use super::*;
+
+    #[test]
+    fn finalize_claim_and_refund() {
+        let secp = Secp256k1::new();
+        let seckey = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let claim_pubkey = PublicKey::new(seckey.public_key(&secp));
+        let refund_pubkey = PublicKey::from_str(
+            "030d417a46946384f88d5f3337267c5e579765875dc4daca813e21734b140639e7",
+        )
+        .unwrap();
+        let preimage = HashPreimage::from_inner(Slice32::from_inner([0x42u8; PREIMAGE_LEN]));
+        let hlc = HlcScript {
+            hash_commitment: HashCommitment::Sha256(HashLock::from(preimage)),
+            claim_pubkey,
+            timeout: Lock::AfterHeight(800_000),
+            refund_pubkey,
+        };
+        let sig = EcdsaSig {
+            sig: secp.sign_ecdsa(&Message::from_slice(&[0x33; 32]).unwrap(), &seckey),
+            hash_ty: EcdsaSighashType::All,
+        };
+
+        let mut input = Input {
+            witness_script: Some(hlc.to_lock_script().unwrap().into_inner().into()),
+            ..Input::default()
+        };
+        input.partial_sigs.insert(claim_pubkey, sig);
+
+        let mut refund = input.clone();
+        assert_eq!(
+            refund.finalize_hlc(None),
+            Err(HlcFinalizeError::NoSignature(0, refund_pubkey))
+        );
+        let wrong = HashPreimage::from_inner(Slice32::from_inner([0u8; PREIMAGE_LEN]));
+        assert_eq!(
+            input.clone().finalize_hlc(Some(&wrong)),
+            Err(HlcFinalizeError::PreimageMismatch(0))
+        );
+
+        assert_eq!(input.finalize_hlc(Some(&preimage)), Ok(hlc));
+        assert!(input.partial_sigs.is_empty());
+        assert_eq!(input.witness_script, None);
+        assert_eq!(
+            input.final_script_witness.unwrap().to_vec(),
+            hlc.claim_witness(&sig, &preimage).unwrap()
+        );
+    }
+}
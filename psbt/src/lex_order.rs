@@ -10,17 +10,76 @@
 // If not, see <https://opensource.org/licenses/Apache-2.0>.
 
 //! Lexicographic sorting functions.
+//!
+//! Sorting is stable: inputs and outputs with equal sorting keys (for instance
+//! outputs with the same amount and `scriptPubkey`) keep their original
+//! relative order. Transactions and PSBTs can be ordered with [`LexPermute`],
+//! which reports the performed permutation, allowing callers to track where
+//! their inputs and outputs (like change outputs) ended up.
 
 use std::cmp::Ordering;
 
-use bitcoin::{secp256k1, Transaction, TxIn, TxOut};
+use bitcoin::{secp256k1, OutPoint, Transaction, TxIn, TxOut};
 
 use crate::v0::PsbtV0;
 use crate::{Input, Output, Psbt};
 
+/// Violation of lexicographic (BIP-69) ordering of transaction inputs or
+/// outputs
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum LexOrderError {
+    /// input #{0} must precede the previous input according to the
+    /// lexicographic ordering
+    Input(usize),
+
+    /// output #{0} must precede the previous output according to the
+    /// lexicographic ordering
+    Output(usize),
+}
+
+/// Permutation of transaction inputs and outputs performed by
+/// [`LexPermute::lex_permute`].
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct LexPermutation {
+    /// New index of each input, indexed by the input position before the
+    /// ordering
+    pub inputs: Vec<usize>,
+
+    /// New index of each output, indexed by the output position before the
+    /// ordering
+    pub outputs: Vec<usize>,
+}
+
+impl LexPermutation {
+    /// Returns new index of the input which had a given `index` before the
+    /// ordering.
+    pub fn input_index(&self, index: usize) -> Option<usize> { self.inputs.get(index).copied() }
+
+    /// Returns new index of the output which had a given `index` before the
+    /// ordering.
+    pub fn output_index(&self, index: usize) -> Option<usize> { self.outputs.get(index).copied() }
+
+    /// Detects whether the ordering has not moved any of inputs or outputs.
+    pub fn is_identity(&self) -> bool {
+        self.inputs
+            .iter()
+            .enumerate()
+            .all(|(pos, index)| pos == *index)
+            && self
+                .outputs
+                .iter()
+                .enumerate()
+                .all(|(pos, index)| pos == *index)
+    }
+}
+
 pub trait LexOrder {
     fn lex_order(&mut self);
 
+    /// Checks whether the data are already lexicographically ordered.
+    fn is_lex_ordered(&self) -> bool;
+
     fn lex_ordered(mut self) -> Self
     where
         Self: Sized,
@@ -32,66 +91,89 @@ pub trait LexOrder {
 
 impl LexOrder for Vec<secp256k1::PublicKey> {
     fn lex_order(&mut self) { self.sort() }
+
+    fn is_lex_ordered(&self) -> bool { first_unordered(self, Ord::cmp).is_none() }
 }
 
 impl LexOrder for Vec<bitcoin::PublicKey> {
     fn lex_order(&mut self) { self.sort() }
+
+    fn is_lex_ordered(&self) -> bool { first_unordered(self, Ord::cmp).is_none() }
 }
 
 impl LexOrder for Vec<TxIn> {
-    fn lex_order(&mut self) { self.sort_by_key(|txin| txin.previous_output) }
+    fn lex_order(&mut self) { self.sort_by(txin_cmp) }
+
+    fn is_lex_ordered(&self) -> bool { first_unordered(self, txin_cmp).is_none() }
 }
 
 impl LexOrder for Vec<TxOut> {
     fn lex_order(&mut self) { self.sort_by(txout_cmp) }
+
+    fn is_lex_ordered(&self) -> bool { first_unordered(self, txout_cmp).is_none() }
+}
+
+/// Lexicographic ordering of transaction inputs and outputs together with the
+/// associated data, reporting the performed permutation.
+pub trait LexPermute: LexOrder {
+    /// Orders inputs and outputs lexicographically, keeping the original
+    /// relative order of items with equal sorting keys.
+    ///
+    /// # Returns
+    ///
+    /// Permutation mapping original input and output indexes to the new ones.
+    fn lex_permute(&mut self) -> LexPermutation;
 }
 
 impl LexOrder for Transaction {
-    fn lex_order(&mut self) {
-        self.input.lex_order();
-        self.output.lex_order();
+    fn lex_order(&mut self) { self.lex_permute(); }
+
+    fn is_lex_ordered(&self) -> bool { verify_tx_lex_order(self).is_ok() }
+}
+
+impl LexPermute for Transaction {
+    fn lex_permute(&mut self) -> LexPermutation {
+        LexPermutation {
+            inputs: sort_permutation(&mut self.input, txin_cmp),
+            outputs: sort_permutation(&mut self.output, txout_cmp),
+        }
     }
 }
 
 impl LexOrder for Vec<(TxOut, crate::v0::OutputV0)> {
     fn lex_order(&mut self) { self.sort_by(|(a, _), (b, _)| txout_cmp(a, b)); }
+
+    fn is_lex_ordered(&self) -> bool {
+        first_unordered(self, |(a, _), (b, _)| txout_cmp(a, b)).is_none()
+    }
 }
 
 impl LexOrder for PsbtV0 {
-    fn lex_order(&mut self) {
-        let tx = &mut self.unsigned_tx;
-        let mut inputs = tx
-            .input
-            .clone()
-            .into_iter()
-            .zip(self.inputs.clone())
-            .collect::<Vec<(_, _)>>();
-        inputs.sort_by_key(|(k, _)| k.previous_output);
+    fn lex_order(&mut self) { self.lex_permute(); }
 
-        let mut outputs = tx
-            .output
-            .clone()
-            .into_iter()
-            .zip(self.outputs.clone())
-            .collect::<Vec<(_, _)>>();
-        outputs.lex_order();
+    fn is_lex_ordered(&self) -> bool { self.unsigned_tx.is_lex_ordered() }
+}
 
-        let (in_tx, in_map): (Vec<_>, Vec<_>) = inputs.into_iter().unzip();
-        let (out_tx, out_map): (Vec<_>, Vec<_>) = outputs.into_iter().unzip();
-        tx.input = in_tx;
-        tx.output = out_tx;
-        self.inputs = in_map;
-        self.outputs = out_map;
+impl LexPermute for PsbtV0 {
+    fn lex_permute(&mut self) -> LexPermutation {
+        let permutation = self.unsigned_tx.lex_permute();
+        // Input and output maps are moved together with the transaction
+        // inputs and outputs they belong to
+        permute(&mut self.inputs, &permutation.inputs);
+        permute(&mut self.outputs, &permutation.outputs);
+        permutation
     }
 }
 
 impl LexOrder for Vec<Input> {
     fn lex_order(&mut self) {
-        self.sort_by_key(|input| input.previous_outpoint);
+        self.sort_by(psbtin_cmp);
         for (index, input) in self.iter_mut().enumerate() {
             input.index = index;
         }
     }
+
+    fn is_lex_ordered(&self) -> bool { first_unordered(self, psbtin_cmp).is_none() }
 }
 
 impl LexOrder for Vec<Output> {
@@ -101,13 +183,103 @@ impl LexOrder for Vec<Output> {
             output.index = index;
         }
     }
+
+    fn is_lex_ordered(&self) -> bool { first_unordered(self, psbtout_cmp).is_none() }
 }
 
 impl LexOrder for Psbt {
-    fn lex_order(&mut self) {
-        self.inputs.lex_order();
-        self.outputs.lex_order();
+    fn lex_order(&mut self) { self.lex_permute(); }
+
+    fn is_lex_ordered(&self) -> bool { verify_psbt_lex_order(self).is_ok() }
+}
+
+impl LexPermute for Psbt {
+    fn lex_permute(&mut self) -> LexPermutation {
+        let permutation = LexPermutation {
+            inputs: sort_permutation(&mut self.inputs, psbtin_cmp),
+            outputs: sort_permutation(&mut self.outputs, psbtout_cmp),
+        };
+        for (index, input) in self.inputs.iter_mut().enumerate() {
+            input.index = index;
+        }
+        for (index, output) in self.outputs.iter_mut().enumerate() {
+            output.index = index;
+        }
+        permutation
+    }
+}
+
+/// Verifies that transaction inputs and outputs follow lexicographic (BIP-69)
+/// ordering, reporting the first input or output violating it.
+pub fn verify_tx_lex_order(tx: &Transaction) -> Result<(), LexOrderError> {
+    if let Some(index) = first_unordered(&tx.input, txin_cmp) {
+        return Err(LexOrderError::Input(index));
     }
+    if let Some(index) = first_unordered(&tx.output, txout_cmp) {
+        return Err(LexOrderError::Output(index));
+    }
+    Ok(())
+}
+
+/// Verifies that PSBT inputs and outputs follow lexicographic (BIP-69)
+/// ordering, reporting the first input or output violating it. Useful for
+/// checking PSBTs provided by counterparties in protocols requiring the
+/// ordering.
+pub fn verify_psbt_lex_order(psbt: &Psbt) -> Result<(), LexOrderError> {
+    if let Some(index) = first_unordered(&psbt.inputs, psbtin_cmp) {
+        return Err(LexOrderError::Input(index));
+    }
+    if let Some(index) = first_unordered(&psbt.outputs, psbtout_cmp) {
+        return Err(LexOrderError::Output(index));
+    }
+    Ok(())
+}
+
+/// Returns index of the first item which must precede its predecessor.
+fn first_unordered<T>(items: &[T], cmp: impl Fn(&T, &T) -> Ordering) -> Option<usize> {
+    items
+        .windows(2)
+        .position(|pair| cmp(&pair[0], &pair[1]) == Ordering::Greater)
+        .map(|pos| pos + 1)
+}
+
+/// Stable-sorts the items, returning new index of each item indexed by its
+/// original position.
+fn sort_permutation<T>(items: &mut Vec<T>, cmp: impl Fn(&T, &T) -> Ordering) -> Vec<usize> {
+    let mut indexed = items.drain(..).enumerate().collect::<Vec<_>>();
+    indexed.sort_by(|(_, a), (_, b)| cmp(a, b));
+    let mut permutation = vec![0; indexed.len()];
+    for (new, (old, item)) in indexed.into_iter().enumerate() {
+        permutation[old] = new;
+        items.push(item);
+    }
+    permutation
+}
+
+/// Moves items to the new positions given by the permutation. Items not
+/// covered by the permutation (which happens only if the PSBT has more maps
+/// than the transaction has inputs or outputs) are left after the permuted
+/// ones in their original order.
+fn permute<T>(items: &mut Vec<T>, permutation: &[usize]) {
+    let mut indexed = items.drain(..).enumerate().collect::<Vec<_>>();
+    indexed.sort_by_key(|(old, _)| permutation.get(*old).copied().unwrap_or(usize::MAX));
+    items.extend(indexed.into_iter().map(|(_, item)| item));
+}
+
+/// Compares outpoints according to BIP-69: transaction ids are compared as
+/// byte strings in their reversed (hex display) byte order, which differs from
+/// the `Ord` implementation of [`bitcoin::Txid`] comparing them in the internal
+/// byte order; output numbers are compared as integers.
+fn outpoint_cmp(left: &OutPoint, right: &OutPoint) -> Ordering {
+    left.txid[..]
+        .iter()
+        .rev()
+        .cmp(right.txid[..].iter().rev())
+        .then(left.vout.cmp(&right.vout))
+}
+
+fn txin_cmp(left: &TxIn, right: &TxIn) -> Ordering {
+    outpoint_cmp(&left.previous_output, &right.previous_output)
 }
 
 fn txout_cmp(left: &TxOut, right: &TxOut) -> Ordering {
@@ -118,6 +290,10 @@ fn txout_cmp(left: &TxOut, right: &TxOut) -> Ordering {
     }
 }
 
+fn psbtin_cmp(left: &Input, right: &Input) -> Ordering {
+    outpoint_cmp(&left.previous_outpoint, &right.previous_outpoint)
+}
+
 fn psbtout_cmp(left: &Output, right: &Output) -> Ordering {
     match (left.amount, right.amount) {
         (l, r) if l < r => Ordering::Less,
@@ -125,3 +301,281 @@ fn psbtout_cmp(left: &Output, right: &Output) -> Ordering {
         _ => left.script.cmp(&right.script),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::hex::ToHex;
+    use bitcoin::hashes::Hash;
+    use bitcoin::psbt::raw;
+    use bitcoin::{PackedLockTime, Script, Txid};
+
+    use super::*;
+    use crate::PsbtVersion;
+
+    /// Generates all permutations of the items using Heap's algorithm
+    fn permutations<T: Clone>(items: &[T]) -> Vec<Vec<T>> {
+        fn generate<T: Clone>(k: usize, items: &mut Vec<T>, acc: &mut Vec<Vec<T>>) {
+            if k <= 1 {
+                acc.push(items.clone());
+                return;
+            }
+            generate(k - 1, items, acc);
+            for i in 0..k - 1 {
+                if k % 2 == 0 {
+                    items.swap(i, k - 1);
+                } else {
+                    items.swap(0, k - 1);
+                }
+                generate(k - 1, items, acc);
+            }
+        }
+
+        let mut acc = vec![];
+        generate(items.len(), &mut items.to_vec(), &mut acc);
+        acc
+    }
+
+    /// Reference BIP-69 input ordering: previous transaction hashes are
+    /// compared in their hex (reversed byte order) representation, output
+    /// numbers as integers.
+    fn reference_inputs(inputs: &[TxIn]) -> Vec<TxIn> {
+        let mut inputs = inputs.to_vec();
+        inputs.sort_by_key(|txin| {
+            (
+                txin.previous_output.txid.to_string(),
+                txin.previous_output.vout,
+            )
+        });
+        inputs
+    }
+
+    /// Reference BIP-69 output ordering: amounts are compared as integers,
+    /// `scriptPubkey`s as hex strings.
+    fn reference_outputs(outputs: &[TxOut]) -> Vec<TxOut> {
+        let mut outputs = outputs.to_vec();
+        outputs.sort_by_key(|txout| (txout.value, txout.script_pubkey.as_bytes().to_hex()));
+        outputs
+    }
+
+    fn txid(pos: usize, byte: u8) -> Txid {
+        let mut inner = [0u8; 32];
+        inner[pos] = byte;
+        Txid::from_inner(inner)
+    }
+
+    /// Inputs covering BIP-69 edge cases: transaction ids which order
+    /// differently in internal and reversed byte order, and equal transaction
+    /// ids with output numbers which order differently as integers and as
+    /// little-endian bytes.
+    fn inputs() -> Vec<TxIn> {
+        [
+            (txid(0, 0x01), 0),
+            (txid(0, 0x01), 1),
+            (txid(0, 0x01), 256),
+            (txid(31, 0x02), 0),
+            (txid(31, 0x02), u32::MAX),
+            (txid(0, 0x00), 0),
+        ]
+        .into_iter()
+        .map(|(txid, vout)| TxIn {
+            previous_output: OutPoint::new(txid, vout),
+            ..TxIn::default()
+        })
+        .collect()
+    }
+
+    /// Outputs covering BIP-69 edge cases: amounts which order differently
+    /// as integers and as little-endian bytes, and equal amounts with scripts
+    /// being prefixes of each other.
+    fn outputs() -> Vec<TxOut> {
+        [
+            (255, vec![]),
+            (256, vec![]),
+            (1000, vec![]),
+            (1000, vec![0x51]),
+            (1000, vec![0x51, 0x00]),
+            (1000, vec![0x00, 0x51]),
+        ]
+        .into_iter()
+        .map(|(value, script)| TxOut {
+            value,
+            script_pubkey: Script::from(script),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn lex_order_verification() {
+        let txin = |vout| TxIn {
+            previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), vout),
+            ..TxIn::default()
+        };
+        let txout = |value| TxOut {
+            value,
+            script_pubkey: Script::new(),
+        };
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![txin(1), txin(0)],
+            output: vec![txout(1000), txout(2000)],
+        };
+        assert_eq!(verify_tx_lex_order(&tx), Err(LexOrderError::Input(1)));
+        assert!(!tx.is_lex_ordered());
+
+        tx.input.swap(0, 1);
+        tx.output.swap(0, 1);
+        assert_eq!(verify_tx_lex_order(&tx), Err(LexOrderError::Output(1)));
+
+        tx.lex_order();
+        assert!(tx.is_lex_ordered());
+        assert_eq!(verify_tx_lex_order(&tx), Ok(()));
+    }
+
+    #[test]
+    fn bip69_txid_byte_order() {
+        // Internal byte order of `Txid` is the reverse of BIP-69 order
+        let first = txid(0, 0x01);
+        let second = txid(31, 0x02);
+        assert!(first.to_string() < second.to_string());
+        assert!(first > second);
+
+        let inputs = vec![OutPoint::new(second, 0), OutPoint::new(first, 0)]
+            .into_iter()
+            .map(|previous_output| TxIn {
+                previous_output,
+                ..TxIn::default()
+            })
+            .collect::<Vec<_>>();
+        assert!(!inputs.is_lex_ordered());
+        let ordered = inputs.lex_ordered();
+        assert_eq!(ordered[0].previous_output.txid, first);
+    }
+
+    #[test]
+    fn bip69_inputs_exhaustive() {
+        let expected = reference_inputs(&inputs());
+        for inputs in permutations(&inputs()) {
+            assert_eq!(inputs.is_lex_ordered(), inputs == expected);
+            let ordered = inputs.lex_ordered();
+            assert!(ordered.is_lex_ordered());
+            assert_eq!(ordered, expected);
+        }
+    }
+
+    #[test]
+    fn bip69_outputs_exhaustive() {
+        let expected = reference_outputs(&outputs());
+        for outputs in permutations(&outputs()) {
+            assert_eq!(outputs.is_lex_ordered(), outputs == expected);
+            let ordered = outputs.lex_ordered();
+            assert!(ordered.is_lex_ordered());
+            assert_eq!(ordered, expected);
+        }
+
+        let duplicates = vec![outputs()[3].clone(), outputs()[3].clone()];
+        assert!(duplicates.is_lex_ordered());
+    }
+
+    #[test]
+    fn bip69_psbt_exhaustive() {
+        let expected_inputs = reference_inputs(&inputs());
+        let expected_outputs = reference_outputs(&outputs());
+        for (input, output) in permutations(&inputs())
+            .into_iter()
+            .zip(permutations(&outputs()))
+        {
+            let tx = Transaction {
+                version: 2,
+                lock_time: PackedLockTime(0),
+                input,
+                output,
+            };
+
+            let ordered = tx.clone().lex_ordered();
+            assert_eq!(verify_tx_lex_order(&ordered), Ok(()));
+            assert_eq!(ordered.input, expected_inputs);
+            assert_eq!(ordered.output, expected_outputs);
+
+            let psbt = PsbtV0::from_unsigned_tx(tx.clone()).unwrap().lex_ordered();
+            assert!(psbt.is_lex_ordered());
+            assert_eq!(psbt.unsigned_tx, ordered);
+
+            let psbt = Psbt::with(tx, PsbtVersion::V0).unwrap().lex_ordered();
+            assert_eq!(verify_psbt_lex_order(&psbt), Ok(()));
+            let unsigned_tx = psbt.to_unsigned_tx();
+            for (index, input) in psbt.inputs.iter().enumerate() {
+                assert_eq!(input.index, index);
+                assert_eq!(
+                    input.previous_outpoint,
+                    expected_inputs[index].previous_output
+                );
+            }
+            for (index, output) in psbt.outputs.iter().enumerate() {
+                assert_eq!(output.index, index);
+            }
+            assert_eq!(unsigned_tx.output, expected_outputs);
+        }
+    }
+
+    #[test]
+    fn lex_permutation() {
+        // Tags PSBT maps with their original position
+        let tag = |pos: usize| {
+            let key = raw::Key {
+                type_value: 0xFC,
+                key: vec![pos as u8],
+            };
+            bmap! { key => vec![] }
+        };
+        let mut outputs = outputs();
+        // Outputs with equal sorting keys must keep their relative order
+        outputs.push(outputs[3].clone());
+        outputs.reverse();
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: inputs(),
+            output: outputs.clone(),
+        };
+
+        let mut ordered = tx.clone();
+        let permutation = ordered.lex_permute();
+        assert!(!permutation.is_identity());
+        for (old, txout) in outputs.iter().enumerate() {
+            let new = permutation.output_index(old).unwrap();
+            assert_eq!(&ordered.output[new], txout);
+        }
+        assert_eq!(permutation.output_index(outputs.len()), None);
+        // The duplicated outputs were at positions 0 and 3 after reversal
+        assert!(permutation.outputs[0] < permutation.outputs[3]);
+        assert!(ordered.clone().lex_permute().is_identity());
+
+        let mut psbt = PsbtV0::from_unsigned_tx(tx.clone()).unwrap();
+        for (pos, input) in psbt.inputs.iter_mut().enumerate() {
+            input.unknown = tag(pos);
+        }
+        for (pos, output) in psbt.outputs.iter_mut().enumerate() {
+            output.unknown = tag(pos);
+        }
+        assert_eq!(psbt.lex_permute(), permutation);
+        assert_eq!(psbt.unsigned_tx, ordered);
+        for (old, new) in permutation.inputs.iter().enumerate() {
+            assert_eq!(psbt.inputs[*new].unknown, tag(old));
+        }
+        for (old, new) in permutation.outputs.iter().enumerate() {
+            assert_eq!(psbt.outputs[*new].unknown, tag(old));
+        }
+
+        let mut psbt = Psbt::with(tx, PsbtVersion::V0).unwrap();
+        for (pos, output) in psbt.outputs.iter_mut().enumerate() {
+            output.unknown = tag(pos);
+        }
+        assert_eq!(psbt.lex_permute(), permutation);
+        assert_eq!(psbt.to_unsigned_tx().output, ordered.output);
+        for (old, new) in permutation.outputs.iter().enumerate() {
+            assert_eq!(psbt.outputs[*new].index, *new);
+            assert_eq!(psbt.outputs[*new].unknown, tag(old));
+        }
+    }
+}
@@ -20,9 +20,10 @@
 //! - advanced signer, supporting pre-segwit, bare and nested segwit v0, taproot
 //!   key and path spendings, different forms of tweaks & commitments, all
 //!   sighash types ([`sign`]);
-//! - commitment-related features: managing tapret-, P2C and S2C-related
-//!   proprietary keys;
-//! - utility methods for fee computing, lexicographic reordering etc;
+//! - commitment-related features: managing tapret-, opret-, LNPBP-4-, P2C and
+//!   S2C-related proprietary keys;
+//! - utility methods for fee computing and analysis, lexicographic reordering
+//!   etc;
 //! - command-line utility for editing PSBT data (WIP).
 
 #[macro_use]
@@ -35,34 +36,94 @@ extern crate strict_encoding;
 #[cfg(feature = "miniscript")]
 extern crate miniscript_crate as miniscript;
 
+pub mod analysis;
+mod annex;
+pub mod audit;
+pub mod bip47;
+pub mod commit;
+#[cfg(feature = "compat")]
+pub mod compat;
+pub mod coordination;
+pub mod diff;
 mod errors;
+#[cfg(feature = "miniscript")]
+pub mod explain;
+#[cfg(feature = "arbitrary")]
+mod fuzzing;
 mod global;
+#[cfg(feature = "descriptors")]
+pub mod hlc;
 mod input;
 mod output;
+pub mod ownership;
 pub mod p2c;
+pub mod payjoin;
+pub mod s2c;
+#[cfg(feature = "miniscript")]
+pub mod signmessage;
+pub mod tap_hidden;
+#[cfg(feature = "descriptors")]
+pub mod template;
 
 #[cfg(feature = "construct")]
 pub mod construct;
 pub mod lex_order;
 mod proprietary;
+pub mod reserves;
 #[cfg(feature = "sign")]
 pub mod sign;
+#[cfg(feature = "construct")]
+pub mod vaults;
 
+pub use analysis::{FeeAnalysis, InputAnalysis, SatisfactionSize};
+pub use annex::{Annex, AnnexError};
+pub use audit::{AuditError, InputAudit, SigAudit, SigSource};
 pub use bitcoin::psbt::raw::ProprietaryKey;
 pub use bitcoin::psbt::{raw, serialize, Error, PsbtSighashType};
+pub use coordination::{CoordinationError, InputStatus, SessionStatus, SigningSession};
+pub use diff::{DiffError, PsbtDiff};
 pub use errors::{FeeError, InputMatchError, TxError, TxinError};
-pub use global::{Psbt, PsbtParseError};
+pub use global::{Psbt, PsbtEncoding, PsbtParseError, PSBT_MAGIC};
+#[cfg(feature = "descriptors")]
+pub use hlc::HlcFinalizeError;
 pub use input::Input;
 pub use output::Output;
+#[cfg(feature = "construct")]
+pub use template::LowerError;
+#[cfg(feature = "descriptors")]
+pub use template::{FeePolicy, LockTimePolicy, TemplateError, TxTemplate};
 pub(crate) mod v0 {
     pub use bitcoin::psbt::{
         Input as InputV0, Output as OutputV0, PartiallySignedTransaction as PsbtV0,
     };
 }
-pub use p2c::{PSBT_IN_P2C_TWEAK, PSBT_P2C_PREFIX};
+pub use commit::lnpbp4::{
+    PSBT_LNPBP4_PREFIX, PSBT_OUT_LNPBP4_ENTROPY, PSBT_OUT_LNPBP4_MESSAGE,
+    PSBT_OUT_LNPBP4_MIN_TREE_DEPTH,
+};
+pub use commit::opret::{PSBT_OPRET_PREFIX, PSBT_OUT_OPRET_COMMITMENT, PSBT_OUT_OPRET_HOST};
+pub use commit::tapret::{
+    PSBT_OUT_TAPRET_COMMITMENT, PSBT_OUT_TAPRET_HOST, PSBT_OUT_TAPRET_PROOF, PSBT_TAPRET_PREFIX,
+};
+#[cfg(feature = "compat")]
+pub use compat::{CompatError, FromLatest, ToLatest};
+pub use ownership::{
+    OwnershipError, PSBT_OUT_OWNERSHIP_ACCOUNT, PSBT_OUT_OWNERSHIP_CHANGE, PSBT_OWNERSHIP_PREFIX,
+};
+pub use p2c::{P2cMaster, P2cTweakChain, PSBT_IN_P2C_TWEAK, PSBT_P2C_PREFIX};
+pub use payjoin::{is_secure_endpoint, PayjoinError, PayjoinInputType, PayjoinParams};
 pub use proprietary::{
     ProprietaryKeyDescriptor, ProprietaryKeyError, ProprietaryKeyLocation, ProprietaryKeyType,
 };
+pub use reserves::ReservesError;
+pub use s2c::{PSBT_IN_S2C_COMMITMENT, PSBT_IN_S2C_PROOF, PSBT_S2C_PREFIX};
+#[cfg(feature = "miniscript")]
+pub use signmessage::{MessageError, MessageSignature};
+pub use tap_hidden::{
+    TapHiddenError, PSBT_IN_TAPHIDDEN_LEAF, PSBT_IN_TAPHIDDEN_PROOF, PSBT_TAPHIDDEN_PREFIX,
+};
+#[cfg(feature = "construct")]
+pub use vaults::{CancelStore, Vault, VaultError};
 
 /// Version of the PSBT (V0 stands for BIP174-defined version; V2 - for BIP370).
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default)]
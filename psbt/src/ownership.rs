@@ -0,0 +1,241 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Processing proprietary PSBT keys carrying output ownership hints for the
+//! signing devices.
+//!
+//! Derivation paths of the output keys do not tell external signers whether an
+//! output returns funds to the wallet. PSBT constructor explicitly marks change
+//! outputs and may name the account they belong to; before omitting such an
+//! output from the user confirmation the signer must check that it truly
+//! derives from the wallet descriptor with [`Psbt::verify_change_outputs`].
+
+#[cfg(any(feature = "construct", feature = "sign"))]
+use amplify::Wrapper;
+#[cfg(any(feature = "construct", feature = "sign"))]
+use bitcoin::secp256k1::{Secp256k1, Verification};
+#[cfg(any(feature = "construct", feature = "sign"))]
+use bitcoin::util::bip32::KeySource;
+#[cfg(any(feature = "construct", feature = "sign"))]
+use bitcoin_hd::{DerivationAccount, UnhardenedIndex};
+#[cfg(any(feature = "construct", feature = "sign"))]
+use descriptors::derive::Descriptor as _;
+#[cfg(any(feature = "construct", feature = "sign"))]
+use miniscript::Descriptor;
+
+use crate::raw::ProprietaryKey;
+use crate::Output;
+#[cfg(any(feature = "construct", feature = "sign"))]
+use crate::Psbt;
+
+/// Prefix of the proprietary keys for output ownership hints
+pub const PSBT_OWNERSHIP_PREFIX: &[u8] = b"OWNERSHIP";
+/// Proprietary key subtype flagging the output as a change returning funds to
+/// the wallet. Both key data and value are empty.
+pub const PSBT_OUT_OWNERSHIP_CHANGE: u8 = 0;
+/// Proprietary key subtype for the name of the wallet account owning the
+/// output. Key data is empty; value is UTF-8 account name.
+pub const PSBT_OUT_OWNERSHIP_ACCOUNT: u8 = 1;
+
+/// Errors verifying output ownership claims
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum OwnershipError {
+    /// output #{0} is claimed to be a change, but it does not derive from any
+    /// of the wallet descriptors
+    ForeignChange(usize),
+}
+
+impl Output {
+    fn ownership_key(subtype: u8) -> ProprietaryKey {
+        ProprietaryKey {
+            prefix: PSBT_OWNERSHIP_PREFIX.to_vec(),
+            subtype,
+            key: vec![],
+        }
+    }
+
+    /// Flags the output as a change returning funds to the wallet
+    pub fn set_change_flag(&mut self) {
+        self.proprietary
+            .insert(Output::ownership_key(PSBT_OUT_OWNERSHIP_CHANGE), vec![]);
+    }
+
+    /// Detects whether the output is claimed to be a change by the PSBT
+    /// constructor. The claim must be verified with
+    /// [`Psbt::verify_change_outputs`] before relying on it.
+    pub fn has_change_flag(&self) -> bool {
+        self.proprietary
+            .contains_key(&Output::ownership_key(PSBT_OUT_OWNERSHIP_CHANGE))
+    }
+
+    /// Sets name of the wallet account owning the output
+    pub fn set_account_name(&mut self, name: &str) {
+        let key = Output::ownership_key(PSBT_OUT_OWNERSHIP_ACCOUNT);
+        self.proprietary.insert(key, name.as_bytes().to_vec());
+    }
+
+    /// Returns name of the wallet account owning the output, if any. Names
+    /// which are not valid UTF-8 strings are ignored.
+    pub fn account_name(&self) -> Option<String> {
+        self.proprietary
+            .get(&Output::ownership_key(PSBT_OUT_OWNERSHIP_ACCOUNT))
+            .and_then(|value| String::from_utf8(value.clone()).ok())
+    }
+
+    /// Checks that the output `scriptPubkey` derives from the descriptor at
+    /// the terminal path of any of the output keys.
+    #[cfg(any(feature = "construct", feature = "sign"))]
+    pub(crate) fn derives_from<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        descriptor: &Descriptor<DerivationAccount>,
+    ) -> bool {
+        let pattern_len = match descriptor.derive_pattern_len() {
+            Ok(len) => len,
+            Err(_) => return false,
+        };
+        let terminal = |(_, path): &KeySource| -> Option<Vec<UnhardenedIndex>> {
+            let path = path.as_ref();
+            let offset = path.len().checked_sub(pattern_len)?;
+            path[offset..]
+                .iter()
+                .map(|child| UnhardenedIndex::try_from(*child).ok())
+                .collect()
+        };
+        let script = self.script.as_inner();
+        self.bip32_derivation
+            .values()
+            .chain(self.tap_key_origins.values().map(|(_, origin)| origin))
+            .filter_map(terminal)
+            .any(|terminal| {
+                let derived = match descriptor {
+                    Descriptor::Tr(_) => descriptor.script_pubkey_tr(secp, &terminal),
+                    _ => descriptor.script_pubkey_pretr(secp, &terminal),
+                };
+                matches!(derived, Ok(derived) if &derived == script)
+            })
+    }
+}
+
+impl Psbt {
+    /// Flags outputs detected as change by their key derivation paths (see
+    /// [`Output::is_change`]) and names the account owning them, if the name
+    /// is given.
+    ///
+    /// # Returns
+    ///
+    /// Number of flagged outputs.
+    pub fn set_change_flags(&mut self, account_name: Option<&str>) -> usize {
+        let mut count = 0usize;
+        for output in self.outputs.iter_mut().filter(|output| output.is_change()) {
+            output.set_change_flag();
+            if let Some(name) = account_name {
+                output.set_account_name(name);
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Verifies that all outputs flagged as change derive from one of the
+    /// wallet `descriptors`, such that signer may omit them from the user
+    /// confirmation.
+    ///
+    /// # Returns
+    ///
+    /// Indexes of the verified change outputs, or an error for the first
+    /// output which is flagged as a change but does not belong to the wallet.
+    #[cfg(any(feature = "construct", feature = "sign"))]
+    pub fn verify_change_outputs<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        descriptors: &[Descriptor<DerivationAccount>],
+    ) -> Result<Vec<usize>, OwnershipError> {
+        let mut verified = vec![];
+        for (index, output) in self.outputs.iter().enumerate() {
+            if !output.has_change_flag() {
+                continue;
+            }
+            if !descriptors
+                .iter()
+                .any(|descriptor| output.derives_from(secp, descriptor))
+            {
+                return Err(OwnershipError::ForeignChange(index));
+            }
+            verified.push(index);
+        }
+        Ok(verified)
+    }
+}
+
+#[cfg(all(test, feature = "construct", feature = "sign"))]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::SECP256K1;
+    use bitcoin::{OutPoint, PackedLockTime, Script, Transaction, TxOut, WPubkeyHash};
+    use bitcoin_hd::{DerivationSubpath, SegmentIndexes};
+    use bitcoin_scripts::PubkeyScript;
+    use descriptors::InputDescriptor;
+
+    use super::*;
+
+    #[test]
+    fn change_verification() {
+        let descriptor = Descriptor::<DerivationAccount>::from_str(
+            "wpkh([d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqF\
+             JPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/*/*)",
+        )
+        .unwrap();
+        let terminal = DerivationSubpath::from(&[UnhardenedIndex::zero(); 2][..]);
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: descriptor
+                    .script_pubkey_pretr(SECP256K1, &terminal)
+                    .unwrap(),
+            }],
+        };
+        let txid = funding.txid();
+        let resolver = bmap! { txid => funding };
+        let inputs = [InputDescriptor::with(OutPoint::new(txid, 0), terminal)];
+        let payee = Script::new_v0_p2wpkh(&WPubkeyHash::hash(b"payee"));
+        let outputs = [(PubkeyScript::from(payee), 50_000)];
+
+        let mut psbt =
+            Psbt::construct(&descriptor, &inputs, &outputs, 5u8, 1_000, &resolver).unwrap();
+        assert!(!psbt.outputs[0].has_change_flag());
+        assert!(psbt.outputs[1].has_change_flag());
+        assert_eq!(psbt.outputs[1].account_name(), None);
+        let verified = psbt.verify_change_outputs(SECP256K1, &[descriptor.clone()]);
+        assert_eq!(verified, Ok(vec![1]));
+
+        assert_eq!(psbt.set_change_flags(Some("savings")), 1);
+        assert_eq!(psbt.outputs[1].account_name().as_deref(), Some("savings"));
+
+        // Payee output claimed to be a change
+        psbt.outputs[0].set_change_flag();
+        let verified = psbt.verify_change_outputs(SECP256K1, &[descriptor.clone()]);
+        assert_eq!(verified, Err(OwnershipError::ForeignChange(0)));
+
+        // Change output with derivation paths pointing to the wallet, but paying
+        // to a foreign script
+        psbt.outputs[0].proprietary.clear();
+        psbt.outputs[1].script = Script::new_v0_p2wpkh(&WPubkeyHash::hash(b"thief")).into();
+        let verified = psbt.verify_change_outputs(SECP256K1, &[descriptor]);
+        assert_eq!(verified, Err(OwnershipError::ForeignChange(1)));
+    }
+}
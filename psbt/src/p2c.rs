@@ -12,16 +12,26 @@
 // TODO: Relocate to BP DBC library
 
 //! Processing proprietary PSBT keys related to pay-to-contract (P2C)
-//! commitments.
+//! commitments and deterministic derivation of P2C tweak series.
+//!
+//! Sequential contracts with the same counterparty may use tweaks from a
+//! [`P2cTweakChain`], derived from a [`P2cMaster`] secret and the counterparty
+//! public key. This way tweaks for `PSBT_IN_P2C_TWEAK` keys can always be
+//! re-derived from the master secret, without storing each of them.
 
-use amplify::Slice32;
+use std::ops::Range;
+
+use amplify::{Slice32, Wrapper};
+use bitcoin::hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
 use bitcoin::secp256k1;
-use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::{PublicKey, Verification};
 
 use crate::raw::ProprietaryKey;
 use crate::Input;
 
+/// Proprietary key prefix used for P2C-related PSBT keys
 pub const PSBT_P2C_PREFIX: &[u8] = b"P2C";
+/// Proprietary key subtype for P2C tweak applied to an input public key
 pub const PSBT_IN_P2C_TWEAK: u8 = 0;
 
 impl Input {
@@ -71,3 +81,138 @@ impl Input {
         )
     }
 }
+
+/// Contract master secret from which P2C tweak chains for all counterparties
+/// are derived.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, From)]
+pub struct P2cMaster(Slice32);
+
+impl P2cMaster {
+    /// Constructs contract master from a secret value
+    #[inline]
+    pub fn with(secret: Slice32) -> P2cMaster { P2cMaster(secret) }
+
+    /// Returns contract master secret
+    #[inline]
+    pub fn secret(&self) -> Slice32 { self.0 }
+
+    /// Derives tweak chain for contracts with a given counterparty
+    pub fn chain(&self, counterparty: PublicKey) -> P2cTweakChain {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.0[..]);
+        engine.input(PSBT_P2C_PREFIX);
+        engine.input(&counterparty.serialize());
+        let seed = Hmac::<sha256::Hash>::from_engine(engine);
+        P2cTweakChain::with_seed(Slice32::from_inner(seed.into_inner()))
+    }
+}
+
+/// Deterministic series of P2C tweaks for sequential contracts with the same
+/// counterparty.
+///
+/// Tweak with index `i` is computed as `HMAC-SHA256(seed, i)`, where index is
+/// serialized as a 4-byte big-endian number.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct P2cTweakChain {
+    seed: Slice32,
+}
+
+impl P2cTweakChain {
+    /// Constructs tweak chain from the chain seed
+    #[inline]
+    pub fn with_seed(seed: Slice32) -> P2cTweakChain { P2cTweakChain { seed } }
+
+    /// Returns chain seed, which is sufficient to recover all chain tweaks
+    #[inline]
+    pub fn seed(&self) -> Slice32 { self.seed }
+
+    /// Computes tweak for the contract with a given index
+    pub fn tweak(&self, index: u32) -> Slice32 {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.seed[..]);
+        engine.input(&index.to_be_bytes());
+        Slice32::from_inner(Hmac::<sha256::Hash>::from_engine(engine).into_inner())
+    }
+
+    /// Enumerates tweaks for contracts within an index range
+    pub fn tweaks(&self, range: Range<u32>) -> impl Iterator<Item = (u32, Slice32)> + '_ {
+        range.map(|index| (index, self.tweak(index)))
+    }
+
+    /// Verifies that the tweak belongs to the chain at a given index
+    #[inline]
+    pub fn verify(&self, index: u32, tweak: Slice32) -> bool { self.tweak(index) == tweak }
+
+    /// Finds index of the tweak within the first `lookahead` chain tweaks
+    pub fn recover_index(&self, tweak: Slice32, lookahead: u32) -> Option<u32> {
+        self.tweaks(0..lookahead)
+            .find(|(_, t)| *t == tweak)
+            .map(|(index, _)| index)
+    }
+
+    /// Tweaks public key with the chain tweak at a given index
+    pub fn tweak_pubkey<C: Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        pubkey: PublicKey,
+        index: u32,
+    ) -> PublicKey {
+        let tweak = secp256k1::Scalar::from_be_bytes(self.tweak(index).into_inner())
+            .expect("negligible probability");
+        pubkey
+            .add_exp_tweak(secp, &tweak)
+            .expect("negligible probability")
+    }
+
+    /// Recovers index and tweak used to produce `tweaked` public key from the
+    /// original `pubkey`, searching within the first `lookahead` chain tweaks
+    pub fn recover_tweak<C: Verification>(
+        &self,
+        secp: &secp256k1::Secp256k1<C>,
+        pubkey: PublicKey,
+        tweaked: PublicKey,
+        lookahead: u32,
+    ) -> Option<(u32, Slice32)> {
+        (0..lookahead)
+            .find(|index| self.tweak_pubkey(secp, pubkey, *index) == tweaked)
+            .map(|index| (index, self.tweak(index)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+    use super::*;
+
+    #[test]
+    fn tweak_chain() {
+        let secp = Secp256k1::new();
+        let seckey = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&secp, &seckey);
+        let counterparty =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[0x22; 32]).unwrap());
+
+        let master = P2cMaster::with(Slice32::from_inner([0x33; 32]));
+        let chain = master.chain(counterparty);
+        assert_eq!(chain, master.chain(counterparty));
+        assert_ne!(chain, master.chain(pubkey));
+
+        let tweaks = chain.tweaks(0..10).collect::<Vec<_>>();
+        assert_eq!(tweaks.len(), 10);
+        assert_ne!(tweaks[0].1, tweaks[1].1);
+        assert!(chain.verify(4, tweaks[4].1));
+        assert!(!chain.verify(5, tweaks[4].1));
+        assert_eq!(chain.recover_index(tweaks[7].1, 10), Some(7));
+        assert_eq!(chain.recover_index(tweaks[7].1, 5), None);
+
+        let tweaked = chain.tweak_pubkey(&secp, pubkey, 3);
+        assert_eq!(
+            chain.recover_tweak(&secp, pubkey, tweaked, 10),
+            Some((3, tweaks[3].1))
+        );
+        assert_eq!(P2cTweakChain::with_seed(chain.seed()), chain);
+
+        let mut input = Input::default();
+        input.set_p2c_tweak(pubkey, chain.tweak(3));
+        assert_eq!(input.p2c_tweak(pubkey), Some(tweaks[3].1));
+    }
+}
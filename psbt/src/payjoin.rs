@@ -0,0 +1,881 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! BIP-78 payjoin protocol flows on top of [`Psbt`].
+//!
+//! Sender side:
+//! 1. creates, signs and finalizes the original PSBT paying to the receiver and
+//!    checks it with [`Psbt::check_payjoin_original`];
+//! 2. sends it to the receiver endpoint with [`PayjoinParams::to_query`]
+//!    parameters;
+//! 3. validates receiver proposal with [`Psbt::validate_payjoin_proposal`];
+//! 4. restores signing information for its own inputs with
+//!    [`Psbt::merge_payjoin_proposal`], signs and broadcasts the result.
+//!
+//! Sender must send the original PSBT only to the endpoints accepted by
+//! [`is_secure_endpoint`].
+//!
+//! Receiver side checks the original PSBT with
+//! [`Psbt::check_payjoin_original`], contributes its inputs with
+//! [`Psbt::payjoin_proposal`], signs and finalizes them and sends the proposal
+//! back to the sender.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use amplify::Wrapper;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::util::bip32::Fingerprint;
+use bitcoin::{consensus, OutPoint, Script};
+use bitcoin_scripts::PubkeyScript;
+
+use crate::{FeeError, Input, Psbt};
+
+/// Version of the payjoin protocol supported by the library
+pub const PAYJOIN_VERSION: u8 = 1;
+
+/// Errors violating BIP-78 payjoin protocol invariants
+#[derive(Clone, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum PayjoinError {
+    /// original PSBT input {0} is not finalized
+    OriginalNotFinalized(OutPoint),
+
+    /// PSBT input {0} does not provide information on the spent output
+    NoInputUtxo(OutPoint),
+
+    /// PSBT input {0} spends output of a type not supported by payjoin
+    UnsupportedInputType(OutPoint),
+
+    /// PSBT mixes inputs of different script types
+    MixedInputTypes,
+
+    /// PSBT mixes inputs with different sequence numbers
+    MixedSequence,
+
+    /// original PSBT has no output paying to the receiver
+    NoPayeeOutput,
+
+    /// additional fee output #{0} does not exist or is the receiver output
+    InvalidFeeOutput(usize),
+
+    /// payjoin proposal changes transaction version or lock time
+    TxParamsChanged,
+
+    /// payjoin proposal does not spend original input {0}
+    MissedSenderInput(OutPoint),
+
+    /// payjoin proposal changes sequence number of the sender input {0}
+    SequenceChanged(OutPoint),
+
+    /// sender input {0} in payjoin proposal contains signatures
+    SenderInputSigned(OutPoint),
+
+    /// receiver input {0} in payjoin proposal is not finalized
+    ReceiverInputNotFinalized(OutPoint),
+
+    /// receiver input {0} spends the same output as one of the other inputs
+    DuplicateInput(OutPoint),
+
+    /// receiver input {0} in payjoin proposal spends output owned by the
+    /// sender
+    ReceiverInputOwned(OutPoint),
+
+    /// payjoin proposal does not contain receiver inputs
+    NoReceiverInputs,
+
+    /// payjoin proposal output #{0} contains key derivation information
+    OutputKeyOrigins(usize),
+
+    /// payjoin proposal does not contain sender output `{0}`
+    MissedSenderOutput(PubkeyScript),
+
+    /// payjoin proposal changes amount of the sender output `{0}`
+    SenderOutputChanged(PubkeyScript),
+
+    /// payjoin proposal substitutes receiver output while the sender disabled
+    /// output substitution
+    OutputSubstituted,
+
+    /// payjoin proposal decreases receiver output amount while the sender
+    /// disabled output substitution
+    PayeeAmountDecreased,
+
+    /// payjoin proposal takes {0} sats of additional fee from the sender,
+    /// exceeding the allowed maximum of {1} sats
+    ContributionExceeded(u64, u64),
+
+    /// sender fee contribution of {0} sats exceeds fee increase of {1} sats
+    ContributionNotForFee(u64, u64),
+
+    /// sender fee contribution of {0} sats exceeds fee of {1} sats required
+    /// for the receiver inputs at the original PSBT feerate
+    ContributionOverpaysInputs(u64, u64),
+
+    /// payjoin proposal feerate of {0} sat/vbyte is below the minimal feerate
+    /// of {1} sat/vbyte
+    FeeRateTooLow(f32, f32),
+
+    /// receiver output amount is insufficient to pay {0} sats of the fee for
+    /// receiver inputs
+    InsufficientPayeeAmount(u64),
+
+    /// unable to compute PSBT fee. Details: {0}
+    #[from]
+    Fee(FeeError),
+}
+
+/// Type of the transaction input supported by payjoin protocol
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+pub enum PayjoinInputType {
+    /// Legacy P2PKH input
+    #[display("p2pkh")]
+    P2pkh,
+
+    /// P2WPKH input nested into P2SH
+    #[display("p2sh-p2wpkh")]
+    P2shP2wpkh,
+
+    /// Native segwit v0 P2WPKH input
+    #[display("p2wpkh")]
+    P2wpkh,
+
+    /// Taproot key path spending input
+    #[display("p2tr")]
+    P2tr,
+}
+
+impl PayjoinInputType {
+    /// Detects type of the PSBT input from the spent output and, for P2SH
+    /// outputs, from the redeem script or finalized `scriptSig`.
+    pub fn detect(input: &Input) -> Option<PayjoinInputType> {
+        let script_pubkey = &input.input_prevout().ok()?.script_pubkey;
+        if script_pubkey.is_p2pkh() {
+            Some(PayjoinInputType::P2pkh)
+        } else if script_pubkey.is_v0_p2wpkh() {
+            Some(PayjoinInputType::P2wpkh)
+        } else if script_pubkey.is_v1_p2tr() {
+            Some(PayjoinInputType::P2tr)
+        } else if script_pubkey.is_p2sh() {
+            let redeem_script = match (&input.redeem_script, &input.final_script_sig) {
+                (Some(redeem_script), _) => redeem_script.as_inner().clone(),
+                (None, Some(script_sig)) => match script_sig.as_inner().instructions().last() {
+                    Some(Ok(Instruction::PushBytes(data))) => Script::from(data.to_vec()),
+                    _ => return None,
+                },
+                (None, None) => return None,
+            };
+            redeem_script
+                .is_v0_p2wpkh()
+                .then_some(PayjoinInputType::P2shP2wpkh)
+        } else {
+            None
+        }
+    }
+
+    /// Returns expected virtual size of a signed input of this type, in
+    /// vbytes.
+    pub fn expected_vsize(self) -> u64 {
+        match self {
+            PayjoinInputType::P2pkh => 148,
+            PayjoinInputType::P2shP2wpkh => 91,
+            PayjoinInputType::P2wpkh => 68,
+            PayjoinInputType::P2tr => 58,
+        }
+    }
+}
+
+/// Checks whether the receiver endpoint URL satisfies BIP-78 requirements,
+/// i.e. uses HTTPS or points to a Tor onion service, so the original PSBT is
+/// not exposed to network observers.
+pub fn is_secure_endpoint(endpoint: &str) -> bool {
+    let (scheme, rest) = match endpoint.split_once("://") {
+        Some(split) => split,
+        None => return false,
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = host.split(':').next().unwrap_or_default();
+    match scheme.to_ascii_lowercase().as_str() {
+        "https" => !host.is_empty(),
+        "http" => host.to_ascii_lowercase().ends_with(".onion"),
+        _ => false,
+    }
+}
+
+/// Optional parameters of the payjoin request set by the sender
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct PayjoinParams {
+    /// Index of the sender output from which the receiver may deduct fee
+    /// for its inputs
+    pub additional_fee_output_index: Option<usize>,
+
+    /// Maximum amount of fee which may be deducted from the sender output
+    pub max_additional_fee_contribution: u64,
+
+    /// Prohibits the receiver from changing its output script and decreasing
+    /// the output amount
+    pub disable_output_substitution: bool,
+
+    /// Minimal feerate of the payjoin transaction, in sat/vbyte
+    pub min_fee_rate: Option<f32>,
+}
+
+impl PayjoinParams {
+    /// Formats parameters as a query string for the receiver endpoint URL
+    pub fn to_query(&self) -> String {
+        let mut query = format!("v={}", PAYJOIN_VERSION);
+        if let Some(index) = self.additional_fee_output_index {
+            query.push_str(&format!(
+                "&additionalfeeoutputindex={}&maxadditionalfeecontribution={}",
+                index, self.max_additional_fee_contribution
+            ));
+        }
+        if self.disable_output_substitution {
+            query.push_str("&disableoutputsubstitution=true");
+        }
+        if let Some(fee_rate) = self.min_fee_rate {
+            query.push_str(&format!("&minfeerate={}", fee_rate));
+        }
+        query
+    }
+
+    /// Parses parameters from the query string of the receiver endpoint URL,
+    /// ignoring unknown and malformed parameters as required by BIP-78
+    pub fn from_query(query: &str) -> PayjoinParams {
+        let mut params = PayjoinParams::default();
+        let mut max_contribution = None;
+        for (key, value) in query
+            .trim_start_matches('?')
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+        {
+            match key {
+                "additionalfeeoutputindex" => {
+                    params.additional_fee_output_index = value.parse().ok()
+                }
+                "maxadditionalfeecontribution" => max_contribution = value.parse().ok(),
+                "disableoutputsubstitution" => params.disable_output_substitution = value == "true",
+                "minfeerate" => params.min_fee_rate = value.parse().ok(),
+                _ => {}
+            }
+        }
+        // Fee output index is meaningful only together with the maximal fee
+        // contribution
+        match max_contribution {
+            Some(max) => params.max_additional_fee_contribution = max,
+            None => params.additional_fee_output_index = None,
+        }
+        params
+    }
+}
+
+impl Input {
+    fn has_signatures(&self) -> bool {
+        !self.partial_sigs.is_empty()
+            || self.tap_key_sig.is_some()
+            || !self.tap_script_sigs.is_empty()
+            || self.final_script_sig.is_some()
+            || self.final_script_witness.is_some()
+    }
+
+    fn is_finalized(&self) -> bool {
+        self.final_script_sig.is_some() || self.final_script_witness.is_some()
+    }
+
+    fn key_fingerprints(&self) -> impl Iterator<Item = Fingerprint> + '_ {
+        self.bip32_derivation
+            .values()
+            .map(|(fingerprint, _)| *fingerprint)
+            .chain(
+                self.tap_key_origins
+                    .values()
+                    .map(|(_, (fingerprint, _))| *fingerprint),
+            )
+    }
+
+    fn clear_signatures(&mut self) {
+        self.partial_sigs.clear();
+        self.tap_key_sig = None;
+        self.tap_script_sigs.clear();
+        self.final_script_sig = None;
+        self.final_script_witness = None;
+    }
+}
+
+impl Psbt {
+    fn payee_output(&self, payee: &PubkeyScript) -> Result<usize, PayjoinError> {
+        self.outputs
+            .iter()
+            .position(|output| &output.script == payee)
+            .ok_or(PayjoinError::NoPayeeOutput)
+    }
+
+    fn common_input_type<'inputs>(
+        inputs: impl IntoIterator<Item = &'inputs Input>,
+    ) -> Result<PayjoinInputType, PayjoinError> {
+        let mut common = None;
+        for input in inputs {
+            let ty = PayjoinInputType::detect(input)
+                .ok_or(PayjoinError::UnsupportedInputType(input.previous_outpoint))?;
+            match common {
+                None => common = Some(ty),
+                Some(common) if common != ty => return Err(PayjoinError::MixedInputTypes),
+                _ => {}
+            }
+        }
+        common.ok_or(PayjoinError::MixedInputTypes)
+    }
+
+    /// Fee paid by the sender per each additional receiver input, computed
+    /// from the original PSBT feerate
+    fn input_fee(&self, input_type: PayjoinInputType) -> Result<u64, PayjoinError> {
+        let vsize = self.extract_signed_tx().vsize() as u64;
+        Ok(self.fee()? * input_type.expected_vsize() / vsize)
+    }
+
+    /// Checks that the finalized original PSBT satisfies requirements of the
+    /// payjoin protocol: all inputs are finalized, of the same supported type
+    /// and with the same sequence number, the PSBT pays to the receiver and
+    /// the additional fee output does not belong to the receiver.
+    ///
+    /// Returns type of the original PSBT inputs.
+    pub fn check_payjoin_original(
+        &self,
+        payee: &PubkeyScript,
+        params: &PayjoinParams,
+    ) -> Result<PayjoinInputType, PayjoinError> {
+        for input in &self.inputs {
+            if !input.is_finalized() {
+                return Err(PayjoinError::OriginalNotFinalized(input.previous_outpoint));
+            }
+            input
+                .input_prevout()
+                .map_err(|_| PayjoinError::NoInputUtxo(input.previous_outpoint))?;
+        }
+        let input_type = Psbt::common_input_type(&self.inputs)?;
+        let seq_no = self.inputs[0].sequence_number;
+        if self
+            .inputs
+            .iter()
+            .any(|input| input.sequence_number != seq_no)
+        {
+            return Err(PayjoinError::MixedSequence);
+        }
+        let payee_index = self.payee_output(payee)?;
+        if let Some(index) = params.additional_fee_output_index {
+            if index >= self.outputs.len() || index == payee_index {
+                return Err(PayjoinError::InvalidFeeOutput(index));
+            }
+        }
+        self.fee()?;
+        Ok(input_type)
+    }
+
+    /// Receiver side: constructs payjoin proposal from the finalized original
+    /// PSBT by adding receiver inputs, which must provide information on the
+    /// spent outputs.
+    ///
+    /// The receiver output is increased by the amount of the receiver inputs,
+    /// minus `receiver_fee` required to pay for the added inputs. The fee is
+    /// taken from the sender additional fee output, as much as allowed by the
+    /// sender parameters, and from the receiver output for the rest.
+    ///
+    /// Signatures of the sender inputs, global extended keys and output key
+    /// origins are removed from the proposal; receiver inputs get the same
+    /// sequence number as the sender inputs and must be signed and finalized
+    /// by the receiver before sending the proposal back.
+    pub fn payjoin_proposal(
+        &self,
+        payee: &PubkeyScript,
+        receiver_inputs: impl IntoIterator<Item = Input>,
+        receiver_fee: u64,
+        params: &PayjoinParams,
+    ) -> Result<Psbt, PayjoinError> {
+        let input_type = self.check_payjoin_original(payee, params)?;
+        let payee_index = self.payee_output(payee)?;
+        let seq_no = self.inputs[0].sequence_number;
+
+        let mut proposal = self.clone();
+        proposal.xpub.clear();
+        for input in &mut proposal.inputs {
+            input.clear_signatures();
+        }
+        for output in &mut proposal.outputs {
+            output.bip32_derivation.clear();
+            output.tap_key_origins.clear();
+        }
+
+        let mut contribution = 0u64;
+        let mut added = 0u64;
+        for mut input in receiver_inputs {
+            if proposal
+                .inputs
+                .iter()
+                .any(|i| i.previous_outpoint == input.previous_outpoint)
+            {
+                return Err(PayjoinError::DuplicateInput(input.previous_outpoint));
+            }
+            contribution += input
+                .input_prevout()
+                .map_err(|_| PayjoinError::NoInputUtxo(input.previous_outpoint))?
+                .value;
+            input.index = proposal.inputs.len();
+            input.sequence_number = seq_no;
+            proposal.inputs.push(input);
+            added += 1;
+        }
+        if added == 0 {
+            return Err(PayjoinError::NoReceiverInputs);
+        }
+
+        let sender_fee = match params.additional_fee_output_index {
+            Some(index) => receiver_fee
+                .min(params.max_additional_fee_contribution)
+                .min(self.input_fee(input_type)? * added)
+                .min(proposal.outputs[index].amount),
+            None => 0,
+        };
+        if let Some(index) = params.additional_fee_output_index {
+            proposal.outputs[index].amount -= sender_fee;
+        }
+        let payee_output = &mut proposal.outputs[payee_index];
+        payee_output.amount = (payee_output.amount + contribution)
+            .checked_sub(receiver_fee - sender_fee)
+            .ok_or(PayjoinError::InsufficientPayeeAmount(
+                receiver_fee - sender_fee,
+            ))?;
+
+        Ok(proposal)
+    }
+
+    /// Sender side: validates payjoin proposal against the finalized original
+    /// PSBT according to the BIP-78 rules. Checks that the proposal does not
+    /// modify sender inputs and outputs (except decreasing the additional fee
+    /// output), that receiver inputs are finalized, of the same type as the
+    /// sender inputs and do not spend sender outputs, and that the fee taken
+    /// from the sender pays only for the receiver inputs within the limits set
+    /// by `params`.
+    ///
+    /// Receiver inputs are considered to be owned by the sender if they spend
+    /// outputs with the same `scriptPubkey` as the sender inputs or outputs,
+    /// or if they have key origins with the master key fingerprints used by
+    /// the sender inputs or global extended keys. Since finalization removes
+    /// key origins from the inputs, the original PSBT should have them
+    /// restored before the validation.
+    ///
+    /// Returns amount of the fee contributed by the sender.
+    pub fn validate_payjoin_proposal(
+        &self,
+        proposal: &Psbt,
+        payee: &PubkeyScript,
+        params: &PayjoinParams,
+    ) -> Result<u64, PayjoinError> {
+        let input_type = self.check_payjoin_original(payee, params)?;
+        let payee_index = self.payee_output(payee)?;
+
+        if proposal.tx_version != self.tx_version || proposal.lock_time() != self.lock_time() {
+            return Err(PayjoinError::TxParamsChanged);
+        }
+
+        // Proposal with the sender inputs information restored from the original PSBT
+        let original_inputs = self
+            .inputs
+            .iter()
+            .map(|input| (input.previous_outpoint, input))
+            .collect::<BTreeMap<_, _>>();
+        let sender_scripts = self
+            .inputs
+            .iter()
+            .filter_map(|input| input.input_prevout().ok())
+            .map(|txout| txout.script_pubkey.clone())
+            .chain(
+                self.outputs
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| *index != payee_index)
+                    .map(|(_, output)| output.script.to_inner()),
+            )
+            .collect::<BTreeSet<_>>();
+        let sender_fingerprints = self
+            .inputs
+            .iter()
+            .flat_map(Input::key_fingerprints)
+            .chain(self.xpub.values().map(|(fingerprint, _)| *fingerprint))
+            .collect::<BTreeSet<_>>();
+        let seq_no = self.inputs[0].sequence_number;
+        let mut restored = proposal.clone();
+        let mut receiver_inputs = vec![];
+        for input in &mut restored.inputs {
+            let outpoint = input.previous_outpoint;
+            match original_inputs.get(&outpoint) {
+                Some(original) => {
+                    if input.sequence_number != original.sequence_number {
+                        return Err(PayjoinError::SequenceChanged(outpoint));
+                    }
+                    if input.has_signatures() {
+                        return Err(PayjoinError::SenderInputSigned(outpoint));
+                    }
+                    input.witness_utxo = original.witness_utxo.clone();
+                    input.non_witness_utxo = original.non_witness_utxo.clone();
+                }
+                None => {
+                    if !input.is_finalized() {
+                        return Err(PayjoinError::ReceiverInputNotFinalized(outpoint));
+                    }
+                    if input.sequence_number != seq_no {
+                        return Err(PayjoinError::MixedSequence);
+                    }
+                    let prevout = input
+                        .input_prevout()
+                        .map_err(|_| PayjoinError::NoInputUtxo(outpoint))?;
+                    if sender_scripts.contains(&prevout.script_pubkey)
+                        || input
+                            .key_fingerprints()
+                            .any(|fingerprint| sender_fingerprints.contains(&fingerprint))
+                    {
+                        return Err(PayjoinError::ReceiverInputOwned(outpoint));
+                    }
+                    receiver_inputs.push(input.clone());
+                }
+            }
+        }
+        for outpoint in original_inputs.keys() {
+            if !restored
+                .inputs
+                .iter()
+                .any(|input| &input.previous_outpoint == outpoint)
+            {
+                return Err(PayjoinError::MissedSenderInput(*outpoint));
+            }
+        }
+        if receiver_inputs.is_empty() {
+            return Err(PayjoinError::NoReceiverInputs);
+        }
+        if Psbt::common_input_type(&receiver_inputs)? != input_type {
+            return Err(PayjoinError::MixedInputTypes);
+        }
+
+        for output in &proposal.outputs {
+            if !output.bip32_derivation.is_empty() || !output.tap_key_origins.is_empty() {
+                return Err(PayjoinError::OutputKeyOrigins(output.index));
+            }
+        }
+
+        // Outputs of the proposal, which are not yet matched to the original ones
+        let mut unmatched = proposal.outputs.iter().collect::<Vec<_>>();
+        let mut contribution = 0u64;
+        for (index, original) in self.outputs.iter().enumerate() {
+            if index == payee_index {
+                continue;
+            }
+            let pos = unmatched
+                .iter()
+                .position(|output| output.script == original.script)
+                .ok_or_else(|| PayjoinError::MissedSenderOutput(original.script.clone()))?;
+            let amount = unmatched.remove(pos).amount;
+            match (Some(index) == params.additional_fee_output_index, amount) {
+                (_, amount) if amount == original.amount => {}
+                (true, amount) if amount < original.amount => {
+                    contribution = original.amount - amount
+                }
+                _ => return Err(PayjoinError::SenderOutputChanged(original.script.clone())),
+            }
+        }
+        let payee_output = &self.outputs[payee_index];
+        match unmatched
+            .iter()
+            .find(|output| output.script == payee_output.script)
+        {
+            Some(output) if params.disable_output_substitution => {
+                if output.amount < payee_output.amount {
+                    return Err(PayjoinError::PayeeAmountDecreased);
+                }
+            }
+            None if params.disable_output_substitution => {
+                return Err(PayjoinError::OutputSubstituted);
+            }
+            _ => {}
+        }
+
+        if contribution > params.max_additional_fee_contribution {
+            return Err(PayjoinError::ContributionExceeded(
+                contribution,
+                params.max_additional_fee_contribution,
+            ));
+        }
+        let original_fee = self.fee()?;
+        let proposal_fee = restored.fee()?;
+        let fee_increase = proposal_fee.saturating_sub(original_fee);
+        if contribution > fee_increase {
+            return Err(PayjoinError::ContributionNotForFee(
+                contribution,
+                fee_increase,
+            ));
+        }
+        let max_input_fee = self.input_fee(input_type)? * receiver_inputs.len() as u64;
+        if contribution > max_input_fee {
+            return Err(PayjoinError::ContributionOverpaysInputs(
+                contribution,
+                max_input_fee,
+            ));
+        }
+
+        if let Some(min_fee_rate) = params.min_fee_rate {
+            let output_size = |psbt: &Psbt| -> u64 {
+                psbt.outputs
+                    .iter()
+                    .map(|output| consensus::serialize(&output.to_txout()).len() as u64)
+                    .sum()
+            };
+            let vsize = self.extract_signed_tx().vsize() as u64
+                + input_type.expected_vsize() * receiver_inputs.len() as u64
+                + output_size(proposal).saturating_sub(output_size(self));
+            let fee_rate = proposal_fee as f32 / vsize as f32;
+            if fee_rate < min_fee_rate {
+                return Err(PayjoinError::FeeRateTooLow(fee_rate, min_fee_rate));
+            }
+        }
+
+        Ok(contribution)
+    }
+
+    /// Sender side: prepares validated payjoin proposal for signing by
+    /// replacing sender inputs with their copies from `self`, which should be
+    /// the sender PSBT before signing, and restoring key derivation
+    /// information for the sender outputs.
+    pub fn merge_payjoin_proposal(&self, proposal: &Psbt) -> Psbt {
+        let mut merged = proposal.clone();
+        merged.xpub = self.xpub.clone();
+        for input in &mut merged.inputs {
+            if let Some(own) = self
+                .inputs
+                .iter()
+                .find(|own| own.previous_outpoint == input.previous_outpoint)
+            {
+                let index = input.index;
+                *input = own.clone();
+                input.index = index;
+                input.clear_signatures();
+            }
+        }
+        for output in &mut merged.outputs {
+            if let Some(own) = self.outputs.iter().find(|own| own.script == output.script) {
+                output.bip32_derivation = own.bip32_derivation.clone();
+                output.tap_key_origins = own.tap_key_origins.clone();
+                output.tap_internal_key = own.tap_internal_key;
+                output.tap_tree = own.tap_tree.clone();
+                output.redeem_script = own.redeem_script.clone();
+                output.witness_script = own.witness_script.clone();
+            }
+        }
+        merged
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+    use bitcoin::{PackedLockTime, Sequence, Transaction, TxIn, TxOut, Txid, WPubkeyHash, Witness};
+
+    use super::*;
+    use crate::PsbtVersion;
+
+    fn p2wpkh(tag: u8) -> Script { Script::new_v0_p2wpkh(&WPubkeyHash::hash(&[tag])) }
+
+    fn input(tag: u8, value: u64) -> Input {
+        let mut input = Input::new(0, TxIn {
+            previous_output: OutPoint::new(Txid::hash(&[tag]), 0),
+            script_sig: Script::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        })
+        .unwrap();
+        input.witness_utxo = Some(TxOut {
+            value,
+            script_pubkey: p2wpkh(tag),
+        });
+        input
+    }
+
+    fn finalize(input: &mut Input) {
+        input.final_script_witness = Some(Witness::from_vec(vec![vec![0x30; 72], vec![0x02; 33]]));
+    }
+
+    #[test]
+    fn payjoin_flow() {
+        let payee = PubkeyScript::from(p2wpkh(0xAA));
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![input(1, 0).to_unsigned_txin()],
+            output: vec![
+                TxOut {
+                    value: 50_000,
+                    script_pubkey: payee.to_inner(),
+                },
+                TxOut {
+                    value: 49_000,
+                    script_pubkey: p2wpkh(0xBB),
+                },
+            ],
+        };
+        let mut unsigned = Psbt::with(tx, PsbtVersion::V0).unwrap();
+        unsigned.inputs[0] = input(1, 100_000);
+        let mut original = unsigned.clone();
+        finalize(&mut original.inputs[0]);
+
+        let params = PayjoinParams {
+            additional_fee_output_index: Some(1),
+            max_additional_fee_contribution: 1_000,
+            disable_output_substitution: false,
+            min_fee_rate: Some(1.0),
+        };
+        assert_eq!(PayjoinParams::from_query(&params.to_query()), params);
+        assert!(matches!(
+            unsigned.check_payjoin_original(&payee, &params),
+            Err(PayjoinError::OriginalNotFinalized(_))
+        ));
+        assert_eq!(
+            original.check_payjoin_original(&payee, &params),
+            Ok(PayjoinInputType::P2wpkh)
+        );
+
+        let mut proposal = original
+            .payjoin_proposal(&payee, [input(2, 30_000)], 500, &params)
+            .unwrap();
+        assert!(!proposal.inputs[0].has_signatures());
+        assert_eq!(
+            proposal.inputs[1].sequence_number,
+            original.inputs[0].sequence_number
+        );
+        assert!(matches!(
+            original.validate_payjoin_proposal(&proposal, &payee, &params),
+            Err(PayjoinError::ReceiverInputNotFinalized(_))
+        ));
+        finalize(&mut proposal.inputs[1]);
+
+        let expected = original.fee().unwrap() * 68 / original.extract_signed_tx().vsize() as u64;
+        assert_eq!(
+            original.validate_payjoin_proposal(&proposal, &payee, &params),
+            Ok(expected)
+        );
+        assert_eq!(proposal.outputs[1].amount, 49_000 - expected);
+        assert_eq!(
+            proposal.outputs[0].amount,
+            50_000 + 30_000 - (500 - expected)
+        );
+
+        let strict = PayjoinParams {
+            max_additional_fee_contribution: 100,
+            ..params.clone()
+        };
+        assert_eq!(
+            original.validate_payjoin_proposal(&proposal, &payee, &strict),
+            Err(PayjoinError::ContributionExceeded(expected, 100))
+        );
+
+        let mut substituted = proposal.clone();
+        substituted.outputs[0].script = PubkeyScript::from(p2wpkh(0xCC));
+        assert!(original
+            .validate_payjoin_proposal(&substituted, &payee, &params)
+            .is_ok());
+        let no_substitution = PayjoinParams {
+            disable_output_substitution: true,
+            ..params
+        };
+        assert_eq!(
+            original.validate_payjoin_proposal(&substituted, &payee, &no_substitution),
+            Err(PayjoinError::OutputSubstituted)
+        );
+
+        let merged = unsigned.merge_payjoin_proposal(&proposal);
+        assert!(!merged.inputs[0].is_finalized());
+        assert!(merged.inputs[1].is_finalized());
+        assert_eq!(merged.inputs[1].index(), 1);
+
+        let mut changed = proposal.clone();
+        changed.outputs[1].amount = 49_000;
+        assert!(original
+            .validate_payjoin_proposal(&changed, &payee, &params)
+            .is_ok());
+        changed.outputs[1].amount = 49_001;
+        assert_eq!(
+            original.validate_payjoin_proposal(&changed, &payee, &params),
+            Err(PayjoinError::SenderOutputChanged(PubkeyScript::from(
+                p2wpkh(0xBB)
+            )))
+        );
+        let no_fee_output = PayjoinParams {
+            additional_fee_output_index: None,
+            max_additional_fee_contribution: 0,
+            ..params.clone()
+        };
+        assert_eq!(
+            original.validate_payjoin_proposal(&proposal, &payee, &no_fee_output),
+            Err(PayjoinError::SenderOutputChanged(PubkeyScript::from(
+                p2wpkh(0xBB)
+            )))
+        );
+
+        let mut owned = proposal.clone();
+        owned.inputs[1].witness_utxo.as_mut().unwrap().script_pubkey = p2wpkh(0xBB);
+        assert_eq!(
+            original.validate_payjoin_proposal(&owned, &payee, &params),
+            Err(PayjoinError::ReceiverInputOwned(
+                owned.inputs[1].previous_outpoint
+            ))
+        );
+
+        let pubkey = |byte| {
+            let seckey = bitcoin::secp256k1::SecretKey::from_slice(&[byte; 32]).unwrap();
+            bitcoin::secp256k1::PublicKey::from_secret_key(bitcoin::secp256k1::SECP256K1, &seckey)
+        };
+        let key_source = |fingerprint: [u8; 4]| {
+            (
+                Fingerprint::from(&fingerprint[..]),
+                bitcoin::util::bip32::DerivationPath::master(),
+            )
+        };
+        let mut owned = proposal;
+        owned.inputs[1]
+            .bip32_derivation
+            .insert(pubkey(2), key_source([0xF1; 4]));
+        assert!(original
+            .validate_payjoin_proposal(&owned, &payee, &params)
+            .is_ok());
+        let mut reference = original.clone();
+        reference.inputs[0]
+            .bip32_derivation
+            .insert(pubkey(1), key_source([0xF1; 4]));
+        assert_eq!(
+            reference.validate_payjoin_proposal(&owned, &payee, &params),
+            Err(PayjoinError::ReceiverInputOwned(
+                owned.inputs[1].previous_outpoint
+            ))
+        );
+    }
+
+    #[test]
+    fn secure_endpoint() {
+        assert!(is_secure_endpoint("https://example.com/pj"));
+        assert!(is_secure_endpoint("HTTPS://example.com:8443/pj?x=1"));
+        assert!(is_secure_endpoint(
+            "http://pjexample2bd6fm5gmkh4ulnsdugqpurupljocfrzzlcldqeqqdsbyd.onion/pj"
+        ));
+        assert!(is_secure_endpoint("http://user@example.onion:80"));
+        assert!(!is_secure_endpoint("http://example.com/pj"));
+        assert!(!is_secure_endpoint("http://example.onion.com/pj"));
+        assert!(!is_secure_endpoint("http://example.com/?x=.onion"));
+        assert!(!is_secure_endpoint("ftp://example.onion/pj"));
+        assert!(!is_secure_endpoint("example.com/pj"));
+        assert!(!is_secure_endpoint("https:///pj"));
+    }
+}
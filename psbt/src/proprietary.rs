@@ -11,10 +11,12 @@
 
 use core::fmt::{self, Display, Formatter};
 use core::str::FromStr;
+use std::collections::BTreeMap;
 
 use amplify::hex::{FromHex, ToHex};
 
 use crate::raw::ProprietaryKey;
+use crate::Psbt;
 
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, Error)]
 #[display(doc_comments)]
@@ -53,6 +55,11 @@ pub enum ProprietaryKeyError {
 }
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
 pub enum ProprietaryKeyLocation {
     #[display("global")]
     Global,
@@ -185,3 +192,142 @@ impl Display for ProprietaryKeyDescriptor {
         )
     }
 }
+
+impl ProprietaryKeyType {
+    /// Checks whether the proprietary key has this type
+    #[inline]
+    pub fn matches(&self, key: &ProprietaryKey) -> bool {
+        key.prefix == self.prefix.as_bytes() && key.subtype == self.subtype
+    }
+}
+
+impl Psbt {
+    /// Returns proprietary key-value map at the given location of the PSBT.
+    pub fn proprietary_map(
+        &self,
+        location: ProprietaryKeyLocation,
+    ) -> Result<&BTreeMap<ProprietaryKey, Vec<u8>>, ProprietaryKeyError> {
+        Ok(match location {
+            ProprietaryKeyLocation::Global => &self.proprietary,
+            ProprietaryKeyLocation::Input(pos) => {
+                &self
+                    .inputs
+                    .get(pos as usize)
+                    .ok_or(ProprietaryKeyError::InputOutOfRange(pos, self.inputs.len()))?
+                    .proprietary
+            }
+            ProprietaryKeyLocation::Output(pos) => {
+                &self
+                    .outputs
+                    .get(pos as usize)
+                    .ok_or(ProprietaryKeyError::OutputOutOfRange(
+                        pos,
+                        self.outputs.len(),
+                    ))?
+                    .proprietary
+            }
+        })
+    }
+
+    /// Returns mutable proprietary key-value map at the given location of the
+    /// PSBT.
+    pub fn proprietary_map_mut(
+        &mut self,
+        location: ProprietaryKeyLocation,
+    ) -> Result<&mut BTreeMap<ProprietaryKey, Vec<u8>>, ProprietaryKeyError> {
+        let (inputs, outputs) = (self.inputs.len(), self.outputs.len());
+        Ok(match location {
+            ProprietaryKeyLocation::Global => &mut self.proprietary,
+            ProprietaryKeyLocation::Input(pos) => {
+                &mut self
+                    .inputs
+                    .get_mut(pos as usize)
+                    .ok_or(ProprietaryKeyError::InputOutOfRange(pos, inputs))?
+                    .proprietary
+            }
+            ProprietaryKeyLocation::Output(pos) => {
+                &mut self
+                    .outputs
+                    .get_mut(pos as usize)
+                    .ok_or(ProprietaryKeyError::OutputOutOfRange(pos, outputs))?
+                    .proprietary
+            }
+        })
+    }
+
+    /// Inserts proprietary key defined by the descriptor into the PSBT,
+    /// returning the previous value for the same key, if any.
+    pub fn insert_proprietary(
+        &mut self,
+        key: &ProprietaryKeyDescriptor,
+    ) -> Result<Option<Vec<u8>>, ProprietaryKeyError> {
+        Ok(self
+            .proprietary_map_mut(key.location)?
+            .insert(key.into(), key.value.as_ref().cloned().unwrap_or_default()))
+    }
+
+    /// Removes proprietary key defined by the descriptor from the PSBT,
+    /// returning its value, if the key was present. The value from the
+    /// descriptor is ignored.
+    pub fn remove_proprietary(
+        &mut self,
+        key: &ProprietaryKeyDescriptor,
+    ) -> Result<Option<Vec<u8>>, ProprietaryKeyError> {
+        Ok(self
+            .proprietary_map_mut(key.location)?
+            .remove(&ProprietaryKey::from(key)))
+    }
+
+    /// Lists all proprietary keys of the given type present at the given
+    /// location of the PSBT.
+    pub fn proprietary_by_type(
+        &self,
+        location: ProprietaryKeyLocation,
+        ty: &ProprietaryKeyType,
+    ) -> Result<Vec<ProprietaryKeyDescriptor>, ProprietaryKeyError> {
+        Ok(self
+            .proprietary_map(location)?
+            .iter()
+            .filter(|(key, _)| ty.matches(key))
+            .map(|(key, value)| ProprietaryKeyDescriptor {
+                location,
+                ty: ty.clone(),
+                key: Some(key.key.clone()).filter(|key| !key.is_empty()),
+                value: Some(value.clone()).filter(|value| !value.is_empty()),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn psbt_proprietary_keys() {
+        let mut psbt = Psbt::default();
+        let key = ProprietaryKeyDescriptor::from_str("global P2C(0) 01:abcd").unwrap();
+        assert_eq!(psbt.insert_proprietary(&key), Ok(None));
+        assert_eq!(psbt.insert_proprietary(&key), Ok(Some(vec![0xab, 0xcd])));
+
+        let ty = ProprietaryKeyType::from_str("P2C(0)").unwrap();
+        assert_eq!(
+            psbt.proprietary_by_type(ProprietaryKeyLocation::Global, &ty),
+            Ok(vec![key.clone()])
+        );
+        let other = ProprietaryKeyType::from_str("P2C(1)").unwrap();
+        assert_eq!(
+            psbt.proprietary_by_type(ProprietaryKeyLocation::Global, &other),
+            Ok(vec![])
+        );
+
+        assert_eq!(psbt.remove_proprietary(&key), Ok(Some(vec![0xab, 0xcd])));
+        assert_eq!(psbt.remove_proprietary(&key), Ok(None));
+
+        let key = ProprietaryKeyDescriptor::from_str("input(1) P2C(0)").unwrap();
+        assert_eq!(
+            psbt.insert_proprietary(&key),
+            Err(ProprietaryKeyError::InputOutOfRange(1, 0))
+        );
+    }
+}
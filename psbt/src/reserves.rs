@@ -0,0 +1,461 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Proof-of-reserves (BIP-127): PSBT spending all wallet UTXOs together with
+//! an unspendable challenge input committing to a message.
+//!
+//! The challenge input spends output #0 of a non-existing transaction with id
+//! equal to `SHA256("Proof-of-Reserves: " || message)`, which makes the proof
+//! transaction invalid and guarantees that it can't be broadcasted, while the
+//! `SIGHASH_ALL` signatures of the reserve inputs commit to the message. The
+//! proof has a single output paying the total amount of reserves.
+
+use std::collections::BTreeSet;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{Secp256k1, Verification};
+use bitcoin::{EcdsaSighashType, OutPoint, SchnorrSighashType, Txid};
+use bitcoin_onchain::blockchain::MiningStatus;
+use bitcoin_onchain::{ResolveTxGraph, TxResolverError};
+
+use crate::{AuditError, InputMatchError, Psbt, PsbtSighashType};
+
+/// Tag prefixing the message in the challenge input transaction id
+pub const RESERVES_CHALLENGE_TAG: &str = "Proof-of-Reserves: ";
+
+/// Returns outpoint spent by the challenge input of a proof-of-reserves for a
+/// given message.
+pub fn challenge_outpoint(message: &str) -> OutPoint {
+    let mut engine = sha256::Hash::engine();
+    engine.input(RESERVES_CHALLENGE_TAG.as_bytes());
+    engine.input(message.as_bytes());
+    let hash = sha256::Hash::from_engine(engine);
+    OutPoint::new(Txid::from_inner(hash.into_inner()), 0)
+}
+
+/// Errors constructing and verifying proof-of-reserves
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ReservesError {
+    /// proof-of-reserves does not contain any reserve inputs
+    NoReserves,
+
+    /// none of the reserve inputs spends segwit output, so the challenge input
+    /// can't be signed
+    NoSegwitInputs,
+
+    /// the first input of the proof-of-reserves does not commit to the
+    /// challenge message
+    ChallengeMismatch,
+
+    /// proof-of-reserves must contain a single output, while it has {0}
+    OutputCount(usize),
+
+    /// proof-of-reserves output amount {output} does not match the total
+    /// amount of reserves {reserves}
+    AmountMismatch {
+        /// Total amount of the reserve inputs
+        reserves: u64,
+        /// Amount of the proof output
+        output: u64,
+    },
+
+    /// input #{0} of the proof-of-reserves is not signed
+    Unsigned(usize),
+
+    /// input #{0} of the proof-of-reserves is signed with sighash type {1},
+    /// which does not commit to the challenge
+    SighashType(usize, PsbtSighashType),
+
+    /// input #{0} of the proof-of-reserves has invalid signature: {1}
+    InvalidSignature(usize, AuditError),
+
+    /// challenge input of the proof-of-reserves is not signed by the keys
+    /// controlling any of the reserves
+    ChallengeNotOwned,
+
+    /// input #{0} of the proof-of-reserves does not provide spent output: {1}
+    Prevout(usize, InputMatchError),
+
+    /// spent output provided for reserve {0} does not match the blockchain
+    /// data
+    PrevoutMismatch(OutPoint),
+
+    /// reserve {0} was not mined at the proof height
+    Unconfirmed(OutPoint),
+
+    /// reserve {0} was already spent at the proof height
+    Spent(OutPoint),
+
+    /// {0}
+    #[from]
+    Resolver(TxResolverError),
+
+    /// {0}
+    #[cfg(feature = "construct")]
+    #[from]
+    Construct(crate::construct::Error),
+}
+
+impl Psbt {
+    /// Verifies proof-of-reserves for a given `message`, checking that all
+    /// reserve inputs were mined and remained unspent at the block `height`.
+    /// Returns total amount of the reserves.
+    ///
+    /// All inputs of the proof must be signed with `SIGHASH_ALL` (or taproot
+    /// default) sighash type; finalized inputs are not accepted since their
+    /// satisfaction can't be verified without script interpreter. The
+    /// challenge input must spend the same `scriptPubkey` as one of the
+    /// reserve inputs and must be signed only with the keys which have signed
+    /// that reserve input, proving that the message was signed by the owner
+    /// of the reserves.
+    pub fn verify_reserves<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        message: &str,
+        height: u32,
+        resolver: &impl ResolveTxGraph,
+    ) -> Result<u64, ReservesError> {
+        let challenge = self.inputs.first().ok_or(ReservesError::NoReserves)?;
+        if challenge.previous_outpoint != challenge_outpoint(message) {
+            return Err(ReservesError::ChallengeMismatch);
+        }
+        if self.inputs.len() < 2 {
+            return Err(ReservesError::NoReserves);
+        }
+        if self.outputs.len() != 1 {
+            return Err(ReservesError::OutputCount(self.outputs.len()));
+        }
+
+        let committing = [
+            PsbtSighashType::from(EcdsaSighashType::All),
+            PsbtSighashType::from(SchnorrSighashType::Default),
+        ];
+        let audits = self.verify_signatures(secp);
+        for audit in &audits {
+            let index = audit.index;
+            if audit.signatures.is_empty() {
+                return Err(ReservesError::Unsigned(index));
+            }
+            for sig in &audit.signatures {
+                if !committing.contains(&sig.sighash_type) {
+                    return Err(ReservesError::SighashType(index, sig.sighash_type));
+                }
+                if let Err(err) = &sig.status {
+                    return Err(ReservesError::InvalidSignature(index, err.clone()));
+                }
+            }
+        }
+
+        let challenge_script = &challenge
+            .input_prevout()
+            .map_err(|err| ReservesError::Prevout(0, err))?
+            .script_pubkey;
+        let owners = self
+            .inputs
+            .iter()
+            .zip(&audits)
+            .skip(1)
+            .filter(|(input, _)| {
+                input
+                    .input_prevout()
+                    .map(|prevout| prevout.script_pubkey == *challenge_script)
+                    .unwrap_or_default()
+            })
+            .flat_map(|(_, audit)| audit.signatures.iter().map(|sig| sig.source))
+            .collect::<BTreeSet<_>>();
+        if audits[0]
+            .signatures
+            .iter()
+            .any(|sig| !owners.contains(&sig.source))
+        {
+            return Err(ReservesError::ChallengeNotOwned);
+        }
+
+        let mut reserves = 0u64;
+        for (index, input) in self.inputs.iter().enumerate().skip(1) {
+            let outpoint = input.previous_outpoint;
+            let prevout = input
+                .input_prevout()
+                .map_err(|err| ReservesError::Prevout(index, err))?;
+            let tx = resolver.resolve_tx(outpoint.txid)?;
+            if tx.output.get(outpoint.vout as usize) != Some(prevout) {
+                return Err(ReservesError::PrevoutMismatch(outpoint));
+            }
+            let mined_before = |status: MiningStatus| match status {
+                MiningStatus::Blockchain(mined) => mined <= height as u64,
+                _ => false,
+            };
+            if !mined_before(resolver.resolve_tx_status(&tx)?) {
+                return Err(ReservesError::Unconfirmed(outpoint));
+            }
+            for child in resolver.resolve_spending_txs(&tx)? {
+                let spends = child
+                    .input
+                    .iter()
+                    .any(|txin| txin.previous_output == outpoint);
+                if spends && mined_before(resolver.resolve_tx_status(&child)?) {
+                    return Err(ReservesError::Spent(outpoint));
+                }
+            }
+            reserves += prevout.value;
+        }
+
+        let output = self.outputs[0].amount;
+        if output != reserves {
+            return Err(ReservesError::AmountMismatch { reserves, output });
+        }
+        Ok(reserves)
+    }
+}
+
+#[cfg(feature = "construct")]
+mod _construct {
+    use bitcoin::{Script, TxOut};
+    use bitcoin_hd::{DerivationAccount, SegmentIndexes, UnhardenedIndex};
+    use bitcoin_onchain::ResolveTx;
+    use bitcoin_scripts::PubkeyScript;
+    use descriptors::InputDescriptor;
+    use miniscript::Descriptor;
+
+    use super::*;
+    use crate::{construct, Input};
+
+    impl Psbt {
+        /// Constructs proof-of-reserves for a given `message` spending UTXOs
+        /// controlled by the wallet `descriptor` or its `change_descriptor`.
+        /// The resulting PSBT is signed with the normal signer and verified
+        /// with [`Psbt::verify_reserves`].
+        ///
+        /// The challenge input copies key and script information from the
+        /// first reserve input spending segwit output, so it is signed by the
+        /// same keys.
+        pub fn construct_reserves<'inputs>(
+            descriptor: &Descriptor<DerivationAccount>,
+            change_descriptor: &Descriptor<DerivationAccount>,
+            message: &str,
+            inputs: impl IntoIterator<Item = &'inputs InputDescriptor>,
+            tx_resolver: &impl ResolveTx,
+        ) -> Result<Psbt, ReservesError> {
+            let inputs = inputs.into_iter().collect::<Vec<_>>();
+            if inputs.is_empty() {
+                return Err(ReservesError::NoReserves);
+            }
+            let mut reserves = 0u64;
+            for input in &inputs {
+                let OutPoint { txid, vout } = input.outpoint;
+                reserves += tx_resolver
+                    .resolve_tx(txid)?
+                    .output
+                    .get(vout as usize)
+                    .ok_or(construct::Error::OutputUnknown(txid, vout))?
+                    .value;
+            }
+
+            let outputs = [(PubkeyScript::from(Script::new_op_return(&[])), reserves)];
+            let mut psbt = Psbt::construct_with_change(
+                descriptor,
+                change_descriptor,
+                inputs,
+                &outputs,
+                UnhardenedIndex::zero(),
+                0,
+                tx_resolver,
+            )?;
+
+            let template = psbt
+                .inputs
+                .iter()
+                .find(|input| input.witness_utxo.is_some())
+                .ok_or(ReservesError::NoSegwitInputs)?;
+            let challenge = Input {
+                index: 0,
+                previous_outpoint: challenge_outpoint(message),
+                sequence_number: template.sequence_number,
+                sighash_type: template.sighash_type,
+                witness_utxo: template.witness_utxo.as_ref().map(|prevout| TxOut {
+                    value: 0,
+                    script_pubkey: prevout.script_pubkey.clone(),
+                }),
+                bip32_derivation: template.bip32_derivation.clone(),
+                redeem_script: template.redeem_script.clone(),
+                witness_script: template.witness_script.clone(),
+                tap_internal_key: template.tap_internal_key,
+                tap_merkle_root: template.tap_merkle_root,
+                tap_scripts: template.tap_scripts.clone(),
+                tap_key_origins: template.tap_key_origins.clone(),
+                ..default!()
+            };
+            psbt.inputs.insert(0, challenge);
+            for (index, input) in psbt.inputs.iter_mut().enumerate() {
+                input.index = index;
+            }
+            Ok(psbt)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::secp256k1::{Message, SecretKey};
+    use bitcoin::util::sighash::SighashCache;
+    use bitcoin::{EcdsaSig, PublicKey, Script, Transaction, TxOut};
+    use bitcoin_onchain::ResolveTx;
+
+    use super::*;
+    use crate::{Input, Output};
+
+    struct EmptyChain;
+
+    impl ResolveTx for EmptyChain {
+        fn resolve_tx(&self, txid: Txid) -> Result<Transaction, TxResolverError> {
+            Err(TxResolverError::with(txid))
+        }
+    }
+
+    impl ResolveTxGraph for EmptyChain {
+        fn resolve_tx_status(&self, _: &Transaction) -> Result<MiningStatus, TxResolverError> {
+            Ok(MiningStatus::UnknownTx)
+        }
+
+        fn resolve_spending_txs(
+            &self,
+            _: &Transaction,
+        ) -> Result<Vec<Transaction>, TxResolverError> {
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn verify() {
+        let secp = Secp256k1::verification_only();
+        let message = "reserves of 2022-06-01";
+        let challenge = challenge_outpoint(message);
+        assert_eq!(challenge.vout, 0);
+        assert_eq!(challenge_outpoint(message), challenge);
+        assert_ne!(challenge_outpoint("another message"), challenge);
+
+        let reserve = OutPoint::from_str(
+            "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8",
+        )
+        .unwrap();
+        let mut psbt = Psbt {
+            inputs: [challenge, reserve]
+                .into_iter()
+                .enumerate()
+                .map(|(index, previous_outpoint)| Input {
+                    index,
+                    previous_outpoint,
+                    ..default!()
+                })
+                .collect(),
+            outputs: vec![],
+            ..default!()
+        };
+        let verify = |psbt: &Psbt| psbt.verify_reserves(&secp, message, 700_000, &EmptyChain);
+
+        assert!(matches!(
+            psbt.verify_reserves(&secp, "another message", 700_000, &EmptyChain),
+            Err(ReservesError::ChallengeMismatch)
+        ));
+        assert!(matches!(verify(&psbt), Err(ReservesError::OutputCount(0))));
+
+        psbt.outputs.push(Output {
+            index: 0,
+            amount: 10_000,
+            script: Script::new_op_return(&[]).into(),
+            ..default!()
+        });
+        assert!(matches!(verify(&psbt), Err(ReservesError::Unsigned(0))));
+
+        psbt.inputs.truncate(1);
+        assert!(matches!(verify(&psbt), Err(ReservesError::NoReserves)));
+    }
+
+    fn sign_wpkh(psbt: &mut Psbt, index: usize, seckey: &SecretKey) {
+        let secp = Secp256k1::new();
+        let pubkey = PublicKey::new(seckey.public_key(&secp));
+        let tx = psbt.to_unsigned_tx();
+        let value = psbt.inputs[index].witness_utxo.as_ref().unwrap().value;
+        let script_code = Script::new_p2pkh(&pubkey.pubkey_hash());
+        let sighash = SighashCache::new(&tx)
+            .segwit_signature_hash(index, &script_code, value, EcdsaSighashType::All)
+            .unwrap();
+        let sig = secp.sign_ecdsa(&Message::from_slice(&sighash[..]).unwrap(), seckey);
+        psbt.inputs[index].partial_sigs.insert(pubkey, EcdsaSig {
+            sig,
+            hash_ty: EcdsaSighashType::All,
+        });
+    }
+
+    #[test]
+    fn challenge_ownership() {
+        let secp = Secp256k1::new();
+        let message = "reserves of 2022-06-01";
+        let owner = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let foreign = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let script = |seckey: &SecretKey| {
+            let pubkey = PublicKey::new(seckey.public_key(&secp));
+            Script::new_v0_p2wpkh(&pubkey.wpubkey_hash().unwrap())
+        };
+        let reserve = OutPoint::from_str(
+            "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8",
+        )
+        .unwrap();
+        let proof = |challenge_key: &SecretKey| {
+            let mut psbt = Psbt {
+                inputs: vec![
+                    Input {
+                        index: 0,
+                        previous_outpoint: challenge_outpoint(message),
+                        witness_utxo: Some(TxOut {
+                            value: 0,
+                            script_pubkey: script(challenge_key),
+                        }),
+                        ..default!()
+                    },
+                    Input {
+                        index: 1,
+                        previous_outpoint: reserve,
+                        witness_utxo: Some(TxOut {
+                            value: 10_000,
+                            script_pubkey: script(&owner),
+                        }),
+                        ..default!()
+                    },
+                ],
+                outputs: vec![Output {
+                    index: 0,
+                    amount: 10_000,
+                    script: Script::new_op_return(&[]).into(),
+                    ..default!()
+                }],
+                ..default!()
+            };
+            sign_wpkh(&mut psbt, 0, challenge_key);
+            sign_wpkh(&mut psbt, 1, &owner);
+            psbt
+        };
+
+        // Ownership is proven, so the verification proceeds to the blockchain
+        // checks
+        assert!(matches!(
+            proof(&owner).verify_reserves(&secp, message, 700_000, &EmptyChain),
+            Err(ReservesError::Resolver(_))
+        ));
+        assert!(matches!(
+            proof(&foreign).verify_reserves(&secp, message, 700_000, &EmptyChain),
+            Err(ReservesError::ChallengeNotOwned)
+        ));
+    }
+}
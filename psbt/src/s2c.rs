@@ -0,0 +1,80 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+// TODO: Relocate to BP DBC library
+
+//! Processing proprietary PSBT keys related to sign-to-contract (S2C)
+//! commitments.
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::PublicKey;
+
+use crate::raw::ProprietaryKey;
+use crate::Input;
+
+pub const PSBT_S2C_PREFIX: &[u8] = b"S2C";
+pub const PSBT_IN_S2C_COMMITMENT: u8 = 0;
+pub const PSBT_IN_S2C_PROOF: u8 = 1;
+
+impl Input {
+    /// Adds commitment which must be embedded into the nonces of signatures
+    /// created for the input by S2C-aware signer
+    pub fn set_s2c_commitment(&mut self, commitment: sha256::Hash) {
+        self.proprietary.insert(
+            ProprietaryKey {
+                prefix: PSBT_S2C_PREFIX.to_vec(),
+                subtype: PSBT_IN_S2C_COMMITMENT,
+                key: vec![],
+            },
+            commitment.to_vec(),
+        );
+    }
+
+    /// Returns commitment which must be embedded into the input signatures, if
+    /// any
+    pub fn s2c_commitment(&self) -> Option<sha256::Hash> {
+        self.proprietary
+            .get(&ProprietaryKey {
+                prefix: PSBT_S2C_PREFIX.to_vec(),
+                subtype: PSBT_IN_S2C_COMMITMENT,
+                key: vec![],
+            })
+            .and_then(|value| sha256::Hash::from_slice(value).ok())
+    }
+
+    /// Adds proof of S2C commitment (original signature nonce) for the
+    /// signature identified by the key data. Key data are the serialized
+    /// public key for ECDSA signatures, serialized tweaked output key for
+    /// taproot key path signatures and x-only public key followed by leaf
+    /// hash for taproot script path signatures.
+    pub fn set_s2c_proof(&mut self, sig_key: impl AsRef<[u8]>, nonce: PublicKey) {
+        self.proprietary.insert(
+            ProprietaryKey {
+                prefix: PSBT_S2C_PREFIX.to_vec(),
+                subtype: PSBT_IN_S2C_PROOF,
+                key: sig_key.as_ref().to_vec(),
+            },
+            nonce.serialize().to_vec(),
+        );
+    }
+
+    /// Finds proof of S2C commitment (original signature nonce) for the
+    /// signature identified by the key data (see [`Input::set_s2c_proof`])
+    pub fn s2c_proof(&self, sig_key: impl AsRef<[u8]>) -> Option<PublicKey> {
+        self.proprietary
+            .get(&ProprietaryKey {
+                prefix: PSBT_S2C_PREFIX.to_vec(),
+                subtype: PSBT_IN_S2C_PROOF,
+                key: sig_key.as_ref().to_vec(),
+            })
+            .and_then(|value| PublicKey::from_slice(value).ok())
+    }
+}
@@ -0,0 +1,269 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Anti-exfil (anti-klepto) signing protocol, preventing a malicious signing
+//! device from leaking its private keys through the choice of ECDSA signature
+//! nonces.
+//!
+//! Implements the protocol from `ecdsa_s2c` module of libsecp256k1-zkp, which
+//! is also used by hardware wallets supporting anti-exfil. The protocol runs
+//! in two rounds:
+//! 1. Host picks random `host_data` and sends its commitment
+//!    `H_data(host_data)` (tagged hash with `s2c/ecdsa/data` tag) to the
+//!    signer, which replies with the S2C opening: the original nonce point `R0`
+//!    derived with RFC6979 using the host commitment as extra entropy.
+//! 2. Host reveals `host_data`; the signer signs using the nonce tweaked with
+//!    `host_data` as a sign-to-contract commitment (see [`sign_ecdsa_s2c`]).
+//!
+//! Since the signer commits to `R0` before learning `host_data`, it can't bias
+//! the final signature nonce, which the host checks against `R0` with
+//! [`verify_anti_exfil`].
+//!
+//! [`sign_ecdsa_s2c`]: super::sign_ecdsa_s2c
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{ecdsa, Message, PublicKey, Secp256k1, SecretKey, Signing, Verification};
+use secp256k1_zkp::EcdsaS2cOpening;
+
+use super::s2c::{proof_from_opening, tagged_hash, S2C_DATA_TAG};
+use super::verify_s2c_commitment;
+
+/// Errors happening during anti-exfil signing
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AntiExfilError<E: std::error::Error> {
+    /// signing device failure: {0}
+    Signer(E),
+
+    /// signature returned by the signing device is not valid for the message
+    /// and the public key
+    InvalidSignature,
+
+    /// signature nonce does not commit to the host randomness, which may
+    /// indicate an attempt to exfiltrate the private key from the signing
+    /// device
+    NonceCommitmentMismatch,
+}
+
+/// Computes host commitment `H_data(host_data)` sent to the signer in the
+/// first round of the protocol.
+pub fn anti_exfil_host_commitment(host_data: &[u8; 32]) -> sha256::Hash {
+    tagged_hash(S2C_DATA_TAG, &[host_data])
+}
+
+/// Signer side of the first protocol round: returns the original nonce point
+/// (S2C opening) committing to the host commitment.
+pub fn anti_exfil_ecdsa_commitment<C: Signing>(
+    secp: &Secp256k1<C>,
+    msg: &Message,
+    seckey: &SecretKey,
+    host_commitment: sha256::Hash,
+) -> PublicKey {
+    proof_from_opening(EcdsaS2cOpening::anti_exfil_signer_commit(
+        secp,
+        msg,
+        seckey,
+        &host_commitment.into_inner(),
+    ))
+}
+
+/// Signer side of the second protocol round: creates low-S ECDSA signature
+/// with the nonce committing to the revealed host randomness.
+pub fn sign_ecdsa_anti_exfil<C: Signing>(
+    secp: &Secp256k1<C>,
+    msg: &Message,
+    seckey: &SecretKey,
+    host_data: &[u8; 32],
+) -> ecdsa::Signature {
+    EcdsaS2cOpening::sign(secp, msg, seckey, host_data).0
+}
+
+/// Verifies that the signature is valid for the message and the public key
+/// and that its nonce commits to the host randomness, using the original
+/// nonce point returned by the signer in the first protocol round.
+pub fn verify_anti_exfil<C: Verification, E: std::error::Error>(
+    secp: &Secp256k1<C>,
+    sig: &ecdsa::Signature,
+    msg: &Message,
+    pubkey: &PublicKey,
+    host_data: &[u8; 32],
+    signer_commitment: PublicKey,
+) -> Result<(), AntiExfilError<E>> {
+    secp.verify_ecdsa(msg, sig, pubkey)
+        .map_err(|_| AntiExfilError::InvalidSignature)?;
+    let host_data = sha256::Hash::from_inner(*host_data);
+    if !verify_s2c_commitment(secp, sig, signer_commitment, host_data) {
+        return Err(AntiExfilError::NonceCommitmentMismatch);
+    }
+    Ok(())
+}
+
+/// Host API for signing with external devices supporting the anti-exfil
+/// protocol. Implementors provide communication with the device for both
+/// protocol rounds; the provided method runs the protocol and verifies the
+/// returned signature.
+pub trait SignWithAntiExfil {
+    /// Error returned by the signing device
+    type Error: std::error::Error;
+
+    /// Sends host commitment to the device, receiving the original nonce
+    /// point for the ECDSA signature with a given key.
+    fn commit_ecdsa_nonce(
+        &mut self,
+        msg: &Message,
+        pubkey: &PublicKey,
+        host_commitment: sha256::Hash,
+    ) -> Result<PublicKey, Self::Error>;
+
+    /// Reveals host randomness to the device, receiving ECDSA signature.
+    fn sign_ecdsa_with_host_data(
+        &mut self,
+        msg: &Message,
+        pubkey: &PublicKey,
+        host_data: &[u8; 32],
+    ) -> Result<ecdsa::Signature, Self::Error>;
+
+    /// Runs anti-exfil protocol for ECDSA signature with the device. The
+    /// `host_data` must be fresh randomness, never reused across signatures.
+    fn anti_exfil_sign_ecdsa<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        msg: &Message,
+        pubkey: &PublicKey,
+        host_data: &[u8; 32],
+    ) -> Result<ecdsa::Signature, AntiExfilError<Self::Error>> {
+        let commitment = anti_exfil_host_commitment(host_data);
+        let signer_commitment = self
+            .commit_ecdsa_nonce(msg, pubkey, commitment)
+            .map_err(AntiExfilError::Signer)?;
+        let sig = self
+            .sign_ecdsa_with_host_data(msg, pubkey, host_data)
+            .map_err(AntiExfilError::Signer)?;
+        verify_anti_exfil(secp, &sig, msg, pubkey, host_data, signer_commitment)?;
+        Ok(sig)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::secp256k1::SECP256K1;
+
+    use super::*;
+
+    #[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+    #[display("signing device failure")]
+    struct DeviceError;
+
+    /// Software signing device; if `malicious` is set, ignores host data in
+    /// the second round and signs with a nonce of its own choice.
+    struct Device {
+        seckey: SecretKey,
+        malicious: bool,
+    }
+
+    impl SignWithAntiExfil for Device {
+        type Error = DeviceError;
+
+        fn commit_ecdsa_nonce(
+            &mut self,
+            msg: &Message,
+            _: &PublicKey,
+            host_commitment: sha256::Hash,
+        ) -> Result<PublicKey, Self::Error> {
+            Ok(anti_exfil_ecdsa_commitment(
+                SECP256K1,
+                msg,
+                &self.seckey,
+                host_commitment,
+            ))
+        }
+
+        fn sign_ecdsa_with_host_data(
+            &mut self,
+            msg: &Message,
+            _: &PublicKey,
+            host_data: &[u8; 32],
+        ) -> Result<ecdsa::Signature, Self::Error> {
+            let host_data = if self.malicious {
+                &[0u8; 32]
+            } else {
+                host_data
+            };
+            Ok(sign_ecdsa_anti_exfil(
+                SECP256K1,
+                msg,
+                &self.seckey,
+                host_data,
+            ))
+        }
+    }
+
+    #[test]
+    fn signer_commitment() {
+        let seckey = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let msg = Message::from_slice(&[0x01; 32]).unwrap();
+        let host_commitment = anti_exfil_host_commitment(&[0x5a; 32]);
+
+        // Original nonce must be the RFC6979 nonce with the host commitment
+        // used as extra entropy
+        let opening = anti_exfil_ecdsa_commitment(SECP256K1, &msg, &seckey, host_commitment);
+        let sig = SECP256K1.sign_ecdsa_with_noncedata(&msg, &seckey, &host_commitment.into_inner());
+        assert_eq!(
+            opening.x_only_public_key().0.serialize()[..],
+            sig.serialize_compact()[..32]
+        );
+    }
+
+    #[test]
+    fn anti_exfil_signing() {
+        let seckey = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(SECP256K1, &seckey);
+        let msg = Message::from_slice(&[0x01; 32]).unwrap();
+        let host_data = [0x5a; 32];
+
+        let mut device = Device {
+            seckey,
+            malicious: false,
+        };
+        let sig = device
+            .anti_exfil_sign_ecdsa(SECP256K1, &msg, &pubkey, &host_data)
+            .unwrap();
+        let opening = device
+            .commit_ecdsa_nonce(&msg, &pubkey, anti_exfil_host_commitment(&host_data))
+            .unwrap();
+        assert_eq!(
+            verify_anti_exfil::<_, DeviceError>(
+                SECP256K1,
+                &sig,
+                &msg,
+                &pubkey,
+                &[0x5b; 32],
+                opening
+            ),
+            Err(AntiExfilError::NonceCommitmentMismatch)
+        );
+        let other =
+            PublicKey::from_secret_key(SECP256K1, &SecretKey::from_slice(&[0x43; 32]).unwrap());
+        assert_eq!(
+            verify_anti_exfil::<_, DeviceError>(SECP256K1, &sig, &msg, &other, &host_data, opening),
+            Err(AntiExfilError::InvalidSignature)
+        );
+
+        device.malicious = true;
+        assert_eq!(
+            device.anti_exfil_sign_ecdsa(SECP256K1, &msg, &pubkey, &host_data),
+            Err(AntiExfilError::NonceCommitmentMismatch)
+        );
+    }
+}
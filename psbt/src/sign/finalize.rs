@@ -0,0 +1,535 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Native finalizer for the common single-key and multisig script types,
+//! which does not require miniscript. Signatures are taken from the input
+//! partial signatures; multisig signatures are placed in the order of the
+//! public keys in the script.
+//!
+//! Combining PSBTs from co-signers with overlapping quorums may produce more
+//! signatures than the multisig threshold; [`Psbt::minimize_signatures`]
+//! strips the extra signatures before the finalization.
+
+use std::collections::BTreeSet;
+
+use amplify::Wrapper;
+use bitcoin::blockdata::opcodes::all::*;
+use bitcoin::blockdata::script::{Builder, Instruction};
+use bitcoin::{EcdsaSig, PublicKey, Script, Witness};
+
+use crate::{Input, InputMatchError, Psbt};
+
+/// Script types supported by the native finalizer
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+pub enum NativeSpendType {
+    /// Pay-to-public-key-hash
+    #[display("pkh")]
+    Pkh,
+
+    /// Bare multisig
+    #[display("multi")]
+    Multi,
+
+    /// Multisig nested into pay-to-script-hash
+    #[display("sh(multi)")]
+    ShMulti,
+
+    /// Pay-to-witness-public-key-hash nested into pay-to-script-hash
+    #[display("sh(wpkh)")]
+    ShWpkh,
+
+    /// Multisig nested into pay-to-witness-script-hash nested into
+    /// pay-to-script-hash
+    #[display("sh(wsh(multi))")]
+    ShWshMulti,
+
+    /// Pay-to-witness-public-key-hash
+    #[display("wpkh")]
+    Wpkh,
+
+    /// Multisig nested into pay-to-witness-script-hash
+    #[display("wsh(multi)")]
+    WshMulti,
+}
+
+/// Errors finalizing PSBT inputs with the native finalizer
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum FinalizeError {
+    /// input #{0} spent output is unknown: {1}
+    Prevout(usize, InputMatchError),
+
+    /// input #{0} spends script which is not supported by the native finalizer
+    UnsupportedScript(usize),
+
+    /// redeem script of input #{0} is absent or does not match the spent
+    /// output
+    RedeemScriptMismatch(usize),
+
+    /// witness script of input #{0} is absent or does not match the spent
+    /// output
+    WitnessScriptMismatch(usize),
+
+    /// input #{0} has no signature made with the key matching the spent
+    /// public key hash
+    NoSignature(usize),
+
+    /// input #{0} has {1} signature(s) matching the multisig keys, while {2}
+    /// are required
+    NotEnoughSignatures(usize, usize, usize),
+}
+
+/// Parses `OP_k <key_1> ... <key_n> OP_n OP_CHECKMULTISIG` script, returning
+/// the threshold and the keys in the script order.
+fn parse_multi(script: &Script) -> Option<(usize, Vec<PublicKey>)> {
+    let instructions = script.instructions().collect::<Result<Vec<_>, _>>().ok()?;
+    let (last, rest) = instructions.split_last()?;
+    if *last != Instruction::Op(OP_CHECKMULTISIG) {
+        return None;
+    }
+    let (n, rest) = rest.split_last()?;
+    let (k, keys) = rest.split_first()?;
+    let (k, n) = (read_small_int(k)?, read_small_int(n)?);
+    let keys = keys
+        .iter()
+        .map(|instruction| match instruction {
+            Instruction::PushBytes(data) => PublicKey::from_slice(data).ok(),
+            Instruction::Op(_) => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    (keys.len() == n && k <= n).then_some((k, keys))
+}
+
+fn read_small_int(instruction: &Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::Op(op) => {
+            let n = op.to_u8().wrapping_sub(OP_PUSHNUM_1.to_u8());
+            (n < 16).then_some(n as usize + 1)
+        }
+        Instruction::PushBytes(_) => None,
+    }
+}
+
+impl Input {
+    /// Finds partial signature made with the key which hashes into the
+    /// `script_pubkey` using `to_script` conversion.
+    fn single_sig(
+        &self,
+        script_pubkey: &Script,
+        to_script: impl Fn(&PublicKey) -> Option<Script>,
+    ) -> Result<(PublicKey, EcdsaSig), FinalizeError> {
+        self.partial_sigs
+            .iter()
+            .find(|(pk, _)| to_script(pk).as_ref() == Some(script_pubkey))
+            .map(|(pk, sig)| (*pk, *sig))
+            .ok_or(FinalizeError::NoSignature(self.index))
+    }
+
+    /// Selects `threshold` multisig keys having partial signatures, in the
+    /// script order. Keys repeated in the script are selected for each of
+    /// their positions.
+    fn multi_signers(&self, threshold: usize, keys: &[PublicKey]) -> Vec<PublicKey> {
+        keys.iter()
+            .filter(|pk| self.partial_sigs.contains_key(pk))
+            .take(threshold)
+            .copied()
+            .collect()
+    }
+
+    /// Detects multisig script spent by the input: witness script, non-witness
+    /// redeem script or bare `scriptPubkey` of the spent output.
+    fn multisig(&self) -> Option<(usize, Vec<PublicKey>)> {
+        if let Some(witness_script) = &self.witness_script {
+            return parse_multi(witness_script.as_inner());
+        }
+        match &self.redeem_script {
+            Some(redeem_script) if redeem_script.is_witness_program() => None,
+            Some(redeem_script) => parse_multi(redeem_script.as_inner()),
+            None => parse_multi(&self.input_prevout().ok()?.script_pubkey),
+        }
+    }
+
+    /// Collects signatures for the multisig `script` in the order of its keys.
+    /// Returns `None` if the script is not a multisig.
+    fn multi_sigs(&self, script: &Script) -> Option<Result<Vec<EcdsaSig>, FinalizeError>> {
+        let (threshold, keys) = parse_multi(script)?;
+        let sigs = self
+            .multi_signers(threshold, &keys)
+            .iter()
+            .map(|pk| self.partial_sigs[pk])
+            .collect::<Vec<_>>();
+        if sigs.len() < threshold {
+            return Some(Err(FinalizeError::NotEnoughSignatures(
+                self.index,
+                sigs.len(),
+                threshold,
+            )));
+        }
+        Some(Ok(sigs))
+    }
+
+    /// Assembles witness spending P2WPKH `script_pubkey`
+    fn wpkh_witness(&self, script_pubkey: &Script) -> Result<Witness, FinalizeError> {
+        let (pk, sig) = self.single_sig(script_pubkey, |pk| {
+            pk.wpubkey_hash().map(|hash| Script::new_v0_p2wpkh(&hash))
+        })?;
+        Ok(Witness::from_vec(vec![sig.to_vec(), pk.to_bytes()]))
+    }
+
+    /// Assembles `scriptSig` spending bare multisig `script`, appending
+    /// `redeem_script` to the signatures if given.
+    fn multi_script_sig(
+        &self,
+        script: &Script,
+        redeem_script: Option<&Script>,
+    ) -> Result<Script, FinalizeError> {
+        let sigs = self
+            .multi_sigs(script)
+            .ok_or(FinalizeError::UnsupportedScript(self.index))??;
+        let builder = sigs.iter().fold(
+            Builder::new().push_opcode(OP_PUSHBYTES_0),
+            |builder, sig| builder.push_slice(&sig.to_vec()),
+        );
+        Ok(match redeem_script {
+            Some(redeem_script) => builder.push_slice(redeem_script.as_bytes()),
+            None => builder,
+        }
+        .into_script())
+    }
+
+    /// Assembles witness spending P2WSH `script_pubkey` with multisig witness
+    /// script
+    fn multi_witness(&self, script_pubkey: &Script) -> Result<Witness, FinalizeError> {
+        let witness_script = self
+            .witness_script
+            .as_ref()
+            .map(|script| script.as_inner())
+            .filter(|script| &script.to_v0_p2wsh() == script_pubkey)
+            .ok_or(FinalizeError::WitnessScriptMismatch(self.index))?;
+        let sigs = self
+            .multi_sigs(witness_script)
+            .ok_or(FinalizeError::UnsupportedScript(self.index))??;
+        let mut witness = vec![vec![]];
+        witness.extend(sigs.iter().map(EcdsaSig::to_vec));
+        witness.push(witness_script.to_bytes());
+        Ok(Witness::from_vec(witness))
+    }
+
+    /// Removes partial signatures which are not required to satisfy multisig
+    /// script spent by the input, keeping signatures for the first
+    /// `threshold` keys in the script order. Does nothing if the input does
+    /// not spend a multisig or does not have enough signatures to satisfy it.
+    ///
+    /// # Returns
+    ///
+    /// Number of removed signatures.
+    pub fn minimize_signatures(&mut self) -> usize {
+        let (threshold, keys) = match self.multisig() {
+            Some(multisig) => multisig,
+            None => return 0,
+        };
+        let signers = self.multi_signers(threshold, &keys);
+        if signers.len() < threshold {
+            return 0;
+        }
+        let signers = signers.into_iter().collect::<BTreeSet<_>>();
+        let count = self.partial_sigs.len();
+        self.partial_sigs.retain(|pk, _| signers.contains(pk));
+        count - self.partial_sigs.len()
+    }
+
+    /// Finalizes input spending one of the [`NativeSpendType`] scripts without
+    /// using miniscript, assembling `scriptSig` and witness from the partial
+    /// signatures.
+    ///
+    /// On success, clears the fields which are not required anymore (as per
+    /// BIP-174 finalizer role) and returns the type of the spent script.
+    pub fn finalize_native(&mut self) -> Result<NativeSpendType, FinalizeError> {
+        let script_pubkey = self
+            .input_prevout()
+            .map_err(|err| FinalizeError::Prevout(self.index, err))?
+            .script_pubkey
+            .clone();
+        let redeem_script = self
+            .redeem_script
+            .as_ref()
+            .map(|script| script.as_inner().clone())
+            .filter(|script| script.to_p2sh() == script_pubkey);
+
+        let (spend_type, script_sig, witness) = if script_pubkey.is_p2pkh() {
+            let (pk, sig) = self.single_sig(&script_pubkey, |pk| {
+                Some(Script::new_p2pkh(&pk.pubkey_hash()))
+            })?;
+            let script_sig = Builder::new()
+                .push_slice(&sig.to_vec())
+                .push_key(&pk)
+                .into_script();
+            (NativeSpendType::Pkh, script_sig, None)
+        } else if script_pubkey.is_v0_p2wpkh() {
+            let witness = self.wpkh_witness(&script_pubkey)?;
+            (NativeSpendType::Wpkh, Script::new(), Some(witness))
+        } else if script_pubkey.is_v0_p2wsh() {
+            let witness = self.multi_witness(&script_pubkey)?;
+            (NativeSpendType::WshMulti, Script::new(), Some(witness))
+        } else if script_pubkey.is_p2sh() {
+            let redeem_script =
+                redeem_script.ok_or(FinalizeError::RedeemScriptMismatch(self.index))?;
+            let script_sig = Builder::new()
+                .push_slice(redeem_script.as_bytes())
+                .into_script();
+            if redeem_script.is_v0_p2wpkh() {
+                let witness = self.wpkh_witness(&redeem_script)?;
+                (NativeSpendType::ShWpkh, script_sig, Some(witness))
+            } else if redeem_script.is_v0_p2wsh() {
+                let witness = self.multi_witness(&redeem_script)?;
+                (NativeSpendType::ShWshMulti, script_sig, Some(witness))
+            } else {
+                let script_sig = self.multi_script_sig(&redeem_script, Some(&redeem_script))?;
+                (NativeSpendType::ShMulti, script_sig, None)
+            }
+        } else {
+            let script_sig = self.multi_script_sig(&script_pubkey, None)?;
+            (NativeSpendType::Multi, script_sig, None)
+        };
+
+        self.final_script_sig = (!script_sig.is_empty()).then(|| script_sig.into());
+        self.final_script_witness = witness;
+        self.partial_sigs.clear();
+        self.sighash_type = None;
+        self.redeem_script = None;
+        self.witness_script = None;
+        self.bip32_derivation.clear();
+        Ok(spend_type)
+    }
+}
+
+impl Psbt {
+    /// Removes extra multisig signatures from all inputs which are not
+    /// finalized yet (see [`Input::minimize_signatures`]).
+    ///
+    /// # Returns
+    ///
+    /// Total number of removed signatures.
+    pub fn minimize_signatures(&mut self) -> usize {
+        self.inputs
+            .iter_mut()
+            .filter(|input| {
+                input.final_script_sig.is_none() && input.final_script_witness.is_none()
+            })
+            .map(Input::minimize_signatures)
+            .sum()
+    }
+
+    /// Finalizes all inputs which are not finalized yet with
+    /// [`Input::finalize_native`]. Inputs which fail finalization are left
+    /// intact.
+    ///
+    /// # Returns
+    ///
+    /// Number of inputs finalized by this call, or errors for all inputs which
+    /// can't be finalized.
+    pub fn finalize_native(&mut self) -> Result<usize, Vec<FinalizeError>> {
+        let mut count = 0usize;
+        let mut errors = vec![];
+        for input in &mut self.inputs {
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                continue;
+            }
+            match input.finalize_native() {
+                Ok(_) => count += 1,
+                Err(err) => errors.push(err),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+    use bitcoin::{EcdsaSighashType, TxOut};
+
+    use super::*;
+
+    fn key_sig(byte: u8) -> (PublicKey, EcdsaSig) {
+        let secp = Secp256k1::new();
+        let seckey = SecretKey::from_slice(&[byte; 32]).unwrap();
+        let sig = EcdsaSig {
+            sig: secp.sign_ecdsa(&Message::from_slice(&[0x33; 32]).unwrap(), &seckey),
+            hash_ty: EcdsaSighashType::All,
+        };
+        (PublicKey::new(seckey.public_key(&secp)), sig)
+    }
+
+    fn multi(threshold: i64, keys: &[PublicKey]) -> Script {
+        keys.iter()
+            .fold(Builder::new().push_int(threshold), |builder, pk| {
+                builder.push_key(pk)
+            })
+            .push_int(keys.len() as i64)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    fn input(script_pubkey: Script) -> Input {
+        Input {
+            witness_utxo: Some(TxOut {
+                value: 10_000,
+                script_pubkey,
+            }),
+            ..Input::default()
+        }
+    }
+
+    #[test]
+    fn single_key() {
+        let (pk, sig) = key_sig(1);
+        let (other_pk, other_sig) = key_sig(2);
+
+        let mut pkh = input(Script::new_p2pkh(&pk.pubkey_hash()));
+        pkh.partial_sigs.insert(other_pk, other_sig);
+        assert_eq!(
+            pkh.clone().finalize_native(),
+            Err(FinalizeError::NoSignature(0))
+        );
+        pkh.partial_sigs.insert(pk, sig);
+        assert_eq!(pkh.finalize_native(), Ok(NativeSpendType::Pkh));
+        let expected = Builder::new()
+            .push_slice(&sig.to_vec())
+            .push_key(&pk)
+            .into_script();
+        assert_eq!(pkh.final_script_sig.unwrap().into_inner(), expected);
+        assert_eq!(pkh.final_script_witness, None);
+        assert!(pkh.partial_sigs.is_empty());
+
+        let wpkh_script = Script::new_v0_p2wpkh(&pk.wpubkey_hash().unwrap());
+        let mut sh_wpkh = input(wpkh_script.to_p2sh());
+        sh_wpkh.partial_sigs.insert(pk, sig);
+        assert_eq!(
+            sh_wpkh.clone().finalize_native(),
+            Err(FinalizeError::RedeemScriptMismatch(0))
+        );
+        sh_wpkh.redeem_script = Some(wpkh_script.clone().into());
+        assert_eq!(sh_wpkh.finalize_native(), Ok(NativeSpendType::ShWpkh));
+        let expected = Builder::new()
+            .push_slice(wpkh_script.as_bytes())
+            .into_script();
+        assert_eq!(sh_wpkh.final_script_sig.unwrap().into_inner(), expected);
+        assert_eq!(sh_wpkh.final_script_witness.unwrap().to_vec(), vec![
+            sig.to_vec(),
+            pk.to_bytes()
+        ]);
+    }
+
+    #[test]
+    fn multisig() {
+        let (pk1, sig1) = key_sig(1);
+        let (pk2, sig2) = key_sig(2);
+        let (pk3, sig3) = key_sig(3);
+        let script = multi(2, &[pk1, pk2, pk3]);
+
+        let mut wsh = input(script.to_v0_p2wsh());
+        wsh.witness_script = Some(script.clone().into());
+        wsh.partial_sigs.insert(pk3, sig3);
+        assert_eq!(
+            wsh.clone().finalize_native(),
+            Err(FinalizeError::NotEnoughSignatures(0, 1, 2))
+        );
+        wsh.partial_sigs.insert(pk1, sig1);
+        wsh.partial_sigs.insert(pk2, sig2);
+        let mut sh_wsh = input(script.to_v0_p2wsh().to_p2sh());
+        sh_wsh.redeem_script = Some(script.to_v0_p2wsh().into());
+        sh_wsh.witness_script = wsh.witness_script.clone();
+        sh_wsh.partial_sigs = wsh.partial_sigs.clone();
+
+        assert_eq!(wsh.finalize_native(), Ok(NativeSpendType::WshMulti));
+        assert_eq!(wsh.final_script_sig, None);
+        let witness = vec![vec![], sig1.to_vec(), sig2.to_vec(), script.to_bytes()];
+        assert_eq!(wsh.final_script_witness.unwrap().to_vec(), witness);
+
+        assert_eq!(sh_wsh.finalize_native(), Ok(NativeSpendType::ShWshMulti));
+        assert_eq!(sh_wsh.final_script_witness.unwrap().to_vec(), witness);
+
+        let mut sh = input(script.to_p2sh());
+        sh.redeem_script = Some(script.clone().into());
+        sh.partial_sigs.insert(pk2, sig2);
+        sh.partial_sigs.insert(pk3, sig3);
+        assert_eq!(sh.finalize_native(), Ok(NativeSpendType::ShMulti));
+        let expected = Builder::new()
+            .push_opcode(OP_PUSHBYTES_0)
+            .push_slice(&sig2.to_vec())
+            .push_slice(&sig3.to_vec())
+            .push_slice(script.as_bytes())
+            .into_script();
+        assert_eq!(sh.final_script_sig.unwrap().into_inner(), expected);
+
+        let mut bare = input(script);
+        bare.partial_sigs.insert(pk1, sig1);
+        bare.partial_sigs.insert(pk3, sig3);
+        assert_eq!(bare.finalize_native(), Ok(NativeSpendType::Multi));
+
+        let mut unsupported = input(Builder::new().push_opcode(OP_RETURN).into_script());
+        assert_eq!(
+            unsupported.finalize_native(),
+            Err(FinalizeError::UnsupportedScript(0))
+        );
+    }
+
+    #[test]
+    fn signature_minimization() {
+        let (pk1, sig1) = key_sig(1);
+        let (pk2, sig2) = key_sig(2);
+        let (pk3, sig3) = key_sig(3);
+        let (pk4, sig4) = key_sig(4);
+        // Overlapping quorums sharing the first key
+        let script = multi(2, &[pk1, pk1, pk2, pk3]);
+
+        let mut wsh = input(script.to_v0_p2wsh());
+        wsh.witness_script = Some(script.clone().into());
+        wsh.partial_sigs.insert(pk3, sig3);
+        wsh.partial_sigs.insert(pk4, sig4);
+        assert_eq!(wsh.minimize_signatures(), 0);
+        assert_eq!(wsh.partial_sigs.len(), 2);
+
+        wsh.partial_sigs.insert(pk1, sig1);
+        wsh.partial_sigs.insert(pk2, sig2);
+        let mut psbt = Psbt::with(
+            bitcoin::Transaction {
+                version: 2,
+                lock_time: bitcoin::PackedLockTime::ZERO,
+                input: vec![bitcoin::TxIn::default()],
+                output: vec![],
+            },
+            crate::PsbtVersion::V0,
+        )
+        .unwrap();
+        psbt.inputs[0] = wsh;
+        assert_eq!(psbt.minimize_signatures(), 3);
+        assert_eq!(psbt.inputs[0].partial_sigs, bmap! { pk1 => sig1 });
+
+        assert_eq!(psbt.finalize_native(), Ok(1));
+        let witness = vec![vec![], sig1.to_vec(), sig1.to_vec(), script.to_bytes()];
+        assert_eq!(
+            psbt.inputs[0]
+                .final_script_witness
+                .clone()
+                .unwrap()
+                .to_vec(),
+            witness
+        );
+    }
+}
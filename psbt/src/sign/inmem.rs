@@ -16,6 +16,7 @@ use std::cmp::Ordering;
 use std::collections::BTreeSet;
 use std::hash::Hasher;
 
+use bitcoin::psbt::PsbtSighashType;
 use bitcoin::secp256k1::{KeyPair, PublicKey, Secp256k1, SecretKey, Signing, XOnlyPublicKey};
 use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey, Fingerprint};
 use bitcoin::XpubIdentifier;
@@ -165,6 +166,8 @@ where
     secp: &'secp Secp256k1<C>,
     /// Participate keys from this provider in musigs
     musig: bool,
+    /// Sighash types allowed to be mixed within a single PSBT
+    allowed_sighash_types: Vec<PsbtSighashType>,
 }
 
 impl<'secp, C> MemoryKeyProvider<'secp, C>
@@ -176,6 +179,7 @@ where
             accounts: default!(),
             secp,
             musig,
+            allowed_sighash_types: vec![],
         }
     }
 
@@ -183,6 +187,14 @@ where
     pub fn add_account(&mut self, account: MemorySigningAccount) -> bool {
         self.accounts.insert(account)
     }
+
+    /// Allows signing PSBTs which mix the given sighash type with other
+    /// sighash types across inputs.
+    pub fn allow_sighash_type(&mut self, sighash_type: PsbtSighashType) {
+        if !self.allowed_sighash_types.contains(&sighash_type) {
+            self.allowed_sighash_types.push(sighash_type);
+        }
+    }
 }
 
 impl<'secp, C> IntoIterator for &'secp MemoryKeyProvider<'secp, C>
@@ -254,4 +266,7 @@ where
 
     #[inline]
     fn use_musig(&self) -> bool { self.musig }
+
+    #[inline]
+    fn allowed_sighash_types(&self) -> &[PsbtSighashType] { &self.allowed_sighash_types }
 }
@@ -16,16 +16,35 @@
 
 // TODO: Add Hash secret provider and hash secret satisfaction
 
+use bitcoin::psbt::PsbtSighashType;
 use bitcoin::secp256k1::{KeyPair, PublicKey, Secp256k1, SecretKey, Signing, XOnlyPublicKey};
 use bitcoin::util::bip32::{DerivationPath, Fingerprint};
 
+mod antiexfil;
+mod finalize;
 mod inmem;
 #[cfg(feature = "miniscript")]
+mod request;
+mod s2c;
+mod sighash;
+#[cfg(feature = "miniscript")]
 mod signer;
 
+pub use antiexfil::{
+    anti_exfil_ecdsa_commitment, anti_exfil_host_commitment, sign_ecdsa_anti_exfil,
+    verify_anti_exfil, AntiExfilError, SignWithAntiExfil,
+};
+pub use finalize::{FinalizeError, NativeSpendType};
 pub use inmem::{MemoryKeyProvider, MemorySigningAccount};
 #[cfg(feature = "miniscript")]
-pub use signer::{SignAll, SignError, SignInputError};
+pub use request::{
+    SigMergeError, SigPayloadParseError, SigRequest, SigRequestError, SigRequestItem, SigResponse,
+    SigResponseItem, SigScope,
+};
+pub use s2c::{sign_ecdsa_s2c, verify_s2c_commitment};
+pub use sighash::{taproot_sighash, TapSpendPath};
+#[cfg(feature = "miniscript")]
+pub use signer::{SighashTypeList, SignAll, SignError, SignInputError, SigningPolicy};
 
 /// Errors returned by secret providers (see [`SecretProvider`])
 #[derive(
@@ -88,4 +107,10 @@ pub trait SecretProvider<C: Signing> {
     /// Returns whether keys returned by this provider can be used for creating
     /// aggregated Schnorr signatures.
     fn use_musig(&self) -> bool;
+
+    /// Returns sighash types which the signer allows to be mixed with other
+    /// sighash types within a single PSBT. `SIGHASH_ALL` is always allowed;
+    /// by default no other types are allowed and PSBTs mixing sighash types
+    /// across inputs are rejected.
+    fn allowed_sighash_types(&self) -> &[PsbtSighashType] { &[] }
 }
@@ -0,0 +1,727 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Compact signature requests for constrained cosigner devices (mobile apps,
+//! air-gapped signers communicating via QR codes), which allow to sign PSBT
+//! inputs without transferring the whole PSBT to the device.
+//!
+//! Watch-only wallet produces [`SigRequest`] with [`Psbt::sig_request`],
+//! containing only sighashes and key origins for each of the signatures. The
+//! cosigner returns [`SigResponse`] with compact 64-byte signatures, which are
+//! verified and merged into the PSBT with [`Psbt::merge_sig_response`].
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use amplify::Wrapper;
+use base64::Engine;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::psbt::PsbtSighashType;
+use bitcoin::schnorr::TapTweak;
+use bitcoin::secp256k1::{self, ecdsa, schnorr, Message, Secp256k1, Verification};
+use bitcoin::util::bip32::{Fingerprint, KeySource};
+use bitcoin::util::sighash::{Prevouts, SighashCache};
+use bitcoin::util::taproot::{TapBranchHash, TapLeafHash};
+use bitcoin::{consensus, EcdsaSig, OutPoint, SchnorrSig};
+use miniscript::{Descriptor, MiniscriptKey};
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use super::{taproot_sighash, SignInputError, TapSpendPath};
+use crate::Psbt;
+
+/// Errors creating signature request for a PSBT
+#[derive(Debug, Display, Error)]
+#[display("unable to create signature request for input #{input_index} because {error}")]
+pub struct SigRequestError {
+    /// Error originating from a specific transaction input
+    pub error: SignInputError,
+    /// Index of the transaction input that has generated a error
+    pub input_index: usize,
+}
+
+/// Errors verifying and merging signatures from [`SigResponse`] into PSBT
+#[derive(Debug, Display, From)]
+#[display(doc_comments)]
+pub enum SigMergeError {
+    /// signature response does not correspond to the provided signature
+    /// request
+    RequestMismatch,
+
+    /// signature request does not match the current PSBT data (signature
+    /// request item #{0} is absent or has outdated sighash)
+    OutdatedRequest(u16),
+
+    /// signature response refers to unknown signature request item #{0}
+    UnknownItem(u16),
+
+    /// signature response contains invalid signature for the signature request
+    /// item #{0}
+    InvalidSignature(u16),
+
+    /// public key with origin {1} required by signature request item #{0} is
+    /// not present in the PSBT input
+    UnknownKey(u16, String),
+
+    /// input #{0} already contains taproot key-path spending signature
+    TaprootKeySigPresent(u16),
+
+    /// unable to apply pay-to-contract tweak to the public key for the
+    /// signature request item #{0}
+    P2cTweak(u16),
+
+    /// non-standard sighash type {0} used for the signature request item
+    NonStandardSighashType(u32),
+
+    /// {0}
+    #[from]
+    Request(SigRequestError),
+}
+
+impl std::error::Error for SigMergeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SigMergeError::RequestMismatch => None,
+            SigMergeError::OutdatedRequest(_) => None,
+            SigMergeError::UnknownItem(_) => None,
+            SigMergeError::InvalidSignature(_) => None,
+            SigMergeError::UnknownKey(..) => None,
+            SigMergeError::TaprootKeySigPresent(_) => None,
+            SigMergeError::P2cTweak(_) => None,
+            SigMergeError::NonStandardSighashType(_) => None,
+            SigMergeError::Request(err) => Some(err),
+        }
+    }
+}
+
+/// Errors parsing Base64-encoded [`SigRequest`] and [`SigResponse`] payloads
+#[derive(Debug, Display, Error, From)]
+#[display(inner)]
+pub enum SigPayloadParseError {
+    /// Payload data encoding error
+    #[from]
+    Data(strict_encoding::Error),
+
+    /// Base64 encoding error
+    #[from]
+    Base64(base64::DecodeError),
+}
+
+/// Kind of the signature requested from the cosigner
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+pub enum SigScope {
+    /// ECDSA signature for pre-taproot inputs
+    #[display("ecdsa")]
+    Ecdsa,
+
+    /// BIP-340 signature for taproot key-path spending. The cosigner must
+    /// tweak the internal key with the provided merkle root (see BIP-341).
+    #[display("tr-key")]
+    TaprootKey(Option<TapBranchHash>),
+
+    /// BIP-340 signature for taproot script-path spending of the leaf with a
+    /// given hash
+    #[display("tr-script({0})")]
+    TaprootScript(TapLeafHash),
+}
+
+/// Single signature requested from the cosigner
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct SigRequestItem {
+    /// Index of the PSBT input which has to be signed
+    pub input_index: u16,
+
+    /// SHA256 hash of the consensus-serialized outpoint spent by the input,
+    /// allowing cosigner to track UTXOs without knowing transaction ids
+    pub outpoint_digest: sha256::Hash,
+
+    /// Kind of the signature which has to be created
+    pub scope: SigScope,
+
+    /// Sighash type which must be used in the signature
+    pub sighash_type: PsbtSighashType,
+
+    /// Message which has to be signed
+    pub sighash: sha256::Hash,
+
+    /// Origin of the key which must be used for signing
+    pub key_source: KeySource,
+
+    /// Pay-to-contract tweak which must be added to the derived private key
+    /// before signing
+    pub p2c_tweak: Option<sha256::Hash>,
+}
+
+impl SigRequestItem {
+    /// Computes digest of the given transaction outpoint, as used in
+    /// [`SigRequestItem::outpoint_digest`]
+    pub fn outpoint_digest(outpoint: OutPoint) -> sha256::Hash {
+        sha256::Hash::hash(&consensus::serialize(&outpoint))
+    }
+
+    fn message(&self) -> Message {
+        Message::from_slice(&self.sighash[..]).expect("sighash has message length")
+    }
+}
+
+/// Compact signature request for a cosigner device
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct SigRequest {
+    /// Hash of the wallet descriptor, allowing cosigner to check that it is
+    /// signing for a known wallet policy (see
+    /// [`SigRequest::descriptor_policy_hash`])
+    pub policy_hash: sha256::Hash,
+
+    /// Signatures requested from the cosigner
+    pub items: Vec<SigRequestItem>,
+}
+
+impl SigRequest {
+    /// Computes descriptor policy hash as a SHA256 hash of the descriptor
+    /// string representation
+    pub fn descriptor_policy_hash<Pk: MiniscriptKey>(descriptor: &Descriptor<Pk>) -> sha256::Hash {
+        sha256::Hash::hash(descriptor.to_string().as_bytes())
+    }
+
+    /// Returns unique identifier of the request, committing to all of its
+    /// data
+    pub fn request_id(&self) -> sha256::Hash {
+        sha256::Hash::hash(
+            &self
+                .strict_serialize()
+                .expect("in-memory strict encoding failure"),
+        )
+    }
+}
+
+/// Single signature produced by the cosigner in response to a
+/// [`SigRequestItem`]
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct SigResponseItem {
+    /// Position of the item in [`SigRequest::items`] list
+    pub request_item: u16,
+
+    /// Compact 64-byte ECDSA or BIP-340 signature, without sighash type
+    pub signature: Vec<u8>,
+}
+
+/// Compact response from a cosigner device containing signatures for a
+/// [`SigRequest`]
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct SigResponse {
+    /// Identifier of the request (see [`SigRequest::request_id`])
+    pub request_id: sha256::Hash,
+
+    /// Produced signatures
+    pub signatures: Vec<SigResponseItem>,
+}
+
+macro_rules! impl_base64_payload {
+    ($ty:ty) => {
+        impl Display for $ty {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                let engine = base64::engine::GeneralPurpose::new(
+                    &base64::alphabet::STANDARD,
+                    base64::engine::GeneralPurposeConfig::new(),
+                );
+                let data = self.strict_serialize().map_err(|_| fmt::Error)?;
+                f.write_str(&engine.encode(data))
+            }
+        }
+
+        impl FromStr for $ty {
+            type Err = SigPayloadParseError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let engine = base64::engine::GeneralPurpose::new(
+                    &base64::alphabet::STANDARD,
+                    base64::engine::GeneralPurposeConfig::new(),
+                );
+                let bytes = engine.decode(s)?;
+                Self::strict_deserialize(bytes).map_err(SigPayloadParseError::from)
+            }
+        }
+    };
+}
+
+impl_base64_payload!(SigRequest);
+impl_base64_payload!(SigResponse);
+
+impl Psbt {
+    /// Constructs compact signature request for all keys from PSBT inputs
+    /// having a given master key fingerprint (or for all keys, if no
+    /// fingerprint is provided).
+    pub fn sig_request<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        policy_hash: sha256::Hash,
+        master_fingerprint: Option<Fingerprint>,
+    ) -> Result<SigRequest, SigRequestError> {
+        let tx = self.to_unsigned_tx();
+        let mut sig_hasher = SighashCache::new(&tx);
+
+        let txout_list = self
+            .inputs
+            .iter()
+            .map(|input| {
+                input
+                    .input_prevout()
+                    .cloned()
+                    .map_err(SignInputError::from)
+                    .map_err(|error| SigRequestError {
+                        error,
+                        input_index: input.index(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let prevouts = Prevouts::All(txout_list.as_ref());
+
+        let matches_fingerprint = |fingerprint: &Fingerprint| {
+            master_fingerprint.is_none() || master_fingerprint == Some(*fingerprint)
+        };
+
+        let mut items = vec![];
+        for input in &self.inputs {
+            let index = input.index();
+            let map_err = |error: SignInputError| SigRequestError {
+                error,
+                input_index: index,
+            };
+            let input_index =
+                u16::try_from(index).map_err(|_| map_err(SignInputError::IndexOverflow))?;
+            let item = |scope, sighash_type, sighash: &[u8], key_source, tweak| SigRequestItem {
+                input_index,
+                outpoint_digest: SigRequestItem::outpoint_digest(input.previous_outpoint),
+                scope,
+                sighash_type,
+                sighash: sha256::Hash::from_slice(sighash).expect("sighash length"),
+                key_source,
+                p2c_tweak: input
+                    .p2c_tweak(tweak)
+                    .map(|tweak| sha256::Hash::from_inner(tweak.into_inner())),
+            };
+
+            if let Some((sighash, sighash_type)) =
+                input.ecdsa_sighash(&mut sig_hasher).map_err(map_err)?
+            {
+                for (pubkey, key_source) in &input.bip32_derivation {
+                    if !matches_fingerprint(&key_source.0) {
+                        continue;
+                    }
+                    items.push(item(
+                        SigScope::Ecdsa,
+                        sighash_type.into(),
+                        &sighash[..],
+                        key_source.clone(),
+                        *pubkey,
+                    ));
+                }
+                continue;
+            }
+
+            if input.tap_key_origins.is_empty() {
+                continue;
+            }
+            let sighash_type = input
+                .taproot_sighash_type(secp, &prevouts)
+                .map_err(map_err)?;
+            for (pubkey, (leaves, key_source)) in &input.tap_key_origins {
+                if !matches_fingerprint(&key_source.0) {
+                    continue;
+                }
+                let full_key =
+                    secp256k1::PublicKey::from_x_only_public_key(*pubkey, secp256k1::Parity::Even);
+                for leaf_hash in leaves {
+                    let sighash = taproot_sighash(
+                        &mut sig_hasher,
+                        index,
+                        &prevouts,
+                        None,
+                        TapSpendPath::script(*leaf_hash),
+                        sighash_type,
+                    )
+                    .map_err(SignInputError::from)
+                    .map_err(map_err)?;
+                    items.push(item(
+                        SigScope::TaprootScript(*leaf_hash),
+                        sighash_type.into(),
+                        &sighash[..],
+                        key_source.clone(),
+                        full_key,
+                    ));
+                }
+                if input.tap_internal_key == Some(*pubkey) {
+                    let sighash = taproot_sighash(
+                        &mut sig_hasher,
+                        index,
+                        &prevouts,
+                        None,
+                        TapSpendPath::Key,
+                        sighash_type,
+                    )
+                    .map_err(SignInputError::from)
+                    .map_err(map_err)?;
+                    items.push(item(
+                        SigScope::TaprootKey(input.tap_merkle_root),
+                        sighash_type.into(),
+                        &sighash[..],
+                        key_source.clone(),
+                        full_key,
+                    ));
+                }
+            }
+        }
+
+        Ok(SigRequest { policy_hash, items })
+    }
+
+    /// Verifies signatures returned by a cosigner device and adds them to
+    /// the PSBT.
+    ///
+    /// Checks that the request matches current PSBT data, i.e. that the PSBT
+    /// was not modified after the request was created.
+    ///
+    /// # Returns
+    ///
+    /// Number of signatures added to the PSBT.
+    pub fn merge_sig_response<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        request: &SigRequest,
+        response: &SigResponse,
+    ) -> Result<usize, SigMergeError> {
+        if response.request_id != request.request_id() {
+            return Err(SigMergeError::RequestMismatch);
+        }
+
+        let actual = self.sig_request(secp, request.policy_hash, None)?;
+
+        let mut count = 0usize;
+        for SigResponseItem {
+            request_item: no,
+            signature,
+        } in &response.signatures
+        {
+            let no = *no;
+            let item = request
+                .items
+                .get(no as usize)
+                .ok_or(SigMergeError::UnknownItem(no))?;
+            if !actual.items.contains(item) {
+                return Err(SigMergeError::OutdatedRequest(no));
+            }
+            let input = &mut self.inputs[item.input_index as usize];
+            let msg = item.message();
+            let tweak = item.p2c_tweak.map(|tweak| {
+                secp256k1::Scalar::from_be_bytes(tweak.into_inner())
+                    .expect("negligible probability")
+            });
+            let unknown_key = || {
+                SigMergeError::UnknownKey(
+                    no,
+                    format!("[{}]{}", item.key_source.0, item.key_source.1),
+                )
+            };
+
+            match item.scope {
+                SigScope::Ecdsa => {
+                    let pubkey = input
+                        .bip32_derivation
+                        .iter()
+                        .find(|(_, source)| *source == &item.key_source)
+                        .map(|(pk, _)| *pk)
+                        .ok_or_else(unknown_key)?;
+                    let tweaked = match tweak {
+                        Some(tweak) => pubkey
+                            .add_exp_tweak(secp, &tweak)
+                            .map_err(|_| SigMergeError::P2cTweak(no))?,
+                        None => pubkey,
+                    };
+                    let sig = ecdsa::Signature::from_compact(signature)
+                        .map_err(|_| SigMergeError::InvalidSignature(no))?;
+                    secp.verify_ecdsa(&msg, &sig, &tweaked)
+                        .map_err(|_| SigMergeError::InvalidSignature(no))?;
+                    let hash_ty = item.sighash_type.ecdsa_hash_ty().map_err(|_| {
+                        SigMergeError::NonStandardSighashType(item.sighash_type.to_u32())
+                    })?;
+                    input
+                        .partial_sigs
+                        .insert(bitcoin::PublicKey::new(pubkey), EcdsaSig { sig, hash_ty });
+                }
+                SigScope::TaprootKey(_) | SigScope::TaprootScript(_) => {
+                    let pubkey = input
+                        .tap_key_origins
+                        .iter()
+                        .find(|(_, (_, source))| source == &item.key_source)
+                        .map(|(pk, _)| *pk)
+                        .ok_or_else(unknown_key)?;
+                    let mut tweaked = match tweak {
+                        Some(tweak) => {
+                            pubkey
+                                .add_tweak(secp, &tweak)
+                                .map_err(|_| SigMergeError::P2cTweak(no))?
+                                .0
+                        }
+                        None => pubkey,
+                    };
+                    if let SigScope::TaprootKey(merkle_root) = item.scope {
+                        tweaked = tweaked.tap_tweak(secp, merkle_root).0.to_inner();
+                    }
+                    let sig = schnorr::Signature::from_slice(signature)
+                        .map_err(|_| SigMergeError::InvalidSignature(no))?;
+                    secp.verify_schnorr(&sig, &msg, &tweaked)
+                        .map_err(|_| SigMergeError::InvalidSignature(no))?;
+                    let hash_ty = item.sighash_type.schnorr_hash_ty().map_err(|_| {
+                        SigMergeError::NonStandardSighashType(item.sighash_type.to_u32())
+                    })?;
+                    let sig = SchnorrSig { sig, hash_ty };
+                    match item.scope {
+                        SigScope::TaprootScript(leaf_hash) => {
+                            input.tap_script_sigs.insert((pubkey, leaf_hash), sig);
+                        }
+                        _ if input.tap_key_sig.is_some() => {
+                            return Err(SigMergeError::TaprootKeySigPresent(item.input_index))
+                        }
+                        _ => input.tap_key_sig = Some(sig),
+                    }
+                }
+            }
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::secp256k1::{KeyPair, SecretKey};
+    use bitcoin::util::bip32::DerivationPath;
+    use bitcoin::{PackedLockTime, Script, Transaction, TxIn, TxOut, Txid};
+
+    use super::*;
+    use crate::PsbtVersion;
+
+    fn key_source(account: u32) -> KeySource {
+        (
+            Fingerprint::from(&[0xDE, 0xAD, 0xBE, 0xEF][..]),
+            DerivationPath::from_str(&format!("m/84'/0'/{}'/0/0", account)).unwrap(),
+        )
+    }
+
+    fn psbt(secp: &Secp256k1<secp256k1::All>, wpkh: SecretKey, tr: SecretKey) -> Psbt {
+        let txin = |vout| TxIn {
+            previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), vout),
+            ..TxIn::default()
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![txin(0), txin(1)],
+            output: vec![TxOut {
+                value: 19_000,
+                script_pubkey: Script::new_op_return(&[]),
+            }],
+        };
+        let mut psbt = Psbt::with(tx, PsbtVersion::V0).unwrap();
+
+        let pubkey = wpkh.public_key(secp);
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: Script::new_v0_p2wpkh(
+                &bitcoin::PublicKey::new(pubkey).wpubkey_hash().unwrap(),
+            ),
+        });
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(pubkey, key_source(0));
+
+        let (xonly, _) = tr.public_key(secp).x_only_public_key();
+        psbt.inputs[1].witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: Script::new_v1_p2tr(secp, xonly, None),
+        });
+        psbt.inputs[1].tap_internal_key = Some(xonly);
+        psbt.inputs[1]
+            .tap_key_origins
+            .insert(xonly, (vec![], key_source(1)));
+
+        psbt
+    }
+
+    fn respond(
+        secp: &Secp256k1<secp256k1::All>,
+        request: &SigRequest,
+        wpkh: SecretKey,
+        tr: SecretKey,
+    ) -> SigResponse {
+        let signatures = request
+            .items
+            .iter()
+            .enumerate()
+            .map(|(no, item)| {
+                let signature = match item.scope {
+                    SigScope::Ecdsa => secp
+                        .sign_ecdsa(&item.message(), &wpkh)
+                        .serialize_compact()
+                        .to_vec(),
+                    SigScope::TaprootKey(merkle_root) => {
+                        let keypair = KeyPair::from_secret_key(secp, &tr)
+                            .tap_tweak(secp, merkle_root)
+                            .to_inner();
+                        secp.sign_schnorr_no_aux_rand(&item.message(), &keypair)[..].to_vec()
+                    }
+                    SigScope::TaprootScript(_) => unreachable!("no script leaves"),
+                };
+                SigResponseItem {
+                    request_item: no as u16,
+                    signature,
+                }
+            })
+            .collect();
+        SigResponse {
+            request_id: request.request_id(),
+            signatures,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let secp = Secp256k1::new();
+        let wpkh = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let tr = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let mut psbt = psbt(&secp, wpkh, tr);
+        let policy_hash = sha256::Hash::hash(b"policy");
+
+        let request = psbt.sig_request(&secp, policy_hash, None).unwrap();
+        assert_eq!(request.policy_hash, policy_hash);
+        assert_eq!(request.items.len(), 2);
+        assert_eq!(request.items[0].input_index, 0);
+        assert_eq!(request.items[0].scope, SigScope::Ecdsa);
+        assert_eq!(request.items[0].key_source, key_source(0));
+        assert_eq!(request.items[1].input_index, 1);
+        assert_eq!(request.items[1].scope, SigScope::TaprootKey(None));
+        assert_eq!(request.items[1].key_source, key_source(1));
+
+        let other = Fingerprint::from(&[0x01, 0x02, 0x03, 0x04][..]);
+        let filtered = psbt.sig_request(&secp, policy_hash, Some(other)).unwrap();
+        assert!(filtered.items.is_empty());
+
+        let response = respond(&secp, &request, wpkh, tr);
+        assert_eq!(SigRequest::from_str(&request.to_string()).unwrap(), request);
+        assert_eq!(
+            SigResponse::from_str(&response.to_string()).unwrap(),
+            response
+        );
+
+        assert_eq!(
+            psbt.merge_sig_response(&secp, &request, &response).unwrap(),
+            2
+        );
+        let pubkey = bitcoin::PublicKey::new(wpkh.public_key(&secp));
+        assert!(psbt.inputs[0].partial_sigs.contains_key(&pubkey));
+        assert!(psbt.inputs[1].tap_key_sig.is_some());
+
+        assert!(matches!(
+            psbt.merge_sig_response(&secp, &request, &response),
+            Err(SigMergeError::TaprootKeySigPresent(1))
+        ));
+    }
+
+    #[test]
+    fn policy_mismatch() {
+        let secp = Secp256k1::new();
+        let wpkh = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let tr = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let mut psbt = psbt(&secp, wpkh, tr);
+
+        let request = psbt
+            .sig_request(&secp, sha256::Hash::hash(b"policy"), None)
+            .unwrap();
+        let response = respond(&secp, &request, wpkh, tr);
+
+        let mut forged = request.clone();
+        forged.policy_hash = sha256::Hash::hash(b"other policy");
+        assert!(matches!(
+            psbt.merge_sig_response(&secp, &forged, &response),
+            Err(SigMergeError::RequestMismatch)
+        ));
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+        assert!(psbt.inputs[1].tap_key_sig.is_none());
+    }
+
+    #[test]
+    fn merge_errors() {
+        let secp = Secp256k1::new();
+        let wpkh = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let tr = SecretKey::from_slice(&[0x22; 32]).unwrap();
+        let mut psbt = psbt(&secp, wpkh, tr);
+        let request = psbt
+            .sig_request(&secp, sha256::Hash::hash(b"policy"), None)
+            .unwrap();
+
+        let mut response = respond(&secp, &request, tr, wpkh);
+        assert!(matches!(
+            psbt.merge_sig_response(&secp, &request, &response),
+            Err(SigMergeError::InvalidSignature(0))
+        ));
+
+        response.signatures[0].request_item = 2;
+        assert!(matches!(
+            psbt.merge_sig_response(&secp, &request, &response),
+            Err(SigMergeError::UnknownItem(2))
+        ));
+
+        let response = respond(&secp, &request, wpkh, tr);
+        psbt.outputs[0].amount = 18_000;
+        assert!(matches!(
+            psbt.merge_sig_response(&secp, &request, &response),
+            Err(SigMergeError::OutdatedRequest(0))
+        ));
+    }
+
+    #[test]
+    fn input_index_overflow() {
+        let secp = Secp256k1::new();
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: (0..=u16::MAX as u32 + 1)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), vout),
+                    ..TxIn::default()
+                })
+                .collect(),
+            output: vec![],
+        };
+        let mut psbt = Psbt::with(tx, PsbtVersion::V0).unwrap();
+        let prevout = TxOut {
+            value: 1_000,
+            script_pubkey: Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::all_zeros()),
+        };
+        for input in &mut psbt.inputs {
+            input.witness_utxo = Some(prevout.clone());
+        }
+
+        let err = psbt
+            .sig_request(&secp, sha256::Hash::hash(b"policy"), None)
+            .unwrap_err();
+        assert!(matches!(err.error, SignInputError::IndexOverflow));
+        assert_eq!(err.input_index, u16::MAX as usize + 1);
+    }
+}
@@ -0,0 +1,123 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Sign-to-contract (S2C) signing: embedding commitments into ECDSA
+//! signature nonces.
+//!
+//! Follows `ecdsa_s2c` module of libsecp256k1-zkp. Signer derives original
+//! nonce `k0` with RFC6979 using `H_data(c)` as extra entropy and tweaks it
+//! with `t = H_point(R0 || c)`, where `c` is the commitment and `R0 = k0·G`,
+//! producing signature with nonce point `R = R0 + t·G`. Original nonce point
+//! `R0` (the S2C opening) serves as a proof of the commitment: anybody
+//! knowing it may check that the signature nonce commits to `c`.
+//!
+//! Signing is performed by libsecp256k1-zkp, which keeps all operations on
+//! the secret values constant-time. Sign-to-contract for BIP-340 signatures
+//! is not specified by libsecp256k1-zkp and is not supported.
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{ecdsa, Message, PublicKey, Secp256k1, SecretKey, Signing, Verification};
+use secp256k1_zkp::EcdsaS2cOpening;
+
+/// Tag of the hash used as RFC6979 extra entropy for the original nonce.
+pub(super) const S2C_DATA_TAG: &[u8] = b"s2c/ecdsa/data";
+
+/// Computes BIP-340-style tagged hash of the concatenated `data`.
+pub(super) fn tagged_hash(tag: &[u8], data: &[&[u8]]) -> sha256::Hash {
+    let tag_hash = sha256::Hash::hash(tag);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag_hash[..]);
+    engine.input(&tag_hash[..]);
+    for item in data {
+        engine.input(item);
+    }
+    sha256::Hash::from_engine(engine)
+}
+
+fn opening_from_proof(proof: PublicKey) -> Option<EcdsaS2cOpening> {
+    EcdsaS2cOpening::from_slice(&proof.serialize()).ok()
+}
+
+pub(super) fn proof_from_opening(opening: EcdsaS2cOpening) -> PublicKey {
+    PublicKey::from_slice(&opening.serialize()).expect("S2C opening is a valid point")
+}
+
+/// Verifies that the ECDSA signature nonce commits to the `commitment` using
+/// the original nonce point as a proof.
+pub fn verify_s2c_commitment<C: Verification>(
+    secp: &Secp256k1<C>,
+    sig: &ecdsa::Signature,
+    proof: PublicKey,
+    commitment: sha256::Hash,
+) -> bool {
+    opening_from_proof(proof)
+        .map(|opening| opening.verify_commit(secp, sig, &commitment.into_inner()))
+        .unwrap_or_default()
+}
+
+/// Creates ECDSA signature committing to `commitment` in its nonce.
+///
+/// # Returns
+///
+/// Low-S signature and the original nonce point, which serves as a proof of
+/// the commitment.
+pub fn sign_ecdsa_s2c<C: Signing>(
+    secp: &Secp256k1<C>,
+    msg: &Message,
+    seckey: &SecretKey,
+    commitment: sha256::Hash,
+) -> (ecdsa::Signature, PublicKey) {
+    let (sig, opening) = EcdsaS2cOpening::sign(secp, msg, seckey, &commitment.into_inner());
+    (sig, proof_from_opening(opening))
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::secp256k1::{Scalar, SECP256K1};
+
+    use super::*;
+
+    /// Tag of the hash used for tweaking the original nonce point.
+    const S2C_POINT_TAG: &[u8] = b"s2c/ecdsa/point";
+
+    /// Recomputes S2C commitment using the protocol definition, without
+    /// calling into libsecp256k1-zkp.
+    fn s2c_nonce(proof: PublicKey, commitment: sha256::Hash) -> [u8; 32] {
+        let tweak = tagged_hash(S2C_POINT_TAG, &[&proof.serialize(), &commitment[..]]);
+        let tweak = Scalar::from_be_bytes(tweak.into_inner()).unwrap();
+        let nonce = proof.add_exp_tweak(SECP256K1, &tweak).unwrap();
+        nonce.x_only_public_key().0.serialize()
+    }
+
+    #[test]
+    fn s2c_signatures() {
+        let seckey = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(SECP256K1, &seckey);
+        let msg = Message::from_slice(&[0x01; 32]).unwrap();
+        let commitment = sha256::Hash::hash(b"commitment");
+        let other = sha256::Hash::hash(b"other commitment");
+
+        let (sig, proof) = sign_ecdsa_s2c(SECP256K1, &msg, &seckey, commitment);
+        SECP256K1.verify_ecdsa(&msg, &sig, &pubkey).unwrap();
+        assert!(verify_s2c_commitment(SECP256K1, &sig, proof, commitment));
+        assert!(!verify_s2c_commitment(SECP256K1, &sig, proof, other));
+        assert_eq!(
+            s2c_nonce(proof, commitment)[..],
+            sig.serialize_compact()[..32]
+        );
+
+        let (sig2, proof2) = sign_ecdsa_s2c(SECP256K1, &msg, &seckey, commitment);
+        assert_eq!((sig, proof), (sig2, proof2));
+    }
+}
@@ -0,0 +1,403 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! BIP-341 signature hash computation used by the PSBT signer, exposed for
+//! the external protocols (adaptor signatures, MuSig2 sessions etc) which
+//! must produce signatures over exactly the same messages.
+
+use core::borrow::Borrow;
+use core::ops::Deref;
+
+use bitcoin::util::sighash::{self, Annex, Prevouts, SighashCache};
+use bitcoin::util::taproot::{TapLeafHash, TapSighashHash};
+use bitcoin::{SchnorrSighashType, Transaction, TxOut};
+
+/// Taproot spending path, defining the value of BIP-341 `ext_flag` and the
+/// signature message extension used in the signature hash computation.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub enum TapSpendPath {
+    /// Key path spending (`ext_flag = 0`), which has no message extension.
+    Key,
+
+    /// Script path spending (`ext_flag = 1`) with the BIP-342 message
+    /// extension.
+    Script {
+        /// Hash of the tapscript leaf being spent.
+        leaf_hash: TapLeafHash,
+        /// Opcode position of the last executed `OP_CODESEPARATOR`, or
+        /// `0xFFFFFFFF` if none was executed.
+        codesep_pos: u32,
+    },
+}
+
+impl TapSpendPath {
+    /// Constructs script path spending of the leaf with the given hash, not
+    /// executing any `OP_CODESEPARATOR`s.
+    #[inline]
+    pub fn script(leaf_hash: impl Into<TapLeafHash>) -> TapSpendPath {
+        TapSpendPath::Script {
+            leaf_hash: leaf_hash.into(),
+            codesep_pos: u32::MAX,
+        }
+    }
+
+    /// Returns BIP-341 `ext_flag` value committed into the signature hash.
+    #[inline]
+    pub fn ext_flag(self) -> u8 {
+        match self {
+            TapSpendPath::Key => 0,
+            TapSpendPath::Script { .. } => 1,
+        }
+    }
+}
+
+/// Computes BIP-341 signature hash for the transaction input `input_index`
+/// from the transaction cached by `sig_hasher`.
+///
+/// This is the function used by the PSBT signer for both key and script path
+/// taproot spendings, so the signatures made over the returned hash are
+/// identical to the ones which will be produced by the signer.
+///
+/// # Errors
+///
+/// Errors if the input index is out of range, if `prevouts` does not match
+/// the transaction inputs or `sighash_type` requirements (i.e. a single
+/// prevout is provided for a non-`SIGHASH_ANYONECANPAY` type), or if
+/// `SIGHASH_SINGLE` is used for an input without the corresponding output.
+pub fn taproot_sighash<R, T>(
+    sig_hasher: &mut SighashCache<R>,
+    input_index: usize,
+    prevouts: &Prevouts<T>,
+    annex: Option<Annex>,
+    spend_path: TapSpendPath,
+    sighash_type: SchnorrSighashType,
+) -> Result<TapSighashHash, sighash::Error>
+where
+    R: Deref<Target = Transaction>,
+    T: Borrow<TxOut>,
+{
+    // rust-bitcoin does not check input index for non-`SIGHASH_ANYONECANPAY`
+    // types, since they do not serialize the input itself
+    if let Prevouts::All(prevouts) = prevouts {
+        if input_index >= prevouts.len() {
+            return Err(sighash::Error::IndexOutOfInputsBounds {
+                index: input_index,
+                inputs_size: prevouts.len(),
+            });
+        }
+    }
+    let leaf_hash_code_separator = match spend_path {
+        TapSpendPath::Key => None,
+        TapSpendPath::Script {
+            leaf_hash,
+            codesep_pos,
+        } => Some((leaf_hash, codesep_pos)),
+    };
+    sig_hasher.taproot_signature_hash(
+        input_index,
+        prevouts,
+        annex,
+        leaf_hash_code_separator,
+        sighash_type,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::consensus::{deserialize, serialize};
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::hashes::{sha256, Hash};
+    use bitcoin::util::taproot::{LeafVersion, TapBranchHash, TapTweakHash};
+    use bitcoin::{secp256k1, OutPoint, PackedLockTime, Script, Sequence, TxIn, Txid, Witness};
+
+    use super::*;
+
+    fn tx() -> (Transaction, Vec<TxOut>) {
+        let input = |vout| TxIn {
+            previous_output: OutPoint::new(Txid::from_inner([0xAB; 32]), vout),
+            script_sig: Script::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        };
+        let output = |value, byte| TxOut {
+            value,
+            script_pubkey: Script::from([vec![0x51, 0x20], vec![byte; 32]].concat()),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![input(0), input(1)],
+            output: vec![output(90_000, 0x03)],
+        };
+        (tx, vec![output(50_000, 0x01), output(50_000, 0x02)])
+    }
+
+    #[test]
+    fn key_and_script_path() {
+        let (tx, spent) = tx();
+        let prevouts = Prevouts::All(&spent[..]);
+        let mut sig_hasher = SighashCache::new(&tx);
+
+        let key = taproot_sighash(
+            &mut sig_hasher,
+            0,
+            &prevouts,
+            None,
+            TapSpendPath::Key,
+            SchnorrSighashType::Default,
+        )
+        .unwrap();
+        assert_eq!(
+            key,
+            sig_hasher
+                .taproot_key_spend_signature_hash(0, &prevouts, SchnorrSighashType::Default)
+                .unwrap()
+        );
+
+        let script = Script::from(vec![0x51]);
+        let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+        let script_path = TapSpendPath::script(leaf_hash);
+        assert_eq!(TapSpendPath::Key.ext_flag(), 0);
+        assert_eq!(script_path.ext_flag(), 1);
+        let script = taproot_sighash(
+            &mut sig_hasher,
+            0,
+            &prevouts,
+            None,
+            script_path,
+            SchnorrSighashType::All,
+        )
+        .unwrap();
+        assert_eq!(
+            script,
+            sig_hasher
+                .taproot_script_spend_signature_hash(
+                    0,
+                    &prevouts,
+                    leaf_hash,
+                    SchnorrSighashType::All,
+                )
+                .unwrap()
+        );
+        assert_ne!(key, script);
+
+        let codesep = taproot_sighash(
+            &mut sig_hasher,
+            0,
+            &prevouts,
+            None,
+            TapSpendPath::Script {
+                leaf_hash,
+                codesep_pos: 0,
+            },
+            SchnorrSighashType::All,
+        )
+        .unwrap();
+        assert_ne!(codesep, script);
+
+        let annex = [0x50, 0x01, 0x02];
+        let annexed = taproot_sighash(
+            &mut sig_hasher,
+            0,
+            &prevouts,
+            Some(Annex::new(&annex).unwrap()),
+            TapSpendPath::Key,
+            SchnorrSighashType::Default,
+        )
+        .unwrap();
+        assert_ne!(annexed, key);
+    }
+
+    /// Reads BIP-341 `wallet-test-vectors.json`, vendored from
+    /// <https://github.com/bitcoin/bips/blob/master/bip-0341/wallet-test-vectors.json>
+    fn bip341_vectors() -> serde_json::Value {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/bip341-wallet-test-vectors.json"
+        );
+        let json = std::fs::read_to_string(path)
+            .unwrap_or_else(|_| panic!("BIP-341 test vectors are not vendored at {}", path));
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn hex(value: &serde_json::Value) -> Vec<u8> {
+        Vec::<u8>::from_hex(value.as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    #[ignore = "requires vendored tests/data/bip341-wallet-test-vectors.json"]
+    fn bip341_key_path_vectors() {
+        let secp = secp256k1::Secp256k1::new();
+        let data = bip341_vectors();
+        assert_eq!(data["version"].as_u64(), Some(1));
+
+        for key_path in data["keyPathSpending"].as_array().unwrap() {
+            let given = &key_path["given"];
+            let tx: Transaction = deserialize(&hex(&given["rawUnsignedTx"])).unwrap();
+            let utxos = given["utxosSpent"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|utxo| TxOut {
+                    value: utxo["amountSats"].as_u64().unwrap(),
+                    script_pubkey: Script::from(hex(&utxo["scriptPubKey"])),
+                })
+                .collect::<Vec<_>>();
+
+            let intermediary = &key_path["intermediary"];
+            let hash = |data: Vec<u8>| sha256::Hash::hash(&data).to_vec();
+            let concat = |items: Vec<Vec<u8>>| items.concat();
+            assert_eq!(
+                hash(concat(
+                    utxos.iter().map(|utxo| serialize(&utxo.value)).collect()
+                )),
+                hex(&intermediary["hashAmounts"])
+            );
+            assert_eq!(
+                hash(concat(tx.output.iter().map(serialize).collect())),
+                hex(&intermediary["hashOutputs"])
+            );
+            assert_eq!(
+                hash(concat(
+                    tx.input
+                        .iter()
+                        .map(|txin| serialize(&txin.previous_output))
+                        .collect()
+                )),
+                hex(&intermediary["hashPrevouts"])
+            );
+            assert_eq!(
+                hash(concat(
+                    utxos
+                        .iter()
+                        .map(|utxo| serialize(&utxo.script_pubkey))
+                        .collect()
+                )),
+                hex(&intermediary["hashScriptPubkeys"])
+            );
+            assert_eq!(
+                hash(concat(
+                    tx.input
+                        .iter()
+                        .map(|txin| serialize(&txin.sequence))
+                        .collect()
+                )),
+                hex(&intermediary["hashSequences"])
+            );
+
+            let prevouts = Prevouts::All(&utxos[..]);
+            let mut sig_hasher = SighashCache::new(&tx);
+            let inputs = key_path["inputSpending"].as_array().unwrap();
+            assert!(!inputs.is_empty());
+            for input in inputs {
+                let given = &input["given"];
+                let intermediary = &input["intermediary"];
+                let index = given["txinIndex"].as_u64().unwrap() as usize;
+                let sighash_type = SchnorrSighashType::from_consensus_u8(
+                    given["hashType"].as_u64().unwrap() as u8,
+                )
+                .unwrap();
+                let merkle_root = given["merkleRoot"]
+                    .as_str()
+                    .map(|root| TapBranchHash::from_hex(root).unwrap());
+                let seckey =
+                    secp256k1::SecretKey::from_slice(&hex(&given["internalPrivkey"])).unwrap();
+                let keypair = secp256k1::KeyPair::from_secret_key(&secp, &seckey);
+                let (internal_key, _) = keypair.x_only_public_key();
+                let tweak = TapTweakHash::from_key_and_tweak(internal_key, merkle_root);
+                let tweaked = keypair.add_xonly_tweak(&secp, &tweak.to_scalar()).unwrap();
+
+                assert_eq!(
+                    internal_key.serialize().to_vec(),
+                    hex(&intermediary["internalPubkey"])
+                );
+                assert_eq!(tweak.to_vec(), hex(&intermediary["tweak"]));
+                assert_eq!(
+                    tweaked.secret_bytes().to_vec(),
+                    hex(&intermediary["tweakedPrivkey"])
+                );
+
+                let mut sig_msg = vec![];
+                sig_hasher
+                    .taproot_encode_signing_data_to(
+                        &mut sig_msg,
+                        index,
+                        &prevouts,
+                        None,
+                        None,
+                        sighash_type,
+                    )
+                    .unwrap();
+                assert_eq!(sig_msg, hex(&intermediary["sigMsg"]));
+
+                let sighash = taproot_sighash(
+                    &mut sig_hasher,
+                    index,
+                    &prevouts,
+                    None,
+                    TapSpendPath::Key,
+                    sighash_type,
+                )
+                .unwrap();
+                assert_eq!(sighash.to_vec(), hex(&intermediary["sigHash"]));
+            }
+        }
+    }
+
+    #[test]
+    fn prevouts_requirements() {
+        let (tx, spent) = tx();
+        let mut sig_hasher = SighashCache::new(&tx);
+
+        let one = Prevouts::One(1, &spent[1]);
+        assert!(taproot_sighash(
+            &mut sig_hasher,
+            1,
+            &one,
+            None,
+            TapSpendPath::Key,
+            SchnorrSighashType::All,
+        )
+        .is_err());
+        assert!(taproot_sighash(
+            &mut sig_hasher,
+            1,
+            &one,
+            None,
+            TapSpendPath::Key,
+            SchnorrSighashType::AllPlusAnyoneCanPay,
+        )
+        .is_ok());
+
+        let all = Prevouts::All(&spent[..]);
+        assert!(taproot_sighash(
+            &mut sig_hasher,
+            2,
+            &all,
+            None,
+            TapSpendPath::Key,
+            SchnorrSighashType::Default,
+        )
+        .is_err());
+        assert!(taproot_sighash(
+            &mut sig_hasher,
+            1,
+            &all,
+            None,
+            TapSpendPath::Key,
+            SchnorrSighashType::Single,
+        )
+        .is_err());
+    }
+}
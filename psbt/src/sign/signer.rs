@@ -16,14 +16,20 @@
 
 #![allow(clippy::result_large_err)]
 
+use core::fmt::{self, Display, Formatter};
 use core::ops::Deref;
+use std::collections::BTreeMap;
 
 use amplify::Wrapper;
+use bitcoin::blockdata::script::Instruction;
 use bitcoin::hashes::Hash;
+use bitcoin::psbt::PsbtSighashType;
 use bitcoin::schnorr::TapTweak;
-use bitcoin::secp256k1::{self, KeyPair, Signing, Verification, XOnlyPublicKey};
+use bitcoin::secp256k1::{
+    self, KeyPair, Message, Secp256k1, Signing, Verification, XOnlyPublicKey,
+};
 use bitcoin::util::address::WitnessVersion;
-use bitcoin::util::sighash::{self, Prevouts, ScriptPath, SighashCache};
+use bitcoin::util::sighash::{self, Prevouts, SighashCache};
 use bitcoin::util::taproot::TapLeafHash;
 use bitcoin::{
     EcdsaSig, EcdsaSighashType, PubkeyHash, PublicKey, SchnorrSig, SchnorrSighashType, Script,
@@ -33,17 +39,102 @@ use bitcoin_scripts::{PubkeyScript, RedeemScript};
 use descriptors::{CompositeDescrType, DeductionError};
 use miniscript::{Miniscript, ToPublicKey};
 
-use super::SecretProvider;
-use crate::{Input, InputMatchError, Psbt};
+use super::{sign_ecdsa_s2c, taproot_sighash, SecretProvider, TapSpendPath};
+use crate::{FeeError, Input, InputMatchError, Psbt, TapHiddenError};
 
 /// Errors happening during whole PSBT signing process
-#[derive(Debug, Display, Error)]
-#[display("failed to sign input #{input_index} because {error}")]
-pub struct SignError {
-    /// Signing error originating from a specific transaction input
-    pub error: SignInputError,
-    /// Index of the transaction input that has generated a error
-    pub input_index: usize,
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum SignError {
+    /// failed to sign input #{input_index} because {error}
+    Input {
+        /// Signing error originating from a specific transaction input
+        error: SignInputError,
+        /// Index of the transaction input that has generated a error
+        input_index: usize,
+    },
+
+    /// transaction fee of {fee} sats exceeds maximum of {max_fee} sats allowed
+    /// by the signing policy
+    FeeExceeded {
+        /// Transaction fee, in satoshis
+        fee: u64,
+        /// Maximum fee allowed by the signing policy, in satoshis
+        max_fee: u64,
+    },
+
+    /// unable to check transaction fee required by the signing policy: {0}
+    #[from]
+    Fee(FeeError),
+}
+
+/// Restrictions on the PSBTs which the signer agrees to sign, protecting hot
+/// signers from abuse when PSBTs come from less-trusted constructors.
+///
+/// Default policy imposes no restrictions.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct SigningPolicy {
+    /// Forbids signing inputs with sighash types other than `SIGHASH_ALL`
+    /// (absent sighash type and taproot `SIGHASH_DEFAULT` are treated as
+    /// `SIGHASH_ALL`), except the types whitelisted for the input in
+    /// [`SigningPolicy::input_sighash_types`].
+    pub sighash_all_only: bool,
+
+    /// Sighash types allowed for specific inputs, indexed by input number,
+    /// when [`SigningPolicy::sighash_all_only`] is set.
+    pub input_sighash_types: BTreeMap<usize, Vec<PsbtSighashType>>,
+
+    /// Rejects inputs not providing `witness_utxo`, which prevents signing
+    /// of legacy inputs and inputs with unverifiable spent amounts.
+    pub require_witness_utxo: bool,
+
+    /// Maximum transaction fee, in satoshis
+    pub max_fee: Option<u64>,
+}
+
+impl SigningPolicy {
+    /// Constructs policy allowing only `SIGHASH_ALL` signatures on inputs
+    /// providing `witness_utxo`, with no limit on the fee.
+    pub fn strict() -> SigningPolicy {
+        SigningPolicy {
+            sighash_all_only: true,
+            require_witness_utxo: true,
+            ..default!()
+        }
+    }
+
+    /// Whitelists sighash type for the input with a given `index`.
+    pub fn allow_input_sighash_type(&mut self, index: usize, sighash_type: PsbtSighashType) {
+        let allowed = self.input_sighash_types.entry(index).or_default();
+        if !allowed.contains(&sighash_type) {
+            allowed.push(sighash_type);
+        }
+    }
+
+    /// Sets the maximum transaction fee, in satoshis.
+    pub fn with_max_fee(mut self, max_fee: u64) -> SigningPolicy {
+        self.max_fee = Some(max_fee);
+        self
+    }
+}
+
+/// List of sighash types used for reporting signer configuration in errors
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct SighashTypeList(pub Vec<PsbtSighashType>);
+
+impl Display for SighashTypeList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("none");
+        }
+        for (no, sighash_type) in self.0.iter().enumerate() {
+            if no > 0 {
+                f.write_str(", ")?;
+            }
+            Display::fmt(sighash_type, f)?;
+        }
+        Ok(())
+    }
 }
 
 /// Errors happening during PSBT input signing process
@@ -114,6 +205,15 @@ pub enum SignInputError {
     /// error applying pay-to-contract public key tweak
     P2cTweak,
 
+    /// input contains sign-to-contract commitment, which can't be embedded
+    /// into taproot (BIP-340) signatures
+    SchnorrS2cUnsupported,
+
+    /// public key {0} produced by applying pay-to-contract tweak is not
+    /// present in the previous output `scriptPubkey` or the scripts of the
+    /// input
+    P2cPubkeyMismatch(secp256k1::PublicKey),
+
     /// error applying tweak matching public key {0}: the tweak
     /// value is either a modulo-negation of the original private key, or
     /// it leads to elliptic curve prime field order (`p`) overflow
@@ -126,6 +226,14 @@ pub enum SignInputError {
     /// non-standard sig hash type {sighash_type} used in PSBT for input {index}
     NonStandardSighashType { sighash_type: u32, index: usize },
 
+    /// input requests sighash type {requested} while other inputs use
+    /// different sighash types; signer configuration allows mixing only
+    /// SIGHASH_ALL and the following sighash types: {allowed}
+    MixedSighashType {
+        requested: PsbtSighashType,
+        allowed: SighashTypeList,
+    },
+
     /// trying to add to aggregated signature second copy of the signature made
     /// made with the negation of the key (previous sig `R` value is {0}, added
     /// sig `R` value is {1}).
@@ -134,6 +242,20 @@ pub enum SignInputError {
     /// trying to add to aggregated signature another signature with non-unique
     /// nonce value (previous `s` value is {0}, added nonce value is {1:02x?}).
     RepeatedSigNonce(String, Box<[u8]>),
+
+    /// invalid hidden taproot leaf data: {0}
+    #[from]
+    TapHidden(TapHiddenError),
+
+    /// input sighash type {0} is forbidden by the signing policy
+    ForbiddenSighashType(PsbtSighashType),
+
+    /// input does not provide `witness_utxo` required by the signing policy
+    NoWitnessUtxo,
+
+    /// input index exceeds 65535, which is the maximum supported by compact
+    /// signature requests
+    IndexOverflow,
 }
 
 impl std::error::Error for SignInputError {
@@ -149,6 +271,7 @@ impl std::error::Error for SignInputError {
             SignInputError::SecpPrivkeyDerivation => None,
             SignInputError::ScriptPubkeyMismatch => None,
             SignInputError::P2cTweak => None,
+            SignInputError::P2cPubkeyMismatch(_) => None,
             SignInputError::TweakFailure(_) => None,
             SignInputError::NonTaprootV1 => None,
             SignInputError::TaprootKeySighashTypeMismatch { .. } => None,
@@ -157,8 +280,13 @@ impl std::error::Error for SignInputError {
             SignInputError::Match(err) => Some(err),
             SignInputError::InvalidRedeemScript => None,
             SignInputError::NonStandardSighashType { .. } => None,
+            SignInputError::MixedSighashType { .. } => None,
             SignInputError::RepeatedSig(..) => None,
             SignInputError::RepeatedSigNonce(..) => None,
+            SignInputError::TapHidden(err) => Some(err),
+            SignInputError::ForbiddenSighashType(_) => None,
+            SignInputError::NoWitnessUtxo => None,
+            SignInputError::IndexOverflow => None,
         }
     }
 }
@@ -166,10 +294,6 @@ impl std::error::Error for SignInputError {
 impl From<DeductionError> for SignInputError {
     fn from(err: DeductionError) -> Self {
         match err {
-            DeductionError::NonTaprootV1 => SignInputError::NonTaprootV1,
-            DeductionError::UnsupportedWitnessVersion(version) => {
-                SignInputError::FutureWitness(version)
-            }
             DeductionError::P2shWithoutRedeemScript => SignInputError::NoRedeemScript,
             DeductionError::InvalidRedeemScript => SignInputError::InvalidRedeemScript,
         }
@@ -179,7 +303,7 @@ impl From<DeductionError> for SignInputError {
 impl SignError {
     #[inline]
     pub fn with_input_no(error: SignInputError, input_index: usize) -> SignError {
-        SignError { error, input_index }
+        SignError::Input { error, input_index }
     }
 }
 
@@ -192,13 +316,44 @@ pub trait SignAll {
     /// outputs with both key- and script- spending paths. Supports all
     /// consensus sighash types.
     ///
+    /// PSBTs mixing different sighash types across inputs are rejected unless
+    /// all non-`SIGHASH_ALL` types are explicitly allowed by
+    /// [`SecretProvider::allowed_sighash_types`]; absent sighash type and
+    /// taproot `SIGHASH_DEFAULT` are treated as `SIGHASH_ALL`.
+    ///
+    /// Before signing any of the inputs the PSBT is checked against the
+    /// signing `policy`; no signatures are created if the check fails.
+    ///
     /// # Returns
     ///
     /// Number of created signatures or error. The number of signatures includes
     /// individual signatures created for different P2TR script spending paths,
     /// i.e. a transaction with one P2TR input having a single key may result
     /// in multiple signatures, one per each listed spending P2TR leaf.
-    fn sign_all<C>(&mut self, provider: &impl SecretProvider<C>) -> Result<usize, SignError>
+    fn sign_all<C>(
+        &mut self,
+        provider: &impl SecretProvider<C>,
+        policy: &SigningPolicy,
+    ) -> Result<usize, SignError>
+    where
+        C: Signing + Verification;
+
+    /// Signs all PSBT inputs in the same way as [`SignAll::sign_all`], but for
+    /// the inputs having sign-to-contract (S2C) commitment
+    /// ([`crate::PSBT_IN_S2C_COMMITMENT`] key) embeds the commitment into the
+    /// nonces of all created ECDSA signatures. The original nonce point for
+    /// each of the signatures, serving as a commitment proof, is saved into
+    /// the input [`crate::PSBT_IN_S2C_PROOF`] key. Taproot inputs with S2C
+    /// commitment fail with [`SignInputError::SchnorrS2cUnsupported`].
+    ///
+    /// # Returns
+    ///
+    /// Number of created signatures or error, like for [`SignAll::sign_all`].
+    fn sign_all_s2c<C>(
+        &mut self,
+        provider: &impl SecretProvider<C>,
+        policy: &SigningPolicy,
+    ) -> Result<usize, SignError>
     where
         C: Signing + Verification;
 }
@@ -207,7 +362,30 @@ impl SignAll for Psbt {
     fn sign_all<C: Signing + Verification>(
         &mut self,
         provider: &impl SecretProvider<C>,
+        policy: &SigningPolicy,
     ) -> Result<usize, SignError> {
+        self.sign_inputs(provider, policy, false)
+    }
+
+    fn sign_all_s2c<C: Signing + Verification>(
+        &mut self,
+        provider: &impl SecretProvider<C>,
+        policy: &SigningPolicy,
+    ) -> Result<usize, SignError> {
+        self.sign_inputs(provider, policy, true)
+    }
+}
+
+impl Psbt {
+    fn sign_inputs<C: Signing + Verification>(
+        &mut self,
+        provider: &impl SecretProvider<C>,
+        policy: &SigningPolicy,
+        s2c: bool,
+    ) -> Result<usize, SignError> {
+        self.check_policy(policy)?;
+        self.check_sighash_types(provider.allowed_sighash_types())?;
+
         let tx = self.clone().into_unsigned_tx();
         let mut signature_count = 0usize;
         let mut sig_hasher = SighashCache::new(&tx);
@@ -227,11 +405,11 @@ impl SignAll for Psbt {
 
         for input in &mut self.inputs {
             let count = input
-                .sign_input_pretr(provider, &mut sig_hasher)
+                .sign_input_pretr(provider, &mut sig_hasher, s2c)
                 .map_err(|err| SignError::with_input_no(err, input.index()))?;
             if count == 0 {
                 signature_count += input
-                    .sign_input_tr(provider, &mut sig_hasher, &prevouts)
+                    .sign_input_tr(provider, &mut sig_hasher, &prevouts, s2c)
                     .map_err(|err| SignError::with_input_no(err, input.index()))?;
             } else {
                 signature_count += count;
@@ -240,6 +418,79 @@ impl SignAll for Psbt {
 
         Ok(signature_count)
     }
+
+    /// Checks that the PSBT complies with the signing policy.
+    fn check_policy(&self, policy: &SigningPolicy) -> Result<(), SignError> {
+        for (index, input) in self.inputs.iter().enumerate() {
+            if policy.require_witness_utxo && input.witness_utxo.is_none() {
+                return Err(SignError::with_input_no(
+                    SignInputError::NoWitnessUtxo,
+                    index,
+                ));
+            }
+            let sighash_type = match input.sighash_type {
+                Some(sighash_type) if policy.sighash_all_only => sighash_type,
+                _ => continue,
+            };
+            let ecdsa_all = EcdsaSighashType::All as u32;
+            let schnorr_default = SchnorrSighashType::Default as u32;
+            if [ecdsa_all, schnorr_default].contains(&sighash_type.to_u32()) {
+                continue;
+            }
+            if !policy
+                .input_sighash_types
+                .get(&index)
+                .map(|allowed| allowed.contains(&sighash_type))
+                .unwrap_or_default()
+            {
+                return Err(SignError::with_input_no(
+                    SignInputError::ForbiddenSighashType(sighash_type),
+                    index,
+                ));
+            }
+        }
+        if let Some(max_fee) = policy.max_fee {
+            let fee = self.fee()?;
+            if fee > max_fee {
+                return Err(SignError::FeeExceeded { fee, max_fee });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the PSBT does not mix sighash types across inputs, unless
+    /// the mixed types are present in the `allowed` list. `SIGHASH_ALL` is
+    /// always allowed.
+    fn check_sighash_types(&self, allowed: &[PsbtSighashType]) -> Result<(), SignError> {
+        let all = PsbtSighashType::from(EcdsaSighashType::All);
+        let requested = self
+            .inputs
+            .iter()
+            .map(|input| {
+                input
+                    .sighash_type
+                    .filter(|sighash_type| sighash_type.to_u32() != 0)
+                    .unwrap_or(all)
+            })
+            .collect::<Vec<_>>();
+        if requested.windows(2).all(|pair| pair[0] == pair[1]) {
+            return Ok(());
+        }
+        match requested
+            .into_iter()
+            .enumerate()
+            .find(|(_, sighash_type)| *sighash_type != all && !allowed.contains(sighash_type))
+        {
+            None => Ok(()),
+            Some((index, requested)) => Err(SignError::with_input_no(
+                SignInputError::MixedSighashType {
+                    requested,
+                    allowed: SighashTypeList(allowed.to_vec()),
+                },
+                index,
+            )),
+        }
+    }
 }
 
 impl Input {
@@ -259,6 +510,7 @@ impl Input {
         &mut self,
         provider: &impl SecretProvider<C>,
         sig_hasher: &mut SighashCache<R>,
+        s2c: bool,
     ) -> Result<usize, SignInputError>
     where
         C: Signing,
@@ -273,7 +525,7 @@ impl Input {
                 Err(_) => continue,
             };
 
-            if self.sign_input_with(provider, sig_hasher, pubkey, seckey)? {
+            if self.sign_input_with(provider, sig_hasher, pubkey, seckey, s2c)? {
                 signature_count += 1;
             }
         }
@@ -301,6 +553,7 @@ impl Input {
         provider: &impl SecretProvider<C>,
         sig_hasher: &mut SighashCache<R>,
         prevouts: &Prevouts<TxOut>,
+        s2c: bool,
     ) -> Result<usize, SignInputError>
     where
         C: Signing + Verification,
@@ -309,6 +562,10 @@ impl Input {
         let mut signature_count = 0usize;
         let tr_origins = self.tap_key_origins.clone();
 
+        // Leaves from hidden subtrees become signable once their merkle proofs
+        // are validated
+        self.resolve_tap_hidden_leaves(provider.secp_context())?;
+
         for (pubkey, (leaves, (fingerprint, derivation))) in tr_origins {
             let keypair = match provider.key_pair(fingerprint, &derivation, pubkey) {
                 Ok(pair) => pair,
@@ -316,7 +573,7 @@ impl Input {
             };
 
             signature_count += self.sign_taproot_input_with(
-                provider, sig_hasher, pubkey, keypair, &leaves, prevouts,
+                provider, sig_hasher, pubkey, keypair, &leaves, prevouts, s2c,
             )?;
         }
 
@@ -329,73 +586,16 @@ impl Input {
         sig_hasher: &mut SighashCache<R>,
         pubkey: secp256k1::PublicKey,
         mut seckey: secp256k1::SecretKey,
+        s2c: bool,
     ) -> Result<bool, SignInputError>
     where
         C: Signing,
         R: Deref<Target = Transaction>,
     {
-        // Extract & check previous output information
-        let index = self.index();
-        let prevout = self.input_prevout()?;
-        let spent_value = prevout.value;
-
-        // Check script_pubkey match and requirements
-        let script_pubkey = PubkeyScript::from_inner(prevout.script_pubkey.clone());
-        let witness_script = self.witness_script.as_ref();
-        let redeem_script = self.redeem_script.as_ref();
-
-        // Compute sighash
-        let sighash_type = self
-            .sighash_type
-            .map(|sht| sht.ecdsa_hash_ty())
-            .transpose()
-            .map_err(|err| SignInputError::NonStandardSighashType {
-                sighash_type: err.0,
-                index,
-            })?
-            .unwrap_or(EcdsaSighashType::All);
-
-        let descr_type =
-            CompositeDescrType::deduce(&script_pubkey, redeem_script, witness_script.is_some())?;
-        let sighash = match (descr_type, witness_script) {
-            (CompositeDescrType::Wsh, Some(witness_script))
-                if prevout.script_pubkey != witness_script.to_v0_p2wsh() =>
-            {
-                return Err(SignInputError::ScriptPubkeyMismatch)
-            }
-            (CompositeDescrType::Sh, _)
-            | (CompositeDescrType::ShWpkh, _)
-            | (CompositeDescrType::ShWsh, _)
-                if Some(&prevout.script_pubkey)
-                    != redeem_script
-                        .map(RedeemScript::to_p2sh)
-                        .map(Into::into)
-                        .as_ref() =>
-            {
-                return Err(SignInputError::ScriptPubkeyMismatch)
-            }
-            (CompositeDescrType::Tr, _) => {
-                // skipping taproot spendings: they are handled by a separate function
-                return Ok(false);
-            }
-            (CompositeDescrType::Wpkh, _) | (CompositeDescrType::ShWpkh, _) => {
-                let pubkey_hash = PubkeyHash::from_slice(&script_pubkey[2..22])
-                    .expect("PubkeyHash hash length failure");
-                let script_code = Script::new_p2pkh(&pubkey_hash);
-                sig_hasher.segwit_signature_hash(index, &script_code, spent_value, sighash_type)?
-            }
-            (CompositeDescrType::Wsh, Some(witness_script))
-            | (CompositeDescrType::ShWsh, Some(witness_script)) => sig_hasher
-                .segwit_signature_hash(index, witness_script, spent_value, sighash_type)?,
-            (CompositeDescrType::Wsh, None) | (CompositeDescrType::ShWsh, None) => {
-                return Err(SignInputError::NoWitnessScript)
-            }
-            _ => {
-                if self.non_witness_utxo.is_none() {
-                    return Err(SignInputError::LegacySpentTransactionMissed);
-                }
-                sig_hasher.legacy_signature_hash(index, &script_pubkey, sighash_type.to_u32())?
-            }
+        let (sighash, sighash_type) = match self.ecdsa_sighash(sig_hasher)? {
+            Some(sighash) => sighash,
+            // skipping taproot spendings: they are handled by a separate function
+            None => return Ok(false),
         };
 
         // Apply past P2C tweaks
@@ -405,14 +605,21 @@ impl Input {
             seckey = seckey
                 .add_tweak(&tweak)
                 .map_err(|_| SignInputError::P2cTweak)?;
+            let tweaked = secp256k1::PublicKey::from_secret_key(provider.secp_context(), &seckey);
+            self.check_p2c_pubkey(tweaked)?;
         }
 
         // Do the signature
-        let signature = provider.secp_context().sign_ecdsa(
-            &bitcoin::secp256k1::Message::from_slice(&sighash[..])
-                .expect("Sighash generation is broken"),
-            &seckey,
-        );
+        let secp = provider.secp_context();
+        let signature = match self.s2c_commitment().filter(|_| s2c) {
+            Some(commitment) => {
+                let (signature, proof) = sign_ecdsa_s2c(secp, &sighash, &seckey, commitment);
+                let pubkey = secp256k1::PublicKey::from_secret_key(secp, &seckey);
+                self.set_s2c_proof(pubkey.serialize(), proof);
+                signature
+            }
+            None => secp.sign_ecdsa(&sighash, &seckey),
+        };
 
         let mut partial_sig = signature.serialize_der().to_vec();
         partial_sig.push(sighash_type as u8);
@@ -424,6 +631,7 @@ impl Input {
         Ok(true)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn sign_taproot_input_with<C, R>(
         &mut self,
         provider: &impl SecretProvider<C>,
@@ -432,6 +640,7 @@ impl Input {
         mut keypair: KeyPair,
         leaves: &[TapLeafHash],
         prevouts: &Prevouts<TxOut>,
+        s2c: bool,
     ) -> Result<usize, SignInputError>
     where
         C: Signing + Verification,
@@ -439,40 +648,11 @@ impl Input {
     {
         let mut signature_count = 0usize;
         let index = self.index();
-
-        // Check script_pubkey match
-        let script_pubkey = PubkeyScript::from_inner(self.input_prevout()?.script_pubkey.clone());
-        if let Some(internal_key) = self.tap_internal_key {
-            if script_pubkey
-                != Script::new_v1_p2tr(provider.secp_context(), internal_key, self.tap_merkle_root)
-                    .into()
-            {
-                return Err(SignInputError::ScriptPubkeyMismatch);
-            }
+        if s2c && self.s2c_commitment().is_some() {
+            return Err(SignInputError::SchnorrS2cUnsupported);
         }
 
-        // Check that prevouts meets sighash type requirements
-        let sighash_type = self
-            .sighash_type
-            .map(|sht| sht.schnorr_hash_ty())
-            .transpose()
-            .map_err(|_| SignInputError::NonStandardSighashType {
-                sighash_type: self.sighash_type.expect("option unwrapped above").to_u32(),
-                index,
-            })?
-            .unwrap_or(SchnorrSighashType::Default);
-        if matches!(
-            (sighash_type, prevouts),
-            (
-                SchnorrSighashType::All
-                    | SchnorrSighashType::None
-                    | SchnorrSighashType::Single
-                    | SchnorrSighashType::Default,
-                Prevouts::One(..),
-            )
-        ) {
-            return Err(SignInputError::TaprootPrevoutsMissed);
-        }
+        let sighash_type = self.taproot_sighash_type(provider.secp_context(), prevouts)?;
 
         // Apply past P2C tweaks
         if let Some(tweak) = self.p2c_tweak(pubkey.to_public_key().inner) {
@@ -481,6 +661,7 @@ impl Input {
             keypair = keypair
                 .add_xonly_tweak(provider.secp_context(), &tweak)
                 .map_err(|_| SignInputError::P2cTweak)?;
+            self.check_p2c_xonly(keypair, leaves)?;
         }
 
         // Sign taproot script spendings
@@ -494,17 +675,17 @@ impl Input {
                 if pk != pubkey {
                     continue;
                 }
-                let sighash = sig_hasher.taproot_script_spend_signature_hash(
+                let sighash = taproot_sighash(
+                    sig_hasher,
                     index,
                     prevouts,
-                    ScriptPath::with_defaults(script),
+                    None,
+                    TapSpendPath::script(tapleaf_hash),
                     sighash_type,
                 )?;
-                let signature = provider.secp_context().sign_schnorr(
-                    &bitcoin::secp256k1::Message::from_slice(&sighash[..])
-                        .expect("taproot Sighash generation is broken"),
-                    &keypair,
-                );
+                let msg = bitcoin::secp256k1::Message::from_slice(&sighash[..])
+                    .expect("taproot Sighash generation is broken");
+                let signature = provider.secp_context().sign_schnorr(&msg, &keypair);
                 let sig = SchnorrSig {
                     sig: signature,
                     hash_ty: sighash_type,
@@ -515,14 +696,20 @@ impl Input {
         }
 
         // Sign taproot key spendings
-        let sighash =
-            sig_hasher.taproot_signature_hash(index, prevouts, None, None, sighash_type)?;
-        let tweaked_keypair = keypair.tap_tweak(provider.secp_context(), self.tap_merkle_root);
-        let signature = provider.secp_context().sign_schnorr(
-            &bitcoin::secp256k1::Message::from_slice(&sighash[..])
-                .expect("taproot Sighash generation is broken"),
-            &tweaked_keypair.to_inner(),
-        );
+        let sighash = taproot_sighash(
+            sig_hasher,
+            index,
+            prevouts,
+            None,
+            TapSpendPath::Key,
+            sighash_type,
+        )?;
+        let tweaked_keypair = keypair
+            .tap_tweak(provider.secp_context(), self.tap_merkle_root)
+            .to_inner();
+        let msg = bitcoin::secp256k1::Message::from_slice(&sighash[..])
+            .expect("taproot Sighash generation is broken");
+        let signature = provider.secp_context().sign_schnorr(&msg, &tweaked_keypair);
 
         match self.tap_key_sig {
             Some(_) if !provider.use_musig() => {
@@ -584,4 +771,423 @@ impl Input {
 
         Ok(signature_count)
     }
+
+    /// Checks that the public key resulting from applying pay-to-contract
+    /// tweak is committed to by the previous output, i.e. that it is present,
+    /// directly or as a hash, in the previous output `scriptPubkey`, or in the
+    /// redeem or witness script of the input.
+    fn check_p2c_pubkey(&self, tweaked: secp256k1::PublicKey) -> Result<(), SignInputError> {
+        let pubkey = PublicKey::new(tweaked);
+        let key_data = pubkey.to_bytes();
+        let hash_data = pubkey.pubkey_hash();
+        let prevout = self.input_prevout()?;
+        let found = [
+            Some(&prevout.script_pubkey),
+            self.redeem_script.as_ref().map(Wrapper::as_inner),
+            self.witness_script.as_ref().map(Wrapper::as_inner),
+        ]
+        .into_iter()
+        .flatten()
+        .any(|script| {
+            script.instructions().any(|instr| {
+                matches!(instr, Ok(Instruction::PushBytes(data))
+                    if data == &key_data[..] || data == &hash_data[..])
+            })
+        });
+        if !found {
+            return Err(SignInputError::P2cPubkeyMismatch(tweaked));
+        }
+        Ok(())
+    }
+
+    /// Checks that the x-only public key resulting from applying
+    /// pay-to-contract tweak is either the taproot internal key of the input
+    /// or is used by one of the script leaves which are to be signed.
+    fn check_p2c_xonly(
+        &self,
+        tweaked: KeyPair,
+        leaves: &[TapLeafHash],
+    ) -> Result<(), SignInputError> {
+        let (xonly, _) = tweaked.x_only_public_key();
+        if self.tap_internal_key == Some(xonly) {
+            return Ok(());
+        }
+        let found = self
+            .tap_scripts
+            .values()
+            .filter(|(script, leaf_ver)| {
+                leaves.contains(&TapLeafHash::from_script(script, *leaf_ver))
+            })
+            .any(|(script, _)| {
+                script.instructions().any(|instr| {
+                    matches!(instr, Ok(Instruction::PushBytes(data))
+                        if data == &xonly.serialize()[..])
+                })
+            });
+        if !found {
+            return Err(SignInputError::P2cPubkeyMismatch(tweaked.public_key()));
+        }
+        Ok(())
+    }
+
+    /// Computes signature hash for signing a non-taproot input with ECDSA
+    /// signature, checking that the scripts provided in the input match its
+    /// previous output `scriptPubkey`.
+    ///
+    /// # Returns
+    ///
+    /// Message to sign and the sighash type which must be used for the
+    /// signature; or `None` if the input spends taproot output.
+    pub(crate) fn ecdsa_sighash<R>(
+        &self,
+        sig_hasher: &mut SighashCache<R>,
+    ) -> Result<Option<(Message, EcdsaSighashType)>, SignInputError>
+    where
+        R: Deref<Target = Transaction>,
+    {
+        // Extract & check previous output information
+        let index = self.index();
+        let prevout = self.input_prevout()?;
+        let spent_value = prevout.value;
+
+        // Check script_pubkey match and requirements
+        let script_pubkey = PubkeyScript::from_inner(prevout.script_pubkey.clone());
+        let witness_script = self.witness_script.as_ref();
+        let redeem_script = self.redeem_script.as_ref();
+
+        // Compute sighash
+        let sighash_type = self
+            .sighash_type
+            .map(|sht| sht.ecdsa_hash_ty())
+            .transpose()
+            .map_err(|err| SignInputError::NonStandardSighashType {
+                sighash_type: err.0,
+                index,
+            })?
+            .unwrap_or(EcdsaSighashType::All);
+
+        let descr_type =
+            CompositeDescrType::deduce(&script_pubkey, redeem_script, witness_script.is_some())?;
+        let sighash = match (descr_type, witness_script) {
+            (CompositeDescrType::Wsh, Some(witness_script))
+                if prevout.script_pubkey != witness_script.to_v0_p2wsh() =>
+            {
+                return Err(SignInputError::ScriptPubkeyMismatch)
+            }
+            (CompositeDescrType::Sh, _)
+            | (CompositeDescrType::ShWpkh, _)
+            | (CompositeDescrType::ShWsh, _)
+                if Some(&prevout.script_pubkey)
+                    != redeem_script
+                        .map(RedeemScript::to_p2sh)
+                        .map(Into::into)
+                        .as_ref() =>
+            {
+                return Err(SignInputError::ScriptPubkeyMismatch)
+            }
+            (CompositeDescrType::Tr, _) => return Ok(None),
+            (CompositeDescrType::Future, _) => {
+                return Err(match script_pubkey.witness_version() {
+                    Some(WitnessVersion::V1) | None => SignInputError::NonTaprootV1,
+                    Some(version) => SignInputError::FutureWitness(version),
+                })
+            }
+            (CompositeDescrType::Wpkh, _) | (CompositeDescrType::ShWpkh, _) => {
+                let pubkey_hash = PubkeyHash::from_slice(&script_pubkey[2..22])
+                    .expect("PubkeyHash hash length failure");
+                let script_code = Script::new_p2pkh(&pubkey_hash);
+                sig_hasher.segwit_signature_hash(index, &script_code, spent_value, sighash_type)?
+            }
+            (CompositeDescrType::Wsh, Some(witness_script))
+            | (CompositeDescrType::ShWsh, Some(witness_script)) => sig_hasher
+                .segwit_signature_hash(index, witness_script, spent_value, sighash_type)?,
+            (CompositeDescrType::Wsh, None) | (CompositeDescrType::ShWsh, None) => {
+                return Err(SignInputError::NoWitnessScript)
+            }
+            _ => {
+                if self.non_witness_utxo.is_none() {
+                    return Err(SignInputError::LegacySpentTransactionMissed);
+                }
+                sig_hasher.legacy_signature_hash(index, &script_pubkey, sighash_type.to_u32())?
+            }
+        };
+
+        Ok(Some((
+            Message::from_slice(&sighash[..]).expect("Sighash generation is broken"),
+            sighash_type,
+        )))
+    }
+
+    /// Detects sighash type which must be used for signing taproot input,
+    /// checking that the input internal key and merkle root match its previous
+    /// output `scriptPubkey` and that the provided `prevouts` are sufficient
+    /// for computing the sighash.
+    pub(crate) fn taproot_sighash_type<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        prevouts: &Prevouts<TxOut>,
+    ) -> Result<SchnorrSighashType, SignInputError> {
+        let index = self.index();
+
+        // Check script_pubkey match
+        let script_pubkey = PubkeyScript::from_inner(self.input_prevout()?.script_pubkey.clone());
+        if let Some(internal_key) = self.tap_internal_key {
+            if script_pubkey != Script::new_v1_p2tr(secp, internal_key, self.tap_merkle_root).into()
+            {
+                return Err(SignInputError::ScriptPubkeyMismatch);
+            }
+        }
+
+        // Check that prevouts meets sighash type requirements
+        let sighash_type = self
+            .sighash_type
+            .map(|sht| sht.schnorr_hash_ty())
+            .transpose()
+            .map_err(|_| SignInputError::NonStandardSighashType {
+                sighash_type: self.sighash_type.expect("option unwrapped above").to_u32(),
+                index,
+            })?
+            .unwrap_or(SchnorrSighashType::Default);
+        if matches!(
+            (sighash_type, prevouts),
+            (
+                SchnorrSighashType::All
+                    | SchnorrSighashType::None
+                    | SchnorrSighashType::Single
+                    | SchnorrSighashType::Default,
+                Prevouts::One(..),
+            )
+        ) {
+            return Err(SignInputError::TaprootPrevoutsMissed);
+        }
+
+        Ok(sighash_type)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, PackedLockTime, TxIn, Txid};
+
+    use super::*;
+    use crate::PsbtVersion;
+
+    #[test]
+    fn mixed_sighash_types() {
+        let txin = |vout| TxIn {
+            previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), vout),
+            ..TxIn::default()
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![txin(0), txin(1), txin(2)],
+            output: vec![],
+        };
+        let mut psbt = Psbt::with(tx, PsbtVersion::V0).unwrap();
+        let single_acp = PsbtSighashType::from(EcdsaSighashType::SinglePlusAnyoneCanPay);
+        psbt.inputs[0].sighash_type = Some(PsbtSighashType::from(SchnorrSighashType::Default));
+        psbt.inputs[1].sighash_type = Some(PsbtSighashType::from(EcdsaSighashType::All));
+        assert!(psbt.check_sighash_types(&[]).is_ok());
+
+        psbt.inputs[2].sighash_type = Some(single_acp);
+        let err = psbt.check_sighash_types(&[]).unwrap_err();
+        assert!(matches!(
+            err,
+            SignError::Input {
+                error: SignInputError::MixedSighashType { requested, .. },
+                input_index: 2,
+            } if requested == single_acp
+        ));
+        assert!(psbt.check_sighash_types(&[single_acp]).is_ok());
+    }
+
+    fn p2c_input(script_pubkey: Script) -> Input {
+        let txin = TxIn {
+            previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), 0),
+            ..TxIn::default()
+        };
+        let mut input = Input::new(0, txin).unwrap();
+        input.witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey,
+        });
+        input
+    }
+
+    #[test]
+    fn p2c_pubkey_wpkh() {
+        let secp = Secp256k1::new();
+        let tweaked = secp256k1::SecretKey::from_slice(&[0x11; 32])
+            .unwrap()
+            .public_key(&secp);
+        let other = secp256k1::SecretKey::from_slice(&[0x22; 32])
+            .unwrap()
+            .public_key(&secp);
+        let wpkh = PublicKey::new(tweaked).wpubkey_hash().unwrap();
+
+        let input = p2c_input(Script::new_v0_p2wpkh(&wpkh));
+        assert!(input.check_p2c_pubkey(tweaked).is_ok());
+        assert!(matches!(
+            input.check_p2c_pubkey(other),
+            Err(SignInputError::P2cPubkeyMismatch(pk)) if pk == other
+        ));
+    }
+
+    #[test]
+    fn p2c_pubkey_sh_wpkh() {
+        let secp = Secp256k1::new();
+        let tweaked = secp256k1::SecretKey::from_slice(&[0x11; 32])
+            .unwrap()
+            .public_key(&secp);
+        let other = secp256k1::SecretKey::from_slice(&[0x22; 32])
+            .unwrap()
+            .public_key(&secp);
+        let redeem_script = Script::new_v0_p2wpkh(&PublicKey::new(tweaked).wpubkey_hash().unwrap());
+
+        let mut input = p2c_input(redeem_script.to_p2sh());
+        assert!(matches!(
+            input.check_p2c_pubkey(tweaked),
+            Err(SignInputError::P2cPubkeyMismatch(pk)) if pk == tweaked
+        ));
+        input.redeem_script = Some(RedeemScript::from_inner(redeem_script));
+        assert!(input.check_p2c_pubkey(tweaked).is_ok());
+        assert!(matches!(
+            input.check_p2c_pubkey(other),
+            Err(SignInputError::P2cPubkeyMismatch(pk)) if pk == other
+        ));
+    }
+
+    #[test]
+    fn p2c_xonly_tr_key_spend() {
+        let secp = Secp256k1::new();
+        let tweaked = KeyPair::from_seckey_slice(&secp, &[0x11; 32]).unwrap();
+        let other = KeyPair::from_seckey_slice(&secp, &[0x22; 32]).unwrap();
+        let (internal_key, _) = tweaked.x_only_public_key();
+
+        let mut input = p2c_input(Script::new_v1_p2tr(&secp, internal_key, None));
+        assert!(matches!(
+            input.check_p2c_xonly(tweaked, &[]),
+            Err(SignInputError::P2cPubkeyMismatch(pk)) if pk == tweaked.public_key()
+        ));
+        input.tap_internal_key = Some(internal_key);
+        assert!(input.check_p2c_xonly(tweaked, &[]).is_ok());
+        assert!(matches!(
+            input.check_p2c_xonly(other, &[]),
+            Err(SignInputError::P2cPubkeyMismatch(pk)) if pk == other.public_key()
+        ));
+    }
+
+    #[test]
+    fn schnorr_s2c_unsupported() {
+        let secp = Secp256k1::new();
+        let keypair = KeyPair::from_seckey_slice(&secp, &[0x11; 32]).unwrap();
+        let (internal_key, _) = keypair.x_only_public_key();
+        let mut input = p2c_input(Script::new_v1_p2tr(&secp, internal_key, None));
+        input.tap_internal_key = Some(internal_key);
+        input.set_s2c_commitment(bitcoin::hashes::sha256::Hash::hash(b"commitment"));
+        let prevout = input.witness_utxo.clone().unwrap();
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![input.to_unsigned_txin()],
+            output: vec![],
+        };
+        let provider = crate::sign::MemoryKeyProvider::with(&secp, false);
+        let mut sig_hasher = SighashCache::new(&tx);
+        let prevouts = [prevout];
+        assert!(matches!(
+            input.sign_taproot_input_with(
+                &provider,
+                &mut sig_hasher,
+                internal_key,
+                keypair,
+                &[],
+                &Prevouts::All(&prevouts),
+                true,
+            ),
+            Err(SignInputError::SchnorrS2cUnsupported)
+        ));
+        assert_eq!(
+            input
+                .sign_taproot_input_with(
+                    &provider,
+                    &mut sig_hasher,
+                    internal_key,
+                    keypair,
+                    &[],
+                    &Prevouts::All(&prevouts),
+                    false,
+                )
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn signing_policy() {
+        let prev_tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::all_zeros()),
+            }],
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(prev_tx.txid(), 0),
+                ..TxIn::default()
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let mut psbt = Psbt::with(tx, PsbtVersion::V0).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx.clone());
+        assert!(psbt.check_policy(&SigningPolicy::default()).is_ok());
+        assert!(matches!(
+            psbt.check_policy(&SigningPolicy::strict()),
+            Err(SignError::Input {
+                error: SignInputError::NoWitnessUtxo,
+                input_index: 0
+            })
+        ));
+
+        psbt.inputs[0].witness_utxo = Some(prev_tx.output[0].clone());
+        psbt.inputs[0].sighash_type = Some(PsbtSighashType::from(EcdsaSighashType::All));
+        assert!(psbt.check_policy(&SigningPolicy::strict()).is_ok());
+
+        let none = PsbtSighashType::from(EcdsaSighashType::None);
+        psbt.inputs[0].sighash_type = Some(none);
+        let mut policy = SigningPolicy::strict();
+        assert!(matches!(
+            psbt.check_policy(&policy),
+            Err(SignError::Input {
+                error: SignInputError::ForbiddenSighashType(sighash_type),
+                input_index: 0
+            }) if sighash_type == none
+        ));
+        policy.allow_input_sighash_type(1, none);
+        assert!(psbt.check_policy(&policy).is_err());
+        policy.allow_input_sighash_type(0, none);
+        assert!(psbt.check_policy(&policy).is_ok());
+
+        assert!(psbt
+            .check_policy(&policy.clone().with_max_fee(1_000))
+            .is_ok());
+        assert!(matches!(
+            psbt.check_policy(&policy.with_max_fee(999)),
+            Err(SignError::FeeExceeded {
+                fee: 1_000,
+                max_fee: 999
+            })
+        ));
+    }
 }
@@ -0,0 +1,380 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Generic signed messages (BIP-322) for any `scriptPubkey` which can be
+//! satisfied with a witness.
+//!
+//! Message is signed by satisfying the only input of a virtual `to_sign`
+//! transaction, which spends output of another virtual `to_spend` transaction
+//! committing to the message and locked with the signer `scriptPubkey`. The
+//! `to_sign` transaction is represented as a PSBT and is signed with the
+//! normal signer (see [`crate::sign`]); the witness of its finalized input is
+//! the message signature in the BIP-322 "simple" format.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use amplify::Wrapper;
+use base64::Engine;
+use bitcoin::blockdata::opcodes::all::OP_PUSHBYTES_0;
+use bitcoin::blockdata::script;
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{Secp256k1, Verification, VerifyOnly};
+use bitcoin::util::sighash::Prevouts;
+use bitcoin::{
+    LockTime, OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use miniscript::psbt::PsbtExt;
+use miniscript::Interpreter;
+
+use crate::v0::PsbtV0;
+use crate::Psbt;
+
+/// Tag of the BIP-322 message hash
+pub const BIP322_TAG: &[u8] = b"BIP0322-signed-message";
+
+/// Errors signing and verifying BIP-322 messages
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum MessageError {
+    /// message signature is not a valid Base64 string
+    #[from(base64::DecodeError)]
+    Base64,
+
+    /// message signature does not contain a valid witness stack
+    #[from(bitcoin::consensus::encode::Error)]
+    WitnessEncoding,
+
+    /// PSBT is not a virtual BIP-322 transaction signing the message
+    NotMessagePsbt,
+
+    /// message can't be signed with `scriptSig`; BIP-322 simple signatures
+    /// require witness-based `scriptPubkey`
+    LegacyScript,
+
+    /// unable to finalize signed message PSBT: {0}
+    Finalize(String),
+
+    /// message signature does not satisfy the `scriptPubkey`: {0}
+    Invalid(String),
+
+    /// {0}
+    #[cfg(feature = "construct")]
+    Construct(String),
+}
+
+/// Computes BIP-322 tagged hash of the message.
+pub fn message_hash(message: &[u8]) -> sha256::Hash {
+    let tag = sha256::Hash::hash(BIP322_TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    engine.input(message);
+    sha256::Hash::from_engine(engine)
+}
+
+/// Constructs virtual `to_spend` transaction, which output committing to the
+/// `message` is locked with `script_pubkey`.
+pub fn to_spend(script_pubkey: &Script, message: &[u8]) -> Transaction {
+    let script_sig = script::Builder::new()
+        .push_opcode(OP_PUSHBYTES_0)
+        .push_slice(&message_hash(message)[..])
+        .into_script();
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig,
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// Constructs unsigned virtual `to_sign` transaction spending the output of
+/// `to_spend` transaction.
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: 0,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: Script::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: Script::new_op_return(&[]),
+        }],
+    }
+}
+
+/// Message signature in BIP-322 "simple" format: witness stack satisfying the
+/// `to_spend` output, encoded in Base64.
+#[derive(Wrapper, Clone, PartialEq, Eq, Debug, From)]
+pub struct MessageSignature(Witness);
+
+impl Display for MessageSignature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&base64::engine::general_purpose::STANDARD.encode(serialize(&self.0)))
+    }
+}
+
+impl FromStr for MessageSignature {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = base64::engine::general_purpose::STANDARD.decode(s)?;
+        Ok(MessageSignature(deserialize(&data)?))
+    }
+}
+
+impl MessageSignature {
+    /// Verifies that the signature satisfies `script_pubkey` for the
+    /// `message`.
+    ///
+    /// Verification is performed with miniscript interpreter, so only the
+    /// scripts which can be represented as miniscript descriptors are
+    /// supported.
+    pub fn verify(
+        &self,
+        secp: &Secp256k1<VerifyOnly>,
+        script_pubkey: &Script,
+        message: &[u8],
+    ) -> Result<(), MessageError> {
+        let to_spend = to_spend(script_pubkey, message);
+        let mut to_sign = to_sign(&to_spend);
+        to_sign.input[0].witness = self.0.clone();
+
+        let empty_script = Script::new();
+        let interpreter = Interpreter::from_txdata(
+            script_pubkey,
+            &empty_script,
+            &self.0,
+            to_sign.input[0].sequence,
+            LockTime::from(to_sign.lock_time),
+        )
+        .map_err(|err| MessageError::Invalid(err.to_string()))?;
+
+        let prevouts = Prevouts::All(&to_spend.output[..]);
+        for constraint in interpreter.iter(secp, &to_sign, 0, &prevouts) {
+            constraint.map_err(|err| MessageError::Invalid(err.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Psbt {
+    /// Detects whether the PSBT is a virtual BIP-322 `to_sign` transaction
+    /// for the `message`.
+    pub fn is_message_psbt(&self, message: &[u8]) -> bool {
+        let input = match self.inputs.as_slice() {
+            [input] => input,
+            _ => return false,
+        };
+        let prevout = match input.input_prevout() {
+            Ok(prevout) => prevout,
+            Err(_) => return false,
+        };
+        let to_spend = to_spend(&prevout.script_pubkey, message);
+        self.to_unsigned_tx() == to_sign(&to_spend) && prevout == &to_spend.output[0]
+    }
+
+    /// Extracts BIP-322 signature of the `message` from signed virtual
+    /// `to_sign` transaction PSBT, finalizing its input if necessary.
+    pub fn message_signature<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        message: &[u8],
+    ) -> Result<MessageSignature, MessageError> {
+        if !self.is_message_psbt(message) {
+            return Err(MessageError::NotMessagePsbt);
+        }
+
+        let input = &self.inputs[0];
+        let (script_sig, witness) =
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                (
+                    input
+                        .final_script_sig
+                        .clone()
+                        .map(|script| script.into_inner()),
+                    input.final_script_witness.clone(),
+                )
+            } else {
+                let mut psbt = PsbtV0::from(self.clone());
+                psbt.finalize_inp_mut(secp, 0)
+                    .map_err(|err| MessageError::Finalize(err.to_string()))?;
+                let input = psbt.inputs.remove(0);
+                (input.final_script_sig, input.final_script_witness)
+            };
+
+        // Nested segwit `scriptPubkey`s require `scriptSig`, which is not a
+        // part of BIP-322 simple signature
+        if script_sig
+            .map(|script| !script.is_empty())
+            .unwrap_or_default()
+        {
+            return Err(MessageError::LegacyScript);
+        }
+        witness
+            .filter(|witness| !witness.is_empty())
+            .map(MessageSignature::from)
+            .ok_or(MessageError::LegacyScript)
+    }
+}
+
+#[cfg(feature = "construct")]
+mod _construct {
+    use bitcoin::secp256k1::SECP256K1;
+    use bitcoin::EcdsaSighashType;
+    use bitcoin_blockchain::locks::SeqNo;
+    use bitcoin_hd::{DerivationAccount, DerivationSubpath, UnhardenedIndex};
+    use bitcoin_scripts::PubkeyScript;
+    use descriptors::derive::Descriptor as _;
+    use descriptors::InputDescriptor;
+    use miniscript::Descriptor;
+
+    use super::*;
+
+    impl Psbt {
+        /// Constructs virtual BIP-322 `to_sign` transaction PSBT for signing
+        /// the `message` with the key(s) of the wallet `descriptor` derived
+        /// at the `terminal` derivation path. The PSBT is signed with the
+        /// normal signer and the signature is extracted with
+        /// [`Psbt::message_signature`].
+        ///
+        /// # Errors
+        ///
+        /// Fails with [`MessageError::LegacyScript`] for descriptors which
+        /// `scriptPubkey` is not a witness program, including nested segwit
+        /// ones, since their satisfaction requires `scriptSig`, which can't be
+        /// represented by BIP-322 simple signatures.
+        pub fn construct_message(
+            descriptor: &Descriptor<DerivationAccount>,
+            terminal: DerivationSubpath<UnhardenedIndex>,
+            message: &[u8],
+        ) -> Result<Psbt, MessageError> {
+            let script_pubkey = match descriptor {
+                Descriptor::Tr(_) => descriptor.script_pubkey_tr(SECP256K1, terminal.as_ref()),
+                _ => descriptor.script_pubkey_pretr(SECP256K1, terminal.as_ref()),
+            }
+            .map_err(|err| MessageError::Construct(err.to_string()))?;
+            if !script_pubkey.is_witness_program() {
+                return Err(MessageError::LegacyScript);
+            }
+
+            let to_spend = to_spend(&script_pubkey, message);
+            let input = InputDescriptor {
+                outpoint: OutPoint::new(to_spend.txid(), 0),
+                terminal,
+                seq_no: SeqNo::from_consensus(0),
+                tweak: None,
+                sighash_type: EcdsaSighashType::All,
+                external: None,
+                redeem_script: None,
+                witness_script: None,
+            };
+            let outputs = [(PubkeyScript::from(Script::new_op_return(&[])), 0u64)];
+            let tx_resolver = bmap! { to_spend.txid() => to_spend };
+            let mut psbt = Psbt::construct(descriptor, &[input], &outputs, 0u16, 0, &tx_resolver)
+                .map_err(|err| MessageError::Construct(err.to_string()))?;
+            psbt.tx_version = 0;
+            psbt.fallback_locktime = None;
+            Ok(psbt)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::Address;
+
+    use super::*;
+
+    #[test]
+    fn bip322_vectors() {
+        assert_eq!(
+            message_hash(b"").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash(b"Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+
+        let secp = Secp256k1::verification_only();
+        let address = Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").unwrap();
+        let script_pubkey = address.script_pubkey();
+        let signature = MessageSignature::from_str(
+            "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/\
+             ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/\
+             EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=",
+        )
+        .unwrap();
+        assert_eq!(
+            signature.to_string().parse::<MessageSignature>(),
+            Ok(signature.clone())
+        );
+        assert_eq!(
+            signature.verify(&secp, &script_pubkey, b"Hello World"),
+            Ok(())
+        );
+        assert!(signature.verify(&secp, &script_pubkey, b"").is_err());
+
+        let to_sign = to_sign(&to_spend(&script_pubkey, b"Hello World"));
+        let mut psbt = Psbt::with(to_sign, default!()).unwrap();
+        assert!(!psbt.is_message_psbt(b"Hello World"));
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 0,
+            script_pubkey,
+        });
+        assert!(psbt.is_message_psbt(b"Hello World"));
+        assert!(!psbt.is_message_psbt(b""));
+
+        psbt.inputs[0].final_script_witness = Some(signature.as_inner().clone());
+        assert_eq!(psbt.message_signature(&secp, b"Hello World"), Ok(signature));
+    }
+
+    #[cfg(feature = "construct")]
+    #[test]
+    fn message_psbt_construction() {
+        use bitcoin_hd::{DerivationAccount, DerivationSubpath, SegmentIndexes, UnhardenedIndex};
+        use miniscript::Descriptor;
+
+        let xpub = "[d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUN\
+                    gqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/*/*";
+        let terminal = DerivationSubpath::from(&[UnhardenedIndex::zero(); 2][..]);
+
+        let wpkh = Descriptor::<DerivationAccount>::from_str(&format!("wpkh({})", xpub)).unwrap();
+        let psbt = Psbt::construct_message(&wpkh, terminal.clone(), b"Hello World").unwrap();
+        assert!(psbt.is_message_psbt(b"Hello World"));
+
+        let sh_wpkh =
+            Descriptor::<DerivationAccount>::from_str(&format!("sh(wpkh({}))", xpub)).unwrap();
+        assert_eq!(
+            Psbt::construct_message(&sh_wpkh, terminal.clone(), b"Hello World"),
+            Err(MessageError::LegacyScript)
+        );
+        let pkh = Descriptor::<DerivationAccount>::from_str(&format!("pkh({})", xpub)).unwrap();
+        assert_eq!(
+            Psbt::construct_message(&pkh, terminal, b"Hello World"),
+            Err(MessageError::LegacyScript)
+        );
+    }
+}
@@ -0,0 +1,264 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Processing proprietary PSBT keys carrying taproot script leaves, which the
+//! wallet is unable to reconstruct control blocks for, together with their
+//! merkle proofs.
+//!
+//! Parties sharing a taproot output may each know only its own script leaves,
+//! while the rest of the script tree remains hidden. The PSBT creator provides
+//! each of the co-signers with its leaves and merkle proofs of their inclusion
+//! into the tree; [`Input::resolve_tap_hidden_leaves`] validates the proofs
+//! against the spent output key and converts them into the standard
+//! `PSBT_IN_TAP_LEAF_SCRIPT` fields, such that the leaves may be signed and
+//! finalized in a usual way.
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::schnorr::TapTweak;
+use bitcoin::secp256k1::{Secp256k1, Verification, XOnlyPublicKey};
+use bitcoin::util::taproot::{
+    ControlBlock, LeafVersion, TapBranchHash, TapLeafHash, TaprootMerkleBranch,
+};
+use bitcoin::Script;
+
+use crate::raw::ProprietaryKey;
+use crate::{Input, InputMatchError};
+
+/// Prefix of the proprietary keys for hidden taproot leaves
+pub const PSBT_TAPHIDDEN_PREFIX: &[u8] = b"TAPHIDDEN";
+/// Proprietary key subtype for the hidden leaf script. Key data is the leaf
+/// hash; value is the leaf version byte followed by the leaf script.
+pub const PSBT_IN_TAPHIDDEN_LEAF: u8 = 0;
+/// Proprietary key subtype for the merkle proof of a hidden leaf. Key data is
+/// the leaf hash; value is the sequence of 32-byte node hashes from the leaf
+/// up to the tree root.
+pub const PSBT_IN_TAPHIDDEN_PROOF: u8 = 1;
+
+/// Errors validating merkle proofs of hidden taproot leaves
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum TapHiddenError {
+    /// input provides hidden taproot leaves but lacks taproot internal key
+    NoInternalKey,
+
+    /// input provides hidden taproot leaves but does not spend taproot output
+    NonTaprootPrevout,
+
+    /// spent output is unknown: {0}
+    #[from]
+    Match(InputMatchError),
+
+    /// merkle proof for the hidden taproot leaf {0} does not commit to the
+    /// spent output key
+    InvalidProof(TapLeafHash),
+
+    /// merkle proof for the hidden taproot leaf {0} does not match taproot
+    /// merkle root provided by the input
+    MerkleRootMismatch(TapLeafHash),
+}
+
+impl Input {
+    fn tap_hidden_key(subtype: u8, leaf_hash: TapLeafHash) -> ProprietaryKey {
+        ProprietaryKey {
+            prefix: PSBT_TAPHIDDEN_PREFIX.to_vec(),
+            subtype,
+            key: leaf_hash.to_vec(),
+        }
+    }
+
+    /// Adds taproot script leaf, for which the wallet is not able to construct
+    /// control block, returning the leaf hash
+    pub fn set_tap_hidden_leaf(
+        &mut self,
+        script: &Script,
+        leaf_version: LeafVersion,
+    ) -> TapLeafHash {
+        let leaf_hash = TapLeafHash::from_script(script, leaf_version);
+        let mut value = vec![leaf_version.to_consensus()];
+        value.extend(script.as_bytes());
+        let key = Input::tap_hidden_key(PSBT_IN_TAPHIDDEN_LEAF, leaf_hash);
+        self.proprietary.insert(key, value);
+        leaf_hash
+    }
+
+    /// Returns hidden taproot script leaves added to the input. Leaves with
+    /// invalid encoding or not matching their leaf hash are ignored.
+    pub fn tap_hidden_leaves(&self) -> Vec<(Script, LeafVersion)> {
+        self.proprietary
+            .iter()
+            .filter(|(key, _)| {
+                key.prefix == PSBT_TAPHIDDEN_PREFIX && key.subtype == PSBT_IN_TAPHIDDEN_LEAF
+            })
+            .filter_map(|(key, value)| {
+                let (leaf_version, script) = value.split_first()?;
+                let leaf_version = LeafVersion::from_consensus(*leaf_version).ok()?;
+                let script = Script::from(script.to_vec());
+                let leaf_hash = TapLeafHash::from_script(&script, leaf_version);
+                (key.key == leaf_hash[..]).then_some((script, leaf_version))
+            })
+            .collect()
+    }
+
+    /// Adds merkle proof of the hidden taproot leaf inclusion into the script
+    /// tree of the spent output
+    pub fn set_tap_hidden_proof(&mut self, leaf_hash: TapLeafHash, proof: &TaprootMerkleBranch) {
+        let key = Input::tap_hidden_key(PSBT_IN_TAPHIDDEN_PROOF, leaf_hash);
+        self.proprietary.insert(key, proof.serialize());
+    }
+
+    /// Returns merkle proof of the hidden taproot leaf, if any
+    pub fn tap_hidden_proof(&self, leaf_hash: TapLeafHash) -> Option<TaprootMerkleBranch> {
+        self.proprietary
+            .get(&Input::tap_hidden_key(PSBT_IN_TAPHIDDEN_PROOF, leaf_hash))
+            .and_then(|value| TaprootMerkleBranch::from_slice(value).ok())
+    }
+
+    /// Validates merkle proofs of the hidden taproot leaves against the spent
+    /// output key and adds control blocks for the leaves into the
+    /// `tap_scripts` input field. Sets taproot merkle root of the input if it
+    /// was not known.
+    ///
+    /// Leaves which are already present in `tap_scripts` or have no merkle
+    /// proof are skipped.
+    ///
+    /// # Returns
+    ///
+    /// Number of resolved leaves, or an error if any of the proofs is invalid.
+    pub fn resolve_tap_hidden_leaves<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+    ) -> Result<usize, TapHiddenError> {
+        let leaves = self.tap_hidden_leaves();
+        if leaves.is_empty() {
+            return Ok(0);
+        }
+
+        let internal_key = self.tap_internal_key.ok_or(TapHiddenError::NoInternalKey)?;
+        let script_pubkey = &self.input_prevout()?.script_pubkey;
+        if !script_pubkey.is_v1_p2tr() {
+            return Err(TapHiddenError::NonTaprootPrevout);
+        }
+        let output_key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+            .map_err(|_| TapHiddenError::NonTaprootPrevout)?;
+
+        let mut count = 0usize;
+        for (script, leaf_version) in leaves {
+            if self
+                .tap_scripts
+                .values()
+                .any(|(known, ver)| known == &script && *ver == leaf_version)
+            {
+                continue;
+            }
+            let leaf_hash = TapLeafHash::from_script(&script, leaf_version);
+            let merkle_branch = match self.tap_hidden_proof(leaf_hash) {
+                Some(proof) => proof,
+                None => continue,
+            };
+
+            let root = merkle_branch.as_inner().iter().fold(
+                sha256::Hash::from_inner(leaf_hash.into_inner()),
+                |node, sibling| {
+                    sha256::Hash::from_inner(
+                        TapBranchHash::from_node_hashes(node, *sibling).into_inner(),
+                    )
+                },
+            );
+            let root = TapBranchHash::from_inner(root.into_inner());
+            if matches!(self.tap_merkle_root, Some(known) if known != root) {
+                return Err(TapHiddenError::MerkleRootMismatch(leaf_hash));
+            }
+            let (tweaked, output_key_parity) = internal_key.tap_tweak(secp, Some(root));
+            if tweaked.to_inner() != output_key {
+                return Err(TapHiddenError::InvalidProof(leaf_hash));
+            }
+
+            let control_block = ControlBlock {
+                leaf_version,
+                output_key_parity,
+                internal_key,
+                merkle_branch,
+            };
+            self.tap_scripts
+                .insert(control_block, (script, leaf_version));
+            self.tap_merkle_root = Some(root);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::secp256k1::{KeyPair, SecretKey};
+    use bitcoin::util::taproot::TaprootBuilder;
+    use bitcoin::{OutPoint, Sequence, TxIn, TxOut, Witness};
+
+    use super::*;
+
+    #[test]
+    fn hidden_leaf_resolution() {
+        let secp = Secp256k1::new();
+        let seckey = SecretKey::from_slice(&[0x01; 32]).unwrap();
+        let (internal_key, _) = KeyPair::from_secret_key(&secp, &seckey).x_only_public_key();
+
+        let own = Script::from(vec![0x51]);
+        let hidden = Script::from(vec![0x52]);
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(1, own.clone())
+            .unwrap()
+            .add_leaf(1, hidden)
+            .unwrap()
+            .finalize(&secp, internal_key)
+            .unwrap();
+        let expected = spend_info
+            .control_block(&(own.clone(), LeafVersion::TapScript))
+            .unwrap();
+
+        let mut input = Input::new(0, TxIn {
+            previous_output: OutPoint::default(),
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        })
+        .unwrap();
+        input.witness_utxo = Some(TxOut {
+            value: 10_000,
+            script_pubkey: Script::new_v1_p2tr_tweaked(spend_info.output_key()),
+        });
+        input.tap_internal_key = Some(internal_key);
+
+        let leaf_hash = input.set_tap_hidden_leaf(&own, LeafVersion::TapScript);
+        assert_eq!(input.tap_hidden_leaves(), vec![(
+            own.clone(),
+            LeafVersion::TapScript
+        )]);
+        assert_eq!(input.resolve_tap_hidden_leaves(&secp), Ok(0));
+
+        let mut forged = input.clone();
+        let forged_proof = TaprootMerkleBranch::from_slice(&[0x00; 32]).unwrap();
+        forged.set_tap_hidden_proof(leaf_hash, &forged_proof);
+        assert_eq!(
+            forged.resolve_tap_hidden_leaves(&secp),
+            Err(TapHiddenError::InvalidProof(leaf_hash))
+        );
+
+        input.set_tap_hidden_proof(leaf_hash, &expected.merkle_branch);
+        assert_eq!(input.resolve_tap_hidden_leaves(&secp), Ok(1));
+        assert_eq!(
+            input.tap_scripts.get(&expected),
+            Some(&(own, LeafVersion::TapScript))
+        );
+        assert_eq!(input.tap_merkle_root, spend_info.merkle_root());
+        assert_eq!(input.resolve_tap_hidden_leaves(&secp), Ok(0));
+    }
+}
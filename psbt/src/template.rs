@@ -0,0 +1,377 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Transaction templates: drafts capturing user intent (what to spend, whom
+//! to pay, lock time, RBF and fee policies) independently from PSBT
+//! mechanics.
+//!
+//! Templates reference inputs by outpoint and derivation terminal only and
+//! do not contain any key or previous transaction data, so they can be
+//! persisted, reviewed and approved before being lowered into a PSBT with
+//! [`TxTemplate::lower`], which resolves the spent transactions and wallet
+//! descriptor data.
+
+use std::collections::BTreeSet;
+
+use bitcoin::{OutPoint, Sequence};
+use bitcoin_blockchain::locks::{LockTime, SeqNo};
+use bitcoin_scripts::PubkeyScript;
+use descriptors::InputDescriptor;
+#[cfg(feature = "serde")]
+use serde_with::{As, DisplayFromStr};
+
+/// Errors of transaction template validation
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TemplateError {
+    /// transaction template has no inputs
+    NoInputs,
+
+    /// transaction template has no outputs
+    NoOutputs,
+
+    /// input {0} is spent more than once
+    DuplicateInput(OutPoint),
+
+    /// output #{0} has zero amount and is not an `OP_RETURN` output
+    ZeroAmount(usize),
+
+    /// zero feerate is not allowed
+    ZeroFeerate,
+
+    /// input {0} signals replace-by-fee while the template has RBF disabled
+    RbfConflict(OutPoint),
+}
+
+/// Lock time policy of a transaction template
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum LockTimePolicy {
+    /// Transaction has no lock time
+    #[default]
+    #[display("none")]
+    None,
+
+    /// Lock time is set to the height of the chain tip at the moment of
+    /// lowering, discouraging fee sniping
+    #[display("anti-fee-sniping")]
+    AntiFeeSniping,
+
+    /// Explicit lock time
+    #[display("{0}")]
+    Explicit(LockTime),
+}
+
+/// Fee policy of a transaction template
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub enum FeePolicy {
+    /// Absolute fee, in satoshis
+    #[display("{0} sats")]
+    Absolute(u64),
+
+    /// Feerate, in satoshis per vbyte; the fee is computed from the maximal
+    /// satisfaction size of the wallet descriptor
+    #[display("{0} sat/vbyte")]
+    Feerate(u32),
+}
+
+impl Default for FeePolicy {
+    fn default() -> Self { FeePolicy::Feerate(1) }
+}
+
+/// Transaction template, which can be built step by step, validated,
+/// serialized and later lowered into a PSBT.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct TxTemplate {
+    /// Inputs to spend, referenced by outpoint and derivation terminal
+    #[cfg_attr(feature = "serde", serde(with = "As::<Vec<DisplayFromStr>>"))]
+    pub inputs: Vec<InputDescriptor>,
+
+    /// Outputs to pay, not including change
+    pub outputs: Vec<(PubkeyScript, u64)>,
+
+    /// Lock time policy
+    pub lock_time: LockTimePolicy,
+
+    /// Whether transaction signals replace-by-fee (BIP-125)
+    pub rbf: bool,
+
+    /// Fee policy
+    pub fee: FeePolicy,
+}
+
+impl TxTemplate {
+    /// Constructs empty template with no lock time, RBF disabled and default
+    /// fee policy.
+    #[inline]
+    pub fn new() -> TxTemplate { TxTemplate::default() }
+
+    /// Adds input to the template.
+    pub fn input(mut self, input: InputDescriptor) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    /// Adds output paying `amount` satoshis to `script_pubkey`.
+    pub fn output(mut self, script_pubkey: PubkeyScript, amount: u64) -> Self {
+        self.outputs.push((script_pubkey, amount));
+        self
+    }
+
+    /// Sets lock time policy.
+    pub fn lock_time(mut self, policy: LockTimePolicy) -> Self {
+        self.lock_time = policy;
+        self
+    }
+
+    /// Enables or disables replace-by-fee signalling.
+    pub fn rbf(mut self, rbf: bool) -> Self {
+        self.rbf = rbf;
+        self
+    }
+
+    /// Sets fee policy.
+    pub fn fee(mut self, policy: FeePolicy) -> Self {
+        self.fee = policy;
+        self
+    }
+
+    /// Sum of the output amounts, not including change.
+    pub fn amount(&self) -> u64 { self.outputs.iter().map(|(_, amount)| amount).sum() }
+
+    /// Checks template consistency.
+    pub fn validate(&self) -> Result<(), TemplateError> {
+        if self.inputs.is_empty() {
+            return Err(TemplateError::NoInputs);
+        }
+        if self.outputs.is_empty() {
+            return Err(TemplateError::NoOutputs);
+        }
+        let mut outpoints = BTreeSet::new();
+        for input in &self.inputs {
+            if !outpoints.insert(input.outpoint) {
+                return Err(TemplateError::DuplicateInput(input.outpoint));
+            }
+            if !self.rbf && Sequence(input.seq_no.into_consensus()).is_rbf() {
+                return Err(TemplateError::RbfConflict(input.outpoint));
+            }
+        }
+        if let Some(index) = self
+            .outputs
+            .iter()
+            .position(|(script, amount)| *amount == 0 && !script.is_op_return())
+        {
+            return Err(TemplateError::ZeroAmount(index));
+        }
+        if self.fee == FeePolicy::Feerate(0) {
+            return Err(TemplateError::ZeroFeerate);
+        }
+        Ok(())
+    }
+
+    /// Returns template inputs with sequence numbers updated according to the
+    /// RBF flag and lock time. Inputs with relative timelocks are not
+    /// changed.
+    pub fn sequenced_inputs(&self) -> Vec<InputDescriptor> {
+        let final_seq = Sequence::MAX.to_consensus_u32();
+        self.inputs
+            .iter()
+            .cloned()
+            .map(|mut input| {
+                let seq_no = input.seq_no.into_consensus();
+                if seq_no < Sequence::ENABLE_LOCKTIME_NO_RBF.to_consensus_u32() {
+                    return input;
+                }
+                if self.rbf {
+                    input.seq_no =
+                        SeqNo::from_consensus(Sequence::ENABLE_RBF_NO_LOCKTIME.to_consensus_u32());
+                } else if seq_no == final_seq && self.lock_time != LockTimePolicy::None {
+                    // Lock time is enforced only for inputs with non-final sequence numbers
+                    input.seq_no =
+                        SeqNo::from_consensus(Sequence::ENABLE_LOCKTIME_NO_RBF.to_consensus_u32());
+                }
+                input
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "construct")]
+mod _construct {
+    use bitcoin_hd::{DerivationAccount, UnhardenedIndex};
+    use bitcoin_onchain::ResolveTx;
+    use descriptors::estimate::TXIN_BASE_WEIGHT;
+    use miniscript::Descriptor;
+
+    use super::*;
+    use crate::construct::{self, max_input_weight};
+    use crate::Psbt;
+
+    /// Errors lowering transaction template into PSBT
+    #[derive(Debug, Display, Error, From)]
+    #[display(doc_comments)]
+    pub enum LowerError {
+        /// invalid transaction template: {0}
+        #[from]
+        Template(TemplateError),
+
+        /// anti-fee-sniping lock time policy requires chain tip height
+        NoTipHeight,
+
+        /// {0}
+        #[from]
+        Construct(construct::Error),
+    }
+
+    impl TxTemplate {
+        /// Lowers the template into a PSBT spending the wallet descriptor and
+        /// sending change to the change descriptor at `change_index`, resolving
+        /// spent transactions with `tx_resolver`. Chain tip height is required
+        /// only for [`LockTimePolicy::AntiFeeSniping`].
+        pub fn lower(
+            &self,
+            descriptor: &Descriptor<DerivationAccount>,
+            change_descriptor: &Descriptor<DerivationAccount>,
+            change_index: impl Into<UnhardenedIndex>,
+            tip_height: Option<u32>,
+            tx_resolver: &impl ResolveTx,
+        ) -> Result<Psbt, LowerError> {
+            self.validate()?;
+
+            let lock_time = match self.lock_time {
+                LockTimePolicy::None => None,
+                LockTimePolicy::AntiFeeSniping => {
+                    Some(LockTime::from(tip_height.ok_or(LowerError::NoTipHeight)?))
+                }
+                LockTimePolicy::Explicit(lock_time) => Some(lock_time),
+            };
+            let inputs = self.sequenced_inputs();
+            let change_index = change_index.into();
+            let construct = |fee| {
+                let mut psbt = Psbt::construct_with_change(
+                    descriptor,
+                    change_descriptor,
+                    &inputs,
+                    &self.outputs,
+                    change_index,
+                    fee,
+                    tx_resolver,
+                )?;
+                psbt.fallback_locktime = lock_time;
+                Ok::<_, LowerError>(psbt)
+            };
+
+            let fee = match self.fee {
+                FeePolicy::Absolute(fee) => fee,
+                FeePolicy::Feerate(feerate) => {
+                    let draft = construct(0)?;
+                    // Unsigned transaction already contains outpoints, sequence
+                    // numbers and empty scriptSig lengths
+                    let satisfaction_weight = max_input_weight(descriptor)?
+                        .max(max_input_weight(change_descriptor)?)
+                        - TXIN_BASE_WEIGHT
+                        - 4;
+                    let weight = draft.to_unsigned_tx().weight()
+                        + 2
+                        + draft.inputs.len() * satisfaction_weight;
+                    ((weight + 3) / 4) as u64 * feerate as u64
+                }
+            };
+            construct(fee)
+        }
+    }
+}
+#[cfg(feature = "construct")]
+pub use _construct::LowerError;
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Script, WPubkeyHash};
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::*;
+
+    #[test]
+    fn template() {
+        let mut input = InputDescriptor::from_str(
+            "9a035b0e6e9d07065a31c49884cb1c2d8953636346e91948df75b20e27f50f24:8 /0/15",
+        )
+        .unwrap();
+        input.seq_no = SeqNo::from_consensus(Sequence::MAX.to_consensus_u32());
+        let script = PubkeyScript::from(Script::new_v0_p2wpkh(&WPubkeyHash::hash(&[1])));
+
+        assert_eq!(TxTemplate::new().validate(), Err(TemplateError::NoInputs));
+        let template = TxTemplate::new()
+            .input(input.clone())
+            .output(script.clone(), 10_000)
+            .lock_time(LockTimePolicy::AntiFeeSniping)
+            .fee(FeePolicy::Feerate(5));
+        assert_eq!(template.validate(), Ok(()));
+        assert_eq!(template.amount(), 10_000);
+        assert_eq!(
+            template.clone().input(input.clone()).validate(),
+            Err(TemplateError::DuplicateInput(input.outpoint))
+        );
+        assert_eq!(
+            template.clone().output(script.clone(), 0).validate(),
+            Err(TemplateError::ZeroAmount(1))
+        );
+        let op_return = PubkeyScript::from(Script::new_op_return(b"memo"));
+        assert_eq!(template.clone().output(op_return, 0).validate(), Ok(()));
+
+        let mut rbf_input = input.clone();
+        rbf_input.outpoint.vout = 9;
+        rbf_input.seq_no =
+            SeqNo::from_consensus(Sequence::ENABLE_RBF_NO_LOCKTIME.to_consensus_u32());
+        assert_eq!(
+            template.clone().input(rbf_input.clone()).validate(),
+            Err(TemplateError::RbfConflict(rbf_input.outpoint))
+        );
+        assert_eq!(
+            template.clone().rbf(true).input(rbf_input).validate(),
+            Ok(())
+        );
+
+        let sequenced = template.sequenced_inputs();
+        assert_eq!(
+            sequenced[0].seq_no.into_consensus(),
+            Sequence::ENABLE_LOCKTIME_NO_RBF.to_consensus_u32()
+        );
+        let sequenced = template.clone().rbf(true).sequenced_inputs();
+        assert!(Sequence(sequenced[0].seq_no.into_consensus()).is_rbf());
+
+        let data = template.strict_serialize().unwrap();
+        assert_eq!(TxTemplate::strict_deserialize(data).unwrap(), template);
+    }
+}
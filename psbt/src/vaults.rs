@@ -0,0 +1,542 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Revault-style vaults: two-stage spending of funds controlled by a set of
+//! stakeholder (cold) keys.
+//!
+//! Funds are deposited to the vault descriptor requiring signatures of all
+//! stakeholders. To spend them, the stakeholders sign an unvault transaction
+//! moving the funds to the unvault descriptor, which can be spent either
+//! - by the managers (hot keys) once the unvault output has remained unspent
+//!   for [`Vault::delay`] blocks; or
+//! - by the stakeholders at any time, which is used by the cancel transaction
+//!   returning funds back to the vault descriptor.
+//!
+//! Stakeholders must never sign the unvault transaction before they have
+//! pre-signed the cancel transaction for each of its outputs (see
+//! [`CancelStore`] and [`Vault::check_unvault`]), such that any unauthorized
+//! unvault can be reverted during the timelock. Cancel transactions are signed
+//! with `SIGHASH_ALL | SIGHASH_ANYONECANPAY`, allowing to add fee-bumping
+//! inputs at the broadcast time.
+//!
+//! Vault descriptors are segwit v0 descriptors compiled from miniscript
+//! policies; all vault keys must use two-step (`/*/*`) terminal derivation
+//! paths.
+
+use std::collections::BTreeMap;
+
+use bitcoin::secp256k1::{self, Secp256k1, Verification};
+use bitcoin::util::bip32::KeySource;
+use bitcoin::{EcdsaSighashType, OutPoint, Sequence};
+use bitcoin_hd::{DerivationAccount, DerivationSubpath, UnhardenedIndex};
+use bitcoin_onchain::ResolveTx;
+use bitcoin_scripts::PubkeyScript;
+use descriptors::derive::Descriptor as _;
+use descriptors::locks::Lock;
+use descriptors::policy::{compile, PolicyError};
+use descriptors::{DescriptorClass, InputDescriptor};
+use miniscript::policy::Concrete;
+use miniscript::Descriptor;
+
+use crate::{construct, AuditError, Error, Psbt};
+
+/// Errors constructing and verifying vault transactions
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum VaultError {
+    /// vault must have at least one stakeholder and one manager key
+    NoKeys,
+
+    /// managers threshold {threshold} is invalid for {managers} manager keys
+    InvalidThreshold {
+        /// Required number of manager signatures
+        threshold: usize,
+        /// Number of manager keys
+        managers: usize,
+    },
+
+    /// unvault timelock must be a non-zero number of blocks
+    ZeroDelay,
+
+    /// unable to compile vault descriptor. {0}
+    #[from]
+    Policy(PolicyError),
+
+    /// unable to construct vault transaction. {0}
+    #[from]
+    Construct(construct::Error),
+
+    /// unvault transaction has no output to cancel
+    NoUnvaultOutput,
+
+    /// unvault output #{0} does not contain derivation information for the
+    /// vault keys
+    UnvaultDerivation(u32),
+
+    /// cancel transaction must spend a single unvault output, while it has {0}
+    /// inputs
+    CancelInputs(usize),
+
+    /// unable to merge cancel transaction signatures: {0}
+    #[from]
+    Combine(Error),
+
+    /// no cancel transaction is known for the unvault output {0}
+    NoCancel(OutPoint),
+
+    /// cancel transaction for the unvault output {0} is not signed by all of
+    /// the stakeholders
+    CancelUnsigned(OutPoint),
+
+    /// cancel transaction for the unvault output {0} has invalid signature:
+    /// {1}
+    CancelSignature(OutPoint, AuditError),
+}
+
+/// Vault parameters: stakeholder and manager keys and the unvault timelock
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Vault {
+    /// Stakeholder (cold) keys, all of which are required to unvault or
+    /// cancel funds
+    pub stakeholders: Vec<DerivationAccount>,
+
+    /// Manager (hot) keys spending unvaulted funds after the timelock expiry
+    pub managers: Vec<DerivationAccount>,
+
+    /// Number of manager signatures required to spend unvaulted funds
+    pub managers_threshold: usize,
+
+    /// Number of blocks the unvault output must remain unspent before the
+    /// managers can spend it
+    pub delay: u16,
+}
+
+impl Vault {
+    /// Constructs vault from the stakeholder and manager keys, checking the
+    /// consistency of the parameters.
+    pub fn with(
+        stakeholders: Vec<DerivationAccount>,
+        managers: Vec<DerivationAccount>,
+        managers_threshold: usize,
+        delay: u16,
+    ) -> Result<Vault, VaultError> {
+        if stakeholders.is_empty() || managers.is_empty() {
+            return Err(VaultError::NoKeys);
+        }
+        if managers_threshold == 0 || managers_threshold > managers.len() {
+            return Err(VaultError::InvalidThreshold {
+                threshold: managers_threshold,
+                managers: managers.len(),
+            });
+        }
+        if delay == 0 {
+            return Err(VaultError::ZeroDelay);
+        }
+        Ok(Vault {
+            stakeholders,
+            managers,
+            managers_threshold,
+            delay,
+        })
+    }
+
+    fn threshold_policy(
+        threshold: usize,
+        keys: &[DerivationAccount],
+    ) -> Concrete<DerivationAccount> {
+        match keys {
+            [key] => Concrete::Key(key.clone()),
+            keys => {
+                Concrete::Threshold(threshold, keys.iter().cloned().map(Concrete::Key).collect())
+            }
+        }
+    }
+
+    /// Returns timelock restricting manager spendings of the unvault outputs
+    pub fn unvault_lock(&self) -> Lock { Lock::OlderBlocks(self.delay) }
+
+    /// Returns spending policy of the vault deposits, requiring signatures of
+    /// all stakeholders
+    pub fn deposit_policy(&self) -> Concrete<DerivationAccount> {
+        Vault::threshold_policy(self.stakeholders.len(), &self.stakeholders)
+    }
+
+    /// Returns spending policy of the unvault outputs: either all
+    /// stakeholders at any time, or the threshold of managers after the
+    /// timelock expiry. Manager spending path is assumed to be more probable.
+    pub fn unvault_policy(&self) -> Concrete<DerivationAccount> {
+        let managers = Vault::threshold_policy(self.managers_threshold, &self.managers);
+        let lock = Concrete::Older(Sequence::from_height(self.delay));
+        Concrete::Or(vec![
+            (1, self.deposit_policy()),
+            (9, Concrete::And(vec![managers, lock])),
+        ])
+    }
+
+    /// Produces vault deposit descriptor
+    pub fn deposit_descriptor(&self) -> Result<Descriptor<DerivationAccount>, VaultError> {
+        Ok(compile(&self.deposit_policy(), DescriptorClass::SegwitV0)?.descriptor)
+    }
+
+    /// Produces descriptor of the unvault transaction outputs
+    pub fn unvault_descriptor(&self) -> Result<Descriptor<DerivationAccount>, VaultError> {
+        Ok(compile(&self.unvault_policy(), DescriptorClass::SegwitV0)?.descriptor)
+    }
+
+    /// Constructs unvault transaction moving funds from the vault `deposits`
+    /// to a single unvault output at the `unvault_index`. The transaction has
+    /// to be signed by the stakeholders only after the cancel transaction for
+    /// its output is pre-signed (see [`Vault::construct_cancel`]).
+    pub fn construct_unvault<'inputs>(
+        &self,
+        deposits: impl IntoIterator<Item = &'inputs InputDescriptor>,
+        unvault_index: UnhardenedIndex,
+        fee: u64,
+        tx_resolver: &impl ResolveTx,
+    ) -> Result<Psbt, VaultError> {
+        let outputs: [(PubkeyScript, u64); 0] = [];
+        let psbt = Psbt::construct_with_change(
+            &self.deposit_descriptor()?,
+            &self.unvault_descriptor()?,
+            deposits,
+            &outputs,
+            unvault_index,
+            fee,
+            tx_resolver,
+        )?;
+        if psbt.outputs.is_empty() {
+            return Err(VaultError::NoUnvaultOutput);
+        }
+        Ok(psbt)
+    }
+
+    /// Constructs cancel transaction spending the output of the (not yet
+    /// signed) `unvault` transaction back to the vault deposit descriptor at
+    /// the `deposit_index`.
+    pub fn construct_cancel(
+        &self,
+        unvault: &Psbt,
+        deposit_index: UnhardenedIndex,
+        fee: u64,
+    ) -> Result<Psbt, VaultError> {
+        let unvault_descriptor = self.unvault_descriptor()?;
+        let output = unvault.outputs.first().ok_or(VaultError::NoUnvaultOutput)?;
+        let vout = output.index() as u32;
+        let pattern_len = unvault_descriptor
+            .derive_pattern_len()
+            .map_err(construct::Error::from)?;
+        let terminal = output
+            .bip32_derivation
+            .values()
+            .find_map(|(_, path)| {
+                let path = path.as_ref();
+                let offset = path.len().checked_sub(pattern_len)?;
+                path[offset..]
+                    .iter()
+                    .map(|child| UnhardenedIndex::try_from(*child).ok())
+                    .collect::<Option<Vec<_>>>()
+            })
+            .ok_or(VaultError::UnvaultDerivation(vout))?;
+
+        let tx = unvault.to_unsigned_tx();
+        let outpoint = OutPoint::new(tx.txid(), vout);
+        let mut input = InputDescriptor::with(outpoint, DerivationSubpath::from(terminal));
+        input.sighash_type = EcdsaSighashType::AllPlusAnyoneCanPay;
+        let outputs: [(PubkeyScript, u64); 0] = [];
+        let psbt = Psbt::construct_with_change(
+            &unvault_descriptor,
+            &self.deposit_descriptor()?,
+            [&input],
+            &outputs,
+            deposit_index,
+            fee,
+            &bmap! { tx.txid() => tx },
+        )?;
+        Ok(psbt)
+    }
+
+    /// Constructs transaction spending unvault outputs by the managers to
+    /// given `outputs`. Input sequence numbers are set to satisfy the unvault
+    /// timelock; change returns to the vault deposit descriptor at the
+    /// `change_index`.
+    pub fn construct_spend<'inputs, 'outputs>(
+        &self,
+        unvaults: impl IntoIterator<Item = &'inputs InputDescriptor>,
+        outputs: impl IntoIterator<Item = &'outputs (PubkeyScript, u64)>,
+        change_index: UnhardenedIndex,
+        fee: u64,
+        tx_resolver: &impl ResolveTx,
+    ) -> Result<Psbt, VaultError> {
+        let seq_no = self
+            .unvault_lock()
+            .to_seq_no()
+            .expect("vault uses relative timelock");
+        let inputs = unvaults
+            .into_iter()
+            .map(|input| InputDescriptor {
+                seq_no,
+                ..input.clone()
+            })
+            .collect::<Vec<_>>();
+        let psbt = Psbt::construct_with_change(
+            &self.unvault_descriptor()?,
+            &self.deposit_descriptor()?,
+            &inputs,
+            outputs,
+            change_index,
+            fee,
+            tx_resolver,
+        )?;
+        Ok(psbt)
+    }
+
+    fn is_stakeholder_key<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        pubkey: &secp256k1::PublicKey,
+        (fingerprint, path): &KeySource,
+    ) -> bool {
+        self.stakeholders.iter().any(|account| {
+            let account_path = account.to_account_derivation_path();
+            let (account_path, path) = (account_path.as_ref(), path.as_ref());
+            if *fingerprint != account.master_fingerprint().unwrap_or_default()
+                || !path.starts_with(account_path)
+            {
+                return false;
+            }
+            let terminal = path[account_path.len()..].to_vec();
+            matches!(
+                account.account_xpub.derive_pub(secp, &terminal),
+                Ok(xpub) if &xpub.public_key == pubkey
+            )
+        })
+    }
+
+    /// Checks that the `cancel` transaction is signed by all stakeholders
+    /// and that all of its signatures are valid.
+    ///
+    /// # Returns
+    ///
+    /// Unvault output spent by the cancel transaction.
+    pub fn check_cancel<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        cancel: &Psbt,
+    ) -> Result<OutPoint, VaultError> {
+        let input = match cancel.inputs.as_slice() {
+            [input] => input,
+            inputs => return Err(VaultError::CancelInputs(inputs.len())),
+        };
+        let outpoint = input.previous_outpoint;
+        for audit in cancel.verify_signatures(secp) {
+            for sig in audit.signatures {
+                sig.status
+                    .map_err(|err| VaultError::CancelSignature(outpoint, err))?;
+            }
+        }
+        let signed = input
+            .bip32_derivation
+            .iter()
+            .filter(|(pubkey, _)| {
+                input
+                    .partial_sigs
+                    .contains_key(&bitcoin::PublicKey::new(**pubkey))
+            })
+            .filter(|(pubkey, source)| self.is_stakeholder_key(secp, pubkey, source))
+            .count();
+        if signed < self.stakeholders.len() {
+            return Err(VaultError::CancelUnsigned(outpoint));
+        }
+        Ok(outpoint)
+    }
+
+    /// Checks that the pre-signed cancel transactions from the `cancels`
+    /// store are known for each of the `unvault` transaction outputs, such
+    /// that the unvault transaction can be signed by the stakeholders.
+    pub fn check_unvault<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        unvault: &Psbt,
+        cancels: &CancelStore,
+    ) -> Result<(), VaultError> {
+        let txid = unvault.to_txid();
+        for output in &unvault.outputs {
+            let outpoint = OutPoint::new(txid, output.index() as u32);
+            let cancel = cancels
+                .get(outpoint)
+                .ok_or(VaultError::NoCancel(outpoint))?;
+            self.check_cancel(secp, cancel)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collection of the cancel transactions indexed by the unvault outputs they
+/// spend. Partially signed cancel transactions coming from different
+/// stakeholders are merged together.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct CancelStore(BTreeMap<OutPoint, Psbt>);
+
+impl CancelStore {
+    /// Adds cancel transaction to the store. If the store already contains
+    /// the same transaction, the signatures are merged; otherwise the
+    /// existing cancel transaction for the same unvault output is replaced.
+    ///
+    /// # Returns
+    ///
+    /// Unvault output spent by the cancel transaction.
+    pub fn insert(&mut self, cancel: Psbt) -> Result<OutPoint, VaultError> {
+        let outpoint = match cancel.inputs.as_slice() {
+            [input] => input.previous_outpoint,
+            inputs => return Err(VaultError::CancelInputs(inputs.len())),
+        };
+        let cancel = match self.0.remove(&outpoint) {
+            Some(existing) if existing.to_txid() == cancel.to_txid() => existing.combine(cancel)?,
+            _ => cancel,
+        };
+        self.0.insert(outpoint, cancel);
+        Ok(outpoint)
+    }
+
+    /// Returns cancel transaction for a given unvault output
+    pub fn get(&self, unvault: OutPoint) -> Option<&Psbt> { self.0.get(&unvault) }
+
+    /// Removes cancel transaction for a given unvault output, for instance
+    /// once the unvault output is spent
+    pub fn remove(&mut self, unvault: OutPoint) -> Option<Psbt> { self.0.remove(&unvault) }
+
+    /// Returns number of cancel transactions in the store
+    pub fn len(&self) -> usize { self.0.len() }
+
+    /// Detects whether the store contains no cancel transactions
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::SECP256K1;
+    use bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey};
+    use bitcoin::{Network, PackedLockTime, Script, Transaction, TxOut, WPubkeyHash};
+    use bitcoin_hd::{SegmentIndexes, TerminalStep, XpubRef};
+    use miniscript::policy::Liftable;
+
+    use super::*;
+
+    fn account(seed: u8) -> DerivationAccount {
+        let xpriv = ExtendedPrivKey::new_master(Network::Bitcoin, &[seed; 32]).unwrap();
+        let account_xpub = ExtendedPubKey::from_priv(SECP256K1, &xpriv);
+        DerivationAccount {
+            master: XpubRef::Fingerprint(account_xpub.fingerprint()),
+            account_path: none!(),
+            account_xpub,
+            revocation_seal: None,
+            terminal_path: vec![TerminalStep::Wildcard, TerminalStep::Wildcard].into(),
+        }
+    }
+
+    #[test]
+    fn vault_stages() {
+        assert!(matches!(
+            Vault::with(vec![account(1)], vec![], 1, 144),
+            Err(VaultError::NoKeys)
+        ));
+        assert!(matches!(
+            Vault::with(vec![account(1)], vec![account(3)], 2, 144),
+            Err(VaultError::InvalidThreshold {
+                threshold: 2,
+                managers: 1
+            })
+        ));
+        let vault = Vault::with(vec![account(1), account(2)], vec![account(3)], 1, 144).unwrap();
+        let deposit_descriptor = vault.deposit_descriptor().unwrap();
+        let unvault_descriptor = vault.unvault_descriptor().unwrap();
+        assert!(unvault_descriptor
+            .lift()
+            .unwrap()
+            .relative_timelocks()
+            .contains(&144));
+
+        let deposit_terminal = [UnhardenedIndex::zero(); 2];
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: deposit_descriptor
+                    .script_pubkey_pretr(SECP256K1, deposit_terminal)
+                    .unwrap(),
+            }],
+        };
+        let txid = funding.txid();
+        let resolver = bmap! { txid => funding };
+        let deposit_terminal = DerivationSubpath::from(&deposit_terminal[..]);
+        let deposit = InputDescriptor::with(OutPoint::new(txid, 0), deposit_terminal);
+
+        let unvault = vault
+            .construct_unvault([&deposit], UnhardenedIndex::from(5u8), 1_000, &resolver)
+            .unwrap();
+        assert_eq!(unvault.outputs.len(), 1);
+        assert_eq!(unvault.outputs[0].amount, 99_000);
+
+        let cancel = vault
+            .construct_cancel(&unvault, UnhardenedIndex::from(6u8), 1_000)
+            .unwrap();
+        let unvault_outpoint = OutPoint::new(unvault.to_txid(), 0);
+        assert_eq!(cancel.inputs[0].previous_outpoint, unvault_outpoint);
+        assert_eq!(
+            cancel.inputs[0].sighash_type,
+            Some(EcdsaSighashType::AllPlusAnyoneCanPay.into())
+        );
+        assert_eq!(cancel.outputs[0].amount, 98_000);
+        let redeposit = [UnhardenedIndex::one(), UnhardenedIndex::from(6u8)];
+        let redeposit = deposit_descriptor
+            .script_pubkey_pretr(SECP256K1, redeposit)
+            .unwrap();
+        assert_eq!(cancel.outputs[0].script, PubkeyScript::from(redeposit));
+
+        let mut cancels = CancelStore::default();
+        assert!(matches!(
+            vault.check_unvault(SECP256K1, &unvault, &cancels),
+            Err(VaultError::NoCancel(outpoint)) if outpoint == unvault_outpoint
+        ));
+        assert_eq!(cancels.insert(cancel.clone()).unwrap(), unvault_outpoint);
+        assert_eq!(cancels.insert(cancel).unwrap(), unvault_outpoint);
+        assert_eq!(cancels.len(), 1);
+        assert!(matches!(
+            vault.check_unvault(SECP256K1, &unvault, &cancels),
+            Err(VaultError::CancelUnsigned(outpoint)) if outpoint == unvault_outpoint
+        ));
+
+        let unvault_tx = unvault.to_unsigned_tx();
+        let resolver = bmap! { unvault_tx.txid() => unvault_tx };
+        let unvault_input = InputDescriptor::with(
+            unvault_outpoint,
+            vec![UnhardenedIndex::one(), UnhardenedIndex::from(5u8)].into(),
+        );
+        let payee = Script::new_v0_p2wpkh(&WPubkeyHash::hash(b"payee"));
+        let spend = vault
+            .construct_spend(
+                [&unvault_input],
+                &[(PubkeyScript::from(payee), 50_000)],
+                UnhardenedIndex::from(7u8),
+                1_000,
+                &resolver,
+            )
+            .unwrap();
+        assert_eq!(
+            spend.inputs[0].sequence_number.unwrap().into_consensus(),
+            144
+        );
+        assert_eq!(spend.outputs.len(), 2);
+    }
+}
@@ -0,0 +1,105 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+use std::str::FromStr;
+
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin_hd::account::DerivePublicKey;
+use bitcoin_hd::{DerivationAccount, SegmentIndexes, UnhardenedIndex};
+use pyo3::prelude::*;
+
+use crate::value_err;
+
+/// Converts terminal derivation indexes provided by Python into unhardened
+/// indexes
+pub(crate) fn terminal(pattern: Vec<u32>) -> PyResult<Vec<UnhardenedIndex>> {
+    pattern
+        .into_iter()
+        .map(UnhardenedIndex::from_index)
+        .collect::<Result<_, _>>()
+        .map_err(value_err)
+}
+
+/// HD wallet account: extended public key with information about its origin
+/// and terminal derivation path
+#[pyclass(name = "DerivationAccount", module = "descriptor_wallet")]
+#[derive(Clone, Debug)]
+pub struct PyDerivationAccount(pub(crate) DerivationAccount);
+
+#[pymethods]
+impl PyDerivationAccount {
+    /// Parses account from a string like `[fingerprint/path]xpub/terminal`
+    #[new]
+    fn new(account: &str) -> PyResult<Self> {
+        DerivationAccount::from_str(account)
+            .map(Self)
+            .map_err(value_err)
+    }
+
+    /// Fingerprint of the master key, if known
+    #[getter]
+    fn master_fingerprint(&self) -> Option<String> {
+        self.0.master_fingerprint().map(|fp| fp.to_string())
+    }
+
+    /// Fingerprint of the account extended public key
+    #[getter]
+    fn account_fingerprint(&self) -> String { self.0.account_fingerprint().to_string() }
+
+    /// Account extended public key
+    #[getter]
+    fn account_xpub(&self) -> String { self.0.account_xpub.to_string() }
+
+    /// Derivation path from the master key to the account key
+    #[getter]
+    fn account_path(&self) -> String { self.0.to_account_derivation_path().to_string() }
+
+    /// Derives hex-encoded public key for the terminal derivation `pattern`
+    fn derive_public_key(&self, pattern: Vec<u32>) -> PyResult<String> {
+        self.0
+            .derive_public_key(SECP256K1, terminal(pattern)?)
+            .map(|pubkey| pubkey.to_string())
+            .map_err(value_err)
+    }
+
+    fn __str__(&self) -> String { self.0.to_string() }
+
+    fn __repr__(&self) -> String { format!("DerivationAccount('{}')", self.0) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ACCOUNT: &str = "[d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQ\
+                           Y4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/*/*";
+
+    #[test]
+    fn account() {
+        let account = PyDerivationAccount::new(ACCOUNT).unwrap();
+        assert_eq!(account.master_fingerprint(), Some("d34db33f".to_owned()));
+        assert_eq!(account.account_path(), "m/84'/0'/0'");
+        assert!(account.account_xpub().starts_with("xpub6D4BDPcP2GT5"));
+        assert_eq!(account.__str__(), account.0.to_string());
+        assert_eq!(
+            account.__repr__(),
+            format!("DerivationAccount('{}')", account.0)
+        );
+
+        let receive = account.derive_public_key(vec![0, 0]).unwrap();
+        let change = account.derive_public_key(vec![1, 0]).unwrap();
+        assert_eq!(receive.len(), 66);
+        assert_ne!(receive, change);
+        assert!(account.derive_public_key(vec![0, 0x8000_0000]).is_err());
+
+        assert!(PyDerivationAccount::new("[d34db33f/84h/0h/0h]xpub").is_err());
+    }
+}
@@ -0,0 +1,120 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+use std::str::FromStr;
+
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::Network;
+use bitcoin_hd::DerivationAccount;
+use descriptors::derive::Descriptor as _;
+use miniscript::{Descriptor, ForEachKey};
+use pyo3::prelude::*;
+
+use crate::account::{terminal, PyDerivationAccount};
+use crate::value_err;
+
+/// Wallet output descriptor with keys given as derivation accounts
+#[pyclass(name = "Descriptor", module = "descriptor_wallet")]
+#[derive(Clone, Debug)]
+pub struct PyDescriptor(pub(crate) Descriptor<DerivationAccount>);
+
+#[pymethods]
+impl PyDescriptor {
+    /// Parses and validates output descriptor
+    #[new]
+    fn new(descriptor: &str) -> PyResult<Self> {
+        let descriptor = Descriptor::from_str(descriptor).map_err(value_err)?;
+        descriptor.check_sanity().map_err(value_err)?;
+        Ok(Self(descriptor))
+    }
+
+    /// Number of terminal derivation indexes required to derive scripts
+    #[getter]
+    fn derive_pattern_len(&self) -> PyResult<usize> {
+        self.0.derive_pattern_len().map_err(value_err)
+    }
+
+    /// Accounts used by the descriptor keys
+    #[getter]
+    fn accounts(&self) -> Vec<PyDerivationAccount> {
+        let mut accounts = vec![];
+        self.0.for_each_key(|account| {
+            accounts.push(PyDerivationAccount(account.clone()));
+            true
+        });
+        accounts
+    }
+
+    /// Derives hex-encoded `scriptPubkey` for the terminal derivation
+    /// `pattern`
+    fn script_pubkey(&self, pattern: Vec<u32>) -> PyResult<String> {
+        let pattern = terminal(pattern)?;
+        match self.0 {
+            Descriptor::Tr(_) => self.0.script_pubkey_tr(SECP256K1, pattern),
+            _ => self.0.script_pubkey_pretr(SECP256K1, pattern),
+        }
+        .map(|script| script.to_hex())
+        .map_err(value_err)
+    }
+
+    /// Derives address for the terminal derivation `pattern` on a `network`
+    /// (`bitcoin`, `testnet`, `signet` or `regtest`)
+    #[pyo3(signature = (pattern, network = "bitcoin"))]
+    fn address(&self, pattern: Vec<u32>, network: &str) -> PyResult<String> {
+        let network = Network::from_str(network).map_err(value_err)?;
+        self.0
+            .address(SECP256K1, terminal(pattern)?, network)
+            .map(|address| address.to_string())
+            .map_err(value_err)
+    }
+
+    fn __str__(&self) -> String { self.0.to_string() }
+
+    fn __repr__(&self) -> String { format!("Descriptor('{}')", self.0) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DESCRIPTOR: &str = "wpkh([d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3P\
+                              WbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcL\
+                              W5/*/*)";
+
+    #[test]
+    fn descriptor() {
+        let descriptor = PyDescriptor::new(DESCRIPTOR).unwrap();
+        assert_eq!(descriptor.derive_pattern_len().unwrap(), 2);
+        let accounts = descriptor.accounts();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(
+            accounts[0].master_fingerprint(),
+            Some("d34db33f".to_owned())
+        );
+
+        let script = descriptor.script_pubkey(vec![0, 0]).unwrap();
+        assert_eq!(script.len(), 44);
+        assert!(script.starts_with("0014"));
+        assert_ne!(script, descriptor.script_pubkey(vec![0, 1]).unwrap());
+        assert!(descriptor.script_pubkey(vec![0, 0x8000_0000]).is_err());
+
+        let address = descriptor.address(vec![0, 0], "bitcoin").unwrap();
+        assert!(address.starts_with("bc1q"));
+        assert!(descriptor
+            .address(vec![0, 0], "testnet")
+            .unwrap()
+            .starts_with("tb1q"));
+        assert!(descriptor.address(vec![0, 0], "mainnet2").is_err());
+
+        assert!(PyDescriptor::new("wpkh(invalid)").is_err());
+    }
+}
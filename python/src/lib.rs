@@ -0,0 +1,48 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Python bindings for descriptor wallet libraries, exposing derivation
+//! accounts, output descriptors, PSBT construction, signing and finalization
+//! and SLIP-132 key conversions to Python scripts.
+
+// Coding conventions
+#![recursion_limit = "256"]
+#![deny(dead_code, missing_docs)]
+
+extern crate miniscript_crate as miniscript;
+
+mod account;
+mod descriptor;
+mod transaction;
+mod xkey;
+
+use std::fmt::Display;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+pub use crate::account::PyDerivationAccount;
+pub use crate::descriptor::PyDescriptor;
+pub use crate::transaction::PyPsbt;
+
+/// Converts library error into Python `ValueError`
+pub(crate) fn value_err(err: impl Display) -> PyErr { PyValueError::new_err(err.to_string()) }
+
+/// Python module `descriptor_wallet`
+#[pymodule]
+fn descriptor_wallet(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyDerivationAccount>()?;
+    m.add_class::<PyDescriptor>()?;
+    m.add_class::<PyPsbt>()?;
+    m.add_function(wrap_pyfunction!(xkey::xpub_from_slip132, m)?)?;
+    m.add_function(wrap_pyfunction!(xkey::xpub_to_slip132, m)?)?;
+    Ok(())
+}
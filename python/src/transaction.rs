@@ -0,0 +1,232 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use bitcoin::consensus::{deserialize, serialize};
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::{Address, Transaction};
+use bitcoin_hd::{SegmentIndexes, UnhardenedIndex};
+use bitcoin_scripts::PubkeyScript;
+use descriptors::InputDescriptor;
+use miniscript::psbt::PsbtExt;
+use psbt::sign::{MemoryKeyProvider, MemorySigningAccount, SignAll, SigningPolicy};
+use psbt::Psbt;
+use pyo3::prelude::*;
+
+use crate::descriptor::PyDescriptor;
+use crate::value_err;
+
+/// Partially signed bitcoin transaction
+#[pyclass(name = "Psbt", module = "descriptor_wallet")]
+#[derive(Clone, Debug)]
+pub struct PyPsbt(pub(crate) Psbt);
+
+#[pymethods]
+impl PyPsbt {
+    /// Parses base64- or hex-encoded PSBT
+    #[new]
+    fn new(psbt: &str) -> PyResult<Self> {
+        let psbt = if psbt.starts_with("70736274ff") {
+            Psbt::from_hex(psbt)
+        } else {
+            Psbt::from_base64(psbt)
+        };
+        psbt.map(Self).map_err(value_err)
+    }
+
+    /// Constructs PSBT spending outputs controlled by the wallet `descriptor`.
+    ///
+    /// Inputs are given as input descriptor strings (`<txid>:<vout>
+    /// <terminal>`), spent transactions as hex strings and outputs as
+    /// `(address, amount)` pairs. Change is sent to the descriptor address at
+    /// `change_index`.
+    #[staticmethod]
+    fn construct(
+        descriptor: &PyDescriptor,
+        inputs: Vec<&str>,
+        spent_txs: Vec<&str>,
+        outputs: Vec<(&str, u64)>,
+        change_index: u32,
+        fee: u64,
+    ) -> PyResult<Self> {
+        let inputs = inputs
+            .into_iter()
+            .map(InputDescriptor::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(value_err)?;
+        let mut tx_resolver = BTreeMap::new();
+        for tx in spent_txs {
+            let data = Vec::<u8>::from_hex(tx).map_err(value_err)?;
+            let tx: Transaction = deserialize(&data).map_err(value_err)?;
+            tx_resolver.insert(tx.txid(), tx);
+        }
+        let outputs = outputs
+            .into_iter()
+            .map(|(address, amount)| {
+                Address::from_str(address)
+                    .map(|address| (PubkeyScript::from(address.script_pubkey()), amount))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(value_err)?;
+        let change_index = UnhardenedIndex::from_index(change_index).map_err(value_err)?;
+
+        Psbt::construct(
+            &descriptor.0,
+            &inputs,
+            &outputs,
+            change_index,
+            fee,
+            &tx_resolver,
+        )
+        .map(Self)
+        .map_err(value_err)
+    }
+
+    /// Transaction fee, in satoshis
+    #[getter]
+    fn fee(&self) -> PyResult<u64> { self.0.fee().map_err(value_err) }
+
+    /// Signs all inputs which can be signed with the keys derived from the
+    /// master extended private key. Returns number of produced signatures.
+    fn sign(&mut self, master_xpriv: &str) -> PyResult<usize> {
+        let master = ExtendedPrivKey::from_str(master_xpriv).map_err(value_err)?;
+        let master_xpub = ExtendedPubKey::from_priv(SECP256K1, &master);
+        let account = MemorySigningAccount::with(
+            SECP256K1,
+            master_xpub.identifier(),
+            DerivationPath::master(),
+            master,
+        );
+        let mut key_provider = MemoryKeyProvider::with(SECP256K1, false);
+        key_provider.add_account(account);
+        self.0
+            .sign_all(&key_provider, &SigningPolicy::default())
+            .map_err(value_err)
+    }
+
+    /// Finalizes all inputs and returns hex-encoded signed transaction
+    fn finalize(&self) -> PyResult<String> {
+        let mut psbt = PartiallySignedTransaction::from(self.0.clone());
+        for index in 0..psbt.inputs.len() {
+            let input = &psbt.inputs[index];
+            // Inputs satisfied by external systems are already final
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                continue;
+            }
+            psbt.finalize_inp_mut(SECP256K1, index).map_err(value_err)?;
+        }
+        Ok(serialize(&psbt.extract_tx()).to_hex())
+    }
+
+    /// Serializes PSBT as hex string
+    fn to_hex(&self) -> String { self.0.to_hex() }
+
+    fn __str__(&self) -> String { self.0.to_base64() }
+
+    fn __repr__(&self) -> String { format!("Psbt('{}')", self.0) }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Network, OutPoint, PackedLockTime, TxIn, TxOut, Txid};
+
+    use super::*;
+
+    fn wallet() -> (ExtendedPrivKey, PyDescriptor) {
+        let master = ExtendedPrivKey::new_master(Network::Testnet, &[0x07; 32]).unwrap();
+        let path = DerivationPath::from_str("m/84'/1'/0'").unwrap();
+        let xpriv = master.derive_priv(SECP256K1, &path).unwrap();
+        let xpub = ExtendedPubKey::from_priv(SECP256K1, &xpriv);
+        let fingerprint = master.fingerprint(SECP256K1);
+        let descriptor = format!("wpkh([{}/84h/1h/0h]{}/*/*)", fingerprint, xpub);
+        (master, PyDescriptor::new(&descriptor).unwrap())
+    }
+
+    fn construct(descriptor: &PyDescriptor, fee: u64) -> (PyResult<PyPsbt>, String) {
+        let address = descriptor.address(vec![0, 0], "testnet").unwrap();
+        let prev_tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), 0),
+                ..TxIn::default()
+            }],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: Address::from_str(&address).unwrap().script_pubkey(),
+            }],
+        };
+        let destination = descriptor.address(vec![0, 5], "testnet").unwrap();
+        let input = format!("{}:0 /0/0", prev_tx.txid());
+        let psbt = PyPsbt::construct(
+            descriptor,
+            vec![input.as_str()],
+            vec![serialize(&prev_tx).to_hex().as_str()],
+            vec![(destination.as_str(), 50_000)],
+            1,
+            fee,
+        );
+        (psbt, destination)
+    }
+
+    #[test]
+    fn construct_sign_finalize() {
+        let (master, descriptor) = wallet();
+        let (psbt, destination) = construct(&descriptor, 1_000);
+        let mut psbt = psbt.unwrap();
+        assert_eq!(psbt.fee().unwrap(), 1_000);
+        assert!(psbt.finalize().is_err());
+
+        let hex = psbt.to_hex();
+        assert!(hex.starts_with("70736274ff"));
+        assert_eq!(PyPsbt::new(&hex).unwrap().0, psbt.0);
+        assert_eq!(PyPsbt::new(&psbt.__str__()).unwrap().0, psbt.0);
+
+        assert_eq!(psbt.sign(&master.to_string()).unwrap(), 1);
+        let tx: Transaction =
+            deserialize(&Vec::from_hex(&psbt.finalize().unwrap()).unwrap()).unwrap();
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].witness.len(), 2);
+        let destination = Address::from_str(&destination).unwrap().script_pubkey();
+        assert!(tx
+            .output
+            .iter()
+            .any(|out| out.value == 50_000 && out.script_pubkey == destination));
+    }
+
+    #[test]
+    fn errors() {
+        let (_, descriptor) = wallet();
+        // Fee exceeding input value
+        assert!(construct(&descriptor, 100_000).0.is_err());
+
+        let input = format!("{}:0 /0/0", Txid::from_inner([0x01; 32]));
+        // Spent transaction is unknown
+        assert!(
+            PyPsbt::construct(&descriptor, vec![input.as_str()], vec![], vec![], 1, 0).is_err()
+        );
+        assert!(PyPsbt::construct(&descriptor, vec!["txid:0 /0/0"], vec![], vec![], 1, 0).is_err());
+        assert!(PyPsbt::construct(&descriptor, vec![], vec!["00"], vec![], 1, 0).is_err());
+        assert!(PyPsbt::construct(&descriptor, vec![], vec![], vec![("bc1", 1)], 1, 0).is_err());
+        assert!(PyPsbt::construct(&descriptor, vec![], vec![], vec![], 0x8000_0000, 0).is_err());
+
+        assert!(PyPsbt::new("cHNidP8=").is_err());
+        assert!(PyPsbt::new("70736274ff").is_err());
+        let mut psbt = construct(&descriptor, 1_000).0.unwrap();
+        assert!(psbt.sign("xprv").is_err());
+    }
+}
@@ -0,0 +1,62 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+use std::str::FromStr;
+
+use bitcoin::util::bip32::ExtendedPubKey;
+use bitcoin::Network;
+use pyo3::prelude::*;
+use slip132::{FromSlip132, KeyApplication, ToSlip132};
+
+use crate::value_err;
+
+/// Converts SLIP-132 extended public key (`ypub`, `zpub`, `Zpub` etc) into
+/// standard BIP-32 `xpub` or `tpub`
+#[pyfunction]
+pub(crate) fn xpub_from_slip132(key: &str) -> PyResult<String> {
+    ExtendedPubKey::from_slip132_str(key)
+        .map(|xpub| xpub.to_string())
+        .map_err(value_err)
+}
+
+/// Converts BIP-32 extended public key into SLIP-132 representation for the
+/// key `application` (`bip44`, `bip84`, `bip49`, `bip48-native` or
+/// `bip48-nested`) on a `network`
+#[pyfunction]
+#[pyo3(signature = (xpub, application, network = "bitcoin"))]
+pub(crate) fn xpub_to_slip132(xpub: &str, application: &str, network: &str) -> PyResult<String> {
+    let xpub = ExtendedPubKey::from_str(xpub).map_err(value_err)?;
+    let application = KeyApplication::from_str(application).map_err(value_err)?;
+    let network = Network::from_str(network).map_err(value_err)?;
+    Ok(xpub.to_slip132_string(application, network))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const XPUB: &str = "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDF\
+                        GTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5";
+
+    #[test]
+    fn slip132() {
+        let zpub = xpub_to_slip132(XPUB, "bip84", "bitcoin").unwrap();
+        assert!(zpub.starts_with("zpub"));
+        assert_eq!(xpub_from_slip132(&zpub).unwrap(), XPUB);
+        let vpub = xpub_to_slip132(XPUB, "bip84", "testnet").unwrap();
+        assert!(vpub.starts_with("vpub"));
+
+        assert!(xpub_to_slip132(XPUB, "unknown", "bitcoin").is_err());
+        assert!(xpub_to_slip132(XPUB, "bip84", "unknown").is_err());
+        assert!(xpub_to_slip132("xpub", "bip84", "bitcoin").is_err());
+        assert!(xpub_from_slip132("zpub").is_err());
+    }
+}
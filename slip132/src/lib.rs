@@ -26,12 +26,15 @@ extern crate strict_encoding;
 #[macro_use]
 extern crate serde_crate as serde;
 
+mod params;
+
 use std::fmt::Debug;
 use std::str::FromStr;
 
 use bitcoin::util::base58;
 use bitcoin::util::bip32::{self, ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey};
 use bitcoin::Network;
+pub use params::NetworkParams;
 
 /// Magical version bytes for xpub: bitcoin mainnet public key for P2PKH or P2SH
 pub const VERSION_MAGIC_XPUB: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
@@ -231,6 +234,18 @@ pub trait VersionResolver:
     /// Returns `None` if the version is not recognized/unknown to the resolver.
     fn derivation_path(_: &KeyVersion, _: Option<ChildNumber>) -> Option<DerivationPath> { None }
 
+    /// Returns BIP 32 derivation path for the provided key version used in a
+    /// multisig descriptor according to a given multisig derivation standard.
+    /// Returns `None` if the version is not recognized/unknown to the resolver
+    /// or can't be used with the standard.
+    fn multisig_derivation_path(
+        _: &KeyVersion,
+        _: Option<ChildNumber>,
+        _: MultisigStandard,
+    ) -> Option<DerivationPath> {
+        None
+    }
+
     /// Converts version into version corresponding to an extended public key.
     /// Returns `None` if the resolver does not know how to perform conversion.
     fn make_pub(_: &KeyVersion) -> Option<KeyVersion> { None }
@@ -268,6 +283,18 @@ impl KeyVersion {
         R::derivation_path(self, account)
     }
 
+    /// Returns BIP 32 derivation path for the provided key version used in a
+    /// multisig descriptor according to a given multisig derivation standard.
+    /// Returns `None` if the version is not recognized/unknown to the resolver
+    /// or can't be used with the standard.
+    pub fn multisig_derivation_path<R: VersionResolver>(
+        &self,
+        account: Option<ChildNumber>,
+        standard: MultisigStandard,
+    ) -> Option<DerivationPath> {
+        R::multisig_derivation_path(self, account, standard)
+    }
+
     /// Converts version into version corresponding to an extended public key.
     /// Returns `None` if the resolver does not know how to perform conversion.
     pub fn try_to_pub<R: VersionResolver>(&self) -> Option<KeyVersion> { R::make_pub(self) }
@@ -345,6 +372,83 @@ impl FromStr for KeyApplication {
     }
 }
 
+/// Standards defining derivation paths for keys participating in multisig
+/// descriptors
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[cfg_attr(feature = "strict_encoding", derive(StrictEncode, StrictDecode))]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+pub enum MultisigStandard {
+    /// BIP-45: `m/45'` derivation for P2SH multisig with cosigner indexes
+    /// following the purpose. Does not support accounts.
+    #[display("BIP45")]
+    #[cfg_attr(feature = "serde", serde(rename = "bip45"))]
+    Bip45,
+
+    /// BIP-48: `m/48'/coin'/account'/script_type'` derivation for segwit
+    /// (native and nested) multisig.
+    #[display("BIP48")]
+    #[cfg_attr(feature = "serde", serde(rename = "bip48"))]
+    Bip48,
+
+    /// BIP-87: `m/87'/coin'/account'` derivation for descriptor-based
+    /// multisig of any script type.
+    #[display("BIP87")]
+    #[cfg_attr(feature = "serde", serde(rename = "bip87"))]
+    Bip87,
+}
+
+/// Unknown string representation of [`MultisigStandard`] enum
+#[derive(
+    Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display, Error
+)]
+#[display(doc_comments)]
+pub struct UnknownMultisigStandardError;
+
+impl FromStr for MultisigStandard {
+    type Err = UnknownMultisigStandardError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "bip45" => MultisigStandard::Bip45,
+            "bip48" => MultisigStandard::Bip48,
+            "bip87" => MultisigStandard::Bip87,
+            _ => return Err(UnknownMultisigStandardError),
+        })
+    }
+}
+
+impl MultisigStandard {
+    /// Enumerates all multisig standard variants
+    pub const ALL: [MultisigStandard; 3] = [
+        MultisigStandard::Bip45,
+        MultisigStandard::Bip48,
+        MultisigStandard::Bip87,
+    ];
+
+    /// Returns BIP-43 purpose field used by the standard
+    pub fn purpose(self) -> ChildNumber {
+        match self {
+            MultisigStandard::Bip45 => ChildNumber::Hardened { index: 45 },
+            MultisigStandard::Bip48 => ChildNumber::Hardened { index: 48 },
+            MultisigStandard::Bip87 => ChildNumber::Hardened { index: 87 },
+        }
+    }
+
+    /// Deduces multisig standard from the purpose field of the provided
+    /// derivation path, if possible.
+    pub fn from_derivation_path(path: &DerivationPath) -> Option<MultisigStandard> {
+        let purpose = path.as_ref().first()?;
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|standard| standard.purpose() == *purpose)
+    }
+}
+
 impl KeyApplication {
     /// Enumerates all application variants    
     pub const ALL: [KeyApplication; 5] = [
@@ -368,6 +472,15 @@ impl KeyApplication {
             }
         }
         let bip48_purpose = ChildNumber::Hardened { index: 48 };
+        match path.first() {
+            Some(purpose) if *purpose == MultisigStandard::Bip45.purpose() => {
+                return Some(KeyApplication::Hashed)
+            }
+            Some(purpose) if *purpose == MultisigStandard::Bip87.purpose() && path.len() >= 3 => {
+                return Some(KeyApplication::Hashed)
+            }
+            _ => {}
+        }
         if path.len() >= 4 && path[0] == bip48_purpose {
             match path[3] {
                 ChildNumber::Hardened { index: 1 } => Some(KeyApplication::NestedMultisig),
@@ -510,6 +623,9 @@ impl VersionResolver for DefaultResolver {
 
     fn is_prv(kv: &KeyVersion) -> Option<bool> { DefaultResolver::is_pub(kv).map(|v| !v) }
 
+    // Testnet key versions are shared by testnet3, signet and regtest, so they
+    // are always resolved into `Network::Testnet`; the actual network must be
+    // specified by the wallet user
     fn network(kv: &KeyVersion) -> Option<Self::Network> {
         match kv.as_bytes() {
             &VERSION_MAGIC_XPRV
@@ -619,6 +735,36 @@ impl VersionResolver for DefaultResolver {
         })
     }
 
+    fn multisig_derivation_path(
+        kv: &KeyVersion,
+        account: Option<ChildNumber>,
+        standard: MultisigStandard,
+    ) -> Option<DerivationPath> {
+        let coin_type = match DefaultResolver::network(kv)? {
+            Network::Bitcoin => ChildNumber::Hardened { index: 0 },
+            _ => ChildNumber::Hardened { index: 1 },
+        };
+        let application = DefaultResolver::application(kv)?;
+        match (standard, application, account) {
+            (MultisigStandard::Bip45, KeyApplication::Hashed, None) => {
+                Some(DerivationPath::from(vec![standard.purpose()]))
+            }
+            (
+                MultisigStandard::Bip48,
+                KeyApplication::SegWitMultisig | KeyApplication::NestedMultisig,
+                Some(_),
+            ) => DefaultResolver::derivation_path(kv, account),
+            (MultisigStandard::Bip87, KeyApplication::Hashed, Some(account)) => {
+                Some(DerivationPath::from(vec![
+                    standard.purpose(),
+                    coin_type,
+                    account,
+                ]))
+            }
+            _ => None,
+        }
+    }
+
     fn make_pub(kv: &KeyVersion) -> Option<KeyVersion> {
         match kv.as_bytes() {
             &VERSION_MAGIC_XPRV => Some(KeyVersion::from_bytes(VERSION_MAGIC_XPUB)),
@@ -694,6 +840,30 @@ pub trait FromSlip132 {
     fn from_slip132_str(s: &str) -> Result<Self, Error>
     where
         Self: Sized;
+
+    /// Constructs standard BIP32 extended key from SLIP132 string using key
+    /// versions of a custom network.
+    fn from_slip132_str_with(s: &str, params: &NetworkParams) -> Result<Self, Error>
+    where
+        Self: Sized;
+}
+
+fn decode_with_params(s: &str, params: &NetworkParams, is_priv: bool) -> Result<Vec<u8>, Error> {
+    let mut data = base58::from_check(s)?;
+    if data.len() < 4 {
+        return Err(Error::WrongExtendedKeyLength(data.len()));
+    }
+    let version = KeyVersion::from_slice(&data[0..4]).ok_or(Error::UnknownSlip32Prefix)?;
+    if !params.is_known_version(&version, is_priv) {
+        return Err(Error::UnknownSlip32Prefix);
+    }
+    data[0..4].copy_from_slice(params.native_version(is_priv).as_slice());
+    Ok(data)
+}
+
+fn encode_with_version(mut data: [u8; 78], version: KeyVersion) -> String {
+    data[0..4].copy_from_slice(version.as_slice());
+    base58::check_encode_slice(&data)
 }
 
 impl FromSlip132 for ExtendedPubKey {
@@ -723,6 +893,11 @@ impl FromSlip132 for ExtendedPubKey {
 
         Ok(xpub)
     }
+
+    fn from_slip132_str_with(s: &str, params: &NetworkParams) -> Result<Self, Error> {
+        let data = decode_with_params(s, params, false)?;
+        Ok(ExtendedPubKey::decode(&data)?)
+    }
 }
 
 impl FromSlip132 for ExtendedPrivKey {
@@ -752,6 +927,11 @@ impl FromSlip132 for ExtendedPrivKey {
 
         Ok(xprv)
     }
+
+    fn from_slip132_str_with(s: &str, params: &NetworkParams) -> Result<Self, Error> {
+        let data = decode_with_params(s, params, true)?;
+        Ok(ExtendedPrivKey::decode(&data)?)
+    }
 }
 
 /// Trait converting standard BIP32 extended keys into SLIP132 representation.
@@ -759,6 +939,14 @@ pub trait ToSlip132 {
     /// Creates SLIP132 key representation matching the provided application
     /// and bitcoin network.
     fn to_slip132_string(&self, key_application: KeyApplication, network: Network) -> String;
+
+    /// Creates SLIP132 key representation matching the provided application
+    /// using key versions of a custom network.
+    fn to_slip132_string_with(
+        &self,
+        key_application: KeyApplication,
+        params: &NetworkParams,
+    ) -> String;
 }
 
 impl ToSlip132 for ExtendedPubKey {
@@ -768,6 +956,14 @@ impl ToSlip132 for ExtendedPubKey {
         xpub[0..4].copy_from_slice(key_version.as_slice());
         base58::check_encode_slice(&xpub)
     }
+
+    fn to_slip132_string_with(
+        &self,
+        key_application: KeyApplication,
+        params: &NetworkParams,
+    ) -> String {
+        encode_with_version(self.encode(), params.key_version(key_application, false))
+    }
 }
 
 impl ToSlip132 for ExtendedPrivKey {
@@ -777,6 +973,14 @@ impl ToSlip132 for ExtendedPrivKey {
         xpriv[0..4].copy_from_slice(key_version.as_slice());
         base58::check_encode_slice(&xpriv)
     }
+
+    fn to_slip132_string_with(
+        &self,
+        key_application: KeyApplication,
+        params: &NetworkParams,
+    ) -> String {
+        encode_with_version(self.encode(), params.key_version(key_application, true))
+    }
 }
 
 #[cfg(test)]
@@ -927,6 +1131,146 @@ mod test {
         );
     }
 
+    const ALL_MAGICS: [[u8; 4]; 20] = [
+        VERSION_MAGIC_XPUB,
+        VERSION_MAGIC_XPRV,
+        VERSION_MAGIC_YPUB,
+        VERSION_MAGIC_YPRV,
+        VERSION_MAGIC_ZPUB,
+        VERSION_MAGIC_ZPRV,
+        VERSION_MAGIC_YPUB_MULTISIG,
+        VERSION_MAGIC_YPRV_MULTISIG,
+        VERSION_MAGIC_ZPUB_MULTISIG,
+        VERSION_MAGIC_ZPRV_MULTISIG,
+        VERSION_MAGIC_TPUB,
+        VERSION_MAGIC_TPRV,
+        VERSION_MAGIC_UPUB,
+        VERSION_MAGIC_UPRV,
+        VERSION_MAGIC_VPUB,
+        VERSION_MAGIC_VPRV,
+        VERSION_MAGIC_UPUB_MULTISIG,
+        VERSION_MAGIC_UPRV_MULTISIG,
+        VERSION_MAGIC_VPUB_MULTISIG,
+        VERSION_MAGIC_VPRV_MULTISIG,
+    ];
+
+    #[test]
+    fn multisig_derivation_path_vectors() {
+        let account = Some(ChildNumber::Hardened { index: 7 });
+        let (bip45, bip48, bip87) = (
+            MultisigStandard::Bip45,
+            MultisigStandard::Bip48,
+            MultisigStandard::Bip87,
+        );
+        let vectors = [
+            (VERSION_MAGIC_XPUB, bip45, None, Some("m/45'")),
+            (VERSION_MAGIC_TPRV, bip45, None, Some("m/45'")),
+            (VERSION_MAGIC_XPUB, bip45, account, None),
+            (VERSION_MAGIC_ZPUB_MULTISIG, bip45, None, None),
+            (VERSION_MAGIC_XPUB, bip48, account, None),
+            (
+                VERSION_MAGIC_ZPUB_MULTISIG,
+                bip48,
+                account,
+                Some("m/48'/0'/7'/2'"),
+            ),
+            (
+                VERSION_MAGIC_YPRV_MULTISIG,
+                bip48,
+                account,
+                Some("m/48'/0'/7'/1'"),
+            ),
+            (
+                VERSION_MAGIC_VPUB_MULTISIG,
+                bip48,
+                account,
+                Some("m/48'/1'/7'/2'"),
+            ),
+            (
+                VERSION_MAGIC_UPUB_MULTISIG,
+                bip48,
+                account,
+                Some("m/48'/1'/7'/1'"),
+            ),
+            (VERSION_MAGIC_ZPUB_MULTISIG, bip48, None, None),
+            (VERSION_MAGIC_XPUB, bip87, account, Some("m/87'/0'/7'")),
+            (VERSION_MAGIC_TPUB, bip87, account, Some("m/87'/1'/7'")),
+            (VERSION_MAGIC_XPRV, bip87, None, None),
+            (VERSION_MAGIC_ZPUB, bip87, account, None),
+        ];
+        for (magic, standard, account, path) in vectors {
+            assert_eq!(
+                KeyVersion(magic).multisig_derivation_path::<DefaultResolver>(account, standard),
+                path.map(|p| DerivationPath::from_str(p).unwrap()),
+                "{:02x?} {} {:?}",
+                magic,
+                standard,
+                account
+            );
+        }
+    }
+
+    #[test]
+    fn multisig_derivation_path_roundtrip() {
+        let accounts = [
+            None,
+            Some(ChildNumber::Hardened { index: 0 }),
+            Some(ChildNumber::Hardened { index: 1 }),
+            Some(ChildNumber::Hardened { index: 0x7FFF_FFFF }),
+        ];
+        let mut count = 0usize;
+        for magic in ALL_MAGICS {
+            let kv = KeyVersion(magic);
+            let network = kv.network::<DefaultResolver>().unwrap();
+            let is_priv = kv.is_prv::<DefaultResolver>().unwrap();
+            for standard in MultisigStandard::ALL {
+                for account in accounts {
+                    let path =
+                        match kv.multisig_derivation_path::<DefaultResolver>(account, standard) {
+                            Some(path) => path,
+                            None => continue,
+                        };
+                    count += 1;
+                    assert_eq!(
+                        MultisigStandard::from_derivation_path(&path),
+                        Some(standard)
+                    );
+                    let application = KeyApplication::from_derivation_path(path.clone())
+                        .expect("multisig path must define key application");
+                    assert_eq!(
+                        DefaultResolver::resolve(network, application, is_priv),
+                        kv,
+                        "{} path {} for {:02x?}",
+                        standard,
+                        path,
+                        magic
+                    );
+                    if standard != MultisigStandard::Bip45 {
+                        let coin_type = path.as_ref()[1];
+                        let expected = if network == Network::Bitcoin { 0 } else { 1 };
+                        assert_eq!(coin_type, ChildNumber::Hardened { index: expected });
+                    }
+                }
+            }
+        }
+        // 4 xkeys × (BIP45 + 3 BIP87 accounts) + 8 multisig keys × 3 BIP48 accounts
+        assert_eq!(count, 4 * 4 + 8 * 3);
+    }
+
+    #[test]
+    fn multisig_standard_from_str() {
+        for standard in MultisigStandard::ALL {
+            assert_eq!(
+                MultisigStandard::from_str(&standard.to_string()),
+                Ok(standard)
+            );
+        }
+        assert_eq!(
+            MultisigStandard::from_str("bip44"),
+            Err(UnknownMultisigStandardError)
+        );
+    }
+
     #[test]
     fn key_version_to_u32() {
         let bytes = [0, 2, 4, 8];
@@ -0,0 +1,281 @@
+// Wallet-level libraries for bitcoin protocol by LNP/BP Association
+//
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// This software is distributed without any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Custom network parameters for bitcoin-derived chains (sidechains, custom
+//! test chains) which use own address prefixes and extended key versions.
+
+use std::collections::BTreeMap;
+
+use bitcoin::bech32::{self, u5, ToBase32};
+use bitcoin::util::address::{Payload, WitnessVersion};
+use bitcoin::util::base58;
+use bitcoin::{Network, Script};
+
+use crate::{
+    DefaultResolver, KeyApplication, KeyVersion, VersionResolver, VERSION_MAGIC_TPRV,
+    VERSION_MAGIC_TPUB, VERSION_MAGIC_XPRV, VERSION_MAGIC_XPUB,
+};
+
+/// Parameters of a bitcoin-like network used for encoding addresses and
+/// extended keys.
+///
+/// Parameters for the standard bitcoin networks are constructed with
+/// `NetworkParams::from(Network)`; parameters for other chains (like Liquid
+/// or custom test chains) can be provided with [`NetworkParams::new`] and
+/// [`NetworkParams::with_slip132_versions`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NetworkParams {
+    /// Human-readable network name
+    pub name: String,
+
+    /// Bitcoin network the chain is based on. Extended keys from the chain
+    /// are represented with the BIP-32 versions of this network inside
+    /// [`bitcoin::util::bip32`] data structures.
+    pub base: Network,
+
+    /// Human-readable part of bech32 and bech32m addresses
+    pub bech32_hrp: String,
+
+    /// Version byte for P2PKH addresses
+    pub p2pkh_prefix: u8,
+
+    /// Version byte for P2SH addresses
+    pub p2sh_prefix: u8,
+
+    /// BIP-32 version of extended public keys
+    pub xpub_version: KeyVersion,
+
+    /// BIP-32 version of extended private keys
+    pub xprv_version: KeyVersion,
+
+    /// SLIP-132 versions of extended public and private keys for specific
+    /// key applications. Applications not present here use
+    /// [`NetworkParams::xpub_version`] and [`NetworkParams::xprv_version`].
+    pub slip132_versions: BTreeMap<KeyApplication, (KeyVersion, KeyVersion)>,
+}
+
+impl From<Network> for NetworkParams {
+    fn from(network: Network) -> Self {
+        let (bech32_hrp, p2pkh_prefix, p2sh_prefix) = match network {
+            Network::Bitcoin => ("bc", 0, 5),
+            Network::Testnet | Network::Signet => ("tb", 111, 196),
+            Network::Regtest => ("bcrt", 111, 196),
+        };
+        let mut params = NetworkParams::new(
+            network.to_string(),
+            network,
+            bech32_hrp,
+            p2pkh_prefix,
+            p2sh_prefix,
+            DefaultResolver::resolve(network, KeyApplication::Hashed, false),
+            DefaultResolver::resolve(network, KeyApplication::Hashed, true),
+        );
+        for app in [
+            KeyApplication::Nested,
+            KeyApplication::SegWit,
+            KeyApplication::NestedMultisig,
+            KeyApplication::SegWitMultisig,
+        ] {
+            params = params.with_slip132_versions(
+                app,
+                DefaultResolver::resolve(network, app, false),
+                DefaultResolver::resolve(network, app, true),
+            );
+        }
+        params
+    }
+}
+
+impl NetworkParams {
+    /// Constructs custom network parameters without application-specific
+    /// SLIP-132 key versions.
+    pub fn new(
+        name: impl ToString,
+        base: Network,
+        bech32_hrp: impl ToString,
+        p2pkh_prefix: u8,
+        p2sh_prefix: u8,
+        xpub_version: KeyVersion,
+        xprv_version: KeyVersion,
+    ) -> NetworkParams {
+        NetworkParams {
+            name: name.to_string(),
+            base,
+            bech32_hrp: bech32_hrp.to_string(),
+            p2pkh_prefix,
+            p2sh_prefix,
+            xpub_version,
+            xprv_version,
+            slip132_versions: empty!(),
+        }
+    }
+
+    /// Adds SLIP-132 extended public and private key versions for a given key
+    /// application.
+    pub fn with_slip132_versions(
+        mut self,
+        application: KeyApplication,
+        xpub_version: KeyVersion,
+        xprv_version: KeyVersion,
+    ) -> NetworkParams {
+        self.slip132_versions
+            .insert(application, (xpub_version, xprv_version));
+        self
+    }
+
+    /// Returns extended key version used by the network for a given key
+    /// application.
+    pub fn key_version(&self, application: KeyApplication, is_priv: bool) -> KeyVersion {
+        let (xpub, xprv) = self
+            .slip132_versions
+            .get(&application)
+            .copied()
+            .unwrap_or((self.xpub_version, self.xprv_version));
+        if is_priv {
+            xprv
+        } else {
+            xpub
+        }
+    }
+
+    /// Detects whether a key version is used by the network for public or
+    /// private (depending on `is_priv` argument) extended keys.
+    pub fn is_known_version(&self, version: &KeyVersion, is_priv: bool) -> bool {
+        let select = |(xpub, xprv): (KeyVersion, KeyVersion)| if is_priv { xprv } else { xpub };
+        select((self.xpub_version, self.xprv_version)) == *version
+            || self
+                .slip132_versions
+                .values()
+                .any(|versions| select(*versions) == *version)
+    }
+
+    /// Returns BIP-32 version under which extended keys of the network are
+    /// represented by [`bitcoin::util::bip32`] data structures.
+    pub fn native_version(&self, is_priv: bool) -> KeyVersion {
+        KeyVersion::from_bytes(match (self.base, is_priv) {
+            (Network::Bitcoin, false) => VERSION_MAGIC_XPUB,
+            (Network::Bitcoin, true) => VERSION_MAGIC_XPRV,
+            (_, false) => VERSION_MAGIC_TPUB,
+            (_, true) => VERSION_MAGIC_TPRV,
+        })
+    }
+
+    /// Encodes address for a given `scriptPubkey` using network address
+    /// prefixes. Returns `None` if the script has no address form.
+    pub fn encode_address(&self, script: &Script) -> Option<String> {
+        match Payload::from_script(script)? {
+            Payload::PubkeyHash(hash) => {
+                let mut data = vec![self.p2pkh_prefix];
+                data.extend(&hash[..]);
+                Some(base58::check_encode_slice(&data))
+            }
+            Payload::ScriptHash(hash) => {
+                let mut data = vec![self.p2sh_prefix];
+                data.extend(&hash[..]);
+                Some(base58::check_encode_slice(&data))
+            }
+            Payload::WitnessProgram { version, program } => {
+                let variant = match version {
+                    WitnessVersion::V0 => bech32::Variant::Bech32,
+                    _ => bech32::Variant::Bech32m,
+                };
+                let mut data = vec![u5::try_from_u8(version.to_num()).ok()?];
+                data.extend(program.to_base32());
+                bech32::encode(&self.bech32_hrp, data, variant).ok()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::util::bip32::ExtendedPubKey;
+    use bitcoin::{Address, PubkeyHash, ScriptHash, WPubkeyHash};
+
+    use super::*;
+    use crate::{Error, FromSlip132, ToSlip132};
+
+    #[test]
+    fn standard_addresses() {
+        let scripts = [
+            Script::new_p2pkh(&PubkeyHash::hash(b"p2pkh")),
+            Script::new_p2sh(&ScriptHash::hash(b"p2sh")),
+            Script::new_v0_p2wpkh(&WPubkeyHash::hash(b"p2wpkh")),
+            Script::new_witness_program(WitnessVersion::V1, &[0x5a; 32]),
+        ];
+        for network in [
+            Network::Bitcoin,
+            Network::Testnet,
+            Network::Signet,
+            Network::Regtest,
+        ] {
+            let params = NetworkParams::from(network);
+            for script in &scripts {
+                let address = Address::from_script(script, network).unwrap();
+                assert_eq!(params.encode_address(script), Some(address.to_string()));
+            }
+            assert_eq!(params.encode_address(&Script::new_op_return(b"data")), None);
+        }
+    }
+
+    #[test]
+    fn custom_network() {
+        let params = NetworkParams::new(
+            "liquid",
+            Network::Bitcoin,
+            "ex",
+            57,
+            39,
+            KeyVersion::from_bytes(VERSION_MAGIC_XPUB),
+            KeyVersion::from_bytes(VERSION_MAGIC_XPRV),
+        )
+        .with_slip132_versions(
+            KeyApplication::SegWit,
+            KeyVersion::from_u32(0x0488_1f4a),
+            KeyVersion::from_u32(0x0488_1a10),
+        );
+
+        let script = Script::new_v0_p2wpkh(&WPubkeyHash::hash(b"p2wpkh"));
+        assert!(params.encode_address(&script).unwrap().starts_with("ex1q"));
+
+        let xpub = ExtendedPubKey::from_str(
+            "xpub6BosfCnifzxcJJ1wYuntGJfF2zPJkDeG9ELNHcKNjezuea4tumswN9sH1psMdSVqCMoJC21Bv8usSe\
+             qSP4Sp1tLzW7aY59fGn9GCYzx5UTo",
+        )
+        .unwrap();
+        let standard = NetworkParams::from(Network::Bitcoin);
+        assert_eq!(
+            xpub.to_slip132_string_with(KeyApplication::Nested, &standard),
+            xpub.to_slip132_string(KeyApplication::Nested, Network::Bitcoin)
+        );
+
+        let custom = xpub.to_slip132_string_with(KeyApplication::SegWit, &params);
+        assert_eq!(
+            ExtendedPubKey::from_slip132_str_with(&custom, &params),
+            Ok(xpub)
+        );
+        assert_eq!(
+            ExtendedPubKey::from_slip132_str(&custom),
+            Err(Error::UnknownSlip32Prefix)
+        );
+        assert_eq!(
+            ExtendedPubKey::from_slip132_str_with(&custom, &standard),
+            Err(Error::UnknownSlip32Prefix)
+        );
+        assert_eq!(
+            xpub.to_slip132_string_with(KeyApplication::Hashed, &params),
+            xpub.to_string()
+        );
+    }
+}
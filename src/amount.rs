@@ -0,0 +1,292 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Formatting and parsing of bitcoin amounts in BTC, mBTC and satoshi
+//! denominations.
+//!
+//! Formatting does not depend on the system locale: amounts always use `.` as
+//! a decimal separator and no digit grouping. All computations are performed
+//! on integer satoshi values, so no floating-point rounding errors may occur.
+
+use std::fmt::{self, Formatter};
+use std::str::FromStr;
+
+use amplify::{Display, Error};
+
+/// Denomination of bitcoin amounts.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub enum Denomination {
+    /// Bitcoins, with 8 decimal digits.
+    Btc,
+
+    /// Milli-bitcoins, with 5 decimal digits.
+    MilliBtc,
+
+    /// Satoshis, with no decimal digits.
+    #[default]
+    Sat,
+}
+
+impl Denomination {
+    /// Returns number of decimal digits representing satoshis in this
+    /// denomination.
+    pub fn decimals(self) -> u8 {
+        match self {
+            Denomination::Btc => 8,
+            Denomination::MilliBtc => 5,
+            Denomination::Sat => 0,
+        }
+    }
+
+    /// Returns unit name used when displaying amounts.
+    pub fn unit(self) -> &'static str {
+        match self {
+            Denomination::Btc => "BTC",
+            Denomination::MilliBtc => "mBTC",
+            Denomination::Sat => "sat",
+        }
+    }
+
+    /// Wraps amount in satoshis for displaying in this denomination together
+    /// with the unit name.
+    pub fn amount(self, sats: u64) -> DisplayAmount {
+        DisplayAmount {
+            sats,
+            denomination: self,
+        }
+    }
+}
+
+impl fmt::Display for Denomination {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str(self.unit()) }
+}
+
+impl FromStr for Denomination {
+    type Err = AmountParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "btc" => Ok(Denomination::Btc),
+            "mbtc" => Ok(Denomination::MilliBtc),
+            "sat" | "sats" | "satoshi" | "satoshis" => Ok(Denomination::Sat),
+            _ => Err(AmountParseError::UnknownDenomination(s.to_owned())),
+        }
+    }
+}
+
+/// Rounding rule used when an amount is formatted with fewer decimal digits
+/// than required for the satoshi precision.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Rounding {
+    /// Round towards zero, never overstating the amount.
+    Down,
+
+    /// Round to the nearest value, with halves rounded up.
+    #[default]
+    HalfUp,
+
+    /// Round away from zero, never understating the amount.
+    Up,
+}
+
+/// Errors parsing bitcoin amounts
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AmountParseError {
+    /// amount string is empty
+    Empty,
+
+    /// invalid amount `{0}`; amounts must use `.` as a decimal separator and
+    /// no digit grouping
+    InvalidNumber(String),
+
+    /// unknown denomination `{0}`; supported denominations are BTC, mBTC and
+    /// sat
+    UnknownDenomination(String),
+
+    /// amount `{0}` has more decimal digits than {1} denomination allows
+    TooPrecise(String, Denomination),
+
+    /// amount `{0}` exceeds maximal possible value
+    Overflow(String),
+}
+
+/// Formats amount in satoshis in the given denomination with all decimal
+/// digits, without the unit name.
+pub fn format_amount(sats: u64, denomination: Denomination) -> String {
+    format_amount_rounded(sats, denomination, denomination.decimals(), Rounding::Down)
+}
+
+/// Formats amount in satoshis in the given denomination with a specific
+/// number of decimal digits, without the unit name. If the number of digits is
+/// insufficient to represent the amount precisely, the amount is rounded
+/// according to the `rounding` rule.
+pub fn format_amount_rounded(
+    sats: u64,
+    denomination: Denomination,
+    decimals: u8,
+    rounding: Rounding,
+) -> String {
+    let precision = denomination.decimals();
+    let value = if decimals < precision {
+        let divisor = 10u128.pow((precision - decimals) as u32);
+        let (quotient, remainder) = (sats as u128 / divisor, sats as u128 % divisor);
+        match rounding {
+            Rounding::Down => quotient,
+            Rounding::HalfUp if remainder * 2 >= divisor => quotient + 1,
+            Rounding::HalfUp => quotient,
+            Rounding::Up if remainder > 0 => quotient + 1,
+            Rounding::Up => quotient,
+        }
+    } else {
+        sats as u128 * 10u128.pow((decimals - precision) as u32)
+    };
+
+    if decimals == 0 {
+        return value.to_string();
+    }
+    let scale = 10u128.pow(decimals as u32);
+    format!(
+        "{}.{:0width$}",
+        value / scale,
+        value % scale,
+        width = decimals as usize
+    )
+}
+
+/// Parses amount with an optional denomination suffix (like `0.5 BTC`,
+/// `12.5mBTC` or `1000 sat`) into satoshis. Amounts without suffix are
+/// interpreted in the `default` denomination.
+///
+/// Amounts having more decimal digits than the denomination allows are
+/// rejected instead of being silently rounded.
+pub fn parse_amount(s: &str, default: Denomination) -> Result<u64, AmountParseError> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(AmountParseError::Empty);
+    }
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = (s[..split].trim(), s[split..].trim());
+    let denomination = if unit.is_empty() {
+        default
+    } else {
+        Denomination::from_str(unit)?
+    };
+
+    let invalid = || AmountParseError::InvalidNumber(s.to_owned());
+    let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+    if (int.is_empty() && frac.is_empty()) || frac.contains('.') {
+        return Err(invalid());
+    }
+    if frac.len() > denomination.decimals() as usize {
+        return Err(AmountParseError::TooPrecise(s.to_owned(), denomination));
+    }
+
+    let overflow = || AmountParseError::Overflow(s.to_owned());
+    let mut sats = 0u64;
+    let digits = int
+        .chars()
+        .chain(frac.chars())
+        .chain(std::iter::repeat('0').take(denomination.decimals() as usize - frac.len()));
+    for digit in digits {
+        let digit = digit.to_digit(10).ok_or_else(invalid)?;
+        sats = sats
+            .checked_mul(10)
+            .and_then(|sats| sats.checked_add(digit as u64))
+            .ok_or_else(overflow)?;
+    }
+    Ok(sats)
+}
+
+/// Amount in satoshis displayed in a specific denomination followed by the
+/// unit name. Supports width and alignment formatting flags.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct DisplayAmount {
+    /// Amount in satoshis.
+    pub sats: u64,
+
+    /// Denomination to display the amount in.
+    pub denomination: Denomination,
+}
+
+impl fmt::Display for DisplayAmount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.pad(&format!(
+            "{} {}",
+            format_amount(self.sats, self.denomination),
+            self.denomination.unit()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format_amount(123_456_789, Denomination::Btc), "1.23456789");
+        assert_eq!(format_amount(1_000, Denomination::MilliBtc), "0.01000");
+        assert_eq!(format_amount(1_000, Denomination::Sat), "1000");
+        assert_eq!(Denomination::Btc.amount(5).to_string(), "0.00000005 BTC");
+        assert_eq!(
+            format!("{:>10}", Denomination::Sat.amount(42)),
+            "    42 sat"
+        );
+
+        let round = |sats, rounding| format_amount_rounded(sats, Denomination::Btc, 2, rounding);
+        assert_eq!(round(1_499_999, Rounding::Down), "0.01");
+        assert_eq!(round(1_500_000, Rounding::HalfUp), "0.02");
+        assert_eq!(round(1_499_999, Rounding::HalfUp), "0.01");
+        assert_eq!(round(1_000_001, Rounding::Up), "0.02");
+        assert_eq!(
+            format_amount_rounded(u64::MAX, Denomination::Sat, 2, Rounding::Down),
+            "18446744073709551615.00"
+        );
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(parse_amount("1.5 BTC", Denomination::Sat), Ok(150_000_000));
+        assert_eq!(parse_amount("0.5mbtc", Denomination::Sat), Ok(50_000));
+        assert_eq!(parse_amount(".1", Denomination::Btc), Ok(10_000_000));
+        assert_eq!(parse_amount("1000", Denomination::Sat), Ok(1000));
+        assert_eq!(parse_amount(" 7 sats ", Denomination::Btc), Ok(7));
+        assert_eq!(
+            parse_amount("1.5 sat", Denomination::Sat),
+            Err(AmountParseError::TooPrecise(
+                "1.5 sat".to_owned(),
+                Denomination::Sat
+            ))
+        );
+        assert_eq!(
+            parse_amount("1,5 BTC", Denomination::Sat),
+            Err(AmountParseError::UnknownDenomination(",5 BTC".to_owned()))
+        );
+        assert_eq!(
+            parse_amount("1.2.3", Denomination::Btc),
+            Err(AmountParseError::InvalidNumber("1.2.3".to_owned()))
+        );
+        assert_eq!(
+            parse_amount("", Denomination::Btc),
+            Err(AmountParseError::Empty)
+        );
+        assert!(matches!(
+            parse_amount("1000000000000 BTC", Denomination::Sat),
+            Err(AmountParseError::Overflow(_))
+        ));
+    }
+}
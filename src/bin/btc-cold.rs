@@ -25,42 +25,86 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::convert::Infallible;
 use std::fmt::{Debug, Display, Formatter, Write};
 use std::io::{stdin, stdout, BufRead, BufReader, Write as IoWrite};
-use std::num::ParseIntError;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::{fmt, fs, io};
+use std::time::Duration;
+use std::{fmt, fs, io, iter, process, thread};
 
-use amplify::hex::ToHex;
+use amplify::hex::{FromHex, ToHex};
 use amplify::{IoError, Wrapper};
 use bitcoin::consensus::Encodable;
+use bitcoin::hashes::sha256;
 use bitcoin::psbt::serialize::Serialize;
-use bitcoin::psbt::PartiallySignedTransaction;
-use bitcoin::secp256k1::Secp256k1;
+use bitcoin::psbt::{PartiallySignedTransaction, TapTree};
+use bitcoin::secp256k1::{self, KeyPair, Secp256k1};
 use bitcoin::util::address;
-use bitcoin::util::bip32::{ChildNumber, ExtendedPubKey};
-use bitcoin::{consensus, Address, Network};
+use bitcoin::util::bip32::{ChildNumber, ExtendedPubKey, Fingerprint};
+use bitcoin::util::taproot::{TapLeafHash, TaprootBuilder};
+use bitcoin::{consensus, Address, Network, OutPoint, Script, Transaction, Txid, XOnlyPublicKey};
 use bitcoin_blockchain::locks::LockTime;
 use bitcoin_hd::DeriveError;
-use bitcoin_onchain::UtxoResolverError;
+use bitcoin_onchain::{
+    BlockResolverError, BroadcastError, BroadcastTx, ElectrumConfig, ElectrumProtocol,
+    ElectrumUrlError, FallbackBroadcaster, PublicNetwork, ResolveBlockInfo, UtxoResolverError,
+};
 use bitcoin_scripts::address::AddressCompat;
+use bitcoin_scripts::taproot::{CutError, DfsOrder, InstillError, TaprootScriptTree};
 use bitcoin_scripts::PubkeyScript;
 use clap::Parser;
 use colored::Colorize;
-use descriptors::derive::Descriptor;
+use descriptors::derive::{DeriveDescriptor, Descriptor};
 use electrum_client as electrum;
 use electrum_client::ElectrumApi;
 use miniscript::psbt::PsbtExt;
-use miniscript::{MiniscriptKey, TranslatePk};
+use miniscript::{ForEachKey, MiniscriptKey, TranslatePk};
 use miniscript_crate::Translator;
-use psbt::serialize::Deserialize;
-use psbt::{construct, ProprietaryKeyDescriptor, ProprietaryKeyError, ProprietaryKeyLocation};
+use psbt::diff::Signature;
+use psbt::{
+    construct, DiffError, MessageError, MessageSignature, ProprietaryKeyDescriptor,
+    ProprietaryKeyError, ProprietaryKeyLocation, ProprietaryKeyType, PsbtDiff, ReservesError,
+};
 use slip132::{
     DefaultResolver, FromSlip132, KeyApplication, KeyVersion, ToSlip132, VersionResolver,
 };
-use wallet::descriptors::InputDescriptor;
-use wallet::hd::{DerivationAccount, SegmentIndexes, UnhardenedIndex};
+use wallet::amount::{self, AmountParseError, Denomination};
+use wallet::config::{Config, ConfigError, FeerateSource, DEFAULT_ELECTRUM_SERVER};
+use wallet::container::{ContainerError, Wallet};
+use wallet::descriptors::address::AddressClassify;
+use wallet::descriptors::policy::{self, PolicyError};
+use wallet::descriptors::taptree::{self, DisclosureError, ScriptTreeText, TreeParseError};
+use wallet::descriptors::{
+    is_key_only_output, Delegation, DelegationLock, DelegationRole, DescriptorClass,
+    DescriptorTree, DescriptorTreeError, Inheritance, InputDescriptor, InputResolveError,
+    ScriptPubkeyDescr,
+};
+use wallet::envelope::{EnvelopeError, Versioned};
+use wallet::export::bitcoin_core::{self, Timestamp};
+use wallet::export::ExportFormat;
+use wallet::hd::{
+    DerivationAccount, DerivationIndexMap, DerivationSubpath, IndexRange, RotationError,
+    SegmentIndexes, UnhardenedIndex,
+};
+use wallet::interop::{self, sparrow};
+use wallet::meta::{ReservationError, WalletMeta};
+use wallet::notify::{Notifier, NotifySink};
+use wallet::onchain::blockchain::{self, Birthday, MiningStatus, Utxo};
+use wallet::onchain::subscribe::{ConfirmationTracker, ScriptWatcher, SubscribeError, WatchEvent};
 use wallet::onchain::ResolveDescriptor;
-use wallet::psbt::{Psbt, PsbtParseError};
+use wallet::payments::{PaymentInstruction, ResolveError, ResolveTxt, TxtRecords};
+use wallet::psbt::{
+    is_secure_endpoint, PayjoinError, PayjoinParams, Psbt, PsbtEncoding, PsbtParseError,
+};
+use wallet::report::{
+    AccountReport, AddressInfoReport, AddressReport, CapabilitiesReport, CompiledReport,
+    ErrorReport, MessageReport, OutputFormat, PsbtReport, ReservesReport, ScanReport,
+    SigAuditReport, StatusReport, SyncReport, TreeNodeReport, TxInspectReport, TxReport,
+    UtxoReport, WalletReport, WatchReport, XpubReport,
+};
+use wallet::sync::{self, ChainTip, SignedSyncBundle, SyncError, SyncState, SyncUtxo};
+
+/// Confirmation targets, in blocks, for which fee estimates are exported by
+/// `sync export` command
+const SYNC_FEE_TARGETS: [u16; 5] = [1, 3, 6, 12, 144];
 
 /// Command-line arguments
 #[derive(Parser)]
@@ -76,21 +120,60 @@ pub struct Args {
     #[clap(subcommand)]
     pub command: Command,
 
-    /// Electrum server to use.
+    /// Electrum server to use, optionally prefixed with `tcp://` or `ssl://`.
+    /// Overrides server from the configuration file; defaults to
+    /// `electrum.blockstream.info`.
     ///
     /// Used only by `check`, `history`, `construct` and some forms of
     /// `extract` command
-    #[clap(short, long, global = true, default_value("electrum.blockstream.info"))]
-    pub electrum_server: String,
+    #[clap(short, long, global = true)]
+    pub electrum_server: Option<String>,
 
     /// Customize electrum server port number. By default the wallet will use
     /// port matching the selected network.
     #[clap(short = 'p', global = true)]
     pub electrum_port: Option<u16>,
 
+    /// Connect electrum server using SSL. Default port changes to the SSL
+    /// port matching the selected network.
+    #[clap(long, global = true)]
+    pub ssl: bool,
+
+    /// SOCKS5 proxy (`host:port`) for network connections, like a Tor daemon
+    /// at `127.0.0.1:9050`. Overrides proxy from the configuration file.
+    #[clap(long, global = true)]
+    pub proxy: Option<String>,
+
+    /// Configuration file to use instead of
+    /// `~/.config/descriptor-wallet/config.toml`.
+    #[clap(long = "config", global = true)]
+    pub config_file: Option<PathBuf>,
+
+    /// Configuration loaded from the configuration file
+    #[clap(skip)]
+    pub config: Config,
+
     /// Use Bitcoin Core descriptor representation.
     #[clap(long = "bitcoin-core-fmt", global = true)]
     pub bitcoin_core_fmt: bool,
+
+    /// Denomination to display amounts in: `BTC`, `mBTC` or `sat`.
+    #[clap(long, global = true, default_value = "sat")]
+    pub unit: Denomination,
+
+    /// Format to print command results to STDOUT in: `plain` for a
+    /// human-readable text, `json` or `yaml` for use by scripts. In `json`
+    /// and `yaml` formats progress information and warnings are still printed
+    /// to STDERR.
+    #[clap(long, global = true, default_value = "plain")]
+    pub format: OutputFormat,
+
+    /// Bitcoin network to use: `mainnet`, `testnet`, `signet` or `regtest`.
+    /// Must match the network of the wallet keys; testnet keys can be used
+    /// with testnet, signet and regtest. Defaults to mainnet or testnet
+    /// depending on the wallet keys.
+    #[clap(long, global = true)]
+    pub network: Option<PublicNetwork>,
     /*
     /// Bitcoin Core backend to use. If used, overrides `electrum_server`,
     /// which becomes unused.
@@ -125,14 +208,47 @@ pub enum Command {
 
         /// File to save descriptor info
         output_file: PathBuf,
+
+        /// Separate output descriptor text file for change outputs, which may
+        /// use script class different from the main descriptor (for instance,
+        /// taproot change for a segwit wallet).
+        #[clap(long)]
+        change_descriptor_file: Option<PathBuf>,
+
+        /// Wallet birthday: height of the first block or UNIX timestamp
+        /// preceding the first transaction of the wallet. Blockchain history
+        /// before the birthday is skipped during wallet scans.
+        #[clap(long)]
+        birthday: Option<Birthday>,
+    },
+
+    /// Replace wallet descriptor (for instance, after key rotation or policy
+    /// change) keeping the previous descriptor generations in the wallet file,
+    /// such that the previously issued addresses are still monitored.
+    Rotate {
+        /// File containing named tracking account definitions, one per line.
+        #[clap(long)]
+        account_file: Option<PathBuf>,
+
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// New wallet output descriptor text file, in the same format as used
+        /// by `create` command.
+        descriptor_file: PathBuf,
     },
 
     /// Read UTXO set from a provided Electrum server for a given descriptor
-    /// wallet file
+    /// wallet file, including all previous wallet descriptor generations
     Check {
         /// Path to the read-only wallet file generated with `create` command
         wallet_file: PathBuf,
 
+        /// Name of the account to use if the wallet file is a multi-account
+        /// wallet container managed with `account` commands
+        #[clap(long)]
+        account: Option<String>,
+
         /// Minimum number of addresses to look ahead
         #[clap(short = 'n', long, default_value = "20")]
         look_ahead: u16,
@@ -140,11 +256,38 @@ pub enum Command {
         /// Number of addresses to skip
         #[clap(short, long, default_value = "0")]
         skip: u16,
+    },
+
+    /// Watch wallet addresses for new transactions and changes in their
+    /// mining status using electrum server subscriptions. Runs until
+    /// interrupted.
+    Watch {
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Name of the account to use if the wallet file is a multi-account
+        /// wallet container managed with `account` commands
+        #[clap(long)]
+        account: Option<String>,
+
+        /// Number of unused addresses watched after the last used address of
+        /// each keychain
+        #[clap(short = 'n', long, default_value = "20")]
+        gap_limit: u32,
+
+        /// Interval between checks for new notifications, in seconds
+        #[clap(long, default_value = "10")]
+        interval: u64,
+
+        /// Shell command to invoke for each event. Event details are provided
+        /// in `WATCH_EVENT`, `WATCH_TXID`, `WATCH_STATUS`,
+        /// `WATCH_CONFIRMATIONS`, `WATCH_TERMINAL`, `WATCH_ADDRESS` and
+        /// `WATCH_MESSAGE` environment variables.
+        #[clap(long)]
+        hook: Option<String>,
 
-        /// Show addresses using regtest prefix. Works only for testnet-based
-        /// wallet descriptors.
-        #[clap(long = "regtest")]
-        regtest: bool,
+        #[clap(flatten)]
+        notify: WatchNotify,
     },
 
     /// Read history of operations with descriptor controlled outputs from
@@ -159,6 +302,11 @@ pub enum Command {
         /// Path to the read-only wallet file generated with `create` command
         wallet_file: PathBuf,
 
+        /// Name of the account to use if the wallet file is a multi-account
+        /// wallet container managed with `account` commands
+        #[clap(long)]
+        account: Option<String>,
+
         /// Number of addresses to list
         #[clap(short = 'n', long, default_value = "20")]
         count: u16,
@@ -170,11 +318,81 @@ pub enum Command {
         /// Whether or not to show change addresses
         #[clap(short = 'c', long = "change")]
         show_change: bool,
+    },
+
+    /// Assign label to an address, or remove existing label if no label text
+    /// is given
+    Label {
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Address to label
+        address: Address,
+
+        /// Label text. If omitted, the existing label is removed.
+        text: Option<String>,
+    },
+
+    /// Freeze UTXO, preventing it from being spent by `construct` command
+    /// with `--exclude-frozen` flag, or unfreeze previously frozen UTXO
+    Freeze {
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// UTXO to freeze, in `txid:vout` format
+        utxo: OutPoint,
+
+        /// Unfreeze previously frozen UTXO
+        #[clap(short, long)]
+        unfreeze: bool,
+    },
+
+    /// Reserve range of derivation terminals for an external protocol (like
+    /// RGB). Reserved terminals are not used by `address` command and can't
+    /// be used as a change in `construct` command
+    Reserve {
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Identifier of the protocol for which terminals are reserved
+        protocol: String,
+
+        /// Keychain (first terminal derivation index) containing reserved
+        /// terminals
+        keychain: UnhardenedIndex,
+
+        /// Range of the reserved derivation indexes, like `10` or `100-199`
+        range: IndexRange<UnhardenedIndex>,
+    },
+
+    /// Release all derivation terminals reserved for an external protocol
+    Release {
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Identifier of the protocol which reservations must be released
+        protocol: String,
+    },
+
+    /// Supersede wallet account key (for instance, after its compromise) with
+    /// a new account key, which is used for derivation of the addresses
+    /// starting from a given index. Addresses below the index are still
+    /// derived with the old key, and outputs sent to both keys are scanned and
+    /// spent by the wallet
+    Supersede {
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Fingerprint of the superseded account extended public key
+        key: Fingerprint,
+
+        /// First derivation index (the last terminal derivation step) using
+        /// the new account key
+        boundary: UnhardenedIndex,
 
-        /// Display address using regtest prefix. Works only for testnet-based
-        /// descriptors.
-        #[clap(long = "regtest")]
-        regtest: bool,
+        /// New tracking account, in the same format as used by `create`
+        /// command
+        account: DerivationAccount,
     },
 
     /// Construct new PSBT.
@@ -194,6 +412,11 @@ pub enum Command {
         /// Path to the read-only wallet file generated with `create` command
         wallet_file: PathBuf,
 
+        /// Name of the account to use if the wallet file is a multi-account
+        /// wallet container managed with `account` commands
+        #[clap(long)]
+        account: Option<String>,
+
         /// List of input descriptors, specifying public keys used in
         /// generating provided UTXOs from the account data.
         #[clap(
@@ -205,9 +428,14 @@ List of input descriptors, specifying public keys used in generating provided
 UTXOs from the account data. Input descriptors are matched to UTXOs in
 automatic manner.
 
+Inputs may be given as a bare `txid:vout` outpoint; in this case the derivation
+terminal is discovered by scanning wallet addresses (see `--look-ahead`) and
+the rest of input descriptor uses default values.
+
 Input descriptor format:
 
-`txid:vout deriv-terminal [fingerprint:tweak] [rbf|height|time] [sighashtype]`
+`txid:vout deriv-terminal [fingerprint:tweak] [rbf|height|time] [sighashtype]
+[scriptsig(HEX)] [witness(HEX,...)] [redeem_script=HEX] [witness_script=HEX]`
 
 In the simplest forms, input descriptors are just UTXO outpuint and derivation
 terminal info used to create public key corresponding to the output descriptor.
@@ -217,7 +445,13 @@ this tweak can be provided as a hex value following fingerprint of the tweaked
 key account and `:` sign. The sequence number defaults to `0xFFFFFFFF`; custom
 sequence numbers may be specified via sequence number modifiers (see below).
 If the input should use `SIGHASH_TYPE` other than `SIGHASH_ALL` they may be
-specified at the end of input descriptor.
+specified at the end of input descriptor. Inputs satisfied by an external
+system (like an L2 contract) may provide their final `scriptSig` and witness
+stack elements as hex values; such inputs are not matched against the wallet
+descriptor and are left intact by the signer and finalizer. Outputs which are
+not covered by the wallet descriptor, but are signable by its keys (like legacy
+P2SH multisigs), may be spent by providing explicit redeem and/or witness
+scripts; bare scripts are detected automatically.
 
 Sequence number representations:
 - `rbf(SEQ)`: use replace-by-fee opt-in for this input;
@@ -236,7 +470,27 @@ SIGHASH_TYPE representations:
 - `SINGLE|ANYONECANPAY`
 "
         )]
-        inputs: Vec<InputDescriptor>,
+        inputs: Vec<InputArg>,
+
+        /// Number of addresses in each wallet keychain scanned to discover
+        /// derivation terminals for inputs given as bare outpoints
+        #[clap(short = 'n', long, default_value = "1000")]
+        look_ahead: u32,
+
+        /// Skip inputs spending UTXOs which were frozen in the wallet file
+        #[clap(long)]
+        exclude_frozen: bool,
+
+        /// Guard the transaction against replay on other chain forks by
+        /// locking it to the current chain tip block, which is recorded in the
+        /// PSBT
+        #[clap(long)]
+        replay_guard: bool,
+
+        /// Heights of the known chain splits; the command warns if the
+        /// constructed transaction may be valid on both sides of any of them
+        #[clap(long = "fork-height")]
+        fork_heights: Vec<u32>,
 
         /// Addresses and amounts, separated by colon. Amounts are always in
         /// satoshis.
@@ -246,6 +500,38 @@ SIGHASH_TYPE representations:
         #[clap(short, long = "output")]
         outputs: Vec<AddressAmount>,
 
+        /// Payment instructions: BIP-21 URIs (which must specify amount) or
+        /// BIP-353 names resolved with DNS-over-HTTPS.
+        ///
+        /// Example: "bitcoin:bc1qtkr96rhavl4z4ftxa4mewlvmgd8dnp6pe9nuht?
+        /// amount=0.01"
+        #[clap(long = "to")]
+        payments: Vec<PaymentInstruction>,
+
+        /// Output script types allowed by the spend policy for the payment
+        /// outputs (pkh, sh, wpkh, wsh, tr, op_return, bare). If omitted, all
+        /// script types are allowed.
+        #[clap(long = "allow-script-type")]
+        allowed_script_types: Vec<construct::OutputScriptType>,
+
+        /// Addresses allowed by the spend policy for the payment outputs. If
+        /// omitted, all destinations are allowed.
+        #[clap(long = "allow-address")]
+        allowed_addresses: Vec<Address>,
+
+        /// Maximal amount of a single payment output, in satoshis
+        #[clap(long)]
+        max_output_amount: Option<u64>,
+
+        /// Maximal sum of all payment outputs, in satoshis
+        #[clap(long)]
+        max_tx_amount: Option<u64>,
+
+        /// Fail if any of the change outputs does not derive from the wallet
+        /// descriptors
+        #[clap(long)]
+        require_wallet_change: bool,
+
         /// Derivation index for change address
         #[clap(short, long, default_value = "0")]
         change_index: UnhardenedIndex,
@@ -265,6 +551,137 @@ SIGHASH_TYPE representations:
         fee: u64,
     },
 
+    /// Construct PSBT migrating funds received by one of the previous wallet
+    /// descriptor generations (for instance, the one using deprecated legacy
+    /// script class) to the change address of the current wallet descriptor.
+    Migrate {
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Number of the previous descriptor generation to migrate funds
+        /// from. Defaults to the oldest generation holding funds.
+        #[clap(short, long)]
+        generation: Option<usize>,
+
+        /// Minimum number of addresses to look ahead
+        #[clap(short = 'n', long, default_value = "20")]
+        look_ahead: u16,
+
+        /// Derivation index for change address receiving migrated funds
+        #[clap(short, long, default_value = "0")]
+        change_index: UnhardenedIndex,
+
+        /// Destination file to save constructed PSBT
+        psbt_file: PathBuf,
+
+        /// Total fee to pay to the miners, in satoshis.
+        fee: u64,
+    },
+
+    /// Construct PSBT sweeping funds of a timelocked inheritance wallet
+    /// (`tr(owner, and_v(v:pk(heir), older(N)))`) to a given address using
+    /// the heir key script path. Only UTXOs which timelock has already
+    /// expired are spent; the input sequence numbers are set to satisfy the
+    /// timelock.
+    SweepInheritance {
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Name of the account to use if the wallet file is a multi-account
+        /// wallet container managed with `account` commands
+        #[clap(long)]
+        account: Option<String>,
+
+        /// Minimum number of addresses to look ahead
+        #[clap(short = 'n', long, default_value = "20")]
+        look_ahead: u16,
+
+        /// Address receiving swept funds
+        address: Address,
+
+        /// Total fee to pay to the miners, in satoshis.
+        fee: u64,
+
+        /// Destination file to save constructed PSBT
+        psbt_file: PathBuf,
+    },
+
+    /// Construct BIP-127 proof-of-reserves: PSBT spending all wallet UTXOs
+    /// together with an unspendable challenge input committing to the
+    /// message. The PSBT has to be signed with `btc-hot` as usual, but it
+    /// can't be finalized and published.
+    ProveReserves {
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Name of the account to use if the wallet file is a multi-account
+        /// wallet container managed with `account` commands
+        #[clap(long)]
+        account: Option<String>,
+
+        /// Minimum number of addresses to look ahead
+        #[clap(short = 'n', long, default_value = "20")]
+        look_ahead: u16,
+
+        /// Challenge message the proof commits to
+        message: String,
+
+        /// Destination file to save constructed PSBT
+        psbt_file: PathBuf,
+    },
+
+    /// Verify BIP-127 proof-of-reserves signed by the owners of all reserves,
+    /// checking that the reserves were unspent at a given block height.
+    VerifyReserves {
+        /// Block height at which the reserves must be unspent. Defaults to
+        /// the current chain tip height.
+        #[clap(long)]
+        height: Option<u32>,
+
+        /// Challenge message the proof must commit to
+        message: String,
+
+        /// File containing signed proof-of-reserves PSBT
+        psbt_file: PathBuf,
+    },
+
+    /// Send PSBT signed by `btc-hot` to the payjoin (BIP-78) receiver
+    /// endpoint and save validated payjoin proposal, which has to be signed
+    /// once more and finalized.
+    PayjoinSend {
+        /// Receiver payjoin endpoint URL (`pj` parameter of the BIP-21 URI),
+        /// which must use HTTPS or point to a Tor onion service
+        #[clap(long)]
+        endpoint: String,
+
+        /// Receiver address from the BIP-21 URI
+        #[clap(short, long)]
+        address: Address,
+
+        /// Index of the PSBT output (usually the change output) from which the
+        /// receiver may deduct fee for its inputs
+        #[clap(long)]
+        fee_output: Option<usize>,
+
+        /// Maximum fee in satoshis the receiver may deduct from the fee output
+        #[clap(long, default_value = "0")]
+        max_fee_contribution: u64,
+
+        /// Prohibit receiver from changing its output script and amount
+        #[clap(long)]
+        disable_output_substitution: bool,
+
+        /// Minimal feerate of the payjoin transaction, in sat/vbyte
+        #[clap(long)]
+        min_feerate: Option<f32>,
+
+        /// File containing PSBT signed for all of the inputs
+        psbt_file: PathBuf,
+
+        /// Destination file to save payjoin proposal PSBT
+        proposal_file: PathBuf,
+    },
+
     /// Try to finalize PSBT
     Finalize {
         /// Destination file to save binary transaction. If no file is given
@@ -281,493 +698,3782 @@ SIGHASH_TYPE representations:
         psbt_file: PathBuf,
     },
 
+    /// Verify all signatures present in PSBT inputs, for instance signatures
+    /// of multisig co-signers before finalizing the transaction
+    Audit {
+        /// File containing partially-signed PSBT
+        psbt_file: PathBuf,
+    },
+
+    /// Sign message with a wallet key according to BIP-322. The command
+    /// constructs PSBT of the virtual transaction, which has to be signed
+    /// with `btc-hot sign`; running the command once more with `--finalize`
+    /// flag extracts the message signature from the signed PSBT.
+    SignMessage {
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Name of the account to use if the wallet file is a multi-account
+        /// wallet container managed with `account` commands
+        #[clap(long)]
+        account: Option<String>,
+
+        /// Read PSBT signed with `btc-hot` from the PSBT file and print the
+        /// message signature
+        #[clap(long)]
+        finalize: bool,
+
+        /// Derivation terminal of the signing address, like `/0/1`
+        terminal: DerivationSubpath<UnhardenedIndex>,
+
+        /// Message to sign
+        message: String,
+
+        /// File to save PSBT for signing with `btc-hot`
+        psbt_file: PathBuf,
+    },
+
+    /// Verify BIP-322 message signature made by an address
+    VerifyMessage {
+        /// Address which signed the message
+        address: Address,
+
+        /// Signed message
+        message: String,
+
+        /// Base64-encoded signature
+        signature: MessageSignature,
+    },
+
     /// Get info about extended public key data
     Info {
         /// Base58-encoded extended public key
         data: String,
     },
 
-    /// Inspect PSBT or transaction file in binary format. If the file is not
-    /// provided it will read user input as a Base-58 encoded string.
+    /// Classify address: report its type, network, `scriptPubkey` and whether
+    /// it is a known burn address
+    AddressInfo {
+        /// Address to classify
+        address: Address,
+    },
+
+    /// Inspect PSBT file in binary, hex or Base64 format (detected
+    /// automatically). If the file is not provided it will read user input as a
+    /// hex or Base64 encoded string.
     Inspect {
-        /// File containing binary PSBT or transaction data to inspect
+        /// File containing binary, hex or Base64-encoded PSBT data to inspect
         file: Option<PathBuf>,
+
+        /// Inspect signed transaction in binary or hex format instead of PSBT:
+        /// disassemble its scripts, classify inputs and outputs and flag
+        /// non-standard scripts and other anomalies
+        #[clap(long, conflicts_with_all = ["explain", "key_only"])]
+        tx: bool,
+
+        /// Explain how each of the finalized inputs satisfies the spent output
+        /// script: spending path taken, keys signed and time- and hash-locks
+        /// used
+        #[clap(long)]
+        explain: bool,
+
+        /// Declare wallet as taproot key-spend only, flagging taproot outputs
+        /// with known internal key which commit to a script tree (have
+        /// non-nil merkle root)
+        #[clap(long)]
+        key_only: bool,
     },
 
-    /// Converts binary PSBT file into a Base58 representation printed to STDIN.
-    Convert { file: PathBuf },
-}
+    /// Converts PSBT file in binary, hex or Base64 format (detected
+    /// automatically) into a Base64 representation printed to STDOUT.
+    Convert {
+        /// File containing binary, hex or Base64-encoded PSBT data
+        file: PathBuf,
 
-impl Args {
-    fn electrum_client(&self, network: Network) -> Result<electrum::Client, electrum::Error> {
-        let electrum_url = format!(
-            "{}:{}",
-            self.electrum_server,
-            self.electrum_port
-                .unwrap_or_else(|| default_electrum_port(network))
-        );
-        eprintln!(
-            "Connecting to network {} using {}",
-            network.to_string().yellow(),
-            electrum_url.yellow()
-        );
-        electrum::Client::new(&electrum_url)
-    }
+        /// Print PSBT in hex encoding instead of Base64
+        #[clap(long)]
+        hex: bool,
+    },
 
-    pub fn exec(&self) -> Result<(), Error> {
-        match &self.command {
-            Command::Inspect { file } => self.inspect(file.as_ref()),
-            Command::Create {
-                account_file,
-                descriptor_file,
-                output_file,
-            } => Self::create(descriptor_file, output_file, account_file.as_deref()),
-            Command::Check {
-                wallet_file,
-                look_ahead,
-                skip,
-                regtest,
-            } => self.check(wallet_file, *look_ahead, *skip, *regtest),
-            Command::History { .. } => self.history(),
-            Command::Address {
-                wallet_file,
-                count,
-                skip,
-                show_change,
-                regtest,
-            } => self.address(wallet_file, *count, *skip, *show_change, *regtest),
-            Command::Construct {
-                locktime,
-                wallet_file,
-                inputs,
-                outputs,
-                change_index,
-                proprietary_keys,
-                psbt_file,
-                fee,
-            } => self.construct(
-                wallet_file,
-                *locktime,
-                inputs,
-                outputs,
-                *change_index,
-                proprietary_keys,
-                *fee,
-                psbt_file,
-            ),
-            Command::Finalize {
-                psbt_file,
-                tx_file,
-                publish,
-            } => self.finalize(
-                psbt_file,
-                tx_file.as_ref(),
-                publish
-                    .as_ref()
-                    .copied()
-                    .map(|n| n.unwrap_or(Network::Bitcoin)),
-            ),
-            Command::Info { data } => self.info(data.as_str()),
-            Command::Convert { file } => self.convert(file),
-        }
-    }
+    /// Compile miniscript concrete policy into a wallet descriptor and report
+    /// its spending costs.
+    Compile {
+        /// Descriptor class to compile the policy for: `sh`, `wsh`, `sh-wsh`
+        /// or `tr`
+        #[clap(short, long, default_value = "wsh")]
+        class: DescriptorClass,
+
+        /// Feerate, in satoshis per vbyte, used to estimate the cost of
+        /// spending a single descriptor input. Defaults to the feerate source
+        /// from the configuration file.
+        #[clap(short, long)]
+        feerate: Option<u32>,
+
+        /// Miniscript concrete policy. Can use explicit or named tracking
+        /// accounts as keys.
+        policy: String,
+    },
 
-    fn create(
-        descriptor_file: &Path,
-        path: &Path,
-        account_file: Option<&Path>,
-    ) -> Result<(), Error> {
-        pub struct DerivationRefTranslator<'a> {
-            account_file: Option<&'a Path>,
-            accounts: &'a AccountIndex,
-        }
+    /// Produce delegation (cold staking) descriptor, which can be spent by the
+    /// owner key at any time and by the delegate key after a timelock expiry
+    Delegation {
+        /// Descriptor class to produce: `sh`, `wsh`, `sh-wsh` or `tr`
+        #[clap(short, long, default_value = "wsh")]
+        class: DescriptorClass,
 
-        impl<'a> Translator<DerivationRef, DerivationAccount, Error> for DerivationRefTranslator<'a> {
-            fn pk(&mut self, pk: &DerivationRef) -> Result<DerivationAccount, Error> {
-                match pk {
-                    DerivationRef::NamedAccount(_) if self.account_file.is_none() => {
-                        Err(Error::AccountsFileRequired)
-                    }
-                    DerivationRef::NamedAccount(name) => self
-                        .accounts
-                        .get(name.as_str())
-                        .cloned()
-                        .ok_or_else(|| Error::UnknownNamedAccount(name.clone())),
+        /// Owner key: explicit or named tracking account
+        owner: DerivationRef,
+
+        /// Delegate key: explicit or named tracking account
+        delegate: DerivationRef,
+
+        /// Timelock restricting delegate spendings: `older(<blocks>)` or
+        /// `after(<height>)`
+        lock: DelegationLock,
+    },
+
+    /// Inspect and edit taproot script trees of wallet descriptors and PSBT
+    /// outputs
+    Taptree {
+        /// Script tree command to execute
+        #[clap(subcommand)]
+        command: TaptreeCommand,
+    },
+
+    /// Edit proprietary keys of an existing PSBT, compare and patch PSBT
+    /// versions
+    Psbt {
+        /// PSBT command to execute
+        #[clap(subcommand)]
+        command: PsbtCommand,
+    },
+
+    /// Export wallet descriptors for use in other wallet software, printing
+    /// them to STDOUT
+    Export {
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Format to export descriptors into
+        #[clap(short = 'f', long = "wallet-format", default_value = "bitcoin-core")]
+        format: ExportFormat,
+
+        /// UNIX timestamp since which the wallet has to be rescanned; if not
+        /// given, the wallet is considered new and is not rescanned
+        #[clap(long)]
+        timestamp: Option<u64>,
+
+        /// Last index of the address range to watch for ranged descriptors
+        #[clap(long, default_value = "999")]
+        range_end: u32,
+
+        /// Block height from which Sparrow and Specter wallets have to be
+        /// rescanned
+        #[clap(long, default_value = "0")]
+        height: u32,
+    },
+
+    /// Create new wallet from a wallet file exported by other wallet software
+    Import {
+        /// Format of the exported wallet file. For `bitcoin-core` format the
+        /// file must contain output of `listdescriptors` command.
+        #[clap(short = 'f', long = "wallet-format", default_value = "bitcoin-core")]
+        format: ExportFormat,
+
+        /// Wallet file exported by other wallet software
+        input_file: PathBuf,
+
+        /// File to save read-only wallet to
+        output_file: PathBuf,
+    },
+
+    /// Synchronize wallet state between an online watcher and an air-gapped
+    /// host using signed sync bundles
+    Sync {
+        /// Synchronization command to execute
+        #[clap(subcommand)]
+        command: SyncCommand,
+    },
+
+    /// Manage named accounts of a multi-account wallet container file
+    Account {
+        /// Account management command to execute
+        #[clap(subcommand)]
+        command: AccountCommand,
+    },
+
+    /// Print features and signer backends the tool was compiled with
+    Capabilities,
+}
+
+/// Multi-account wallet container command to execute
+#[derive(Subcommand)]
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum AccountCommand {
+    /// Add named account defined with a given output descriptor to the wallet
+    /// container, creating the container file if it does not exist
+    Add {
+        /// File containing named tracking account definitions, one per line.
+        #[clap(long)]
+        account_file: Option<PathBuf>,
+
+        /// Path to the multi-account wallet container file
+        wallet_file: PathBuf,
+
+        /// Name of the account, like `receive`, `change` or name of a
+        /// multisig co-account
+        name: String,
+
+        /// Account output descriptor text file, in the same format as used by
+        /// `create` command
+        descriptor_file: PathBuf,
+    },
+
+    /// List accounts of the wallet container
+    List {
+        /// Path to the multi-account wallet container file
+        wallet_file: PathBuf,
+    },
+
+    /// Remove named account from the wallet container
+    Remove {
+        /// Path to the multi-account wallet container file
+        wallet_file: PathBuf,
+
+        /// Name of the account to remove
+        name: String,
+    },
+}
+
+/// Wallet state synchronization command to execute
+#[derive(Subcommand)]
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SyncCommand {
+    /// Scan wallet UTXO set with Electrum server and export signed bundle
+    /// containing changes since the previously exported bundle, chain tip and
+    /// fee estimates. Used by the online watcher.
+    Export {
+        /// File containing hex-encoded 32-byte secret key used to sign
+        /// bundles
+        #[clap(short = 'k', long)]
+        watcher_key: PathBuf,
+
+        /// Minimum number of addresses to look ahead
+        #[clap(short = 'n', long, default_value = "20")]
+        look_ahead: u16,
+
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Watcher sync state file; created if not present
+        state_file: PathBuf,
+
+        /// File to save the signed bundle to
+        bundle_file: PathBuf,
+    },
+
+    /// Verify signed bundle exported by the watcher and apply it to the
+    /// wallet sync state. Used by the air-gapped host.
+    Import {
+        /// Public key of the trusted watcher. Required for the first import,
+        /// when the sync state file does not exist yet.
+        #[clap(short, long)]
+        watcher: Option<XOnlyPublicKey>,
+
+        /// Path to the read-only wallet file generated with `create` command
+        wallet_file: PathBuf,
+
+        /// Wallet sync state file
+        state_file: PathBuf,
+
+        /// File containing signed bundle
+        bundle_file: PathBuf,
+    },
+
+    /// Show wallet state from the sync state file
+    Status {
+        /// Wallet sync state file
+        state_file: PathBuf,
+    },
+}
+
+/// PSBT command to execute
+#[derive(Subcommand)]
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum PsbtCommand {
+    /// Set proprietary key, replacing its previous value
+    SetKey {
+        /// PSBT file to modify
+        psbt_file: PathBuf,
+
+        /// Proprietary key descriptor in form of `location type key:value`,
+        /// like `input(0) P2C(0) 02e3...:ab01`
+        key: ProprietaryKeyDescriptor,
+    },
+
+    /// Print all proprietary keys of a given type
+    GetKey {
+        /// PSBT file to read
+        psbt_file: PathBuf,
+
+        /// Key location: `global`, `input(X)` or `output(X)`
+        location: ProprietaryKeyLocation,
+
+        /// Key type in form of `prefix(subtype)`, like `P2C(0)`
+        ty: ProprietaryKeyType,
+    },
+
+    /// Delete proprietary key
+    DeleteKey {
+        /// PSBT file to modify
+        psbt_file: PathBuf,
+
+        /// Proprietary key descriptor in form of `location type [key:]`;
+        /// value, if given, is ignored
+        key: ProprietaryKeyDescriptor,
+    },
+
+    /// Print field-level differences between two versions of a PSBT: added
+    /// and removed signatures, modified proprietary keys and changed outputs
+    Diff {
+        /// Original PSBT file
+        from: PathBuf,
+
+        /// Modified PSBT file
+        to: PathBuf,
+
+        /// Save diff in JSON format to a file, which can be applied to other
+        /// PSBT copies with `psbt patch` command
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Apply diff produced by `psbt diff` command to a PSBT
+    Patch {
+        /// PSBT file to modify
+        psbt_file: PathBuf,
+
+        /// JSON file with the diff
+        diff_file: PathBuf,
+    },
+}
+
+/// Taproot script tree command to execute
+#[derive(Subcommand)]
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum TaptreeCommand {
+    /// Show script tree nodes with their DFS paths and node hashes
+    Show {
+        #[clap(flatten)]
+        source: TaptreeSource,
+    },
+
+    /// Print script tree in the text format accepted by the `instill` command
+    Export {
+        #[clap(flatten)]
+        source: TaptreeSource,
+
+        /// Print script tree using output descriptor syntax (like
+        /// `{A,{B,C}}`) instead
+        #[clap(short, long)]
+        descriptor: bool,
+    },
+
+    /// Instill script subtree into the script tree of a PSBT output. Updates
+    /// output `scriptPubkey` to commit to the new tree.
+    Instill {
+        /// PSBT file to modify
+        psbt_file: PathBuf,
+
+        /// Number of the PSBT output to modify
+        output: u16,
+
+        /// File containing script subtree in text format
+        subtree_file: PathBuf,
+
+        /// DFS path of the node at which the subtree must be instilled, as a
+        /// string of `0` (DFS first) and `1` (DFS last) characters. Empty
+        /// string stands for the tree root.
+        #[clap(default_value = "")]
+        path: String,
+
+        /// Put subtree to the DFS-last side of the new branch
+        #[clap(long)]
+        dfs_last: bool,
+    },
+
+    /// Cut script subtree out of the script tree of a PSBT output. Updates
+    /// output `scriptPubkey` to commit to the remaining tree.
+    Cut {
+        /// PSBT file to modify
+        psbt_file: PathBuf,
+
+        /// Number of the PSBT output to modify
+        output: u16,
+
+        /// File to save the cut subtree to, in text format
+        subtree_file: PathBuf,
+
+        /// DFS path of the branch at which the subtree must be cut, as a
+        /// string of `0` (DFS first) and `1` (DFS last) characters
+        path: String,
+
+        /// Cut the DFS-last child of the branch instead of the DFS-first one
+        #[clap(long)]
+        dfs_last: bool,
+    },
+}
+
+/// Delivery of watch events to webhooks and Unix sockets
+#[derive(clap::Args)]
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct WatchNotify {
+    /// Webhook URL (`http(s)://...`) or Unix socket path (`unix:<path>`) to
+    /// deliver JSON-serialized events to. May be given multiple times.
+    #[clap(long)]
+    notify: Vec<NotifySink>,
+
+    /// Secret used to sign notification payloads with HMAC-SHA256; required
+    /// if `--notify` is given
+    #[clap(long)]
+    notify_secret: Option<String>,
+
+    /// Number of confirmations at which `confirmed` event is produced for a
+    /// wallet transaction. May be given multiple times.
+    #[clap(long = "confirmations")]
+    confirmations: Vec<u32>,
+}
+
+/// Source of the taproot script tree: a wallet file or a PSBT output
+#[derive(clap::Args)]
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TaptreeSource {
+    /// Wallet file with taproot descriptor or, if `--output` is given, PSBT
+    /// file
+    file: PathBuf,
+
+    /// Number of the PSBT output to take script tree from
+    #[clap(short, long)]
+    output: Option<u16>,
+
+    /// Derive wallet descriptor script tree for change keychain
+    #[clap(short = 'c', long = "change")]
+    show_change: bool,
+
+    /// Derivation index to use for wallet descriptor script tree
+    #[clap(short, long, default_value = "0")]
+    index: UnhardenedIndex,
+}
+
+impl Command {
+    /// Resolves relative wallet file paths against the wallet directory from
+    /// the configuration file.
+    fn resolve_wallet_paths(&mut self, config: &Config) {
+        let path = match self {
+            Command::Create { output_file, .. } | Command::Import { output_file, .. } => {
+                output_file
+            }
+            Command::Rotate { wallet_file, .. }
+            | Command::Check { wallet_file, .. }
+            | Command::Watch { wallet_file, .. }
+            | Command::History { wallet_file, .. }
+            | Command::Address { wallet_file, .. }
+            | Command::Label { wallet_file, .. }
+            | Command::Freeze { wallet_file, .. }
+            | Command::Reserve { wallet_file, .. }
+            | Command::Release { wallet_file, .. }
+            | Command::Supersede { wallet_file, .. }
+            | Command::Construct { wallet_file, .. }
+            | Command::Migrate { wallet_file, .. }
+            | Command::SweepInheritance { wallet_file, .. }
+            | Command::ProveReserves { wallet_file, .. }
+            | Command::SignMessage { wallet_file, .. }
+            | Command::Export { wallet_file, .. }
+            | Command::Sync {
+                command: SyncCommand::Export { wallet_file, .. },
+            }
+            | Command::Sync {
+                command: SyncCommand::Import { wallet_file, .. },
+            }
+            | Command::Account {
+                command: AccountCommand::Add { wallet_file, .. },
+            }
+            | Command::Account {
+                command: AccountCommand::List { wallet_file },
+            }
+            | Command::Account {
+                command: AccountCommand::Remove { wallet_file, .. },
+            } => wallet_file,
+            _ => return,
+        };
+        *path = config.wallet_path(&*path);
+    }
+}
+
+impl Args {
+    /// Loads configuration file and resolves wallet file paths against the
+    /// configured wallet directory.
+    pub fn load_config(&mut self) -> Result<(), ConfigError> {
+        self.config = Config::load(self.config_file.as_deref())?;
+        self.command.resolve_wallet_paths(&self.config);
+        Ok(())
+    }
+
+    /// Selects network for the wallet descriptor, using `--network` argument
+    /// if it is compatible with the descriptor keys
+    fn wallet_network(
+        &self,
+        descriptor: &miniscript::Descriptor<DerivationAccount>,
+    ) -> Result<Network, Error> {
+        let key_network = descriptor.network()?;
+        match self.network {
+            None => Ok(key_network),
+            Some(network) if network.is_compatible_with(key_network) => Ok(network.into()),
+            Some(network) => Err(Error::NetworkMismatch(network.into(), key_network)),
+        }
+    }
+
+    fn proxy(&self) -> Option<&str> { self.proxy.as_deref().or(self.config.proxy.as_deref()) }
+
+    /// Resolves payment instruction into a transaction output
+    fn payment_output(&self, instruction: &PaymentInstruction) -> Result<AddressAmount, Error> {
+        let request = match instruction {
+            PaymentInstruction::Request(request) => request.clone(),
+            PaymentInstruction::Name(name) => {
+                eprint!("Resolving {} ... ", name);
+                let request = name.resolve(&mut DohResolver::with(
+                    self.config.dns_resolver(),
+                    self.proxy(),
+                )?)?;
+                eprintln!("{}", "done".green());
+                request
+            }
+        };
+        match (request.address, request.amount) {
+            (Some(address), Some(amount)) => Ok(AddressAmount { address, amount }),
+            (None, _) => Err(Error::NoPaymentAddress(instruction.to_string())),
+            (_, None) => Err(Error::NoPaymentAmount(instruction.to_string())),
+        }
+    }
+
+    fn electrum_config(&self, network: Network) -> Result<ElectrumConfig, Error> {
+        let configured = self.config.electrum_server(network);
+        let (server, port, ssl) = match (&self.electrum_server, configured) {
+            (Some(server), _) => (server.as_str(), self.electrum_port, self.ssl),
+            (None, Some(configured)) => (
+                configured.server.as_str(),
+                self.electrum_port.or(configured.port),
+                self.ssl || configured.ssl,
+            ),
+            (None, None) => (DEFAULT_ELECTRUM_SERVER, self.electrum_port, self.ssl),
+        };
+        let protocol = if ssl {
+            ElectrumProtocol::Ssl
+        } else {
+            ElectrumProtocol::Tcp
+        };
+        let mut config = ElectrumConfig::parse(server, protocol, PublicNetwork::from(network))?;
+        if let Some(port) = port {
+            config.port = port;
+        }
+        config.proxy = self.proxy().map(str::to_owned);
+        Ok(config)
+    }
+
+    fn electrum_client(&self, network: Network) -> Result<electrum::Client, Error> {
+        let config = self.electrum_config(network)?;
+        eprintln!(
+            "Connecting to network {} using {}",
+            network.to_string().yellow(),
+            config.to_string().yellow()
+        );
+        Ok(config.connect()?)
+    }
+
+    /// Constructs broadcaster using configured electrum server, falling back
+    /// to the default electrum server if the configured one is not reachable.
+    fn broadcaster(&self, network: Network) -> Result<FallbackBroadcaster, Error> {
+        let primary = self.electrum_config(network)?;
+        let mut fallback = ElectrumConfig::parse(
+            DEFAULT_ELECTRUM_SERVER,
+            primary.protocol,
+            PublicNetwork::from(network),
+        )?;
+        fallback.proxy = primary.proxy.clone();
+
+        eprintln!(
+            "Publishing to network {} using {}",
+            network.to_string().yellow(),
+            primary.to_string().yellow()
+        );
+        let mut broadcaster = FallbackBroadcaster::default();
+        let add_fallback = primary.host != fallback.host;
+        broadcaster.push(primary);
+        if add_fallback {
+            broadcaster.push(fallback);
+        }
+        Ok(broadcaster)
+    }
+
+    /// Prints command results in JSON or YAML format, if one of these output
+    /// formats is selected.
+    ///
+    /// # Returns
+    ///
+    /// Whether the report was printed, in which case the human-readable output
+    /// must be skipped.
+    fn report(&self, report: &impl serde_crate::Serialize) -> Result<bool, Error> {
+        match self.format {
+            OutputFormat::Plain => return Ok(false),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(report)?),
+            OutputFormat::Yaml => print!("{}", serde_yaml::to_string(report)?),
+        }
+        Ok(true)
+    }
+
+    fn default_feerate(&self) -> Result<u32, Error> {
+        match self.config.feerate {
+            FeerateSource::Fixed { sat_per_vbyte } => Ok(sat_per_vbyte),
+            FeerateSource::Electrum { target_blocks } => {
+                let client = self.electrum_client(Network::Bitcoin)?;
+                // Electrum servers report feerate in BTC per kvbyte
+                let btc_per_kvb = client.estimate_fee(target_blocks as usize)?;
+                Ok((btc_per_kvb * 100_000.0).ceil().max(1.0) as u32)
+            }
+        }
+    }
+
+    pub fn exec(&self) -> Result<(), Error> {
+        match &self.command {
+            Command::Inspect { file, tx: true, .. } => self.inspect_tx(file.as_ref()),
+            Command::Inspect {
+                file,
+                explain,
+                key_only,
+                tx: false,
+            } => self.inspect(file.as_ref(), *explain, *key_only),
+            Command::Create {
+                account_file,
+                descriptor_file,
+                output_file,
+                change_descriptor_file,
+                birthday,
+            } => self.create(
+                descriptor_file,
+                output_file,
+                account_file.as_deref(),
+                change_descriptor_file.as_deref(),
+                *birthday,
+            ),
+            Command::Rotate {
+                account_file,
+                wallet_file,
+                descriptor_file,
+            } => self.rotate(wallet_file, descriptor_file, account_file.as_deref()),
+            Command::Check {
+                wallet_file,
+                account,
+                look_ahead,
+                skip,
+            } => self.check(wallet_file, account.as_deref(), *look_ahead, *skip),
+            Command::Watch {
+                wallet_file,
+                account,
+                gap_limit,
+                interval,
+                hook,
+                notify,
+            } => self.watch(
+                wallet_file,
+                account.as_deref(),
+                *gap_limit,
+                *interval,
+                hook.as_deref(),
+                notify,
+            ),
+            Command::History { .. } => self.history(),
+            Command::Address {
+                wallet_file,
+                account,
+                count,
+                skip,
+                show_change,
+            } => self.address(wallet_file, account.as_deref(), *count, *skip, *show_change),
+            Command::Label {
+                wallet_file,
+                address,
+                text,
+            } => self.label(wallet_file, address, text.as_deref()),
+            Command::Freeze {
+                wallet_file,
+                utxo,
+                unfreeze,
+            } => self.freeze(wallet_file, *utxo, *unfreeze),
+            Command::Reserve {
+                wallet_file,
+                protocol,
+                keychain,
+                range,
+            } => self.reserve(wallet_file, protocol, *keychain, range.clone()),
+            Command::Release {
+                wallet_file,
+                protocol,
+            } => self.release(wallet_file, protocol),
+            Command::Supersede {
+                wallet_file,
+                key,
+                boundary,
+                account,
+            } => self.supersede(wallet_file, *key, *boundary, account.clone()),
+            Command::Construct {
+                locktime,
+                wallet_file,
+                account,
+                inputs,
+                look_ahead,
+                exclude_frozen,
+                replay_guard,
+                fork_heights,
+                outputs,
+                payments,
+                allowed_script_types,
+                allowed_addresses,
+                max_output_amount,
+                max_tx_amount,
+                require_wallet_change,
+                change_index,
+                proprietary_keys,
+                psbt_file,
+                fee,
+            } => self.construct(
+                wallet_file,
+                account.as_deref(),
+                *locktime,
+                inputs,
+                *look_ahead,
+                *exclude_frozen,
+                *replay_guard,
+                fork_heights,
+                outputs,
+                payments,
+                &construct::SpendPolicy {
+                    allowed_script_types: allowed_script_types.iter().copied().collect(),
+                    allowed_destinations: allowed_addresses
+                        .iter()
+                        .map(Address::script_pubkey)
+                        .collect(),
+                    max_output_amount: *max_output_amount,
+                    max_tx_amount: *max_tx_amount,
+                    require_wallet_change: *require_wallet_change,
+                },
+                *change_index,
+                proprietary_keys,
+                *fee,
+                psbt_file,
+            ),
+            Command::Migrate {
+                wallet_file,
+                generation,
+                look_ahead,
+                change_index,
+                psbt_file,
+                fee,
+            } => self.migrate(
+                wallet_file,
+                *generation,
+                *look_ahead,
+                *change_index,
+                *fee,
+                psbt_file,
+            ),
+            Command::SweepInheritance {
+                wallet_file,
+                account,
+                look_ahead,
+                address,
+                fee,
+                psbt_file,
+            } => self.sweep_inheritance(
+                wallet_file,
+                account.as_deref(),
+                *look_ahead,
+                address,
+                *fee,
+                psbt_file,
+            ),
+            Command::ProveReserves {
+                wallet_file,
+                account,
+                look_ahead,
+                message,
+                psbt_file,
+            } => self.prove_reserves(
+                wallet_file,
+                account.as_deref(),
+                *look_ahead,
+                message,
+                psbt_file,
+            ),
+            Command::VerifyReserves {
+                height,
+                message,
+                psbt_file,
+            } => self.verify_reserves(psbt_file, message, *height),
+            Command::PayjoinSend {
+                endpoint,
+                address,
+                fee_output,
+                max_fee_contribution,
+                disable_output_substitution,
+                min_feerate,
+                psbt_file,
+                proposal_file,
+            } => self.payjoin_send(
+                endpoint,
+                address,
+                PayjoinParams {
+                    additional_fee_output_index: *fee_output,
+                    max_additional_fee_contribution: *max_fee_contribution,
+                    disable_output_substitution: *disable_output_substitution,
+                    min_fee_rate: *min_feerate,
+                },
+                psbt_file,
+                proposal_file,
+            ),
+            Command::Finalize {
+                psbt_file,
+                tx_file,
+                publish,
+            } => self.finalize(
+                psbt_file,
+                tx_file.as_ref(),
+                publish.as_ref().copied().map(|n| {
+                    n.or(self.network.map(Network::from))
+                        .unwrap_or(Network::Bitcoin)
+                }),
+            ),
+            Command::Audit { psbt_file } => self.audit(psbt_file),
+            Command::SignMessage {
+                wallet_file,
+                account,
+                finalize,
+                terminal,
+                message,
+                psbt_file,
+            } => self.sign_message(
+                wallet_file,
+                account.as_deref(),
+                terminal,
+                message,
+                psbt_file,
+                *finalize,
+            ),
+            Command::VerifyMessage {
+                address,
+                message,
+                signature,
+            } => self.verify_message(address, message, signature),
+            Command::Info { data } => self.info(data.as_str()),
+            Command::AddressInfo { address } => self.address_info(address),
+            Command::Convert { file, hex } => self.convert(file, *hex),
+            Command::Compile {
+                class,
+                feerate,
+                policy,
+            } => self.compile(policy, *class, *feerate),
+            Command::Delegation {
+                class,
+                owner,
+                delegate,
+                lock,
+            } => self.delegation(owner, delegate, *lock, *class),
+            Command::Taptree { command } => self.taptree(command),
+            Command::Psbt { command } => self.psbt(command),
+            Command::Export {
+                wallet_file,
+                format,
+                timestamp,
+                range_end,
+                height,
+            } => self.export(wallet_file, *format, *timestamp, *range_end, *height),
+            Command::Import {
+                format,
+                input_file,
+                output_file,
+            } => self.import(*format, input_file, output_file),
+            Command::Sync { command } => self.sync(command),
+            Command::Account { command } => self.account(command),
+            Command::Capabilities => {
+                let capabilities = wallet::capabilities();
+                if !self.report(&CapabilitiesReport::from(&capabilities))? {
+                    println!("{}", capabilities);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn create(
+        &self,
+        descriptor_file: &Path,
+        path: &Path,
+        account_file: Option<&Path>,
+        change_descriptor_file: Option<&Path>,
+        birthday: Option<Birthday>,
+    ) -> Result<(), Error> {
+        let mut meta = WalletMeta::default();
+        let descriptor = self.read_descriptor(descriptor_file, account_file, &mut meta)?;
+        let change_descriptor = change_descriptor_file
+            .map(|file| self.read_descriptor(file, account_file, &mut meta))
+            .transpose()?;
+        if let Some(ref change_descriptor) = change_descriptor {
+            let network = descriptor.network()?;
+            let change_network = change_descriptor.network()?;
+            if network != change_network {
+                return Err(Error::ChangeNetworkMismatch(network, change_network));
+            }
+        }
+
+        let wallet = WalletFile {
+            descriptor,
+            change_descriptor,
+            birthday,
+            history: vec![],
+            meta,
+        };
+        wallet.write(path)?;
+
+        if self.report(&wallet.report(path, self.bitcoin_core_fmt)?)? {
+            return Ok(());
+        }
+        println!(
+            "{} in `{}`\n",
+            "Wallet created".bright_green(),
+            path.display()
+        );
+
+        Ok(())
+    }
+
+    fn rotate(
+        &self,
+        wallet_path: &Path,
+        descriptor_file: &Path,
+        account_file: Option<&Path>,
+    ) -> Result<(), Error> {
+        let mut wallet = WalletFile::read(wallet_path)?;
+
+        let descriptor = self.read_descriptor(descriptor_file, account_file, &mut wallet.meta)?;
+        let prev_network = wallet.descriptor.network()?;
+        let network = descriptor.network()?;
+        if network != prev_network {
+            return Err(Error::RotationNetworkMismatch(prev_network, network));
+        }
+        if descriptor == wallet.descriptor {
+            eprintln!("{} descriptor is not changed", "Warning:".bright_yellow());
+            self.report(&wallet.report(wallet_path, self.bitcoin_core_fmt)?)?;
+            return Ok(());
+        }
+
+        wallet.rotate(descriptor);
+        wallet.write(wallet_path)?;
+
+        if self.report(&wallet.report(wallet_path, self.bitcoin_core_fmt)?)? {
+            return Ok(());
+        }
+        println!(
+            "{} to generation {} in `{}`\n",
+            "Wallet descriptor rotated".bright_green(),
+            wallet.history.len(),
+            wallet_path.display()
+        );
+
+        Ok(())
+    }
+
+    fn read_descriptor(
+        &self,
+        descriptor_file: &Path,
+        account_file: Option<&Path>,
+        meta: &mut WalletMeta,
+    ) -> Result<miniscript::Descriptor<DerivationAccount>, Error> {
+        pub struct DerivationRefTranslator<'a> {
+            account_file: Option<&'a Path>,
+            accounts: &'a AccountIndex,
+            meta: &'a mut WalletMeta,
+        }
+
+        impl<'a> Translator<DerivationRef, DerivationAccount, Error> for DerivationRefTranslator<'a> {
+            fn pk(&mut self, pk: &DerivationRef) -> Result<DerivationAccount, Error> {
+                match pk {
+                    DerivationRef::NamedAccount(_) if self.account_file.is_none() => {
+                        Err(Error::AccountsFileRequired)
+                    }
+                    DerivationRef::NamedAccount(name) => {
+                        let account = self
+                            .accounts
+                            .get(name.as_str())
+                            .cloned()
+                            .ok_or_else(|| Error::UnknownNamedAccount(name.clone()))?;
+                        self.meta
+                            .set_account_alias(account.account_fingerprint(), name);
+                        Ok(account)
+                    }
                     DerivationRef::TrackingAccount(account) => Ok(account.clone()),
                 }
             }
 
-            miniscript::translate_hash_fail!(DerivationRef, DerivationAccount, Error);
+            miniscript::translate_hash_fail!(DerivationRef, DerivationAccount, Error);
+        }
+
+        let accounts = account_file
+            .and_then(AccountIndex::read_file)
+            .unwrap_or_default();
+
+        let descriptor_str =
+            fs::read_to_string(descriptor_file)?.replace(['\n', '\r', ' ', '\t'], "");
+        if self.format.is_plain() {
+            println!(
+                "Using wallet descriptor:\n{}",
+                descriptor_str.bright_white()
+            );
+        }
+        let descriptor = miniscript::Descriptor::<DerivationRef>::from_str(&descriptor_str)?;
+        descriptor.translate_pk(&mut DerivationRefTranslator {
+            account_file,
+            accounts: &accounts,
+            meta,
+        })
+    }
+
+    fn address(
+        &self,
+        path: &Path,
+        account: Option<&str>,
+        count: u16,
+        skip: u16,
+        show_change: bool,
+    ) -> Result<(), Error> {
+        let secp = Secp256k1::new();
+
+        let wallet = WalletFile::load(path, account)?;
+        let meta = &wallet.meta;
+        let descriptor = if show_change {
+            wallet.change_descriptor()
+        } else {
+            &wallet.descriptor
+        };
+
+        if self.format.is_plain() {
+            println!(
+                "{}\n{}\n",
+                "\nWallet descriptor:".bright_white(),
+                descriptor.to_string_std(self.bitcoin_core_fmt)
+            );
+        }
+
+        if descriptor.derive_pattern_len()? != 2 {
+            return Err(Error::DescriptorDerivePattern);
+        }
+        let network = self.wallet_network(descriptor)?;
+        let keychain = UnhardenedIndex::from(u8::from(show_change));
+        let mut next = Some(UnhardenedIndex::from(skip));
+        let mut addresses = vec![];
+        for _ in 0..count {
+            let index = match next.and_then(|index| meta.next_unreserved(keychain, index)) {
+                Some(index) => index,
+                None => break,
+            };
+            next = index.checked_inc();
+            let address = meta
+                .key_rotations
+                .effective_descriptor(descriptor, index)
+                .address(&secp, [keychain, index], network)?;
+
+            let label = meta.label(&PubkeyScript::from_inner(address.script_pubkey()));
+            addresses.push((index, AddressReport {
+                terminal: format!("{}/{}", keychain, index),
+                address: address.to_string(),
+                label: label.map(str::to_owned),
+            }));
+        }
+
+        let reports = addresses
+            .iter()
+            .map(|(_, report)| report)
+            .collect::<Vec<_>>();
+        if self.report(&reports)? {
+            return Ok(());
+        }
+        for (index, report) in &addresses {
+            println!(
+                "{:>6} {} {}",
+                format!("#{}", index).dimmed(),
+                report.address,
+                report.label.as_deref().unwrap_or_default().bright_cyan()
+            );
+        }
+        println!();
+
+        Ok(())
+    }
+
+    fn export(
+        &self,
+        path: &Path,
+        format: ExportFormat,
+        timestamp: Option<u64>,
+        range_end: u32,
+        height: u32,
+    ) -> Result<(), Error> {
+        let wallet = WalletFile::read(path)?;
+        let timestamp = timestamp.map(Timestamp::Time).unwrap_or_default();
+        if format != ExportFormat::BitcoinCore && wallet.change_descriptor.is_some() {
+            return Err(Error::ChangeDescriptorExport(format));
+        }
+
+        let json = match format {
+            ExportFormat::BitcoinCore => bitcoin_core::import_descriptors(
+                &wallet.descriptor,
+                wallet.change_descriptor.as_ref(),
+                timestamp,
+                range_end,
+            )?,
+            ExportFormat::Electrum => interop::electrum::wallet_file(&wallet.descriptor)?,
+            ExportFormat::Sparrow => {
+                let label = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                sparrow::wallet_file(&wallet.descriptor, &label, height)?
+            }
+        };
+        println!("{:#}", json);
+
+        Ok(())
+    }
+
+    fn import(&self, format: ExportFormat, input_file: &Path, path: &Path) -> Result<(), Error> {
+        let data = fs::read_to_string(input_file)?;
+        let descriptor = match format {
+            ExportFormat::BitcoinCore => {
+                let mut descriptors = bitcoin_core::parse_list_descriptors(&data)?.into_iter();
+                let descriptor = descriptors.next().ok_or(Error::NoImportedDescriptors)?;
+                let skipped = descriptors.count();
+                if skipped > 0 {
+                    eprintln!(
+                        "{} {} more descriptor(s) are ignored; only the first one is used",
+                        "Warning:".bright_yellow(),
+                        skipped
+                    );
+                }
+                descriptor
+            }
+            ExportFormat::Electrum => interop::electrum::parse_wallet_file(&data)?,
+            ExportFormat::Sparrow => sparrow::parse_wallet_file(&data)?,
+        };
+        descriptor.check_sanity()?;
+
+        let wallet = WalletFile {
+            descriptor,
+            change_descriptor: None,
+            birthday: None,
+            history: vec![],
+            meta: WalletMeta::default(),
+        };
+        wallet.write(path)?;
+
+        if self.report(&wallet.report(path, self.bitcoin_core_fmt)?)? {
+            return Ok(());
+        }
+        println!(
+            "{} in `{}`\n",
+            "Wallet imported".bright_green(),
+            path.display()
+        );
+
+        Ok(())
+    }
+
+    fn check(
+        &self,
+        path: &Path,
+        account: Option<&str>,
+        batch_size: u16,
+        skip: u16,
+    ) -> Result<(), Error> {
+        let wallet = WalletFile::load(path, account)?;
+        let meta = &wallet.meta;
+
+        let network = self.wallet_network(&wallet.descriptor)?;
+        let client = self.electrum_client(network)?;
+        let birthday = wallet.birthday_height(&client)?;
+
+        let class = DescriptorClass::from(wallet.descriptor.desc_type());
+        let mut report = ScanReport::default();
+        let mut deprecated = vec![];
+        for (generation, descriptor) in wallet.generations() {
+            if self.format.is_plain() {
+                let title = if wallet.history.is_empty() {
+                    s!("\nWallet descriptor:")
+                } else {
+                    format!("\nWallet descriptor generation {}:", generation)
+                };
+                println!(
+                    "{}\n{}\n",
+                    title.bright_white(),
+                    descriptor.to_string_std(self.bitcoin_core_fmt)
+                );
+            }
+            let amount = self.check_descriptor(
+                &client,
+                descriptor,
+                meta,
+                batch_size,
+                skip,
+                birthday,
+                network,
+                Some(generation),
+                &mut report.utxos,
+            )?;
+            if generation < wallet.history.len() && amount > 0 {
+                deprecated.push((generation, amount));
+            }
+            report.total += amount;
+        }
+        if let Some(ref descriptor) = wallet.change_descriptor {
+            if self.format.is_plain() {
+                println!(
+                    "{}\n{}\n",
+                    "\nWallet change descriptor:".bright_white(),
+                    descriptor.to_string_std(self.bitcoin_core_fmt)
+                );
+            }
+            report.total += self.check_descriptor(
+                &client,
+                descriptor,
+                meta,
+                batch_size,
+                skip,
+                birthday,
+                network,
+                None,
+                &mut report.utxos,
+            )?;
+        }
+
+        if self.format.is_plain() {
+            println!(
+                "Total {}\n",
+                self.unit
+                    .amount(report.total)
+                    .to_string()
+                    .bright_yellow()
+                    .underline()
+            );
+        }
+
+        for (generation, amount) in deprecated {
+            let reason = match wallet.deprecated_class(generation) {
+                Some(generation_class) => {
+                    format!("using deprecated `{}` script class", generation_class)
+                }
+                None => s!("which was replaced"),
+            };
+            let warning = format!(
+                "{} received by descriptor generation {} {}; use `migrate -g {}` command to move \
+                 the funds to the current `{}` wallet descriptor",
+                self.unit.amount(amount),
+                generation,
+                reason,
+                generation,
+                class
+            );
+            eprintln!("{} {}", "Warning:".bright_yellow(), warning);
+            report.warnings.push(warning);
+        }
+
+        self.report(&report)?;
+        Ok(())
+    }
+
+    /// Collects UTXOs which are not frozen, controlled by a wallet descriptor,
+    /// stopping scan of each keychain after a batch of `batch_size` addresses
+    /// without funds. Outputs mined before the `birthday` block are skipped.
+    /// Each batch is scanned with all descriptor versions produced by account
+    /// key rotations.
+    fn descriptor_utxos(
+        &self,
+        client: &electrum::Client,
+        descriptor: &miniscript::Descriptor<DerivationAccount>,
+        meta: &WalletMeta,
+        batch_size: u16,
+        birthday: u32,
+    ) -> Result<Vec<(InputDescriptor, Utxo)>, Error> {
+        let secp = Secp256k1::new();
+
+        let keychains = match descriptor.derive_pattern_len()? {
+            1 => vec![vec![]],
+            2 => vec![vec![UnhardenedIndex::zero()], vec![UnhardenedIndex::one()]],
+            _ => return Err(Error::DescriptorDerivePattern),
+        };
+        let versions = meta.key_rotations.descriptor_versions(descriptor);
+        let mut inputs = vec![];
+        for keychain in keychains {
+            let mut offset = 0u16;
+            loop {
+                let mut count = 0usize;
+                let mut batch = vec![];
+                for (_, version) in &versions {
+                    batch.extend(client.resolve_descriptor_utxo_since(
+                        &secp,
+                        version,
+                        &keychain,
+                        UnhardenedIndex::from(offset),
+                        batch_size as u32,
+                        birthday,
+                    )?);
+                }
+                for (index, (_, utxo_set)) in batch {
+                    for utxo in utxo_set {
+                        count += 1;
+                        if meta.is_frozen(utxo.outpoint()) {
+                            eprintln!(
+                                "{} frozen UTXO {}",
+                                "Skipping".bright_yellow(),
+                                utxo.outpoint()
+                            );
+                            continue;
+                        }
+                        let mut terminal = keychain.clone();
+                        terminal.push(index);
+                        let input = InputDescriptor {
+                            outpoint: *utxo.outpoint(),
+                            terminal: terminal.into(),
+                            seq_no: none!(),
+                            tweak: None,
+                            sighash_type: bitcoin::EcdsaSighashType::All,
+                            external: None,
+                            redeem_script: None,
+                            witness_script: None,
+                        };
+                        inputs.push((input, utxo));
+                    }
+                }
+                if count == 0 {
+                    break;
+                }
+                offset += batch_size;
+            }
+        }
+
+        Ok(inputs)
+    }
+
+    /// Scans outputs of a wallet descriptor, adding found UTXOs to `utxos`.
+    /// Descriptor generation is `None` for the separate change descriptor,
+    /// for which only the change keychain is scanned. Outputs mined before the
+    /// `birthday` block are skipped. Each batch is scanned with all descriptor
+    /// versions produced by account key rotations.
+    #[allow(clippy::too_many_arguments)]
+    fn check_descriptor(
+        &self,
+        client: &electrum::Client,
+        descriptor: &miniscript::Descriptor<DerivationAccount>,
+        meta: &WalletMeta,
+        batch_size: u16,
+        skip: u16,
+        birthday: u32,
+        network: Network,
+        generation: Option<usize>,
+        utxos: &mut Vec<UtxoReport>,
+    ) -> Result<u64, Error> {
+        let secp = Secp256k1::new();
+
+        let mut total = 0u64;
+        let mut single_pat = [UnhardenedIndex::zero(); 1];
+        let mut double_pat = [UnhardenedIndex::zero(); 2];
+        let derive_pattern = match descriptor.derive_pattern_len()? {
+            1 => single_pat.as_mut_slice(),
+            2 => double_pat.as_mut_slice(),
+            _ => return Err(Error::DescriptorDerivePattern),
+        };
+        let versions = meta.key_rotations.descriptor_versions(descriptor);
+        let first_case = u8::from(generation.is_none() && derive_pattern.len() > 1);
+        for case in first_case..(derive_pattern.len() as u8) {
+            let mut offset = skip;
+            let mut last_count = 1usize;
+            if derive_pattern.len() > 1 {
+                if let Some(idx) = derive_pattern.first_mut() {
+                    *idx = UnhardenedIndex::from(case)
+                }
+            }
+            loop {
+                eprint!("Batch {}/{}..{}", case, offset, offset + batch_size);
+
+                let mut addr_total = 0u64;
+                let mut count = 0usize;
+                eprint!(" ... ");
+                let mut batch = vec![];
+                for (_, version) in &versions {
+                    batch.extend(client.resolve_descriptor_utxo_since(
+                        &secp,
+                        version,
+                        [UnhardenedIndex::from(case)],
+                        UnhardenedIndex::from(offset),
+                        batch_size as u32,
+                        birthday,
+                    )?);
+                }
+                for (index, (script, utxo_set)) in batch {
+                    if utxo_set.is_empty() {
+                        continue;
+                    }
+                    count += utxo_set.len();
+
+                    let derive_term = format!("{}/{}", case, index);
+                    let label = meta.label(&PubkeyScript::from(script.clone()));
+                    let address =
+                        AddressCompat::from_script(&script.clone().into(), network.into());
+                    match &address {
+                        _ if !self.format.is_plain() => {}
+                        Some(address) => println!(
+                            "\n  {} address {}: {}",
+                            derive_term.bright_white(),
+                            address.to_string().bright_white(),
+                            label.unwrap_or_default().bright_cyan()
+                        ),
+                        None => println!(
+                            "\n  {} no-address script {}: {}",
+                            derive_term.bright_white(),
+                            script,
+                            label.unwrap_or_default().bright_cyan()
+                        ),
+                    }
+
+                    for utxo in utxo_set {
+                        let frozen = meta.is_frozen(utxo.outpoint());
+                        if self.format.is_plain() {
+                            println!(
+                                "{:>18} @ {} - {} {}",
+                                self.unit
+                                    .amount(utxo.amount().to_sat())
+                                    .to_string()
+                                    .bright_yellow(),
+                                utxo.outpoint(),
+                                utxo.mined(),
+                                if frozen { "frozen" } else { "" }.bright_blue()
+                            );
+                        }
+                        utxos.push(UtxoReport {
+                            generation,
+                            terminal: derive_term.clone(),
+                            address: address.as_ref().map(ToString::to_string),
+                            script_pubkey: script.as_bytes().to_hex(),
+                            outpoint: *utxo.outpoint(),
+                            amount: utxo.amount().to_sat(),
+                            height: UtxoReport::mined_height(utxo.mined()),
+                            frozen,
+                            label: label.map(str::to_owned),
+                        });
+                        addr_total += utxo.amount().to_sat();
+                    }
+                }
+
+                offset += batch_size;
+                total += addr_total;
+
+                if count == 0 {
+                    eprintln!("empty");
+                }
+                if last_count == 0 && count == 0 {
+                    break;
+                }
+                last_count = count;
+            }
+        }
+
+        Ok(total)
+    }
+
+    fn watch(
+        &self,
+        path: &Path,
+        account: Option<&str>,
+        gap_limit: u32,
+        interval: u64,
+        hook: Option<&str>,
+        notify: &WatchNotify,
+    ) -> Result<(), Error> {
+        let notifier = match (&notify.notify_secret, notify.notify.is_empty()) {
+            (_, true) => None,
+            (None, false) => return Err(Error::NotifySecretRequired),
+            (Some(secret), false) => {
+                let mut agent = ureq::AgentBuilder::new();
+                if let Some(proxy) = self.proxy() {
+                    let proxy = ureq::Proxy::new(format!("socks5://{}", proxy))
+                        .map_err(|err| Error::Notify(err.to_string()))?;
+                    agent = agent.proxy(proxy);
+                }
+                Some(Notifier::with(notify.notify.clone(), secret, agent.build()))
+            }
+        };
+        let mut confirmations = ConfirmationTracker::with(notify.confirmations.iter().copied());
+
+        let wallet = WalletFile::load(path, account)?;
+        let network = self.wallet_network(&wallet.descriptor)?;
+        let secp = Secp256k1::verification_only();
+
+        // Separate change descriptor is used only for its change keychain
+        let mut keychains = vec![];
+        let descriptors = iter::once((&wallet.descriptor, false)).chain(
+            wallet
+                .change_descriptor
+                .iter()
+                .map(|descriptor| (descriptor, true)),
+        );
+        for (descriptor, change_only) in descriptors {
+            match descriptor.derive_pattern_len()? {
+                1 => keychains.push((descriptor, vec![])),
+                2 if change_only => keychains.push((descriptor, vec![UnhardenedIndex::one()])),
+                2 => {
+                    keychains.push((descriptor, vec![UnhardenedIndex::zero()]));
+                    keychains.push((descriptor, vec![UnhardenedIndex::one()]));
+                }
+                _ => return Err(Error::DescriptorDerivePattern),
+            }
+        }
+        let script = |keychain: usize, index: UnhardenedIndex| {
+            let (descriptor, prefix) = &keychains[keychain];
+            let mut pat = prefix.clone();
+            pat.push(index);
+            descriptor.script_pubkey_pretr(&secp, pat)
+        };
+        let terminal = |keychain: usize, index: UnhardenedIndex| {
+            keychains[keychain]
+                .1
+                .iter()
+                .chain(iter::once(&index))
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+        let address = |keychain: usize, index: UnhardenedIndex| {
+            let script = script(keychain, index).ok()?;
+            AddressCompat::from_script(&script.into(), network.into()).map(|a| a.to_string())
+        };
+
+        let client = self.electrum_client(network)?;
+        let mut watcher = ScriptWatcher::with(&client, keychains.len(), gap_limit, &script)?;
+        eprintln!(
+            "Watching {} addresses with {} known transactions; press Ctrl-C to stop\n",
+            watcher.script_count().to_string().bright_white(),
+            watcher.transactions().len().to_string().bright_white()
+        );
+        // Transactions confirmed before the watch has started are not reported
+        let tip = client.block_headers_subscribe()?.height as u64;
+        confirmations.update(watcher.transactions(), tip);
+
+        loop {
+            let mut reports = vec![];
+            for event in watcher.poll()? {
+                let mut report = WatchReport {
+                    event: s!(""),
+                    txid: None,
+                    status: None,
+                    confirmations: None,
+                    terminal: None,
+                    address: None,
+                    message: event.to_string(),
+                };
+                if event.is_reorg() {
+                    report.event = s!("reorg");
+                }
+                match event {
+                    WatchEvent::NewTx {
+                        keychain,
+                        index,
+                        txid,
+                        status,
+                    } => {
+                        let mined = matches!(status, MiningStatus::Blockchain(_));
+                        report.event = if mined && self.is_outgoing(&client, &watcher, txid)? {
+                            s!("outgoing_confirmed")
+                        } else {
+                            s!("new_tx")
+                        };
+                        report.txid = Some(txid);
+                        report.status = Some(status);
+                        report.terminal = Some(terminal(keychain, index));
+                        report.address = address(keychain, index);
+                    }
+                    WatchEvent::StatusChange { txid, from, to } => {
+                        let mined = !matches!(from, MiningStatus::Blockchain(_))
+                            && matches!(to, MiningStatus::Blockchain(_));
+                        if mined && self.is_outgoing(&client, &watcher, txid)? {
+                            report.event = s!("outgoing_confirmed");
+                        } else if report.event.is_empty() {
+                            report.event = s!("status_change");
+                        }
+                        report.txid = Some(txid);
+                        report.status = Some(to);
+                    }
+                    WatchEvent::Extended { .. } => report.event = s!("extended"),
+                }
+                reports.push(report);
+            }
+
+            let tip = client.block_headers_subscribe()?.height as u64;
+            for (txid, count) in confirmations.update(watcher.transactions(), tip) {
+                reports.push(WatchReport {
+                    event: s!("confirmed"),
+                    txid: Some(txid),
+                    status: watcher.transactions().get(&txid).copied(),
+                    confirmations: Some(count),
+                    terminal: None,
+                    address: None,
+                    message: format!("transaction {} reached {} confirmation(s)", txid, count),
+                });
+            }
+
+            for report in reports {
+                // Events are streamed as JSON lines or as YAML documents
+                if self.format == OutputFormat::Json {
+                    println!("{}", serde_json::to_string(&report)?);
+                } else if self.format == OutputFormat::Yaml {
+                    print!("---\n{}", serde_yaml::to_string(&report)?);
+                } else {
+                    match (&report.address, report.event.as_str()) {
+                        (_, "extended") => eprintln!("{}", report.message.bright_black()),
+                        (Some(address), _) => println!(
+                            "{} ({})",
+                            report.message.bright_yellow(),
+                            address.bright_white()
+                        ),
+                        (None, _) => println!("{}", report.message.bright_yellow()),
+                    }
+                }
+                if let Some(hook) = hook {
+                    self.run_hook(hook, &report);
+                }
+                if let Some(notifier) = &notifier {
+                    for err in notifier.notify(&report) {
+                        eprintln!("{} {}", "Warning:".bright_yellow(), err);
+                    }
+                }
+            }
+            thread::sleep(Duration::from_secs(interval));
+        }
+    }
+
+    /// Detects whether the transaction spends any of the watched wallet
+    /// outputs
+    fn is_outgoing<F>(
+        &self,
+        client: &electrum::Client,
+        watcher: &ScriptWatcher<electrum::Client, F>,
+        txid: Txid,
+    ) -> Result<bool, Error>
+    where
+        F: FnMut(usize, UnhardenedIndex) -> Result<Script, DeriveError>,
+    {
+        let tx = client.transaction_get(&txid)?;
+        for txin in tx.input {
+            let prevout = txin.previous_output;
+            if !watcher.transactions().contains_key(&prevout.txid) {
+                continue;
+            }
+            let prev_tx = client.transaction_get(&prevout.txid)?;
+            let spent = prev_tx
+                .output
+                .get(prevout.vout as usize)
+                .map(|txout| watcher.script_index(&txout.script_pubkey).is_some())
+                .unwrap_or_default();
+            if spent {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn run_hook(&self, hook: &str, report: &WatchReport) {
+        let mut cmd = process::Command::new("sh");
+        cmd.arg("-c")
+            .arg(hook)
+            .env("WATCH_EVENT", &report.event)
+            .env("WATCH_MESSAGE", &report.message);
+        if let Some(txid) = report.txid {
+            cmd.env("WATCH_TXID", txid.to_string());
+        }
+        if let Some(status) = report.status {
+            cmd.env("WATCH_STATUS", status.to_string());
+        }
+        if let Some(terminal) = &report.terminal {
+            cmd.env("WATCH_TERMINAL", terminal);
+        }
+        if let Some(address) = &report.address {
+            cmd.env("WATCH_ADDRESS", address);
+        }
+        if let Some(confirmations) = report.confirmations {
+            cmd.env("WATCH_CONFIRMATIONS", confirmations.to_string());
+        }
+        match cmd.status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!("{} hook exited with {}", "Warning:".bright_yellow(), status),
+            Err(err) => eprintln!("{} unable to run hook: {}", "Warning:".bright_yellow(), err),
+        }
+    }
+
+    fn history(&self) -> Result<(), Error> { todo!() }
+
+    fn label(&self, path: &Path, address: &Address, text: Option<&str>) -> Result<(), Error> {
+        let mut wallet = WalletFile::read(path)?;
+
+        let script_pubkey = PubkeyScript::from_inner(address.script_pubkey());
+        let status = match text {
+            Some(text) => {
+                wallet.meta.set_label(script_pubkey, text);
+                eprintln!("{} {}", "Labeled address".bright_green(), address);
+                StatusReport::changed(format!("labeled address {}", address))
+            }
+            None if wallet.meta.remove_label(&script_pubkey).is_some() => {
+                eprintln!("{} {}", "Removed label from".bright_green(), address);
+                StatusReport::changed(format!("removed label from {}", address))
+            }
+            None => {
+                eprintln!("{} {} has no label", "Warning:".bright_yellow(), address);
+                self.report(&StatusReport::unchanged(format!(
+                    "{} has no label",
+                    address
+                )))?;
+                return Ok(());
+            }
+        };
+
+        wallet.write(path)?;
+        self.report(&status)?;
+        Ok(())
+    }
+
+    fn reserve(
+        &self,
+        path: &Path,
+        protocol: &str,
+        keychain: UnhardenedIndex,
+        range: IndexRange<UnhardenedIndex>,
+    ) -> Result<(), Error> {
+        let mut wallet = WalletFile::read(path)?;
+
+        wallet
+            .meta
+            .reserve_terminals(protocol, keychain, range.clone())?;
+        eprintln!(
+            "{} {}/{:#} for {}",
+            "Reserved".bright_green(),
+            keychain,
+            range,
+            protocol
+        );
+
+        wallet.write(path)?;
+        self.report(&StatusReport::changed(format!(
+            "reserved {}/{:#} for {}",
+            keychain, range, protocol
+        )))?;
+        Ok(())
+    }
+
+    fn release(&self, path: &Path, protocol: &str) -> Result<(), Error> {
+        let mut wallet = WalletFile::read(path)?;
+
+        let status = match wallet.meta.release_terminals(protocol) {
+            0 => {
+                eprintln!(
+                    "{} no terminals are reserved for {}",
+                    "Warning:".bright_yellow(),
+                    protocol
+                );
+                StatusReport::unchanged(format!("no terminals are reserved for {}", protocol))
+            }
+            count => {
+                eprintln!(
+                    "{} {} range(s) reserved for {}",
+                    "Released".bright_green(),
+                    count,
+                    protocol
+                );
+                wallet.write(path)?;
+                StatusReport::changed(format!(
+                    "released {} range(s) reserved for {}",
+                    count, protocol
+                ))
+            }
+        };
+        self.report(&status)?;
+        Ok(())
+    }
+
+    fn supersede(
+        &self,
+        path: &Path,
+        key: Fingerprint,
+        boundary: UnhardenedIndex,
+        account: DerivationAccount,
+    ) -> Result<(), Error> {
+        let mut wallet = WalletFile::read(path)?;
+
+        // Superseded key may be a key of any descriptor generation, or a key
+        // which was already introduced by a previous rotation
+        let mut superseded = None;
+        for (_, descriptor) in wallet.generations().chain(
+            wallet
+                .change_descriptor
+                .as_ref()
+                .map(|descriptor| (0, descriptor)),
+        ) {
+            for (_, version) in wallet.meta.key_rotations.descriptor_versions(descriptor) {
+                version.for_each_key(|pk| {
+                    if pk.account_fingerprint() == key {
+                        superseded = Some(pk.clone());
+                    }
+                    true
+                });
+            }
+        }
+        let superseded = superseded.ok_or(Error::UnknownKey(key))?;
+
+        wallet
+            .meta
+            .key_rotations
+            .supersede(&superseded, boundary, account.clone())?;
+        eprintln!(
+            "{} {} with {} starting from index {}",
+            "Superseded".bright_green(),
+            key,
+            account.account_fingerprint(),
+            boundary
+        );
+
+        wallet.write(path)?;
+        self.report(&StatusReport::changed(format!(
+            "superseded {} with {} starting from index {}",
+            key,
+            account.account_fingerprint(),
+            boundary
+        )))?;
+        Ok(())
+    }
+
+    fn freeze(&self, path: &Path, outpoint: OutPoint, unfreeze: bool) -> Result<(), Error> {
+        let mut wallet = WalletFile::read(path)?;
+
+        if unfreeze && !wallet.meta.unfreeze(&outpoint) {
+            eprintln!("{} {} is not frozen", "Warning:".bright_yellow(), outpoint);
+            self.report(&StatusReport::unchanged(format!(
+                "{} is not frozen",
+                outpoint
+            )))?;
+            return Ok(());
+        } else if !unfreeze && !wallet.meta.freeze(outpoint) {
+            eprintln!(
+                "{} {} is already frozen",
+                "Warning:".bright_yellow(),
+                outpoint
+            );
+            self.report(&StatusReport::unchanged(format!(
+                "{} is already frozen",
+                outpoint
+            )))?;
+            return Ok(());
+        }
+
+        let action = if unfreeze { "Unfrozen" } else { "Frozen" };
+        eprintln!("{} {}", action.bright_green(), outpoint);
+        wallet.write(path)?;
+        self.report(&StatusReport::changed(format!(
+            "{} {}",
+            action.to_lowercase(),
+            outpoint
+        )))?;
+        Ok(())
+    }
+
+    fn address_info(&self, address: &Address) -> Result<(), Error> {
+        let class = address.address_class();
+        let script_pubkey = address.script_pubkey();
+        let descriptor = address.to_script_pubkey_descr();
+        if self.report(&AddressInfoReport {
+            address: address.to_string(),
+            class: class.to_string(),
+            network: address.address_network(),
+            witness_version: class.witness_version().map(|version| version.to_num()),
+            script_pubkey: format!("{:x}", script_pubkey),
+            descriptor: descriptor.as_ref().ok().map(ScriptPubkeyDescr::to_string),
+            burn: address.is_burn(),
+        })? {
+            return Ok(());
+        }
+
+        println!();
+        println!("{:-15} {}", "Address:", address);
+        println!("{:-15} {}", "Class:", class);
+        println!("{:-15} {}", "Network:", address.address_network());
+        if let Some(version) = class.witness_version() {
+            println!("{:-15} {}", "SegWit version:", version);
+        }
+        println!("{:-15} {:x}", "scriptPubkey:", script_pubkey);
+        println!("{:-15} {}", "", script_pubkey.asm());
+        match descriptor {
+            Ok(descriptor) => println!("{:-15} {}", "Descriptor:", descriptor),
+            Err(err) => eprintln!("{:-15} {}", "Descriptor:", err.to_string().bright_red()),
+        }
+        if class.is_future() {
+            println!(
+                "{}",
+                "Witness version has no consensus meaning yet; funds sent to the address can be \
+                 spent by anyone"
+                    .bright_yellow()
+            );
+        }
+        if address.is_burn() {
+            println!(
+                "{}",
+                "Address is a known burn address, funds sent to it are lost".bright_red()
+            );
+        }
+        println!();
+        Ok(())
+    }
+
+    fn info(&self, data: &str) -> Result<(), Error> {
+        let xpub = ExtendedPubKey::from_slip132_str(data)?;
+        if !self.format.is_plain() {
+            let version = KeyVersion::from_xkey_str(data).ok();
+            let derivation = version.as_ref().and_then(|ver| {
+                DefaultResolver::derivation_path(ver, None).or_else(|| {
+                    DefaultResolver::derivation_path(ver, Some(ChildNumber::Hardened { index: 0 }))
+                })
+            });
+            let variants = [bitcoin::Network::Bitcoin, bitcoin::Network::Testnet]
+                .into_iter()
+                .flat_map(|network| {
+                    KeyApplication::ALL.map(|app| xpub.to_slip132_string(app, network))
+                })
+                .collect();
+            self.report(&XpubReport {
+                fingerprint: xpub.fingerprint().to_string(),
+                identifier: xpub.identifier().to_string(),
+                network: xpub.network,
+                public_key: xpub.public_key.to_string(),
+                chain_code: xpub.chain_code.to_string(),
+                application: version
+                    .as_ref()
+                    .and_then(DefaultResolver::application)
+                    .map(|application| application.to_string()),
+                derivation: derivation.map(|path| path.to_string()),
+                depth: xpub.depth,
+                child_number: format!("{:#}", xpub.child_number),
+                variants,
+            })?;
+            return Ok(());
+        }
+
+        println!();
+        println!("{:-13} {}", "Fingerprint:", xpub.fingerprint());
+        println!("{:-13} {}", "Identifier:", xpub.identifier());
+        println!("{:-13} {}", "Network:", xpub.network);
+        println!("{:-13} {}", "Public key:", xpub.public_key);
+        println!("{:-13} {}", "Chain code:", xpub.chain_code);
+        match KeyVersion::from_xkey_str(data) {
+            Ok(ver) => {
+                if let Some(application) = DefaultResolver::application(&ver) {
+                    println!("{:-13} {}", "Application:", application);
+                }
+                if let Some(derivation_path) = DefaultResolver::derivation_path(&ver, None) {
+                    println!("{:-13} {}", "Derivation:", derivation_path);
+                } else if let Some(derivation_path) =
+                    DefaultResolver::derivation_path(&ver, Some(ChildNumber::Hardened { index: 0 }))
+                {
+                    println!("{:-13} {}  # (account 0)", "Derivation:", derivation_path);
+                }
+            }
+            Err(err) => eprintln!(
+                "{:-13} {} {}",
+                "Application:",
+                "unable to read SLIP-132 information.".bright_red(),
+                err
+            ),
+        }
+        println!("{:-13} {}", "Depth:", xpub.depth);
+        println!("{:-13} {:#}", "Child number:", xpub.child_number);
+        println!("Variants:");
+        for network in [bitcoin::Network::Bitcoin, bitcoin::Network::Testnet] {
+            for app in KeyApplication::ALL {
+                println!("  - {}", xpub.to_slip132_string(app, network));
+            }
+        }
+        println!();
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn construct(
+        &self,
+        wallet_path: &Path,
+        account: Option<&str>,
+        lock_time: LockTime,
+        inputs: &[InputArg],
+        look_ahead: u32,
+        exclude_frozen: bool,
+        replay_guard: bool,
+        fork_heights: &[u32],
+        outputs: &[AddressAmount],
+        payments: &[PaymentInstruction],
+        spend_policy: &construct::SpendPolicy,
+        change_index: UnhardenedIndex,
+        proprietary_keys: &[ProprietaryKeyDescriptor],
+        fee: u64,
+        psbt_path: &Path,
+    ) -> Result<(), Error> {
+        let mut outputs = outputs.to_vec();
+        for payment in payments {
+            outputs.push(self.payment_output(payment)?);
+        }
+        let outputs = outputs.as_slice();
+
+        let wallet = WalletFile::load(wallet_path, account)?;
+        let change_descriptor = wallet.change_descriptor().clone();
+        let WalletFile {
+            descriptor, meta, ..
+        } = wallet;
+
+        if let Some(protocol) = meta.terminal_reservation(UnhardenedIndex::one(), change_index) {
+            return Err(Error::ReservedChangeIndex(
+                change_index,
+                protocol.to_owned(),
+            ));
+        }
+
+        let inputs = inputs
+            .iter()
+            .filter(|input| {
+                if !meta.is_frozen(&input.outpoint()) {
+                    return true;
+                }
+                if exclude_frozen {
+                    eprintln!(
+                        "{} frozen UTXO {}",
+                        "Skipping".bright_yellow(),
+                        input.outpoint()
+                    );
+                    false
+                } else {
+                    eprintln!(
+                        "{} spending frozen UTXO {}",
+                        "Warning:".bright_yellow(),
+                        input.outpoint()
+                    );
+                    true
+                }
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let network = self.wallet_network(&descriptor)?;
+        let electrum = self.electrum_config(network)?;
+        let client = electrum.connect()?;
+
+        if self.format.is_plain() {
+            println!(
+                "{}\n{}\n",
+                "\nWallet descriptor:".bright_white(),
+                descriptor
+            );
+        }
+
+        eprint!(
+            "Re-scanning network {} using {} ... ",
+            network.to_string().yellow(),
+            electrum.to_string().yellow()
+        );
+
+        let txid_set: BTreeSet<_> = inputs.iter().map(|input| input.outpoint().txid).collect();
+        let tx_map = client
+            .batch_transaction_get(&txid_set)?
+            .into_iter()
+            .map(|tx| (tx.txid(), tx))
+            .collect::<BTreeMap<_, _>>();
+
+        eprintln!("{}", "done\n".green());
+
+        // Inputs are discovered with all descriptor versions produced by account key
+        // rotations; errors are reported for the original descriptor
+        let secp = Secp256k1::verification_only();
+        let versions = meta.key_rotations.descriptor_versions(&descriptor);
+        let mut indexes = versions
+            .iter()
+            .map(|_| DerivationIndexMap::new())
+            .collect::<Vec<_>>();
+        let inputs = inputs
+            .into_iter()
+            .map(|input| match input {
+                InputArg::Descriptor(input) => Ok(input),
+                InputArg::Outpoint(outpoint) => {
+                    let mut result = None;
+                    for ((_, version), index) in versions.iter().zip(&mut indexes) {
+                        let attempt = InputDescriptor::resolve_from_outpoint(
+                            &secp,
+                            version,
+                            outpoint,
+                            |outpoint| {
+                                tx_map
+                                    .get(&outpoint.txid)
+                                    .and_then(|tx| tx.output.get(outpoint.vout as usize))
+                                    .map(|txout| txout.script_pubkey.clone())
+                            },
+                            index,
+                            look_ahead,
+                        );
+                        let found = attempt.is_ok();
+                        if result.is_none() || found {
+                            result = Some(attempt);
+                        }
+                        if found {
+                            break;
+                        }
+                    }
+                    let input = result.expect("descriptor always has a version")?;
+                    eprintln!("{} input {}", "Discovered".bright_green(), input);
+                    Ok(input)
+                }
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let outputs = outputs
+            .iter()
+            .map(|a| {
+                (
+                    PubkeyScript::from_inner(a.address.script_pubkey()),
+                    a.amount,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut psbt = Psbt::construct_with_rotations(
+            &descriptor,
+            &change_descriptor,
+            &meta.key_rotations,
+            &inputs,
+            iter::empty(),
+            &outputs,
+            change_index,
+            fee,
+            &tx_map,
+        )?;
+        psbt.fallback_locktime = Some(lock_time);
+
+        let violations = psbt.spend_policy_violations(spend_policy, &[
+            descriptor.clone(),
+            change_descriptor.clone(),
+        ]);
+        if !violations.is_empty() {
+            return Err(VecDisplay::from(violations).into());
+        }
+
+        let guard = if replay_guard {
+            let tip = client.block_headers_subscribe()?;
+            Some(construct::ReplayGuard {
+                height: tip.height as u32,
+                block_hash: tip.header.block_hash(),
+            })
+        } else {
+            None
+        };
+        let replay_policy = construct::ReplayPolicy {
+            guard,
+            fork_heights: fork_heights.to_vec(),
+        };
+        for warning in psbt.apply_replay_policy(&replay_policy) {
+            eprintln!("{} {}", "Warning:".bright_yellow(), warning);
+        }
+
+        for key in proprietary_keys {
+            psbt.insert_proprietary(key)?;
+        }
+
+        fs::write(psbt_path, psbt.serialize())?;
+
+        if !self.report(&PsbtReport::with(&psbt, Some(psbt_path.to_owned())))? {
+            println!("{} {}\n", "PSBT:".bright_white(), psbt);
+            self.print_fee_analysis(&psbt);
+            println!();
+        }
+
+        Ok(())
+    }
+
+    fn migrate(
+        &self,
+        wallet_path: &Path,
+        generation: Option<usize>,
+        look_ahead: u16,
+        change_index: UnhardenedIndex,
+        fee: u64,
+        psbt_path: &Path,
+    ) -> Result<(), Error> {
+        let wallet = WalletFile::read(wallet_path)?;
+
+        if let Some(protocol) = wallet
+            .meta
+            .terminal_reservation(UnhardenedIndex::one(), change_index)
+        {
+            return Err(Error::ReservedChangeIndex(
+                change_index,
+                protocol.to_owned(),
+            ));
+        }
+
+        let generations = wallet.previous_generations(generation)?;
+
+        let network = self.wallet_network(&wallet.descriptor)?;
+        let client = self.electrum_client(network)?;
+        let birthday = wallet.birthday_height(&client)?;
+
+        let mut source = None;
+        for (no, descriptor) in generations {
+            eprint!("Scanning descriptor generation {} ... ", no);
+            let inputs = self
+                .descriptor_utxos(&client, descriptor, &wallet.meta, look_ahead, birthday)?
+                .into_iter()
+                .map(|(input, _)| input)
+                .collect::<Vec<_>>();
+            if inputs.is_empty() {
+                eprintln!("{}", "empty".yellow());
+                continue;
+            }
+            eprintln!("{}", "done".green());
+            source = Some((no, descriptor, inputs));
+            break;
+        }
+        let (no, descriptor, inputs) = match source {
+            Some(source) => source,
+            None => {
+                eprintln!("No funds to migrate\n");
+                self.report(&StatusReport::unchanged("no funds to migrate"))?;
+                return Ok(());
+            }
+        };
+
+        let txid_set: BTreeSet<_> = inputs.iter().map(|input| input.outpoint.txid).collect();
+        let tx_map = client
+            .batch_transaction_get(&txid_set)?
+            .into_iter()
+            .map(|tx| (tx.txid(), tx))
+            .collect::<BTreeMap<_, _>>();
+
+        let outputs: [(PubkeyScript, u64); 0] = [];
+        let psbt = Psbt::construct_with_rotations(
+            descriptor,
+            wallet.change_descriptor(),
+            &wallet.meta.key_rotations,
+            &inputs,
+            iter::empty(),
+            &outputs,
+            change_index,
+            fee,
+            &tx_map,
+        )?;
+
+        fs::write(psbt_path, psbt.serialize())?;
+
+        if self.report(&PsbtReport::with(&psbt, Some(psbt_path.to_owned())))? {
+            return Ok(());
+        }
+        println!(
+            "{} {} UTXOs from descriptor generation {} ({}) to the current wallet descriptor \
+             ({})\n",
+            "Migrating".bright_green(),
+            inputs.len(),
+            no,
+            DescriptorClass::from(descriptor.desc_type()),
+            DescriptorClass::from(wallet.change_descriptor().desc_type())
+        );
+        println!("{} {}\n", "PSBT:".bright_white(), psbt);
+
+        Ok(())
+    }
+
+    fn sweep_inheritance(
+        &self,
+        wallet_path: &Path,
+        account: Option<&str>,
+        look_ahead: u16,
+        address: &Address,
+        fee: u64,
+        psbt_path: &Path,
+    ) -> Result<(), Error> {
+        let wallet = WalletFile::load(wallet_path, account)?;
+        let inheritance =
+            Inheritance::from_descriptor(&wallet.descriptor).ok_or(Error::NotInheritance)?;
+        let network = self.wallet_network(&wallet.descriptor)?;
+        let client = self.electrum_client(network)?;
+        let birthday = wallet.birthday_height(&client)?;
+
+        eprint!("Scanning wallet descriptor ... ");
+        let utxos = self.descriptor_utxos(
+            &client,
+            &wallet.descriptor,
+            &wallet.meta,
+            look_ahead,
+            birthday,
+        )?;
+        eprintln!("{}", "done".green());
+
+        let tip = client.resolve_chain_tip()?;
+        let mut inputs = vec![];
+        let mut amount = 0u64;
+        let mut earliest = None;
+        for (mut input, utxo) in utxos {
+            let height = match utxo.mined() {
+                MiningStatus::Blockchain(height) => *height as u32,
+                _ => {
+                    eprintln!(
+                        "{} unconfirmed UTXO {}",
+                        "Skipping".bright_yellow(),
+                        utxo.outpoint()
+                    );
+                    continue;
+                }
+            };
+            let anchor = client.resolve_anchor(height)?;
+            let satisfaction = inheritance.heir_satisfaction(anchor);
+            if !satisfaction.maturity.is_reached(tip) {
+                eprintln!(
+                    "{} UTXO {} timelocked for {} more blocks",
+                    "Skipping".bright_yellow(),
+                    utxo.outpoint(),
+                    inheritance.heir_blocks_left(anchor, tip)
+                );
+                let height = satisfaction.maturity.height;
+                earliest = Some(earliest.map_or(height, |earliest: u32| earliest.min(height)));
+                continue;
+            }
+            input.apply_satisfaction(&satisfaction);
+            amount += utxo.amount().to_sat();
+            inputs.push(input);
+        }
+        match (inputs.is_empty(), earliest) {
+            (true, Some(height)) => return Err(Error::HeirTimelocked(height)),
+            (true, None) => return Err(Error::NoHeirUtxos),
+            _ => {}
+        }
+        if amount <= fee {
+            return Err(Error::SweepFeeTooHigh(amount, fee));
+        }
+
+        let txid_set: BTreeSet<_> = inputs.iter().map(|input| input.outpoint.txid).collect();
+        let tx_map = client
+            .batch_transaction_get(&txid_set)?
+            .into_iter()
+            .map(|tx| (tx.txid(), tx))
+            .collect::<BTreeMap<_, _>>();
+
+        let outputs = [(PubkeyScript::from(address.script_pubkey()), amount - fee)];
+        let psbt = Psbt::construct(
+            &wallet.descriptor,
+            &inputs,
+            &outputs,
+            UnhardenedIndex::zero(),
+            fee,
+            &tx_map,
+        )?;
+
+        fs::write(psbt_path, psbt.serialize())?;
+
+        if self.report(&PsbtReport::with(&psbt, Some(psbt_path.to_owned())))? {
+            return Ok(());
         }
+        println!(
+            "{} {} from {} UTXOs to {} with the heir key\n",
+            "Sweeping".bright_green(),
+            self.unit.amount(amount - fee),
+            inputs.len(),
+            address
+        );
+        println!("{} {}\n", "PSBT:".bright_white(), psbt);
 
-        let accounts = account_file
-            .and_then(AccountIndex::read_file)
-            .unwrap_or_default();
+        Ok(())
+    }
 
-        let descriptor_str =
-            fs::read_to_string(descriptor_file)?.replace(['\n', '\r', ' ', '\t'], "");
+    fn prove_reserves(
+        &self,
+        wallet_path: &Path,
+        account: Option<&str>,
+        look_ahead: u16,
+        message: &str,
+        psbt_path: &Path,
+    ) -> Result<(), Error> {
+        let wallet = WalletFile::load(wallet_path, account)?;
+        let network = self.wallet_network(&wallet.descriptor)?;
+        let client = self.electrum_client(network)?;
+        let birthday = wallet.birthday_height(&client)?;
+
+        eprint!("Scanning wallet descriptor ... ");
+        let mut utxos = self.descriptor_utxos(
+            &client,
+            &wallet.descriptor,
+            &wallet.meta,
+            look_ahead,
+            birthday,
+        )?;
+        if let Some(change_descriptor) = &wallet.change_descriptor {
+            utxos.extend(self.descriptor_utxos(
+                &client,
+                change_descriptor,
+                &wallet.meta,
+                look_ahead,
+                birthday,
+            )?);
+        }
+        let inputs = utxos
+            .into_iter()
+            .map(|(input, _)| input)
+            .collect::<Vec<_>>();
+        eprintln!("{}", "done".green());
+
+        let txid_set: BTreeSet<_> = inputs.iter().map(|input| input.outpoint.txid).collect();
+        let tx_map = client
+            .batch_transaction_get(&txid_set)?
+            .into_iter()
+            .map(|tx| (tx.txid(), tx))
+            .collect::<BTreeMap<_, _>>();
+
+        let psbt = Psbt::construct_reserves(
+            &wallet.descriptor,
+            wallet.change_descriptor(),
+            message,
+            &inputs,
+            &tx_map,
+        )?;
+
+        fs::write(psbt_path, psbt.serialize())?;
+
+        if self.report(&PsbtReport::with(&psbt, Some(psbt_path.to_owned())))? {
+            return Ok(());
+        }
         println!(
-            "Creating wallet for descriptor:\n{}",
-            descriptor_str.bright_white()
+            "{} of {} in {} UTXOs committing to message \"{}\"\n",
+            "Proving reserves".bright_green(),
+            self.unit.amount(psbt.outputs[0].amount),
+            inputs.len(),
+            message
         );
-        let descriptor = miniscript::Descriptor::<DerivationRef>::from_str(&descriptor_str)?;
-        let descriptor = descriptor.translate_pk(&mut DerivationRefTranslator {
-            account_file,
-            accounts: &accounts,
-        })?;
+        println!("{} {}\n", "PSBT:".bright_white(), psbt);
+
+        Ok(())
+    }
+
+    fn verify_reserves(
+        &self,
+        psbt_path: &Path,
+        message: &str,
+        height: Option<u32>,
+    ) -> Result<(), Error> {
+        let secp = Secp256k1::verification_only();
+
+        let data = fs::read(psbt_path)?;
+        let psbt = Psbt::decode_any(&data)?;
+
+        let network = self.network.map(Network::from).unwrap_or(Network::Bitcoin);
+        let client = self.electrum_client(network)?;
+        let height = match height {
+            Some(height) => height,
+            None => client.block_headers_subscribe()?.height as u32,
+        };
 
-        fs::write(path, descriptor.to_string())?;
+        let amount = psbt.verify_reserves(&secp, message, height, &client)?;
+        let reserves = psbt
+            .inputs
+            .iter()
+            .skip(1)
+            .map(|input| input.previous_outpoint)
+            .collect::<Vec<_>>();
 
+        if self.report(&ReservesReport {
+            message: message.to_owned(),
+            height,
+            reserves: reserves.clone(),
+            amount,
+        })? {
+            return Ok(());
+        }
         println!(
-            "{} in `{}`\n",
-            "Wallet created".bright_green(),
-            path.display()
+            "\n{} of {} in {} UTXOs unspent at height {}\n",
+            "Verified reserves".bright_green(),
+            self.unit.amount(amount),
+            reserves.len(),
+            height
         );
+        for outpoint in reserves {
+            println!("\t{}", outpoint);
+        }
+        println!();
 
         Ok(())
     }
 
-    fn address(
+    fn payjoin_send(
         &self,
-        path: &Path,
-        count: u16,
-        skip: u16,
-        show_change: bool,
-        regtest: bool,
+        endpoint: &str,
+        address: &Address,
+        params: PayjoinParams,
+        psbt_path: &Path,
+        proposal_path: &Path,
     ) -> Result<(), Error> {
+        if !is_secure_endpoint(endpoint) {
+            return Err(Error::InsecurePayjoinEndpoint(endpoint.to_owned()));
+        }
+
         let secp = Secp256k1::new();
 
-        let descriptor_str = fs::read_to_string(path)?;
-        let descriptor: miniscript::Descriptor<DerivationAccount> =
-            miniscript::Descriptor::from_str(&descriptor_str)?;
+        let data = fs::read(psbt_path)?;
+        let signed = consensus::encode::deserialize::<PartiallySignedTransaction>(&data)
+            .map_err(Error::psbt_from_consensus)?;
+
+        let mut finalized = signed.clone();
+        let mut errors = vec![];
+        for index in 0..finalized.inputs.len() {
+            let input = &finalized.inputs[index];
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                continue;
+            }
+            if let Err(err) = finalized.finalize_inp_mut(&secp, index) {
+                errors.push(err);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(VecDisplay::from(errors).into());
+        }
+
+        let payee = PubkeyScript::from(address.script_pubkey());
+        let original = Psbt::from(finalized);
+        let input_type = original.check_payjoin_original(&payee, &params)?;
+
+        let separator = if endpoint.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}{}", endpoint, separator, params.to_query());
+        let mut agent = ureq::AgentBuilder::new();
+        if let Some(proxy) = self.proxy() {
+            let proxy = ureq::Proxy::new(format!("socks5://{}", proxy))
+                .map_err(|err| Error::PayjoinRequest(Box::new(err)))?;
+            agent = agent.proxy(proxy);
+        }
+        eprint!(
+            "Sending original PSBT ({} inputs) to {} ... ",
+            input_type, endpoint
+        );
+        let response = agent
+            .build()
+            .post(&url)
+            .set("Content-Type", "text/plain")
+            .send_string(&original.to_base64())
+            .map_err(|err| Error::PayjoinRequest(Box::new(err)))?
+            .into_string()?;
+        eprintln!("{}", "done".green());
+
+        // Finalization removes key origins, which are required to detect
+        // receiver inputs owned by the wallet
+        let mut reference = original.clone();
+        for (input, signed) in reference.inputs.iter_mut().zip(&signed.inputs) {
+            input.bip32_derivation = signed.bip32_derivation.clone();
+            input.tap_key_origins = signed.tap_key_origins.clone();
+        }
+
+        let proposal = Psbt::from_str(response.trim())?;
+        let contribution = reference.validate_payjoin_proposal(&proposal, &payee, &params)?;
+        let proposal = Psbt::from(signed).merge_payjoin_proposal(&proposal);
+
+        fs::write(proposal_path, proposal.serialize())?;
 
+        if self.report(&PsbtReport::with(&proposal, Some(proposal_path.to_owned())))? {
+            return Ok(());
+        }
         println!(
-            "{}\n{}\n",
-            "\nWallet descriptor:".bright_white(),
-            descriptor.to_string_std(self.bitcoin_core_fmt)
+            "{} with {} receiver inputs; fee contribution {}\n",
+            "Payjoin proposal validated".bright_green(),
+            proposal.inputs.len() - original.inputs.len(),
+            self.unit.amount(contribution)
         );
+        println!("{} {}\n", "PSBT:".bright_white(), proposal);
 
-        if descriptor.derive_pattern_len()? != 2 {
-            return Err(Error::DescriptorDerivePattern);
+        Ok(())
+    }
+
+    fn finalize(
+        &self,
+        psbt_path: &Path,
+        tx_path: Option<&PathBuf>,
+        publish: Option<Network>,
+    ) -> Result<(), Error> {
+        let secp = Secp256k1::new();
+
+        let data = fs::read(psbt_path)?;
+        let mut psbt = consensus::encode::deserialize::<PartiallySignedTransaction>(&data)
+            .map_err(Error::psbt_from_consensus)?;
+
+        let mut errors = vec![];
+        for index in 0..psbt.inputs.len() {
+            let input = &psbt.inputs[index];
+            // Inputs satisfied by external systems are already final
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                continue;
+            }
+            if let Err(err) = psbt.finalize_inp_mut(&secp, index) {
+                errors.push(err);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(VecDisplay::from(errors).into());
+        }
+
+        let tx = psbt.extract_tx();
+
+        if let Some(tx_path) = tx_path {
+            let mut file = fs::File::create(tx_path)?;
+            tx.consensus_encode(&mut file)?;
+        } else if self.format.is_plain() {
+            println!("{}\n", tx.serialize().to_hex());
+        }
+
+        if let Some(network) = publish {
+            let broadcaster = self.broadcaster(network)?;
+            broadcaster.broadcast_tx(&tx)?;
+            eprintln!(
+                "{} {} {}\n",
+                "Transaction".bright_yellow(),
+                tx.txid().to_string().yellow(),
+                "published".bright_yellow()
+            );
+        }
+
+        self.report(&TxReport::with(&tx, tx_path.cloned(), publish))?;
+        Ok(())
+    }
+
+    fn inspect(&self, path: Option<&PathBuf>, explain: bool, key_only: bool) -> Result<(), Error> {
+        let psbt = if let Some(path) = path {
+            let data = fs::read(path)?;
+            Psbt::decode_any(&data)?
+        } else {
+            eprint!("Type in hex or Base64 encoded PSBT and press enter: ");
+            stdout().flush()?;
+            let stdin = stdin();
+            let psbt_str = stdin.lock().lines().next().expect("no PSBT data")?;
+            Psbt::decode_any(psbt_str.as_bytes())?
+        };
+        if key_only {
+            let secp = Secp256k1::verification_only();
+            for output in &psbt.outputs {
+                let internal_key = match output.tap_internal_key {
+                    Some(internal_key) => internal_key,
+                    None => continue,
+                };
+                if output.tap_tree.is_some()
+                    || !is_key_only_output(&secp, internal_key, output.script.as_inner())
+                {
+                    eprintln!(
+                        "{} taproot output #{} commits to a script tree, while the wallet is \
+                         key-spend only",
+                        "Warning:".bright_yellow(),
+                        output.index
+                    );
+                }
+            }
+        }
+        if self.report(&psbt)? {
+            return Ok(());
+        }
+        println!("\n{}", serde_yaml::to_string(&psbt)?);
+        let output_total = psbt.outputs.iter().map(|output| output.amount).sum::<u64>();
+        println!(
+            "{:<14} {}",
+            "Total output:".bright_white(),
+            self.unit.amount(output_total)
+        );
+        if let Err(err) = psbt.fee() {
+            println!("{:<14} {}", "Fee:".bright_white(), err);
+        }
+        self.print_fee_analysis(&psbt);
+        println!();
+        if explain {
+            for index in 0..psbt.inputs.len() {
+                println!("{} #{}:", "Input".bright_white(), index);
+                match psbt.explain_input(index) {
+                    Ok(trace) => println!("{}\n", trace),
+                    Err(err) => println!("{} {}\n", "Error:".bright_red(), err),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn inspect_tx(&self, path: Option<&PathBuf>) -> Result<(), Error> {
+        let data = if let Some(path) = path {
+            fs::read(path)?
+        } else {
+            eprint!("Type in hex encoded transaction and press enter: ");
+            stdout().flush()?;
+            let stdin = stdin();
+            stdin
+                .lock()
+                .lines()
+                .next()
+                .expect("no transaction data")?
+                .into_bytes()
+        };
+        let data = match std::str::from_utf8(&data) {
+            Ok(s) => Vec::<u8>::from_hex(s.trim()).map_err(Error::TxHex)?,
+            Err(_) => data,
+        };
+        let tx: Transaction = consensus::deserialize(&data)?;
+        let report = TxInspectReport::with(&tx);
+        if self.report(&report)? {
+            return Ok(());
+        }
+
+        println!(
+            "\n{} {}, version {}, lock time {}, {} vbytes\n",
+            "Transaction".bright_white(),
+            report.txid,
+            report.version,
+            report.lock_time,
+            tx.vsize()
+        );
+        for (index, input) in report.inputs.iter().enumerate() {
+            println!(
+                "{} #{} spending {} {}",
+                "Input".bright_white(),
+                index,
+                input.prev_output,
+                format!("[{}]", input.descr_type.as_deref().unwrap_or("unknown")).bright_green()
+            );
+            println!("  {:<13} {}", "sequence".dimmed(), input.sequence);
+            println!("  {:<13} {}", "scriptSig".dimmed(), input.script_sig);
+            for element in &input.witness {
+                println!("  {:<13} {}", "witness".dimmed(), element);
+            }
+            if let Some(ref script) = input.revealed_script {
+                println!("  {:<13} {}", "script".dimmed(), script);
+            }
+        }
+        println!();
+        for (index, output) in report.outputs.iter().enumerate() {
+            println!(
+                "{} #{} {} {}",
+                "Output".bright_white(),
+                index,
+                self.unit.amount(output.value),
+                format!("[{}]", output.descr_type.as_deref().unwrap_or("unknown")).bright_green()
+            );
+            println!("  {:<13} {}", "scriptPubkey".dimmed(), output.script_pubkey);
+        }
+        println!();
+        for anomaly in &report.anomalies {
+            eprintln!("{} {}", "Warning:".bright_yellow(), anomaly);
+        }
+        Ok(())
+    }
+
+    fn audit(&self, path: &Path) -> Result<(), Error> {
+        let secp = Secp256k1::verification_only();
+
+        let data = fs::read(path)?;
+        let psbt = Psbt::decode_any(&data)?;
+        let reports = psbt
+            .verify_signatures(&secp)
+            .into_iter()
+            .flat_map(|input| {
+                let index = input.index;
+                input.signatures.into_iter().map(move |sig| SigAuditReport {
+                    input: index,
+                    signature: sig.source.to_string(),
+                    sighash_type: sig.sighash_type.to_string(),
+                    valid: sig.status.is_ok(),
+                    error: sig.status.err().map(|err| err.to_string()),
+                })
+            })
+            .collect::<Vec<_>>();
+        let invalid = reports.iter().filter(|report| !report.valid).count();
+
+        if !self.report(&reports)? {
+            for report in &reports {
+                let status = match report.error {
+                    None => "valid".bright_green(),
+                    Some(ref err) => format!("invalid: {}", err).bright_red(),
+                };
+                println!(
+                    "{:>6} {} {} {}",
+                    format!("#{}", report.input).dimmed(),
+                    report.signature,
+                    report.sighash_type.bright_white(),
+                    status
+                );
+            }
+            if reports.is_empty() {
+                println!("PSBT contains no signatures");
+            }
+            println!();
+        }
+
+        if invalid > 0 {
+            return Err(Error::InvalidSignatures(invalid));
+        }
+        Ok(())
+    }
+
+    fn sign_message(
+        &self,
+        wallet_path: &Path,
+        account: Option<&str>,
+        terminal: &DerivationSubpath<UnhardenedIndex>,
+        message: &str,
+        psbt_path: &Path,
+        finalize: bool,
+    ) -> Result<(), Error> {
+        let wallet = WalletFile::load(wallet_path, account)?;
+        let network = self.wallet_network(&wallet.descriptor)?;
+        let expected =
+            Psbt::construct_message(&wallet.descriptor, terminal.clone(), message.as_bytes())?;
+
+        if !finalize {
+            fs::write(psbt_path, expected.serialize())?;
+            if self.report(&PsbtReport::with(&expected, Some(psbt_path.to_owned())))? {
+                return Ok(());
+            }
+            println!("{} {}\n", "PSBT:".bright_white(), expected);
+            println!(
+                "Sign the PSBT with `btc-hot sign` and run the command once more with \
+                 `--finalize` flag\n"
+            );
+            return Ok(());
+        }
+
+        let secp = Secp256k1::verification_only();
+        let psbt = Psbt::decode_any(&fs::read(psbt_path)?)?;
+        if psbt.to_txid() != expected.to_txid() {
+            return Err(MessageError::NotMessagePsbt.into());
+        }
+        let signature = psbt.message_signature(&secp, message.as_bytes())?;
+        let script_pubkey = &psbt.inputs[0]
+            .input_prevout()
+            .expect("message PSBT always has spent output")
+            .script_pubkey;
+        signature.verify(&secp, script_pubkey, message.as_bytes())?;
+
+        let address = Address::from_script(script_pubkey, network)
+            .map(|address| address.to_string())
+            .unwrap_or_else(|| script_pubkey.as_bytes().to_hex());
+        if self.report(&MessageReport {
+            address: address.clone(),
+            message: message.to_owned(),
+            signature: signature.to_string(),
+            valid: true,
+        })? {
+            return Ok(());
+        }
+        println!("\n{:-11} {}", "Address:", address);
+        println!("{:-11} {}\n", "Signature:", signature);
+        Ok(())
+    }
+
+    fn verify_message(
+        &self,
+        address: &Address,
+        message: &str,
+        signature: &MessageSignature,
+    ) -> Result<(), Error> {
+        let secp = Secp256k1::verification_only();
+        let result = signature.verify(&secp, &address.script_pubkey(), message.as_bytes());
+
+        if !self.report(&MessageReport {
+            address: address.to_string(),
+            message: message.to_owned(),
+            signature: signature.to_string(),
+            valid: result.is_ok(),
+        })? {
+            match &result {
+                Ok(()) => println!("\n{}\n", "Signature is valid".bright_green()),
+                Err(err) => println!("\n{} {}\n", "Invalid signature:".bright_red(), err),
+            }
+        }
+        Ok(result?)
+    }
+
+    fn convert(&self, path: &Path, hex: bool) -> Result<(), Error> {
+        let data = fs::read(path)?;
+        let psbt = Psbt::decode_any(&data)?;
+        if self.report(&PsbtReport::with(&psbt, None))? {
+            return Ok(());
+        }
+        if hex {
+            println!("\n{}\n", psbt.to_hex());
+        } else {
+            println!("\n{}\n", psbt.to_base64());
+        }
+        Ok(())
+    }
+
+    fn taptree(&self, command: &TaptreeCommand) -> Result<(), Error> {
+        match command {
+            TaptreeCommand::Show { source } => {
+                let tree = source.read_tree()?;
+                if !self.format.is_plain() {
+                    let nodes = taptree::tree_nodes(&tree)
+                        .into_iter()
+                        .map(|node| TreeNodeReport {
+                            path: node.path_string(),
+                            depth: node.depth(),
+                            node_hash: node.node_hash.to_string(),
+                            leaf_version: node.leaf.as_ref().map(|(ver, _)| ver.to_consensus()),
+                            script: node.leaf.as_ref().map(|(_, script)| script.asm()),
+                        })
+                        .collect::<Vec<_>>();
+                    self.report(&nodes)?;
+                    return Ok(());
+                }
+                println!();
+                for node in taptree::tree_nodes(&tree) {
+                    let mut path = node.path_string();
+                    if path.is_empty() {
+                        path = s!("<root>");
+                    }
+                    let indent = "  ".repeat(node.depth());
+                    match node.leaf {
+                        Some((version, script)) => println!(
+                            "{}{} {} {} {:02x} {}",
+                            indent,
+                            "leaf".bright_green(),
+                            path.dimmed(),
+                            node.node_hash,
+                            version.to_consensus(),
+                            script.asm()
+                        ),
+                        None if node.hidden => println!(
+                            "{}{} {} {}",
+                            indent,
+                            "hidden".dimmed(),
+                            path.dimmed(),
+                            node.node_hash
+                        ),
+                        None => println!(
+                            "{}{} {} {}",
+                            indent,
+                            "branch".bright_white(),
+                            path.dimmed(),
+                            node.node_hash
+                        ),
+                    }
+                }
+                println!();
+            }
+            TaptreeCommand::Export { source, descriptor } => {
+                let tree = source.read_tree()?;
+                let text = if *descriptor {
+                    format!("{}\n", tree.to_descriptor_tree()?)
+                } else {
+                    ScriptTreeText::from(tree).to_string()
+                };
+                if !self.report(&text)? {
+                    print!("{}", text);
+                }
+            }
+            TaptreeCommand::Instill {
+                psbt_file,
+                output,
+                subtree_file,
+                path,
+                dfs_last,
+            } => {
+                let subtree = ScriptTreeText::from_str(&fs::read_to_string(subtree_file)?)?;
+                let path = taptree::parse_dfs_path(path)?;
+                self.edit_output_tree(psbt_file, *output, |mut tree| {
+                    let dfs_order = if *dfs_last {
+                        DfsOrder::Last
+                    } else {
+                        DfsOrder::First
+                    };
+                    let new_path = tree.instill(subtree.into_inner(), path, dfs_order)?;
+                    eprintln!(
+                        "Subtree instilled at DFS path {}",
+                        taptree::dfs_path_string(new_path.as_ref()).bright_yellow()
+                    );
+                    Ok(tree)
+                })?;
+            }
+            TaptreeCommand::Cut {
+                psbt_file,
+                output,
+                subtree_file,
+                path,
+                dfs_last,
+            } => {
+                let path = taptree::parse_dfs_path(path)?;
+                self.edit_output_tree(psbt_file, *output, |tree| {
+                    let dfs_side = if *dfs_last {
+                        DfsOrder::Last
+                    } else {
+                        DfsOrder::First
+                    };
+                    let (remnant, subtree) = tree.cut(path, dfs_side)?;
+                    fs::write(subtree_file, ScriptTreeText::from(subtree).to_string())?;
+                    Ok(remnant)
+                })?;
+            }
         }
-        for index in skip..(skip + count) {
-            let address = descriptor.address(
-                &secp,
-                [
-                    UnhardenedIndex::from(u8::from(show_change)),
-                    UnhardenedIndex::from(index),
-                ],
-                regtest,
-            )?;
+        Ok(())
+    }
 
-            println!("{:>6} {}", format!("#{}", index).dimmed(), address);
+    fn psbt(&self, command: &PsbtCommand) -> Result<(), Error> {
+        match command {
+            PsbtCommand::SetKey { psbt_file, key } => {
+                let data = fs::read(psbt_file)?;
+                let mut psbt = Psbt::decode_any(&data)?;
+                if psbt.insert_proprietary(key)?.is_some() {
+                    eprintln!("{} previous value of the key", "Replaced".bright_yellow());
+                }
+                fs::write(psbt_file, psbt.serialize())?;
+                self.report(&PsbtReport::with(&psbt, Some(psbt_file.clone())))?;
+            }
+            PsbtCommand::GetKey {
+                psbt_file,
+                location,
+                ty,
+            } => {
+                let data = fs::read(psbt_file)?;
+                let psbt = Psbt::decode_any(&data)?;
+                let keys = psbt.proprietary_by_type(*location, ty)?;
+                let keys = keys.iter().map(ToString::to_string).collect::<Vec<_>>();
+                if !self.report(&keys)? {
+                    for key in keys {
+                        println!("{}", key);
+                    }
+                }
+            }
+            PsbtCommand::DeleteKey { psbt_file, key } => {
+                let data = fs::read(psbt_file)?;
+                let mut psbt = Psbt::decode_any(&data)?;
+                if psbt.remove_proprietary(key)?.is_none() {
+                    eprintln!("{} key is not present in PSBT", "Warning:".bright_yellow());
+                    self.report(&StatusReport::unchanged("key is not present in PSBT"))?;
+                    return Ok(());
+                }
+                fs::write(psbt_file, psbt.serialize())?;
+                self.report(&PsbtReport::with(&psbt, Some(psbt_file.clone())))?;
+            }
+            PsbtCommand::Diff { from, to, output } => {
+                let original = Psbt::decode_any(&fs::read(from)?)?;
+                let modified = Psbt::decode_any(&fs::read(to)?)?;
+                let diff = original.diff(&modified);
+                if let Some(path) = output {
+                    fs::write(path, serde_json::to_string_pretty(&diff)?)?;
+                }
+                if !self.report(&diff)? {
+                    print_psbt_diff(&diff);
+                }
+            }
+            PsbtCommand::Patch {
+                psbt_file,
+                diff_file,
+            } => {
+                let diff: PsbtDiff = serde_json::from_slice(&fs::read(diff_file)?)?;
+                let mut psbt = Psbt::decode_any(&fs::read(psbt_file)?)?;
+                psbt.apply(&diff)?;
+                fs::write(psbt_file, psbt.serialize())?;
+                self.report(&PsbtReport::with(&psbt, Some(psbt_file.clone())))?;
+            }
         }
-
-        println!();
-
         Ok(())
     }
 
-    fn check(&self, path: &Path, batch_size: u16, skip: u16, regtest: bool) -> Result<(), Error> {
-        let secp = Secp256k1::new();
-
-        let descriptor_str = fs::read_to_string(path)?;
-        let descriptor: miniscript::Descriptor<DerivationAccount> =
-            miniscript::Descriptor::from_str(&descriptor_str)?;
-
-        let network = descriptor.network(regtest)?;
-        let client = self.electrum_client(network)?;
-
-        println!(
-            "{}\n{}\n",
-            "\nWallet descriptor:".bright_white(),
-            descriptor.to_string_std(self.bitcoin_core_fmt)
-        );
-
-        let mut total = 0u64;
-        let mut single_pat = [UnhardenedIndex::zero(); 1];
-        let mut double_pat = [UnhardenedIndex::zero(); 2];
-        let derive_pattern = match descriptor.derive_pattern_len()? {
-            1 => single_pat.as_mut_slice(),
-            2 => double_pat.as_mut_slice(),
-            _ => return Err(Error::DescriptorDerivePattern),
-        };
-        for case in 0u8..(derive_pattern.len() as u8) {
-            let mut offset = skip;
-            let mut last_count = 1usize;
-            if derive_pattern.len() > 1 {
-                if let Some(idx) = derive_pattern.first_mut() {
-                    *idx = UnhardenedIndex::from(case)
+    fn account(&self, command: &AccountCommand) -> Result<(), Error> {
+        match command {
+            AccountCommand::Add {
+                account_file,
+                wallet_file,
+                name,
+                descriptor_file,
+            } => {
+                let mut container = if wallet_file.exists() {
+                    read_container(wallet_file)?
+                } else {
+                    Wallet::new()
+                };
+                let descriptor = self.read_descriptor(
+                    descriptor_file,
+                    account_file.as_deref(),
+                    &mut container.meta,
+                )?;
+                descriptor.check_sanity()?;
+                container.add_account(name, descriptor)?;
+                write_container(wallet_file, &container)?;
+                eprintln!(
+                    "{} `{}` to `{}`",
+                    "Account added".bright_green(),
+                    name,
+                    wallet_file.display()
+                );
+            }
+            AccountCommand::List { wallet_file } => {
+                let container = read_container(wallet_file)?;
+                let reports = container
+                    .accounts()
+                    .map(|(name, descriptor)| {
+                        Ok(AccountReport {
+                            name: name.to_owned(),
+                            descriptor: descriptor.to_string_std(self.bitcoin_core_fmt),
+                            network: descriptor.network()?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                if self.report(&reports)? {
+                    return Ok(());
+                }
+                for report in reports {
+                    println!(
+                        "{} {}
+{}
+",
+                        report.name.bright_white(),
+                        report.network.to_string().dimmed(),
+                        report.descriptor
+                    );
                 }
             }
-            loop {
-                eprint!("Batch {}/{}..{}", case, offset, offset + batch_size);
+            AccountCommand::Remove { wallet_file, name } => {
+                let mut container = read_container(wallet_file)?;
+                container.remove_account(name)?;
+                write_container(wallet_file, &container)?;
+                eprintln!(
+                    "{} `{}` from `{}`",
+                    "Account removed".bright_green(),
+                    name,
+                    wallet_file.display()
+                );
+            }
+        }
+        Ok(())
+    }
 
-                let mut addr_total = 0u64;
-                let mut count = 0usize;
-                eprint!(" ... ");
-                for (index, (script, utxo_set)) in client.resolve_descriptor_utxo(
-                    &secp,
-                    &descriptor,
-                    [UnhardenedIndex::from(case)],
-                    UnhardenedIndex::from(offset),
-                    batch_size as u32,
-                )? {
-                    if utxo_set.is_empty() {
-                        continue;
-                    }
-                    count += utxo_set.len();
+    fn sync(&self, command: &SyncCommand) -> Result<(), Error> {
+        let secp = Secp256k1::new();
+        match command {
+            SyncCommand::Export {
+                watcher_key,
+                look_ahead,
+                wallet_file,
+                state_file,
+                bundle_file,
+            } => {
+                let wallet = WalletFile::read(wallet_file)?;
+                let key = fs::read_to_string(watcher_key)?;
+                let keypair =
+                    KeyPair::from_seckey_str(&secp, key.trim()).map_err(Error::WatcherKey)?;
+                let mut state = if state_file.exists() {
+                    read_sync_file::<SyncState>(state_file)?
+                } else {
+                    SyncState::new(wallet.id(), keypair.x_only_public_key().0)
+                };
+                if state.wallet_id != wallet.id() {
+                    return Err(Error::SyncStateMismatch);
+                }
 
-                    let derive_term = format!("{}/{}", case, index);
-                    if let Some(address) =
-                        AddressCompat::from_script(&script.clone().into(), network.into())
-                    {
-                        println!(
-                            "\n  {} address {}:",
-                            derive_term.bright_white(),
-                            address.to_string().bright_white(),
-                        );
-                    } else {
-                        println!(
-                            "\n  {} no-address script {}:",
-                            derive_term.bright_white(),
-                            script
-                        );
+                let network = self.wallet_network(&wallet.descriptor)?;
+                let client = self.electrum_client(network)?;
+                let birthday = wallet.birthday_height(&client)?;
+                let mut utxos = vec![];
+                for (generation, descriptor) in wallet.generations() {
+                    self.check_descriptor(
+                        &client,
+                        descriptor,
+                        &wallet.meta,
+                        *look_ahead,
+                        0,
+                        birthday,
+                        network,
+                        Some(generation),
+                        &mut utxos,
+                    )?;
+                }
+                if let Some(ref descriptor) = wallet.change_descriptor {
+                    self.check_descriptor(
+                        &client,
+                        descriptor,
+                        &wallet.meta,
+                        *look_ahead,
+                        0,
+                        birthday,
+                        network,
+                        None,
+                        &mut utxos,
+                    )?;
+                }
+                let utxos = utxos.into_iter().map(|utxo| SyncUtxo {
+                    outpoint: utxo.outpoint,
+                    amount: utxo.amount,
+                    script_pubkey: Script::from(
+                        Vec::<u8>::from_hex(&utxo.script_pubkey).expect("scan produces valid hex"),
+                    )
+                    .into(),
+                    mined: utxo
+                        .height
+                        .map(MiningStatus::Blockchain)
+                        .unwrap_or(MiningStatus::Mempool),
+                });
+
+                let header = client.block_headers_subscribe()?;
+                let tip = ChainTip {
+                    height: header.height as u32,
+                    block_hash: header.header.block_hash(),
+                    time: header.header.time,
+                };
+                let mut fee_estimates = bmap! {};
+                for target in SYNC_FEE_TARGETS {
+                    // Electrum servers report feerate in BTC per kvbyte, or a
+                    // negative value if the estimate is not available
+                    let btc_per_kvb = client.estimate_fee(target as usize)?;
+                    if btc_per_kvb > 0.0 {
+                        fee_estimates.insert(target, (btc_per_kvb * 100_000_000.0).round() as u64);
                     }
+                }
 
-                    for utxo in utxo_set {
-                        println!(
-                            "{:>10} @ {} - {}",
-                            utxo.amount().to_string().bright_yellow(),
-                            utxo.outpoint(),
-                            utxo.mined()
-                        );
-                        addr_total += utxo.amount().to_sat();
+                let signed = state
+                    .prepare(utxos, tip, fee_estimates)
+                    .sign(&secp, &keypair);
+                let summary = state.apply(&secp, &signed)?;
+                write_sync_file(bundle_file, &signed)?;
+                write_sync_file(state_file, &state)?;
+
+                let report = SyncReport::with(&state, Some(summary), Some(bundle_file.clone()));
+                if !self.report(&report)? {
+                    println!(
+                        "{} #{} to `{}`",
+                        "Sync bundle exported".bright_green(),
+                        summary.sequence,
+                        bundle_file.display()
+                    );
+                    self.print_sync_report(&report);
+                }
+            }
+            SyncCommand::Import {
+                watcher,
+                wallet_file,
+                state_file,
+                bundle_file,
+            } => {
+                let wallet = WalletFile::read(wallet_file)?;
+                let signed = read_sync_file::<SignedSyncBundle>(bundle_file)?;
+                let mut state = match (state_file.exists(), watcher) {
+                    (true, _) => read_sync_file::<SyncState>(state_file)?,
+                    (false, Some(watcher)) => SyncState::new(wallet.id(), *watcher),
+                    (false, None) => return Err(Error::SyncWatcherRequired),
+                };
+                if state.wallet_id != wallet.id() {
+                    return Err(Error::SyncStateMismatch);
+                }
+                if let Some(watcher) = watcher {
+                    if *watcher != state.watcher {
+                        return Err(Error::SyncWatcherMismatch(state.watcher));
                     }
                 }
 
-                offset += batch_size;
-                total += addr_total;
+                let summary = state.apply(&secp, &signed)?;
+                write_sync_file(state_file, &state)?;
 
-                if count == 0 {
-                    eprintln!("empty");
+                let report = SyncReport::with(&state, Some(summary), Some(bundle_file.clone()));
+                if !self.report(&report)? {
+                    println!(
+                        "{} #{} from `{}`",
+                        "Sync bundle imported".bright_green(),
+                        summary.sequence,
+                        bundle_file.display()
+                    );
+                    self.print_sync_report(&report);
                 }
-                if last_count == 0 && count == 0 {
-                    break;
+            }
+            SyncCommand::Status { state_file } => {
+                let state = read_sync_file::<SyncState>(state_file)?;
+                let report = SyncReport::with(&state, None, None);
+                if !self.report(&report)? {
+                    self.print_sync_report(&report);
                 }
-                last_count = count;
             }
         }
+        Ok(())
+    }
 
+    fn print_sync_report(&self, report: &SyncReport) {
+        if let (Some(added), Some(spent)) = (report.added, report.spent) {
+            println!(
+                "  {} new or updated outputs, {} spent outputs",
+                added, spent
+            );
+        }
+        println!("{} {}", "Watcher:".bright_white(), report.watcher);
+        println!("{} {}", "Last bundle:".bright_white(), report.sequence);
+        match (report.tip_height, report.tip_hash) {
+            (Some(height), Some(hash)) => {
+                println!("{} {} ({})", "Chain tip:".bright_white(), height, hash)
+            }
+            _ => println!("{} unknown", "Chain tip:".bright_white()),
+        }
         println!(
-            "Total {} sats\n",
-            total.to_string().bright_yellow().underline()
+            "{} {} in {} outputs",
+            "Balance:".bright_white(),
+            self.unit.amount(report.balance).to_string().bright_yellow(),
+            report.utxo_count
         );
-
-        Ok(())
+        for (target, sat_per_kvb) in &report.fee_estimates {
+            println!(
+                "{} {:.1} sat/vbyte within {} blocks",
+                "Fee estimate:".bright_white(),
+                *sat_per_kvb as f64 / 1000.0,
+                target
+            );
+        }
     }
 
-    fn history(&self) -> Result<(), Error> { todo!() }
-
-    fn info(&self, data: &str) -> Result<(), Error> {
-        let xpub = ExtendedPubKey::from_slip132_str(data)?;
-        println!();
-        println!("{:-13} {}", "Fingerprint:", xpub.fingerprint());
-        println!("{:-13} {}", "Identifier:", xpub.identifier());
-        println!("{:-13} {}", "Network:", xpub.network);
-        println!("{:-13} {}", "Public key:", xpub.public_key);
-        println!("{:-13} {}", "Chain code:", xpub.chain_code);
-        match KeyVersion::from_xkey_str(data) {
-            Ok(ver) => {
-                if let Some(application) = DefaultResolver::application(&ver) {
-                    println!("{:-13} {}", "Application:", application);
-                }
-                if let Some(derivation_path) = DefaultResolver::derivation_path(&ver, None) {
-                    println!("{:-13} {}", "Derivation:", derivation_path);
-                } else if let Some(derivation_path) =
-                    DefaultResolver::derivation_path(&ver, Some(ChildNumber::Hardened { index: 0 }))
-                {
-                    println!("{:-13} {}  # (account 0)", "Derivation:", derivation_path);
-                }
-            }
-            Err(err) => eprintln!(
-                "{:-13} {} {}",
-                "Application:",
-                "unable to read SLIP-132 information.".bright_red(),
-                err
+    fn print_fee_analysis(&self, psbt: &Psbt) {
+        let analysis = psbt.analyze();
+        if let Some(fee) = analysis.fee {
+            println!("{:<14} {}", "Fee:".bright_white(), self.unit.amount(fee));
+        }
+        match analysis.signed_vsize() {
+            Some(vsize) => println!(
+                "{:<14} {} vbytes unsigned, ~{} vbytes signed",
+                "Size:".bright_white(),
+                analysis.unsigned_vsize(),
+                vsize
+            ),
+            None => println!(
+                "{:<14} {} vbytes unsigned, signed size is unknown",
+                "Size:".bright_white(),
+                analysis.unsigned_vsize()
             ),
         }
-        println!("{:-13} {}", "Depth:", xpub.depth);
-        println!("{:-13} {:#}", "Child number:", xpub.child_number);
-        println!("Variants:");
-        for network in [bitcoin::Network::Bitcoin, bitcoin::Network::Testnet] {
-            for app in KeyApplication::ALL {
-                println!("  - {}", xpub.to_slip132_string(app, network));
-            }
+        if let Some(fee_rate) = analysis.fee_rate() {
+            println!(
+                "{:<14} {:.2} sat/vbyte",
+                "Fee rate:".bright_white(),
+                fee_rate
+            );
+        }
+        for input in &analysis.inputs {
+            let amount = input
+                .amount
+                .map(|amount| self.unit.amount(amount).to_string())
+                .unwrap_or_else(|| s!("unknown amount"));
+            let weight = match input.satisfaction {
+                Some(size) if input.finalized => format!("{} WU witness weight", size.weight()),
+                Some(size) => format!("~{} WU witness weight", size.weight()),
+                None => s!("unknown witness weight"),
+            };
+            println!(
+                "  {} #{}: {}, {}",
+                "Input".bright_white(),
+                input.index,
+                amount,
+                weight
+            );
+        }
+        for index in &analysis.change_outputs {
+            println!(
+                "  {} #{}: {}",
+                "Change".bright_white(),
+                index,
+                self.unit.amount(psbt.outputs[*index].amount)
+            );
         }
-        println!();
-
-        Ok(())
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn construct(
+    fn edit_output_tree(
         &self,
-        wallet_path: &Path,
-        lock_time: LockTime,
-        inputs: &[InputDescriptor],
-        outputs: &[AddressAmount],
-        change_index: UnhardenedIndex,
-        proprietary_keys: &[ProprietaryKeyDescriptor],
-        fee: u64,
         psbt_path: &Path,
+        output_no: u16,
+        f: impl FnOnce(TaprootScriptTree) -> Result<TaprootScriptTree, Error>,
     ) -> Result<(), Error> {
-        let descriptor_str = fs::read_to_string(wallet_path)?;
-        let descriptor: miniscript::Descriptor<DerivationAccount> =
-            miniscript::Descriptor::from_str(&descriptor_str)?;
-
-        let network = descriptor.network(false)?;
-        let electrum_url = format!(
-            "{}:{}",
-            self.electrum_server,
-            self.electrum_port
-                .unwrap_or_else(|| default_electrum_port(network))
-        );
-        let client = electrum::Client::new(&electrum_url)?;
-
-        println!(
-            "{}\n{}\n",
-            "\nWallet descriptor:".bright_white(),
-            descriptor
-        );
+        let secp = Secp256k1::verification_only();
 
-        eprint!(
-            "Re-scanning network {} using {} ... ",
-            network.to_string().yellow(),
-            electrum_url.yellow()
-        );
+        let data = fs::read(psbt_path)?;
+        let encoding = PsbtEncoding::detect(&data);
+        let mut psbt = Psbt::decode(&data, encoding)?;
+        let output = psbt
+            .outputs
+            .get_mut(output_no as usize)
+            .ok_or(Error::UnknownOutput(output_no))?;
+        let internal_key = output
+            .tap_internal_key
+            .ok_or(Error::NoInternalKey(output_no))?;
+        let tree = output.tap_tree.clone().ok_or(Error::NoTaptree)?;
+
+        let tap_tree = taptree::to_tap_tree(&f(TaprootScriptTree::from(tree))?)?;
+        let spend_info = tap_tree
+            .clone()
+            .into_builder()
+            .finalize(&secp, internal_key)
+            .expect("TapTree is always finalizable");
+        let leaf_hashes = tap_tree
+            .script_leaves()
+            .map(|leaf| TapLeafHash::from_script(leaf.script(), leaf.leaf_version()))
+            .collect::<BTreeSet<_>>();
+
+        let script = Script::new_v1_p2tr_tweaked(spend_info.output_key());
+        if self.format.is_plain() {
+            println!(
+                "\n{} {}\n",
+                "Output scriptPubkey:".bright_white(),
+                script.as_bytes().to_hex()
+            );
+        }
 
-        let txid_set: BTreeSet<_> = inputs.iter().map(|input| input.outpoint.txid).collect();
-        let tx_map = client
-            .batch_transaction_get(&txid_set)?
-            .into_iter()
-            .map(|tx| (tx.txid(), tx))
-            .collect::<BTreeMap<_, _>>();
+        output.script = script.into();
+        output.tap_tree = Some(tap_tree);
+        for (leaves, _) in output.tap_key_origins.values_mut() {
+            leaves.retain(|leaf_hash| leaf_hashes.contains(leaf_hash));
+        }
 
-        eprintln!("{}", "done\n".green());
+        fs::write(psbt_path, psbt.encode(encoding))?;
 
-        let outputs = outputs
-            .iter()
-            .map(|a| {
-                (
-                    PubkeyScript::from_inner(a.address.script_pubkey()),
-                    a.amount,
-                )
-            })
-            .collect::<Vec<_>>();
+        self.report(&PsbtReport::with(&psbt, Some(psbt_path.to_owned())))?;
+        Ok(())
+    }
 
-        let mut psbt = Psbt::construct(&descriptor, inputs, &outputs, change_index, fee, &tx_map)?;
-        psbt.fallback_locktime = Some(lock_time);
+    fn compile(
+        &self,
+        policy: &str,
+        class: DescriptorClass,
+        feerate: Option<u32>,
+    ) -> Result<(), Error> {
+        let compiled = policy::compile_str::<DerivationRef>(policy, class)?;
+        let feerate = match feerate {
+            Some(feerate) => feerate,
+            None => self.default_feerate()?,
+        };
 
-        for key in proprietary_keys {
-            match key.location {
-                ProprietaryKeyLocation::Input(pos) if pos as usize >= psbt.inputs.len() => {
-                    return Err(ProprietaryKeyError::InputOutOfRange(pos, psbt.inputs.len()).into())
-                }
-                ProprietaryKeyLocation::Output(pos) if pos as usize >= psbt.outputs.len() => {
-                    return Err(
-                        ProprietaryKeyError::OutputOutOfRange(pos, psbt.inputs.len()).into(),
-                    )
-                }
-                ProprietaryKeyLocation::Global => {
-                    psbt.proprietary
-                        .insert(key.into(), key.value.as_ref().cloned().unwrap_or_default());
-                }
-                ProprietaryKeyLocation::Input(pos) => {
-                    psbt.inputs[pos as usize]
-                        .proprietary
-                        .insert(key.into(), key.value.as_ref().cloned().unwrap_or_default());
-                }
-                ProprietaryKeyLocation::Output(pos) => {
-                    psbt.outputs[pos as usize]
-                        .proprietary
-                        .insert(key.into(), key.value.as_ref().cloned().unwrap_or_default());
-                }
-            }
+        if self.report(&CompiledReport {
+            descriptor: compiled.descriptor.to_string(),
+            class: class.to_string(),
+            policy: policy.to_owned(),
+            script_size: compiled.script_size,
+            max_satisfaction_weight: compiled.max_satisfaction_weight,
+            max_input_vsize: compiled.max_input_vsize(),
+            input_fee: Some(compiled.input_fee(feerate as f32)),
+            feerate: Some(feerate),
+        })? {
+            return Ok(());
         }
-
-        fs::write(psbt_path, psbt.serialize())?;
-
-        println!("{} {}\n", "PSBT:".bright_white(), psbt);
+        println!("\n{}\n", compiled.descriptor.to_string().bright_white());
+        println!("{:<24} {}", "Descriptor class:".bright_white(), class);
+        println!(
+            "{:<24} {} bytes",
+            "Script size:".bright_white(),
+            compiled.script_size
+        );
+        println!(
+            "{:<24} {} WU",
+            "Max witness size:".bright_white(),
+            compiled.max_satisfaction_weight
+        );
+        println!(
+            "{:<24} {} vbytes",
+            "Max input size:".bright_white(),
+            compiled.max_input_vsize()
+        );
+        println!(
+            "{:<24} {} at {} sat/vbyte\n",
+            "Input spending fee:".bright_white(),
+            self.unit.amount(compiled.input_fee(feerate as f32)),
+            feerate
+        );
 
         Ok(())
     }
 
-    fn finalize(
+    fn delegation(
         &self,
-        psbt_path: &Path,
-        tx_path: Option<&PathBuf>,
-        publish: Option<Network>,
+        owner: &DerivationRef,
+        delegate: &DerivationRef,
+        lock: DelegationLock,
+        class: DescriptorClass,
     ) -> Result<(), Error> {
-        let secp = Secp256k1::new();
+        let delegation = Delegation::with(owner.clone(), delegate.clone(), lock);
+        let compiled = delegation.compile(class)?;
+
+        if self.report(&CompiledReport {
+            descriptor: compiled.descriptor.to_string(),
+            class: class.to_string(),
+            policy: delegation.to_string(),
+            script_size: compiled.script_size,
+            max_satisfaction_weight: compiled.max_satisfaction_weight,
+            max_input_vsize: compiled.max_input_vsize(),
+            input_fee: None,
+            feerate: None,
+        })? {
+            return Ok(());
+        }
+        println!("\n{}\n", compiled.descriptor.to_string().bright_white());
+        println!("{:<24} {}", "Descriptor class:".bright_white(), class);
+        println!("{:<24} {}", "Spending policy:".bright_white(), delegation);
+        for (role, key) in [
+            (DelegationRole::Owner, &delegation.owner),
+            (DelegationRole::Delegate, &delegation.delegate),
+        ] {
+            print!(
+                "{:<24} {}",
+                format!("{} spending:", role).bright_white(),
+                key
+            );
+            match delegation.seq_no(role) {
+                Some(seq_no) => print!(", inputs require `{}` sequence", seq_no),
+                None => print!(", at any time"),
+            }
+            if let Some(lock_time) = delegation.lock_time(role) {
+                print!(", transaction requires lock time {}", lock_time.0);
+            }
+            println!();
+        }
+        println!();
 
-        let data = fs::read(psbt_path)?;
-        let mut psbt = consensus::encode::deserialize::<PartiallySignedTransaction>(&data)
-            .map_err(Error::psbt_from_consensus)?;
+        Ok(())
+    }
+}
 
-        psbt.finalize_mut(&secp).map_err(VecDisplay::from)?;
+/// Wallet file produced by `create` command.
+///
+/// The file starts with the current wallet descriptor string, optionally
+/// followed by a line with a separate change descriptor prefixed with
+/// `change `, then by a line with the wallet [`Birthday`] prefixed with
+/// `birthday `, then by descriptors of the previous wallet generations (from
+/// the oldest to the most recent one), one per line, and optionally by a new
+/// line containing hex-encoded strict-serialized [`WalletMeta`]. Wallet files
+/// without history and metadata consist of a descriptor string only.
+pub struct WalletFile {
+    pub descriptor: miniscript::Descriptor<DerivationAccount>,
+    pub change_descriptor: Option<miniscript::Descriptor<DerivationAccount>>,
+    pub birthday: Option<Birthday>,
+    pub history: Vec<miniscript::Descriptor<DerivationAccount>>,
+    pub meta: WalletMeta,
+}
 
-        let tx = psbt.extract_tx();
+const WALLET_FILE_CHANGE_PREFIX: &str = "change ";
+const WALLET_FILE_BIRTHDAY_PREFIX: &str = "birthday ";
+
+impl WalletFile {
+    /// Reads wallet file, or, if the `account` name is given, the named
+    /// account from a multi-account wallet container file. Accounts of the
+    /// container share the container metadata and have no descriptor history.
+    pub fn load(path: &Path, account: Option<&str>) -> Result<Self, Error> {
+        let name = match account {
+            None => return WalletFile::read(path),
+            Some(name) => name,
+        };
+        let container = read_container(path)?;
+        let descriptor = container
+            .account(name)
+            .ok_or_else(|| ContainerError::UnknownAccount(name.to_owned()))?
+            .clone();
+        Ok(WalletFile {
+            descriptor,
+            change_descriptor: None,
+            birthday: None,
+            history: vec![],
+            meta: container.meta,
+        })
+    }
 
-        if let Some(tx_path) = tx_path {
-            let mut file = fs::File::create(tx_path)?;
-            tx.consensus_encode(&mut file)?;
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let data = fs::read_to_string(path)?;
+        let mut lines = data.lines().map(str::trim);
+        let descriptor_str = lines.next().unwrap_or_default();
+        let descriptor = miniscript::Descriptor::from_str(descriptor_str)?;
+        let mut change_descriptor = None;
+        let mut birthday = None;
+        let mut history = vec![];
+        let mut meta_str = String::new();
+        for line in lines {
+            if let Some(descriptor_str) = line.strip_prefix(WALLET_FILE_CHANGE_PREFIX) {
+                change_descriptor = Some(miniscript::Descriptor::from_str(descriptor_str)?);
+            } else if let Some(birthday_str) = line.strip_prefix(WALLET_FILE_BIRTHDAY_PREFIX) {
+                birthday = Some(Birthday::from_str(birthday_str)?);
+            } else if line.contains('(') {
+                history.push(miniscript::Descriptor::from_str(line)?);
+            } else {
+                meta_str.push_str(line);
+            }
+        }
+        let meta = if meta_str.is_empty() {
+            WalletMeta::default()
         } else {
-            println!("{}\n", tx.serialize().to_hex());
+            WalletMeta::from_envelope(Vec::<u8>::from_hex(&meta_str)?)?
+        };
+        Ok(WalletFile {
+            descriptor,
+            change_descriptor,
+            birthday,
+            history,
+            meta,
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut data = self.descriptor.to_string();
+        if let Some(ref descriptor) = self.change_descriptor {
+            data.push('\n');
+            data.push_str(WALLET_FILE_CHANGE_PREFIX);
+            data.push_str(&descriptor.to_string());
         }
+        if let Some(birthday) = self.birthday {
+            data.push('\n');
+            data.push_str(WALLET_FILE_BIRTHDAY_PREFIX);
+            data.push_str(&birthday.to_string());
+        }
+        for descriptor in &self.history {
+            data.push('\n');
+            data.push_str(&descriptor.to_string());
+        }
+        if !self.meta.is_empty() {
+            data.push('\n');
+            data.push_str(&self.meta.to_envelope()?.to_hex());
+        }
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Returns wallet identifier used by the sync protocol, computed from the
+    /// first wallet descriptor generation.
+    pub fn id(&self) -> sha256::Hash {
+        let (_, descriptor) = self
+            .generations()
+            .next()
+            .expect("wallet always has a descriptor");
+        sync::wallet_id(descriptor)
+    }
+
+    /// Returns height of the block from which wallet scans start, converting
+    /// time-based wallet birthday into a block height with the `resolver`.
+    pub fn birthday_height(&self, resolver: &impl ResolveBlockInfo) -> Result<u32, Error> {
+        Ok(match self.birthday {
+            None => 0,
+            Some(Birthday::Height(height)) => height,
+            Some(Birthday::Time(time)) => resolver.resolve_height_before(time)?,
+        })
+    }
+
+    /// Returns descriptor used for change outputs, which is either a separate
+    /// change descriptor or the main wallet descriptor.
+    pub fn change_descriptor(&self) -> &miniscript::Descriptor<DerivationAccount> {
+        self.change_descriptor.as_ref().unwrap_or(&self.descriptor)
+    }
 
-        if let Some(network) = publish {
-            let client = self.electrum_client(network)?;
-            client.transaction_broadcast(&tx)?;
-            eprintln!(
-                "{} {} {}\n",
-                "Transaction".bright_yellow(),
-                tx.txid().to_string().yellow(),
-                "published".bright_yellow()
-            );
-        }
+    /// Replaces wallet descriptor with a new one, moving the current
+    /// descriptor into the wallet history.
+    pub fn rotate(&mut self, descriptor: miniscript::Descriptor<DerivationAccount>) {
+        let prev = std::mem::replace(&mut self.descriptor, descriptor);
+        self.history.push(prev);
+    }
 
-        Ok(())
+    /// Iterates over all wallet descriptor generations, starting from the
+    /// oldest one and ending with the current descriptor, together with their
+    /// generation numbers.
+    pub fn generations(
+        &self,
+    ) -> impl Iterator<Item = (usize, &miniscript::Descriptor<DerivationAccount>)> {
+        self.history
+            .iter()
+            .chain(std::iter::once(&self.descriptor))
+            .enumerate()
     }
 
-    fn inspect(&self, path: Option<&PathBuf>) -> Result<(), Error> {
-        let psbt = if let Some(path) = path {
-            let data = fs::read(path)?;
-            Psbt::deserialize(&data).map_err(Error::psbt_from_consensus)?
-        } else {
-            eprint!("Type in Base58 encoded PSBT and press enter: ");
-            stdout().flush()?;
-            let stdin = stdin();
-            let psbt64 = stdin.lock().lines().next().expect("no PSBT data")?;
-            Psbt::from_str(psbt64.trim())?
-        };
-        println!("\n{}", serde_yaml::to_string(&psbt)?);
-        Ok(())
+    /// Returns previous wallet descriptor generations, starting from the
+    /// oldest one; or only the generation with a given number, if provided.
+    /// Errors if the wallet has no previous generation with that number.
+    pub fn previous_generations(
+        &self,
+        generation: Option<usize>,
+    ) -> Result<Vec<(usize, &miniscript::Descriptor<DerivationAccount>)>, Error> {
+        match generation {
+            Some(no) if no < self.history.len() => Ok(vec![(no, &self.history[no])]),
+            Some(no) => Err(Error::UnknownGeneration(no)),
+            None => Ok(self.history.iter().enumerate().collect()),
+        }
     }
 
-    fn convert(&self, path: &Path) -> Result<(), Error> {
-        let data = fs::read(path)?;
-        let psbt = Psbt::deserialize(&data).map_err(Error::psbt_from_consensus)?;
-        println!("\n{}\n", psbt);
-        Ok(())
+    /// Returns script class of a previous descriptor generation if it differs
+    /// from the class of the current wallet descriptor, i.e. if the generation
+    /// uses a deprecated script class.
+    pub fn deprecated_class(&self, generation: usize) -> Option<DescriptorClass> {
+        let class = DescriptorClass::from(self.history.get(generation)?.desc_type());
+        if class == DescriptorClass::from(self.descriptor.desc_type()) {
+            return None;
+        }
+        Some(class)
+    }
+
+    /// Produces report on the wallet saved to a given file.
+    pub fn report(&self, path: &Path, bitcoin_core_fmt: bool) -> Result<WalletReport, Error> {
+        Ok(WalletReport {
+            wallet_file: path.to_owned(),
+            descriptor: self.descriptor.to_string_std(bitcoin_core_fmt),
+            change_descriptor: self
+                .change_descriptor
+                .as_ref()
+                .map(|descriptor| descriptor.to_string_std(bitcoin_core_fmt)),
+            generation: self.history.len(),
+            birthday: self.birthday.map(u32::from),
+            network: self.descriptor.network()?,
+        })
     }
 }
 
-fn default_electrum_port(network: Network) -> u16 {
-    match network {
-        Network::Bitcoin => 50001,
-        Network::Testnet => 60001,
-        Network::Signet | Network::Regtest => 60601,
+impl TaptreeSource {
+    pub fn read_tree(&self) -> Result<TaprootScriptTree, Error> {
+        if let Some(output_no) = self.output {
+            let data = fs::read(&self.file)?;
+            let psbt = Psbt::decode_any(&data)?;
+            let output = psbt
+                .outputs
+                .get(output_no as usize)
+                .ok_or(Error::UnknownOutput(output_no))?;
+            let tree = output.tap_tree.clone().ok_or(Error::NoTaptree)?;
+            return Ok(TaprootScriptTree::from(tree));
+        }
+
+        let secp = Secp256k1::verification_only();
+        let wallet = WalletFile::read(&self.file)?;
+        let descriptor = if self.show_change {
+            wallet.change_descriptor()
+        } else {
+            &wallet.descriptor
+        };
+        if descriptor.derive_pattern_len()? != 2 {
+            return Err(Error::DescriptorDerivePattern);
+        }
+        let keychain = UnhardenedIndex::from(u8::from(self.show_change));
+        let descriptor =
+            DeriveDescriptor::<XOnlyPublicKey>::derive_descriptor(descriptor, &secp, [
+                keychain, self.index,
+            ])?;
+        let tree = match descriptor {
+            miniscript::Descriptor::Tr(ref tr) => tr.taptree().ok_or(Error::NoTaptree)?,
+            _ => return Err(Error::NoTaptree),
+        };
+        let mut builder = TaprootBuilder::new();
+        for (depth, ms) in tree.iter() {
+            builder = builder
+                .add_leaf(depth, ms.encode())
+                .expect("insane miniscript taptree");
+        }
+        let tree = TapTree::try_from(builder).expect("non-finalized TaprootBuilder");
+        Ok(TaprootScriptTree::from(tree))
     }
 }
 
@@ -781,9 +4487,9 @@ pub enum ParseError {
     #[from]
     InvalidAddress(address::Error),
 
-    /// invalid amount
+    /// invalid amount: {0}
     #[from]
-    InvalidAmount(ParseIntError),
+    InvalidAmount(AmountParseError),
 }
 
 impl std::error::Error for ParseError {
@@ -811,13 +4517,130 @@ impl FromStr for AddressAmount {
         match (split.next(), split.next(), split.next()) {
             (Some(addr), Some(val), None) => Ok(AddressAmount {
                 address: addr.parse()?,
-                amount: val.parse()?,
+                amount: amount::parse_amount(val, Denomination::Sat)?,
             }),
             _ => Err(ParseError::InvalidFormat),
         }
     }
 }
 
+/// DNS-over-HTTPS endpoint used for resolving BIP-353 names
+/// Errors resolving DNS records with DNS-over-HTTPS
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum DohError {
+    /// DNS-over-HTTPS request failed: {0}
+    #[from]
+    Request(Box<ureq::Error>),
+
+    /// DNS-over-HTTPS response can't be read: {0}
+    #[from]
+    Io(io::Error),
+
+    /// invalid DNS-over-HTTPS response: {0}
+    #[from]
+    Json(serde_json::Error),
+
+    /// DNS query for {0} failed with status {1}
+    Status(String, u64),
+
+    /// invalid TXT record data `{0}` in DNS-over-HTTPS response
+    InvalidTxt(String),
+}
+
+/// TXT record resolver using DNS-over-HTTPS JSON API
+pub struct DohResolver {
+    agent: ureq::Agent,
+    url: String,
+}
+
+impl DohResolver {
+    pub fn with(url: &str, proxy: Option<&str>) -> Result<Self, DohError> {
+        let mut agent = ureq::AgentBuilder::new();
+        if let Some(proxy) = proxy {
+            agent = agent.proxy(ureq::Proxy::new(format!("socks5://{}", proxy)).map_err(Box::new)?);
+        }
+        Ok(DohResolver {
+            agent: agent.build(),
+            url: url.to_owned(),
+        })
+    }
+}
+
+impl ResolveTxt for DohResolver {
+    type Error = DohError;
+
+    fn resolve_txt(&mut self, name: &str) -> Result<TxtRecords, Self::Error> {
+        let response = self
+            .agent
+            .get(&self.url)
+            .query("name", name)
+            .query("type", "TXT")
+            .query("do", "1")
+            .set("Accept", "application/dns-json")
+            .call()
+            .map_err(Box::new)?
+            .into_string()?;
+        let response: serde_json::Value = serde_json::from_str(&response)?;
+        match response["Status"].as_u64() {
+            Some(0) => {}
+            status => {
+                return Err(DohError::Status(
+                    name.to_owned(),
+                    status.unwrap_or(u64::MAX),
+                ))
+            }
+        }
+        let records = response["Answer"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|answer| answer["type"].as_u64() == Some(16))
+            .filter_map(|answer| answer["data"].as_str())
+            .map(|data| {
+                TxtRecords::parse_presentation(data)
+                    .ok_or_else(|| DohError::InvalidTxt(data.to_owned()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TxtRecords {
+            records,
+            authenticated: response["AD"].as_bool().unwrap_or_default(),
+        })
+    }
+}
+
+/// Transaction input given either as a full input descriptor or as a bare
+/// outpoint, for which input descriptor is discovered from the wallet
+#[derive(Clone, PartialEq, Eq, Debug, Display, From)]
+#[display(inner)]
+#[allow(clippy::large_enum_variant)]
+pub enum InputArg {
+    #[from]
+    Descriptor(InputDescriptor),
+    #[from]
+    Outpoint(OutPoint),
+}
+
+impl InputArg {
+    pub fn outpoint(&self) -> OutPoint {
+        match self {
+            InputArg::Descriptor(input) => input.outpoint,
+            InputArg::Outpoint(outpoint) => *outpoint,
+        }
+    }
+}
+
+impl FromStr for InputArg {
+    type Err = <InputDescriptor as FromStr>::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match OutPoint::from_str(s.trim()) {
+            Ok(outpoint) => Ok(InputArg::Outpoint(outpoint)),
+            Err(_) => InputDescriptor::from_str(s).map(InputArg::Descriptor),
+        }
+    }
+}
+
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display, From)]
 #[display(inner)]
 #[allow(clippy::large_enum_variant)]
@@ -942,15 +4765,132 @@ pub enum Error {
     #[from]
     ResolveUtxo(UtxoResolverError),
 
+    #[from]
+    ResolveBlock(BlockResolverError),
+
     #[from]
     Electrum(electrum::Error),
 
+    #[from]
+    ElectrumUrl(ElectrumUrlError),
+
+    #[from]
+    Subscribe(SubscribeError),
+
+    /// notification sinks require `--notify-secret` for signing payloads
+    #[display(doc_comments)]
+    NotifySecretRequired,
+
+    /// unable to set up event notifications: {0}
+    #[display(doc_comments)]
+    Notify(String),
+
+    /// unable to publish transaction: {0}
+    #[from]
+    #[display(doc_comments)]
+    Broadcast(BroadcastError),
+
     #[from]
     Yaml(serde_yaml::Error),
 
+    #[from]
+    Json(serde_json::Error),
+
     #[from]
     PsbtConstruction(construct::Error),
 
+    #[from]
+    InputResolve(InputResolveError),
+
+    #[from]
+    Payjoin(PayjoinError),
+
+    #[from]
+    Reserves(ReservesError),
+
+    #[from]
+    Message(MessageError),
+
+    /// payjoin endpoint request failed: {0}
+    #[display(doc_comments)]
+    PayjoinRequest(Box<ureq::Error>),
+
+    /// payjoin endpoint `{0}` must use HTTPS or be a Tor onion service
+    #[display(doc_comments)]
+    InsecurePayjoinEndpoint(String),
+
+    /// unable to resolve payment instruction: {0}
+    #[from]
+    #[display(doc_comments)]
+    PaymentResolve(ResolveError<DohError>),
+
+    /// payment instruction `{0}` has no on-chain address
+    #[display(doc_comments)]
+    NoPaymentAddress(String),
+
+    /// payment instruction `{0}` does not specify amount
+    #[display(doc_comments)]
+    NoPaymentAmount(String),
+
+    /// wallet file contains invalid birthday: {0}
+    #[from]
+    #[display(doc_comments)]
+    Birthday(blockchain::ParseError),
+
+    /// invalid hex encoding of the transaction: {0}
+    #[display(doc_comments)]
+    TxHex(amplify::hex::Error),
+
+    /// invalid transaction data: {0}
+    #[from]
+    #[display(doc_comments)]
+    TxEncoding(consensus::encode::Error),
+
+    /// wallet file contains invalid metadata encoding: {0}
+    #[from]
+    #[display(doc_comments)]
+    MetaHex(amplify::hex::Error),
+
+    /// wallet file contains invalid metadata: {0}
+    #[from]
+    #[display(doc_comments)]
+    MetaEncoding(EnvelopeError),
+
+    #[from]
+    Reservation(ReservationError),
+
+    #[from]
+    Rotation(RotationError),
+
+    /// wallet descriptors do not contain account key with fingerprint {0}
+    #[display(doc_comments)]
+    UnknownKey(Fingerprint),
+
+    /// change index {0} is reserved for `{1}` protocol
+    #[display(doc_comments)]
+    ReservedChangeIndex(UnhardenedIndex, String),
+
+    /// wallet has no previous descriptor generation {0}
+    #[display(doc_comments)]
+    UnknownGeneration(usize),
+
+    /// wallet descriptor is not a timelocked inheritance descriptor
+    #[display(doc_comments)]
+    NotInheritance,
+
+    /// wallet has no confirmed UTXOs to sweep
+    #[display(doc_comments)]
+    NoHeirUtxos,
+
+    /// heir timelock has not expired for any of the wallet UTXOs; the earliest
+    /// of them becomes spendable at block {0}
+    #[display(doc_comments)]
+    HeirTimelocked(u32),
+
+    /// swept amount of {0} sats does not cover the fee of {1} sats
+    #[display(doc_comments)]
+    SweepFeeTooHigh(u64, u64),
+
     /// can't finalize PSBT data due to following problem(s):
     ///
     /// {0}
@@ -958,6 +4898,13 @@ pub enum Error {
     #[from]
     PsbtFinalization(VecDisplay<miniscript::psbt::Error, true, '-', '\n'>),
 
+    /// constructed transaction violates the spend policy:
+    ///
+    /// {0}
+    #[display(doc_comments)]
+    #[from]
+    SpendPolicy(VecDisplay<construct::SpendPolicyError, true, '-', '\n'>),
+
     /// unrecognized number of wildcards in the descriptor derive pattern
     #[display(doc_comments)]
     DescriptorDerivePattern,
@@ -980,6 +4927,209 @@ pub enum Error {
     #[from]
     #[display(doc_comments)]
     PsbtProprietaryKey(ProprietaryKeyError),
+
+    /// can't apply PSBT diff: {0}
+    #[from]
+    #[display(doc_comments)]
+    PsbtDiff(DiffError),
+
+    #[from]
+    Policy(PolicyError),
+
+    #[from]
+    Config(ConfigError),
+
+    #[from]
+    TaptreeParse(TreeParseError),
+
+    #[from]
+    DescriptorTree(DescriptorTreeError),
+
+    #[from]
+    TaptreeDisclosure(DisclosureError),
+
+    /// unable to instill script subtree: {0}
+    #[from]
+    #[display(doc_comments)]
+    TaptreeInstill(InstillError),
+
+    /// unable to cut script subtree: {0}
+    #[from]
+    #[display(doc_comments)]
+    TaptreeCut(CutError),
+
+    /// PSBT has no output #{0}
+    #[display(doc_comments)]
+    UnknownOutput(u16),
+
+    /// PSBT output #{0} has no taproot internal key
+    #[display(doc_comments)]
+    NoInternalKey(u16),
+
+    /// no taproot script tree is present
+    #[display(doc_comments)]
+    NoTaptree,
+
+    /// new wallet descriptor is for {1} network, while the wallet uses {0}
+    #[display(doc_comments)]
+    RotationNetworkMismatch(Network, Network),
+
+    /// change descriptor is for {1} network, while the wallet uses {0}
+    #[display(doc_comments)]
+    ChangeNetworkMismatch(Network, Network),
+
+    /// wallet keys are created for {1} and can't be used on {0}
+    #[display(doc_comments)]
+    NetworkMismatch(Network, Network),
+
+    /// unable to export wallet descriptors: {0}
+    #[from]
+    #[display(doc_comments)]
+    BitcoinCoreExport(bitcoin_core::Error),
+
+    /// Electrum wallet file error: {0}
+    #[from]
+    #[display(doc_comments)]
+    ElectrumWallet(interop::electrum::Error),
+
+    /// Sparrow wallet file error: {0}
+    #[from]
+    #[display(doc_comments)]
+    SparrowWallet(sparrow::Error),
+
+    /// wallet uses separate change descriptor, which can't be exported in
+    /// {0} format
+    #[display(doc_comments)]
+    ChangeDescriptorExport(ExportFormat),
+
+    /// imported wallet file contains no descriptors
+    #[display(doc_comments)]
+    NoImportedDescriptors,
+
+    /// invalid watcher secret key: {0}
+    #[display(doc_comments)]
+    WatcherKey(secp256k1::Error),
+
+    /// invalid sync file: {0}
+    #[display(doc_comments)]
+    SyncEncoding(EnvelopeError),
+
+    /// sync bundle is rejected: {0}
+    #[from]
+    #[display(doc_comments)]
+    Sync(SyncError),
+
+    /// sync state file does not exist; provide watcher public key with
+    /// `--watcher` option to create it
+    #[display(doc_comments)]
+    SyncWatcherRequired,
+
+    /// sync state file trusts watcher {0}, which differs from the provided one
+    #[display(doc_comments)]
+    SyncWatcherMismatch(XOnlyPublicKey),
+
+    /// sync state file belongs to a different wallet
+    #[display(doc_comments)]
+    SyncStateMismatch,
+
+    /// PSBT contains {0} invalid or unverifiable signature(s)
+    #[display(doc_comments)]
+    InvalidSignatures(usize),
+
+    /// invalid wallet container file: {0}
+    #[display(doc_comments)]
+    ContainerEncoding(EnvelopeError),
+
+    #[from]
+    Container(ContainerError),
+}
+
+fn print_psbt_diff(diff: &PsbtDiff) {
+    fn marker<T>(from: &Option<T>, to: &Option<T>) -> colored::ColoredString {
+        match (from, to) {
+            (None, _) => "+".bright_green(),
+            (_, None) => "-".bright_red(),
+            _ => "~".bright_yellow(),
+        }
+    }
+    fn sig_hex(sig: &Signature) -> String {
+        match sig {
+            Signature::Ecdsa(sig) => sig.to_vec().to_hex(),
+            Signature::Schnorr(sig) => sig.to_vec().to_hex(),
+        }
+    }
+
+    if diff.from_txid != diff.to_txid {
+        println!(
+            "{} {} -> {}",
+            "Transaction".bright_white(),
+            diff.from_txid,
+            diff.to_txid
+        );
+    }
+    for change in &diff.signatures {
+        let value = change.to.as_ref().or(change.from.as_ref()).map(sig_hex);
+        println!(
+            "{} {:>6} {} {}",
+            marker(&change.from, &change.to),
+            format!("#{}", change.input).dimmed(),
+            change.source,
+            value.unwrap_or_default().dimmed()
+        );
+    }
+    for change in &diff.proprietary {
+        let hex = |value: &Option<Vec<u8>>| value.as_ref().map(Vec::to_hex).unwrap_or_default();
+        println!(
+            "{} {} {}({}) {}: {} -> {}",
+            marker(&change.from, &change.to),
+            change.location,
+            String::from_utf8_lossy(&change.key.prefix),
+            change.key.subtype,
+            change.key.key.to_hex(),
+            hex(&change.from).dimmed(),
+            hex(&change.to)
+        );
+    }
+    for change in &diff.outputs {
+        let txout = |txout: &Option<bitcoin::TxOut>| {
+            txout
+                .as_ref()
+                .map(|txout| format!("{} sat {}", txout.value, txout.script_pubkey.asm()))
+                .unwrap_or_default()
+        };
+        println!(
+            "{} output({}) {} -> {}",
+            marker(&change.from, &change.to),
+            change.index,
+            txout(&change.from).dimmed(),
+            txout(&change.to)
+        );
+    }
+    if diff.is_empty() {
+        println!("PSBTs are identical");
+    }
+    println!();
+}
+
+fn read_container(path: &Path) -> Result<Wallet, Error> {
+    Wallet::from_envelope(fs::read(path)?).map_err(Error::ContainerEncoding)
+}
+
+fn write_container(path: &Path, container: &Wallet) -> Result<(), Error> {
+    fs::write(
+        path,
+        container.to_envelope().map_err(Error::ContainerEncoding)?,
+    )?;
+    Ok(())
+}
+
+fn read_sync_file<T: Versioned>(path: &Path) -> Result<T, Error> {
+    T::from_envelope(fs::read(path)?).map_err(Error::SyncEncoding)
+}
+
+fn write_sync_file(path: &Path, data: &impl Versioned) -> Result<(), Error> {
+    fs::write(path, data.to_envelope().map_err(Error::SyncEncoding)?)?;
+    Ok(())
 }
 
 impl Error {
@@ -1046,8 +5196,70 @@ impl ToStringStd for miniscript::Descriptor<DerivationAccount> {
 }
 
 fn main() {
-    let args = Args::parse();
-    if let Err(err) = args.exec() {
+    let mut args = Args::parse();
+    if let Err(err) = args
+        .load_config()
+        .map_err(Error::from)
+        .and_then(|_| args.exec())
+    {
+        let report = ErrorReport {
+            error: err.to_string(),
+        };
+        // Error report is a best effort: the error is always printed to STDERR
+        let _ = args.report(&report);
         eprintln!("{}: {}\n", "Error".bright_red(), err);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn descriptor(class: &str, account: u8) -> miniscript::Descriptor<DerivationAccount> {
+        miniscript::Descriptor::from_str(&format!(
+            "{}([d34db33f/84h/0h/{}h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJP\
+             MM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/*/*)",
+            class, account
+        ))
+        .unwrap()
+    }
+
+    fn wallet() -> WalletFile {
+        let mut wallet = WalletFile {
+            descriptor: descriptor("pkh", 0),
+            change_descriptor: None,
+            history: vec![],
+            meta: WalletMeta::default(),
+        };
+        wallet.rotate(descriptor("wpkh", 1));
+        wallet.rotate(descriptor("wpkh", 2));
+        wallet
+    }
+
+    #[test]
+    fn previous_generations() {
+        let wallet = wallet();
+        let pkh = descriptor("pkh", 0);
+        let wpkh = descriptor("wpkh", 1);
+
+        assert_eq!(wallet.previous_generations(None).unwrap(), vec![
+            (0, &pkh),
+            (1, &wpkh)
+        ]);
+        assert_eq!(wallet.previous_generations(Some(1)).unwrap(), vec![(
+            1, &wpkh
+        )]);
+        assert!(matches!(
+            wallet.previous_generations(Some(2)),
+            Err(Error::UnknownGeneration(2))
+        ));
+    }
+
+    #[test]
+    fn deprecated_class() {
+        let wallet = wallet();
+        assert_eq!(wallet.deprecated_class(0), Some(DescriptorClass::PreSegwit));
+        assert_eq!(wallet.deprecated_class(1), None);
+        assert_eq!(wallet.deprecated_class(2), None);
+    }
+}
@@ -34,6 +34,8 @@ use colored::Colorize;
 use electrum_client as electrum;
 use electrum_client::ElectrumApi;
 use miniscript_crate::{Legacy, Miniscript, Segwitv0, Tap};
+use wallet::amount::Denomination;
+use wallet::onchain::{ElectrumConfig, ElectrumProtocol, ElectrumUrlError, PublicNetwork};
 
 /// Command-line arguments
 #[derive(Parser)]
@@ -49,7 +51,7 @@ pub struct Args {
     #[clap(subcommand)]
     pub command: Command,
 
-    /// Electrum server to use.
+    /// Electrum server to use, optionally prefixed with `tcp://` or `ssl://`.
     ///
     /// Used only by `check`, `history`, `construct` and some forms of
     /// `extract` command
@@ -61,10 +63,22 @@ pub struct Args {
     #[clap(short = 'p', global = true)]
     pub electrum_port: Option<u16>,
 
+    /// Connect electrum server using SSL.
+    #[clap(long, global = true)]
+    pub ssl: bool,
+
+    /// SOCKS5 proxy (`host:port`) for connecting electrum server.
+    #[clap(long, global = true)]
+    pub proxy: Option<String>,
+
     /// Publish the transaction to the network; optional argument allows
     /// to specify some custom network (testnet, for instance).
     #[clap(short, long, global = true, default_value = "bitcoin")]
     network: Network,
+
+    /// Denomination to display amounts in: `BTC`, `mBTC` or `sat`.
+    #[clap(long, global = true, default_value = "BTC")]
+    unit: Denomination,
 }
 
 /// Wallet command to execute
@@ -78,30 +92,28 @@ pub enum Command {
     },
 }
 
-fn default_electrum_port(network: Network) -> u16 {
-    match network {
-        Network::Bitcoin => 50001,
-        Network::Testnet => 60001,
-        Network::Signet | Network::Regtest => 60601,
-    }
-}
-
-const SATS_IN_BTC: u64 = 100_000_000;
-
 impl Args {
-    fn electrum_client(&self) -> Result<electrum::Client, electrum::Error> {
-        let electrum_url = format!(
-            "{}:{}",
-            self.electrum_server,
-            self.electrum_port
-                .unwrap_or_else(|| default_electrum_port(self.network))
-        );
+    fn electrum_client(&self) -> Result<electrum::Client, Error> {
+        let protocol = if self.ssl {
+            ElectrumProtocol::Ssl
+        } else {
+            ElectrumProtocol::Tcp
+        };
+        let mut config = ElectrumConfig::parse(
+            &self.electrum_server,
+            protocol,
+            PublicNetwork::from(self.network),
+        )?;
+        if let Some(port) = self.electrum_port {
+            config.port = port;
+        }
+        config.proxy = self.proxy.clone();
         eprintln!(
             "Connecting to network {} using {}",
             self.network.to_string().yellow(),
-            electrum_url.yellow()
+            config.to_string().yellow()
         );
-        electrum::Client::new(&electrum_url)
+        Ok(config.connect()?)
     }
 
     pub fn exec(self) -> Result<(), Error> {
@@ -143,13 +155,9 @@ impl Args {
             println!("  sequence value is {seq}");
 
             total_in += prevout.value;
-            let btc = prevout.value / SATS_IN_BTC;
             println!(
-                "  spending {} BTC, {} sats",
-                btc.to_string().bright_yellow(),
-                (prevout.value - btc * SATS_IN_BTC)
-                    .to_string()
-                    .bright_yellow()
+                "  spending {}",
+                self.unit.amount(prevout.value).to_string().bright_yellow()
             );
             let prev_addr = AddressCompat::from_script(
                 &prevout.script_pubkey.clone().into(),
@@ -341,15 +349,11 @@ impl Args {
 
         for (vout, txout) in tx.output.iter().enumerate() {
             total_out += txout.value;
-            let btc = txout.value / SATS_IN_BTC;
             println!(
-                "{} {} of {} BTC, {} sats",
+                "{} {} of {}",
                 (vout + 1).to_string().bright_white(),
                 "output".bright_white(),
-                btc.to_string().bright_yellow(),
-                (txout.value - btc * SATS_IN_BTC)
-                    .to_string()
-                    .bright_yellow()
+                self.unit.amount(txout.value).to_string().bright_yellow()
             );
             println!("  locked with {}", txout.script_pubkey);
             let addr_compat = AddressCompat::from_script(
@@ -366,24 +370,16 @@ impl Args {
         println!("  size is {} bytes", size);
         println!("  witness data size is {} bytes", witness_size);
         let fee = total_in - total_out;
-        let btc_in = total_in / SATS_IN_BTC;
-        let btc_out = total_out / SATS_IN_BTC;
         println!(
-            "Transaction spends {} BTC {} sats",
-            btc_in.to_string().bright_yellow(),
-            (total_in - btc_in * SATS_IN_BTC)
-                .to_string()
-                .bright_yellow()
+            "Transaction spends {}",
+            self.unit.amount(total_in).to_string().bright_yellow()
         );
         println!(
-            "  paying {} sats in fees ({:.2} sats per vbyte)",
-            fee.to_string().bright_yellow(),
+            "  paying {} in fees ({:.2} sats per vbyte)",
+            self.unit.amount(fee).to_string().bright_yellow(),
             fee as f32 / weight as f32
         );
-        println!(
-            "  sending {btc_out} BTC {} sats to its outputs",
-            total_out - btc_out * SATS_IN_BTC
-        );
+        println!("  sending {} to its outputs", self.unit.amount(total_out));
         println!();
 
         if let Ok(info) = electrum.transaction_get_merkle(txid, 0) {
@@ -421,6 +417,9 @@ pub enum Error {
 
     #[from]
     Electrum(electrum::Error),
+
+    #[from]
+    ElectrumUrl(ElectrumUrlError),
 }
 
 fn main() {
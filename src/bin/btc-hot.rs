@@ -48,8 +48,8 @@ use hwi::HWIClient;
 use miniscript::Descriptor;
 use miniscript_crate::ForEachKey;
 use psbt::serialize::{Deserialize, Serialize};
-use psbt::sign::{MemoryKeyProvider, MemorySigningAccount, SignAll, SignError};
-use psbt::Psbt;
+use psbt::sign::{MemoryKeyProvider, MemorySigningAccount, SignAll, SignError, SigningPolicy};
+use psbt::{Psbt, PsbtSighashType};
 use slip132::{KeyApplication, ToSlip132};
 use wallet::hd::standards::DerivationBlockchain;
 use wallet::hd::{Bip43, HardenedIndex};
@@ -452,6 +452,23 @@ pub enum Command {
         #[clap(short, long)]
         musig: bool,
 
+        /// Allow signing PSBTs mixing the given sighash type (like
+        /// `SIGHASH_SINGLE|SIGHASH_ANYONECANPAY`) with other sighash types
+        /// across transaction inputs. May be repeated.
+        #[clap(long = "allow-sighash")]
+        allow_sighash: Vec<PsbtSighashType>,
+
+        /// Refuse to sign PSBTs with inputs using sighash types other than
+        /// `SIGHASH_ALL` or missing `witness_utxo`, which is recommended for
+        /// PSBTs coming from less-trusted constructors
+        #[clap(long)]
+        strict: bool,
+
+        /// Refuse to sign PSBTs paying transaction fee above the given amount
+        /// of satoshis
+        #[clap(long)]
+        max_fee: Option<u64>,
+
         /// Seed password
         #[clap(short, long)]
         password: Option<String>,
@@ -507,10 +524,30 @@ impl Args {
             Command::Info { file, password } => self.info(file, password),
             Command::Sign {
                 musig,
+                allow_sighash,
+                strict,
+                max_fee,
                 psbt_file,
                 signing_account,
                 password,
-            } => self.sign(psbt_file, signing_account, *musig, password),
+            } => {
+                let policy = SigningPolicy {
+                    max_fee: *max_fee,
+                    ..if *strict {
+                        SigningPolicy::strict()
+                    } else {
+                        SigningPolicy::default()
+                    }
+                };
+                self.sign(
+                    psbt_file,
+                    signing_account,
+                    *musig,
+                    allow_sighash,
+                    &policy,
+                    password,
+                )
+            }
             Command::Key {
                 debug,
                 seed_file,
@@ -903,6 +940,8 @@ impl Args {
         psbt_path: &Path,
         account_path: &Path,
         musig: bool,
+        allow_sighash: &[PsbtSighashType],
+        policy: &SigningPolicy,
         password: &Option<String>,
     ) -> Result<(), Error> {
         let password = get_password(password.clone(), "Account password")?;
@@ -924,8 +963,11 @@ impl Args {
 
         let mut key_provider = MemoryKeyProvider::with(&secp, musig);
         key_provider.add_account(account);
+        for sighash_type in allow_sighash {
+            key_provider.allow_sighash_type(*sighash_type);
+        }
 
-        let sig_count = psbt.sign_all(&key_provider)?;
+        let sig_count = psbt.sign_all(&key_provider, policy)?;
         println!("Done {} signatures\n", sig_count.to_string().bright_green());
 
         fs::write(psbt_path, psbt.serialize())?;
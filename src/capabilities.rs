@@ -0,0 +1,265 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Runtime discovery of the features the library was compiled with.
+
+use std::fmt::{self, Formatter};
+
+use amplify::{Display, Error};
+
+/// Optional library feature which may be enabled at compile time.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Feature {
+    /// Miniscript-based descriptors (`miniscript` feature).
+    Miniscript,
+
+    /// Miniscript policy compiler (`compiler` feature).
+    Compiler,
+
+    /// Electrum server client (`electrum` feature).
+    Electrum,
+
+    /// Asynchronous resolver traits (`async` feature).
+    Async,
+
+    /// Asynchronous esplora server client (`esplora` feature).
+    Esplora,
+
+    /// Strict encoding of wallet data (`strict_encoding` feature).
+    StrictEncoding,
+
+    /// Key and seed generation (`keygen` feature).
+    Keygen,
+
+    /// PSBT construction from descriptors (`construct` feature).
+    Construct,
+
+    /// PSBT signing with in-memory keys (`sign` feature).
+    Sign,
+
+    /// Hot wallet seed storage (`hot` feature).
+    Hot,
+
+    /// Hardware signers via HWI (`hwi` feature).
+    Hwi,
+
+    /// Serde serialization of wallet data (`serde` feature).
+    Serde,
+
+    /// Deterministic test wallets (`fixtures` feature).
+    Fixtures,
+
+    /// Export of wallet descriptors to other wallet software (`export`
+    /// feature).
+    Export,
+
+    /// Configuration files (`config` feature).
+    Config,
+}
+
+impl Feature {
+    /// Lists all optional features known to the library.
+    pub const ALL: [Feature; 15] = [
+        Feature::Miniscript,
+        Feature::Compiler,
+        Feature::Electrum,
+        Feature::Async,
+        Feature::Esplora,
+        Feature::StrictEncoding,
+        Feature::Keygen,
+        Feature::Construct,
+        Feature::Sign,
+        Feature::Hot,
+        Feature::Hwi,
+        Feature::Serde,
+        Feature::Fixtures,
+        Feature::Export,
+        Feature::Config,
+    ];
+
+    /// Returns name of the cargo feature.
+    pub fn cargo_name(self) -> &'static str {
+        match self {
+            Feature::Miniscript => "miniscript",
+            Feature::Compiler => "compiler",
+            Feature::Electrum => "electrum",
+            Feature::Async => "async",
+            Feature::Esplora => "esplora",
+            Feature::StrictEncoding => "strict_encoding",
+            Feature::Keygen => "keygen",
+            Feature::Construct => "construct",
+            Feature::Sign => "sign",
+            Feature::Hot => "hot",
+            Feature::Hwi => "hwi",
+            Feature::Serde => "serde",
+            Feature::Fixtures => "fixtures",
+            Feature::Export => "export",
+            Feature::Config => "config",
+        }
+    }
+
+    /// Detects whether the feature was enabled at compile time.
+    pub fn is_enabled(self) -> bool {
+        match self {
+            Feature::Miniscript => cfg!(feature = "miniscript"),
+            Feature::Compiler => cfg!(feature = "compiler"),
+            Feature::Electrum => cfg!(feature = "electrum"),
+            Feature::Async => cfg!(feature = "async"),
+            Feature::Esplora => cfg!(feature = "esplora"),
+            Feature::StrictEncoding => cfg!(feature = "strict_encoding"),
+            Feature::Keygen => cfg!(feature = "keygen"),
+            Feature::Construct => cfg!(feature = "construct"),
+            Feature::Sign => cfg!(feature = "sign"),
+            Feature::Hot => cfg!(feature = "hot"),
+            Feature::Hwi => cfg!(feature = "hwi"),
+            Feature::Serde => cfg!(feature = "serde"),
+            Feature::Fixtures => cfg!(feature = "fixtures"),
+            Feature::Export => cfg!(feature = "export"),
+            Feature::Config => cfg!(feature = "config"),
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str(self.cargo_name()) }
+}
+
+/// Backend able to produce signatures for PSBT inputs.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Display)]
+#[display(lowercase)]
+pub enum SignerBackend {
+    /// Signing with extended private keys kept in memory.
+    Memory,
+
+    /// Signing with keys from encrypted hot wallet seed files.
+    Hot,
+
+    /// Signing with hardware wallets via HWI.
+    Hwi,
+}
+
+/// Error returned when an operation requires a feature which was not compiled
+/// in.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(
+    "operation requires `{0}` feature, which is not compiled in; rebuild with `--features {0}`"
+)]
+pub struct MissingFeature(pub Feature);
+
+/// Structured description of the features the library was compiled with.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Capabilities {
+    /// Version of the library.
+    pub version: &'static str,
+
+    /// Optional features enabled at compile time.
+    pub features: Vec<Feature>,
+
+    /// Whether taproot script tree extensions (tree construction, merkle
+    /// proofs, disclosure of hidden nodes) are available. They are always
+    /// compiled in.
+    pub taproot_extensions: bool,
+
+    /// Signer backends available for PSBT signing.
+    pub signers: Vec<SignerBackend>,
+}
+
+impl Capabilities {
+    /// Checks whether the feature is compiled in.
+    pub fn has(&self, feature: Feature) -> bool { self.features.contains(&feature) }
+
+    /// Checks whether the signer backend is compiled in.
+    pub fn has_signer(&self, signer: SignerBackend) -> bool { self.signers.contains(&signer) }
+
+    /// Returns [`MissingFeature`] error if the feature is not compiled in, to
+    /// be used by the operations depending on the feature.
+    pub fn require(&self, feature: Feature) -> Result<(), MissingFeature> {
+        if self.has(feature) {
+            Ok(())
+        } else {
+            Err(MissingFeature(feature))
+        }
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "version: {}", self.version)?;
+        for feature in Feature::ALL {
+            let flag = if self.has(feature) { "+" } else { "-" };
+            writeln!(f, "{}{}", flag, feature)?;
+        }
+        writeln!(
+            f,
+            "taproot extensions: {}",
+            if self.taproot_extensions { "yes" } else { "no" }
+        )?;
+        write!(f, "signers:")?;
+        if self.signers.is_empty() {
+            write!(f, " none")?;
+        }
+        for signer in &self.signers {
+            write!(f, " {}", signer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns description of the features the library was compiled with.
+pub fn capabilities() -> Capabilities {
+    let features = Feature::ALL
+        .into_iter()
+        .filter(|feature| feature.is_enabled())
+        .collect();
+    let signers = [
+        (SignerBackend::Memory, Feature::Sign),
+        (SignerBackend::Hot, Feature::Hot),
+        (SignerBackend::Hwi, Feature::Hwi),
+    ]
+    .into_iter()
+    .filter(|(_, feature)| feature.is_enabled())
+    .map(|(signer, _)| signer)
+    .collect();
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        features,
+        taproot_extensions: true,
+        signers,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compiled_features() {
+        let caps = capabilities();
+        assert_eq!(caps.has(Feature::Sign), cfg!(feature = "sign"));
+        assert_eq!(
+            caps.has_signer(SignerBackend::Memory),
+            cfg!(feature = "sign")
+        );
+        assert_eq!(caps.has_signer(SignerBackend::Hwi), cfg!(feature = "hwi"));
+        assert!(caps.taproot_extensions);
+        if !cfg!(feature = "electrum") {
+            let err = caps.require(Feature::Electrum).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "operation requires `electrum` feature, which is not compiled in; rebuild with \
+                 `--features electrum`"
+            );
+        }
+    }
+}
@@ -0,0 +1,310 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Wallet configuration shared by command-line tools, stored in TOML file
+//! (by default `~/.config/descriptor-wallet/config.toml`).
+//!
+//! ```toml
+//! wallet_dir = "/home/user/wallets"
+//! proxy = "127.0.0.1:9050"
+//! dns_resolver = "https://dns.google/resolve"
+//!
+//! [electrum.bitcoin]
+//! server = "electrum.blockstream.info"
+//! port = 50001
+//!
+//! [electrum.testnet]
+//! server = "testnet.example.com"
+//! ssl = true
+//!
+//! [feerate]
+//! source = "electrum"
+//! target_blocks = 6
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use amplify::{Display, Error, From};
+use bitcoin::Network;
+use bitcoin_onchain::PublicNetwork;
+use serde_crate::{Deserialize, Serialize};
+
+/// Name of the directory containing configuration file inside the user
+/// configuration directory.
+pub const CONFIG_DIR: &str = "descriptor-wallet";
+
+/// Name of the configuration file.
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Default electrum server used when no server is configured for a network.
+pub const DEFAULT_ELECTRUM_SERVER: &str = "electrum.blockstream.info";
+
+/// Default DNS-over-HTTPS resolver used when no resolver is configured.
+pub const DEFAULT_DNS_RESOLVER: &str = "https://cloudflare-dns.com/dns-query";
+
+/// Errors reading and writing configuration files
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ConfigError {
+    /// unable to access configuration file: {0}
+    #[from]
+    Io(io::Error),
+
+    /// invalid configuration file: {0}
+    #[from]
+    Parse(toml::de::Error),
+
+    /// unable to serialize configuration: {0}
+    #[from]
+    Serialize(toml::ser::Error),
+}
+
+/// Electrum server endpoint
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate")]
+pub struct ElectrumServer {
+    /// Server host name or IP address
+    pub server: String,
+
+    /// Server port; if absent, the default port for the network is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+
+    /// Whether the server must be connected with SSL
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ssl: bool,
+}
+
+impl ElectrumServer {
+    /// Returns default electrum port for a network.
+    pub fn default_port(network: Network) -> u16 { PublicNetwork::from(network).electrum_port() }
+
+    /// Returns default SSL electrum port for a network.
+    pub fn default_ssl_port(network: Network) -> u16 {
+        PublicNetwork::from(network).electrum_ssl_port()
+    }
+
+    /// Returns `host:port` server address (prefixed with `ssl://` for SSL
+    /// servers), using default port for the network if no port is configured.
+    pub fn address(&self, network: Network) -> String {
+        match self.ssl {
+            false => format!(
+                "{}:{}",
+                self.server,
+                self.port.unwrap_or_else(|| Self::default_port(network))
+            ),
+            true => format!(
+                "ssl://{}:{}",
+                self.server,
+                self.port.unwrap_or_else(|| Self::default_ssl_port(network))
+            ),
+        }
+    }
+}
+
+/// Source of the feerate used when no feerate is given explicitly
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(crate = "serde_crate", tag = "source", rename_all = "lowercase")]
+pub enum FeerateSource {
+    /// Fixed feerate
+    Fixed {
+        /// Feerate in satoshis per vbyte
+        sat_per_vbyte: u32,
+    },
+
+    /// Feerate estimated by electrum server
+    Electrum {
+        /// Number of blocks in which the transaction should be confirmed
+        target_blocks: u16,
+    },
+}
+
+impl Default for FeerateSource {
+    fn default() -> Self { FeerateSource::Fixed { sat_per_vbyte: 1 } }
+}
+
+/// Wallet configuration
+#[derive(Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "serde_crate", default)]
+pub struct Config {
+    /// Directory containing wallet files, used to resolve relative wallet file
+    /// paths
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wallet_dir: Option<PathBuf>,
+
+    /// SOCKS5 proxy address (`host:port`) for network connections
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+
+    /// URL of DNS-over-HTTPS resolver supporting JSON API and DNSSEC
+    /// validation, used for resolving BIP-353 names
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_resolver: Option<String>,
+
+    /// Electrum servers, per network name (`bitcoin`, `testnet`, `signet` or
+    /// `regtest`)
+    pub electrum: BTreeMap<String, ElectrumServer>,
+
+    /// Source of the default feerate
+    pub feerate: FeerateSource,
+}
+
+impl Config {
+    /// Returns path to the default configuration file, located in
+    /// `$XDG_CONFIG_HOME` or in `~/.config` directory. Returns `None` if the
+    /// user home directory is unknown.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join(CONFIG_DIR).join(CONFIG_FILE))
+    }
+
+    /// Parses configuration from TOML string.
+    pub fn from_toml(s: &str) -> Result<Config, ConfigError> { Ok(toml::from_str(s)?) }
+
+    /// Serializes configuration into TOML string.
+    pub fn to_toml(&self) -> Result<String, ConfigError> { Ok(toml::to_string(self)?) }
+
+    /// Reads configuration from a file.
+    pub fn read(path: impl AsRef<Path>) -> Result<Config, ConfigError> {
+        Config::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Reads configuration from a file, if it exists, or from the default
+    /// configuration file location otherwise. Returns default configuration
+    /// if the default configuration file does not exist.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        match (path, Config::default_path()) {
+            (Some(path), _) => Config::read(path),
+            (None, Some(path)) if path.exists() => Config::read(path),
+            (None, _) => Ok(Config::default()),
+        }
+    }
+
+    /// Writes configuration into a file, creating parent directories if
+    /// necessary.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    /// Returns electrum server configured for a network.
+    pub fn electrum_server(&self, network: Network) -> Option<&ElectrumServer> {
+        self.electrum.get(&network.to_string())
+    }
+
+    /// Returns `host:port` address of the electrum server for a network,
+    /// falling back to [`DEFAULT_ELECTRUM_SERVER`] if the network has no
+    /// configured server.
+    pub fn electrum_address(&self, network: Network) -> String {
+        match self.electrum_server(network) {
+            Some(server) => server.address(network),
+            None => ElectrumServer {
+                server: DEFAULT_ELECTRUM_SERVER.to_owned(),
+                port: None,
+                ssl: false,
+            }
+            .address(network),
+        }
+    }
+
+    /// Returns URL of DNS-over-HTTPS resolver, falling back to
+    /// [`DEFAULT_DNS_RESOLVER`] if no resolver is configured.
+    pub fn dns_resolver(&self) -> &str {
+        self.dns_resolver.as_deref().unwrap_or(DEFAULT_DNS_RESOLVER)
+    }
+
+    /// Resolves relative wallet file path against the configured wallet
+    /// directory. Absolute paths and paths to existing files are returned
+    /// unchanged.
+    pub fn wallet_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        match &self.wallet_dir {
+            Some(dir) if path.is_relative() && !path.exists() => dir.join(path),
+            _ => path.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_toml() {
+        let config = Config::from_toml(
+            r#"
+            wallet_dir = "/wallets"
+            proxy = "127.0.0.1:9050"
+            dns_resolver = "https://dns.google/resolve"
+
+            [electrum.bitcoin]
+            server = "electrum.example.com"
+            port = 50002
+
+            [electrum.testnet]
+            server = "localhost"
+
+            [electrum.signet]
+            server = "signet.example.com"
+            ssl = true
+
+            [feerate]
+            source = "electrum"
+            target_blocks = 6
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.electrum_address(Network::Bitcoin),
+            "electrum.example.com:50002"
+        );
+        assert_eq!(config.electrum_address(Network::Testnet), "localhost:60001");
+        assert_eq!(
+            config.electrum_address(Network::Signet),
+            "ssl://signet.example.com:60602"
+        );
+        assert_eq!(
+            config.electrum_address(Network::Regtest),
+            "electrum.blockstream.info:60401"
+        );
+        assert_eq!(config.feerate, FeerateSource::Electrum { target_blocks: 6 });
+        assert_eq!(config.proxy.as_deref(), Some("127.0.0.1:9050"));
+        assert_eq!(config.dns_resolver(), "https://dns.google/resolve");
+        assert_eq!(Config::default().dns_resolver(), DEFAULT_DNS_RESOLVER);
+        assert_eq!(
+            config.wallet_path("missing.wallet"),
+            PathBuf::from("/wallets/missing.wallet")
+        );
+        assert_eq!(
+            config.wallet_path("/tmp/my.wallet"),
+            PathBuf::from("/tmp/my.wallet")
+        );
+
+        assert_eq!(
+            Config::from_toml(&config.to_toml().unwrap()).unwrap(),
+            config
+        );
+        assert_eq!(Config::from_toml("").unwrap(), Config::default());
+    }
+}
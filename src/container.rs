@@ -0,0 +1,259 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Multi-account wallet container, holding several named output descriptors
+//! (like receive, change or multisig co-accounts) together with the shared
+//! wallet metadata in a single strict-encoded file.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::str::FromStr;
+
+use bitcoin_hd::{DerivationAccount, UnhardenedIndex};
+use miniscript_crate::Descriptor;
+use strict_encoding::{StrictDecode, StrictEncode};
+
+use crate::envelope::Versioned;
+use crate::meta::WalletMeta;
+
+/// Errors managing accounts of the wallet container
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ContainerError {
+    /// wallet already contains account named `{0}`
+    DuplicateAccount(String),
+
+    /// wallet contains no account named `{0}`
+    UnknownAccount(String),
+
+    /// invalid account name `{0}`; account names must be non-empty and must
+    /// not contain whitespaces
+    InvalidName(String),
+}
+
+/// Multi-account wallet container.
+///
+/// Each account is an output descriptor using [`DerivationAccount`] keys,
+/// which is identified by a unique name. Wallet metadata (address labels,
+/// frozen UTXOs etc.) are shared by all accounts.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Wallet {
+    accounts: BTreeMap<String, Descriptor<DerivationAccount>>,
+
+    /// Metadata shared by all wallet accounts
+    pub meta: WalletMeta,
+}
+
+impl Wallet {
+    /// Constructs empty wallet container
+    #[inline]
+    pub fn new() -> Wallet { Wallet::default() }
+
+    /// Returns number of accounts in the wallet
+    #[inline]
+    pub fn len(&self) -> usize { self.accounts.len() }
+
+    /// Detects whether the wallet has no accounts
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.accounts.is_empty() }
+
+    /// Iterates over wallet accounts in the order of their names
+    #[inline]
+    pub fn accounts(&self) -> impl Iterator<Item = (&str, &Descriptor<DerivationAccount>)> {
+        self.accounts
+            .iter()
+            .map(|(name, descriptor)| (name.as_str(), descriptor))
+    }
+
+    /// Returns descriptor of the account with a given name, if any
+    #[inline]
+    pub fn account(&self, name: &str) -> Option<&Descriptor<DerivationAccount>> {
+        self.accounts.get(name)
+    }
+
+    /// Returns version of the descriptor of the account with a given name
+    /// used for derivation at a given `index` of the last terminal derivation
+    /// step, applying key rotations from the wallet metadata.
+    pub fn account_at(
+        &self,
+        name: &str,
+        index: impl Into<UnhardenedIndex>,
+    ) -> Option<Descriptor<DerivationAccount>> {
+        self.accounts.get(name).map(|descriptor| {
+            self.meta
+                .key_rotations
+                .effective_descriptor(descriptor, index)
+        })
+    }
+
+    /// Returns all versions of the descriptor of the account with a given
+    /// name produced by key rotations, together with the derivation index
+    /// starting from which each version is used.
+    pub fn account_versions(
+        &self,
+        name: &str,
+    ) -> Option<Vec<(UnhardenedIndex, Descriptor<DerivationAccount>)>> {
+        self.accounts
+            .get(name)
+            .map(|descriptor| self.meta.key_rotations.descriptor_versions(descriptor))
+    }
+
+    /// Adds new named account to the wallet.
+    ///
+    /// Errors if the name is already used by another account or is not a
+    /// valid account name.
+    pub fn add_account(
+        &mut self,
+        name: impl ToString,
+        descriptor: Descriptor<DerivationAccount>,
+    ) -> Result<(), ContainerError> {
+        let name = name.to_string();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(ContainerError::InvalidName(name));
+        }
+        if self.accounts.contains_key(&name) {
+            return Err(ContainerError::DuplicateAccount(name));
+        }
+        self.accounts.insert(name, descriptor);
+        Ok(())
+    }
+
+    /// Removes account with a given name from the wallet, returning its
+    /// descriptor.
+    pub fn remove_account(
+        &mut self,
+        name: &str,
+    ) -> Result<Descriptor<DerivationAccount>, ContainerError> {
+        self.accounts
+            .remove(name)
+            .ok_or_else(|| ContainerError::UnknownAccount(name.to_owned()))
+    }
+}
+
+// Descriptors are stored in their string representation, which is the only
+// representation stable across miniscript versions
+impl StrictEncode for Wallet {
+    fn strict_encode<E: io::Write>(&self, mut e: E) -> Result<usize, strict_encoding::Error> {
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|(name, descriptor)| (name.clone(), descriptor.to_string()))
+            .collect::<BTreeMap<_, _>>();
+        Ok(accounts.strict_encode(&mut e)? + self.meta.strict_encode(&mut e)?)
+    }
+}
+
+impl StrictDecode for Wallet {
+    fn strict_decode<D: io::Read>(mut d: D) -> Result<Self, strict_encoding::Error> {
+        let accounts = BTreeMap::<String, String>::strict_decode(&mut d)?
+            .into_iter()
+            .map(|(name, descriptor)| {
+                Descriptor::from_str(&descriptor)
+                    .map(|descriptor| (name, descriptor))
+                    .map_err(|err| {
+                        strict_encoding::Error::DataIntegrityError(format!(
+                            "invalid wallet account descriptor: {}",
+                            err
+                        ))
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Wallet {
+            accounts,
+            meta: WalletMeta::strict_decode(&mut d)?,
+        })
+    }
+}
+
+impl Versioned for Wallet {
+    const MAGIC: [u8; 4] = *b"DWCT";
+    const VERSION: u16 = 1;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ACCOUNT: &str = "[d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZw\
+                           QY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5";
+    const ROTATED: &str = "[d34db33f/84h/0h/1h]xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSik\
+                           HjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+
+    #[test]
+    fn container_accounts() {
+        let receive = Descriptor::from_str(&format!("wpkh({}/0/*)", ACCOUNT)).unwrap();
+        let change = Descriptor::from_str(&format!("wpkh({}/1/*)", ACCOUNT)).unwrap();
+
+        let mut wallet = Wallet::new();
+        wallet.add_account("receive", receive.clone()).unwrap();
+        wallet.add_account("change", change.clone()).unwrap();
+        assert_eq!(
+            wallet.add_account("change", receive.clone()),
+            Err(ContainerError::DuplicateAccount(s!("change")))
+        );
+        assert_eq!(
+            wallet.add_account("co signer", receive.clone()),
+            Err(ContainerError::InvalidName(s!("co signer")))
+        );
+        assert_eq!(
+            wallet.accounts().map(|(name, _)| name).collect::<Vec<_>>(),
+            vec!["change", "receive"]
+        );
+
+        let data = wallet.strict_serialize().unwrap();
+        let decoded = Wallet::strict_deserialize(data.clone()).unwrap();
+        assert_eq!(decoded, wallet);
+        assert_eq!(decoded.account("receive"), Some(&receive));
+        assert_eq!(Wallet::from_envelope(data).unwrap(), wallet);
+        assert_eq!(
+            Wallet::from_envelope(wallet.to_envelope().unwrap()).unwrap(),
+            wallet
+        );
+
+        assert_eq!(wallet.remove_account("change"), Ok(change));
+        assert_eq!(
+            wallet.remove_account("change"),
+            Err(ContainerError::UnknownAccount(s!("change")))
+        );
+        assert_eq!(wallet.len(), 1);
+    }
+
+    #[test]
+    fn container_key_rotation() {
+        let receive = Descriptor::from_str(&format!("wpkh({}/0/*)", ACCOUNT)).unwrap();
+        let rotated = Descriptor::from_str(&format!("wpkh({}/0/*)", ROTATED)).unwrap();
+        let old = DerivationAccount::from_str(&format!("{}/0/*", ACCOUNT)).unwrap();
+        let new = DerivationAccount::from_str(&format!("{}/0/*", ROTATED)).unwrap();
+
+        let mut wallet = Wallet::new();
+        wallet.add_account("receive", receive.clone()).unwrap();
+        wallet
+            .meta
+            .key_rotations
+            .supersede(&old, 20u8, new)
+            .unwrap();
+        assert_eq!(wallet.account_at("receive", 19u8), Some(receive.clone()));
+        assert_eq!(wallet.account_at("receive", 20u8), Some(rotated.clone()));
+        assert_eq!(
+            wallet.account_versions("receive"),
+            Some(vec![
+                (UnhardenedIndex::from(0u8), receive),
+                (UnhardenedIndex::from(20u8), rotated)
+            ])
+        );
+
+        let data = wallet.strict_serialize().unwrap();
+        assert_eq!(Wallet::strict_deserialize(data).unwrap(), wallet);
+    }
+}
@@ -0,0 +1,178 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Versioned envelope for strict-encoded wallet artifacts (wallet containers,
+//! wallet metadata, sync state and bundles), allowing them to survive crate
+//! upgrades.
+//!
+//! Envelope starts with four magic bytes identifying the artifact type,
+//! followed by a 16-bit little-endian encoding version and the strict-encoded
+//! payload:
+//!
+//! ```text
+//! MAGIC[4] || VERSION: u16 || PAYLOAD
+//! ```
+//!
+//! Payload must be entirely consumed by decoding: data following the known
+//! payload fields are rejected rather than silently dropped on re-encoding.
+//! Thus any change to the payload fields, including appending new fields,
+//! requires bumping [`Versioned::VERSION`] and converting payloads of the
+//! previous versions in [`Versioned::migrate`]. Payloads of newer versions are
+//! rejected with [`EnvelopeError::FutureVersion`]. Data without envelope,
+//! produced by the releases preceding envelope introduction, are decoded with
+//! [`Versioned::migrate_legacy`].
+
+use strict_encoding::{StrictDecode, StrictEncode};
+
+/// Length of the envelope header: magic bytes and encoding version
+pub const ENVELOPE_HEADER_LEN: usize = 6;
+
+/// Errors decoding and encoding versioned envelopes
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum EnvelopeError {
+    /// data use encoding version {found}, which is newer than the most recent
+    /// version {supported} known to this software; please upgrade it
+    FutureVersion {
+        /// Version of the data
+        found: u16,
+        /// Most recent version supported by the software
+        supported: u16,
+    },
+
+    /// data use obsolete encoding version {0}, which can't be migrated
+    UnsupportedVersion(u16),
+
+    /// invalid data encoding: {0}
+    #[from]
+    Encoding(strict_encoding::Error),
+}
+
+/// Artifacts serialized with a versioned envelope
+pub trait Versioned: StrictEncode + StrictDecode + Sized {
+    /// Magic bytes identifying artifact type
+    const MAGIC: [u8; 4];
+
+    /// Current encoding version; must be increased with each change to the
+    /// strict-encoded fields of the artifact
+    const VERSION: u16;
+
+    /// Migration hook decoding payload of an older encoding `version`.
+    ///
+    /// Defaults to [`EnvelopeError::UnsupportedVersion`] error.
+    fn migrate(version: u16, payload: &[u8]) -> Result<Self, EnvelopeError> {
+        let _ = payload;
+        Err(EnvelopeError::UnsupportedVersion(version))
+    }
+
+    /// Migration hook decoding data which are not wrapped into an envelope.
+    ///
+    /// Defaults to decoding data with the current strict encoding.
+    fn migrate_legacy(data: &[u8]) -> Result<Self, EnvelopeError> {
+        Ok(Self::strict_deserialize(data)?)
+    }
+
+    /// Serializes artifact with an envelope using the current encoding
+    /// version
+    fn to_envelope(&self) -> Result<Vec<u8>, EnvelopeError> {
+        let mut data = Self::MAGIC.to_vec();
+        data.extend(Self::VERSION.to_le_bytes());
+        self.strict_encode(&mut data)?;
+        Ok(data)
+    }
+
+    /// Deserializes artifact from data with an envelope, migrating data of
+    /// older versions and data without envelope.
+    fn from_envelope(data: impl AsRef<[u8]>) -> Result<Self, EnvelopeError> {
+        let data = data.as_ref();
+        if data.len() < ENVELOPE_HEADER_LEN || data[..4] != Self::MAGIC {
+            return Self::migrate_legacy(data);
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        let payload = &data[ENVELOPE_HEADER_LEN..];
+        if version > Self::VERSION {
+            Err(EnvelopeError::FutureVersion {
+                found: version,
+                supported: Self::VERSION,
+            })
+        } else if version < Self::VERSION {
+            Self::migrate(version, payload)
+        } else {
+            Ok(Self::strict_deserialize(payload)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    #[derive(StrictEncode, StrictDecode)]
+    struct Artifact {
+        value: u32,
+    }
+
+    impl Versioned for Artifact {
+        const MAGIC: [u8; 4] = *b"TEST";
+        const VERSION: u16 = 2;
+
+        fn migrate(version: u16, mut payload: &[u8]) -> Result<Self, EnvelopeError> {
+            match version {
+                1 => Ok(Artifact {
+                    value: u16::strict_decode(&mut payload)? as u32,
+                }),
+                _ => Err(EnvelopeError::UnsupportedVersion(version)),
+            }
+        }
+    }
+
+    #[test]
+    fn envelope() {
+        let artifact = Artifact { value: 0xdead_beef };
+        let mut data = artifact.to_envelope().unwrap();
+        assert_eq!(&data[..6], b"TEST\x02\x00");
+        assert_eq!(Artifact::from_envelope(&data).unwrap(), artifact);
+
+        // Unknown appended fields are rejected
+        data.extend([0xFF; 4]);
+        assert!(matches!(
+            Artifact::from_envelope(&data),
+            Err(EnvelopeError::Encoding(
+                strict_encoding::Error::DataNotEntirelyConsumed
+            ))
+        ));
+
+        data[4] = 3;
+        assert!(matches!(
+            Artifact::from_envelope(&data),
+            Err(EnvelopeError::FutureVersion {
+                found: 3,
+                supported: 2
+            })
+        ));
+
+        assert_eq!(
+            Artifact::from_envelope(b"TEST\x01\x00\x2a\x00").unwrap(),
+            Artifact { value: 42 }
+        );
+        assert!(matches!(
+            Artifact::from_envelope(b"TEST\x00\x00\x2a\x00"),
+            Err(EnvelopeError::UnsupportedVersion(0))
+        ));
+
+        let legacy = artifact.strict_serialize().unwrap();
+        assert_eq!(Artifact::from_envelope(legacy).unwrap(), artifact);
+    }
+}
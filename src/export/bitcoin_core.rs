@@ -0,0 +1,292 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Bitcoin Core descriptor wallet interoperability: rendering wallet
+//! descriptors as `importdescriptors` request and parsing `listdescriptors`
+//! output.
+//!
+//! Bitcoin Core keeps separate descriptors for receive (external) and change
+//! (internal) addresses. Wallet descriptors with two-step derive pattern
+//! (like `/*/*`) are split into `/0/*` and `/1/*` descriptors on export, and
+//! such pairs are merged back into a single descriptor on import.
+
+use std::convert::Infallible;
+use std::str::FromStr;
+
+use amplify::{Display, Error, From};
+use bitcoin_hd::{DerivationAccount, DeriveError, SegmentIndexes, TerminalStep, UnhardenedIndex};
+use descriptors::derive::Descriptor as _;
+use miniscript_crate::{translate_hash_clone, Descriptor, TranslatePk, Translator};
+use serde_json::{json, Value};
+
+/// Default last index of the address range which Bitcoin Core has to watch
+/// for the ranged descriptors.
+pub const DEFAULT_RANGE_END: u32 = 999;
+
+/// Time since which Bitcoin Core has to rescan the blockchain for the
+/// transactions of the imported descriptors.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Timestamp {
+    /// Skip rescanning: the wallet is new and has no transactions.
+    #[default]
+    Now,
+
+    /// Rescan blocks starting from the given UNIX timestamp.
+    Time(u64),
+}
+
+impl Timestamp {
+    fn to_json(self) -> Value {
+        match self {
+            Timestamp::Now => json!("now"),
+            Timestamp::Time(time) => json!(time),
+        }
+    }
+}
+
+/// Errors converting descriptors to and from Bitcoin Core format
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum Error {
+    /// invalid JSON data: {0}
+    #[from]
+    Json(serde_json::Error),
+
+    /// `listdescriptors` output does not contain `descriptors` array
+    NoDescriptors,
+
+    /// descriptor entry #{0} does not contain `desc` string
+    NoDescriptorString(usize),
+
+    /// invalid descriptor in entry #{0}: {1}
+    Descriptor(usize, miniscript_crate::Error),
+
+    /// descriptor derive pattern has {0} wildcard steps, while Bitcoin Core
+    /// export supports at most two of them (for keychain and address index)
+    DerivePattern(usize),
+
+    /// inconsistent descriptor keys: {0}
+    #[from]
+    Derive(DeriveError),
+}
+
+/// Replaces keychain step of the derive pattern with a specific index.
+struct KeychainPin(UnhardenedIndex);
+
+impl Translator<DerivationAccount, DerivationAccount, Infallible> for KeychainPin {
+    fn pk(&mut self, pk: &DerivationAccount) -> Result<DerivationAccount, Infallible> {
+        let mut pk = pk.clone();
+        if let Some(step) = pk.terminal_path.iter_mut().find(|step| step.count() > 1) {
+            *step = TerminalStep::Index(self.0);
+        }
+        Ok(pk)
+    }
+
+    translate_hash_clone!(DerivationAccount, DerivationAccount, Infallible);
+}
+
+/// Replaces keychain index preceding the terminal wildcard with a wildcard,
+/// collecting the keychain index, which must be the same for all keys.
+#[derive(Default)]
+struct KeychainWildcard(Option<UnhardenedIndex>);
+
+impl Translator<DerivationAccount, DerivationAccount, ()> for KeychainWildcard {
+    fn pk(&mut self, pk: &DerivationAccount) -> Result<DerivationAccount, ()> {
+        let mut pk = pk.clone();
+        let len = pk.terminal_path.len();
+        if len < 2 || pk.terminal_path[len - 1] != TerminalStep::Wildcard {
+            return Err(());
+        }
+        let keychain = match pk.terminal_path[len - 2] {
+            TerminalStep::Index(index) => index,
+            _ => return Err(()),
+        };
+        match self.0 {
+            None => self.0 = Some(keychain),
+            Some(prev) if prev != keychain => return Err(()),
+            Some(_) => {}
+        }
+        pk.terminal_path[len - 2] = TerminalStep::Wildcard;
+        Ok(pk)
+    }
+
+    translate_hash_clone!(DerivationAccount, DerivationAccount, ());
+}
+
+/// Replaces keychain index in descriptor keys having `/<keychain>/*` terminal
+/// path with a wildcard, returning the resulting descriptor and the keychain
+/// index. Returns `None` if the descriptor keys do not follow the pattern or
+/// use different keychains.
+pub(crate) fn keychain_template(
+    descriptor: &Descriptor<DerivationAccount>,
+) -> Option<(Descriptor<DerivationAccount>, UnhardenedIndex)> {
+    let mut translator = KeychainWildcard::default();
+    let template = descriptor.translate_pk(&mut translator).ok()?;
+    Some((template, translator.0?))
+}
+
+/// Produces descriptor for a single keychain (`0` for receive and `1` for
+/// change addresses) out of the wallet descriptor.
+pub(crate) fn keychain_descriptor(
+    descriptor: &Descriptor<DerivationAccount>,
+    keychain: u8,
+) -> Result<Descriptor<DerivationAccount>, Error> {
+    match descriptor.derive_pattern_len()? {
+        0 | 1 => Ok(descriptor.clone()),
+        2 => Ok(descriptor
+            .translate_pk(&mut KeychainPin(UnhardenedIndex::from(keychain)))
+            .expect("infallible")),
+        len => Err(Error::DerivePattern(len)),
+    }
+}
+
+fn descriptor_entry(
+    descriptor: &Descriptor<DerivationAccount>,
+    internal: Option<bool>,
+    timestamp: Timestamp,
+    range_end: u32,
+) -> Result<Value, Error> {
+    let mut entry = json!({
+        "desc": descriptor.to_string(),
+        "timestamp": timestamp.to_json(),
+    });
+    if descriptor.derive_pattern_len()? > 0 {
+        entry["active"] = json!(true);
+        entry["range"] = json!([0, range_end]);
+    }
+    if let Some(internal) = internal {
+        entry["internal"] = json!(internal);
+    }
+    Ok(entry)
+}
+
+/// Renders wallet descriptor as a JSON array for Bitcoin Core
+/// `importdescriptors` command.
+///
+/// Descriptors are rendered in Bitcoin Core key format with checksums.
+/// Wallet descriptors with two-step derive pattern are split into receive and
+/// change descriptors; if a separate `change_descriptor` is given, it is used
+/// for the change addresses instead. Ranged descriptors are marked as active
+/// and watched for the addresses with indexes `0..=range_end`.
+pub fn import_descriptors(
+    descriptor: &Descriptor<DerivationAccount>,
+    change_descriptor: Option<&Descriptor<DerivationAccount>>,
+    timestamp: Timestamp,
+    range_end: u32,
+) -> Result<Value, Error> {
+    let external = keychain_descriptor(descriptor, 0)?;
+    if change_descriptor.is_none() && descriptor.derive_pattern_len()? < 2 {
+        let entry = descriptor_entry(&external, None, timestamp, range_end)?;
+        return Ok(json!([entry]));
+    }
+    let internal = keychain_descriptor(change_descriptor.unwrap_or(descriptor), 1)?;
+    Ok(json!([
+        descriptor_entry(&external, Some(false), timestamp, range_end)?,
+        descriptor_entry(&internal, Some(true), timestamp, range_end)?,
+    ]))
+}
+
+/// Parses output of Bitcoin Core `listdescriptors` command into the list of
+/// wallet descriptors.
+///
+/// Pairs of receive (`/0/*`) and change (`/1/*`) descriptors differing only
+/// in the keychain index are merged into a single descriptor with `/*/*`
+/// derive pattern; all other descriptors are returned as they are.
+pub fn parse_list_descriptors(json: &str) -> Result<Vec<Descriptor<DerivationAccount>>, Error> {
+    let value: Value = serde_json::from_str(json)?;
+    let entries = value
+        .get("descriptors")
+        .and_then(Value::as_array)
+        .ok_or(Error::NoDescriptors)?;
+
+    let descriptors = entries
+        .iter()
+        .enumerate()
+        .map(|(no, entry)| {
+            let desc = entry
+                .get("desc")
+                .and_then(Value::as_str)
+                .ok_or(Error::NoDescriptorString(no))?;
+            Descriptor::from_str(desc).map_err(|err| Error::Descriptor(no, err))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let templates = descriptors
+        .iter()
+        .map(keychain_template)
+        .collect::<Vec<_>>();
+    let has_pair = |template: &Descriptor<DerivationAccount>, keychain: u8| {
+        templates
+            .iter()
+            .flatten()
+            .any(|(other, index)| other == template && *index == UnhardenedIndex::from(keychain))
+    };
+
+    let mut merged = Vec::with_capacity(descriptors.len());
+    for (descriptor, template) in descriptors.into_iter().zip(&templates) {
+        match template {
+            Some((template, keychain)) if *keychain == UnhardenedIndex::zero() => {
+                if has_pair(template, 1) {
+                    merged.push(template.clone());
+                } else {
+                    merged.push(descriptor);
+                }
+            }
+            Some((template, keychain))
+                if *keychain == UnhardenedIndex::one() && has_pair(template, 0) => {}
+            _ => merged.push(descriptor),
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DESCRIPTOR: &str = "wpkh([d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3\
+                              PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6f\
+                              cLW5/*/*)";
+
+    #[test]
+    fn core_roundtrip() {
+        let descriptor = Descriptor::<DerivationAccount>::from_str(DESCRIPTOR).unwrap();
+        let export =
+            import_descriptors(&descriptor, None, Timestamp::Now, DEFAULT_RANGE_END).unwrap();
+        let entries = export.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["internal"], json!(false));
+        assert_eq!(entries[1]["internal"], json!(true));
+        assert_eq!(entries[0]["range"], json!([0, DEFAULT_RANGE_END]));
+        assert_eq!(entries[0]["timestamp"], json!("now"));
+        let desc = entries[0]["desc"].as_str().unwrap();
+        assert!(desc.contains("/0/*)#"));
+        assert!(entries[1]["desc"].as_str().unwrap().contains("/1/*)#"));
+
+        let list = json!({ "wallet_name": "test", "descriptors": export }).to_string();
+        let imported = parse_list_descriptors(&list).unwrap();
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].to_string(), descriptor.to_string());
+
+        let list = json!({ "descriptors": [{ "desc": desc }] }).to_string();
+        let imported = parse_list_descriptors(&list).unwrap();
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].to_string(), desc);
+
+        assert!(matches!(
+            parse_list_descriptors("{}"),
+            Err(Error::NoDescriptors)
+        ));
+    }
+}
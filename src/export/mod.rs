@@ -0,0 +1,69 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Export of wallet descriptors into the formats used by other wallet
+//! software and import of descriptors from them.
+
+pub mod bitcoin_core;
+
+use std::fmt::{self, Formatter};
+use std::str::FromStr;
+
+use amplify::{Display, Error};
+
+/// Wallet software format to export wallet descriptors into or import them
+/// from.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub enum ExportFormat {
+    /// JSON array for Bitcoin Core `importdescriptors` RPC command; on import
+    /// the output of `listdescriptors` command.
+    #[default]
+    BitcoinCore,
+
+    /// Electrum watch-only wallet file (see [`crate::interop::electrum`]).
+    Electrum,
+
+    /// Specter-style JSON wallet file for Sparrow and Specter; on import
+    /// plain-text output descriptor files are accepted as well (see
+    /// [`crate::interop::sparrow`]).
+    Sparrow,
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportFormat::BitcoinCore => f.write_str("bitcoin-core"),
+            ExportFormat::Electrum => f.write_str("electrum"),
+            ExportFormat::Sparrow => f.write_str("sparrow"),
+        }
+    }
+}
+
+/// Error parsing export format name
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display("unknown export format `{0}`; supported formats are: bitcoin-core, electrum, sparrow")]
+pub struct UnknownExportFormat(pub String);
+
+impl FromStr for ExportFormat {
+    type Err = UnknownExportFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bitcoin-core" | "core" => Ok(ExportFormat::BitcoinCore),
+            "electrum" => Ok(ExportFormat::Electrum),
+            "sparrow" | "specter" => Ok(ExportFormat::Sparrow),
+            _ => Err(UnknownExportFormat(s.to_owned())),
+        }
+    }
+}
@@ -0,0 +1,413 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Deterministic testnet wallets for each of the descriptor classes, to be
+//! used in unit and integration tests.
+//!
+//! All fixture wallets are derived from the same fixed seed [`FIXTURE_SEED`]
+//! using the BIP-43 single-sig account standard matching the descriptor class.
+//! Each wallet has a funding transaction paying to its first
+//! [`FIXTURE_ADDRESS_COUNT`] receive addresses, which outputs are used as the
+//! sample UTXO set, and a signed transaction spending two of them.
+//!
+//! NB: Since BIP-340 signatures use random auxiliary data, witnesses of signed
+//! taproot transactions differ between runs; their txids and all data for
+//! other descriptor classes are stable.
+
+use std::collections::BTreeMap;
+
+use bitcoin::consensus::encode::deserialize;
+use bitcoin::hashes::Hash;
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::secp256k1::SECP256K1;
+use bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::{
+    EcdsaSighashType, Network, OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn,
+    TxOut, Txid, Witness,
+};
+use bitcoin_blockchain::locks::SeqNo;
+use bitcoin_hd::{DerivationAccount, DerivationStandard, HardenedIndex, UnhardenedIndex};
+use bitcoin_scripts::address::AddressCompat;
+use bitcoin_scripts::PubkeyScript;
+use descriptors::derive::Descriptor as _;
+use descriptors::{DescriptorClass, InputDescriptor};
+use miniscript_crate::psbt::PsbtExt;
+use miniscript_crate::Descriptor;
+use psbt::sign::{MemoryKeyProvider, MemorySigningAccount, SignAll, SigningPolicy};
+use psbt::Psbt;
+
+/// Seed from which all fixture wallets are derived.
+pub const FIXTURE_SEED: [u8; 32] = *b"descriptor-wallet fixture seed!!";
+
+/// Number of the receive addresses funded in each of the fixture wallets.
+pub const FIXTURE_ADDRESS_COUNT: u8 = 10;
+
+/// Amount of the first funded UTXO; each next UTXO has this amount more.
+pub const FIXTURE_UTXO_STEP: u64 = 100_000;
+
+/// Fee paid by the fixture signed transaction.
+pub const FIXTURE_FEE: u64 = 1_000;
+
+/// Deterministic testnet wallet with known private keys.
+#[derive(Clone, Debug)]
+pub struct FixtureWallet {
+    /// Descriptor class of the wallet.
+    pub class: DescriptorClass,
+
+    /// Signing account holding wallet private keys.
+    pub signing_account: MemorySigningAccount,
+
+    /// Wallet descriptor.
+    pub descriptor: Descriptor<DerivationAccount>,
+}
+
+impl FixtureWallet {
+    /// Derives fixture wallet for the given descriptor class.
+    pub fn with(class: DescriptorClass) -> FixtureWallet {
+        let master_xpriv = ExtendedPrivKey::new_master(Network::Testnet, &FIXTURE_SEED)
+            .expect("fixture seed is valid");
+        let master_xpub = ExtendedPubKey::from_priv(SECP256K1, &master_xpriv);
+        let derivation = class
+            .bip43(1)
+            .to_account_derivation(HardenedIndex::from(0u8).into(), Network::Testnet.into());
+        let account_xpriv = master_xpriv
+            .derive_priv(SECP256K1, &derivation)
+            .expect("fixture derivation is valid");
+        let signing_account = MemorySigningAccount::with(
+            SECP256K1,
+            master_xpub.identifier(),
+            derivation,
+            account_xpriv,
+        );
+        let descriptor = signing_account
+            .recommended_descriptor()
+            .expect("single-sig BIP-43 standards always have a descriptor");
+        FixtureWallet {
+            class,
+            signing_account,
+            descriptor,
+        }
+    }
+
+    /// Returns fixture wallets for all descriptor classes.
+    pub fn all() -> Vec<FixtureWallet> {
+        [
+            DescriptorClass::PreSegwit,
+            DescriptorClass::SegwitV0,
+            DescriptorClass::NestedV0,
+            DescriptorClass::TaprootC0,
+        ]
+        .into_iter()
+        .map(FixtureWallet::with)
+        .collect()
+    }
+
+    /// Returns `scriptPubkey` for the given keychain (`0` for receive and `1`
+    /// for change addresses) and address index.
+    pub fn script_pubkey(&self, keychain: u8, index: u8) -> Script {
+        let pat = [
+            UnhardenedIndex::from(keychain),
+            UnhardenedIndex::from(index),
+        ];
+        match self.class {
+            DescriptorClass::TaprootC0 => self.descriptor.script_pubkey_tr(SECP256K1, pat),
+            _ => self.descriptor.script_pubkey_pretr(SECP256K1, pat),
+        }
+        .expect("fixture descriptor derivation is valid")
+    }
+
+    /// Returns first [`FIXTURE_ADDRESS_COUNT`] addresses for the keychain
+    /// (`0` for receive and `1` for change addresses).
+    pub fn addresses(&self, keychain: u8) -> Vec<AddressCompat> {
+        (0..FIXTURE_ADDRESS_COUNT)
+            .map(|index| {
+                AddressCompat::from_script(
+                    &PubkeyScript::from(self.script_pubkey(keychain, index)),
+                    Network::Testnet.into(),
+                )
+                .expect("fixture descriptors always have addresses")
+            })
+            .collect()
+    }
+
+    /// Returns transaction funding the first [`FIXTURE_ADDRESS_COUNT`]
+    /// receive addresses of the wallet, with amounts growing by
+    /// [`FIXTURE_UTXO_STEP`].
+    pub fn funding_tx(&self) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), 0),
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: (0..FIXTURE_ADDRESS_COUNT)
+                .map(|index| TxOut {
+                    value: FIXTURE_UTXO_STEP * (index as u64 + 1),
+                    script_pubkey: self.script_pubkey(0, index),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns input descriptors for the UTXOs created by the funding
+    /// transaction, one per each funded receive address.
+    pub fn utxos(&self) -> Vec<InputDescriptor> {
+        let txid = self.funding_tx().txid();
+        (0..FIXTURE_ADDRESS_COUNT)
+            .map(|index| InputDescriptor {
+                outpoint: OutPoint::new(txid, index as u32),
+                terminal: format!("/0/{}", index)
+                    .parse()
+                    .expect("fixture derivation is valid"),
+                seq_no: SeqNo::default(),
+                tweak: None,
+                sighash_type: EcdsaSighashType::All,
+                external: None,
+                redeem_script: None,
+                witness_script: None,
+            })
+            .collect()
+    }
+
+    /// Returns resolver for the wallet transactions, which can be used for
+    /// PSBT construction.
+    pub fn tx_resolver(&self) -> BTreeMap<Txid, Transaction> {
+        let tx = self.funding_tx();
+        let mut resolver = BTreeMap::new();
+        resolver.insert(tx.txid(), tx);
+        resolver
+    }
+
+    /// Returns PSBT spending the first two UTXOs to the last receive address
+    /// of the wallet, with a change sent to the first change address, signed
+    /// with the wallet keys.
+    pub fn signed_psbt(&self) -> Psbt {
+        let inputs = self.utxos();
+        let outputs = [(
+            PubkeyScript::from(self.script_pubkey(0, FIXTURE_ADDRESS_COUNT - 1)),
+            FIXTURE_UTXO_STEP * 2,
+        )];
+        let mut psbt = Psbt::construct(
+            &self.descriptor,
+            &inputs[..2],
+            &outputs,
+            UnhardenedIndex::from(0u8),
+            FIXTURE_FEE,
+            &self.tx_resolver(),
+        )
+        .expect("fixture PSBT construction is valid");
+
+        let mut key_provider = MemoryKeyProvider::with(SECP256K1, false);
+        key_provider.add_account(self.signing_account.clone());
+        psbt.sign_all(&key_provider, &SigningPolicy::default())
+            .expect("fixture PSBT signing is valid");
+        psbt
+    }
+
+    /// Returns finalized signed transaction created from
+    /// [`FixtureWallet::signed_psbt`].
+    pub fn signed_tx(&self) -> Transaction {
+        let mut psbt: PartiallySignedTransaction =
+            deserialize(&self.signed_psbt().serialize()).expect("PSBT serialization is consistent");
+        psbt.finalize_mut(SECP256K1)
+            .expect("fixture PSBT is fully signed");
+        psbt.extract_tx()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use std::iter;
+
+    use bitcoin_hd::KeyRotations;
+    use descriptors::ExternalInput;
+    use psbt::construct;
+
+    use super::*;
+
+    #[test]
+    fn fixture_wallets() {
+        for wallet in FixtureWallet::all() {
+            let addresses = wallet.addresses(0);
+            assert_eq!(addresses.len(), FIXTURE_ADDRESS_COUNT as usize);
+            let addresses = addresses
+                .iter()
+                .map(AddressCompat::to_string)
+                .collect::<BTreeSet<_>>();
+            assert_eq!(addresses.len(), FIXTURE_ADDRESS_COUNT as usize);
+            assert_eq!(
+                FixtureWallet::with(wallet.class)
+                    .addresses(0)
+                    .iter()
+                    .map(AddressCompat::to_string)
+                    .collect::<BTreeSet<_>>(),
+                addresses,
+                "fixture wallets must be deterministic"
+            );
+
+            let funding_tx = wallet.funding_tx();
+            let tx = wallet.signed_tx();
+            assert_eq!(tx.input.len(), 2);
+            assert_eq!(tx.output.len(), 2);
+            assert_eq!(
+                tx.output.iter().map(|txout| txout.value).sum::<u64>() + FIXTURE_FEE,
+                FIXTURE_UTXO_STEP * 3
+            );
+            assert_eq!(tx.output[1].script_pubkey, wallet.script_pubkey(1, 0));
+            for txin in &tx.input {
+                assert_eq!(txin.previous_output.txid, funding_tx.txid());
+                assert!(!txin.script_sig.is_empty() || !txin.witness.is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn separate_change_descriptor() {
+        let wallet = FixtureWallet::with(DescriptorClass::SegwitV0);
+        let change_wallet = FixtureWallet::with(DescriptorClass::TaprootC0);
+        let outputs = [(
+            PubkeyScript::from(wallet.script_pubkey(0, 0)),
+            FIXTURE_UTXO_STEP,
+        )];
+        let psbt = Psbt::construct_with_change(
+            &wallet.descriptor,
+            &change_wallet.descriptor,
+            &wallet.utxos()[..2],
+            &outputs,
+            UnhardenedIndex::from(0u8),
+            FIXTURE_FEE,
+            &wallet.tx_resolver(),
+        )
+        .unwrap();
+        assert!(psbt
+            .inputs
+            .iter()
+            .all(|input| !input.bip32_derivation.is_empty()));
+        let change = &psbt.outputs[1];
+        assert_eq!(
+            change.script,
+            PubkeyScript::from(change_wallet.script_pubkey(1, 0))
+        );
+        assert!(change.tap_internal_key.is_some());
+        assert_eq!(change.amount, FIXTURE_UTXO_STEP * 2 - FIXTURE_FEE);
+    }
+
+    #[test]
+    fn foreign_inputs() {
+        let wallet = FixtureWallet::with(DescriptorClass::SegwitV0);
+        let foreign_wallet = FixtureWallet::with(DescriptorClass::NestedV0);
+        let funding_tx = foreign_wallet.funding_tx();
+        let mut foreign = ExternalInput::with(
+            OutPoint::new(funding_tx.txid(), 1),
+            funding_tx.output[1].clone(),
+        );
+        foreign.redeem_script = Some(Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::all_zeros()));
+
+        let outputs = [
+            (
+                PubkeyScript::from(wallet.script_pubkey(0, 0)),
+                FIXTURE_UTXO_STEP,
+            ),
+            (
+                PubkeyScript::from(foreign_wallet.script_pubkey(0, 0)),
+                FIXTURE_UTXO_STEP * 2,
+            ),
+        ];
+        let psbt = Psbt::construct_with_foreign(
+            &wallet.descriptor,
+            &wallet.descriptor,
+            &wallet.utxos()[..2],
+            [&foreign],
+            &outputs,
+            UnhardenedIndex::from(0u8),
+            FIXTURE_FEE,
+            &wallet.tx_resolver(),
+        )
+        .unwrap();
+        assert_eq!(psbt.inputs.len(), 3);
+        assert!(!psbt.inputs[1].bip32_derivation.is_empty());
+        let input = &psbt.inputs[2];
+        assert_eq!(input.index, 2);
+        assert!(input.bip32_derivation.is_empty());
+        assert!(input.non_witness_utxo.is_none());
+        assert_eq!(input.witness_utxo.as_ref(), Some(&funding_tx.output[1]));
+        assert_eq!(psbt.outputs[2].amount, FIXTURE_UTXO_STEP * 2 - FIXTURE_FEE);
+
+        let mut resolver = wallet.tx_resolver();
+        resolver.insert(funding_tx.txid(), funding_tx.clone());
+        foreign.utxo.value += 1;
+        assert!(matches!(
+            Psbt::construct_with_foreign(
+                &wallet.descriptor,
+                &wallet.descriptor,
+                &wallet.utxos()[..2],
+                [&foreign],
+                &outputs,
+                UnhardenedIndex::from(0u8),
+                FIXTURE_FEE,
+                &resolver,
+            ),
+            Err(construct::Error::ForeignUtxoMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn key_rotation() {
+        let wallet = FixtureWallet::with(DescriptorClass::SegwitV0);
+        let old = match &wallet.descriptor {
+            Descriptor::Wpkh(wpkh) => wpkh.as_inner().clone(),
+            _ => unreachable!(),
+        };
+        let new = match FixtureWallet::with(DescriptorClass::TaprootC0).descriptor {
+            Descriptor::Tr(tr) => tr.internal_key().clone(),
+            _ => unreachable!(),
+        };
+        let rotated = Descriptor::new_wpkh(new.clone()).unwrap();
+        let mut rotations = KeyRotations::default();
+        rotations.supersede(&old, 5u8, new.clone()).unwrap();
+
+        // UTXO #7 was sent to the superseded key after the rotation boundary,
+        // so it must be spent with the old descriptor version
+        let utxos = wallet.utxos();
+        let outputs = [(
+            PubkeyScript::from(wallet.script_pubkey(0, 0)),
+            FIXTURE_UTXO_STEP,
+        )];
+        let psbt = Psbt::construct_with_rotations(
+            &wallet.descriptor,
+            &wallet.descriptor,
+            &rotations,
+            [&utxos[0], &utxos[7]],
+            iter::empty(),
+            &outputs,
+            UnhardenedIndex::from(6u8),
+            FIXTURE_FEE,
+            &wallet.tx_resolver(),
+        )
+        .unwrap();
+        assert!(psbt
+            .inputs
+            .iter()
+            .all(|input| !input.bip32_derivation.is_empty()));
+        assert!(psbt.xpub.contains_key(&old.account_xpub));
+        assert!(psbt.xpub.contains_key(&new.account_xpub));
+        let terminal = [UnhardenedIndex::from(1u8), UnhardenedIndex::from(6u8)];
+        let change = rotated.script_pubkey_pretr(SECP256K1, terminal).unwrap();
+        assert_eq!(psbt.outputs[1].script, PubkeyScript::from(change));
+    }
+}
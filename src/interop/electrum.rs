@@ -0,0 +1,292 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Electrum watch-only wallet files.
+//!
+//! Electrum does not use descriptors: script type of the wallet is defined by
+//! SLIP-132 version of the extended public keys, and addresses are always
+//! derived with `/0/*` (receive) and `/1/*` (change) terminal paths. Thus,
+//! only single-sig `pkh`, `wpkh` and `sh(wpkh)` and `sortedmulti`
+//! multi-signature descriptors with `/*/*` derive pattern can be exported.
+
+use std::str::FromStr;
+
+use amplify::{Display, Error, From};
+use bitcoin::util::bip32::{DerivationPath, ExtendedPubKey, Fingerprint};
+use bitcoin_hd::{
+    AccountStep, DerivationAccount, DerivationSubpath, SegmentIndexes, TerminalStep, XpubRef,
+};
+use miniscript_crate::descriptor::{ShInner, WshInner};
+use miniscript_crate::Descriptor;
+use serde_json::{json, Map, Value};
+use slip132::{DefaultResolver, FromSlip132, KeyApplication, KeyVersion, ToSlip132};
+
+/// Version of Electrum wallet file format produced on export.
+pub const ELECTRUM_SEED_VERSION: u32 = 18;
+
+/// Errors converting descriptors to and from Electrum wallet files
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum Error {
+    /// invalid JSON data: {0}
+    #[from]
+    Json(serde_json::Error),
+
+    /// descriptor type is not supported by Electrum; only `pkh`, `wpkh`,
+    /// `sh(wpkh)` and `sortedmulti` descriptors may be used
+    UnsupportedDescriptor,
+
+    /// descriptor key {0} must have `/*/*` terminal derivation path to be used
+    /// by Electrum
+    TerminalPath(DerivationAccount),
+
+    /// Electrum wallet type `{0}` is not supported
+    UnsupportedWalletType(String),
+
+    /// Electrum wallet file does not contain `{0}` field
+    NoField(String),
+
+    /// invalid extended public key in Electrum wallet file: {0}
+    #[from]
+    Xpub(slip132::Error),
+
+    /// extended public key `{0}` has unknown SLIP-132 version
+    UnknownKeyVersion(String),
+
+    /// invalid derivation path `{0}` in Electrum wallet file
+    DerivationPath(String),
+
+    /// invalid root fingerprint `{0}` in Electrum wallet file
+    Fingerprint(String),
+
+    /// keys of Electrum multi-signature wallet use different script types
+    MixedKeyApplications,
+
+    /// Electrum wallet keys do not form a valid descriptor: {0}
+    Descriptor(miniscript_crate::Error),
+}
+
+fn keystore(account: &DerivationAccount, application: KeyApplication) -> Result<Value, Error> {
+    let terminal = &account.terminal_path;
+    if terminal.len() != 2
+        || !terminal[0].contains(0)
+        || !terminal[0].contains(1)
+        || terminal[1] != TerminalStep::Wildcard
+    {
+        return Err(Error::TerminalPath(account.clone()));
+    }
+
+    let root_fingerprint = match account.master_fingerprint() {
+        Some(fingerprint) => json!(fingerprint.to_string()),
+        None if account.account_path.is_empty() => {
+            json!(account.account_fingerprint().to_string())
+        }
+        None => Value::Null,
+    };
+    let xpub = account
+        .account_xpub
+        .to_slip132_string(application, account.account_xpub.network);
+    Ok(json!({
+        "type": "bip32",
+        "xpub": xpub,
+        "derivation": account.to_account_derivation_path().to_string(),
+        "root_fingerprint": root_fingerprint,
+        "label": "",
+        "xprv": null,
+    }))
+}
+
+/// Renders wallet descriptor as Electrum watch-only wallet file.
+///
+/// Extended public keys are encoded with SLIP-132 version matching the
+/// descriptor script type. Multi-signature descriptors are exported as
+/// Electrum `MofN` wallets.
+pub fn wallet_file(descriptor: &Descriptor<DerivationAccount>) -> Result<Value, Error> {
+    let (application, threshold, accounts): (_, _, Vec<_>) = match descriptor {
+        Descriptor::Pkh(pkh) => (KeyApplication::Hashed, None, vec![pkh.as_inner()]),
+        Descriptor::Wpkh(wpkh) => (KeyApplication::SegWit, None, vec![wpkh.as_inner()]),
+        Descriptor::Sh(sh) => match sh.as_inner() {
+            ShInner::Wpkh(wpkh) => (KeyApplication::Nested, None, vec![wpkh.as_inner()]),
+            ShInner::SortedMulti(multi) => (
+                KeyApplication::Hashed,
+                Some(multi.k),
+                multi.pks.iter().collect(),
+            ),
+            ShInner::Wsh(wsh) => match wsh.as_inner() {
+                WshInner::SortedMulti(multi) => (
+                    KeyApplication::NestedMultisig,
+                    Some(multi.k),
+                    multi.pks.iter().collect(),
+                ),
+                WshInner::Ms(_) => return Err(Error::UnsupportedDescriptor),
+            },
+            ShInner::Ms(_) => return Err(Error::UnsupportedDescriptor),
+        },
+        Descriptor::Wsh(wsh) => match wsh.as_inner() {
+            WshInner::SortedMulti(multi) => (
+                KeyApplication::SegWitMultisig,
+                Some(multi.k),
+                multi.pks.iter().collect(),
+            ),
+            WshInner::Ms(_) => return Err(Error::UnsupportedDescriptor),
+        },
+        _ => return Err(Error::UnsupportedDescriptor),
+    };
+
+    let mut wallet = Map::new();
+    match threshold {
+        None => {
+            wallet.insert("keystore".to_owned(), keystore(accounts[0], application)?);
+            wallet.insert("wallet_type".to_owned(), json!("standard"));
+        }
+        Some(threshold) => {
+            for (no, account) in accounts.iter().enumerate() {
+                wallet.insert(format!("x{}/", no + 1), keystore(account, application)?);
+            }
+            let wallet_type = format!("{}of{}", threshold, accounts.len());
+            wallet.insert("wallet_type".to_owned(), json!(wallet_type));
+        }
+    }
+    wallet.insert("use_encryption".to_owned(), json!(false));
+    wallet.insert("seed_version".to_owned(), json!(ELECTRUM_SEED_VERSION));
+    Ok(Value::Object(wallet))
+}
+
+fn field<'value>(value: &'value Value, name: &str) -> Result<&'value Value, Error> {
+    value
+        .get(name)
+        .ok_or_else(|| Error::NoField(name.to_owned()))
+}
+
+fn parse_keystore(keystore: &Value) -> Result<(KeyApplication, DerivationAccount), Error> {
+    let xpub = field(keystore, "xpub")?
+        .as_str()
+        .ok_or_else(|| Error::NoField("xpub".to_owned()))?;
+    let application = KeyVersion::from_xkey_str(xpub)?
+        .application::<DefaultResolver>()
+        .ok_or_else(|| Error::UnknownKeyVersion(xpub.to_owned()))?;
+    let account_xpub = ExtendedPubKey::from_slip132_str(xpub)?;
+
+    let account_path = match keystore.get("derivation").and_then(Value::as_str) {
+        None => DerivationSubpath::default(),
+        Some(path) => DerivationPath::from_str(path)
+            .ok()
+            .and_then(|derivation| {
+                derivation
+                    .as_ref()
+                    .iter()
+                    .map(|child| AccountStep::try_from(*child).ok())
+                    .collect()
+            })
+            .ok_or_else(|| Error::DerivationPath(path.to_owned()))?,
+    };
+    let master = match keystore.get("root_fingerprint").and_then(Value::as_str) {
+        None => XpubRef::Unknown,
+        Some(fingerprint) => Fingerprint::from_str(fingerprint)
+            .map(XpubRef::Fingerprint)
+            .map_err(|_| Error::Fingerprint(fingerprint.to_owned()))?,
+    };
+
+    Ok((application, DerivationAccount {
+        master,
+        account_path,
+        account_xpub,
+        revocation_seal: None,
+        terminal_path: [TerminalStep::Wildcard, TerminalStep::Wildcard]
+            .into_iter()
+            .collect(),
+    }))
+}
+
+/// Parses Electrum wallet file into a wallet descriptor with `/*/*` derive
+/// pattern.
+///
+/// Supports standard single-sig and `MofN` multi-signature wallets; the
+/// descriptor script type is detected from SLIP-132 version of the wallet
+/// extended public keys.
+pub fn parse_wallet_file(json: &str) -> Result<Descriptor<DerivationAccount>, Error> {
+    let wallet: Value = serde_json::from_str(json)?;
+    let wallet_type = field(&wallet, "wallet_type")?
+        .as_str()
+        .ok_or_else(|| Error::NoField("wallet_type".to_owned()))?;
+    let unsupported = || Error::UnsupportedWalletType(wallet_type.to_owned());
+
+    if wallet_type == "standard" {
+        let (application, account) = parse_keystore(field(&wallet, "keystore")?)?;
+        return match application {
+            KeyApplication::Hashed => Ok(Descriptor::new_pkh(account)),
+            KeyApplication::SegWit => Descriptor::new_wpkh(account).map_err(Error::Descriptor),
+            KeyApplication::Nested => Descriptor::new_sh_wpkh(account).map_err(Error::Descriptor),
+            _ => Err(unsupported()),
+        };
+    }
+
+    let (threshold, count) = wallet_type
+        .split_once("of")
+        .and_then(|(m, n)| Some((m.parse::<usize>().ok()?, n.parse::<usize>().ok()?)))
+        .ok_or_else(unsupported)?;
+    let mut application = None;
+    let mut accounts = Vec::with_capacity(count);
+    for no in 1..=count {
+        let (key_application, account) = parse_keystore(field(&wallet, &format!("x{}/", no))?)?;
+        if *application.get_or_insert(key_application) != key_application {
+            return Err(Error::MixedKeyApplications);
+        }
+        accounts.push(account);
+    }
+    match application {
+        Some(KeyApplication::Hashed) => Descriptor::new_sh_sortedmulti(threshold, accounts),
+        Some(KeyApplication::SegWitMultisig) => {
+            Descriptor::new_wsh_sortedmulti(threshold, accounts)
+        }
+        Some(KeyApplication::NestedMultisig) => {
+            Descriptor::new_sh_wsh_sortedmulti(threshold, accounts)
+        }
+        _ => return Err(unsupported()),
+    }
+    .map_err(Error::Descriptor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn electrum_roundtrip() {
+        let xpub = "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxx\
+                    pG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5";
+        let descriptor = Descriptor::<DerivationAccount>::from_str(&format!(
+            "wpkh([d34db33f/84h/0h/0h]{xpub}/*/*)"
+        ))
+        .unwrap();
+
+        let wallet = wallet_file(&descriptor).unwrap();
+        assert_eq!(wallet["wallet_type"], json!("standard"));
+        assert_eq!(wallet["keystore"]["root_fingerprint"], json!("d34db33f"));
+        assert_eq!(wallet["keystore"]["derivation"], json!("m/84'/0'/0'"));
+        assert!(wallet["keystore"]["xpub"]
+            .as_str()
+            .unwrap()
+            .starts_with("zpub"));
+
+        let imported = parse_wallet_file(&wallet.to_string()).unwrap();
+        assert_eq!(imported.to_string(), descriptor.to_string());
+
+        let pinned = Descriptor::<DerivationAccount>::from_str(&format!(
+            "wpkh([d34db33f/84h/0h/0h]{xpub}/0/*)"
+        ))
+        .unwrap();
+        assert!(matches!(wallet_file(&pinned), Err(Error::TerminalPath(_))));
+    }
+}
@@ -0,0 +1,132 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Sparrow and Specter wallet files.
+//!
+//! Both wallets import Specter-style JSON wallet files containing receive
+//! (`/0/*`) output descriptor with a checksum; change descriptor is deduced
+//! by the wallets by replacing the keychain index. Sparrow also exports
+//! plain-text output descriptor files, which are supported on import.
+
+use std::str::FromStr;
+
+use amplify::{Display, Error, From};
+use bitcoin_hd::{DerivationAccount, SegmentIndexes, UnhardenedIndex};
+use miniscript_crate::{Descriptor, ForEachKey};
+use serde_json::{json, Value};
+
+use crate::export::bitcoin_core::{self, keychain_descriptor, keychain_template};
+
+/// Errors converting descriptors to and from Sparrow and Specter wallet files
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum Error {
+    /// invalid JSON data: {0}
+    #[from]
+    Json(serde_json::Error),
+
+    /// wallet file does not contain output descriptor
+    NoDescriptor,
+
+    /// invalid output descriptor in wallet file: {0}
+    #[from]
+    Descriptor(miniscript_crate::Error),
+
+    /// unable to produce receive descriptor: {0}
+    #[from]
+    Keychain(bitcoin_core::Error),
+}
+
+/// Renders wallet descriptor as Specter-style JSON wallet file, which can be
+/// imported by both Sparrow and Specter.
+///
+/// The file contains receive descriptor with a checksum, wallet `label` and
+/// `blockheight` from which the wallet has to be rescanned.
+pub fn wallet_file(
+    descriptor: &Descriptor<DerivationAccount>,
+    label: &str,
+    blockheight: u32,
+) -> Result<Value, Error> {
+    let receive = keychain_descriptor(descriptor, 0)?;
+    let mut devices = vec![];
+    descriptor.for_each_key(|account| {
+        let fingerprint = account
+            .master_fingerprint()
+            .unwrap_or_else(|| account.account_fingerprint());
+        devices.push(json!({ "type": "other", "label": fingerprint.to_string() }));
+        true
+    });
+    Ok(json!({
+        "label": label,
+        "blockheight": blockheight,
+        "descriptor": receive.to_string(),
+        "devices": devices,
+    }))
+}
+
+/// Parses Sparrow or Specter wallet file into a wallet descriptor.
+///
+/// Accepts both Specter-style JSON wallet files and plain-text output
+/// descriptor files, where the first line which is not a comment must
+/// contain the descriptor. Receive descriptors (`/0/*`) are converted into
+/// wallet descriptors with `/*/*` derive pattern.
+pub fn parse_wallet_file(data: &str) -> Result<Descriptor<DerivationAccount>, Error> {
+    let data = data.trim();
+    let descriptor = if data.starts_with('{') {
+        let wallet: Value = serde_json::from_str(data)?;
+        let descriptor = wallet
+            .get("descriptor")
+            .and_then(Value::as_str)
+            .ok_or(Error::NoDescriptor)?;
+        Descriptor::from_str(descriptor)?
+    } else {
+        let descriptor = data
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .ok_or(Error::NoDescriptor)?;
+        Descriptor::from_str(descriptor)?
+    };
+
+    Ok(match keychain_template(&descriptor) {
+        Some((template, keychain)) if keychain == UnhardenedIndex::zero() => template,
+        _ => descriptor,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sparrow_roundtrip() {
+        let descriptor = Descriptor::<DerivationAccount>::from_str(
+            "wpkh([d34db33f/84h/0h/0h]xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VU\
+             NgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5/*/*)",
+        )
+        .unwrap();
+
+        let wallet = wallet_file(&descriptor, "test", 800_000).unwrap();
+        let receive = wallet["descriptor"].as_str().unwrap();
+        assert!(receive.contains("/0/*)#"));
+        assert_eq!(wallet["devices"][0]["label"], json!("d34db33f"));
+
+        let imported = parse_wallet_file(&wallet.to_string()).unwrap();
+        assert_eq!(imported.to_string(), descriptor.to_string());
+
+        let text = format!("# Receive descriptor\n{}\n", receive);
+        let imported = parse_wallet_file(&text).unwrap();
+        assert_eq!(imported.to_string(), descriptor.to_string());
+    }
+}
@@ -18,14 +18,50 @@
 #![recursion_limit = "256"]
 #![deny(dead_code, missing_docs, warnings)]
 
+#[cfg(feature = "strict_encoding")]
+#[macro_use]
+extern crate amplify;
+
 pub extern crate bitcoin_hd as hd;
 pub extern crate bitcoin_onchain as onchain;
 pub extern crate descriptors;
 pub extern crate psbt;
 pub extern crate slip132;
+#[cfg(feature = "strict_encoding")]
+#[macro_use]
+extern crate strict_encoding_crate as strict_encoding;
 
+pub mod amount;
+mod capabilities;
 #[cfg(feature = "cli")]
 pub(crate) mod cli;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(all(
+    feature = "strict_encoding",
+    feature = "miniscript",
+    feature = "miniscript_crate"
+))]
+pub mod container;
+#[cfg(feature = "strict_encoding")]
+pub mod envelope;
+#[cfg(feature = "export")]
+pub mod export;
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
+#[cfg(feature = "export")]
+pub mod interop;
+#[cfg(feature = "strict_encoding")]
+pub mod meta;
+#[cfg(feature = "notify")]
+pub mod notify;
+pub mod payments;
+#[cfg(feature = "serde")]
+pub mod report;
+#[cfg(feature = "strict_encoding")]
+pub mod sync;
+
+pub use capabilities::{capabilities, Capabilities, Feature, MissingFeature, SignerBackend};
 
 pub mod lex_order {
     //! Lexicographic sorting functions.
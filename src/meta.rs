@@ -0,0 +1,328 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Optional wallet metadata which is not a part of the wallet descriptor:
+//! address labels, account aliases, UTXO freeze flags, derivation terminals
+//! reserved for external protocols and account key rotations.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bitcoin::util::bip32::Fingerprint;
+use bitcoin::OutPoint;
+use bitcoin_hd::{IndexRange, KeyRotations, SegmentIndexes, UnhardenedIndex};
+use bitcoin_scripts::PubkeyScript;
+use strict_encoding::StrictDecode;
+
+use crate::envelope::{EnvelopeError, Versioned};
+
+/// Errors reserving derivation terminals for an external protocol
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ReservationError {
+    /// derivation terminals {keychain}/{range:#} intersect with terminals
+    /// {existing_keychain}/{existing_range:#} already reserved for `{protocol}`
+    Overlap {
+        /// Keychain of the requested reservation
+        keychain: UnhardenedIndex,
+        /// Index range of the requested reservation
+        range: IndexRange<UnhardenedIndex>,
+        /// Keychain of the existing reservation
+        existing_keychain: UnhardenedIndex,
+        /// Index range of the existing reservation
+        existing_range: IndexRange<UnhardenedIndex>,
+        /// Protocol owning the existing reservation
+        protocol: String,
+    },
+}
+
+/// Wallet metadata stored in the wallet file alongside the wallet descriptor.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct WalletMeta {
+    /// Labels assigned to the wallet addresses, indexed by their
+    /// `scriptPubkey`.
+    pub address_labels: BTreeMap<PubkeyScript, String>,
+
+    /// Human-readable aliases of the accounts used in the wallet descriptor,
+    /// indexed by the fingerprint of the account extended public key.
+    pub account_aliases: BTreeMap<Fingerprint, String>,
+
+    /// UTXOs which must not be spent by the wallet.
+    pub frozen_utxos: BTreeSet<OutPoint>,
+
+    /// Ranges of derivation terminals reserved for external protocols (like
+    /// RGB), indexed by the keychain (first terminal derivation segment) and
+    /// range of indexes within it. Values are protocol identifiers.
+    ///
+    /// Reserved terminals are not used for address issuance and change
+    /// outputs.
+    pub reserved_terminals: BTreeMap<(UnhardenedIndex, IndexRange<UnhardenedIndex>), String>,
+
+    /// Account extended public keys superseded by new keys starting from some
+    /// derivation index (key rotations), applying to all wallet descriptors
+    /// using the superseded keys.
+    pub key_rotations: KeyRotations,
+}
+
+impl WalletMeta {
+    /// Detects whether the metadata does not contain any information.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.address_labels.is_empty()
+            && self.account_aliases.is_empty()
+            && self.frozen_utxos.is_empty()
+            && self.reserved_terminals.is_empty()
+            && self.key_rotations.is_empty()
+    }
+
+    /// Returns label assigned to the address with a given `scriptPubkey`, if
+    /// any.
+    #[inline]
+    pub fn label(&self, script_pubkey: &PubkeyScript) -> Option<&str> {
+        self.address_labels.get(script_pubkey).map(String::as_str)
+    }
+
+    /// Assigns label to the address with a given `scriptPubkey`, returning
+    /// the previously assigned label, if any.
+    #[inline]
+    pub fn set_label(
+        &mut self,
+        script_pubkey: PubkeyScript,
+        label: impl ToString,
+    ) -> Option<String> {
+        self.address_labels.insert(script_pubkey, label.to_string())
+    }
+
+    /// Removes label from the address with a given `scriptPubkey`, returning
+    /// the removed label, if any.
+    #[inline]
+    pub fn remove_label(&mut self, script_pubkey: &PubkeyScript) -> Option<String> {
+        self.address_labels.remove(script_pubkey)
+    }
+
+    /// Returns alias for the account with a given extended key fingerprint, if
+    /// any.
+    #[inline]
+    pub fn account_alias(&self, fingerprint: Fingerprint) -> Option<&str> {
+        self.account_aliases.get(&fingerprint).map(String::as_str)
+    }
+
+    /// Assigns alias to the account with a given extended key fingerprint,
+    /// returning the previous alias, if any.
+    #[inline]
+    pub fn set_account_alias(
+        &mut self,
+        fingerprint: Fingerprint,
+        alias: impl ToString,
+    ) -> Option<String> {
+        self.account_aliases.insert(fingerprint, alias.to_string())
+    }
+
+    /// Detects whether a given UTXO is frozen.
+    #[inline]
+    pub fn is_frozen(&self, outpoint: &OutPoint) -> bool { self.frozen_utxos.contains(outpoint) }
+
+    /// Freezes UTXO, preventing it from being spent. Returns `false` if the
+    /// UTXO was already frozen.
+    #[inline]
+    pub fn freeze(&mut self, outpoint: OutPoint) -> bool { self.frozen_utxos.insert(outpoint) }
+
+    /// Unfreezes previously frozen UTXO. Returns `false` if the UTXO was not
+    /// frozen.
+    #[inline]
+    pub fn unfreeze(&mut self, outpoint: &OutPoint) -> bool { self.frozen_utxos.remove(outpoint) }
+
+    /// Reserves range of derivation terminal indexes under a given keychain
+    /// for an external protocol.
+    ///
+    /// Errors if the range intersects with any of the existing reservations,
+    /// including reservations made for the same protocol.
+    pub fn reserve_terminals(
+        &mut self,
+        protocol: impl ToString,
+        keychain: UnhardenedIndex,
+        range: IndexRange<UnhardenedIndex>,
+    ) -> Result<(), ReservationError> {
+        if let Some(((existing_keychain, existing_range), protocol)) = self
+            .reserved_terminals
+            .iter()
+            .find(|((k, r), _)| *k == keychain && r.does_intersect(&range))
+        {
+            return Err(ReservationError::Overlap {
+                keychain,
+                range,
+                existing_keychain: *existing_keychain,
+                existing_range: existing_range.clone(),
+                protocol: protocol.clone(),
+            });
+        }
+        self.reserved_terminals
+            .insert((keychain, range), protocol.to_string());
+        Ok(())
+    }
+
+    /// Releases all derivation terminals reserved for a given protocol,
+    /// returning number of released index ranges.
+    pub fn release_terminals(&mut self, protocol: &str) -> usize {
+        let count = self.reserved_terminals.len();
+        self.reserved_terminals.retain(|_, p| p != protocol);
+        count - self.reserved_terminals.len()
+    }
+
+    /// Returns iterator over derivation terminal ranges reserved for a given
+    /// protocol.
+    pub fn protocol_reservations<'a>(
+        &'a self,
+        protocol: &'a str,
+    ) -> impl Iterator<Item = &'a (UnhardenedIndex, IndexRange<UnhardenedIndex>)> + 'a {
+        self.reserved_terminals
+            .iter()
+            .filter(move |(_, p)| *p == protocol)
+            .map(|(terminal, _)| terminal)
+    }
+
+    /// Returns identifier of the protocol for which a given derivation
+    /// terminal is reserved, if any.
+    pub fn terminal_reservation(
+        &self,
+        keychain: UnhardenedIndex,
+        index: UnhardenedIndex,
+    ) -> Option<&str> {
+        self.reserved_terminals
+            .iter()
+            .find(|((k, r), _)| *k == keychain && r.contains(index.first_index()))
+            .map(|(_, protocol)| protocol.as_str())
+    }
+
+    /// Detects whether a given derivation terminal is reserved for some
+    /// external protocol.
+    #[inline]
+    pub fn is_reserved(&self, keychain: UnhardenedIndex, index: UnhardenedIndex) -> bool {
+        self.terminal_reservation(keychain, index).is_some()
+    }
+
+    /// Finds first derivation index under a given keychain, starting from
+    /// `from` index (inclusive), which is not reserved for any external
+    /// protocol. Returns `None` if all the remaining indexes are reserved.
+    pub fn next_unreserved(
+        &self,
+        keychain: UnhardenedIndex,
+        from: UnhardenedIndex,
+    ) -> Option<UnhardenedIndex> {
+        let mut index = from;
+        while let Some(((_, range), _)) = self
+            .reserved_terminals
+            .iter()
+            .find(|((k, r), _)| *k == keychain && r.contains(index.first_index()))
+        {
+            index = UnhardenedIndex::from_index(range.last_index().checked_add(1)?).ok()?;
+        }
+        Some(index)
+    }
+}
+
+impl Versioned for WalletMeta {
+    const MAGIC: [u8; 4] = *b"DWMT";
+    const VERSION: u16 = 1;
+
+    // Metadata written before the envelope introduction may lack key
+    // rotations, which were the last field added to the metadata
+    fn migrate_legacy(mut data: &[u8]) -> Result<Self, EnvelopeError> {
+        let mut meta = WalletMeta {
+            address_labels: StrictDecode::strict_decode(&mut data)?,
+            account_aliases: StrictDecode::strict_decode(&mut data)?,
+            frozen_utxos: StrictDecode::strict_decode(&mut data)?,
+            reserved_terminals: StrictDecode::strict_decode(&mut data)?,
+            key_rotations: default!(),
+        };
+        if !data.is_empty() {
+            meta.key_rotations = StrictDecode::strict_decode(&mut data)?;
+        }
+        Ok(meta)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use strict_encoding::{StrictDecode, StrictEncode};
+
+    use super::*;
+
+    #[test]
+    fn strict_encoding_roundtrip() {
+        let mut meta = WalletMeta::default();
+        assert!(meta.is_empty());
+
+        let script = PubkeyScript::from(bitcoin::Script::new_op_return(&[0u8; 4]));
+        let outpoint = OutPoint::from_str(
+            "c8dd8bd4f8d0f2ae2fc7dbe1e95b8b29ef08ff8d4fc1d1c70da2c6d8c1dc3d6a:1",
+        )
+        .unwrap();
+        meta.set_label(script.clone(), "donation");
+        meta.set_account_alias(Fingerprint::from(&[0xde, 0xad, 0xbe, 0xef][..]), "alice");
+        assert!(meta.freeze(outpoint));
+        assert!(!meta.freeze(outpoint));
+
+        let data = meta.strict_serialize().unwrap();
+        let decoded = WalletMeta::strict_deserialize(data).unwrap();
+        assert_eq!(decoded, meta);
+        assert_eq!(decoded.label(&script), Some("donation"));
+        assert!(decoded.is_frozen(&outpoint));
+
+        let envelope = meta.to_envelope().unwrap();
+        assert_eq!(&envelope[..4], b"DWMT");
+        assert_eq!(WalletMeta::from_envelope(envelope).unwrap(), meta);
+
+        // Metadata written by the releases without key rotations support
+        let mut legacy = vec![];
+        meta.address_labels.strict_encode(&mut legacy).unwrap();
+        meta.account_aliases.strict_encode(&mut legacy).unwrap();
+        meta.frozen_utxos.strict_encode(&mut legacy).unwrap();
+        meta.reserved_terminals.strict_encode(&mut legacy).unwrap();
+        assert_eq!(WalletMeta::from_envelope(legacy).unwrap(), meta);
+    }
+
+    #[test]
+    fn terminal_reservation() {
+        let mut meta = WalletMeta::default();
+        let keychain = UnhardenedIndex::zero();
+        meta.reserve_terminals("rgb", keychain, IndexRange::with(10u8, 19u8))
+            .unwrap();
+        meta.reserve_terminals("rgb", keychain, IndexRange::with(20u8, 29u8))
+            .unwrap();
+        assert!(meta
+            .reserve_terminals("lnp", keychain, IndexRange::with(15u8, 25u8))
+            .is_err());
+        meta.reserve_terminals("lnp", UnhardenedIndex::one(), IndexRange::with(15u8, 25u8))
+            .unwrap();
+
+        assert_eq!(
+            meta.terminal_reservation(keychain, 12u8.into()),
+            Some("rgb")
+        );
+        assert!(!meta.is_reserved(keychain, 9u8.into()));
+        assert_eq!(meta.next_unreserved(keychain, 9u8.into()), Some(9u8.into()));
+        assert_eq!(
+            meta.next_unreserved(keychain, 10u8.into()),
+            Some(30u8.into())
+        );
+        assert_eq!(meta.protocol_reservations("rgb").count(), 2);
+
+        assert_eq!(meta.release_terminals("rgb"), 2);
+        assert!(!meta.is_reserved(keychain, 12u8.into()));
+        assert!(meta.is_reserved(UnhardenedIndex::one(), 20u8.into()));
+    }
+}
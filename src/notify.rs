@@ -0,0 +1,431 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Delivery of wallet events to external services, allowing merchant
+//! backends to integrate with a wallet without polling it.
+//!
+//! Events are delivered as JSON-serialized payloads either to webhooks (as
+//! HTTP POST request bodies) or to Unix sockets (as lines of text). Each
+//! payload is signed with HMAC-SHA256 using a secret shared with the receiver.
+//! The signature covers the delivery timestamp followed by a dot and the
+//! payload, so receivers can reject replayed notifications with
+//! [`verify_payload`].
+//!
+//! Webhook requests provide the timestamp (in seconds since the Unix epoch)
+//! in the [`TIMESTAMP_HEADER`] header and the signature in the
+//! [`SIGNATURE_HEADER`] header as `sha256=<hex>`; Unix socket lines have the
+//! form of `<timestamp> sha256=<hex> <payload>`.
+
+use std::fmt::{self, Display, Formatter};
+#[cfg(unix)]
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use amplify::hex::{FromHex, ToHex};
+use amplify::{Display, Error};
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use serde_crate::Serialize;
+use subtle::ConstantTimeEq;
+
+/// HTTP header carrying signature of the webhook payload
+pub const SIGNATURE_HEADER: &str = "X-Wallet-Signature";
+
+/// HTTP header carrying timestamp of the webhook delivery
+pub const TIMESTAMP_HEADER: &str = "X-Wallet-Timestamp";
+
+/// Maximal difference between the delivery timestamp and the receiver clock,
+/// in seconds, accepted by [`verify_payload`]
+pub const TIMESTAMP_TOLERANCE: u64 = 300;
+
+/// Default timeout for delivering notification to a single sink
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors delivering event notifications
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum NotifyError {
+    /// unrecognized notification sink `{0}`; sinks must be given either as
+    /// `http(s)://` webhook URLs or as `unix:<path>` socket paths
+    UnknownSink(String),
+
+    /// webhook {0} request failed: {1}
+    Webhook(String, String),
+
+    /// unable to deliver notification to socket {0}: {1}
+    Socket(String, String),
+
+    /// unable to serialize notification: {0}
+    Serialize(String),
+
+    /// notification signature is invalid
+    InvalidSignature,
+
+    /// notification timestamp {0} is too far from the current time, which
+    /// may indicate a replayed notification
+    StaleTimestamp(u64),
+}
+
+/// Destination for event notifications
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum NotifySink {
+    /// Webhook receiving notifications as HTTP POST requests
+    Webhook(String),
+
+    /// Unix socket receiving notifications as lines of text
+    #[cfg(unix)]
+    UnixSocket(PathBuf),
+}
+
+impl Display for NotifySink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifySink::Webhook(url) => f.write_str(url),
+            #[cfg(unix)]
+            NotifySink::UnixSocket(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for NotifySink {
+    type Err = NotifyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(NotifySink::Webhook(s.to_owned()));
+        }
+        #[cfg(unix)]
+        if let Some(path) = s.strip_prefix("unix:") {
+            if !path.is_empty() {
+                return Ok(NotifySink::UnixSocket(PathBuf::from(path)));
+            }
+        }
+        Err(NotifyError::UnknownSink(s.to_owned()))
+    }
+}
+
+fn hmac(secret: &[u8], data: &[&[u8]]) -> Hmac<sha256::Hash> {
+    let mut engine = HmacEngine::<sha256::Hash>::new(secret);
+    for chunk in data {
+        engine.input(chunk);
+    }
+    Hmac::<sha256::Hash>::from_engine(engine)
+}
+
+/// Computes hex-encoded HMAC-SHA256 signature of the notification payload
+/// delivered at `timestamp` with the shared secret
+pub fn sign_payload(secret: &[u8], timestamp: u64, payload: &[u8]) -> String {
+    hmac(secret, &[timestamp.to_string().as_bytes(), b".", payload])[..].to_hex()
+}
+
+/// Verifies hex-encoded signature of the notification payload, as produced by
+/// [`sign_payload`], and checks that the delivery `timestamp` is within
+/// [`TIMESTAMP_TOLERANCE`] from `now`. Signatures are compared in constant
+/// time.
+pub fn verify_payload(
+    secret: &[u8],
+    timestamp: u64,
+    payload: &[u8],
+    signature: &str,
+    now: u64,
+) -> Result<(), NotifyError> {
+    let signature = Vec::<u8>::from_hex(signature).map_err(|_| NotifyError::InvalidSignature)?;
+    let expected = hmac(secret, &[timestamp.to_string().as_bytes(), b".", payload]);
+    if !bool::from(expected[..].ct_eq(&signature)) {
+        return Err(NotifyError::InvalidSignature);
+    }
+    if timestamp.abs_diff(now) > TIMESTAMP_TOLERANCE {
+        return Err(NotifyError::StaleTimestamp(timestamp));
+    }
+    Ok(())
+}
+
+/// Notifier delivering events to a set of sinks
+#[derive(Clone, Debug)]
+pub struct Notifier {
+    sinks: Vec<NotifySink>,
+    secret: Vec<u8>,
+    agent: ureq::Agent,
+    timeout: Duration,
+}
+
+impl Notifier {
+    /// Constructs notifier for the given sinks, signing payloads with
+    /// `secret`. Webhook requests are performed with the provided HTTP agent;
+    /// delivery to each of the sinks is limited by [`DEFAULT_TIMEOUT`].
+    pub fn with(sinks: Vec<NotifySink>, secret: impl AsRef<[u8]>, agent: ureq::Agent) -> Self {
+        Notifier {
+            sinks,
+            secret: secret.as_ref().to_vec(),
+            agent,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets timeout for delivering notification to a single sink
+    pub fn set_timeout(&mut self, timeout: Duration) { self.timeout = timeout; }
+
+    /// Returns sinks receiving notifications
+    pub fn sinks(&self) -> &[NotifySink] { &self.sinks }
+
+    /// Delivers event to all sinks. Failure to deliver to one of the sinks
+    /// does not prevent delivery to the others; returns errors for all sinks
+    /// which failed.
+    pub fn notify(&self, event: &impl Serialize) -> Vec<NotifyError> {
+        let payload = match serde_json::to_string(event) {
+            Ok(payload) => payload,
+            Err(err) => return vec![NotifyError::Serialize(err.to_string())],
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let signature = sign_payload(&self.secret, timestamp, payload.as_bytes());
+        self.sinks
+            .iter()
+            .filter_map(|sink| self.deliver(sink, timestamp, &payload, &signature).err())
+            .collect()
+    }
+
+    fn deliver(
+        &self,
+        sink: &NotifySink,
+        timestamp: u64,
+        payload: &str,
+        signature: &str,
+    ) -> Result<(), NotifyError> {
+        match sink {
+            NotifySink::Webhook(url) => self
+                .agent
+                .post(url)
+                .timeout(self.timeout)
+                .set("Content-Type", "application/json")
+                .set(TIMESTAMP_HEADER, &timestamp.to_string())
+                .set(SIGNATURE_HEADER, &format!("sha256={}", signature))
+                .send_string(payload)
+                .map(|_| ())
+                .map_err(|err| NotifyError::Webhook(url.clone(), err.to_string())),
+            #[cfg(unix)]
+            NotifySink::UnixSocket(path) => UnixStream::connect(path)
+                .and_then(|mut stream| {
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    writeln!(stream, "{} sha256={} {}", timestamp, signature, payload)
+                })
+                .map_err(|err| NotifyError::Socket(sink.to_string(), err.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn event() -> serde_json::Value { json!({ "event": "confirmed", "confirmations": 6 }) }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    #[test]
+    fn sinks() {
+        assert_eq!(
+            NotifySink::from_str("https://shop.example/hook"),
+            Ok(NotifySink::Webhook("https://shop.example/hook".to_owned()))
+        );
+        assert_eq!(
+            NotifySink::from_str("unix:/run/wallet.sock")
+                .unwrap()
+                .to_string(),
+            "unix:/run/wallet.sock"
+        );
+        assert_eq!(
+            NotifySink::from_str("unix:"),
+            Err(NotifyError::UnknownSink("unix:".to_owned()))
+        );
+        assert_eq!(
+            NotifySink::from_str("ftp://shop.example"),
+            Err(NotifyError::UnknownSink("ftp://shop.example".to_owned()))
+        );
+    }
+
+    #[test]
+    fn signature() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hmac(b"Jefe", &[b"what do ya want", b" for nothing?"])[..].to_hex(),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        let payload = b"{\"event\":\"confirmed\"}";
+        let signature = sign_payload(b"secret", 1_700_000_000, payload);
+        assert_eq!(
+            signature,
+            hmac(b"secret", &[b"1700000000.", payload])[..].to_hex()
+        );
+        assert_eq!(
+            verify_payload(b"secret", 1_700_000_000, payload, &signature, 1_700_000_100),
+            Ok(())
+        );
+        assert_eq!(
+            verify_payload(b"Secret", 1_700_000_000, payload, &signature, 1_700_000_000),
+            Err(NotifyError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_payload(b"secret", 1_700_000_001, payload, &signature, 1_700_000_000),
+            Err(NotifyError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_payload(b"secret", 1_700_000_000, b"{}", &signature, 1_700_000_000),
+            Err(NotifyError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_payload(b"secret", 1_700_000_000, payload, "sha256", 1_700_000_000),
+            Err(NotifyError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_payload(
+                b"secret",
+                1_700_000_000,
+                payload,
+                &signature[..62],
+                1_700_000_000
+            ),
+            Err(NotifyError::InvalidSignature)
+        );
+
+        // Replayed notification
+        assert_eq!(
+            verify_payload(b"secret", 1_700_000_000, payload, &signature, 1_700_000_301),
+            Err(NotifyError::StaleTimestamp(1_700_000_000))
+        );
+        assert_eq!(
+            verify_payload(b"secret", 1_700_000_000, payload, &signature, 1_699_999_699),
+            Err(NotifyError::StaleTimestamp(1_700_000_000))
+        );
+    }
+
+    #[test]
+    fn webhook_delivery() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut headers = vec![];
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                headers.push(line.trim().to_owned());
+            }
+            let len = headers
+                .iter()
+                .find_map(|header| {
+                    header
+                        .to_lowercase()
+                        .strip_prefix("content-length: ")?
+                        .parse()
+                        .ok()
+                })
+                .unwrap();
+            let mut body = vec![0u8; len];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            (headers, body)
+        });
+
+        let sink = NotifySink::from_str(&url).unwrap();
+        let notifier = Notifier::with(vec![sink], b"secret", ureq::Agent::new());
+        assert_eq!(notifier.notify(&event()), vec![]);
+
+        let (headers, body) = server.join().unwrap();
+        assert_eq!(body, serde_json::to_vec(&event()).unwrap());
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find_map(|header| header.strip_prefix(&format!("{}: ", name)))
+                .unwrap()
+        };
+        let timestamp = header(TIMESTAMP_HEADER).parse().unwrap();
+        let signature = header(SIGNATURE_HEADER).strip_prefix("sha256=").unwrap();
+        assert_eq!(
+            verify_payload(b"secret", timestamp, &body, signature, now()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn webhook_timeout() {
+        // Server accepting connections but never responding
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let sink = NotifySink::from_str(&url).unwrap();
+        let mut notifier = Notifier::with(vec![sink], b"secret", ureq::Agent::new());
+        notifier.set_timeout(Duration::from_millis(200));
+        let errors = notifier.notify(&event());
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], NotifyError::Webhook(sink, _) if *sink == url));
+        drop(listener);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket_delivery() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("wallet-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let socket = NotifySink::UnixSocket(path.clone());
+        let missing = NotifySink::UnixSocket(path.with_extension("missing"));
+        let notifier = Notifier::with(vec![missing.clone(), socket], b"secret", ureq::Agent::new());
+        let errors = notifier.notify(&event());
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], NotifyError::Socket(sink, _) if *sink == missing.to_string()));
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        let mut parts = line.trim_end().splitn(3, ' ');
+        let timestamp = parts.next().unwrap().parse().unwrap();
+        let signature = parts.next().unwrap().strip_prefix("sha256=").unwrap();
+        let payload = parts.next().unwrap();
+        assert_eq!(payload, serde_json::to_string(&event()).unwrap());
+        assert_eq!(
+            verify_payload(b"secret", timestamp, payload.as_bytes(), signature, now()),
+            Ok(())
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,529 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Parsing of payment instructions: BIP-21 `bitcoin:` URIs and BIP-353
+//! human-readable names (`₿user@domain`), which resolve into BIP-21 URIs
+//! published in DNS TXT records.
+//!
+//! DNS resolution is performed by the [`ResolveTxt`] implementations
+//! provided by the library users. BIP-353 requires TXT records to be
+//! authenticated with DNSSEC, thus records which were not authenticated by the
+//! resolver are always rejected.
+
+use std::fmt::{self, Formatter, Write};
+use std::str::FromStr;
+
+use amplify::{Display, Error};
+use bitcoin::util::address;
+use bitcoin::Address;
+
+use crate::amount::{self, AmountParseError, Denomination};
+
+const BIP21_SCHEME: &str = "bitcoin:";
+
+/// Errors parsing payment instructions
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PaymentError {
+    /// invalid address in payment request: {0}
+    InvalidAddress(address::Error),
+
+    /// invalid amount `{0}` in payment request; amount must be given in BTC
+    InvalidAmount(String),
+
+    /// invalid percent-encoding in `{0}`
+    InvalidEncoding(String),
+
+    /// payment request contains duplicated parameter `{0}`
+    DuplicateParam(String),
+
+    /// payment request requires unsupported parameter `{0}`
+    UnsupportedParam(String),
+
+    /// payment request has neither address nor lightning invoice
+    NoDestination,
+
+    /// `{0}` is neither bitcoin address, BIP-21 URI nor BIP-353 name
+    Unrecognized(String),
+}
+
+/// Errors resolving BIP-353 human-readable names
+#[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ResolveError<E: std::error::Error> {
+    /// unable to resolve DNS records: {0}
+    Resolver(E),
+
+    /// no BIP-353 payment instruction is published for {0}
+    NoRecord(HumanReadableName),
+
+    /// multiple BIP-353 payment instructions are published for {0}
+    MultipleRecords(HumanReadableName),
+
+    /// DNS records for {0} are not authenticated with DNSSEC
+    Unauthenticated(HumanReadableName),
+
+    /// invalid payment instruction published for {0}: {1}
+    Request(HumanReadableName, PaymentError),
+}
+
+/// Payment request, parsed from BIP-21 URI, bitcoin address or resolved from
+/// BIP-353 name.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct PaymentRequest {
+    /// On-chain address of the payee, if any
+    pub address: Option<Address>,
+
+    /// Requested amount, in satoshis
+    pub amount: Option<u64>,
+
+    /// Label for the payee address
+    pub label: Option<String>,
+
+    /// Message describing the payment
+    pub message: Option<String>,
+
+    /// BOLT-11 lightning invoice, which may be paid instead of the on-chain
+    /// address
+    pub lightning: Option<String>,
+
+    /// BIP-353 name the request was resolved from
+    pub name: Option<HumanReadableName>,
+}
+
+impl PaymentRequest {
+    /// Parses BIP-21 `bitcoin:` URI. Unknown optional parameters are ignored;
+    /// unknown parameters prefixed with `req-` result in an error.
+    pub fn from_uri(uri: &str) -> Result<Self, PaymentError> {
+        if !is_bip21(uri) {
+            return Err(PaymentError::Unrecognized(uri.to_owned()));
+        }
+        let rest = &uri[BIP21_SCHEME.len()..];
+        let (addr, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+        let mut request = PaymentRequest::default();
+        if !addr.is_empty() {
+            let address = Address::from_str(addr).map_err(PaymentError::InvalidAddress)?;
+            request.address = Some(address);
+        }
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let value = percent_decode(value)?;
+            let field = match key.to_lowercase().as_str() {
+                "amount" => {
+                    if request.amount.is_some() {
+                        return Err(PaymentError::DuplicateParam(key.to_owned()));
+                    }
+                    request.amount = Some(parse_btc(&value)?);
+                    continue;
+                }
+                "label" => &mut request.label,
+                "message" => &mut request.message,
+                "lightning" => &mut request.lightning,
+                key if key.starts_with("req-") => {
+                    return Err(PaymentError::UnsupportedParam(key.to_owned()))
+                }
+                _ => continue,
+            };
+            if field.replace(value).is_some() {
+                return Err(PaymentError::DuplicateParam(key.to_owned()));
+            }
+        }
+
+        if request.address.is_none() && request.lightning.is_none() {
+            return Err(PaymentError::NoDestination);
+        }
+        Ok(request)
+    }
+}
+
+impl fmt::Display for PaymentRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(BIP21_SCHEME)?;
+        if let Some(address) = &self.address {
+            fmt::Display::fmt(address, f)?;
+        }
+        let mut separator = '?';
+        let mut param = |f: &mut Formatter<'_>, key: &str, value: &str| -> fmt::Result {
+            write!(f, "{}{}={}", separator, key, percent_encode(value))?;
+            separator = '&';
+            Ok(())
+        };
+        if let Some(amount) = self.amount {
+            param(
+                f,
+                "amount",
+                &amount::format_amount(amount, Denomination::Btc),
+            )?;
+        }
+        if let Some(label) = &self.label {
+            param(f, "label", label)?;
+        }
+        if let Some(message) = &self.message {
+            param(f, "message", message)?;
+        }
+        if let Some(lightning) = &self.lightning {
+            param(f, "lightning", lightning)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_bip21(s: &str) -> bool {
+    s.get(..BIP21_SCHEME.len())
+        .map(|scheme| scheme.eq_ignore_ascii_case(BIP21_SCHEME))
+        .unwrap_or_default()
+}
+
+fn parse_btc(value: &str) -> Result<u64, PaymentError> {
+    let invalid = || PaymentError::InvalidAmount(value.to_owned());
+    if value.is_empty() || !value.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return Err(invalid());
+    }
+    amount::parse_amount(value, Denomination::Btc).map_err(|_: AmountParseError| invalid())
+}
+
+fn percent_decode(s: &str) -> Result<String, PaymentError> {
+    let invalid = || PaymentError::InvalidEncoding(s.to_owned());
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(byte) = iter.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = [
+            iter.next().ok_or_else(invalid)?,
+            iter.next().ok_or_else(invalid)?,
+        ];
+        let hex = std::str::from_utf8(&hex).map_err(|_| invalid())?;
+        bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => write!(encoded, "%{:02X}", byte).expect("writing to string"),
+        }
+    }
+    encoded
+}
+
+/// BIP-353 human-readable name `₿user@domain`
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display("₿{user}@{domain}")]
+pub struct HumanReadableName {
+    /// User part of the name
+    pub user: String,
+
+    /// Domain part of the name
+    pub domain: String,
+}
+
+impl FromStr for HumanReadableName {
+    type Err = PaymentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PaymentError::Unrecognized(s.to_owned());
+        let name = s.strip_prefix('₿').unwrap_or(s);
+        let (user, domain) = name.split_once('@').ok_or_else(invalid)?;
+        let is_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+        if !is_label(user) || !domain.split('.').all(is_label) || !domain.contains('.') {
+            return Err(invalid());
+        }
+        Ok(HumanReadableName {
+            user: user.to_lowercase(),
+            domain: domain.to_lowercase(),
+        })
+    }
+}
+
+impl HumanReadableName {
+    /// Returns DNS name holding TXT record with the payment instruction
+    pub fn dns_name(&self) -> String {
+        format!("{}.user._bitcoin-payment.{}.", self.user, self.domain)
+    }
+
+    /// Extracts payment request from TXT records published under
+    /// [`HumanReadableName::dns_name`]. Each record must be given as a
+    /// concatenation of its character strings; records which are not
+    /// BIP-21 URIs are ignored.
+    pub fn payment_request<E: std::error::Error>(
+        &self,
+        records: &TxtRecords,
+    ) -> Result<PaymentRequest, ResolveError<E>> {
+        if !records.authenticated {
+            return Err(ResolveError::Unauthenticated(self.clone()));
+        }
+        let mut uris = records.records.iter().filter(|record| is_bip21(record));
+        let uri = uris
+            .next()
+            .ok_or_else(|| ResolveError::NoRecord(self.clone()))?;
+        if uris.next().is_some() {
+            return Err(ResolveError::MultipleRecords(self.clone()));
+        }
+        let mut request = PaymentRequest::from_uri(uri)
+            .map_err(|err| ResolveError::Request(self.clone(), err))?;
+        request.name = Some(self.clone());
+        Ok(request)
+    }
+
+    /// Resolves payment request published for the name in DNS.
+    pub fn resolve<R: ResolveTxt>(
+        &self,
+        resolver: &mut R,
+    ) -> Result<PaymentRequest, ResolveError<R::Error>> {
+        let records = resolver
+            .resolve_txt(&self.dns_name())
+            .map_err(ResolveError::Resolver)?;
+        self.payment_request(&records)
+    }
+}
+
+/// DNS TXT records returned by [`ResolveTxt`]
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct TxtRecords {
+    /// Records, each being a concatenation of its character strings
+    pub records: Vec<String>,
+
+    /// Whether the records were authenticated with DNSSEC
+    pub authenticated: bool,
+}
+
+impl TxtRecords {
+    /// Parses TXT record data given in DNS presentation format (RFC 1035,
+    /// section 5.1) as a sequence of quoted or unquoted character strings,
+    /// returning their concatenation. Returns `None` if the data are not a
+    /// valid sequence of UTF-8 character strings.
+    pub fn parse_presentation(data: &str) -> Option<String> {
+        let mut bytes = data.bytes().peekable();
+        let mut record = Vec::with_capacity(data.len());
+        loop {
+            while bytes.next_if(u8::is_ascii_whitespace).is_some() {}
+            let quoted = match bytes.peek() {
+                None => break,
+                Some(c) => *c == b'"',
+            };
+            if quoted {
+                bytes.next();
+            }
+            loop {
+                match bytes.next() {
+                    None if quoted => return None,
+                    None => break,
+                    Some(b'"') if quoted => break,
+                    Some(b'"') => return None,
+                    Some(c) if !quoted && c.is_ascii_whitespace() => break,
+                    Some(b'\\') => match bytes.next()? {
+                        digit @ b'0'..=b'9' => {
+                            let mut code = (digit - b'0') as u16;
+                            for _ in 0..2 {
+                                let digit = bytes.next().filter(u8::is_ascii_digit)?;
+                                code = code * 10 + (digit - b'0') as u16;
+                            }
+                            record.push(u8::try_from(code).ok()?);
+                        }
+                        c => record.push(c),
+                    },
+                    Some(c) => record.push(c),
+                }
+            }
+        }
+        String::from_utf8(record).ok()
+    }
+}
+
+/// DNS resolver for TXT records, required to resolve BIP-353 names
+pub trait ResolveTxt {
+    /// Error returned by the resolver
+    type Error: std::error::Error;
+
+    /// Returns TXT records published under a given fully-qualified DNS name
+    fn resolve_txt(&mut self, name: &str) -> Result<TxtRecords, Self::Error>;
+}
+
+/// Payment instruction given by the user: either a complete payment request
+/// (BIP-21 URI or bitcoin address), or a BIP-353 name which has to be
+/// resolved into a payment request with [`HumanReadableName::resolve`].
+#[derive(Clone, PartialEq, Eq, Debug, Display)]
+#[display(inner)]
+pub enum PaymentInstruction {
+    /// Payment request
+    Request(PaymentRequest),
+
+    /// BIP-353 name
+    Name(HumanReadableName),
+}
+
+impl FromStr for PaymentInstruction {
+    type Err = PaymentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if is_bip21(s) {
+            return PaymentRequest::from_uri(s).map(PaymentInstruction::Request);
+        }
+        if s.contains('@') {
+            return HumanReadableName::from_str(s).map(PaymentInstruction::Name);
+        }
+        let address = Address::from_str(s).map_err(|_| PaymentError::Unrecognized(s.to_owned()))?;
+        Ok(PaymentInstruction::Request(PaymentRequest {
+            address: Some(address),
+            ..Default::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Clone, PartialEq, Eq, Debug, Display, Error)]
+    #[display("no such domain")]
+    struct NxDomain;
+
+    impl ResolveTxt for TxtRecords {
+        type Error = NxDomain;
+
+        fn resolve_txt(&mut self, name: &str) -> Result<TxtRecords, Self::Error> {
+            match name {
+                "alice.user._bitcoin-payment.example.com." => Ok(self.clone()),
+                _ => Err(NxDomain),
+            }
+        }
+    }
+
+    #[test]
+    fn bip21() {
+        let uri = "BITCOIN:bc1qtkr96rhavl4z4ftxa4mewlvmgd8dnp6pe9nuht?amount=0.015&label=Luke%\
+                   20Jr&message=Donation%20for%20project%20xyz&somethingelse=1";
+        let request = PaymentRequest::from_uri(uri).unwrap();
+        assert_eq!(
+            request.address.as_ref().unwrap().to_string(),
+            "bc1qtkr96rhavl4z4ftxa4mewlvmgd8dnp6pe9nuht"
+        );
+        assert_eq!(request.amount, Some(1_500_000));
+        assert_eq!(request.label.as_deref(), Some("Luke Jr"));
+        assert_eq!(request.message.as_deref(), Some("Donation for project xyz"));
+        assert_eq!(
+            request.to_string(),
+            "bitcoin:bc1qtkr96rhavl4z4ftxa4mewlvmgd8dnp6pe9nuht?amount=0.01500000&label=Luke%20Jr&\
+             message=Donation%20for%20project%20xyz"
+        );
+        assert_eq!(PaymentRequest::from_uri(&request.to_string()), Ok(request));
+
+        let lightning = PaymentRequest::from_uri("bitcoin:?lightning=lnbc1qqq").unwrap();
+        assert_eq!(lightning.address, None);
+        assert_eq!(lightning.lightning.as_deref(), Some("lnbc1qqq"));
+
+        let no_destination = PaymentRequest::from_uri("bitcoin:?amount=1");
+        assert_eq!(no_destination, Err(PaymentError::NoDestination));
+        let addr = "bitcoin:bc1qtkr96rhavl4z4ftxa4mewlvmgd8dnp6pe9nuht";
+        assert_eq!(
+            PaymentRequest::from_uri(&format!("{}?amount=1BTC", addr)),
+            Err(PaymentError::InvalidAmount(String::from("1BTC")))
+        );
+        assert_eq!(
+            PaymentRequest::from_uri(&format!("{}?req-pop=1", addr)),
+            Err(PaymentError::UnsupportedParam(String::from("req-pop")))
+        );
+        assert_eq!(
+            PaymentRequest::from_uri(&format!("{}?label=a&label=b", addr)),
+            Err(PaymentError::DuplicateParam(String::from("label")))
+        );
+    }
+
+    #[test]
+    fn bip353() {
+        let name = HumanReadableName::from_str("₿Alice@Example.com").unwrap();
+        assert_eq!(name.to_string(), "₿alice@example.com");
+        assert_eq!(
+            PaymentInstruction::from_str("alice@example.com"),
+            Ok(PaymentInstruction::Name(name.clone()))
+        );
+        assert!(HumanReadableName::from_str("alice@localhost").is_err());
+        assert!(HumanReadableName::from_str("al ice@example.com").is_err());
+
+        let mut records = TxtRecords {
+            records: vec![
+                String::from("v=spf1 -all"),
+                String::from("bitcoin:bc1qtkr96rhavl4z4ftxa4mewlvmgd8dnp6pe9nuht?amount=0.001"),
+            ],
+            authenticated: true,
+        };
+        let request = name.resolve(&mut records.clone()).unwrap();
+        assert_eq!(request.amount, Some(100_000));
+        assert_eq!(request.name, Some(name.clone()));
+
+        let unknown = HumanReadableName::from_str("bob@example.com").unwrap();
+        let resolved = unknown.resolve(&mut records.clone());
+        assert_eq!(resolved, Err(ResolveError::Resolver(NxDomain)));
+
+        records
+            .records
+            .push(String::from("bitcoin:?lightning=lnbc1qqq"));
+        assert_eq!(
+            name.resolve(&mut records.clone()),
+            Err(ResolveError::MultipleRecords(name.clone()))
+        );
+
+        records.records.clear();
+        assert_eq!(
+            name.resolve(&mut records.clone()),
+            Err(ResolveError::NoRecord(name.clone()))
+        );
+
+        records.records.push(String::from(
+            "bitcoin:bc1qtkr96rhavl4z4ftxa4mewlvmgd8dnp6pe9nuht?amount=0.001",
+        ));
+        records.authenticated = false;
+        assert_eq!(
+            name.resolve(&mut records),
+            Err(ResolveError::Unauthenticated(name))
+        );
+    }
+
+    #[test]
+    fn txt_presentation() {
+        assert_eq!(
+            TxtRecords::parse_presentation(r#""bitcoin:?amount=0.1" "&label=a\"b""#).as_deref(),
+            Some(r#"bitcoin:?amount=0.1&label=a"b"#)
+        );
+        assert_eq!(
+            TxtRecords::parse_presentation(r#""bitcoin:\063lightning" =lnbc"#).as_deref(),
+            Some("bitcoin:?lightning=lnbc")
+        );
+        assert_eq!(
+            TxtRecords::parse_presentation(r#""a\\b c""#).as_deref(),
+            Some(r"a\b c")
+        );
+        assert_eq!(TxtRecords::parse_presentation("").as_deref(), Some(""));
+        assert_eq!(TxtRecords::parse_presentation(r#""unterminated"#), None);
+        assert_eq!(TxtRecords::parse_presentation(r#""a\25""#), None);
+        assert_eq!(TxtRecords::parse_presentation(r#""a\256""#), None);
+        assert_eq!(TxtRecords::parse_presentation(r#"a"b"#), None);
+    }
+}
@@ -0,0 +1,1010 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Machine-readable reports on the results of wallet operations, used by the
+//! command-line tools in JSON and YAML output modes. All amounts are in
+//! satoshis.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use amplify::hex::ToHex;
+use amplify::Wrapper;
+use bitcoin::blockdata::opcodes::all as op;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::consensus::serialize;
+use bitcoin::{BlockHash, Network, OutPoint, Script, Transaction, Txid, XOnlyPublicKey};
+use bitcoin_scripts::{PubkeyScript, TaprootWitness};
+use descriptors::{is_standard_script, CompositeDescrType, DeductionError};
+use onchain::blockchain::MiningStatus;
+use psbt::Psbt;
+use serde_crate::Serialize;
+
+#[cfg(feature = "strict_encoding")]
+use crate::sync::{SyncState, SyncSummary};
+use crate::Capabilities;
+
+/// Format in which command-line tools print results of the commands
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub enum OutputFormat {
+    /// Human-readable text, which may be colored
+    #[default]
+    Plain,
+
+    /// JSON serialization of the command report
+    Json,
+
+    /// YAML serialization of the command report
+    Yaml,
+}
+
+impl OutputFormat {
+    /// Detects whether the results are printed as a human-readable text
+    /// instead of a structured report
+    pub fn is_plain(self) -> bool { self == OutputFormat::Plain }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Plain => f.write_str("plain"),
+            OutputFormat::Json => f.write_str("json"),
+            OutputFormat::Yaml => f.write_str("yaml"),
+        }
+    }
+}
+
+/// Error parsing output format name
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display("unknown output format `{0}`; supported formats are: plain, json, yaml")]
+pub struct UnknownOutputFormat(pub String);
+
+impl FromStr for OutputFormat {
+    type Err = UnknownOutputFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "plain" | "text" => Ok(OutputFormat::Plain),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" | "yml" => Ok(OutputFormat::Yaml),
+            _ => Err(UnknownOutputFormat(s.to_owned())),
+        }
+    }
+}
+
+/// Wallet file created, imported or updated by a command
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct WalletReport {
+    /// Path to the wallet file
+    pub wallet_file: PathBuf,
+
+    /// Current wallet descriptor
+    pub descriptor: String,
+
+    /// Separate change descriptor, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change_descriptor: Option<String>,
+
+    /// Generation of the current wallet descriptor, i.e. the number of
+    /// previous descriptors replaced by descriptor rotations
+    pub generation: usize,
+
+    /// Wallet birthday block height or UNIX timestamp, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub birthday: Option<u32>,
+
+    /// Network used by the wallet
+    pub network: Network,
+}
+
+/// Result of verification of a PSBT input signature
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct SigAuditReport {
+    /// Index of the PSBT input
+    pub input: usize,
+
+    /// Signature location within the input
+    pub signature: String,
+
+    /// Sighash type used by the signature
+    pub sighash_type: String,
+
+    /// Whether the signature is valid
+    pub valid: bool,
+
+    /// Reason why the signature is invalid or can't be verified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Named account of a multi-account wallet container
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct AccountReport {
+    /// Account name
+    pub name: String,
+
+    /// Account output descriptor
+    pub descriptor: String,
+
+    /// Network used by the account
+    pub network: Network,
+}
+
+/// Address derived by the wallet
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct AddressReport {
+    /// Terminal derivation path of the address
+    pub terminal: String,
+
+    /// Address string
+    pub address: String,
+
+    /// Label assigned to the address, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Classification of an arbitrary address
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct AddressInfoReport {
+    /// Address string
+    pub address: String,
+
+    /// Address class: `p2pkh`, `p2sh`, `p2wpkh`, `p2wsh`, `p2tr` or
+    /// `future(<version>)`
+    pub class: String,
+
+    /// Network of the address
+    pub network: Network,
+
+    /// Witness version used by the address, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub witness_version: Option<u8>,
+
+    /// Hex-encoded `scriptPubkey` of the address
+    pub script_pubkey: String,
+
+    /// `scriptPubkey` descriptor of the address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub descriptor: Option<String>,
+
+    /// Whether the address is known to burn the funds sent to it
+    pub burn: bool,
+}
+
+/// Unspent output found by the wallet scan
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct UtxoReport {
+    /// Wallet descriptor generation controlling the output; `None` for the
+    /// separate change descriptor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<usize>,
+
+    /// Terminal derivation path of the output script
+    pub terminal: String,
+
+    /// Address of the output, if the output script has an address form
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+
+    /// Hex-encoded output script
+    pub script_pubkey: String,
+
+    /// Outpoint of the unspent output
+    pub outpoint: OutPoint,
+
+    /// Amount stored in the output
+    pub amount: u64,
+
+    /// Height of the block mining the output, or `None` for unconfirmed
+    /// outputs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u64>,
+
+    /// Whether the output is frozen
+    pub frozen: bool,
+
+    /// Label assigned to the output address, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+impl UtxoReport {
+    /// Returns block height from the output mining status
+    pub fn mined_height(status: &MiningStatus) -> Option<u64> {
+        match status {
+            MiningStatus::Blockchain(height) => Some(*height),
+            _ => None,
+        }
+    }
+}
+
+/// Results of the wallet scan
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct ScanReport {
+    /// Found unspent outputs
+    pub utxos: Vec<UtxoReport>,
+
+    /// Total amount of the found outputs
+    pub total: u64,
+
+    /// Warnings produced by the scan
+    pub warnings: Vec<String>,
+}
+
+/// Summary of a constructed or modified PSBT
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct PsbtReport {
+    /// File containing the PSBT, if it was saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub psbt_file: Option<PathBuf>,
+
+    /// Base64-encoded PSBT
+    pub psbt: String,
+
+    /// Id of the unsigned transaction
+    pub txid: Txid,
+
+    /// Number of the transaction inputs
+    pub inputs: usize,
+
+    /// Number of the transaction outputs
+    pub outputs: usize,
+
+    /// Total amount of the transaction outputs
+    pub output_total: u64,
+
+    /// Transaction fee, if all spent outputs are known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee: Option<u64>,
+
+    /// Virtual size of the unsigned transaction
+    pub unsigned_vsize: usize,
+
+    /// Estimated virtual size of the signed transaction, if it can be
+    /// estimated for all inputs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signed_vsize: Option<usize>,
+
+    /// Indexes of the change outputs
+    pub change_outputs: Vec<usize>,
+}
+
+impl PsbtReport {
+    /// Constructs report for a PSBT, saved to a given file, if any
+    pub fn with(psbt: &Psbt, psbt_file: Option<PathBuf>) -> PsbtReport {
+        let analysis = psbt.analyze();
+        PsbtReport {
+            psbt_file,
+            psbt: psbt.to_base64(),
+            txid: psbt.to_txid(),
+            inputs: psbt.inputs.len(),
+            outputs: psbt.outputs.len(),
+            output_total: psbt.outputs.iter().map(|output| output.amount).sum(),
+            fee: analysis.fee,
+            unsigned_vsize: analysis.unsigned_vsize(),
+            signed_vsize: analysis.signed_vsize(),
+            change_outputs: analysis.change_outputs,
+        }
+    }
+}
+
+/// Finalized transaction
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct TxReport {
+    /// Transaction id
+    pub txid: Txid,
+
+    /// Hex-encoded consensus serialization of the transaction
+    pub tx: String,
+
+    /// File containing the transaction, if it was saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_file: Option<PathBuf>,
+
+    /// Network to which the transaction was published, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<Network>,
+}
+
+impl TxReport {
+    /// Constructs report for a transaction
+    pub fn with(tx: &Transaction, tx_file: Option<PathBuf>, published: Option<Network>) -> Self {
+        TxReport {
+            txid: tx.txid(),
+            tx: serialize(tx).to_hex(),
+            tx_file,
+            published,
+        }
+    }
+}
+
+/// Disassembled transaction input
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct InputInspectReport {
+    /// Spent transaction output
+    pub prev_output: OutPoint,
+
+    /// Input sequence number
+    pub sequence: u32,
+
+    /// Disassembled `scriptSig`
+    pub script_sig: String,
+
+    /// Hex-encoded witness stack elements
+    pub witness: Vec<String>,
+
+    /// Disassembled redeem script, witness script or taproot leaf script
+    /// revealed by the input, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revealed_script: Option<String>,
+
+    /// Type of the spent descriptor guessed from the spending data, if
+    /// recognized
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub descr_type: Option<String>,
+
+    /// Class of the spent descriptor, if recognized
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+}
+
+/// Disassembled transaction output
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct OutputInspectReport {
+    /// Output amount
+    pub value: u64,
+
+    /// Disassembled `scriptPubkey`
+    pub script_pubkey: String,
+
+    /// Type of the descriptor matching `scriptPubkey`, if recognized
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub descr_type: Option<String>,
+
+    /// Class of the descriptor matching `scriptPubkey`, if recognized
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+}
+
+/// Disassembled transaction with classified inputs and outputs
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct TxInspectReport {
+    /// Transaction id
+    pub txid: Txid,
+
+    /// Transaction version
+    pub version: i32,
+
+    /// Transaction lock time, in consensus encoding
+    pub lock_time: u32,
+
+    /// Transaction weight, in weight units
+    pub weight: usize,
+
+    /// Disassembled transaction inputs
+    pub inputs: Vec<InputInspectReport>,
+
+    /// Disassembled transaction outputs
+    pub outputs: Vec<OutputInspectReport>,
+
+    /// Non-standard scripts and other anomalies detected in the transaction
+    pub anomalies: Vec<String>,
+}
+
+impl TxInspectReport {
+    /// Disassembles transaction scripts and classifies its inputs (using the
+    /// spending data only, since the spent outputs are unknown) and outputs
+    pub fn with(tx: &Transaction) -> Self {
+        let mut anomalies = vec![];
+        if !(1..=3).contains(&tx.version) {
+            anomalies.push(format!(
+                "transaction version {} is non-standard",
+                tx.version
+            ));
+        }
+
+        let mut input_types = BTreeSet::new();
+        let mut inputs = Vec::with_capacity(tx.input.len());
+        for (index, txin) in tx.input.iter().enumerate() {
+            let descr_type = CompositeDescrType::deduce_spending(&txin.script_sig, &txin.witness);
+            let revealed_script = match descr_type {
+                Some(CompositeDescrType::Sh) => match txin.script_sig.instructions().last() {
+                    Some(Ok(Instruction::PushBytes(data))) => Some(Script::from(data.to_vec())),
+                    _ => None,
+                },
+                Some(CompositeDescrType::Wsh | CompositeDescrType::ShWsh) => txin
+                    .witness
+                    .last()
+                    .map(|script| Script::from(script.to_vec())),
+                Some(CompositeDescrType::Tr) => {
+                    match TaprootWitness::try_from(txin.witness.clone()) {
+                        Ok(TaprootWitness::ScriptSpending { script, .. }) => {
+                            Some(script.script.into_inner())
+                        }
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            match descr_type {
+                _ if tx.is_coin_base() => {}
+                Some(ty) => {
+                    input_types.insert(ty);
+                }
+                None if !is_push_only(&txin.script_sig) => anomalies.push(format!(
+                    "input #{} has non-standard scriptSig with non-push operations",
+                    index
+                )),
+                None => {
+                    anomalies.push(format!("input #{} spending data are not recognized", index))
+                }
+            }
+            inputs.push(InputInspectReport {
+                prev_output: txin.previous_output,
+                sequence: txin.sequence.to_consensus_u32(),
+                script_sig: txin.script_sig.asm(),
+                witness: txin
+                    .witness
+                    .iter()
+                    .map(|element| element.to_hex())
+                    .collect(),
+                revealed_script: revealed_script.as_ref().map(Script::asm),
+                descr_type: descr_type.as_ref().map(CompositeDescrType::to_string),
+                class: descr_type
+                    .and_then(CompositeDescrType::descriptor_class)
+                    .as_ref()
+                    .map(ToString::to_string),
+            });
+        }
+        if input_types.len() > 1 {
+            let types = input_types
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            anomalies.push(format!(
+                "inputs spend mixed script types: {}",
+                types.join(", ")
+            ));
+        }
+
+        let mut op_returns = 0usize;
+        let mut outputs = Vec::with_capacity(tx.output.len());
+        for (index, txout) in tx.output.iter().enumerate() {
+            let spk = PubkeyScript::from(txout.script_pubkey.clone());
+            let descr_type = match CompositeDescrType::deduce(&spk, None, false) {
+                _ if spk.is_op_return() => None,
+                Ok(ty) => Some(ty),
+                Err(DeductionError::P2shWithoutRedeemScript) => Some(CompositeDescrType::Sh),
+                Err(_) => None,
+            };
+            if spk.is_op_return() {
+                op_returns += 1;
+            }
+            if !is_standard_script(&spk) {
+                anomalies.push(format!("output #{} has non-standard scriptPubkey", index));
+            } else if let (Some(CompositeDescrType::Future), Some(version)) =
+                (descr_type, spk.witness_version())
+            {
+                anomalies.push(format!(
+                    "output #{} pays to future witness version {}, which is spendable by anyone",
+                    index, version
+                ));
+            }
+            outputs.push(OutputInspectReport {
+                value: txout.value,
+                script_pubkey: spk.asm(),
+                descr_type: descr_type.as_ref().map(CompositeDescrType::to_string),
+                class: descr_type
+                    .and_then(CompositeDescrType::descriptor_class)
+                    .as_ref()
+                    .map(ToString::to_string),
+            });
+        }
+        if op_returns > 1 {
+            anomalies.push(format!("transaction has {} OP_RETURN outputs", op_returns));
+        }
+
+        TxInspectReport {
+            txid: tx.txid(),
+            version: tx.version,
+            lock_time: tx.lock_time.0,
+            weight: tx.weight(),
+            inputs,
+            outputs,
+            anomalies,
+        }
+    }
+}
+
+/// Checks whether the script consists of push operations only.
+fn is_push_only(script: &Script) -> bool {
+    script.instructions().all(|instruction| match instruction {
+        Ok(Instruction::PushBytes(_)) => true,
+        Ok(Instruction::Op(opcode)) => opcode.to_u8() <= op::OP_PUSHNUM_16.to_u8(),
+        Err(_) => false,
+    })
+}
+
+/// Verified proof-of-reserves
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct ReservesReport {
+    /// Challenge message the proof commits to
+    pub message: String,
+
+    /// Block height at which the reserves were unspent
+    pub height: u32,
+
+    /// Outputs holding the reserves
+    pub reserves: Vec<OutPoint>,
+
+    /// Total amount of the reserves
+    pub amount: u64,
+}
+
+/// BIP-322 message signature produced or verified by a command
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct MessageReport {
+    /// Address (or hex-encoded `scriptPubkey` for outputs without address
+    /// form) which signed the message
+    pub address: String,
+
+    /// Signed message
+    pub message: String,
+
+    /// Base64-encoded signature
+    pub signature: String,
+
+    /// Whether the signature is valid
+    pub valid: bool,
+}
+
+/// Event detected while watching wallet addresses
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct WatchReport {
+    /// Event type: `new_tx`, `status_change`, `confirmed`,
+    /// `outgoing_confirmed`, `reorg` or `extended`
+    pub event: String,
+
+    /// Transaction the event is related to, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txid: Option<Txid>,
+
+    /// Current mining status of the transaction, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<MiningStatus>,
+
+    /// Number of transaction confirmations for `confirmed` events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confirmations: Option<u32>,
+
+    /// Derivation terminal of the affected wallet address, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terminal: Option<String>,
+
+    /// Affected wallet address, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+
+    /// Human-readable event description
+    pub message: String,
+}
+
+/// Information about extended public key
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct XpubReport {
+    /// Key fingerprint
+    pub fingerprint: String,
+
+    /// Key identifier
+    pub identifier: String,
+
+    /// Network of the key
+    pub network: Network,
+
+    /// Public key
+    pub public_key: String,
+
+    /// Chain code
+    pub chain_code: String,
+
+    /// SLIP-132 key application, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub application: Option<String>,
+
+    /// Standard derivation path for the key application, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub derivation: Option<String>,
+
+    /// Key depth
+    pub depth: u8,
+
+    /// Child number
+    pub child_number: String,
+
+    /// SLIP-132 representations of the key
+    pub variants: Vec<String>,
+}
+
+/// Descriptor compiled from a spending policy
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct CompiledReport {
+    /// Compiled descriptor
+    pub descriptor: String,
+
+    /// Descriptor class
+    pub class: String,
+
+    /// Spending policy
+    pub policy: String,
+
+    /// Script size, in bytes
+    pub script_size: usize,
+
+    /// Maximal size of the data satisfying the descriptor, in weight units
+    pub max_satisfaction_weight: usize,
+
+    /// Maximal input size, in vbytes
+    pub max_input_vsize: usize,
+
+    /// Fee paid for spending a single input at the `feerate`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_fee: Option<u64>,
+
+    /// Feerate used for the fee computation, in sat/vbyte
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feerate: Option<u32>,
+}
+
+/// Node of a taproot script tree
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct TreeNodeReport {
+    /// DFS path to the node
+    pub path: String,
+
+    /// Node depth
+    pub depth: usize,
+
+    /// Node hash
+    pub node_hash: String,
+
+    /// Leaf version, for leaf nodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leaf_version: Option<u8>,
+
+    /// Leaf script in assembly form, for leaf nodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+}
+
+/// Status of an operation which does not produce other results
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct StatusReport {
+    /// Whether the operation has changed anything
+    pub changed: bool,
+
+    /// Human-readable description of the operation result
+    pub message: String,
+}
+
+impl StatusReport {
+    /// Constructs report for an operation which has changed the data
+    pub fn changed(message: impl ToString) -> Self {
+        StatusReport {
+            changed: true,
+            message: message.to_string(),
+        }
+    }
+
+    /// Constructs report for an operation which has not changed anything
+    pub fn unchanged(message: impl ToString) -> Self {
+        StatusReport {
+            changed: false,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Wallet state synchronized between the online watcher and the air-gapped
+/// host
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct SyncReport {
+    /// File containing the exported or imported sync bundle, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bundle_file: Option<PathBuf>,
+
+    /// Public key of the trusted watcher
+    pub watcher: XOnlyPublicKey,
+
+    /// Sequence number of the last applied sync bundle
+    pub sequence: u64,
+
+    /// Number of outputs added or updated by the applied bundle, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added: Option<usize>,
+
+    /// Number of outputs spent by the applied bundle, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spent: Option<usize>,
+
+    /// Height of the last known chain tip
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tip_height: Option<u32>,
+
+    /// Hash of the last known chain tip block
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tip_hash: Option<BlockHash>,
+
+    /// Number of the wallet unspent outputs
+    pub utxo_count: usize,
+
+    /// Total amount of the wallet unspent outputs
+    pub balance: u64,
+
+    /// Fee rate estimates in satoshis per 1000 vbytes, indexed by the
+    /// confirmation target in blocks
+    pub fee_estimates: BTreeMap<u16, u64>,
+}
+
+#[cfg(feature = "strict_encoding")]
+impl SyncReport {
+    /// Constructs report for a sync state, optionally updated with a bundle
+    /// from a given file
+    pub fn with(
+        state: &SyncState,
+        summary: Option<SyncSummary>,
+        bundle_file: Option<PathBuf>,
+    ) -> SyncReport {
+        SyncReport {
+            bundle_file,
+            watcher: state.watcher,
+            sequence: state.sequence,
+            added: summary.map(|summary| summary.added),
+            spent: summary.map(|summary| summary.spent),
+            tip_height: state.tip.map(|tip| tip.height),
+            tip_hash: state.tip.map(|tip| tip.block_hash),
+            utxo_count: state.utxos.len(),
+            balance: state.balance(),
+            fee_estimates: state.fee_estimates.clone(),
+        }
+    }
+}
+
+/// Features the library was compiled with
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct CapabilitiesReport {
+    /// Version of the library
+    pub version: String,
+
+    /// Cargo names of the enabled features
+    pub features: Vec<String>,
+
+    /// Whether taproot script tree extensions are available
+    pub taproot_extensions: bool,
+
+    /// Available signer backends
+    pub signers: Vec<String>,
+}
+
+impl From<&Capabilities> for CapabilitiesReport {
+    fn from(capabilities: &Capabilities) -> Self {
+        CapabilitiesReport {
+            version: capabilities.version.to_owned(),
+            features: capabilities
+                .features
+                .iter()
+                .map(|feature| feature.cargo_name().to_owned())
+                .collect(),
+            taproot_extensions: capabilities.taproot_extensions,
+            signers: capabilities
+                .signers
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        }
+    }
+}
+
+/// Error produced by a command
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(crate = "serde_crate")]
+pub struct ErrorReport {
+    /// Error description
+    pub error: String,
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "serde_json")]
+    use bitcoin::hashes::Hash;
+    #[cfg(feature = "serde_json")]
+    use bitcoin::{PackedLockTime, TxIn, TxOut};
+    #[cfg(feature = "serde_json")]
+    use psbt::PsbtVersion;
+    #[cfg(feature = "serde_json")]
+    use serde_json::json;
+
+    use super::*;
+
+    #[cfg(feature = "serde_json")]
+    fn tx() -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::from_inner([0x01; 32]), 0),
+                ..TxIn::default()
+            }],
+            output: vec![
+                TxOut {
+                    value: 1_000,
+                    script_pubkey: Script::new_op_return(&[]),
+                },
+                TxOut {
+                    value: 2_000,
+                    script_pubkey: Script::new_op_return(&[1]),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde_json")]
+    fn status_report() {
+        assert_eq!(
+            serde_json::to_value(StatusReport::changed("key added")).unwrap(),
+            json!({ "changed": true, "message": "key added" })
+        );
+        assert_eq!(
+            serde_json::to_value(StatusReport::unchanged("no key")).unwrap(),
+            json!({ "changed": false, "message": "no key" })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde_json")]
+    fn tx_report() {
+        let tx = tx();
+        let report = TxReport::with(&tx, None, None);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({ "txid": tx.txid().to_string(), "tx": serialize(&tx).to_hex() })
+        );
+
+        let report = TxReport::with(&tx, Some(PathBuf::from("tx.bin")), Some(Network::Testnet));
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "txid": tx.txid().to_string(),
+                "tx": serialize(&tx).to_hex(),
+                "tx_file": "tx.bin",
+                "published": "testnet",
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde_json")]
+    fn psbt_report() {
+        let tx = tx();
+        let psbt = Psbt::with(tx.clone(), PsbtVersion::V0).unwrap();
+        let report = PsbtReport::with(&psbt, Some(PathBuf::from("tx.psbt")));
+        assert_eq!(report.psbt_file, Some(PathBuf::from("tx.psbt")));
+        assert_eq!(report.psbt, psbt.to_base64());
+        assert_eq!(report.txid, tx.txid());
+        assert_eq!(report.inputs, 1);
+        assert_eq!(report.outputs, 2);
+        assert_eq!(report.output_total, 3_000);
+
+        let value = serde_json::to_value(PsbtReport::with(&psbt, None)).unwrap();
+        assert_eq!(value["txid"], json!(tx.txid().to_string()));
+        assert!(value.get("psbt_file").is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "serde_json")]
+    fn utxo_report() {
+        assert_eq!(
+            UtxoReport::mined_height(&MiningStatus::Blockchain(100)),
+            Some(100)
+        );
+        assert_eq!(UtxoReport::mined_height(&MiningStatus::Mempool), None);
+        assert_eq!(UtxoReport::mined_height(&MiningStatus::Undefined), None);
+
+        let report = UtxoReport {
+            generation: None,
+            terminal: s!("0/1"),
+            address: None,
+            script_pubkey: s!("6a"),
+            outpoint: OutPoint::new(Txid::from_inner([0x01; 32]), 1),
+            amount: 1_000,
+            height: UtxoReport::mined_height(&MiningStatus::Mempool),
+            frozen: false,
+            label: None,
+        };
+        let value = serde_json::to_value(report).unwrap();
+        for field in ["generation", "address", "height", "label"] {
+            assert!(value.get(field).is_none());
+        }
+        assert_eq!(value["amount"], json!(1_000));
+        assert_eq!(value["frozen"], json!(false));
+    }
+
+    #[test]
+    fn output_format() {
+        for (name, format) in [
+            ("plain", OutputFormat::Plain),
+            ("text", OutputFormat::Plain),
+            ("json", OutputFormat::Json),
+            ("JSON", OutputFormat::Json),
+            ("yaml", OutputFormat::Yaml),
+            ("Yml", OutputFormat::Yaml),
+        ] {
+            assert_eq!(OutputFormat::from_str(name), Ok(format));
+        }
+        for format in [OutputFormat::Plain, OutputFormat::Json, OutputFormat::Yaml] {
+            assert_eq!(OutputFormat::from_str(&format.to_string()), Ok(format));
+        }
+        assert_eq!(OutputFormat::default(), OutputFormat::Plain);
+        assert!(OutputFormat::Plain.is_plain());
+        assert!(!OutputFormat::Json.is_plain());
+        assert!(!OutputFormat::Yaml.is_plain());
+
+        let err = OutputFormat::from_str("Toml").unwrap_err();
+        assert_eq!(err, UnknownOutputFormat(s!("Toml")));
+        assert_eq!(
+            err.to_string(),
+            "unknown output format `Toml`; supported formats are: plain, json, yaml"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde_yaml")]
+    fn yaml_report() {
+        assert_eq!(
+            serde_yaml::to_string(&StatusReport::changed("key added")).unwrap(),
+            "changed: true\nmessage: key added\n"
+        );
+        assert_eq!(
+            serde_yaml::to_string(&ErrorReport {
+                error: s!("no wallet"),
+            })
+            .unwrap(),
+            "error: no wallet\n"
+        );
+    }
+}
@@ -0,0 +1,480 @@
+// Descriptor wallet library extending bitcoin & miniscript functionality
+// by LNP/BP Association (https://lnp-bp.org)
+// Written in 2020-2022 by
+//     Dr. Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// To the extent possible under law, the author(s) have dedicated all
+// copyright and related and neighboring rights to this software to
+// the public domain worldwide. This software is distributed without
+// any warranty.
+//
+// You should have received a copy of the Apache-2.0 License
+// along with this software.
+// If not, see <https://opensource.org/licenses/Apache-2.0>.
+
+//! Synchronization of the watch-only wallet state between an online watcher
+//! host and an air-gapped host.
+//!
+//! The watcher scans the blockchain and exports [`SignedSyncBundle`]s, each of
+//! which contains changes to the wallet UTXO set since the previous bundle,
+//! information about the chain tip and fee estimates. Bundles are numbered
+//! sequentially and signed with the watcher key, which is pinned by the
+//! air-gapped host on the first import. Both hosts keep a [`SyncState`]: the
+//! watcher uses it to compute the next bundle, and the air-gapped host applies
+//! bundles to it, rejecting bundles which are forged, replayed, stale or
+//! received out of order.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{schnorr, KeyPair, Message, Secp256k1, Signing, Verification};
+use bitcoin::{BlockHash, OutPoint, XOnlyPublicKey};
+use bitcoin_scripts::PubkeyScript;
+use onchain::blockchain::MiningStatus;
+use strict_encoding::StrictEncode;
+
+use crate::envelope::Versioned;
+
+/// Tag used for computing the tagged hash of the sync bundle, which is signed
+/// by the watcher
+pub const SYNC_BUNDLE_TAG: &[u8] = b"descriptor-wallet:sync-bundle";
+
+/// Errors applying sync bundle to the wallet sync state
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum SyncError {
+    /// sync bundle is signed by watcher {0} which is not trusted by the wallet
+    UnknownWatcher(XOnlyPublicKey),
+
+    /// sync bundle is produced for a different wallet {0}
+    WalletMismatch(sha256::Hash),
+
+    /// sync bundle signature is invalid, indicating that the bundle data were
+    /// tampered with
+    InvalidSignature,
+
+    /// sync bundle #{bundle} is stale or replayed since the wallet state is
+    /// already at #{state}
+    Stale {
+        /// Sequence number of the sync bundle
+        bundle: u64,
+        /// Sequence number of the last applied sync bundle
+        state: u64,
+    },
+
+    /// sync bundle #{bundle} can't be applied before the missing bundles
+    /// following #{state}
+    Gap {
+        /// Sequence number of the sync bundle
+        bundle: u64,
+        /// Sequence number of the last applied sync bundle
+        state: u64,
+    },
+
+    /// sync bundle chain tip at height {bundle} is behind the known chain tip
+    /// at height {state}
+    TipRegression {
+        /// Height of the sync bundle chain tip
+        bundle: u32,
+        /// Height of the last known chain tip
+        state: u32,
+    },
+
+    /// sync bundle spends output {0} unknown to the wallet, indicating that
+    /// the wallet and the watcher states diverged
+    UnknownSpent(OutPoint),
+}
+
+/// Blockchain tip known to the watcher at the moment of the bundle creation
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[derive(StrictEncode, StrictDecode)]
+#[display("{height}#{block_hash}")]
+pub struct ChainTip {
+    /// Height of the tip block
+    pub height: u32,
+
+    /// Hash of the tip block
+    pub block_hash: BlockHash,
+
+    /// Timestamp of the tip block header
+    pub time: u32,
+}
+
+/// Unspent output controlled by the wallet
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct SyncUtxo {
+    /// Outpoint of the unspent output
+    pub outpoint: OutPoint,
+
+    /// Amount stored in the output, in satoshis
+    pub amount: u64,
+
+    /// Output `scriptPubkey`
+    pub script_pubkey: PubkeyScript,
+
+    /// Mining status of the transaction creating the output
+    pub mined: MiningStatus,
+}
+
+/// Changes to the wallet state since the previous sync bundle
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct SyncBundle {
+    /// Identifier of the wallet, see [`wallet_id`]
+    pub wallet_id: sha256::Hash,
+
+    /// Sequence number of the bundle, starting from 1
+    pub sequence: u64,
+
+    /// Chain tip at the moment of the bundle creation
+    pub tip: ChainTip,
+
+    /// Outputs which were not known before or which mining status has changed
+    pub new_utxos: Vec<SyncUtxo>,
+
+    /// Previously known outputs which were spent
+    pub spent: BTreeSet<OutPoint>,
+
+    /// Fee rate estimates in satoshis per 1000 vbytes, indexed by the target
+    /// number of blocks for the transaction confirmation
+    pub fee_estimates: BTreeMap<u16, u64>,
+}
+
+impl SyncBundle {
+    /// Computes tagged hash of the bundle data, which is signed by the watcher
+    pub fn sighash(&self) -> sha256::Hash {
+        let tag = sha256::Hash::hash(SYNC_BUNDLE_TAG);
+        let mut engine = sha256::Hash::engine();
+        engine.input(&tag[..]);
+        engine.input(&tag[..]);
+        self.strict_encode(&mut engine)
+            .expect("hash engines do not error");
+        sha256::Hash::from_engine(engine)
+    }
+
+    /// Signs the bundle with the watcher key
+    pub fn sign<C: Signing>(self, secp: &Secp256k1<C>, keypair: &KeyPair) -> SignedSyncBundle {
+        let msg = Message::from_slice(&self.sighash()[..]).expect("hash has a valid message size");
+        let signature = secp.sign_schnorr_no_aux_rand(&msg, keypair);
+        SignedSyncBundle {
+            bundle: self,
+            watcher: keypair.x_only_public_key().0,
+            signature,
+        }
+    }
+}
+
+/// Sync bundle signed by the watcher
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct SignedSyncBundle {
+    /// Bundle data
+    pub bundle: SyncBundle,
+
+    /// Public key of the watcher which has signed the bundle
+    pub watcher: XOnlyPublicKey,
+
+    /// BIP-340 signature over [`SyncBundle::sighash`]
+    pub signature: schnorr::Signature,
+}
+
+impl SignedSyncBundle {
+    /// Verifies bundle signature against the watcher key
+    pub fn verify<C: Verification>(&self, secp: &Secp256k1<C>) -> Result<(), SyncError> {
+        let msg =
+            Message::from_slice(&self.bundle.sighash()[..]).expect("hash has a valid message size");
+        secp.verify_schnorr(&self.signature, &msg, &self.watcher)
+            .map_err(|_| SyncError::InvalidSignature)
+    }
+}
+
+impl Versioned for SignedSyncBundle {
+    const MAGIC: [u8; 4] = *b"DWSB";
+    const VERSION: u16 = 1;
+}
+
+/// Results of applying sync bundle to the wallet sync state
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct SyncSummary {
+    /// Sequence number of the applied bundle
+    pub sequence: u64,
+
+    /// Number of added or updated outputs
+    pub added: usize,
+
+    /// Number of spent outputs
+    pub spent: usize,
+
+    /// Chain tip reported by the bundle
+    pub tip: ChainTip,
+}
+
+/// Wallet state synchronized between the watcher and the air-gapped host
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[derive(StrictEncode, StrictDecode)]
+pub struct SyncState {
+    /// Identifier of the wallet, see [`wallet_id`]
+    pub wallet_id: sha256::Hash,
+
+    /// Public key of the trusted watcher
+    pub watcher: XOnlyPublicKey,
+
+    /// Sequence number of the last applied bundle; zero if no bundles were
+    /// applied yet
+    pub sequence: u64,
+
+    /// Last known chain tip
+    pub tip: Option<ChainTip>,
+
+    /// Unspent outputs controlled by the wallet
+    pub utxos: BTreeMap<OutPoint, SyncUtxo>,
+
+    /// Last known fee rate estimates in satoshis per 1000 vbytes, indexed by
+    /// the confirmation target in blocks
+    pub fee_estimates: BTreeMap<u16, u64>,
+}
+
+/// Computes wallet identifier from the string representation of the first
+/// wallet descriptor, which remains the same across descriptor rotations.
+pub fn wallet_id(descriptor: &impl Display) -> sha256::Hash {
+    sha256::Hash::hash(descriptor.to_string().as_bytes())
+}
+
+impl SyncState {
+    /// Constructs empty sync state trusting bundles from a given watcher
+    pub fn new(wallet_id: sha256::Hash, watcher: XOnlyPublicKey) -> SyncState {
+        SyncState {
+            wallet_id,
+            watcher,
+            sequence: 0,
+            tip: None,
+            utxos: empty!(),
+            fee_estimates: empty!(),
+        }
+    }
+
+    /// Returns total amount of the unspent outputs
+    pub fn balance(&self) -> u64 { self.utxos.values().map(|utxo| utxo.amount).sum() }
+
+    /// Prepares next sync bundle from the full set of the wallet unspent
+    /// outputs found by the watcher. The bundle includes only the changes to
+    /// the UTXO set known to this state.
+    ///
+    /// The watcher must sign the bundle and apply it to its own state before
+    /// preparing the next one.
+    pub fn prepare(
+        &self,
+        utxos: impl IntoIterator<Item = SyncUtxo>,
+        tip: ChainTip,
+        fee_estimates: BTreeMap<u16, u64>,
+    ) -> SyncBundle {
+        let utxos = utxos
+            .into_iter()
+            .map(|utxo| (utxo.outpoint, utxo))
+            .collect::<BTreeMap<_, _>>();
+        SyncBundle {
+            wallet_id: self.wallet_id,
+            sequence: self.sequence + 1,
+            tip,
+            new_utxos: utxos
+                .values()
+                .filter(|utxo| self.utxos.get(&utxo.outpoint) != Some(utxo))
+                .cloned()
+                .collect(),
+            spent: self
+                .utxos
+                .keys()
+                .filter(|outpoint| !utxos.contains_key(outpoint))
+                .copied()
+                .collect(),
+            fee_estimates,
+        }
+    }
+
+    /// Validates signed sync bundle and applies it to the state. State is not
+    /// modified if the bundle is rejected.
+    pub fn apply<C: Verification>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        signed: &SignedSyncBundle,
+    ) -> Result<SyncSummary, SyncError> {
+        let bundle = &signed.bundle;
+        if signed.watcher != self.watcher {
+            return Err(SyncError::UnknownWatcher(signed.watcher));
+        }
+        signed.verify(secp)?;
+        if bundle.wallet_id != self.wallet_id {
+            return Err(SyncError::WalletMismatch(bundle.wallet_id));
+        }
+        if bundle.sequence <= self.sequence {
+            return Err(SyncError::Stale {
+                bundle: bundle.sequence,
+                state: self.sequence,
+            });
+        }
+        if bundle.sequence > self.sequence + 1 {
+            return Err(SyncError::Gap {
+                bundle: bundle.sequence,
+                state: self.sequence,
+            });
+        }
+        if let Some(tip) = self.tip {
+            if bundle.tip.height < tip.height {
+                return Err(SyncError::TipRegression {
+                    bundle: bundle.tip.height,
+                    state: tip.height,
+                });
+            }
+        }
+        if let Some(outpoint) = bundle
+            .spent
+            .iter()
+            .find(|outpoint| !self.utxos.contains_key(outpoint))
+        {
+            return Err(SyncError::UnknownSpent(*outpoint));
+        }
+
+        for outpoint in &bundle.spent {
+            self.utxos.remove(outpoint);
+        }
+        for utxo in &bundle.new_utxos {
+            self.utxos.insert(utxo.outpoint, utxo.clone());
+        }
+        self.sequence = bundle.sequence;
+        self.tip = Some(bundle.tip);
+        if !bundle.fee_estimates.is_empty() {
+            self.fee_estimates = bundle.fee_estimates.clone();
+        }
+
+        Ok(SyncSummary {
+            sequence: bundle.sequence,
+            added: bundle.new_utxos.len(),
+            spent: bundle.spent.len(),
+            tip: bundle.tip,
+        })
+    }
+}
+
+impl Versioned for SyncState {
+    const MAGIC: [u8; 4] = *b"DWSS";
+    const VERSION: u16 = 1;
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::Script;
+    use strict_encoding::StrictDecode;
+
+    use super::*;
+
+    fn utxo(vout: u32, amount: u64, mined: MiningStatus) -> SyncUtxo {
+        SyncUtxo {
+            outpoint: OutPoint::new(bitcoin::Txid::from_inner([0xAB; 32]), vout),
+            amount,
+            script_pubkey: PubkeyScript::from(Script::new_op_return(&[vout as u8])),
+            mined,
+        }
+    }
+
+    fn tip(height: u32) -> ChainTip {
+        ChainTip {
+            height,
+            block_hash: BlockHash::from_inner([height as u8; 32]),
+            time: 1_600_000_000 + height,
+        }
+    }
+
+    #[test]
+    fn sync_roundtrip() {
+        let secp = Secp256k1::new();
+        let keypair = KeyPair::from_secret_key(&secp, &SecretKey::from_slice(&[1; 32]).unwrap());
+        let watcher_key = keypair.x_only_public_key().0;
+        let id = wallet_id(&"wpkh([01020304/84h/0h/0h]xpub/<0;1>/*)");
+        let mut watcher = SyncState::new(id, watcher_key);
+        let mut cold = SyncState::new(id, watcher_key);
+
+        let first = watcher
+            .prepare(
+                vec![
+                    utxo(0, 1000, MiningStatus::Mempool),
+                    utxo(1, 2000, MiningStatus::Blockchain(9)),
+                ],
+                tip(10),
+                bmap! { 1u16 => 20_000u64 },
+            )
+            .sign(&secp, &keypair);
+        watcher.apply(&secp, &first).unwrap();
+        let data = first.strict_serialize().unwrap();
+        let first = SignedSyncBundle::strict_deserialize(data).unwrap();
+        let summary = cold.apply(&secp, &first).unwrap();
+        assert_eq!(summary.added, 2);
+        assert_eq!(cold.balance(), 3000);
+        assert_eq!(
+            cold.apply(&secp, &first),
+            Err(SyncError::Stale {
+                bundle: 1,
+                state: 1
+            })
+        );
+
+        let second = watcher
+            .prepare(
+                vec![utxo(0, 1000, MiningStatus::Blockchain(11))],
+                tip(11),
+                empty!(),
+            )
+            .sign(&secp, &keypair);
+        assert_eq!(second.bundle.new_utxos.len(), 1);
+        assert_eq!(
+            second.bundle.spent,
+            bset! { utxo(1, 0, MiningStatus::Mempool).outpoint }
+        );
+        watcher.apply(&secp, &second).unwrap();
+
+        let mut tampered = second.clone();
+        tampered.bundle.spent.clear();
+        assert_eq!(
+            cold.apply(&secp, &tampered),
+            Err(SyncError::InvalidSignature)
+        );
+
+        let third = watcher
+            .prepare(vec![], tip(12), empty!())
+            .sign(&secp, &keypair);
+        assert_eq!(
+            cold.apply(&secp, &third),
+            Err(SyncError::Gap {
+                bundle: 3,
+                state: 1
+            })
+        );
+
+        cold.apply(&secp, &second).unwrap();
+        assert_eq!(cold, watcher);
+        assert_eq!(cold.balance(), 1000);
+        assert_eq!(cold.fee_estimates.get(&1), Some(&20_000));
+
+        let stranger = KeyPair::from_secret_key(&secp, &SecretKey::from_slice(&[2; 32]).unwrap());
+        let forged = watcher
+            .prepare(vec![], tip(12), empty!())
+            .sign(&secp, &stranger);
+        assert_eq!(
+            cold.apply(&secp, &forged),
+            Err(SyncError::UnknownWatcher(stranger.x_only_public_key().0))
+        );
+
+        let regressed = watcher
+            .prepare(vec![], tip(5), empty!())
+            .sign(&secp, &keypair);
+        assert_eq!(
+            cold.apply(&secp, &regressed),
+            Err(SyncError::TipRegression {
+                bundle: 5,
+                state: 11
+            })
+        );
+    }
+}